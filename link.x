@@ -27,6 +27,47 @@ MEMORY
  */
 ENTRY(Reset);
 
+/*
+ * Handlers that the program can replace by defining a function with the same
+ * name. PROVIDE only defines a symbol if nothing else does, so these are the
+ * defaults. IEL0 to IEL31 are the external interrupts, which go to the ICU
+ * dispatcher unless e.g. RTIC binds a hardware task to them.
+ */
+PROVIDE(PendSV = default_exception_handler);
+PROVIDE(SysTick = default_exception_handler);
+PROVIDE(IEL0 = __icu_dispatch);
+PROVIDE(IEL1 = __icu_dispatch);
+PROVIDE(IEL2 = __icu_dispatch);
+PROVIDE(IEL3 = __icu_dispatch);
+PROVIDE(IEL4 = __icu_dispatch);
+PROVIDE(IEL5 = __icu_dispatch);
+PROVIDE(IEL6 = __icu_dispatch);
+PROVIDE(IEL7 = __icu_dispatch);
+PROVIDE(IEL8 = __icu_dispatch);
+PROVIDE(IEL9 = __icu_dispatch);
+PROVIDE(IEL10 = __icu_dispatch);
+PROVIDE(IEL11 = __icu_dispatch);
+PROVIDE(IEL12 = __icu_dispatch);
+PROVIDE(IEL13 = __icu_dispatch);
+PROVIDE(IEL14 = __icu_dispatch);
+PROVIDE(IEL15 = __icu_dispatch);
+PROVIDE(IEL16 = __icu_dispatch);
+PROVIDE(IEL17 = __icu_dispatch);
+PROVIDE(IEL18 = __icu_dispatch);
+PROVIDE(IEL19 = __icu_dispatch);
+PROVIDE(IEL20 = __icu_dispatch);
+PROVIDE(IEL21 = __icu_dispatch);
+PROVIDE(IEL22 = __icu_dispatch);
+PROVIDE(IEL23 = __icu_dispatch);
+PROVIDE(IEL24 = __icu_dispatch);
+PROVIDE(IEL25 = __icu_dispatch);
+PROVIDE(IEL26 = __icu_dispatch);
+PROVIDE(IEL27 = __icu_dispatch);
+PROVIDE(IEL28 = __icu_dispatch);
+PROVIDE(IEL29 = __icu_dispatch);
+PROVIDE(IEL30 = __icu_dispatch);
+PROVIDE(IEL31 = __icu_dispatch);
+
 SECTIONS
 {
 	/*
@@ -92,6 +133,11 @@ SECTIONS
 	{
 		_sdata = .;
 		*(.data .data.*);
+		/*
+		 * Functions that must run from RAM, e.g. while the code flash is
+		 * programmed. They are copied along with the initial values.
+		 */
+		*(.ramfunc .ramfunc.*);
 		_edata = .;
 	} > RAM AT > FLASH
 
@@ -0,0 +1,245 @@
+//! `analog_read` and `analog_write`.
+//!
+//! [`analog_read`] converts A0 to A5 with the ADC, and returns 10 bits by default, like the Arduino
+//! core. [`analog_write`] outputs PWM at [`PWM_FREQUENCY`] with the GPT timers on the PWM pins D3,
+//! D5, D6, D9, D10 and D11, and sets the voltage of A0 with the 12-bit D/A converter. It takes 8
+//! bits by default. A value of 0 or the maximum makes a PWM pin a digital output, so it is fully
+//! LOW or HIGH, and a pin without PWM outputs HIGH from half of the range on.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapters
+//! "General PWM Timer (GPT)" and "12-Bit D/A Converter (DAC12)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+
+use super::{digital_write, pin_mode, port_pin, Mode, PinNumber, A0, A5};
+use crate::peripherals::adc;
+use crate::peripherals::clocks::Cgc;
+use crate::peripherals::mstp::{self, unit};
+use crate::peripherals::pins::{self, PinStatus};
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Frequency of the PWM of [`analog_write`] in Hz, the one of most pins of the classic boards.
+pub const PWM_FREQUENCY: u32 = 490;
+
+/// Default resolutions of [`analog_read`] and [`analog_write`] in bits.
+const DEFAULT_READ_BITS: u8 = 10;
+const DEFAULT_WRITE_BITS: u8 = 8;
+
+/// Resolutions set with [`analog_read_resolution`] and [`analog_write_resolution`].
+static READ_BITS: AtomicU8 = AtomicU8::new(DEFAULT_READ_BITS);
+static WRITE_BITS: AtomicU8 = AtomicU8::new(DEFAULT_WRITE_BITS);
+
+/// The GPT channels that have been set up for PWM, one bit per channel.
+static PWM_CHANNELS: AtomicU8 = AtomicU8::new(0);
+
+/// ADC channels of A0 to A5.
+const ADC_CHANNELS: [u8; 6] = [9, 0, 1, 2, 21, 22];
+
+/// Address of the registers of GPT channel 0, and the distance to the next channel. Channels 0 and
+/// 1 have 32-bit counters, the others 16-bit ones.
+const GPT_BASE: u32 = 0x40078000;
+const GPT_GAP: u32 = 0x100;
+
+/// General PWM Timer Control Register, offset.
+/// * b0: CST, the counter runs.
+/// * b16-b18: MD, mode, 0 for saw-wave PWM.
+/// * b24-b26: TPCS, prescaler of PCLKD, 0-5 for 1, 4, 16, 64, 256 and 1024.
+const GTCR: u32 = 0x2c;
+
+/// General PWM Timer I/O Control Register, offset.
+/// * b0-b4: GTIOA, output of GTIOCnA. b0-b1 at compare match, b2-b3 at the end of the period
+///   (01: LOW, 10: HIGH), b4 at the start.
+/// * b8: OAE, enable the output of GTIOCnA.
+/// * b16-b20: GTIOB, same for GTIOCnB.
+/// * b24: OBE, enable the output of GTIOCnB.
+const GTIOR: u32 = 0x34;
+
+/// General PWM Timer Buffer Enable Register, offset.
+/// * b16-b17: CCRA, 1 to load GTCCRA from GTCCRC at the end of the period.
+/// * b18-b19: CCRB, 1 to load GTCCRB from GTCCRE at the end of the period.
+const GTBER: u32 = 0x40;
+
+/// General PWM Timer Compare Capture Registers A and B, which end the HIGH part of the period of
+/// the outputs, their buffers C and E, and the Cycle Setting Register, which holds the period
+/// minus 1. Offsets.
+const GTCCRA: u32 = 0x4c;
+const GTCCRB: u32 = 0x50;
+const GTCCRC: u32 = 0x54;
+const GTCCRE: u32 = 0x58;
+const GTPR: u32 = 0x64;
+
+const GTCR_CST: u32 = 1 << 0;
+/// HIGH at the start of the period, LOW from the compare match on.
+const GTIO_PWM: u32 = 0b1_10_01;
+const GTIOR_OAE: u32 = 1 << 8;
+const GTIOR_OBE: u32 = 1 << 24;
+const GTBER_BUFFERS: u32 = (1 << 16) | (1 << 18);
+
+/// Pin function of the GPT outputs.
+const PSEL_GPT: u32 = 3;
+
+/// D/A Data Register 0, right-aligned 12 bits.
+const DADR0: *mut u16 = 0x4005e000 as *mut u16;
+
+/// D/A Control Register.
+/// * b6: DAOE0, enable the output of channel 0.
+const DACR: *mut u8 = 0x4005e004 as *mut u8;
+
+/// D/A VREF Control Register.
+/// * b0-b2: REF, reference voltage, 1 for AVCC0/AVSS0.
+const DAVREFCR: *mut u8 = 0x4005e007 as *mut u8;
+
+const DACR_DAOE0: u8 = 1 << 6;
+const DAVREFCR_AVCC0: u8 = 1;
+
+/// Resolution of the D/A converter in bits.
+const DAC_BITS: u8 = 12;
+
+/// An output of a GPT channel.
+#[derive(Clone, Copy)]
+enum Output {
+    A,
+    B,
+}
+
+/// Returns the GPT channel and output that drive `pin`, if it is a PWM pin.
+fn pwm_output(pin: PinNumber) -> Option<(u32, Output)> {
+    match pin {
+        3 => Some((1, Output::A)),
+        5 => Some((0, Output::A)),
+        6 => Some((3, Output::A)),
+        9 => Some((7, Output::B)),
+        10 => Some((2, Output::A)),
+        11 => Some((6, Output::A)),
+        _ => None,
+    }
+}
+
+/// Returns `value` of `from` bits with `to` bits, like the Arduino core does.
+fn rescale(value: u32, from: u8, to: u8) -> u32 {
+    if from >= to {
+        value >> (from - to)
+    } else {
+        value << (to - from)
+    }
+}
+
+/// Set the resolution of [`analog_read`], 1 to 16 bits. The ADC converts with 14 bits, so higher
+/// resolutions have zeros in the lowest bits.
+pub fn analog_read_resolution(bits: u8) {
+    READ_BITS.store(bits.clamp(1, 16), Ordering::Relaxed);
+}
+
+/// Set the resolution of [`analog_write`], 1 to 16 bits.
+pub fn analog_write_resolution(bits: u8) {
+    WRITE_BITS.store(bits.clamp(1, 16), Ordering::Relaxed);
+}
+
+/// Returns the voltage at `pin`, A0 to A5, relative to the supply voltage, with the resolution of
+/// [`analog_read_resolution`]. Other pins read 0.
+///
+/// The pin stays an analog input afterwards, so [`pin_mode`] is needed to use it as a digital
+/// pin again.
+pub fn analog_read(pin: PinNumber) -> u16 {
+    let (Some((port_no, pin_no)), A0..=A5) = (port_pin(pin), pin) else {
+        return 0;
+    };
+    pins::set_function(port_no, pin_no, 1 << 15);
+    let value = adc::convert_channel(&mstp::token(), ADC_CHANNELS[(pin - A0) as usize]);
+    let bits = READ_BITS.load(Ordering::Relaxed);
+    rescale(value as u32, adc::CHANNEL_BITS as u8, bits) as u16
+}
+
+/// Output `value` on `pin`, with the resolution of [`analog_write_resolution`]: PWM with that
+/// duty cycle on a PWM pin, and that share of the supply voltage on A0. Higher values are capped.
+pub fn analog_write(pin: PinNumber, value: u32) {
+    let bits = WRITE_BITS.load(Ordering::Relaxed);
+    let max = (1 << bits) - 1;
+    let value = value.min(max);
+    let Some((port_no, pin_no)) = port_pin(pin) else {
+        return;
+    };
+    if pin == A0 {
+        pins::set_function(port_no, pin_no, 1 << 15);
+        write_dac(rescale(value, bits, DAC_BITS) as u16);
+        return;
+    }
+    match pwm_output(pin) {
+        Some((channel, output)) if value != 0 && value != max => {
+            let period = start_pwm(channel);
+            let duty = (period as u64 * value as u64 / max as u64) as u32;
+            let buffer = match output {
+                Output::A => GTCCRC,
+                Output::B => GTCCRE,
+            };
+            unsafe { gpt_register(channel, buffer).write_volatile(duty) };
+            pins::set_peripheral(port_no, pin_no, PSEL_GPT);
+        }
+        _ => {
+            pin_mode(pin, Mode::Output);
+            let status = if value > max / 2 {
+                PinStatus::High
+            } else {
+                PinStatus::Low
+            };
+            digital_write(pin, status);
+        }
+    }
+}
+
+/// Returns the address of the register at `offset` of GPT channel `channel`.
+fn gpt_register(channel: u32, offset: u32) -> *mut u32 {
+    (GPT_BASE + channel * GPT_GAP + offset) as *mut u32
+}
+
+/// Start saw-wave PWM at [`PWM_FREQUENCY`] on both outputs of GPT channel `channel`, unless it
+/// runs already, and return the period in counts.
+fn start_pwm(channel: u32) -> u32 {
+    let register = |offset| gpt_register(channel, offset);
+    let pclkd = Cgc::current().pclkd();
+    let max_period = if channel < 2 {
+        u32::MAX
+    } else {
+        u16::MAX as u32
+    };
+    // The smallest prescaler for which the period fits the counter, for the finest duty cycle.
+    let (tpcs, period) = (0..6)
+        .map(|tpcs| (tpcs, pclkd / (1 << (2 * tpcs)) / PWM_FREQUENCY))
+        .find(|&(_, period)| period <= max_period)
+        .unwrap_or((5, max_period));
+    if PWM_CHANNELS.fetch_or(1 << channel, Ordering::AcqRel) & (1 << channel) != 0 {
+        return period;
+    }
+    // The PWM keeps running for the rest of the program.
+    if channel < 2 {
+        core::mem::forget(mstp::token::<unit::Gpt32>());
+    } else {
+        core::mem::forget(mstp::token::<unit::Gpt16>());
+    }
+    unsafe {
+        register(GTCR).write_volatile(0);
+        register(GTPR).write_volatile(period - 1);
+        register(GTCCRA).write_volatile(0);
+        register(GTCCRB).write_volatile(0);
+        register(GTCCRC).write_volatile(0);
+        register(GTCCRE).write_volatile(0);
+        register(GTBER).write_volatile(GTBER_BUFFERS);
+        register(GTIOR).write_volatile(GTIO_PWM | GTIOR_OAE | GTIO_PWM << 16 | GTIOR_OBE);
+        register(GTCR).write_volatile(tpcs << 24 | GTCR_CST);
+    }
+    period
+}
+
+/// Output `value` of 12 bits with the D/A converter, turning it on first if needed.
+fn write_dac(value: u16) {
+    unsafe {
+        if DACR.read_volatile() & DACR_DAOE0 == 0 {
+            core::mem::forget(mstp::token::<unit::Dac12>());
+            DAVREFCR.write_volatile(DAVREFCR_AVCC0);
+            DADR0.write_volatile(value);
+            DACR.write_volatile(DACR.read_volatile() | DACR_DAOE0);
+        } else {
+            DADR0.write_volatile(value);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! `attach_interrupt` and `detach_interrupt`.
+//!
+//! The pins interrupt through the IRQ lines of the ICU. Each pin with an interrupt has a fixed
+//! line, and D0 and A1 share IRQ6, so only one of them can have an interrupt at a time.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter
+//! "Interrupt Controller Unit (ICU)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+
+use super::{port_pin, Error, PinNumber};
+use crate::interrupt;
+use crate::peripherals::icu::{self, Event, Slot};
+use crate::peripherals::pins;
+
+use core::ptr;
+
+/// Address of the IRQ Control Register of IRQ0, one byte per line.
+/// * b0-b1: IRQMD, detection, see [`Edge`].
+const IRQCR_BASE: u32 = 0x40006000;
+
+/// Number of IRQ lines.
+const IRQ_COUNT: usize = 16;
+
+/// The slots of the IRQ lines with an interrupt.
+static mut SLOTS: [Option<Slot>; IRQ_COUNT] = [None; IRQ_COUNT];
+
+/// When a pin interrupts, see [`attach_interrupt`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Edge {
+    /// When it goes from LOW to HIGH.
+    Rising,
+    /// When it goes from HIGH to LOW.
+    Falling,
+    /// When it changes.
+    Change,
+    /// Again and again while it is LOW.
+    Low,
+}
+
+impl Edge {
+    /// Returns the IRQMD bits of IRQCR.
+    fn irqmd(self) -> u8 {
+        match self {
+            Edge::Falling => 0b00,
+            Edge::Rising => 0b01,
+            Edge::Change => 0b10,
+            Edge::Low => 0b11,
+        }
+    }
+}
+
+/// Returns the IRQ line of `pin`, if it has one.
+fn irq_line(pin: PinNumber) -> Option<u8> {
+    match pin {
+        0 => Some(6),
+        1 => Some(5),
+        2 => Some(1),
+        3 => Some(0),
+        8 => Some(9),
+        15 => Some(6),
+        16 => Some(7),
+        17 => Some(8),
+        _ => None,
+    }
+}
+
+/// Call `handler` when `pin` sees `edge`, from then on until [`detach_interrupt`]. The pins with
+/// an interrupt are D0, D1, D2, D3, D8 and A1 to A3.
+///
+/// This replaces the handler of an interrupt that the pin or another one on the same IRQ line
+/// had. The pin keeps its mode, so it needs [`pin_mode`](super::pin_mode) as an input.
+pub fn attach_interrupt(pin: PinNumber, edge: Edge, handler: fn()) -> Result<(), Error> {
+    let (Some((port_no, pin_no)), Some(line)) = (port_pin(pin), irq_line(pin)) else {
+        return Err(Error::NoInterrupt);
+    };
+    detach_line(line);
+    let irqcr = (IRQCR_BASE + line as u32) as *mut u8;
+    unsafe { irqcr.write_volatile(edge.irqmd()) };
+    pins::set_irq_input(port_no, pin_no, true);
+    let event = Event::irq(line).ok_or(Error::NoInterrupt)?;
+    // This also clears a request that selecting the edge may have caused.
+    let slot = icu::attach(event, handler).ok_or(Error::NoFreeSlot)?;
+    interrupt::free(|| unsafe { (*ptr::addr_of_mut!(SLOTS))[line as usize] = Some(slot) });
+    Ok(())
+}
+
+/// Stop the interrupt of `pin`. Does nothing if it has none.
+pub fn detach_interrupt(pin: PinNumber) {
+    let (Some((port_no, pin_no)), Some(line)) = (port_pin(pin), irq_line(pin)) else {
+        return;
+    };
+    detach_line(line);
+    pins::set_irq_input(port_no, pin_no, false);
+}
+
+/// Detach the slot of IRQ line `line`, if it has one.
+fn detach_line(line: u8) {
+    let slot = interrupt::free(|| unsafe { (*ptr::addr_of_mut!(SLOTS))[line as usize].take() });
+    if let Some(slot) = slot {
+        icu::detach(slot);
+    }
+}
@@ -0,0 +1,235 @@
+//! Functions like those of the Arduino core, for porting sketches.
+//!
+//! The functions take the pin numbers of the board, 0 to 13 for D0 to D13 and [`A0`] to [`A5`]
+//! for the analog pins, which can be used as digital pins, too. They are checked at runtime:
+//! Functions ignore pins that don't exist, and reads return LOW. This makes it quick to get a
+//! sketch running, but the pins, the peripherals and their drivers in
+//! [`peripherals`](crate::peripherals) catch mistakes when compiling, so moving on to them is
+//! worth it.
+//!
+//! [`begin`] takes the pins and starts the AGT0 timer for [`millis`], [`micros`] and [`delay`],
+//! unless [`crate::peripherals::millis`] runs already.
+//! [`analog_read`] uses the ADC, and [`analog_write`] the GPT timers for PWM and the D/A converter
+//! for A0. [`attach_interrupt`] calls a function on an edge at a pin, through the IRQ lines of the
+//! ICU. [`Serial`] is the serial port on D0 and D1, or the USB one with the `arduino-serial-usb`
+//! feature, and [`Wire`] and [`SPI`] forward to the I2C and SPI drivers. [`random`] seeds itself
+//! from the noise of the chip. [`map`] and [`constrain`] are those of [`crate::math`].
+//!
+//! Needs the `arduino` feature.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::arduino::{self, delay, digital_write, pin_mode, Mode};
+//! use arduino_uno_r4_wifi_rt::arduino::{HIGH, LED_BUILTIN, LOW};
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! arduino::begin(get_pins().unwrap(), &clocks).unwrap();
+//! pin_mode(LED_BUILTIN, Mode::Output);
+//! loop {
+//!     digital_write(LED_BUILTIN, HIGH);
+//!     delay(1000);
+//!     digital_write(LED_BUILTIN, LOW);
+//!     delay(1000);
+//! }
+//! ```
+
+mod analog;
+mod interrupts;
+mod random;
+mod serial;
+mod spi;
+mod wire;
+
+pub use crate::math::{constrain, map};
+pub use crate::peripherals::millis::{micros, millis};
+pub use analog::{
+    analog_read, analog_read_resolution, analog_write, analog_write_resolution, PWM_FREQUENCY,
+};
+pub use interrupts::{attach_interrupt, detach_interrupt, Edge};
+pub use random::{random, random_range, random_seed};
+pub use serial::{HardwareSerial, Serial, SERIAL_BUFFER_SIZE};
+pub use spi::{SpiClass, SpiSettings, SPI};
+pub use wire::{TwoWire, Wire, WIRE_BUFFER_SIZE};
+
+use crate::peripherals::clocks::{self, Clocks};
+use crate::peripherals::iic;
+use crate::peripherals::millis;
+use crate::peripherals::mstp;
+use crate::peripherals::pins::{self, ArduinoPins, LedMatrixPins, PinModeUnknown, PinStatus};
+use crate::peripherals::pins::{P109, P110, P205};
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// A pin number of the board.
+pub type PinNumber = u8;
+
+/// Analog pins A0 to A5, which follow D13.
+pub const A0: PinNumber = 14;
+pub const A1: PinNumber = 15;
+pub const A2: PinNumber = 16;
+pub const A3: PinNumber = 17;
+pub const A4: PinNumber = 18;
+pub const A5: PinNumber = 19;
+
+/// The pin with the LED on the board, D13.
+pub const LED_BUILTIN: PinNumber = 13;
+
+pub const HIGH: PinStatus = PinStatus::High;
+pub const LOW: PinStatus = PinStatus::Low;
+
+/// Number of pins.
+const PIN_COUNT: usize = 20;
+
+/// Port and pin numbers of D0 to D13 and A0 to A5, see [`ArduinoPins`].
+const PINS: [(u32, u32); PIN_COUNT] = [
+    (3, 1),
+    (3, 2),
+    (1, 4),
+    (1, 5),
+    (1, 6),
+    (1, 7),
+    (1, 11),
+    (1, 12),
+    (3, 4),
+    (3, 3),
+    (1, 3),
+    (4, 11),
+    (4, 10),
+    (1, 2),
+    (0, 14),
+    (0, 0),
+    (0, 1),
+    (0, 2),
+    (1, 1),
+    (1, 0),
+];
+
+/// Set by [`begin`].
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Frequency of ICLK in Hz, for [`delay_microseconds`].
+static ICLK: AtomicU32 = AtomicU32::new(0);
+
+/// Errors of the functions of this module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// [`begin`] was called before.
+    AlreadyStarted,
+    /// All interrupt slots are in use, see [`icu::attach`].
+    NoFreeSlot,
+    /// PCLKB is too fast or too slow for a 1 ms period of AGT0.
+    UnsupportedClock,
+    /// The pin has no interrupt, see [`attach_interrupt`].
+    NoInterrupt,
+    /// The bit rate can't be generated from PCLKA, see [`HardwareSerial::begin`].
+    UnsupportedBaudRate,
+    /// No USB serial port is installed, see [`HardwareSerial::begin`].
+    NoSerialPort,
+    /// [`begin`] hasn't been called, so [`Wire`] and [`SPI`] don't have their pins.
+    NotStarted,
+    /// An I2C transfer failed.
+    I2c(iic::Error),
+}
+
+/// The mode of a pin, see [`pin_mode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    /// A digital input.
+    Input,
+    /// A digital output.
+    Output,
+    /// A digital input with pull-up.
+    InputPullup,
+}
+
+/// The pins that [`begin`] doesn't take, since they aren't on the headers.
+pub struct OtherPins {
+    pub p205: P205<PinModeUnknown>,
+    pub p109: P109<PinModeUnknown>,
+    pub p110: P110<PinModeUnknown>,
+    pub led_matrix: LedMatrixPins,
+}
+
+/// Take the pins on the headers for the functions of this module, and start counting the time
+/// with [`crate::peripherals::millis`] unless it runs already.
+///
+/// This is for the whole program, so AGT0 and an interrupt slot stay in use from now on.
+pub fn begin(pins: ArduinoPins, clocks: &Clocks) -> Result<OtherPins, Error> {
+    if STARTED.swap(true, Ordering::AcqRel) {
+        return Err(Error::AlreadyStarted);
+    }
+    let counting = match millis::start(mstp::token(), clocks) {
+        Ok(()) | Err(millis::Error::AlreadyStarted) => Ok(()),
+        Err(millis::Error::NoFreeSlot) => Err(Error::NoFreeSlot),
+        Err(millis::Error::UnsupportedClock) => Err(Error::UnsupportedClock),
+    };
+    if let Err(error) = counting {
+        STARTED.store(false, Ordering::Release);
+        return Err(error);
+    }
+    wire::give_pins(pins.a5, pins.a4);
+    spi::give_pins(pins.d13, pins.d12, pins.d11);
+    ICLK.store(clocks.iclk(), Ordering::Relaxed);
+    Ok(OtherPins {
+        p205: pins.p205,
+        p109: pins.p109,
+        p110: pins.p110,
+        led_matrix: pins.led_matrix,
+    })
+}
+
+/// Returns the port and pin numbers of `pin`, if it exists.
+pub(crate) fn port_pin(pin: PinNumber) -> Option<(u32, u32)> {
+    PINS.get(pin as usize).copied()
+}
+
+/// Configure `pin` as a digital input or output.
+pub fn pin_mode(pin: PinNumber, mode: Mode) {
+    let Some((port_no, pin_no)) = port_pin(pin) else {
+        return;
+    };
+    let pfs = match mode {
+        Mode::Input => 0,
+        Mode::Output => 1 << 2,
+        Mode::InputPullup => 1 << 4,
+    };
+    pins::set_function(port_no, pin_no, pfs);
+}
+
+/// Make `pin` output `status`, if it is an output.
+pub fn digital_write(pin: PinNumber, status: PinStatus) {
+    if let Some((port_no, pin_no)) = port_pin(pin) {
+        pins::write_pin(port_no, pin_no, matches!(status, PinStatus::High));
+    }
+}
+
+/// Returns the voltage that `pin` receives.
+pub fn digital_read(pin: PinNumber) -> PinStatus {
+    match port_pin(pin) {
+        Some((port_no, pin_no)) if pins::read_pin(port_no, pin_no) => PinStatus::High,
+        _ => PinStatus::Low,
+    }
+}
+
+/// Wait for `ms` milliseconds. Before the milliseconds are counted, this returns right away.
+pub fn delay(ms: u32) {
+    if !millis::is_started() {
+        return;
+    }
+    let start = millis();
+    while millis().wrapping_sub(start) < ms {
+        core::hint::spin_loop();
+    }
+}
+
+/// Wait for at least `us` microseconds. Before [`begin`], this returns right away.
+pub fn delay_microseconds(us: u32) {
+    let iclk = ICLK.load(Ordering::Relaxed);
+    if iclk != 0 {
+        clocks::wait_us(iclk, us);
+    }
+}
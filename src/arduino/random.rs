@@ -0,0 +1,93 @@
+//! `random` and `random_seed`.
+//!
+//! The RA4M1 has no hardware random number generator, so the numbers come from a xorshift
+//! generator. Unless [`random_seed`] is called first, it seeds itself on the first call from the
+//! unique ID of the chip, the noise in the lowest bit of conversions of the temperature sensor,
+//! and the SysTick counter and [`micros`] at that moment. This makes the sequence different on
+//! every board and at every start, but it isn't fit for cryptography.
+
+use super::micros;
+use crate::peripherals::adc::{self, Internal};
+use crate::peripherals::{device_id, mstp};
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// SysTick Current Value Register, counting down while SysTick runs.
+const SYST_CVR: *const u32 = 0xe000e018 as *const u32;
+
+/// Conversions of the temperature sensor for the seed, one bit each.
+const NOISE_BITS: u32 = 32;
+
+/// State of the generator, 0 until it is seeded.
+static STATE: AtomicU32 = AtomicU32::new(0);
+
+/// Mix `value` into the hash `hash`.
+fn mix(hash: u32, value: u32) -> u32 {
+    (hash ^ value).wrapping_mul(0x9e37_79b1).rotate_left(15)
+}
+
+/// Returns a seed from the sources of the module documentation, never 0.
+fn entropy() -> u32 {
+    let mut hash = 0;
+    for word in device_id::unique_id().chunks_exact(4) {
+        hash = mix(
+            hash,
+            u32::from_le_bytes([word[0], word[1], word[2], word[3]]),
+        );
+    }
+    let clock = mstp::token();
+    let mut noise = 0;
+    for _ in 0..NOISE_BITS {
+        let value = adc::convert_internal(&clock, Internal::TemperatureSensor, 0);
+        noise = noise << 1 | (value & 1) as u32;
+        hash = mix(hash, unsafe { SYST_CVR.read_volatile() });
+    }
+    hash = mix(hash, noise);
+    hash = mix(hash, micros());
+    hash.max(1)
+}
+
+/// Returns the next number of the generator, seeding it first if needed.
+fn next() -> u32 {
+    let step = |mut x: u32| {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        x
+    };
+    let mut state = STATE.load(Ordering::Relaxed);
+    if state == 0 {
+        // Another caller may seed it meanwhile, the first seed wins.
+        let _ = STATE.compare_exchange(0, entropy(), Ordering::Relaxed, Ordering::Relaxed);
+        state = STATE.load(Ordering::Relaxed);
+    }
+    loop {
+        let next = step(state);
+        match STATE.compare_exchange_weak(state, next, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return next,
+            Err(current) => state = current,
+        }
+    }
+}
+
+/// Start the sequence from `seed`, so it repeats for the same seed. 0 is ignored, like in the
+/// Arduino core.
+pub fn random_seed(seed: u32) {
+    if seed != 0 {
+        STATE.store(seed, Ordering::Relaxed);
+    }
+}
+
+/// Returns a random number from 0 to `max` - 1, or 0 if `max` isn't positive.
+pub fn random(max: i32) -> i32 {
+    random_range(0, max)
+}
+
+/// Returns a random number from `min` to `max` - 1, or `min` if `max` isn't larger.
+pub fn random_range(min: i32, max: i32) -> i32 {
+    if max <= min {
+        return min;
+    }
+    let span = max.abs_diff(min) as u64;
+    min.wrapping_add(((next() as u64 * span) >> 32) as i32)
+}
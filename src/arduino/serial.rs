@@ -0,0 +1,299 @@
+//! `Serial`, the serial port of sketches.
+//!
+//! Without the `arduino-serial-usb` feature, [`Serial`] is the SCI2 unit on D0 (RXD2) and D1
+//! (TXD2), which is `Serial1` in the Arduino core. [`HardwareSerial::begin`] hands both pins over
+//! to it, so the pin functions must not be used with them until [`HardwareSerial::end`]. The
+//! receive interrupt collects the bytes in a buffer of [`SERIAL_BUFFER_SIZE`] bytes.
+//!
+//! With the `arduino-serial-usb` feature, [`Serial`] is the USB serial port of
+//! [`usb::serial`](crate::peripherals::usb::serial), which has to be installed with
+//! [`install`](crate::peripherals::usb::serial::install) before [`HardwareSerial::begin`]. The
+//! baud rate is ignored, and received bytes are moved from the port to the buffer when
+//! [`HardwareSerial::available`], [`HardwareSerial::read`] or [`HardwareSerial::peek`] is called.
+//!
+//! Writes wait until the bytes are handed over, and [`HardwareSerial::read`] returns `None` where
+//! the Arduino core returns -1. [`HardwareSerial::print`] takes anything that implements
+//! [`Display`](core::fmt::Display), so `format_args!` prints formatted text.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Serial
+//! Communications Interface (SCI)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+
+use super::Error;
+use crate::interrupt;
+use crate::peripherals::esp32::RxBuffer;
+
+use core::fmt::{self, Write};
+use core::ptr;
+
+/// Size of the buffer for received bytes.
+pub const SERIAL_BUFFER_SIZE: usize = 512;
+
+/// Received bytes that haven't been read yet.
+static mut RX_BUFFER: RxBuffer<SERIAL_BUFFER_SIZE> = RxBuffer::new();
+
+/// Run `f` on the receive buffer, with interrupts disabled.
+fn with_rx_buffer<R>(f: impl FnOnce(&mut RxBuffer<SERIAL_BUFFER_SIZE>) -> R) -> R {
+    interrupt::free(|| unsafe { f(&mut *ptr::addr_of_mut!(RX_BUFFER)) })
+}
+
+/// The serial port of sketches, see [`Serial`].
+pub struct HardwareSerial {
+    _private: (),
+}
+
+/// The serial port, named like the one of the Arduino core.
+#[allow(non_upper_case_globals)]
+pub static Serial: HardwareSerial = HardwareSerial { _private: () };
+
+impl HardwareSerial {
+    /// Start the port at `baud` bits per second, dropping bytes that haven't been read. Can be
+    /// called again to change the bit rate.
+    pub fn begin(&self, baud: u32) -> Result<(), Error> {
+        with_rx_buffer(RxBuffer::clear);
+        backend::start(baud)
+    }
+
+    /// Stop the port. With SCI2, D0 and D1 become inputs again.
+    pub fn end(&self) {
+        backend::stop();
+    }
+
+    /// Returns the number of received bytes that haven't been read.
+    pub fn available(&self) -> usize {
+        backend::fill();
+        with_rx_buffer(|buffer| buffer.len())
+    }
+
+    /// Returns the next received byte, if there is one.
+    pub fn read(&self) -> Option<u8> {
+        backend::fill();
+        with_rx_buffer(RxBuffer::pop)
+    }
+
+    /// Returns the next received byte without taking it, if there is one.
+    pub fn peek(&self) -> Option<u8> {
+        backend::fill();
+        with_rx_buffer(|buffer| buffer.peek())
+    }
+
+    /// Send `data`, and return the number of bytes sent.
+    pub fn write(&self, data: &[u8]) -> usize {
+        if backend::send(data) {
+            data.len()
+        } else {
+            0
+        }
+    }
+
+    /// Send `value` as text.
+    pub fn print(&self, value: impl fmt::Display) {
+        // The port can't fail, it only drops text that can't be sent.
+        let _ = write!(Writer(self), "{}", value);
+    }
+
+    /// Send `value` as text, followed by `"\r\n"`.
+    pub fn println(&self, value: impl fmt::Display) {
+        let _ = write!(Writer(self), "{}\r\n", value);
+    }
+
+    /// Wait until all bytes have been sent.
+    pub fn flush(&self) {
+        backend::flush();
+    }
+}
+
+/// Formats text into the port for [`HardwareSerial::print`].
+struct Writer<'a>(&'a HardwareSerial);
+
+impl fmt::Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "arduino-serial-usb"))]
+mod backend {
+    use super::{with_rx_buffer, Error};
+    use crate::interrupt;
+    use crate::peripherals::clocks::{self, Cgc};
+    use crate::peripherals::esp32::bit_rate_settings;
+    use crate::peripherals::icu::{self, Event, Slot};
+    use crate::peripherals::mstp::{self, unit, MstpToken};
+    use crate::peripherals::pins;
+    use crate::peripherals::registers::VolatileBoolOps;
+
+    use core::ptr;
+
+    // The registers of SCI2, see the SCI9 ones in `esp32` for the bits.
+    const SMR: *mut u8 = 0x40070040 as *mut u8;
+    const BRR: *mut u8 = 0x40070041 as *mut u8;
+    const SCR: *mut u8 = 0x40070042 as *mut u8;
+    const TDR: *mut u8 = 0x40070043 as *mut u8;
+    const SSR: *mut u8 = 0x40070044 as *mut u8;
+    const RDR: *const u8 = 0x40070045 as *const u8;
+    const SCMR: *mut u8 = 0x40070046 as *mut u8;
+    const SEMR: *mut u8 = 0x40070047 as *mut u8;
+    const MDDR: *mut u8 = 0x40070052 as *mut u8;
+
+    const SCR_RE: u8 = 1 << 4;
+    const SCR_TE: u8 = 1 << 5;
+    const SCR_RIE: u8 = 1 << 6;
+    const SSR_TEND: u8 = 1 << 2;
+    const SSR_PER: u8 = 1 << 3;
+    const SSR_FER: u8 = 1 << 4;
+    const SSR_ORER: u8 = 1 << 5;
+    const SSR_TDRE: u8 = 1 << 7;
+    const SEMR_BRME: u8 = 1 << 2;
+    const SEMR_ABCS: u8 = 1 << 4;
+    const SEMR_BGDM: u8 = 1 << 6;
+
+    /// Peripheral function 4 is SCI0, 2, 4, 6 and 8.
+    const PSEL_SCI: u32 = 0b00100;
+
+    /// Port and pin numbers of D0 (RXD2) and D1 (TXD2).
+    const RX: (u32, u32) = (3, 1);
+    const TX: (u32, u32) = (3, 2);
+
+    /// The interrupt slots of RXI and ERI, and the clock of SCI2, while the port runs.
+    struct Running {
+        slots: [Slot; 2],
+        _clock: MstpToken<unit::Sci2>,
+    }
+
+    static mut RUNNING: Option<Running> = None;
+
+    /// Interrupt handler for RXI: Move the received byte to the buffer.
+    fn on_receive() {
+        let byte = unsafe { RDR.read_volatile() };
+        with_rx_buffer(|buffer| buffer.push(byte));
+    }
+
+    /// Interrupt handler for ERI: Drop the byte and clear the error.
+    fn on_error() {
+        unsafe {
+            RDR.read_volatile();
+            SSR.volatile_and(!(SSR_ORER | SSR_FER | SSR_PER));
+        }
+    }
+
+    pub(super) fn start(baud: u32) -> Result<(), Error> {
+        let clocks = Cgc::current();
+        let (cks, brr, mddr) =
+            bit_rate_settings(clocks.pclka(), baud).ok_or(Error::UnsupportedBaudRate)?;
+        let running = interrupt::free(|| unsafe { (*ptr::addr_of!(RUNNING)).is_some() });
+        if !running {
+            let receive = icu::attach(Event::Sci2Rxi, on_receive).ok_or(Error::NoFreeSlot)?;
+            let Some(error) = icu::attach(Event::Sci2Eri, on_error) else {
+                icu::detach(receive);
+                return Err(Error::NoFreeSlot);
+            };
+            let clock = mstp::token::<unit::Sci2>();
+            interrupt::free(|| unsafe {
+                *ptr::addr_of_mut!(RUNNING) = Some(Running {
+                    slots: [receive, error],
+                    _clock: clock,
+                })
+            });
+            pins::set_peripheral(RX.0, RX.1, PSEL_SCI);
+            pins::set_peripheral(TX.0, TX.1, PSEL_SCI);
+        }
+        unsafe {
+            SCR.write_volatile(0);
+            SCMR.write_volatile(0xf2);
+            SMR.write_volatile(cks);
+            BRR.write_volatile(brr);
+            match mddr {
+                Some(mddr) => {
+                    MDDR.write_volatile(mddr);
+                    SEMR.write_volatile(SEMR_ABCS | SEMR_BGDM | SEMR_BRME);
+                }
+                None => SEMR.write_volatile(SEMR_ABCS | SEMR_BGDM),
+            }
+            SSR.volatile_and(!(SSR_ORER | SSR_FER | SSR_PER));
+        }
+        // The unit needs one bit period before it is enabled.
+        clocks::wait_us(clocks.iclk(), 1_000_000 / baud + 1);
+        unsafe { SCR.write_volatile(SCR_TE | SCR_RE | SCR_RIE) };
+        Ok(())
+    }
+
+    pub(super) fn stop() {
+        let Some(running) = interrupt::free(|| unsafe { (*ptr::addr_of_mut!(RUNNING)).take() })
+        else {
+            return;
+        };
+        unsafe { SCR.write_volatile(0) };
+        for slot in running.slots {
+            icu::detach(slot);
+        }
+        pins::set_function(RX.0, RX.1, 0);
+        pins::set_function(TX.0, TX.1, 0);
+    }
+
+    /// The interrupt handler fills the buffer.
+    pub(super) fn fill() {}
+
+    /// Returns false if the port isn't running.
+    pub(super) fn send(data: &[u8]) -> bool {
+        if unsafe { SCR.read_volatile() } & SCR_TE == 0 {
+            return false;
+        }
+        for &byte in data {
+            unsafe {
+                while SSR.read_volatile() & SSR_TDRE == 0 {}
+                TDR.write_volatile(byte);
+            }
+        }
+        true
+    }
+
+    pub(super) fn flush() {
+        unsafe {
+            if SCR.read_volatile() & SCR_TE != 0 {
+                while SSR.read_volatile() & SSR_TEND == 0 {}
+            }
+        }
+    }
+}
+
+#[cfg(feature = "arduino-serial-usb")]
+mod backend {
+    use super::{with_rx_buffer, Error, SERIAL_BUFFER_SIZE};
+    use crate::peripherals::usb::serial;
+
+    /// Bytes moved from the port to the buffer at a time.
+    const CHUNK_SIZE: usize = 64;
+
+    /// The baud rate doesn't matter over USB, it only checks that the port is installed.
+    pub(super) fn start(_baud: u32) -> Result<(), Error> {
+        serial::with(|_| ()).ok_or(Error::NoSerialPort)
+    }
+
+    /// The port stays installed.
+    pub(super) fn stop() {}
+
+    /// Move received bytes from the port to the buffer, as many as fit.
+    pub(super) fn fill() {
+        serial::with(|serial| {
+            with_rx_buffer(|buffer| {
+                let mut chunk = [0; CHUNK_SIZE];
+                let free = (SERIAL_BUFFER_SIZE - buffer.len()).min(CHUNK_SIZE);
+                let count = serial.read(&mut chunk[..free]);
+                for &byte in &chunk[..count] {
+                    buffer.push(byte);
+                }
+            })
+        });
+    }
+
+    /// Returns false if no port is installed or no terminal has it open.
+    pub(super) fn send(data: &[u8]) -> bool {
+        serial::with(|serial| serial.write_all(data)).unwrap_or(false)
+    }
+
+    pub(super) fn flush() {
+        serial::with(|serial| serial.flush());
+    }
+}
@@ -0,0 +1,153 @@
+//! `SPI`, the SPI bus of sketches.
+//!
+//! [`SPI`] forwards to the blocking [`Spi`] driver on D11 (MOSI), D12 (MISO) and D13 (SCK). The
+//! chip select is up to the sketch, usually D10 with [`digital_write`](super::digital_write).
+//! While the bus runs, the pin functions must not be used with D11 to D13, so the LED on D13 is
+//! off limits, too.
+//!
+//! Transactions don't mask interrupts, [`SpiClass::begin_transaction`] only applies the settings.
+
+use super::Error;
+use crate::interrupt;
+use crate::peripherals::clocks::Cgc;
+use crate::peripherals::mstp;
+use crate::peripherals::pins::{PinModeUnknown, P102, P410, P411};
+use crate::peripherals::spi::{BitOrder, Blocking, DataMode, Spi};
+
+use core::ptr;
+
+/// The pins of the bus, taken by [`super::begin`], or the driver while the bus runs.
+enum Bus {
+    Stopped(
+        P102<PinModeUnknown>,
+        P410<PinModeUnknown>,
+        P411<PinModeUnknown>,
+    ),
+    Running(Spi<Blocking>),
+}
+
+static mut BUS: Option<Bus> = None;
+
+/// Take the bus out of its static, so the transfers run with interrupts enabled, and put it back
+/// afterwards.
+fn with_bus<R>(f: impl FnOnce(Option<Bus>) -> (Option<Bus>, R)) -> R {
+    let bus = interrupt::free(|| unsafe { (*ptr::addr_of_mut!(BUS)).take() });
+    let (bus, result) = f(bus);
+    interrupt::free(|| unsafe { *ptr::addr_of_mut!(BUS) = bus });
+    result
+}
+
+/// Run `f` on the driver, if the bus runs.
+fn with_spi<R>(f: impl FnOnce(&mut Spi<Blocking>) -> R) -> Result<R, Error> {
+    with_bus(|mut bus| {
+        let result = match &mut bus {
+            Some(Bus::Running(spi)) => Ok(f(spi)),
+            _ => Err(Error::NotStarted),
+        };
+        (bus, result)
+    })
+}
+
+/// Keep the pins of the bus, called by [`super::begin`].
+pub(super) fn give_pins(
+    sck: P102<PinModeUnknown>,
+    miso: P410<PinModeUnknown>,
+    mosi: P411<PinModeUnknown>,
+) {
+    interrupt::free(|| unsafe { *ptr::addr_of_mut!(BUS) = Some(Bus::Stopped(sck, miso, mosi)) });
+}
+
+/// The settings of a transaction, see [`SpiClass::begin_transaction`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SpiSettings {
+    /// The highest bit rate of the device in Hz.
+    pub clock: u32,
+    pub bit_order: BitOrder,
+    pub data_mode: DataMode,
+}
+
+impl SpiSettings {
+    pub const fn new(clock: u32, bit_order: BitOrder, data_mode: DataMode) -> Self {
+        Self {
+            clock,
+            bit_order,
+            data_mode,
+        }
+    }
+}
+
+impl Default for SpiSettings {
+    /// 4 MHz, MSB first, mode 0, like the Arduino core.
+    fn default() -> Self {
+        Self::new(4_000_000, BitOrder::MsbFirst, DataMode::Mode0)
+    }
+}
+
+/// The SPI bus of sketches, see [`SPI`].
+pub struct SpiClass {
+    _private: (),
+}
+
+/// The SPI bus, named like the one of the Arduino core.
+pub static SPI: SpiClass = SpiClass { _private: () };
+
+impl SpiClass {
+    /// Start the bus with the default [`SpiSettings`]. Does nothing if it runs already.
+    pub fn begin(&self) -> Result<(), Error> {
+        with_bus(|bus| match bus {
+            Some(Bus::Stopped(sck, miso, mosi)) => {
+                let settings = SpiSettings::default();
+                let spi = Spi::new(
+                    sck,
+                    miso,
+                    mosi,
+                    mstp::token(),
+                    &Cgc::current(),
+                    settings.clock,
+                    settings.data_mode,
+                );
+                (Some(Bus::Running(spi)), Ok(()))
+            }
+            Some(bus) => (Some(bus), Ok(())),
+            None => (None, Err(Error::NotStarted)),
+        })
+    }
+
+    /// Stop the bus. D11 to D13 keep no function until [`pin_mode`](super::pin_mode).
+    pub fn end(&self) {
+        with_bus(|bus| match bus {
+            Some(Bus::Running(spi)) => {
+                let (sck, miso, mosi, _) = spi.release();
+                (Some(Bus::Stopped(sck, miso, mosi)), ())
+            }
+            bus => (bus, ()),
+        });
+    }
+
+    /// Apply `settings` for the following transfers.
+    pub fn begin_transaction(&self, settings: SpiSettings) -> Result<(), Error> {
+        with_spi(|spi| {
+            spi.set_frequency(settings.clock);
+            spi.set_bit_order(settings.bit_order);
+            spi.set_data_mode(settings.data_mode);
+        })
+    }
+
+    /// End a transaction. There is nothing to undo, this is for ported code.
+    pub fn end_transaction(&self) {}
+
+    /// Send `byte` and return the byte received at the same time.
+    pub fn transfer(&self, byte: u8) -> Result<u8, Error> {
+        with_spi(|spi| spi.transfer_byte(byte))
+    }
+
+    /// Send `word` in one 16-bit frame and return the word received at the same time.
+    pub fn transfer16(&self, word: u16) -> Result<u16, Error> {
+        with_spi(|spi| spi.transfer_word(word))
+    }
+
+    /// Send the bytes in `buffer` and replace them with the received bytes.
+    pub fn transfer_buffer(&self, buffer: &mut [u8]) -> Result<(), Error> {
+        with_spi(|spi| spi.transfer_in_place(buffer))
+    }
+}
@@ -0,0 +1,209 @@
+//! `Wire`, the I2C bus of sketches.
+//!
+//! [`Wire`] forwards to the blocking [`Iic`] driver on A4 (SDA) and A5 (SCL). Like the Arduino
+//! core, it collects the bytes of a transmission in a buffer of [`WIRE_BUFFER_SIZE`] bytes and
+//! sends them in [`TwoWire::end_transmission`], and [`TwoWire::request_from`] reads into another
+//! buffer, from which [`TwoWire::read`] takes the bytes. While the bus runs, the pin functions
+//! must not be used with A4 and A5.
+//!
+//! The transfers return [`Error::I2c`] where the Arduino core returns a status code.
+
+use super::Error;
+use crate::interrupt;
+use crate::peripherals::clocks::Cgc;
+use crate::peripherals::iic::{Blocking, Iic, Speed};
+use crate::peripherals::mstp;
+use crate::peripherals::pins::{PinModeUnknown, P100, P101};
+
+use core::ptr;
+
+/// Size of the buffers for sent and received bytes.
+pub const WIRE_BUFFER_SIZE: usize = 32;
+
+/// The pins of the bus, taken by [`super::begin`], or the driver while the bus runs.
+enum Bus {
+    Stopped(P100<PinModeUnknown>, P101<PinModeUnknown>),
+    Running(Iic<Blocking>),
+}
+
+static mut BUS: Option<Bus> = None;
+
+/// The transmission that is being collected, and the bytes that have been received.
+struct Buffers {
+    address: u8,
+    tx: [u8; WIRE_BUFFER_SIZE],
+    tx_len: usize,
+    /// The transmission was ended without a stop condition, so it is sent with the next request.
+    tx_pending: bool,
+    rx: [u8; WIRE_BUFFER_SIZE],
+    rx_start: usize,
+    rx_len: usize,
+}
+
+static mut BUFFERS: Buffers = Buffers {
+    address: 0,
+    tx: [0; WIRE_BUFFER_SIZE],
+    tx_len: 0,
+    tx_pending: false,
+    rx: [0; WIRE_BUFFER_SIZE],
+    rx_start: 0,
+    rx_len: 0,
+};
+
+/// Run `f` on the buffers, with interrupts disabled.
+fn with_buffers<R>(f: impl FnOnce(&mut Buffers) -> R) -> R {
+    interrupt::free(|| unsafe { f(&mut *ptr::addr_of_mut!(BUFFERS)) })
+}
+
+/// Take the bus out of its static, so the transfers run with interrupts enabled, and put it back
+/// afterwards.
+fn with_bus<R>(f: impl FnOnce(Option<Bus>) -> (Option<Bus>, R)) -> R {
+    let bus = interrupt::free(|| unsafe { (*ptr::addr_of_mut!(BUS)).take() });
+    let (bus, result) = f(bus);
+    interrupt::free(|| unsafe { *ptr::addr_of_mut!(BUS) = bus });
+    result
+}
+
+/// Run `f` on the driver, if the bus runs.
+fn with_iic<R>(f: impl FnOnce(&mut Iic<Blocking>) -> R) -> Result<R, Error> {
+    with_bus(|mut bus| {
+        let result = match &mut bus {
+            Some(Bus::Running(iic)) => Ok(f(iic)),
+            _ => Err(Error::NotStarted),
+        };
+        (bus, result)
+    })
+}
+
+/// Keep the pins of the bus, called by [`super::begin`].
+pub(super) fn give_pins(scl: P100<PinModeUnknown>, sda: P101<PinModeUnknown>) {
+    interrupt::free(|| unsafe { *ptr::addr_of_mut!(BUS) = Some(Bus::Stopped(scl, sda)) });
+}
+
+/// The I2C bus of sketches, see [`Wire`].
+pub struct TwoWire {
+    _private: (),
+}
+
+/// The I2C bus, named like the one of the Arduino core.
+#[allow(non_upper_case_globals)]
+pub static Wire: TwoWire = TwoWire { _private: () };
+
+impl TwoWire {
+    /// Start the bus at 100 kHz. Does nothing if it runs already.
+    pub fn begin(&self) -> Result<(), Error> {
+        self.start(Speed::Standard)
+    }
+
+    /// Stop the bus. A4 and A5 keep no function until [`pin_mode`](super::pin_mode).
+    pub fn end(&self) {
+        with_bus(|bus| match bus {
+            Some(Bus::Running(iic)) => {
+                let (scl, sda) = iic.release();
+                (Some(Bus::Stopped(scl, sda)), ())
+            }
+            bus => (bus, ()),
+        });
+    }
+
+    /// Set the bit rate: 400 kHz from `frequency` 400 000 on, 100 kHz below. Starts the bus if it
+    /// doesn't run.
+    pub fn set_clock(&self, frequency: u32) -> Result<(), Error> {
+        self.end();
+        let speed = if frequency >= 400_000 {
+            Speed::Fast
+        } else {
+            Speed::Standard
+        };
+        self.start(speed)
+    }
+
+    fn start(&self, speed: Speed) -> Result<(), Error> {
+        with_bus(|bus| match bus {
+            Some(Bus::Stopped(scl, sda)) => {
+                let iic = Iic::new(scl, sda, mstp::token(), &Cgc::current(), speed);
+                (Some(Bus::Running(iic)), Ok(()))
+            }
+            Some(bus) => (Some(bus), Ok(())),
+            None => (None, Err(Error::NotStarted)),
+        })
+    }
+
+    /// Start collecting the bytes of a transmission to the device at `address`.
+    pub fn begin_transmission(&self, address: u8) {
+        with_buffers(|buffers| {
+            buffers.address = address;
+            buffers.tx_len = 0;
+            buffers.tx_pending = false;
+        });
+    }
+
+    /// Add `data` to the transmission, and return the number of bytes that fit in the buffer.
+    pub fn write(&self, data: &[u8]) -> usize {
+        with_buffers(|buffers| {
+            let count = data.len().min(WIRE_BUFFER_SIZE - buffers.tx_len);
+            buffers.tx[buffers.tx_len..][..count].copy_from_slice(&data[..count]);
+            buffers.tx_len += count;
+            count
+        })
+    }
+
+    /// Send the transmission, ending it with a stop condition.
+    pub fn end_transmission(&self) -> Result<(), Error> {
+        let (address, tx, tx_len) =
+            with_buffers(|buffers| (buffers.address, buffers.tx, buffers.tx_len));
+        with_iic(|iic| iic.write(address, &tx[..tx_len]))?.map_err(Error::I2c)
+    }
+
+    /// Keep the transmission for the next [`TwoWire::request_from`], which sends it followed by a
+    /// repeated start condition instead of a stop condition. This is `endTransmission(false)` in
+    /// the Arduino core.
+    pub fn end_transmission_without_stop(&self) {
+        with_buffers(|buffers| buffers.tx_pending = true);
+    }
+
+    /// Read `quantity` bytes from the device at `address`, up to [`WIRE_BUFFER_SIZE`], and
+    /// return how many were read.
+    pub fn request_from(&self, address: u8, quantity: usize) -> Result<usize, Error> {
+        let count = quantity.min(WIRE_BUFFER_SIZE);
+        let (tx, tx_len) = with_buffers(|buffers| {
+            let tx_len = if buffers.tx_pending && buffers.address == address {
+                buffers.tx_len
+            } else {
+                0
+            };
+            buffers.tx_pending = false;
+            buffers.rx_start = 0;
+            buffers.rx_len = 0;
+            (buffers.tx, tx_len)
+        });
+        let mut rx = [0; WIRE_BUFFER_SIZE];
+        with_iic(|iic| iic.write_read(address, &tx[..tx_len], &mut rx[..count]))?
+            .map_err(Error::I2c)?;
+        with_buffers(|buffers| {
+            buffers.rx = rx;
+            buffers.rx_len = count;
+        });
+        Ok(count)
+    }
+
+    /// Returns the number of received bytes that haven't been read.
+    pub fn available(&self) -> usize {
+        with_buffers(|buffers| buffers.rx_len)
+    }
+
+    /// Returns the next received byte, if there is one.
+    pub fn read(&self) -> Option<u8> {
+        with_buffers(|buffers| {
+            let byte = (buffers.rx_len > 0).then(|| buffers.rx[buffers.rx_start])?;
+            buffers.rx_start += 1;
+            buffers.rx_len -= 1;
+            Some(byte)
+        })
+    }
+
+    /// Returns the next received byte without taking it, if there is one.
+    pub fn peek(&self) -> Option<u8> {
+        with_buffers(|buffers| (buffers.rx_len > 0).then(|| buffers.rx[buffers.rx_start]))
+    }
+}
@@ -0,0 +1,703 @@
+//! AT command engine for the ESP-AT firmware of the ESP32-S3, on top of the serial link of
+//! [`Esp32`].
+//!
+//! [`At`] sends one command at a time and reads the answer line by line until the final result:
+//! `OK` or `SEND OK` for success, `ERROR`, `FAIL`, `SEND FAIL` or `busy ...` for the [`Error`]s.
+//! [`At::query`] hands the other lines of the answer to a closure, e.g. the `+CWJAP:` line of
+//! `AT+CWJAP?`. Each command has its own timeout, in ms.
+//!
+//! The firmware also sends unsolicited result codes (URCs) at any time, e.g. `WIFI DISCONNECT` or
+//! `+IPD` with received data. They are recognized by their prefix, also in the middle of the answer
+//! to a command, and kept in a buffer of [`URC_BUFFER_SIZE`] bytes until they are taken with
+//! [`At::next_urc`], or picked out with [`At::take_urc`]. Lines that arrive while no command runs
+//! are URCs as well.
+//!
+//! Binary data in `+IPD,<link>,<length>:<data>`, `+CIPRECVDATA:<length>,<data>` and the `+WRITE`
+//! of BLE is read by its length, so it may contain line breaks. [`At::send_data`] waits for the
+//! `>` prompt of commands like `AT+CIPSEND` and then sends the data.
+//!
+//! [`At::new`] switches off the echo of commands, and echoed commands are skipped anyway, e.g.
+//! after the ESP32-S3 restarted on its own. Commands can also be queued with [`At::enqueue`], and
+//! are sent in order by [`At::flush`] or before the next command that is sent directly.
+//!
+//! For the commands, see the ESP-AT User Guide.
+//! <https://docs.espressif.com/projects/esp-at/en/latest/esp32s3/AT_Command_Set/index.html>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::at::{self, At, Quoted};
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::esp32::Esp32;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let mut esp32 = Esp32::new(pins.p109, pins.p110, mstp::token(), &clocks).unwrap();
+//! esp32.reset().unwrap();
+//! let mut at = At::new(esp32, &clocks).unwrap();
+//! at.execute(format_args!("AT+CWMODE=1"), 1000).unwrap();
+//! at.execute(format_args!("AT+CWJAP={},{}", Quoted("my network"), Quoted("secret")), 20_000)
+//!     .unwrap();
+//! let mut rssi = None;
+//! at.query(format_args!("AT+CWJAP?"), 1000, |line| {
+//!     if let Some(fields) = line.strip_prefix(b"+CWJAP:") {
+//!         rssi = at::fields(fields).nth(3).and_then(at::parse_int);
+//!     }
+//! })
+//! .unwrap();
+//! ```
+
+use crate::peripherals::clocks::{self, Clocks};
+use crate::peripherals::esp32::{self, Esp32};
+
+use core::fmt::{self, Write};
+
+/// Maximum length of a line, including the data of `+IPD` and `+CIPRECVDATA`.
+pub const LINE_SIZE: usize = 1536;
+
+/// Size of the buffer for URCs. Each URC takes two more bytes for its length.
+pub const URC_BUFFER_SIZE: usize = 2048;
+
+/// Number of bytes at the start of a URC that [`At::take_urc`] checks.
+pub const URC_HEAD_SIZE: usize = 48;
+
+/// Maximum length of a command, without the line break.
+pub const COMMAND_SIZE: usize = 254;
+
+/// Maximum length of a queued command, without the line break.
+pub const QUEUED_COMMAND_SIZE: usize = 126;
+
+/// Number of commands that can be queued.
+pub const QUEUE_SIZE: usize = 8;
+
+/// Prefixes of the URCs. Lines with the prefix of the running command, e.g. `+BLECONN:` for
+/// `AT+BLECONN?`, are part of its answer though.
+const URC_PREFIXES: [&[u8]; 15] = [
+    b"+IPD,",
+    b"WIFI ",
+    b"+STA_CONNECTED:",
+    b"+STA_DISCONNECTED:",
+    b"+DIST_STA_IP:",
+    b"+BLECONN:",
+    b"+BLEDISCONN:",
+    b"+BLECONNPARAM:",
+    b"+WRITE:",
+    b"+READ:",
+    b"+MQTTCONNECTED:",
+    b"+MQTTDISCONNECTED:",
+    b"+MQTTSUBRECV:",
+    b"+TIME_UPDATED",
+    b"ready",
+];
+
+/// Errors of a command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Error of the serial link.
+    Link(esp32::Error),
+    /// No final result arrived in time.
+    Timeout,
+    /// The firmware answered `ERROR` or `FAIL`, with the code of its `ERR CODE` line if it sent
+    /// one.
+    Command(Option<u32>),
+    /// The firmware is still busy with the previous command.
+    Busy,
+    /// The firmware answered `SEND FAIL`, the data wasn't sent.
+    SendFailed,
+    /// A line of the answer didn't fit in [`LINE_SIZE`] bytes and was dropped.
+    LineTooLong,
+    /// The command doesn't fit in [`COMMAND_SIZE`] or [`QUEUED_COMMAND_SIZE`] bytes.
+    CommandTooLong,
+    /// The queue is full.
+    QueueFull,
+}
+
+impl From<esp32::Error> for Error {
+    fn from(error: esp32::Error) -> Self {
+        Error::Link(error)
+    }
+}
+
+/// A string parameter of a command, in quotes and with `"`, `,` and `\` escaped.
+pub struct Quoted<'a>(pub &'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            if matches!(c, '"' | ',' | '\\') {
+                f.write_char('\\')?;
+            }
+            f.write_char(c)?;
+        }
+        f.write_char('"')
+    }
+}
+
+/// Bytes as hex digits, for the parameters that take binary data.
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02X}", byte))
+    }
+}
+
+/// Iterator over the comma-separated fields of an answer, see [`fields`].
+pub struct Fields<'a> {
+    rest: Option<&'a [u8]>,
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let rest = self.rest?;
+        let mut quoted = false;
+        let mut escaped = false;
+        let end = rest.iter().position(|&byte| {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' if quoted => escaped = true,
+                b'"' => quoted = !quoted,
+                b',' if !quoted => return true,
+                _ => {}
+            }
+            false
+        });
+        let field = match end {
+            Some(end) => {
+                self.rest = Some(&rest[end + 1..]);
+                &rest[..end]
+            }
+            None => {
+                self.rest = None;
+                rest
+            }
+        };
+        Some(match field {
+            [b'"', inner @ .., b'"'] => inner,
+            field => field,
+        })
+    }
+}
+
+/// Split the parameters of an answer, e.g. `"my network",-60,6` of a `+CWLAP:` line, at the
+/// commas outside of quotes. Quotes around a field are removed, escapes in it are kept.
+pub fn fields(parameters: &[u8]) -> Fields<'_> {
+    Fields {
+        rest: Some(parameters),
+    }
+}
+
+/// Parse a decimal field, e.g. of [`fields`].
+pub fn parse_int(field: &[u8]) -> Option<i32> {
+    core::str::from_utf8(field).ok()?.parse().ok()
+}
+
+/// Returns true if `line` is a connection URC: `CONNECT`, `CLOSED` or `CONNECT FAIL`, with
+/// `<link>,` in front if the firmware allows several connections.
+fn is_connection_urc(line: &[u8]) -> bool {
+    let status = match line {
+        [link, b',', status @ ..] if link.is_ascii_digit() => status,
+        status => status,
+    };
+    matches!(status, b"CONNECT" | b"CLOSED" | b"CONNECT FAIL")
+}
+
+/// Returns the number of binary bytes that follow the header `line` of `+IPD`, `+CIPRECVDATA` or
+/// `+WRITE`, 0 if it isn't complete or isn't one of these.
+fn binary_length(line: &[u8]) -> usize {
+    let length = if let Some(header) = line
+        .strip_prefix(b"+IPD,")
+        .and_then(|line| line.strip_suffix(b":"))
+    {
+        // +IPD,<length>: or +IPD,<link>,<length>:, optionally followed by the remote address.
+        let mut fields = fields(header);
+        let first = fields.next().and_then(parse_int);
+        fields.next().and_then(parse_int).or(first)
+    } else if let Some(header) = line
+        .strip_prefix(b"+CIPRECVDATA:")
+        .and_then(|line| line.strip_suffix(b","))
+    {
+        parse_int(header)
+    } else if let Some(header) = line
+        .strip_prefix(b"+WRITE:")
+        .and_then(|line| line.strip_suffix(b","))
+    {
+        // +WRITE:<conn>,<service>,<characteristic>,[<descriptor>],<length>,
+        let mut fields = fields(header);
+        match (fields.nth(4), fields.next()) {
+            (Some(length), None) => parse_int(length),
+            _ => None,
+        }
+    } else {
+        None
+    };
+    length.map_or(0, |length| length.max(0) as usize)
+}
+
+/// Received URCs, each stored as its length (u16, little endian) followed by the line.
+struct UrcBuffer {
+    data: [u8; URC_BUFFER_SIZE],
+    start: usize,
+    len: usize,
+    overrun: bool,
+}
+
+impl UrcBuffer {
+    fn push(&mut self, line: &[u8]) {
+        if line.len() + 2 > URC_BUFFER_SIZE - self.len {
+            self.overrun = true;
+            return;
+        }
+        let length = (line.len() as u16).to_le_bytes();
+        for &byte in length.iter().chain(line) {
+            self.push_byte(byte);
+        }
+    }
+
+    fn push_byte(&mut self, byte: u8) {
+        self.data[(self.start + self.len) % URC_BUFFER_SIZE] = byte;
+        self.len += 1;
+    }
+
+    fn pop_byte(&mut self) -> u8 {
+        let byte = self.data[self.start];
+        self.start = (self.start + 1) % URC_BUFFER_SIZE;
+        self.len -= 1;
+        byte
+    }
+
+    /// Move the oldest URC to `buffer`, cut off at its end, and return its length in `buffer`.
+    fn pop(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let length = u16::from_le_bytes([self.pop_byte(), self.pop_byte()]) as usize;
+        for i in 0..length {
+            let byte = self.pop_byte();
+            if let Some(slot) = buffer.get_mut(i) {
+                *slot = byte;
+            }
+        }
+        Some(length.min(buffer.len()))
+    }
+
+    /// Move the oldest URC whose first [`URC_HEAD_SIZE`] bytes `matches` to `buffer`, cut off at
+    /// its end, and return its length in `buffer`. The other URCs keep their order.
+    fn take(&mut self, buffer: &mut [u8], matches: impl Fn(&[u8]) -> bool) -> Option<usize> {
+        let mut found = None;
+        let mut remaining = self.len;
+        while remaining > 0 {
+            let length_bytes = [self.pop_byte(), self.pop_byte()];
+            let length = u16::from_le_bytes(length_bytes) as usize;
+            remaining -= 2 + length;
+            let mut head = [0; URC_HEAD_SIZE];
+            let head_len = length.min(URC_HEAD_SIZE);
+            for (i, slot) in head[..head_len].iter_mut().enumerate() {
+                *slot = self.data[(self.start + i) % URC_BUFFER_SIZE];
+            }
+            if found.is_none() && matches(&head[..head_len]) {
+                for i in 0..length {
+                    let byte = self.pop_byte();
+                    if let Some(slot) = buffer.get_mut(i) {
+                        *slot = byte;
+                    }
+                }
+                found = Some(length.min(buffer.len()));
+            } else {
+                // Move it behind the others, going once around the buffer keeps the order.
+                for byte in length_bytes {
+                    self.push_byte(byte);
+                }
+                for _ in 0..length {
+                    let byte = self.pop_byte();
+                    self.push_byte(byte);
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Formats a command into a byte buffer.
+struct CommandWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for CommandWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.buffer.len() {
+            return Err(fmt::Error);
+        }
+        self.buffer[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Format `command` followed by a line break into `buffer`, and return the length.
+fn format_command(buffer: &mut [u8], command: fmt::Arguments) -> Result<usize, Error> {
+    let mut writer = CommandWriter { buffer, len: 0 };
+    writer
+        .write_fmt(command)
+        .and_then(|_| writer.write_str("\r\n"))
+        .map_err(|_| Error::CommandTooLong)?;
+    Ok(writer.len)
+}
+
+/// A command waiting in the queue.
+#[derive(Clone, Copy)]
+struct Queued {
+    command: [u8; QUEUED_COMMAND_SIZE + 2],
+    len: usize,
+    timeout_ms: u32,
+}
+
+/// What the receiver found.
+enum Input {
+    /// A complete line, in `At::line`.
+    Line,
+    /// The `>` prompt for data.
+    Prompt,
+}
+
+/// The AT command engine.
+pub struct At {
+    link: Esp32,
+    iclk: u32,
+    /// The line being received.
+    line: [u8; LINE_SIZE],
+    line_len: usize,
+    /// The line didn't fit and is being dropped.
+    line_overflow: bool,
+    /// Number of binary bytes of the line still to be received.
+    binary: usize,
+    /// The running command, with its line break, for recognizing its echo and its answer.
+    command: [u8; COMMAND_SIZE + 2],
+    command_len: usize,
+    /// The last `ERR CODE` of the running command.
+    error_code: Option<u32>,
+    /// A line of the answer was dropped.
+    answer_overflow: bool,
+    urcs: UrcBuffer,
+    queue: [Queued; QUEUE_SIZE],
+    queue_len: usize,
+}
+
+impl At {
+    /// Take over the link, switch off the echo and check that the firmware answers.
+    pub fn new(link: Esp32, clocks: &Clocks) -> Result<Self, Error> {
+        let mut at = Self {
+            link,
+            iclk: clocks.iclk(),
+            line: [0; LINE_SIZE],
+            line_len: 0,
+            line_overflow: false,
+            binary: 0,
+            command: [0; COMMAND_SIZE + 2],
+            command_len: 0,
+            error_code: None,
+            answer_overflow: false,
+            urcs: UrcBuffer {
+                data: [0; URC_BUFFER_SIZE],
+                start: 0,
+                len: 0,
+                overrun: false,
+            },
+            queue: [Queued {
+                command: [0; QUEUED_COMMAND_SIZE + 2],
+                len: 0,
+                timeout_ms: 0,
+            }; QUEUE_SIZE],
+            queue_len: 0,
+        };
+        at.execute(format_args!("ATE0"), 1000)?;
+        Ok(at)
+    }
+
+    /// Returns the serial link, e.g. to change the bit rate.
+    pub fn link(&mut self) -> &mut Esp32 {
+        &mut self.link
+    }
+
+    /// Return the serial link. Queued commands and URCs are dropped.
+    pub fn release(self) -> Esp32 {
+        self.link
+    }
+
+    /// Send `command` and wait up to `timeout_ms` ms for the final result. The other lines of the
+    /// answer are ignored.
+    pub fn execute(&mut self, command: fmt::Arguments, timeout_ms: u32) -> Result<(), Error> {
+        self.query(command, timeout_ms, |_| {})
+    }
+
+    /// Send `command` and wait up to `timeout_ms` ms for the final result, calling `on_line` with
+    /// each line of the answer that isn't a URC, the echo or the result.
+    pub fn query(
+        &mut self,
+        command: fmt::Arguments,
+        timeout_ms: u32,
+        mut on_line: impl FnMut(&[u8]),
+    ) -> Result<(), Error> {
+        self.flush()?;
+        self.command_len = format_command(&mut self.command[..], command)?;
+        self.send_command();
+        self.run(timeout_ms, false, &mut on_line)
+    }
+
+    /// Send `command`, wait for the `>` prompt, then send `data` and wait for `SEND OK`, up to
+    /// `timeout_ms` ms each.
+    pub fn send_data(
+        &mut self,
+        command: fmt::Arguments,
+        data: &[u8],
+        timeout_ms: u32,
+    ) -> Result<(), Error> {
+        self.send_data_parts(command, &[data], timeout_ms)
+    }
+
+    /// Like [`At::send_data`], with the data in `parts` that are sent one after the other.
+    pub fn send_data_parts(
+        &mut self,
+        command: fmt::Arguments,
+        parts: &[&[u8]],
+        timeout_ms: u32,
+    ) -> Result<(), Error> {
+        self.flush()?;
+        self.command_len = format_command(&mut self.command[..], command)?;
+        self.send_command();
+        self.run(timeout_ms, true, &mut |_| {})?;
+        for part in parts {
+            self.link.write(part);
+        }
+        self.run(timeout_ms, false, &mut |_| {})
+    }
+
+    /// Add `command` to the queue, to be executed with a timeout of `timeout_ms` ms by
+    /// [`At::flush`] or before the next command.
+    pub fn enqueue(&mut self, command: fmt::Arguments, timeout_ms: u32) -> Result<(), Error> {
+        let queued = self.queue.get_mut(self.queue_len).ok_or(Error::QueueFull)?;
+        queued.len = format_command(&mut queued.command[..], command)?;
+        queued.timeout_ms = timeout_ms;
+        self.queue_len += 1;
+        Ok(())
+    }
+
+    /// Returns the number of queued commands.
+    pub fn queued(&self) -> usize {
+        self.queue_len
+    }
+
+    /// Execute the queued commands in order. If one fails, the rest of the queue is dropped and
+    /// its error is returned.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        while self.queue_len > 0 {
+            let queued = self.queue[0];
+            self.queue.copy_within(1..self.queue_len, 0);
+            self.queue_len -= 1;
+            self.command[..queued.len].copy_from_slice(&queued.command[..queued.len]);
+            self.command_len = queued.len;
+            self.send_command();
+            if let Err(error) = self.run(queued.timeout_ms, false, &mut |_| {}) {
+                self.queue_len = 0;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the received lines without waiting, and keep them as URCs.
+    pub fn process(&mut self) {
+        self.command_len = 0;
+        while let Some(input) = self.receive(false) {
+            if let Input::Line = input {
+                let line = &self.line[..self.line_len];
+                if !self.line_overflow && !line.is_empty() && Self::final_result(line).is_none() {
+                    self.urcs.push(line);
+                }
+                self.next_line();
+            }
+        }
+    }
+
+    /// Wait `ms` ms, keeping the lines received meanwhile as URCs.
+    pub fn delay_ms(&mut self, ms: u32) {
+        for _ in 0..ms.saturating_mul(10) {
+            self.process();
+            clocks::wait_us(self.iclk, 100);
+        }
+    }
+
+    /// Move the oldest URC to `buffer` and return it, cut off at the end of `buffer`. Reads the
+    /// received lines first.
+    pub fn next_urc<'b>(&mut self, buffer: &'b mut [u8]) -> Option<&'b [u8]> {
+        self.process();
+        let len = self.urcs.pop(buffer)?;
+        Some(&buffer[..len])
+    }
+
+    /// Move the oldest URC whose start `matches` to `buffer` and return it, cut off at the end of
+    /// `buffer`. `matches` gets the first [`URC_HEAD_SIZE`] bytes of each URC. Reads the received
+    /// lines first.
+    pub fn take_urc<'b>(
+        &mut self,
+        buffer: &'b mut [u8],
+        matches: impl Fn(&[u8]) -> bool,
+    ) -> Option<&'b [u8]> {
+        self.process();
+        let len = self.urcs.take(buffer, matches)?;
+        Some(&buffer[..len])
+    }
+
+    /// Returns true if URCs were dropped since the last call because the buffer was full.
+    pub fn take_urc_overrun(&mut self) -> bool {
+        core::mem::replace(&mut self.urcs.overrun, false)
+    }
+
+    /// Collect what has been received so far as URCs, then send the command in `self.command`.
+    fn send_command(&mut self) {
+        let len = self.command_len;
+        self.process();
+        self.command_len = len;
+        self.error_code = None;
+        self.answer_overflow = false;
+        self.link.write(&self.command[..len]);
+    }
+
+    /// Read the answer to the running command until its final result, or the prompt if `prompt`
+    /// is true.
+    fn run(
+        &mut self,
+        timeout_ms: u32,
+        prompt: bool,
+        on_line: &mut dyn FnMut(&[u8]),
+    ) -> Result<(), Error> {
+        for _ in 0..=timeout_ms.saturating_mul(10) {
+            while let Some(input) = self.receive(prompt) {
+                let result = match input {
+                    Input::Prompt => Some(Ok(())),
+                    Input::Line => self.handle_line(prompt, on_line),
+                };
+                self.next_line();
+                if let Some(result) = result {
+                    self.command_len = 0;
+                    return match result {
+                        Ok(()) if self.answer_overflow => Err(Error::LineTooLong),
+                        result => result,
+                    };
+                }
+            }
+            clocks::wait_us(self.iclk, 100);
+        }
+        self.command_len = 0;
+        Err(Error::Timeout)
+    }
+
+    /// Move received bytes to the line until it is complete.
+    fn receive(&mut self, prompt: bool) -> Option<Input> {
+        while let Some(byte) = self.link.read_byte() {
+            if self.binary > 0 {
+                self.binary -= 1;
+                self.push_line(byte);
+                if self.binary == 0 {
+                    return Some(Input::Line);
+                }
+            } else if byte == b'>' && prompt && self.line_len == 0 {
+                return Some(Input::Prompt);
+            } else if byte == b'\n' {
+                if self.line[..self.line_len].ends_with(b"\r") {
+                    self.line_len -= 1;
+                }
+                return Some(Input::Line);
+            } else {
+                self.push_line(byte);
+                if matches!(byte, b':' | b',') && !self.line_overflow {
+                    self.binary = binary_length(&self.line[..self.line_len]);
+                }
+            }
+        }
+        None
+    }
+
+    fn push_line(&mut self, byte: u8) {
+        match self.line.get_mut(self.line_len) {
+            Some(slot) => {
+                *slot = byte;
+                self.line_len += 1;
+            }
+            None => self.line_overflow = true,
+        }
+    }
+
+    fn next_line(&mut self) {
+        self.line_len = 0;
+        self.line_overflow = false;
+    }
+
+    /// Returns the result if `line` is a final result.
+    fn final_result(line: &[u8]) -> Option<Result<(), Error>> {
+        match line {
+            b"OK" | b"SEND OK" => Some(Ok(())),
+            b"ERROR" | b"FAIL" => Some(Err(Error::Command(None))),
+            b"SEND FAIL" => Some(Err(Error::SendFailed)),
+            _ if line.starts_with(b"busy ") => Some(Err(Error::Busy)),
+            _ => None,
+        }
+    }
+
+    /// Handle a complete line of the answer to the running command. Returns the result if the
+    /// command is done.
+    fn handle_line(
+        &mut self,
+        prompt: bool,
+        on_line: &mut dyn FnMut(&[u8]),
+    ) -> Option<Result<(), Error>> {
+        if self.line_overflow {
+            self.answer_overflow = true;
+            return None;
+        }
+        let line = &self.line[..self.line_len];
+        let command = &self.command[..self.command_len];
+        if line.is_empty() || command.strip_suffix(b"\r\n") == Some(line) {
+            return None;
+        }
+        if let Some(code) = line.strip_prefix(b"ERR CODE:0x") {
+            self.error_code = core::str::from_utf8(code)
+                .ok()
+                .and_then(|code| u32::from_str_radix(code, 16).ok());
+            return None;
+        }
+        match Self::final_result(line) {
+            // Commands with data answer OK before the prompt.
+            Some(Ok(())) if prompt => return None,
+            Some(Err(Error::Command(None))) => return Some(Err(Error::Command(self.error_code))),
+            Some(result) => return Some(result),
+            None => {}
+        }
+        // The name of the command, e.g. "+CWJAP" of "AT+CWJAP?".
+        let name = command.get(2..).unwrap_or_default();
+        let name = &name[..name
+            .iter()
+            .position(|byte| matches!(byte, b'=' | b'?' | b'\r'))
+            .unwrap_or(name.len())];
+        let own = !name.is_empty()
+            && line
+                .strip_prefix(name)
+                .is_some_and(|rest| rest.starts_with(b":"));
+        if !own
+            && (URC_PREFIXES.iter().any(|prefix| line.starts_with(prefix))
+                || is_connection_urc(line))
+        {
+            self.urcs.push(line);
+        } else {
+            on_line(line);
+        }
+        None
+    }
+}
@@ -0,0 +1,451 @@
+//! Bluetooth LE peripheral through the ESP32-S3, with the AT commands of [`crate::at`].
+//!
+//! [`Ble`] makes the ESP32-S3 a GATT server that phones can connect to, without WiFi:
+//! * [`Ble::advertise`] starts advertising with the name, service UUID and manufacturer data of an
+//!   [`Advertising`].
+//! * The services come from the GATT table in the firmware, which can't be changed at runtime.
+//!   [`Ble::characteristics`] lists their characteristics with UUIDs and properties, e.g. read,
+//!   write and notify. The default table of ESP-AT has the service `0xA002` with the
+//!   characteristics `0xC300` to `0xC307`.
+//! * [`Ble::set_value`] sets the value that the phone reads, [`Ble::notify`] and [`Ble::indicate`]
+//!   send it to a connected phone.
+//! * [`Ble::next_event`] returns connections, disconnections and values the phone wrote, from the
+//!   URCs of the [`At`] engine. Other URCs stay in the buffer.
+//!
+//! Like the handles of [`crate::wifi`], [`Ble`] borrows the engine and keeps no state of its own.
+//!
+//! For the commands, see the ESP-AT User Guide, "Bluetooth LE AT Commands".
+//! <https://docs.espressif.com/projects/esp-at/en/latest/esp32s3/AT_Command_Set/BLE_AT_Commands.html>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::ble::{Advertising, Ble, Event};
+//!
+//! // With `at` set up as in the `at` example.
+//! let mut ble = Ble::new(&mut at).unwrap();
+//! ble.advertise(&Advertising::new("UNO R4").with_service_uuid("A002")).unwrap();
+//! ble.set_value(1, 1, b"hello").unwrap();
+//! let mut buffer = [0; 256];
+//! loop {
+//!     match ble.next_event(&mut buffer) {
+//!         Some(Event::Connected { connection, .. }) => {
+//!             ble.notify(connection, 1, 3, b"welcome").unwrap();
+//!         }
+//!         Some(Event::Write { value, .. }) => {
+//!             // Handle the value.
+//!         }
+//!         _ => {}
+//!     }
+//! }
+//! ```
+
+use crate::at::{self, At, Hex, Quoted};
+
+/// Time for simple commands, in ms.
+const COMMAND_TIMEOUT_MS: u32 = 1000;
+
+/// Time for initializing the BLE stack, in ms.
+const INIT_TIMEOUT_MS: u32 = 5000;
+
+/// Role of `AT+BLEINIT` for a server.
+const ROLE_SERVER: i32 = 2;
+
+/// Advertising type of `AT+BLEADVPARAM`: connectable and scannable undirected (`ADV_IND`).
+const ADV_TYPE_IND: u8 = 0;
+
+/// All three advertising channels.
+const ADV_CHANNELS_ALL: u8 = 0b111;
+
+/// Properties of a characteristic.
+/// * b1: Read
+/// * b2: Write without response
+/// * b3: Write
+/// * b4: Notify
+/// * b5: Indicate
+pub const PROPERTY_READ: u8 = 1 << 1;
+pub const PROPERTY_WRITE_WITHOUT_RESPONSE: u8 = 1 << 2;
+pub const PROPERTY_WRITE: u8 = 1 << 3;
+pub const PROPERTY_NOTIFY: u8 = 1 << 4;
+pub const PROPERTY_INDICATE: u8 = 1 << 5;
+
+/// Errors of the BLE functions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Error of the AT command.
+    At(at::Error),
+    /// The settings are out of the range the firmware accepts.
+    InvalidConfig,
+    /// The firmware sent an answer that can't be parsed.
+    InvalidResponse,
+}
+
+impl From<at::Error> for Error {
+    fn from(error: at::Error) -> Self {
+        Error::At(error)
+    }
+}
+
+/// Advertising settings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Advertising<'a> {
+    /// Device name, also set as the GAP name.
+    pub name: &'a str,
+    /// UUID of a service, in hex, e.g. `A002`, or empty.
+    pub service_uuid: &'a str,
+    /// Manufacturer specific data, starting with the company ID.
+    pub manufacturer_data: &'a [u8],
+    /// Advertise the transmit power.
+    pub include_tx_power: bool,
+    /// Minimum and maximum advertising interval, in units of 0.625 ms, 0x20-0x4000.
+    pub interval_min: u16,
+    pub interval_max: u16,
+}
+
+impl<'a> Advertising<'a> {
+    /// Advertise `name` every 100 ms to 200 ms.
+    pub fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            service_uuid: "",
+            manufacturer_data: &[],
+            include_tx_power: false,
+            interval_min: 160,
+            interval_max: 320,
+        }
+    }
+
+    /// Set the service UUID.
+    pub fn with_service_uuid(self, service_uuid: &'a str) -> Self {
+        Self {
+            service_uuid,
+            ..self
+        }
+    }
+
+    /// Set the manufacturer specific data.
+    pub fn with_manufacturer_data(self, manufacturer_data: &'a [u8]) -> Self {
+        Self {
+            manufacturer_data,
+            ..self
+        }
+    }
+
+    /// Advertise the transmit power.
+    pub fn with_tx_power(self, include_tx_power: bool) -> Self {
+        Self {
+            include_tx_power,
+            ..self
+        }
+    }
+
+    /// Set the advertising interval, in units of 0.625 ms.
+    pub fn with_interval(self, interval_min: u16, interval_max: u16) -> Self {
+        Self {
+            interval_min,
+            interval_max,
+            ..self
+        }
+    }
+
+    /// Returns true if the firmware accepts the settings. The advertising data has room for 31
+    /// bytes, which isn't checked.
+    fn is_valid(&self) -> bool {
+        (0x20..=0x4000).contains(&self.interval_min)
+            && (self.interval_min..=0x4000).contains(&self.interval_max)
+            && self
+                .service_uuid
+                .bytes()
+                .all(|byte| byte.is_ascii_hexdigit())
+    }
+}
+
+/// A characteristic of the GATT table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Characteristic {
+    /// Index of the service, from 1.
+    pub service: u8,
+    /// Index of the characteristic in the service, from 1.
+    pub index: u8,
+    /// UUID, 16 or 128 bits.
+    pub uuid: u128,
+    /// `PROPERTY_*` bits.
+    pub properties: u8,
+}
+
+/// What happened on the BLE connection, see [`Ble::next_event`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event<'b> {
+    /// A central connected.
+    Connected { connection: u8, address: [u8; 6] },
+    /// A central disconnected.
+    Disconnected { connection: u8, address: [u8; 6] },
+    /// A central wrote `value` to a characteristic, or to one of its descriptors, e.g. to enable
+    /// notifications.
+    Write {
+        connection: u8,
+        service: u8,
+        characteristic: u8,
+        descriptor: Option<u8>,
+        value: &'b [u8],
+    },
+}
+
+impl<'b> Event<'b> {
+    fn parse(urc: &'b [u8]) -> Option<Self> {
+        if let Some(parameters) = urc.strip_prefix(b"+BLECONN:") {
+            let mut fields = at::fields(parameters);
+            let connection = at::parse_int(fields.next()?)? as u8;
+            let address = parse_address(fields.next()?)?;
+            Some(Event::Connected {
+                connection,
+                address,
+            })
+        } else if let Some(parameters) = urc.strip_prefix(b"+BLEDISCONN:") {
+            let mut fields = at::fields(parameters);
+            let connection = at::parse_int(fields.next()?)? as u8;
+            let address = parse_address(fields.next()?)?;
+            Some(Event::Disconnected {
+                connection,
+                address,
+            })
+        } else if let Some(parameters) = urc.strip_prefix(b"+WRITE:") {
+            // The value is binary, so only the fields in front of it are split.
+            let mut header = parameters.splitn(6, |&byte| byte == b',');
+            let connection = at::parse_int(header.next()?)? as u8;
+            let service = at::parse_int(header.next()?)? as u8;
+            let characteristic = at::parse_int(header.next()?)? as u8;
+            let descriptor = at::parse_int(header.next()?).map(|index| index as u8);
+            let length = at::parse_int(header.next()?)? as usize;
+            let value = header.next()?;
+            Some(Event::Write {
+                connection,
+                service,
+                characteristic,
+                descriptor,
+                value: value.get(..length).unwrap_or(value),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Parse a BLE address like `24:0a:c4:00:01:02`.
+fn parse_address(field: &[u8]) -> Option<[u8; 6]> {
+    let mut address = [0; 6];
+    let mut parts = field.split(|&byte| byte == b':');
+    for byte in address.iter_mut() {
+        let part = core::str::from_utf8(parts.next()?).ok()?;
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    parts.next().is_none().then_some(address)
+}
+
+/// Parse a UUID like `0xC300`.
+fn parse_uuid(field: &[u8]) -> Option<u128> {
+    let digits = core::str::from_utf8(field).ok()?;
+    let digits = digits.strip_prefix("0x").unwrap_or(digits);
+    u128::from_str_radix(digits, 16).ok()
+}
+
+/// The BLE server.
+pub struct Ble<'a> {
+    at: &'a mut At,
+}
+
+impl<'a> Ble<'a> {
+    /// Initialize the BLE stack as a server and start the services of the GATT table.
+    pub fn new(at: &'a mut At) -> Result<Self, Error> {
+        let mut role = None;
+        at.query(format_args!("AT+BLEINIT?"), COMMAND_TIMEOUT_MS, |line| {
+            if let Some(parameters) = line.strip_prefix(b"+BLEINIT:") {
+                role = at::parse_int(parameters);
+            }
+        })?;
+        if role != Some(ROLE_SERVER) {
+            at.execute(format_args!("AT+BLEINIT={}", ROLE_SERVER), INIT_TIMEOUT_MS)?;
+            at.execute(format_args!("AT+BLEGATTSSRVCRE"), COMMAND_TIMEOUT_MS)?;
+            at.execute(format_args!("AT+BLEGATTSSRVSTART"), COMMAND_TIMEOUT_MS)?;
+        }
+        Ok(Self { at })
+    }
+
+    /// Start advertising with `advertising`.
+    pub fn advertise(&mut self, advertising: &Advertising) -> Result<(), Error> {
+        if !advertising.is_valid() {
+            return Err(Error::InvalidConfig);
+        }
+        self.at.execute(
+            format_args!("AT+BLENAME={}", Quoted(advertising.name)),
+            COMMAND_TIMEOUT_MS,
+        )?;
+        self.at.execute(
+            format_args!(
+                "AT+BLEADVPARAM={},{},{},0,{}",
+                advertising.interval_min, advertising.interval_max, ADV_TYPE_IND, ADV_CHANNELS_ALL
+            ),
+            COMMAND_TIMEOUT_MS,
+        )?;
+        self.at.execute(
+            format_args!(
+                "AT+BLEADVDATAEX={},\"{}\",\"{}\",{}",
+                Quoted(advertising.name),
+                advertising.service_uuid,
+                Hex(advertising.manufacturer_data),
+                advertising.include_tx_power as u8
+            ),
+            COMMAND_TIMEOUT_MS,
+        )?;
+        self.at
+            .execute(format_args!("AT+BLEADVSTART"), COMMAND_TIMEOUT_MS)?;
+        Ok(())
+    }
+
+    /// Stop advertising. Connections are kept.
+    pub fn stop_advertising(&mut self) -> Result<(), Error> {
+        self.at
+            .execute(format_args!("AT+BLEADVSTOP"), COMMAND_TIMEOUT_MS)?;
+        Ok(())
+    }
+
+    /// Store the characteristics of the GATT table in `characteristics` and return how many, up
+    /// to the length of `characteristics`.
+    pub fn characteristics(
+        &mut self,
+        characteristics: &mut [Characteristic],
+    ) -> Result<usize, Error> {
+        let mut count = 0;
+        let mut invalid = false;
+        self.at.query(
+            format_args!("AT+BLEGATTSCHAR?"),
+            COMMAND_TIMEOUT_MS,
+            |line| {
+                let Some(parameters) = line.strip_prefix(b"+BLEGATTSCHAR:") else {
+                    return;
+                };
+                let mut fields = at::fields(parameters);
+                // Descriptors are listed as well, with "desc" in front.
+                if fields.next() != Some(b"char") {
+                    return;
+                }
+                let service = fields.next().and_then(at::parse_int);
+                let index = fields.next().and_then(at::parse_int);
+                let uuid = fields.next().and_then(parse_uuid);
+                let properties = fields.next().and_then(parse_uuid);
+                match (service, index, uuid, properties) {
+                    (Some(service), Some(index), Some(uuid), Some(properties)) => {
+                        if let Some(slot) = characteristics.get_mut(count) {
+                            *slot = Characteristic {
+                                service: service as u8,
+                                index: index as u8,
+                                uuid,
+                                properties: properties as u8,
+                            };
+                            count += 1;
+                        }
+                    }
+                    _ => invalid = true,
+                }
+            },
+        )?;
+        if invalid {
+            return Err(Error::InvalidResponse);
+        }
+        Ok(count)
+    }
+
+    /// Set the value of `characteristic` of `service`, which centrals read.
+    pub fn set_value(
+        &mut self,
+        service: u8,
+        characteristic: u8,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        self.at.send_data(
+            format_args!(
+                "AT+BLEGATTSSETATTR={},{},,{}",
+                service,
+                characteristic,
+                value.len()
+            ),
+            value,
+            COMMAND_TIMEOUT_MS,
+        )?;
+        Ok(())
+    }
+
+    /// Send `value` of `characteristic` of `service` as a notification to `connection`.
+    pub fn notify(
+        &mut self,
+        connection: u8,
+        service: u8,
+        characteristic: u8,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        self.at.send_data(
+            format_args!(
+                "AT+BLEGATTSNTFY={},{},{},{}",
+                connection,
+                service,
+                characteristic,
+                value.len()
+            ),
+            value,
+            COMMAND_TIMEOUT_MS,
+        )?;
+        Ok(())
+    }
+
+    /// Send `value` of `characteristic` of `service` as an indication to `connection`, which
+    /// the central confirms.
+    pub fn indicate(
+        &mut self,
+        connection: u8,
+        service: u8,
+        characteristic: u8,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        self.at.send_data(
+            format_args!(
+                "AT+BLEGATTSIND={},{},{},{}",
+                connection,
+                service,
+                characteristic,
+                value.len()
+            ),
+            value,
+            COMMAND_TIMEOUT_MS,
+        )?;
+        Ok(())
+    }
+
+    /// Drop `connection`.
+    pub fn disconnect(&mut self, connection: u8) -> Result<(), Error> {
+        self.at.execute(
+            format_args!("AT+BLEDISCONN={}", connection),
+            COMMAND_TIMEOUT_MS,
+        )?;
+        Ok(())
+    }
+
+    /// Returns the oldest BLE event, with the written value in `buffer`.
+    pub fn next_event<'b>(&mut self, buffer: &'b mut [u8]) -> Option<Event<'b>> {
+        let urc = self.at.take_urc(buffer, |head| {
+            head.starts_with(b"+BLECONN:")
+                || head.starts_with(b"+BLEDISCONN:")
+                || head.starts_with(b"+WRITE:")
+        })?;
+        Event::parse(urc)
+    }
+
+    /// Stop advertising, drop the connections and deinitialize the BLE stack.
+    pub fn stop(self) -> Result<(), Error> {
+        self.at
+            .execute(format_args!("AT+BLEINIT=0"), INIT_TIMEOUT_MS)?;
+        Ok(())
+    }
+}
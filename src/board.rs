@@ -0,0 +1,152 @@
+//! Bring-up of the board in one call, like the Arduino core does before `setup()`.
+//!
+//! [`init`] sets the clocks to the ones of the Arduino core, ICLK at 48 MHz from the HOCO, turns
+//! on the FPU, hands the SysTick timer to a [`Delay`] and starts counting
+//! [`millis`](crate::peripherals::millis::millis). With the `arduino` feature, it starts
+//! [`crate::arduino`] with [`arduino::begin`](crate::arduino::begin), which takes the pins on the
+//! headers, and with the `usbd-serial` feature, it installs the USB serial port (see
+//! [`crate::peripherals::usb::serial`]).
+//!
+//! The rest is returned in [`Peripherals`]: The clocks, the pins and the peripherals that are taken
+//! as a whole. The drivers that take pins or settings are constructed from those, with the token
+//! of their unit from [`mstp::token`].
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::board;
+//! use arduino_uno_r4_wifi_rt::peripherals::crc::Algorithm;
+//!
+//! let mut board = board::init().unwrap();
+//! board.delay.delay_ms(100);
+//! let checksum = board.crc.checksum(Algorithm::CRC_32, b"123456789");
+//! ```
+
+#[cfg(feature = "arduino")]
+use crate::arduino::{self, OtherPins};
+use crate::interrupt;
+use crate::peripherals::clocks::{self, Cgc, Clocks, Config};
+use crate::peripherals::crc::Crc;
+use crate::peripherals::dma;
+use crate::peripherals::flash::{Blocking, CodeFlash, DataFlash};
+use crate::peripherals::iwdt::Iwdt;
+use crate::peripherals::millis;
+use crate::peripherals::mstp;
+use crate::peripherals::pins::get_pins;
+#[cfg(not(feature = "arduino"))]
+use crate::peripherals::pins::ArduinoPins;
+use crate::peripherals::rtc::Rtc;
+use crate::peripherals::systick::{Delay, SysTick};
+use crate::peripherals::wdt::{self, Wdt};
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Coprocessor Access Control Register.
+/// * b20-b23: CP10 and CP11, access to the FPU. 0b1111 for full access.
+const CPACR: *mut u32 = 0xe000ed88 as *mut u32;
+
+const CPACR_FPU: u32 = 0b1111 << 20;
+
+/// Set by [`init`].
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Errors of [`init`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// [`init`] was called before.
+    AlreadyInitialized,
+    /// A peripheral of [`Peripherals`] was taken before [`init`].
+    AlreadyTaken,
+    /// The clocks can't be set.
+    Clocks(clocks::Error),
+    /// The milliseconds can't be counted.
+    Millis(millis::Error),
+    /// [`arduino::begin`] failed.
+    #[cfg(feature = "arduino")]
+    Arduino(arduino::Error),
+    /// The USB serial port can't be installed, because all interrupt slots are in use.
+    #[cfg(feature = "usbd-serial")]
+    NoFreeSlot,
+}
+
+/// The pins that [`init`] returns: All of them, or those that [`arduino::begin`] doesn't take.
+#[cfg(feature = "arduino")]
+pub type Pins = OtherPins;
+#[cfg(not(feature = "arduino"))]
+pub type Pins = ArduinoPins;
+
+/// What [`init`] sets up, and the peripherals that are taken as a whole.
+pub struct Peripherals {
+    pub clocks: Clocks,
+    pub pins: Pins,
+    pub delay: Delay,
+    pub rtc: Rtc,
+    pub crc: Crc,
+    pub dma: dma::Channels,
+    pub data_flash: DataFlash<Blocking>,
+    pub code_flash: CodeFlash,
+    pub iwdt: Iwdt,
+    pub wdt: Wdt<wdt::Stopped>,
+}
+
+/// Set up the board, see the module documentation.
+///
+/// Returns [`Error::AlreadyInitialized`] if it was called before.
+pub fn init() -> Result<Peripherals, Error> {
+    if INITIALIZED.swap(true, Ordering::AcqRel) {
+        return Err(Error::AlreadyInitialized);
+    }
+    enable_fpu();
+    let cgc = Cgc::take().ok_or(Error::AlreadyTaken)?;
+    let clocks = cgc.freeze(&Config::default()).map_err(Error::Clocks)?;
+    let pins = get_pins().ok_or(Error::AlreadyTaken)?;
+    let systick = SysTick::instance().ok_or(Error::AlreadyTaken)?;
+    let delay = Delay::new(systick, &clocks);
+    let rtc = Rtc::take().ok_or(Error::AlreadyTaken)?;
+    let crc = Crc::take(mstp::token()).ok_or(Error::AlreadyTaken)?;
+    let dma = dma::Channels::take(mstp::token()).ok_or(Error::AlreadyTaken)?;
+    let data_flash = DataFlash::take(&clocks).ok_or(Error::AlreadyTaken)?;
+    let code_flash = CodeFlash::take(&clocks).ok_or(Error::AlreadyTaken)?;
+    let iwdt = Iwdt::take().ok_or(Error::AlreadyTaken)?;
+    let wdt = Wdt::take().ok_or(Error::AlreadyTaken)?;
+    millis::start(mstp::token(), &clocks).map_err(Error::Millis)?;
+    #[cfg(feature = "arduino")]
+    let pins = arduino::begin(pins, &clocks).map_err(Error::Arduino)?;
+    #[cfg(feature = "usbd-serial")]
+    install_usb_serial()?;
+    Ok(Peripherals {
+        clocks,
+        pins,
+        delay,
+        rtc,
+        crc,
+        dma,
+        data_flash,
+        code_flash,
+        iwdt,
+        wdt,
+    })
+}
+
+/// Give the CPU full access to the FPU. The crate is built for soft floats, but code built for
+/// `thumbv7em-none-eabihf` can use it then.
+fn enable_fpu() {
+    interrupt::free(|| unsafe {
+        CPACR.write_volatile(CPACR.read_volatile() | CPACR_FPU);
+        core::arch::asm!("dsb", "isb", options(nostack, preserves_flags));
+    });
+}
+
+/// Install the USB serial port, polled from the USB interrupt.
+#[cfg(feature = "usbd-serial")]
+fn install_usb_serial() -> Result<(), Error> {
+    use crate::peripherals::usb::serial::{self, Serial};
+    use crate::peripherals::usb::UsbBus;
+    use usb_device::bus::UsbBusAllocator;
+
+    static mut BUS: Option<UsbBusAllocator<UsbBus>> = None;
+
+    let bus = UsbBus::take(mstp::token()).ok_or(Error::AlreadyTaken)?;
+    let bus = interrupt::free(|| unsafe { (*core::ptr::addr_of_mut!(BUS)).insert(bus) });
+    serial::install(Serial::new(bus)).map_err(|_| Error::NoFreeSlot)
+}
@@ -0,0 +1,156 @@
+//! A global logger for `defmt`, which sends the log frames over SCI2 on pin D1 (TXD2).
+//!
+//! After [`init`], the `defmt` macros of all crates write to the port, busy-waiting until each
+//! byte is handed to the unit. Interrupts are disabled while a frame is written, so frames from
+//! interrupt handlers aren't mixed into others. Before [`init`], the frames are dropped.
+//!
+//! The frames are binary, and are decoded on the host with `defmt-print` and the ELF file of the
+//! program, e.g. with a USB serial adapter on D1:
+//! `stty -F /dev/ttyUSB0 raw 115200 && defmt-print -e firmware.elf < /dev/ttyUSB0`.
+//!
+//! SCI2 and D1 are also the ones of `arduino::Serial` (unless the `arduino-serial-usb` feature is
+//! enabled), so only one of them can be used.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Serial
+//! Communications Interface (SCI)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::defmt_uart;
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! defmt_uart::init(pins.d1, &clocks, 115_200).unwrap();
+//! defmt::info!("ICLK is {} Hz", clocks.iclk());
+//! ```
+
+use crate::interrupt;
+use crate::peripherals::clocks::{self, Clocks};
+use crate::peripherals::esp32::bit_rate_settings;
+use crate::peripherals::mstp::{self, unit};
+use crate::peripherals::pins::{PinMode, P302};
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+// The registers of SCI2, see the SCI9 ones in `esp32` for the bits.
+const SMR: *mut u8 = 0x40070040 as *mut u8;
+const BRR: *mut u8 = 0x40070041 as *mut u8;
+const SCR: *mut u8 = 0x40070042 as *mut u8;
+const TDR: *mut u8 = 0x40070043 as *mut u8;
+const SSR: *const u8 = 0x40070044 as *const u8;
+const SCMR: *mut u8 = 0x40070046 as *mut u8;
+const SEMR: *mut u8 = 0x40070047 as *mut u8;
+const MDDR: *mut u8 = 0x40070052 as *mut u8;
+
+const SCR_TE: u8 = 1 << 5;
+const SSR_TEND: u8 = 1 << 2;
+const SSR_TDRE: u8 = 1 << 7;
+const SEMR_BRME: u8 = 1 << 2;
+const SEMR_ABCS: u8 = 1 << 4;
+const SEMR_BGDM: u8 = 1 << 6;
+
+/// Peripheral function 4 is SCI0, 2, 4, 6 and 8.
+const PSEL_SCI: u32 = 0b00100;
+
+/// Errors of [`init`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The bit rate can't be derived from PCLKA.
+    UnsupportedBaudRate,
+    /// [`init`] was called before.
+    AlreadyInitialized,
+}
+
+/// Set when [`init`] has started the port.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Set while a frame is written.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Whether interrupts were enabled before the frame that is written.
+static mut RESTORE_INTERRUPTS: bool = false;
+
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+
+/// Start SCI2 on `tx` at `baud_rate`, and send the `defmt` frames over it from now on.
+pub fn init<M: PinMode>(tx: P302<M>, clocks: &Clocks, baud_rate: u32) -> Result<(), Error> {
+    let (cks, brr, mddr) =
+        bit_rate_settings(clocks.pclka(), baud_rate).ok_or(Error::UnsupportedBaudRate)?;
+    if INITIALIZED.load(Ordering::Acquire) {
+        return Err(Error::AlreadyInitialized);
+    }
+    // The logger is used until the end of the program.
+    core::mem::forget(mstp::token::<unit::Sci2>());
+    tx.into_peripheral(PSEL_SCI, 0);
+    unsafe {
+        SCR.write_volatile(0);
+        SCMR.write_volatile(0xf2);
+        SMR.write_volatile(cks);
+        BRR.write_volatile(brr);
+        match mddr {
+            Some(mddr) => {
+                MDDR.write_volatile(mddr);
+                SEMR.write_volatile(SEMR_ABCS | SEMR_BGDM | SEMR_BRME);
+            }
+            None => SEMR.write_volatile(SEMR_ABCS | SEMR_BGDM),
+        }
+    }
+    // The unit needs one bit period before it is enabled.
+    clocks::wait_us(clocks.iclk(), 1_000_000 / baud_rate + 1);
+    unsafe { SCR.write_volatile(SCR_TE) };
+    INITIALIZED.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Send `bytes`, if the port is started.
+fn send(bytes: &[u8]) {
+    if !INITIALIZED.load(Ordering::Acquire) {
+        return;
+    }
+    for &byte in bytes {
+        unsafe {
+            while SSR.read_volatile() & SSR_TDRE == 0 {}
+            TDR.write_volatile(byte);
+        }
+    }
+}
+
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        let enabled = interrupt::is_enabled();
+        interrupt::disable();
+        if TAKEN.swap(true, Ordering::Acquire) {
+            panic!("defmt logger taken reentrantly");
+        }
+        unsafe {
+            RESTORE_INTERRUPTS = enabled;
+            (*ptr::addr_of_mut!(ENCODER)).start_frame(send);
+        }
+    }
+
+    unsafe fn flush() {
+        if INITIALIZED.load(Ordering::Acquire) {
+            while SSR.read_volatile() & SSR_TEND == 0 {}
+        }
+    }
+
+    unsafe fn release() {
+        (*ptr::addr_of_mut!(ENCODER)).end_frame(send);
+        TAKEN.store(false, Ordering::Release);
+        if RESTORE_INTERRUPTS {
+            interrupt::enable();
+        }
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        (*ptr::addr_of_mut!(ENCODER)).write(bytes, send);
+    }
+}
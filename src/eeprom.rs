@@ -0,0 +1,252 @@
+//! EEPROM emulation on the data flash, like the `EEPROM` library of the Arduino core.
+//!
+//! [`Eeprom`] holds `N` bytes, which read as 0xff until they are written. The flash can only be
+//! erased in blocks of 1 KB, so instead of rewriting a block for every byte, each write appends a
+//! record with the address and the new value to the active block. When the block is full, the
+//! current contents are copied to the next block of the range, which then becomes the active one.
+//! The blocks are used in turn, so they wear evenly, and a range of `B` blocks endures roughly
+//! `B * 100_000 * (1016 - N) / 4` writes.
+//!
+//! Every write is committed on its own: A record that was cut off by a reset or power loss fails
+//! its check and is ignored, so the byte keeps its old value. When the contents move to the next
+//! block, that block only becomes valid once its header is programmed as the last step, so the old
+//! block stays in use until then.
+//!
+//! The contents are mirrored in RAM, so reading doesn't touch the flash.
+//!
+//! [`Eeprom::put`] and [`Eeprom::get`] store and load whole values of types that implement
+//! [`Pod`], like `EEPROM.put` and `EEPROM.get` of the Arduino core. The bytes are stored as they
+//! are in memory, so the layout must stay the same between the program that writes and the one
+//! that reads.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::eeprom::Eeprom;
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::flash::DataFlash;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let flash = DataFlash::take(&clocks).unwrap();
+//! // 256 bytes in the first four blocks of the data flash.
+//! let mut eeprom: Eeprom<256> = Eeprom::new(flash, 0..4).unwrap();
+//! let resets = eeprom.read(0).wrapping_add(1);
+//! eeprom.write(0, resets).unwrap();
+//! let calibration: [f32; 2] = eeprom.get(4);
+//! eeprom.put(4, &[calibration[0] + 0.5, calibration[1]]).unwrap();
+//! ```
+
+use crate::flash_blocks::{self, BLOCK_HEADER_SIZE};
+use crate::peripherals::flash::{Blocking, DataFlash, Error, DATA_FLASH_BLOCK_SIZE};
+
+use core::mem::size_of;
+use core::ops::Range;
+
+/// Marks a block that holds the contents of the EEPROM.
+const MAGIC: u32 = 0x4545_5052;
+
+/// Size of a record: The address, the value and a check byte.
+const RECORD_SIZE: u32 = 4;
+
+/// Value of the bytes that haven't been written.
+const UNWRITTEN: u8 = 0xff;
+
+/// Returns the check byte of a record.
+fn check(address: u16, value: u8) -> u8 {
+    let [low, high] = address.to_le_bytes();
+    !(low ^ high ^ value).rotate_left(3)
+}
+
+/// Plain data that [`Eeprom::put`] and [`Eeprom::get`] store as its bytes.
+///
+/// # Safety
+///
+/// The type must have no padding bytes, and every pattern of bytes must be a valid value of it.
+/// This holds for the integers, the floats, arrays of them and `#[repr(C)]` structs of such fields
+/// without gaps, but not for `bool`, `char`, references or enums.
+pub unsafe trait Pod: Copy {}
+
+macro_rules! impl_pod {
+    ($($type:ty),*) => {
+        $(unsafe impl Pod for $type {})*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Pod, const M: usize> Pod for [T; M] {}
+
+/// An EEPROM of `N` bytes, emulated on a range of data flash blocks.
+pub struct Eeprom<const N: usize> {
+    flash: DataFlash<Blocking>,
+    blocks: Range<u32>,
+    /// The block with the current contents.
+    active: u32,
+    sequence: u32,
+    /// Offset of the next record in the active block.
+    next: u32,
+    data: [u8; N],
+}
+
+impl<const N: usize> Eeprom<N> {
+    /// Open the EEPROM in the data flash blocks `blocks`, e.g. `0..2` for the first two of the 8
+    /// blocks. If none of them holds valid contents, all bytes are unwritten, and the first block
+    /// is erased for them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `blocks` holds fewer than 2 blocks, or `N` is too large to leave room for records
+    /// in a block.
+    pub fn new(flash: DataFlash<Blocking>, blocks: Range<u32>) -> Result<Self, Error> {
+        assert!(blocks.len() >= 2, "the EEPROM needs at least two blocks");
+        assert!(
+            BLOCK_HEADER_SIZE + N as u32 + 16 * RECORD_SIZE <= DATA_FLASH_BLOCK_SIZE,
+            "the EEPROM is too large"
+        );
+        let mut eeprom = Self {
+            flash,
+            active: blocks.start,
+            blocks,
+            sequence: 0,
+            next: 0,
+            data: [UNWRITTEN; N],
+        };
+        match flash_blocks::find_active(&mut eeprom.flash, eeprom.blocks.clone(), MAGIC)? {
+            Some((active, sequence)) => {
+                eeprom.active = active;
+                eeprom.sequence = sequence;
+                eeprom.load()?;
+            }
+            None => eeprom.move_to(eeprom.blocks.start, 0)?,
+        }
+        Ok(eeprom)
+    }
+
+    /// Returns the flash driver.
+    pub fn release(self) -> DataFlash<Blocking> {
+        self.flash
+    }
+
+    /// Returns the size of the EEPROM.
+    pub fn length(&self) -> usize {
+        N
+    }
+
+    /// Returns the byte at `address`, or 0xff if it hasn't been written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `address` is outside of the EEPROM.
+    pub fn read(&self, address: usize) -> u8 {
+        self.data[address]
+    }
+
+    /// Copy the bytes from `address` into `buffer`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bytes aren't within the EEPROM.
+    pub fn read_slice(&self, address: usize, buffer: &mut [u8]) {
+        buffer.copy_from_slice(&self.data[address..address + buffer.len()]);
+    }
+
+    /// Write `value` to `address`. Doesn't touch the flash if the byte already has this value.
+    pub fn write(&mut self, address: usize, value: u8) -> Result<(), Error> {
+        if address >= N {
+            return Err(Error::OutOfBounds);
+        }
+        if self.data[address] == value {
+            return Ok(());
+        }
+        if self.next + RECORD_SIZE > DATA_FLASH_BLOCK_SIZE {
+            self.data[address] = value;
+            let next = match self.active + 1 {
+                block if block == self.blocks.end => self.blocks.start,
+                block => block,
+            };
+            if let Err(error) = self.move_to(next, self.sequence.wrapping_add(1)) {
+                self.load()?;
+                return Err(error);
+            }
+            return Ok(());
+        }
+        let address = address as u16;
+        let [low, high] = address.to_le_bytes();
+        let record = [low, high, value, check(address, value)];
+        let offset = self.active * DATA_FLASH_BLOCK_SIZE + self.next;
+        // The slot is used up even if programming fails half way.
+        self.next += RECORD_SIZE;
+        self.flash.program(offset, &record)?;
+        self.data[address as usize] = value;
+        Ok(())
+    }
+
+    /// Write `bytes` to `address`, one byte after the other. Each byte is committed on its own, so
+    /// after a power loss, only some of them may have the new value.
+    pub fn write_slice(&mut self, address: usize, bytes: &[u8]) -> Result<(), Error> {
+        if address + bytes.len() > N {
+            return Err(Error::OutOfBounds);
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.write(address + i, byte)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the value of type `T` stored at `address` by [`Eeprom::put`]. If the bytes haven't
+    /// been written, it is made of 0xff bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value isn't within the EEPROM.
+    pub fn get<T: Pod>(&self, address: usize) -> T {
+        let bytes = &self.data[address..address + size_of::<T>()];
+        // `Pod` makes any bytes a valid `T`, and the slice is long enough.
+        unsafe { bytes.as_ptr().cast::<T>().read_unaligned() }
+    }
+
+    /// Store the bytes of `value` at `address`. Only the bytes that change are written, and each
+    /// is committed on its own, see [`Eeprom::write_slice`].
+    pub fn put<T: Pod>(&mut self, address: usize, value: &T) -> Result<(), Error> {
+        // `Pod` rules out padding, so all bytes of `value` are initialized.
+        let bytes = unsafe {
+            core::slice::from_raw_parts((value as *const T).cast::<u8>(), size_of::<T>())
+        };
+        self.write_slice(address, bytes)
+    }
+
+    /// Read the contents of the active block and apply its records.
+    fn load(&mut self) -> Result<(), Error> {
+        let start = self.active * DATA_FLASH_BLOCK_SIZE;
+        self.flash.read(start + BLOCK_HEADER_SIZE, &mut self.data)?;
+        self.next = BLOCK_HEADER_SIZE + N as u32;
+        while self.next + RECORD_SIZE <= DATA_FLASH_BLOCK_SIZE {
+            let offset = start + self.next;
+            if self.flash.blank_check(offset, RECORD_SIZE)? {
+                break;
+            }
+            self.next += RECORD_SIZE;
+            let mut record = [0; RECORD_SIZE as usize];
+            self.flash.read(offset, &mut record)?;
+            let [low, high, value, checked] = record;
+            let address = u16::from_le_bytes([low, high]);
+            if checked == check(address, value) && (address as usize) < N {
+                self.data[address as usize] = value;
+            }
+        }
+        Ok(())
+    }
+
+    /// Erase `block`, write the contents to it and make it the active block with `sequence`.
+    fn move_to(&mut self, block: u32, sequence: u32) -> Result<(), Error> {
+        let start = block * DATA_FLASH_BLOCK_SIZE;
+        self.flash.erase(start, start + DATA_FLASH_BLOCK_SIZE)?;
+        self.flash.program(start + BLOCK_HEADER_SIZE, &self.data)?;
+        // The magic number comes last, it makes the block valid.
+        self.flash.program(start + 4, &sequence.to_le_bytes())?;
+        self.flash.program(start, &MAGIC.to_le_bytes())?;
+        self.active = block;
+        self.sequence = sequence;
+        self.next = BLOCK_HEADER_SIZE + N as u32;
+        Ok(())
+    }
+}
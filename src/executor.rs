@@ -0,0 +1,108 @@
+//! A minimal executor, to run the async drivers without an RTOS or another executor.
+//!
+//! [`block_on`] polls one future until it completes, and sleeps with WFI in between until an
+//! interrupt handler or another part of the program wakes it (see
+//! [`WakerCell`](crate::interrupt::WakerCell)). Tasks that run side by side are combined into one
+//! future with [`join`]. All wakers wake the same executor, so every future is polled again on
+//! every wake-up, which is cheap for the handful of tasks of a firmware.
+//!
+//! [`entry_async!`](crate::entry_async) is the async variant of [`entry!`](crate::entry): It makes
+//! an `async fn() -> !` the main function and runs it with [`block_on`].
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::entry_async;
+//! use arduino_uno_r4_wifi_rt::peripherals::async_delay::AsyncDelay;
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//!
+//! entry_async!(main);
+//!
+//! async fn main() -> ! {
+//!     let clocks = Cgc::take().unwrap().into_clocks();
+//!     let mut delay = AsyncDelay::take(mstp::token(), &clocks).unwrap();
+//!     loop {
+//!         delay.delay_ms(1000).await;
+//!     }
+//! }
+//! ```
+
+use crate::peripherals::power;
+
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/// Set by the wakers, cleared before each poll.
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop_waker);
+
+fn clone(_: *const ()) -> RawWaker {
+    RawWaker::new(core::ptr::null(), &VTABLE)
+}
+
+fn wake(_: *const ()) {
+    WOKEN.store(true, Ordering::Release);
+}
+
+fn drop_waker(_: *const ()) {}
+
+/// Run `future` to completion, sleeping while it waits.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    // The waker has no data, so it is valid for the whole program.
+    let waker = unsafe { Waker::from_raw(clone(core::ptr::null())) };
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        WOKEN.store(false, Ordering::Release);
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        power::sleep_until(|| WOKEN.load(Ordering::Acquire));
+    }
+}
+
+/// Run `a` and `b` side by side, and return both outputs when both are done.
+pub async fn join<A: Future, B: Future>(a: A, b: B) -> (A::Output, B::Output) {
+    let mut a = pin!(a);
+    let mut b = pin!(b);
+    let mut a_output = None;
+    let mut b_output = None;
+    poll_fn(|cx| {
+        if a_output.is_none() {
+            if let Poll::Ready(output) = a.as_mut().poll(cx) {
+                a_output = Some(output);
+            }
+        }
+        if b_output.is_none() {
+            if let Poll::Ready(output) = b.as_mut().poll(cx) {
+                b_output = Some(output);
+            }
+        }
+        match (a_output.take(), b_output.take()) {
+            (Some(a), Some(b)) => Poll::Ready((a, b)),
+            (a, b) => {
+                a_output = a;
+                b_output = b;
+                Poll::Pending
+            }
+        }
+    })
+    .await
+}
+
+/// Let the other futures of a [`join`] run before continuing.
+pub async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
@@ -0,0 +1,71 @@
+//! The parts that the stores on the data flash have in common: [`crate::eeprom`],
+//! [`crate::kv_store`] and [`crate::flash_log`] each use a range of blocks, one of which is
+//! active at a time.
+//!
+//! Every block starts with a header of the magic number of the store and a sequence number, which
+//! increases every time the store moves to the next block. The magic number is programmed last,
+//! so a block only counts once it is complete.
+
+use crate::peripherals::flash::{Blocking, DataFlash, Error, DATA_FLASH_BLOCK_SIZE};
+
+use core::ops::Range;
+
+/// Size of the header of a block: The magic number and the sequence number.
+pub(crate) const BLOCK_HEADER_SIZE: u32 = 8;
+
+/// CRC-16/CCITT-FALSE, updated with `data`.
+pub(crate) fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Returns the magic number and the sequence number of `block`, if it has been programmed.
+pub(crate) fn block_header(
+    flash: &mut DataFlash<Blocking>,
+    block: u32,
+) -> Result<Option<(u32, u32)>, Error> {
+    let start = block * DATA_FLASH_BLOCK_SIZE;
+    if flash.blank_check(start, BLOCK_HEADER_SIZE)? {
+        return Ok(None);
+    }
+    let mut header = [0; BLOCK_HEADER_SIZE as usize];
+    flash.read(start, &mut header)?;
+    let [m0, m1, m2, m3, s0, s1, s2, s3] = header;
+    Ok(Some((
+        u32::from_le_bytes([m0, m1, m2, m3]),
+        u32::from_le_bytes([s0, s1, s2, s3]),
+    )))
+}
+
+/// Returns the block of `blocks` with `magic` and the highest sequence number, and the sequence
+/// number.
+pub(crate) fn find_active(
+    flash: &mut DataFlash<Blocking>,
+    blocks: Range<u32>,
+    magic: u32,
+) -> Result<Option<(u32, u32)>, Error> {
+    let mut active: Option<(u32, u32)> = None;
+    for block in blocks {
+        let Some((found, sequence)) = block_header(flash, block)? else {
+            continue;
+        };
+        if found != magic {
+            continue;
+        }
+        // The sequence numbers wrap around, so compare the difference.
+        let newer = active.is_none_or(|(_, current)| (sequence.wrapping_sub(current) as i32) > 0);
+        if newer {
+            active = Some((block, sequence));
+        }
+    }
+    Ok(active)
+}
@@ -0,0 +1,286 @@
+//! A persistent ring log on the data flash, for short records that survive a reset, e.g. errors
+//! and crash reports to read after the board has failed in the field.
+//!
+//! [`FlashLog`] appends each record with a timestamp to the active block. When the block is full,
+//! the next block of the range is erased and becomes the active one, so the oldest records are
+//! dropped a block at a time. The timestamp is up to the program, e.g. the seconds of
+//! [`DateTime::to_unix`](crate::peripherals::rtc::DateTime::to_unix) or the milliseconds since
+//! the start.
+//!
+//! Every record is protected by a CRC-16, so a record that was cut off by a reset or power loss is
+//! skipped.
+//!
+//! [`FlashLog::dump`] prints the records to any [`fmt::Write`], e.g. the USB serial port.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::flash_log::FlashLog;
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::flash::DataFlash;
+//! use arduino_uno_r4_wifi_rt::peripherals::reset;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let flash = DataFlash::take(&clocks).unwrap();
+//! let mut log = FlashLog::new(flash, 4..8).unwrap();
+//! log.log_fmt(0, format_args!("reset: {:?}", reset::cause())).unwrap();
+//! log.for_each(|timestamp, data| {
+//!     // Send the records to the host.
+//! })
+//! .unwrap();
+//! ```
+
+use crate::flash_blocks::{self, crc16, BLOCK_HEADER_SIZE};
+use crate::peripherals::flash::{self, Blocking, DataFlash, DATA_FLASH_BLOCK_SIZE};
+
+use core::fmt;
+use core::ops::Range;
+
+/// Marks a block of the log.
+const MAGIC: u32 = 0x4c4f_4731;
+
+/// Size of the header of a record: The length of the data, its complement, the CRC of the
+/// timestamp and the data, and the timestamp.
+const RECORD_HEADER_SIZE: u32 = 8;
+
+/// Maximum size of the data of a record.
+pub const MAX_RECORD_SIZE: usize = 255;
+
+/// Errors of the log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The flash driver failed.
+    Flash(flash::Error),
+    /// The data is larger than [`MAX_RECORD_SIZE`].
+    TooLarge,
+}
+
+impl From<flash::Error> for Error {
+    fn from(error: flash::Error) -> Self {
+        Error::Flash(error)
+    }
+}
+
+/// Formats into a record, and cuts off what doesn't fit.
+struct RecordWriter {
+    data: [u8; MAX_RECORD_SIZE],
+    len: usize,
+}
+
+impl fmt::Write for RecordWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(MAX_RECORD_SIZE - self.len);
+        self.data[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// A ring log on a range of data flash blocks.
+pub struct FlashLog {
+    flash: DataFlash<Blocking>,
+    blocks: Range<u32>,
+    /// The block that records are appended to.
+    active: u32,
+    sequence: u32,
+    /// Offset of the next record in the data flash.
+    next: u32,
+}
+
+impl FlashLog {
+    /// Open the log in the data flash blocks `blocks`, e.g. `4..8` for the second half of the 8
+    /// blocks. If none of them holds records, the log is empty, and the first block is erased for
+    /// it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `blocks` holds fewer than 2 blocks.
+    pub fn new(flash: DataFlash<Blocking>, blocks: Range<u32>) -> Result<Self, Error> {
+        assert!(blocks.len() >= 2, "the log needs at least two blocks");
+        let mut log = Self {
+            flash,
+            active: blocks.start,
+            blocks,
+            sequence: 0,
+            next: 0,
+        };
+        match flash_blocks::find_active(&mut log.flash, log.blocks.clone(), MAGIC)? {
+            Some((active, sequence)) => {
+                log.active = active;
+                log.sequence = sequence;
+                log.next = log.block_end(active)?;
+            }
+            None => log.start_block(log.blocks.start, 0)?,
+        }
+        Ok(log)
+    }
+
+    /// Returns the flash driver.
+    pub fn release(self) -> DataFlash<Blocking> {
+        self.flash
+    }
+
+    /// Append a record with `timestamp` and `data`.
+    pub fn log(&mut self, timestamp: u32, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_RECORD_SIZE {
+            return Err(Error::TooLarge);
+        }
+        let size = RECORD_HEADER_SIZE + data.len() as u32;
+        if self.next + size > (self.active + 1) * DATA_FLASH_BLOCK_SIZE {
+            self.start_block(self.next_block(self.active), self.sequence.wrapping_add(1))?;
+        }
+        let len = data.len() as u8;
+        let [c0, c1] = crc16(crc16(0xffff, &timestamp.to_le_bytes()), data).to_le_bytes();
+        let [t0, t1, t2, t3] = timestamp.to_le_bytes();
+        let header = [len, !len, c0, c1, t0, t1, t2, t3];
+        let offset = self.next;
+        // The space is used up even if programming fails half way.
+        self.next += size;
+        self.flash.program(offset, &header)?;
+        self.flash.program(offset + RECORD_HEADER_SIZE, data)?;
+        Ok(())
+    }
+
+    /// Append a record with `timestamp` and the formatted text, which is cut off after
+    /// [`MAX_RECORD_SIZE`] bytes.
+    pub fn log_fmt(&mut self, timestamp: u32, args: fmt::Arguments<'_>) -> Result<(), Error> {
+        let mut writer = RecordWriter {
+            data: [0; MAX_RECORD_SIZE],
+            len: 0,
+        };
+        let _ = fmt::write(&mut writer, args);
+        self.log(timestamp, &writer.data[..writer.len])
+    }
+
+    /// Call `f` with the timestamp and the data of each record, from the oldest to the newest.
+    pub fn for_each(&mut self, mut f: impl FnMut(u32, &[u8])) -> Result<(), Error> {
+        let mut data = [0; MAX_RECORD_SIZE];
+        let mut block = self.next_block(self.active);
+        loop {
+            if self.is_valid_block(block)? {
+                let end = if block == self.active {
+                    self.next
+                } else {
+                    self.block_end(block)?
+                };
+                let mut offset = block * DATA_FLASH_BLOCK_SIZE + BLOCK_HEADER_SIZE;
+                while let Some((timestamp, len)) = self.record_at(offset, end, &mut data)? {
+                    offset += RECORD_HEADER_SIZE + len as u32;
+                    if let Some(timestamp) = timestamp {
+                        f(timestamp, &data[..len]);
+                    }
+                }
+            }
+            if block == self.active {
+                return Ok(());
+            }
+            block = self.next_block(block);
+        }
+    }
+
+    /// Print the records to `writer`, one per line, with the timestamp first. Records that aren't
+    /// valid UTF-8 are printed as hexadecimal bytes.
+    pub fn dump(&mut self, writer: &mut impl fmt::Write) -> Result<fmt::Result, Error> {
+        let mut result = Ok(());
+        self.for_each(|timestamp, data| {
+            if result.is_err() {
+                return;
+            }
+            result = match core::str::from_utf8(data) {
+                Ok(text) => writeln!(writer, "[{timestamp}] {text}"),
+                Err(_) => write!(writer, "[{timestamp}]").and_then(|_| {
+                    for byte in data {
+                        write!(writer, " {byte:02x}")?;
+                    }
+                    writeln!(writer)
+                }),
+            };
+        })?;
+        Ok(result)
+    }
+
+    /// Remove all records.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        let next = self.next_block(self.active);
+        for block in self.blocks.clone() {
+            if block != next {
+                let start = block * DATA_FLASH_BLOCK_SIZE;
+                self.flash.erase(start, start + DATA_FLASH_BLOCK_SIZE)?;
+            }
+        }
+        self.start_block(next, self.sequence.wrapping_add(1))
+    }
+
+    /// Read the record at `offset` into `data`. Returns its timestamp, or `None` if its CRC doesn't
+    /// match, and the length of its data, or `None` at the end of the records before `end`.
+    fn record_at(
+        &mut self,
+        offset: u32,
+        end: u32,
+        data: &mut [u8; MAX_RECORD_SIZE],
+    ) -> Result<Option<(Option<u32>, usize)>, Error> {
+        if offset + RECORD_HEADER_SIZE > end {
+            return Ok(None);
+        }
+        let mut header = [0; RECORD_HEADER_SIZE as usize];
+        self.flash.read(offset, &mut header)?;
+        let [len, not_len, c0, c1, t0, t1, t2, t3] = header;
+        let len = len as usize;
+        if not_len != !(len as u8) || offset + RECORD_HEADER_SIZE + len as u32 > end {
+            return Ok(None);
+        }
+        self.flash
+            .read(offset + RECORD_HEADER_SIZE, &mut data[..len])?;
+        let timestamp = u32::from_le_bytes([t0, t1, t2, t3]);
+        let crc = crc16(crc16(0xffff, &timestamp.to_le_bytes()), &data[..len]);
+        let valid = crc == u16::from_le_bytes([c0, c1]);
+        Ok(Some((valid.then_some(timestamp), len)))
+    }
+
+    /// Returns the block after `block` in the range.
+    fn next_block(&self, block: u32) -> u32 {
+        match block + 1 {
+            block if block == self.blocks.end => self.blocks.start,
+            block => block,
+        }
+    }
+
+    /// Erase `block` and make it the active block with `sequence`.
+    fn start_block(&mut self, block: u32, sequence: u32) -> Result<(), Error> {
+        let start = block * DATA_FLASH_BLOCK_SIZE;
+        self.flash.erase(start, start + DATA_FLASH_BLOCK_SIZE)?;
+        // The magic number comes last, it makes the block valid.
+        self.flash.program(start + 4, &sequence.to_le_bytes())?;
+        self.flash.program(start, &MAGIC.to_le_bytes())?;
+        self.active = block;
+        self.sequence = sequence;
+        self.next = start + BLOCK_HEADER_SIZE;
+        Ok(())
+    }
+
+    /// Returns true if `block` belongs to the log.
+    fn is_valid_block(&mut self, block: u32) -> Result<bool, Error> {
+        let header = flash_blocks::block_header(&mut self.flash, block)?;
+        Ok(matches!(header, Some((MAGIC, _))))
+    }
+
+    /// Returns the offset after the last record of `block`. After a record whose header was cut
+    /// off, the rest of the block is treated as used.
+    fn block_end(&mut self, block: u32) -> Result<u32, Error> {
+        let end = (block + 1) * DATA_FLASH_BLOCK_SIZE;
+        let mut offset = block * DATA_FLASH_BLOCK_SIZE + BLOCK_HEADER_SIZE;
+        let mut data = [0; MAX_RECORD_SIZE];
+        loop {
+            if offset + RECORD_HEADER_SIZE > end {
+                return Ok(end);
+            }
+            if self.flash.blank_check(offset, RECORD_HEADER_SIZE)? {
+                return Ok(offset);
+            }
+            match self.record_at(offset, end, &mut data)? {
+                Some((_, len)) => offset += RECORD_HEADER_SIZE + len as u32,
+                None => return Ok(end),
+            }
+        }
+    }
+}
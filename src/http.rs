@@ -0,0 +1,599 @@
+//! HTTP/1.1 client over the connections of [`wifi::Stack`], for talking to REST APIs.
+//!
+//! [`Client::request`] opens a connection for each [`Request`], over TLS for `https://` URLs, and
+//! sends it with `Connection: close`. The status line and the headers of the [`Response`] are kept
+//! in a buffer of the caller, the body is read in pieces with [`Response::read`] or at once with
+//! [`Response::read_to_end`]. Bodies with `Content-Length`, `Transfer-Encoding: chunked` or neither,
+//! up to the end of the connection, are supported. Redirects aren't followed.
+//!
+//! For HTTP/1.1, see RFC 9112.
+//! <https://www.rfc-editor.org/rfc/rfc9112>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::http::{Client, Request};
+//! use arduino_uno_r4_wifi_rt::wifi::{Stack, TlsConfig};
+//!
+//! // With `at` set up and the station connected as in the `wifi` example.
+//! let mut stack = Stack::new(&mut at).unwrap();
+//! let mut client = Client::new(&mut stack);
+//! let request = Request::get("https://api.example.com/status")
+//!     .with_headers(&[("Accept", "application/json")])
+//!     .with_tls(TlsConfig::default().with_ca_certificate(0));
+//! let mut head = [0; 1024];
+//! let mut response = client.request(&request, &mut head).unwrap();
+//! if response.status() == 200 {
+//!     let mut body = [0; 2048];
+//!     let len = response.read_to_end(&mut body).unwrap();
+//! }
+//! ```
+
+use crate::wifi::{self, Socket, Stack, TlsConfig};
+
+use core::fmt::{self, Write};
+
+/// Size of the buffer for data received from the connection.
+const RECEIVE_BUFFER_SIZE: usize = 256;
+
+/// Size of the buffer for the request head, which is sent whenever it is full.
+const SEND_BUFFER_SIZE: usize = 256;
+
+/// Time between polls of the connection, in ms.
+const POLL_INTERVAL_MS: u32 = 10;
+
+/// Default time to wait for data from the server, in ms.
+const DEFAULT_TIMEOUT_MS: u32 = 10_000;
+
+/// Errors of the HTTP client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Error of the connection.
+    Wifi(wifi::Error),
+    /// The URL isn't `http://` or `https://` followed by a host.
+    InvalidUrl,
+    /// The server didn't send anything for the timeout.
+    Timeout,
+    /// The status line and headers don't fit in the buffer.
+    HeadTooLarge,
+    /// The body doesn't fit in the buffer of [`Response::read_to_end`].
+    BodyTooLarge,
+    /// The server sent something that isn't HTTP/1.x.
+    InvalidResponse,
+    /// The server closed the connection before the end of the response.
+    UnexpectedClose,
+}
+
+impl From<wifi::Error> for Error {
+    fn from(error: wifi::Error) -> Self {
+        Error::Wifi(error)
+    }
+}
+
+/// Method of a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Patch,
+    Delete,
+}
+
+impl Method {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Patch => "PATCH",
+            Method::Delete => "DELETE",
+        }
+    }
+}
+
+/// A request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Request<'a> {
+    pub method: Method,
+    /// `http://` or `https://`, the host, optionally `:<port>`, and the path.
+    pub url: &'a str,
+    /// Headers besides `Host`, `Connection` and `Content-Length`, which are set by the client.
+    pub headers: &'a [(&'a str, &'a str)],
+    pub body: &'a [u8],
+    /// Authentication of `https://` connections.
+    pub tls: TlsConfig<'a>,
+}
+
+impl<'a> Request<'a> {
+    /// A request without headers and body.
+    pub fn new(method: Method, url: &'a str) -> Self {
+        Self {
+            method,
+            url,
+            headers: &[],
+            body: &[],
+            tls: TlsConfig::default(),
+        }
+    }
+
+    /// A `GET` request.
+    pub fn get(url: &'a str) -> Self {
+        Self::new(Method::Get, url)
+    }
+
+    /// A `POST` request with `body`.
+    pub fn post(url: &'a str, body: &'a [u8]) -> Self {
+        Self::new(Method::Post, url).with_body(body)
+    }
+
+    /// Set the headers.
+    pub fn with_headers(self, headers: &'a [(&'a str, &'a str)]) -> Self {
+        Self { headers, ..self }
+    }
+
+    /// Set the body.
+    pub fn with_body(self, body: &'a [u8]) -> Self {
+        Self { body, ..self }
+    }
+
+    /// Set the authentication of `https://` connections.
+    pub fn with_tls(self, tls: TlsConfig<'a>) -> Self {
+        Self { tls, ..self }
+    }
+}
+
+/// The parts of a URL.
+struct Url<'a> {
+    tls: bool,
+    host: &'a str,
+    port: u16,
+    /// The path with the query, `/` if the URL has none.
+    path: &'a str,
+}
+
+impl<'a> Url<'a> {
+    fn parse(url: &'a str) -> Result<Self, Error> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else {
+            return Err(Error::InvalidUrl);
+        };
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| Error::InvalidUrl)?),
+            None => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(Error::InvalidUrl);
+        }
+        let path = match path {
+            "" => "/",
+            path => path,
+        };
+        Ok(Url {
+            tls,
+            host,
+            port,
+            path,
+        })
+    }
+}
+
+/// Collects the request head and sends it in pieces of [`SEND_BUFFER_SIZE`] bytes.
+struct Sender<'s, 'a> {
+    stack: &'s mut Stack<'a>,
+    socket: &'s Socket,
+    buffer: [u8; SEND_BUFFER_SIZE],
+    len: usize,
+    error: Option<wifi::Error>,
+}
+
+impl Sender<'_, '_> {
+    fn flush(&mut self) -> Result<(), wifi::Error> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        let len = core::mem::replace(&mut self.len, 0);
+        self.stack.send(self.socket, &self.buffer[..len])
+    }
+}
+
+impl Write for Sender<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            if self.len == SEND_BUFFER_SIZE {
+                self.flush().map_err(|error| {
+                    self.error = Some(error);
+                    fmt::Error
+                })?;
+            }
+            self.buffer[self.len] = byte;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+/// HTTP client.
+pub struct Client<'s, 'a> {
+    stack: &'s mut Stack<'a>,
+    timeout_ms: u32,
+}
+
+impl<'s, 'a> Client<'s, 'a> {
+    /// A client that waits up to 10 s for data from the server.
+    pub fn new(stack: &'s mut Stack<'a>) -> Self {
+        Self {
+            stack,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+        }
+    }
+
+    /// Set the time to wait for data from the server, in ms.
+    pub fn with_timeout(self, timeout_ms: u32) -> Self {
+        Self { timeout_ms, ..self }
+    }
+
+    /// Send `request` and read the status line and the headers of the response into `head`.
+    pub fn request<'c, 'h>(
+        &'c mut self,
+        request: &Request,
+        head: &'h mut [u8],
+    ) -> Result<Response<'c, 'a, 'h>, Error> {
+        let url = Url::parse(request.url)?;
+        let socket = if url.tls {
+            self.stack.connect_tls(url.host, url.port, &request.tls)?
+        } else {
+            self.stack
+                .connect(wifi::Protocol::Tcp, url.host, url.port)?
+        };
+        let mut response = Response {
+            stack: &mut *self.stack,
+            socket: Some(socket),
+            timeout_ms: self.timeout_ms,
+            head,
+            head_len: 0,
+            status: 0,
+            body: Body::UntilClose,
+            buffer: [0; RECEIVE_BUFFER_SIZE],
+            start: 0,
+            end: 0,
+        };
+        response.send(request, &url)?;
+        response.read_head()?;
+        if request.method == Method::Head
+            || (100..200).contains(&response.status)
+            || response.status == 204
+            || response.status == 304
+        {
+            response.body = Body::Length(0);
+        } else if response.header("Transfer-Encoding").is_some_and(is_chunked) {
+            response.body = Body::Chunked {
+                remaining: 0,
+                first: true,
+            };
+        } else if let Some(length) = response.header("Content-Length") {
+            let length = length.parse().map_err(|_| Error::InvalidResponse)?;
+            response.body = Body::Length(length);
+        }
+        Ok(response)
+    }
+}
+
+/// Returns true if the last coding of a `Transfer-Encoding` header is `chunked`.
+fn is_chunked(codings: &str) -> bool {
+    codings
+        .rsplit(',')
+        .next()
+        .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"))
+}
+
+/// How the end of the body is found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Body {
+    /// This many bytes are left.
+    Length(usize),
+    /// This many bytes are left in the current chunk. Before the first chunk, there is no line
+    /// break to skip.
+    Chunked { remaining: usize, first: bool },
+    /// The body ends when the server closes the connection.
+    UntilClose,
+    /// The body has been read.
+    Done,
+}
+
+/// The response to a request. The connection is closed when it is dropped.
+pub struct Response<'c, 'a, 'h> {
+    stack: &'c mut Stack<'a>,
+    socket: Option<Socket>,
+    timeout_ms: u32,
+    head: &'h mut [u8],
+    head_len: usize,
+    status: u16,
+    body: Body,
+    buffer: [u8; RECEIVE_BUFFER_SIZE],
+    start: usize,
+    end: usize,
+}
+
+impl Response<'_, '_, '_> {
+    /// Returns the status code, e.g. 200.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Returns the status line and the headers, without the empty line at the end.
+    pub fn head(&self) -> &[u8] {
+        &self.head[..self.head_len]
+    }
+
+    /// Returns the headers as names and values. Headers that aren't UTF-8 are left out.
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.head()
+            .split(|&byte| byte == b'\n')
+            .skip(1)
+            .filter_map(|line| {
+                let line = core::str::from_utf8(line).ok()?;
+                let (name, value) = line.split_once(':')?;
+                Some((name.trim(), value.trim()))
+            })
+    }
+
+    /// Returns the value of the first header `name`, which is compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Read the next part of the body into `buffer` and return its length, 0 at the end.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let available = loop {
+            match self.body {
+                Body::Done | Body::Length(0) => return Ok(0),
+                Body::Length(remaining) => break remaining,
+                Body::Chunked {
+                    remaining: 0,
+                    first,
+                } => {
+                    if !first {
+                        self.expect_line_break()?;
+                    }
+                    let size = self.read_chunk_size()?;
+                    if size == 0 {
+                        self.skip_trailers()?;
+                        self.body = Body::Done;
+                    } else {
+                        self.body = Body::Chunked {
+                            remaining: size,
+                            first: false,
+                        };
+                    }
+                }
+                Body::Chunked { remaining, .. } => break remaining,
+                Body::UntilClose => break usize::MAX,
+            }
+        };
+        if !self.fill()? {
+            return match self.body {
+                Body::UntilClose => {
+                    self.body = Body::Done;
+                    Ok(0)
+                }
+                _ => Err(Error::UnexpectedClose),
+            };
+        }
+        let count = buffer.len().min(available).min(self.end - self.start);
+        buffer[..count].copy_from_slice(&self.buffer[self.start..self.start + count]);
+        self.start += count;
+        match &mut self.body {
+            Body::Length(remaining) | Body::Chunked { remaining, .. } => *remaining -= count,
+            _ => {}
+        }
+        Ok(count)
+    }
+
+    /// Read the whole body into `buffer` and return its length.
+    pub fn read_to_end(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut len = 0;
+        loop {
+            if len == buffer.len() {
+                let mut byte = [0];
+                return match self.read(&mut byte)? {
+                    0 => Ok(len),
+                    _ => Err(Error::BodyTooLarge),
+                };
+            }
+            match self.read(&mut buffer[len..])? {
+                0 => return Ok(len),
+                count => len += count,
+            }
+        }
+    }
+
+    /// Close the connection.
+    pub fn close(mut self) -> Result<(), Error> {
+        match self.socket.take() {
+            Some(socket) => Ok(self.stack.close(socket)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Send the request head and the body.
+    fn send(&mut self, request: &Request, url: &Url) -> Result<(), Error> {
+        let socket = self.socket.as_ref().ok_or(Error::UnexpectedClose)?;
+        let mut sender = Sender {
+            stack: &mut *self.stack,
+            socket,
+            buffer: [0; SEND_BUFFER_SIZE],
+            len: 0,
+            error: None,
+        };
+        let default_port = if url.tls { 443 } else { 80 };
+        let result = (|| {
+            write!(
+                sender,
+                "{} {} HTTP/1.1\r\nHost: {}",
+                request.method.as_str(),
+                url.path,
+                url.host
+            )?;
+            if url.port != default_port {
+                write!(sender, ":{}", url.port)?;
+            }
+            write!(sender, "\r\nConnection: close\r\n")?;
+            if !request.body.is_empty() || matches!(request.method, Method::Post | Method::Put) {
+                write!(sender, "Content-Length: {}\r\n", request.body.len())?;
+            }
+            for (name, value) in request.headers {
+                write!(sender, "{}: {}\r\n", name, value)?;
+            }
+            write!(sender, "\r\n")
+        })();
+        if result.is_err() {
+            return Err(sender.error.unwrap_or(wifi::Error::InvalidResponse).into());
+        }
+        sender.flush()?;
+        if !request.body.is_empty() {
+            self.stack.send(socket, request.body)?;
+        }
+        Ok(())
+    }
+
+    /// Make sure that there is received data in the buffer. Returns false if the server closed the
+    /// connection and everything has been read.
+    fn fill(&mut self) -> Result<bool, Error> {
+        if self.start < self.end {
+            return Ok(true);
+        }
+        let Some(socket) = &self.socket else {
+            return Ok(false);
+        };
+        let mut waited = 0;
+        loop {
+            // Check first, so that nothing that arrives before the close is missed.
+            let open = self.stack.is_open(socket)?;
+            let count = self.stack.receive(socket, &mut self.buffer)?;
+            if count > 0 {
+                self.start = 0;
+                self.end = count;
+                return Ok(true);
+            }
+            if !open {
+                return Ok(false);
+            }
+            if waited >= self.timeout_ms {
+                return Err(Error::Timeout);
+            }
+            self.stack.at().delay_ms(POLL_INTERVAL_MS);
+            waited += POLL_INTERVAL_MS;
+        }
+    }
+
+    /// Returns the next received byte.
+    fn next_byte(&mut self) -> Result<u8, Error> {
+        if !self.fill()? {
+            return Err(Error::UnexpectedClose);
+        }
+        self.start += 1;
+        Ok(self.buffer[self.start - 1])
+    }
+
+    /// Read the status line and the headers into `head`, up to the empty line.
+    fn read_head(&mut self) -> Result<(), Error> {
+        while !self.head[..self.head_len].ends_with(b"\r\n\r\n") {
+            let byte = self.next_byte()?;
+            let slot = self
+                .head
+                .get_mut(self.head_len)
+                .ok_or(Error::HeadTooLarge)?;
+            *slot = byte;
+            self.head_len += 1;
+        }
+        self.head_len -= 2;
+        // Lines end with CRLF, keep only the LF so that `headers` can split at it.
+        let mut len = 0;
+        for index in 0..self.head_len {
+            if self.head[index] != b'\r' {
+                self.head[len] = self.head[index];
+                len += 1;
+            }
+        }
+        self.head_len = len.saturating_sub(1);
+
+        let status_line = self.head().split(|&byte| byte == b'\n').next();
+        let status = status_line
+            .filter(|line| line.starts_with(b"HTTP/1."))
+            .and_then(|line| line.get(9..12))
+            .and_then(|code| core::str::from_utf8(code).ok())
+            .and_then(|code| code.parse().ok());
+        self.status = status.ok_or(Error::InvalidResponse)?;
+        Ok(())
+    }
+
+    /// Skip the line break after the data of a chunk.
+    fn expect_line_break(&mut self) -> Result<(), Error> {
+        match (self.next_byte()?, self.next_byte()?) {
+            (b'\r', b'\n') => Ok(()),
+            _ => Err(Error::InvalidResponse),
+        }
+    }
+
+    /// Read the line with the size of a chunk, ignoring chunk extensions.
+    fn read_chunk_size(&mut self) -> Result<usize, Error> {
+        let mut size: usize = 0;
+        let mut digits = 0;
+        let mut extension = false;
+        loop {
+            match self.next_byte()? {
+                b'\r' => {}
+                b'\n' if digits > 0 => return Ok(size),
+                b'\n' => return Err(Error::InvalidResponse),
+                b';' => extension = true,
+                _ if extension => {}
+                byte => {
+                    let digit = (byte as char).to_digit(16).ok_or(Error::InvalidResponse)?;
+                    size = size
+                        .checked_mul(16)
+                        .and_then(|size| size.checked_add(digit as usize))
+                        .ok_or(Error::InvalidResponse)?;
+                    digits += 1;
+                }
+            }
+        }
+    }
+
+    /// Skip the trailer fields after the last chunk, up to the empty line.
+    fn skip_trailers(&mut self) -> Result<(), Error> {
+        let mut line_len = 0;
+        loop {
+            match self.next_byte()? {
+                b'\r' => {}
+                b'\n' if line_len == 0 => return Ok(()),
+                b'\n' => line_len = 0,
+                _ => line_len += 1,
+            }
+        }
+    }
+}
+
+impl Drop for Response<'_, '_, '_> {
+    fn drop(&mut self) {
+        if let Some(socket) = self.socket.take() {
+            let _ = self.stack.close(socket);
+        }
+    }
+}
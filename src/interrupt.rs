@@ -0,0 +1,117 @@
+//! Globally enable and disable interrupts, and share data between interrupt handlers and the main
+//! program.
+//!
+//! Interrupts are masked with the PRIMASK register of the CPU. While PRIMASK is set, no interrupt
+//! with configurable priority is taken. Use [`free`] to run a piece of code without being
+//! interrupted.
+//!
+//! See Armv7-M Architecture Reference Manual, section B1.4.3 (PRIMASK) and B5.2.1 (CPS).
+
+use core::arch::asm;
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+
+/// Disable all interrupts with configurable priority by setting PRIMASK.
+#[inline]
+pub fn disable() {
+    unsafe {
+        asm!("cpsid i", options(nostack, preserves_flags));
+    }
+}
+
+/// Enable interrupts by clearing PRIMASK.
+///
+/// # Safety
+///
+/// Must not be called inside a critical section, since the code in it relies on not being
+/// interrupted.
+#[inline]
+pub unsafe fn enable() {
+    asm!("cpsie i", options(nostack, preserves_flags));
+}
+
+/// Returns true if interrupts are enabled, i.e. PRIMASK is not set.
+#[inline]
+pub fn is_enabled() -> bool {
+    let primask: u32;
+    unsafe {
+        asm!("mrs {}, PRIMASK", out(reg) primask, options(nomem, nostack, preserves_flags));
+    }
+    primask & 1 == 0
+}
+
+/// Execute `f` with interrupts disabled (a "critical section").
+///
+/// Interrupts are only enabled again afterwards if they were enabled before, so critical sections
+/// can be nested.
+#[inline]
+pub fn free<F: FnOnce() -> R, R>(f: F) -> R {
+    let was_enabled = is_enabled();
+    disable();
+    let result = f();
+    if was_enabled {
+        unsafe {
+            enable();
+        }
+    }
+    result
+}
+
+/// Storage for a [`Waker`] that is shared between a future and an interrupt handler.
+///
+/// The future registers its waker before waiting for an interrupt, the interrupt handler calls
+/// [`WakerCell::wake`] to let the executor know that the future can make progress.
+pub struct WakerCell {
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+// The waker is only accessed inside critical sections.
+unsafe impl Sync for WakerCell {}
+
+impl WakerCell {
+    pub const fn new() -> Self {
+        Self {
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    /// Register `waker` to be woken by the next call to [`WakerCell::wake`]. Replaces the waker
+    /// that was registered before, if any.
+    pub fn register(&self, waker: &Waker) {
+        free(|| {
+            let slot = unsafe { &mut *self.waker.get() };
+            match slot {
+                Some(registered) if registered.will_wake(waker) => {}
+                _ => *slot = Some(waker.clone()),
+            }
+        });
+    }
+
+    /// Wake the registered waker, if any, and remove it.
+    pub fn wake(&self) {
+        let waker = free(|| unsafe { (*self.waker.get()).take() });
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for WakerCell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run a future to completion by polling it in a loop. Used by the blocking drivers, whose futures
+/// never wait for a waker.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
@@ -0,0 +1,399 @@
+//! A persistent key-value store on the data flash, e.g. for WiFi credentials or calibration
+//! constants.
+//!
+//! [`KvStore`] maps `u16` keys to byte strings of up to [`MAX_VALUE_SIZE`] bytes. Like the
+//! [`Eeprom`](crate::eeprom::Eeprom), it is a log: Each [`KvStore::set`] appends an entry with the
+//! key and the new value to the active block, and the last entry of a key holds its current value.
+//! When the block is full, the current values are copied to the next block of the range, which then
+//! becomes the active one, so the blocks wear evenly.
+//!
+//! Every entry is protected by a CRC-16, so an entry that was cut off by a reset or power loss is
+//! ignored and the key keeps its old value. A block only becomes valid once its header is
+//! programmed as the last step of the copy, so the old block stays in use until then.
+//!
+//! The values aren't kept in RAM, reading one scans the active block.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::kv_store::KvStore;
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::flash::DataFlash;
+//!
+//! const SSID: u16 = 1;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let flash = DataFlash::take(&clocks).unwrap();
+//! // The last two blocks of the data flash.
+//! let mut store = KvStore::new(flash, 6..8).unwrap();
+//! store.set(SSID, b"my network").unwrap();
+//! let mut buffer = [0; 32];
+//! assert_eq!(store.get(SSID, &mut buffer).unwrap(), Some(&b"my network"[..]));
+//! ```
+
+use crate::flash_blocks::{self, crc16, BLOCK_HEADER_SIZE};
+use crate::peripherals::flash::{self, Blocking, DataFlash, DATA_FLASH_BLOCK_SIZE};
+
+use core::ops::Range;
+
+/// Marks a block that holds the entries of the store.
+const MAGIC: u32 = 0x4b56_5331;
+
+/// Size of the header of an entry: The key, the length of the value, the complement of the length
+/// and the CRC of the key, the length and the value.
+const ENTRY_HEADER_SIZE: u32 = 8;
+
+/// Set in the length of an entry that removes the key.
+const REMOVED: u16 = 0x8000;
+
+/// Maximum size of a value.
+pub const MAX_VALUE_SIZE: usize =
+    (DATA_FLASH_BLOCK_SIZE - BLOCK_HEADER_SIZE - ENTRY_HEADER_SIZE) as usize;
+
+/// Size of the buffer for copying and checking values.
+const CHUNK_SIZE: usize = 32;
+
+/// Errors of the key-value store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The flash driver failed.
+    Flash(flash::Error),
+    /// The value is larger than [`MAX_VALUE_SIZE`].
+    TooLarge,
+    /// The current values and the new one don't fit in a block.
+    Full,
+    /// The value doesn't fit in the buffer.
+    BufferTooSmall,
+}
+
+impl From<flash::Error> for Error {
+    fn from(error: flash::Error) -> Self {
+        Error::Flash(error)
+    }
+}
+
+/// An entry in the active block.
+#[derive(Clone, Copy)]
+struct Entry {
+    /// Offset of the entry in the data flash.
+    offset: u32,
+    key: u16,
+    /// The length of the value, with [`REMOVED`] if the entry removes the key.
+    length: u16,
+    crc: u16,
+}
+
+impl Entry {
+    fn value_len(&self) -> u32 {
+        (self.length & !REMOVED) as u32
+    }
+
+    fn value_offset(&self) -> u32 {
+        self.offset + ENTRY_HEADER_SIZE
+    }
+
+    fn end(&self) -> u32 {
+        self.value_offset() + self.value_len()
+    }
+
+    fn is_removal(&self) -> bool {
+        self.length & REMOVED != 0
+    }
+
+    fn header(&self) -> [u8; ENTRY_HEADER_SIZE as usize] {
+        let [k0, k1] = self.key.to_le_bytes();
+        let [l0, l1] = self.length.to_le_bytes();
+        let [n0, n1] = (!self.length).to_le_bytes();
+        let [c0, c1] = self.crc.to_le_bytes();
+        [k0, k1, l0, l1, n0, n1, c0, c1]
+    }
+}
+
+/// A key-value store on a range of data flash blocks.
+pub struct KvStore {
+    flash: DataFlash<Blocking>,
+    blocks: Range<u32>,
+    /// The block with the current entries.
+    active: u32,
+    sequence: u32,
+    /// Offset of the next entry in the data flash.
+    next: u32,
+}
+
+impl KvStore {
+    /// Open the store in the data flash blocks `blocks`, e.g. `6..8` for the last two of the 8
+    /// blocks. If none of them holds valid entries, the store is empty, and the first block is
+    /// erased for it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `blocks` holds fewer than 2 blocks.
+    pub fn new(flash: DataFlash<Blocking>, blocks: Range<u32>) -> Result<Self, Error> {
+        assert!(blocks.len() >= 2, "the store needs at least two blocks");
+        let mut store = Self {
+            flash,
+            active: blocks.start,
+            blocks,
+            sequence: 0,
+            next: 0,
+        };
+        match flash_blocks::find_active(&mut store.flash, store.blocks.clone(), MAGIC)? {
+            Some((active, sequence)) => {
+                store.active = active;
+                store.sequence = sequence;
+                store.next = store.find_end()?;
+            }
+            None => {
+                let start = store.start_block(store.blocks.start)?;
+                store.finish_block(store.blocks.start, 0, start)?;
+            }
+        }
+        Ok(store)
+    }
+
+    /// Returns the flash driver.
+    pub fn release(self) -> DataFlash<Blocking> {
+        self.flash
+    }
+
+    /// Read the value of `key` into `buffer` and return it, or `None` if the key has no value.
+    pub fn get<'a>(&mut self, key: u16, buffer: &'a mut [u8]) -> Result<Option<&'a [u8]>, Error> {
+        let Some(entry) = self.find(key)? else {
+            return Ok(None);
+        };
+        let len = entry.value_len() as usize;
+        if len > buffer.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        self.flash.read(entry.value_offset(), &mut buffer[..len])?;
+        Ok(Some(&buffer[..len]))
+    }
+
+    /// Returns true if `key` has a value.
+    pub fn contains(&mut self, key: u16) -> Result<bool, Error> {
+        Ok(self.find(key)?.is_some())
+    }
+
+    /// Set the value of `key` to `value`. Doesn't touch the flash if the key already has this
+    /// value.
+    pub fn set(&mut self, key: u16, value: &[u8]) -> Result<(), Error> {
+        if value.len() > MAX_VALUE_SIZE {
+            return Err(Error::TooLarge);
+        }
+        if let Some(entry) = self.find(key)? {
+            if entry.value_len() as usize == value.len() && self.value_equals(&entry, value)? {
+                return Ok(());
+            }
+        }
+        let length = value.len() as u16;
+        let crc = crc16(crc16(0xffff, &key.to_le_bytes()), &length.to_le_bytes());
+        self.append(key, length, crc16(crc, value), value)
+    }
+
+    /// Remove the value of `key`.
+    pub fn remove(&mut self, key: u16) -> Result<(), Error> {
+        if self.find(key)?.is_none() {
+            return Ok(());
+        }
+        let crc = crc16(crc16(0xffff, &key.to_le_bytes()), &REMOVED.to_le_bytes());
+        self.append(key, REMOVED, crc, &[])
+    }
+
+    /// Remove all values.
+    pub fn clear(&mut self) -> Result<(), Error> {
+        let block = self.next_block();
+        let start = self.start_block(block)?;
+        self.finish_block(block, self.sequence.wrapping_add(1), start)
+    }
+
+    /// Append an entry, and move the current values to the next block first if it doesn't fit.
+    fn append(&mut self, key: u16, length: u16, crc: u16, value: &[u8]) -> Result<(), Error> {
+        let entry_size = ENTRY_HEADER_SIZE + value.len() as u32;
+        let block_end = (self.active + 1) * DATA_FLASH_BLOCK_SIZE;
+        if self.next + entry_size > block_end {
+            let size = BLOCK_HEADER_SIZE + self.live_size(key)?;
+            if size + entry_size > DATA_FLASH_BLOCK_SIZE {
+                return Err(Error::Full);
+            }
+            self.compact(key)?;
+            if length & REMOVED != 0 {
+                // The key was dropped with the old entries.
+                return Ok(());
+            }
+        }
+        let entry = Entry {
+            offset: self.next,
+            key,
+            length,
+            crc,
+        };
+        // The space is used up even if programming fails half way.
+        self.next = entry.end();
+        self.flash.program(entry.offset, &entry.header())?;
+        self.flash.program(entry.value_offset(), value)?;
+        Ok(())
+    }
+
+    /// Returns the last valid entry of `key`, unless it removes the key.
+    fn find(&mut self, key: u16) -> Result<Option<Entry>, Error> {
+        let mut found = None;
+        let mut offset = self.active * DATA_FLASH_BLOCK_SIZE + BLOCK_HEADER_SIZE;
+        while let Some(entry) = self.entry_at(offset)? {
+            offset = entry.end();
+            if entry.key == key && self.is_valid(&entry)? {
+                found = Some(entry);
+            }
+        }
+        Ok(found.filter(|entry| !entry.is_removal()))
+    }
+
+    /// Returns the entry at `offset`, or `None` at the end of the entries.
+    fn entry_at(&mut self, offset: u32) -> Result<Option<Entry>, Error> {
+        if offset + ENTRY_HEADER_SIZE > self.next {
+            return Ok(None);
+        }
+        let mut header = [0; ENTRY_HEADER_SIZE as usize];
+        self.flash.read(offset, &mut header)?;
+        let [k0, k1, l0, l1, n0, n1, c0, c1] = header;
+        let length = u16::from_le_bytes([l0, l1]);
+        let entry = Entry {
+            offset,
+            key: u16::from_le_bytes([k0, k1]),
+            length,
+            crc: u16::from_le_bytes([c0, c1]),
+        };
+        if u16::from_le_bytes([n0, n1]) != !length || entry.end() > self.next {
+            return Ok(None);
+        }
+        Ok(Some(entry))
+    }
+
+    /// Returns true if the CRC of `entry` matches.
+    fn is_valid(&mut self, entry: &Entry) -> Result<bool, Error> {
+        let mut crc = crc16(0xffff, &entry.key.to_le_bytes());
+        crc = crc16(crc, &entry.length.to_le_bytes());
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut offset = entry.value_offset();
+        while offset < entry.end() {
+            let len = ((entry.end() - offset) as usize).min(CHUNK_SIZE);
+            self.flash.read(offset, &mut chunk[..len])?;
+            crc = crc16(crc, &chunk[..len]);
+            offset += len as u32;
+        }
+        Ok(crc == entry.crc)
+    }
+
+    /// Returns true if the value of `entry` equals `value`.
+    fn value_equals(&mut self, entry: &Entry, value: &[u8]) -> Result<bool, Error> {
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut offset = entry.value_offset();
+        for part in value.chunks(CHUNK_SIZE) {
+            self.flash.read(offset, &mut chunk[..part.len()])?;
+            if &chunk[..part.len()] != part {
+                return Ok(false);
+            }
+            offset += part.len() as u32;
+        }
+        Ok(true)
+    }
+
+    /// Returns true if `entry` holds the current value of its key.
+    fn is_current(&mut self, entry: &Entry) -> Result<bool, Error> {
+        if entry.is_removal() || !self.is_valid(entry)? {
+            return Ok(false);
+        }
+        let mut offset = entry.end();
+        while let Some(later) = self.entry_at(offset)? {
+            offset = later.end();
+            if later.key == entry.key && self.is_valid(&later)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the size of the current entries, except the one of `skip`.
+    fn live_size(&mut self, skip: u16) -> Result<u32, Error> {
+        let mut size = 0;
+        let mut offset = self.active * DATA_FLASH_BLOCK_SIZE + BLOCK_HEADER_SIZE;
+        while let Some(entry) = self.entry_at(offset)? {
+            offset = entry.end();
+            if entry.key != skip && self.is_current(&entry)? {
+                size += entry.end() - entry.offset;
+            }
+        }
+        Ok(size)
+    }
+
+    /// Copy the current entries, except the one of `skip`, to the next block and make it the
+    /// active block.
+    fn compact(&mut self, skip: u16) -> Result<(), Error> {
+        let block = self.next_block();
+        let mut to = self.start_block(block)?;
+        let mut offset = self.active * DATA_FLASH_BLOCK_SIZE + BLOCK_HEADER_SIZE;
+        let mut chunk = [0; CHUNK_SIZE];
+        while let Some(entry) = self.entry_at(offset)? {
+            offset = entry.end();
+            if entry.key == skip || !self.is_current(&entry)? {
+                continue;
+            }
+            let mut from = entry.offset;
+            while from < entry.end() {
+                let len = ((entry.end() - from) as usize).min(CHUNK_SIZE);
+                self.flash.read(from, &mut chunk[..len])?;
+                self.flash.program(to, &chunk[..len])?;
+                from += len as u32;
+                to += len as u32;
+            }
+        }
+        self.finish_block(block, self.sequence.wrapping_add(1), to)
+    }
+
+    /// Returns the block after the active one.
+    fn next_block(&self) -> u32 {
+        match self.active + 1 {
+            block if block == self.blocks.end => self.blocks.start,
+            block => block,
+        }
+    }
+
+    /// Erase `block` and return the offset of its first entry.
+    fn start_block(&mut self, block: u32) -> Result<u32, Error> {
+        let start = block * DATA_FLASH_BLOCK_SIZE;
+        self.flash.erase(start, start + DATA_FLASH_BLOCK_SIZE)?;
+        Ok(start + BLOCK_HEADER_SIZE)
+    }
+
+    /// Make `block`, with entries up to `next`, the active block with `sequence`.
+    fn finish_block(&mut self, block: u32, sequence: u32, next: u32) -> Result<(), Error> {
+        let start = block * DATA_FLASH_BLOCK_SIZE;
+        // The magic number comes last, it makes the block valid.
+        self.flash.program(start + 4, &sequence.to_le_bytes())?;
+        self.flash.program(start, &MAGIC.to_le_bytes())?;
+        self.active = block;
+        self.sequence = sequence;
+        self.next = next;
+        Ok(())
+    }
+
+    /// Returns the offset after the last entry of the active block. After an entry whose header
+    /// was cut off, the rest of the block is treated as used.
+    fn find_end(&mut self) -> Result<u32, Error> {
+        let block_end = (self.active + 1) * DATA_FLASH_BLOCK_SIZE;
+        // Let entry_at look at the whole block.
+        self.next = block_end;
+        let mut offset = self.active * DATA_FLASH_BLOCK_SIZE + BLOCK_HEADER_SIZE;
+        loop {
+            if offset + ENTRY_HEADER_SIZE > block_end {
+                return Ok(block_end);
+            }
+            if self.flash.blank_check(offset, ENTRY_HEADER_SIZE)? {
+                return Ok(offset);
+            }
+            match self.entry_at(offset)? {
+                Some(entry) => offset = entry.end(),
+                None => return Ok(block_end),
+            }
+        }
+    }
+}
@@ -1,6 +1,47 @@
 #![no_std]
 
+/// Log a driver error with `defmt::error!` if the `defmt` feature is enabled, or do nothing.
+macro_rules! log_error {
+    ($format:literal $(, $arg:expr)* $(,)?) => {
+        #[cfg(feature = "defmt")]
+        defmt::error!($format $(, $arg)*);
+        #[cfg(not(feature = "defmt"))]
+        {
+            $(let _ = &$arg;)*
+        }
+    };
+}
+
+#[cfg(feature = "arduino")]
+pub mod arduino;
+pub mod at;
+pub mod ble;
+pub mod board;
+#[cfg(feature = "defmt-uart")]
+pub mod defmt_uart;
+pub mod eeprom;
+pub mod executor;
+mod flash_blocks;
+pub mod flash_log;
+pub mod http;
+pub mod interrupt;
+pub mod kv_store;
+pub mod math;
+pub mod mqtt;
+pub mod ota;
+#[cfg(feature = "pac")]
+pub mod pac;
 pub mod peripherals;
+pub mod provisioning;
+#[cfg(feature = "rtic")]
+pub mod rtic;
+#[cfg(feature = "rtt")]
+pub mod rtt;
+#[cfg(feature = "embedded-sdmmc")]
+pub mod sdcard;
+#[cfg(feature = "semihosting")]
+pub mod semihosting;
+pub mod wifi;
 
 use core::panic::PanicInfo;
 use core::ptr;
@@ -29,9 +70,32 @@ macro_rules! entry {
     };
 }
 
+#[macro_export]
+/// Macro to set an async entry point, run by [`executor::block_on`]. The type of the function
+/// must be `async fn() -> !`.
+macro_rules! entry_async {
+    ($path:path) => {
+        $crate::entry!(__async_main);
+
+        fn __async_main() -> ! {
+            $crate::executor::block_on($path())
+        }
+    };
+}
+
 #[panic_handler]
-/// Dummy panic handler, loops infinitely.
-fn panic(_panic: &PanicInfo<'_>) -> ! {
+/// Panic handler, loops infinitely. With the `usbd-serial` feature, it reports the panic on the
+/// serial port installed with `peripherals::usb::serial::install` first, if there is one. With the
+/// `semihosting` feature, it prints the panic on the host and exits before that.
+#[cfg_attr(
+    not(any(feature = "usbd-serial", feature = "semihosting")),
+    allow(unused_variables)
+)]
+fn panic(panic: &PanicInfo<'_>) -> ! {
+    #[cfg(feature = "semihosting")]
+    semihosting::report_panic(panic);
+    #[cfg(feature = "usbd-serial")]
+    peripherals::usb::serial::report_panic(panic);
     loop {}
 }
 
@@ -62,6 +126,8 @@ unsafe fn initialize_ram() {
 }
 
 /// Dummy exception handler, loops infinitely. It is public so that it can't be optimized away.
+/// `link.x` also uses it as the default for the exceptions that programs can handle themselves.
+#[no_mangle]
 pub fn default_exception_handler() {
     loop {}
 }
@@ -87,15 +153,22 @@ pub unsafe extern "C" fn Reset() -> ! {
 /// marked as public so they can't be optimized away.
 pub static RESET_VECTOR: unsafe extern "C" fn() -> ! = Reset;
 
+extern "Rust" {
+    fn PendSV();
+    fn SysTick();
+}
+
 #[link_section = ".vector_table.exceptions"]
 #[no_mangle]
 /// Array of pointers to the exception/interrupt handler functions. Some are reserved and set to 0.
-/// The others are set to [`default_exception_handler`]. Comes after the reset pointer in the vector
+/// The NMI is set to [`peripherals::nmi::dispatch`], the others to [`default_exception_handler`].
+/// PendSV and SysTick can be handled by the program instead, by defining a `#[no_mangle]` function
+/// `PendSV` or `SysTick`, e.g. for RTIC monotonics. Comes after the reset pointer in the vector
 /// table.
 pub static EXCEPTIONS: [VectorTableEntry; 14] = [
     // 2: NMI
     VectorTableEntry {
-        handler: default_exception_handler,
+        handler: peripherals::nmi::dispatch,
     },
     // 3: HardFault
     VectorTableEntry {
@@ -128,25 +201,38 @@ pub static EXCEPTIONS: [VectorTableEntry; 14] = [
     },
     // 13: reserved
     VectorTableEntry { reserved: 0 },
-    // 14: PendSV
-    VectorTableEntry {
-        handler: default_exception_handler,
-    },
-    // 15: SysTick
-    VectorTableEntry {
-        handler: default_exception_handler,
-    },
+    // 14: PendSV, see `link.x`.
+    VectorTableEntry { handler: PendSV },
+    // 15: SysTick, see `link.x`.
+    VectorTableEntry { handler: SysTick },
 ];
 
 /// The number of external interrupts is implementation-defined. For the Arduino UNO R4 WIFI, the
 /// number is 32. This number can be calculated from the ICTR register (`0xe000e004`).
-const NUM_EXTERNAL_INTERRUPTS: usize = 32;
+pub(crate) const NUM_EXTERNAL_INTERRUPTS: usize = 32;
 
-#[link_section = ".vector_table.external_interrupts"]
-#[no_mangle]
-/// Pointers to the handlers of external interrupts, all set to [`default_exception_handler`].
-/// Placed in the vector table after [`EXCEPTIONS`].
-pub static EXTERNAL_INTERRUPTS: [VectorTableEntry; NUM_EXTERNAL_INTERRUPTS] = [VectorTableEntry {
-    handler: default_exception_handler,
-};
-    NUM_EXTERNAL_INTERRUPTS];
+/// Declares the handlers of the external interrupts and puts them in the vector table.
+macro_rules! external_interrupts {
+    ($($name:ident),* $(,)?) => {
+        extern "Rust" {
+            $(fn $name();)*
+        }
+
+        #[link_section = ".vector_table.external_interrupts"]
+        #[no_mangle]
+        /// Pointers to the handlers of external interrupts, `IEL0` to `IEL31` after the slots of
+        /// [`peripherals::icu`]. `link.x` sets all of them to [`peripherals::icu::dispatch`], which
+        /// calls the handler registered for the interrupt with [`peripherals::icu::attach`], unless
+        /// the program defines a function with that name, e.g. a hardware task of RTIC (see
+        /// the `rtic` module). Placed in the vector table after [`EXCEPTIONS`].
+        pub static EXTERNAL_INTERRUPTS: [VectorTableEntry; NUM_EXTERNAL_INTERRUPTS] = [
+            $(VectorTableEntry { handler: $name }),*
+        ];
+    };
+}
+
+external_interrupts!(
+    IEL0, IEL1, IEL2, IEL3, IEL4, IEL5, IEL6, IEL7, IEL8, IEL9, IEL10, IEL11, IEL12, IEL13, IEL14,
+    IEL15, IEL16, IEL17, IEL18, IEL19, IEL20, IEL21, IEL22, IEL23, IEL24, IEL25, IEL26, IEL27,
+    IEL28, IEL29, IEL30, IEL31,
+);
@@ -0,0 +1,204 @@
+//! Integer math for sensor values: [`map`] and [`constrain`] like in the Arduino core, the
+//! Q16.16 fixed-point type [`Fixed`], and the filters [`Ema`] and [`median3`].
+//!
+//! The crate is built for soft floats, so floats are slow. [`Fixed`] has 16 bits of fraction,
+//! enough for scale factors and calibration offsets, and computes with integer instructions only.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::math::{constrain, map, median3, Ema, Fixed};
+//!
+//! let mut filter = Ema::new(3);
+//! let raw = median3(512, 530, 498);
+//! let smooth = filter.update(raw);
+//! let percent = constrain(map(smooth, 0, 1023, 0, 100), 0, 100);
+//! // 3.3 V full scale, in millivolts.
+//! let millivolts = (Fixed::from_int(smooth) * Fixed::from_ratio(3300, 1023)).round();
+//! ```
+
+use core::ops::{Add, Div, Mul, Neg, Sub};
+
+/// Returns `value` moved from the range `from_low..=from_high` to `to_low..=to_high`, rounded
+/// towards zero like in the Arduino core. The value isn't clamped, see [`constrain`]. Returns
+/// `to_low` if the input range is empty.
+pub fn map(value: i32, from_low: i32, from_high: i32, to_low: i32, to_high: i32) -> i32 {
+    let from_span = from_high as i64 - from_low as i64;
+    if from_span == 0 {
+        return to_low;
+    }
+    let to_span = to_high as i64 - to_low as i64;
+    let mapped = (value as i64 - from_low as i64) * to_span / from_span + to_low as i64;
+    mapped.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+/// Returns `value` limited to `low..=high`.
+pub fn constrain<T: PartialOrd>(value: T, low: T, high: T) -> T {
+    if value < low {
+        low
+    } else if value > high {
+        high
+    } else {
+        value
+    }
+}
+
+/// Returns the middle one of three values, which drops a single outlier.
+pub fn median3<T: Ord>(a: T, b: T, c: T) -> T {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    if c <= low {
+        low
+    } else if c >= high {
+        high
+    } else {
+        c
+    }
+}
+
+/// A Q16.16 fixed-point number: An `i32` that counts 1/65536ths. Arithmetic saturates instead of
+/// wrapping around.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Fixed(i32);
+
+impl Fixed {
+    /// Bits of the fraction.
+    pub const FRACTION_BITS: u32 = 16;
+    pub const ZERO: Self = Self(0);
+    pub const ONE: Self = Self(1 << Self::FRACTION_BITS);
+
+    /// Returns the number with the raw value `bits`, in 1/65536ths.
+    pub const fn from_bits(bits: i32) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the raw value in 1/65536ths.
+    pub const fn to_bits(self) -> i32 {
+        self.0
+    }
+
+    /// Returns `value`, saturated to -32768..32768.
+    pub const fn from_int(value: i32) -> Self {
+        Self::saturate((value as i64) << Self::FRACTION_BITS)
+    }
+
+    /// Returns `numerator / denominator`, rounded down. A denominator of 0 saturates.
+    pub const fn from_ratio(numerator: i32, denominator: i32) -> Self {
+        if denominator == 0 {
+            return if numerator < 0 {
+                Self(i32::MIN)
+            } else {
+                Self(i32::MAX)
+            };
+        }
+        Self::saturate(((numerator as i64) << Self::FRACTION_BITS) / denominator as i64)
+    }
+
+    /// Returns the integer part, rounded towards negative infinity.
+    pub const fn floor(self) -> i32 {
+        self.0 >> Self::FRACTION_BITS
+    }
+
+    /// Returns the nearest integer, halves rounded up.
+    pub const fn round(self) -> i32 {
+        ((self.0 as i64 + (1 << (Self::FRACTION_BITS - 1))) >> Self::FRACTION_BITS) as i32
+    }
+
+    const fn saturate(value: i64) -> Self {
+        if value > i32::MAX as i64 {
+            Self(i32::MAX)
+        } else if value < i32::MIN as i64 {
+            Self(i32::MIN)
+        } else {
+            Self(value as i32)
+        }
+    }
+}
+
+impl Add for Fixed {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Self::saturate((self.0 as i64 * other.0 as i64) >> Self::FRACTION_BITS)
+    }
+}
+
+impl Div for Fixed {
+    type Output = Self;
+
+    /// Divides, saturating on division by zero.
+    fn div(self, other: Self) -> Self {
+        if other.0 == 0 {
+            return Self::from_ratio(self.0, 0);
+        }
+        Self::saturate(((self.0 as i64) << Self::FRACTION_BITS) / other.0 as i64)
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(self.0.saturating_neg())
+    }
+}
+
+/// Exponential moving average of integer samples: Each sample moves the average by
+/// 1 / 2^`shift` of the difference. Larger shifts smooth more and follow changes more slowly.
+///
+/// The average keeps 16 bits of fraction, so small steps aren't lost to rounding.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Ema {
+    shift: u32,
+    /// The average in Q16.16, `None` before the first sample.
+    average: Option<i64>,
+}
+
+impl Ema {
+    /// Returns a filter that moves by 1 / 2^`shift`, with `shift` capped at 16.
+    pub const fn new(shift: u32) -> Self {
+        Self {
+            shift: if shift > 16 { 16 } else { shift },
+            average: None,
+        }
+    }
+
+    /// Add `sample` and return the new average, rounded. The first sample becomes the average.
+    pub fn update(&mut self, sample: i32) -> i32 {
+        let sample = (sample as i64) << Fixed::FRACTION_BITS;
+        let average = match self.average {
+            Some(average) => average + ((sample - average) >> self.shift),
+            None => sample,
+        };
+        self.average = Some(average);
+        self.value().unwrap_or(0)
+    }
+
+    /// Returns the average, rounded, or `None` before the first sample.
+    pub fn value(&self) -> Option<i32> {
+        let half = 1 << (Fixed::FRACTION_BITS - 1);
+        self.average
+            .map(|average| ((average + half) >> Fixed::FRACTION_BITS) as i32)
+    }
+
+    /// Forget the samples.
+    pub fn reset(&mut self) {
+        self.average = None;
+    }
+}
@@ -0,0 +1,745 @@
+//! MQTT 3.1.1 client over the connections of [`wifi::Stack`].
+//!
+//! [`Client::connect`] opens a TCP or TLS connection to the broker and sends `CONNECT` with the
+//! [`Options`]: client ID, credentials, keep-alive interval and last will. Messages are published
+//! with QoS 0 or 1, and [`Client::subscribe`] registers a handler for a topic filter, with `+` and
+//! `#` wildcards. The handlers are called from [`Client::poll`], and from the other methods while
+//! they wait for the broker, with the messages that match their filter.
+//!
+//! There is no time base besides the busy waits of the client, so the keep-alive interval only
+//! counts the time spent in the methods of the client. Call [`Client::poll`] regularly, with a
+//! wait if there is nothing else to do, so the broker gets a `PINGREQ` in time.
+//!
+//! Incoming packets are limited to [`PACKET_SIZE`] bytes, larger messages are dropped. Subscribing
+//! with QoS 2 isn't supported, the broker sends these messages with QoS 1.
+//!
+//! For the protocol, see the MQTT Version 3.1.1 OASIS Standard.
+//! <https://docs.oasis-open.org/mqtt/mqtt/v3.1.1/mqtt-v3.1.1.html>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::mqtt::{Client, Message, Options, QoS, Will};
+//! use arduino_uno_r4_wifi_rt::wifi::Stack;
+//!
+//! fn on_command(message: &Message) {
+//!     // Handle message.payload.
+//! }
+//!
+//! // With `at` set up and the station connected as in the `wifi` example.
+//! let mut stack = Stack::new(&mut at).unwrap();
+//! let options = Options::new("uno-r4")
+//!     .with_keep_alive(30)
+//!     .with_will(Will::new("devices/uno-r4/status", b"offline").with_retain(true));
+//! let mut client = Client::connect(&mut stack, "broker.example.com", 1883, None, &options).unwrap();
+//! client.subscribe("devices/uno-r4/command/#", QoS::AtLeastOnce, on_command).unwrap();
+//! client.publish("devices/uno-r4/status", b"online", QoS::AtLeastOnce, true).unwrap();
+//! loop {
+//!     client.poll(100).unwrap();
+//! }
+//! ```
+
+use crate::wifi::{self, Protocol, Socket, Stack, TlsConfig};
+
+/// Maximum size of a packet from the broker, and of the packets the client builds in one piece.
+pub const PACKET_SIZE: usize = 1024;
+
+/// Maximum number of subscriptions.
+pub const MAX_SUBSCRIPTIONS: usize = 8;
+
+/// Time between polls of the connection, in ms.
+const POLL_INTERVAL_MS: u32 = 10;
+
+/// Time to wait for the acknowledgement of a packet, in ms.
+const ACK_TIMEOUT_MS: u32 = 10_000;
+
+/// Space for the fixed header in front of a packet: the type and up to 4 bytes of length.
+const HEADER_SPACE: usize = 5;
+
+/// Packet types, in the upper 4 bits of the first byte.
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// Flags of `CONNECT`.
+/// * b1: Clean Session
+/// * b2: Will Flag
+/// * b3-4: Will QoS
+/// * b5: Will Retain
+/// * b6: Password Flag
+/// * b7: User Name Flag
+const CONNECT_CLEAN_SESSION: u8 = 1 << 1;
+const CONNECT_WILL: u8 = 1 << 2;
+const CONNECT_WILL_RETAIN: u8 = 1 << 5;
+const CONNECT_PASSWORD: u8 = 1 << 6;
+const CONNECT_USER_NAME: u8 = 1 << 7;
+
+/// Errors of the MQTT client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Error of the connection.
+    Wifi(wifi::Error),
+    /// The broker refused the connection with this return code of `CONNACK`.
+    Refused(u8),
+    /// The broker didn't answer in time.
+    Timeout,
+    /// The broker closed the connection.
+    Closed,
+    /// The packet doesn't fit in [`PACKET_SIZE`] bytes.
+    PacketTooLarge,
+    /// The broker sent a packet that can't be parsed.
+    InvalidPacket,
+    /// The broker refused the subscription.
+    SubscriptionRefused,
+    /// There are [`MAX_SUBSCRIPTIONS`] subscriptions already.
+    TooManySubscriptions,
+}
+
+impl From<wifi::Error> for Error {
+    fn from(error: wifi::Error) -> Self {
+        Error::Wifi(error)
+    }
+}
+
+/// Quality of service of a message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum QoS {
+    /// Sent once, without acknowledgement.
+    #[default]
+    AtMostOnce = 0,
+    /// Sent until it is acknowledged, so it may arrive more than once.
+    AtLeastOnce = 1,
+}
+
+/// The message the broker publishes when the client disconnects without `DISCONNECT`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+impl<'a> Will<'a> {
+    /// A will with QoS 0 that isn't retained.
+    pub fn new(topic: &'a str, payload: &'a [u8]) -> Self {
+        Self {
+            topic,
+            payload,
+            qos: QoS::AtMostOnce,
+            retain: false,
+        }
+    }
+
+    /// Set the QoS.
+    pub fn with_qos(self, qos: QoS) -> Self {
+        Self { qos, ..self }
+    }
+
+    /// Let the broker retain the will for new subscribers.
+    pub fn with_retain(self, retain: bool) -> Self {
+        Self { retain, ..self }
+    }
+}
+
+/// Settings of the connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Options<'a> {
+    pub client_id: &'a str,
+    pub user_name: Option<&'a str>,
+    pub password: Option<&'a [u8]>,
+    /// Keep-alive interval in s, 0 to disable it.
+    pub keep_alive: u16,
+    /// Drop the session of an earlier connection with the same client ID.
+    pub clean_session: bool,
+    pub will: Option<Will<'a>>,
+}
+
+impl<'a> Options<'a> {
+    /// A clean session for `client_id` without credentials and will, with a keep-alive interval of
+    /// 60 s.
+    pub fn new(client_id: &'a str) -> Self {
+        Self {
+            client_id,
+            user_name: None,
+            password: None,
+            keep_alive: 60,
+            clean_session: true,
+            will: None,
+        }
+    }
+
+    /// Set the user name and password.
+    pub fn with_credentials(self, user_name: &'a str, password: &'a [u8]) -> Self {
+        Self {
+            user_name: Some(user_name),
+            password: Some(password),
+            ..self
+        }
+    }
+
+    /// Set the keep-alive interval in s.
+    pub fn with_keep_alive(self, keep_alive: u16) -> Self {
+        Self { keep_alive, ..self }
+    }
+
+    /// Keep the session of an earlier connection, with its subscriptions and queued messages.
+    pub fn with_clean_session(self, clean_session: bool) -> Self {
+        Self {
+            clean_session,
+            ..self
+        }
+    }
+
+    /// Set the last will.
+    pub fn with_will(self, will: Will<'a>) -> Self {
+        Self {
+            will: Some(will),
+            ..self
+        }
+    }
+}
+
+/// A message from the broker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Message<'m> {
+    pub topic: &'m str,
+    pub payload: &'m [u8],
+    pub qos: QoS,
+    /// The message was retained by the broker, not published just now.
+    pub retain: bool,
+}
+
+/// Returns true if `topic` matches `filter`, with `+` for one level and `#` for the rest.
+fn topic_matches(filter: &str, topic: &str) -> bool {
+    // Topics starting with `$` are for the broker, wildcards don't match them.
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut filter = filter.split('/');
+    let mut topic = topic.split('/');
+    loop {
+        match (filter.next(), topic.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(filter), Some(topic)) if filter == topic => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+/// A topic filter with its handler.
+#[derive(Clone, Copy)]
+struct Subscription {
+    filter: &'static str,
+    handler: fn(&Message),
+}
+
+/// Builds a packet in a buffer, behind space for the fixed header.
+struct Packet<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+    overflow: bool,
+}
+
+impl<'b> Packet<'b> {
+    fn new(buffer: &'b mut [u8]) -> Self {
+        Self {
+            buffer,
+            len: HEADER_SPACE,
+            overflow: false,
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        match self.buffer.get_mut(self.len..self.len + bytes.len()) {
+            Some(slot) => {
+                slot.copy_from_slice(bytes);
+                self.len += bytes.len();
+            }
+            None => self.overflow = true,
+        }
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_be_bytes());
+    }
+
+    /// A string or binary data with its length in front.
+    fn prefixed(&mut self, bytes: &[u8]) {
+        match u16::try_from(bytes.len()) {
+            Ok(len) => {
+                self.u16(len);
+                self.bytes(bytes);
+            }
+            Err(_) => self.overflow = true,
+        }
+    }
+
+    /// Write the fixed header for `first` and a total of `extra` more bytes to be sent after the
+    /// buffer, and return the packet.
+    fn finish(self, first: u8, extra: usize) -> Result<&'b [u8], Error> {
+        let mut remaining = self.len - HEADER_SPACE + extra;
+        if self.overflow || remaining >= 1 << 28 {
+            return Err(Error::PacketTooLarge);
+        }
+        let mut header = [0; HEADER_SPACE];
+        header[0] = first;
+        let mut header_len = 1;
+        loop {
+            let byte = (remaining % 128) as u8;
+            remaining /= 128;
+            header[header_len] = if remaining > 0 { byte | 0x80 } else { byte };
+            header_len += 1;
+            if remaining == 0 {
+                break;
+            }
+        }
+        let start = HEADER_SPACE - header_len;
+        self.buffer[start..HEADER_SPACE].copy_from_slice(&header[..header_len]);
+        Ok(&self.buffer[start..self.len])
+    }
+}
+
+/// An MQTT connection.
+pub struct Client<'s, 'a> {
+    stack: &'s mut Stack<'a>,
+    socket: Option<Socket>,
+    subscriptions: [Option<Subscription>; MAX_SUBSCRIPTIONS],
+    /// Keep-alive interval in ms, 0 if disabled.
+    keep_alive_ms: u32,
+    /// Time since the last packet was sent, in ms.
+    idle_ms: u32,
+    /// Time since the unanswered `PINGREQ` was sent, in ms.
+    ping_ms: Option<u32>,
+    next_packet_id: u16,
+    /// Received data, starting with the next packet.
+    rx: [u8; PACKET_SIZE],
+    rx_len: usize,
+    /// Number of bytes of a packet that is too large still to be dropped.
+    discard: usize,
+    tx: [u8; PACKET_SIZE],
+}
+
+impl<'s, 'a> Client<'s, 'a> {
+    /// Connect to the broker at `port` on `host`, over TLS if `tls` is set, and wait for the
+    /// broker to accept the connection.
+    pub fn connect(
+        stack: &'s mut Stack<'a>,
+        host: &str,
+        port: u16,
+        tls: Option<&TlsConfig>,
+        options: &Options,
+    ) -> Result<Self, Error> {
+        let socket = match tls {
+            Some(tls) => stack.connect_tls(host, port, tls)?,
+            None => stack.connect(Protocol::Tcp, host, port)?,
+        };
+        let mut client = Self {
+            stack,
+            socket: Some(socket),
+            subscriptions: [None; MAX_SUBSCRIPTIONS],
+            keep_alive_ms: options.keep_alive as u32 * 1000,
+            idle_ms: 0,
+            ping_ms: None,
+            next_packet_id: 1,
+            rx: [0; PACKET_SIZE],
+            rx_len: 0,
+            discard: 0,
+            tx: [0; PACKET_SIZE],
+        };
+
+        let mut flags = 0;
+        if options.clean_session {
+            flags |= CONNECT_CLEAN_SESSION;
+        }
+        if let Some(will) = &options.will {
+            flags |= CONNECT_WILL | (will.qos as u8) << 3;
+            if will.retain {
+                flags |= CONNECT_WILL_RETAIN;
+            }
+        }
+        if options.user_name.is_some() {
+            flags |= CONNECT_USER_NAME;
+        }
+        if options.password.is_some() {
+            flags |= CONNECT_PASSWORD;
+        }
+        let mut packet = Packet::new(&mut client.tx);
+        packet.prefixed(b"MQTT");
+        packet.bytes(&[4, flags]);
+        packet.u16(options.keep_alive);
+        packet.prefixed(options.client_id.as_bytes());
+        if let Some(will) = &options.will {
+            packet.prefixed(will.topic.as_bytes());
+            packet.prefixed(will.payload);
+        }
+        if let Some(user_name) = options.user_name {
+            packet.prefixed(user_name.as_bytes());
+        }
+        if let Some(password) = options.password {
+            packet.prefixed(password);
+        }
+        let packet = packet.finish(CONNECT << 4, 0)?;
+        let socket = client.socket.as_ref().ok_or(Error::Closed)?;
+        client.stack.send(socket, packet)?;
+
+        let mut code = 0;
+        client.wait_for(CONNACK, |body| {
+            if let [_, return_code] = body {
+                code = *return_code;
+            }
+        })?;
+        match code {
+            0 => Ok(client),
+            code => Err(Error::Refused(code)),
+        }
+    }
+
+    /// Returns true until the connection is closed or lost.
+    pub fn is_connected(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Publish `payload` to `topic`. With QoS 1, wait until the broker acknowledges it.
+    pub fn publish(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        qos: QoS,
+        retain: bool,
+    ) -> Result<(), Error> {
+        let packet_id = self.packet_id();
+        let mut packet = Packet::new(&mut self.tx);
+        packet.prefixed(topic.as_bytes());
+        if qos == QoS::AtLeastOnce {
+            packet.u16(packet_id);
+        }
+        // Send the payload along if it fits, to save a command.
+        let together = packet.len + payload.len() <= PACKET_SIZE;
+        if together {
+            packet.bytes(payload);
+        }
+        let first = PUBLISH << 4 | (qos as u8) << 1 | retain as u8;
+        let packet = packet.finish(first, if together { 0 } else { payload.len() })?;
+        let socket = self.socket.as_ref().ok_or(Error::Closed)?;
+        self.stack.send(socket, packet)?;
+        if !together {
+            self.stack.send(socket, payload)?;
+        }
+        self.idle_ms = 0;
+        if qos == QoS::AtLeastOnce {
+            self.wait_for_id(PUBACK, packet_id)?;
+        }
+        Ok(())
+    }
+
+    /// Subscribe to `filter` with a maximum QoS of `qos`, and call `handler` with the messages
+    /// that match it.
+    pub fn subscribe(
+        &mut self,
+        filter: &'static str,
+        qos: QoS,
+        handler: fn(&Message),
+    ) -> Result<(), Error> {
+        let slot = self
+            .subscriptions
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::TooManySubscriptions)?;
+        let packet_id = self.packet_id();
+        let mut packet = Packet::new(&mut self.tx);
+        packet.u16(packet_id);
+        packet.prefixed(filter.as_bytes());
+        packet.bytes(&[qos as u8]);
+        let packet = packet.finish(SUBSCRIBE << 4 | 0b0010, 0)?;
+        let socket = self.socket.as_ref().ok_or(Error::Closed)?;
+        self.stack.send(socket, packet)?;
+        self.idle_ms = 0;
+
+        // Register the handler first, so retained messages that arrive before `SUBACK` are
+        // handled.
+        self.subscriptions[slot] = Some(Subscription { filter, handler });
+        let mut granted = 0x80;
+        let result = self.wait_for(SUBACK, |body| {
+            if let [id_high, id_low, code] = body {
+                if u16::from_be_bytes([*id_high, *id_low]) == packet_id {
+                    granted = *code;
+                }
+            }
+        });
+        match result {
+            Ok(()) if granted != 0x80 => Ok(()),
+            result => {
+                self.subscriptions[slot] = None;
+                result.and(Err(Error::SubscriptionRefused))
+            }
+        }
+    }
+
+    /// Unsubscribe from `filter` and remove its handler.
+    pub fn unsubscribe(&mut self, filter: &str) -> Result<(), Error> {
+        for slot in self.subscriptions.iter_mut() {
+            if slot.is_some_and(|subscription| subscription.filter == filter) {
+                *slot = None;
+            }
+        }
+        let packet_id = self.packet_id();
+        let mut packet = Packet::new(&mut self.tx);
+        packet.u16(packet_id);
+        packet.prefixed(filter.as_bytes());
+        let packet = packet.finish(UNSUBSCRIBE << 4 | 0b0010, 0)?;
+        let socket = self.socket.as_ref().ok_or(Error::Closed)?;
+        self.stack.send(socket, packet)?;
+        self.idle_ms = 0;
+        self.wait_for_id(UNSUBACK, packet_id)
+    }
+
+    /// Handle the packets from the broker and keep the connection alive, waiting up to `wait_ms`
+    /// ms for more.
+    pub fn poll(&mut self, wait_ms: u32) -> Result<(), Error> {
+        let mut waited = 0;
+        loop {
+            while let Some((first, len)) = self.receive_packet()? {
+                self.handle_packet(first, len)?;
+                self.consume(len);
+            }
+            self.keep_alive()?;
+            if waited >= wait_ms {
+                return Ok(());
+            }
+            self.delay();
+            waited += POLL_INTERVAL_MS;
+        }
+    }
+
+    /// Send `DISCONNECT`, so the broker drops the will, and close the connection.
+    pub fn disconnect(mut self) -> Result<(), Error> {
+        let Some(socket) = self.socket.take() else {
+            return Ok(());
+        };
+        self.stack.send(&socket, &[DISCONNECT << 4, 0])?;
+        self.stack.close(socket)?;
+        Ok(())
+    }
+
+    /// Returns the next packet ID, which is never 0.
+    fn packet_id(&mut self) -> u16 {
+        let id = self.next_packet_id;
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        id
+    }
+
+    /// Wait one poll interval, counting it for the keep-alive.
+    fn delay(&mut self) {
+        self.stack.at().delay_ms(POLL_INTERVAL_MS);
+        self.idle_ms = self.idle_ms.saturating_add(POLL_INTERVAL_MS);
+        if let Some(ping_ms) = &mut self.ping_ms {
+            *ping_ms = ping_ms.saturating_add(POLL_INTERVAL_MS);
+        }
+    }
+
+    /// Send `PINGREQ` when half the keep-alive interval has passed, and give up on the connection
+    /// if the broker doesn't answer within the interval.
+    fn keep_alive(&mut self) -> Result<(), Error> {
+        if self.keep_alive_ms == 0 {
+            return Ok(());
+        }
+        match self.ping_ms {
+            Some(ping_ms) if ping_ms >= self.keep_alive_ms => {
+                self.socket = None;
+                Err(Error::Timeout)
+            }
+            None if self.idle_ms >= self.keep_alive_ms / 2 => {
+                let socket = self.socket.as_ref().ok_or(Error::Closed)?;
+                self.stack.send(socket, &[PINGREQ << 4, 0])?;
+                self.idle_ms = 0;
+                self.ping_ms = Some(0);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Handle packets until one of `kind` arrives, and call `on_body` with its body.
+    fn wait_for(&mut self, kind: u8, mut on_body: impl FnMut(&[u8])) -> Result<(), Error> {
+        let mut waited = 0;
+        loop {
+            while let Some((first, len)) = self.receive_packet()? {
+                let found = first >> 4 == kind;
+                if found {
+                    let body_start = len - self.body_len(len);
+                    on_body(&self.rx[body_start..len]);
+                } else {
+                    self.handle_packet(first, len)?;
+                }
+                self.consume(len);
+                if found {
+                    return Ok(());
+                }
+            }
+            self.keep_alive()?;
+            if waited >= ACK_TIMEOUT_MS {
+                return Err(Error::Timeout);
+            }
+            self.delay();
+            waited += POLL_INTERVAL_MS;
+        }
+    }
+
+    /// Handle packets until one of `kind` with `packet_id` arrives.
+    fn wait_for_id(&mut self, kind: u8, packet_id: u16) -> Result<(), Error> {
+        loop {
+            let mut id = None;
+            self.wait_for(kind, |body| {
+                if let [high, low, ..] = body {
+                    id = Some(u16::from_be_bytes([*high, *low]));
+                }
+            })?;
+            if id == Some(packet_id) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns the length of the body of the complete packet of `len` bytes at the start of `rx`.
+    fn body_len(&self, len: usize) -> usize {
+        let length_bytes = self.rx[1..len]
+            .iter()
+            .position(|&byte| byte & 0x80 == 0)
+            .unwrap_or(0)
+            + 1;
+        len - 1 - length_bytes
+    }
+
+    /// Drop the first `len` bytes of `rx`.
+    fn consume(&mut self, len: usize) {
+        self.rx.copy_within(len..self.rx_len, 0);
+        self.rx_len -= len;
+    }
+
+    /// Read from the connection and return the first byte and the total length of the packet at
+    /// the start of `rx` if it is complete. Packets that don't fit in `rx` are dropped.
+    fn receive_packet(&mut self) -> Result<Option<(u8, usize)>, Error> {
+        loop {
+            if let Some(packet) = self.complete_packet()? {
+                return Ok(Some(packet));
+            }
+            let socket = self.socket.as_ref().ok_or(Error::Closed)?;
+            let open = self.stack.is_open(socket)?;
+            let count = self.stack.receive(socket, &mut self.rx[self.rx_len..])?;
+            if count == 0 {
+                if !open {
+                    self.socket = None;
+                    return Err(Error::Closed);
+                }
+                return Ok(None);
+            }
+            let dropped = count.min(self.discard);
+            self.discard -= dropped;
+            self.rx
+                .copy_within(self.rx_len + dropped..self.rx_len + count, self.rx_len);
+            self.rx_len += count - dropped;
+        }
+    }
+
+    /// Returns the first byte and the total length of the packet at the start of `rx` if it is
+    /// complete. Starts dropping it if it doesn't fit.
+    fn complete_packet(&mut self) -> Result<Option<(u8, usize)>, Error> {
+        let mut remaining = 0;
+        let mut header_len = 1;
+        loop {
+            let Some(&byte) = self.rx[..self.rx_len].get(header_len) else {
+                return Ok(None);
+            };
+            if header_len == HEADER_SPACE {
+                return Err(Error::InvalidPacket);
+            }
+            remaining |= ((byte & 0x7f) as usize) << (7 * (header_len - 1));
+            header_len += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let len = header_len + remaining;
+        if len > PACKET_SIZE {
+            self.discard = len - self.rx_len;
+            self.rx_len = 0;
+            return Ok(None);
+        }
+        Ok((self.rx_len >= len).then_some((self.rx[0], len)))
+    }
+
+    /// Handle the packet of `len` bytes at the start of `rx` that nothing waits for.
+    fn handle_packet(&mut self, first: u8, len: usize) -> Result<(), Error> {
+        match first >> 4 {
+            PUBLISH => self.handle_publish(first, len),
+            PINGRESP => {
+                self.ping_ms = None;
+                Ok(())
+            }
+            // Late acknowledgements of requests that timed out.
+            CONNACK | PUBACK | SUBACK | UNSUBACK => Ok(()),
+            _ => Err(Error::InvalidPacket),
+        }
+    }
+
+    /// Call the handlers of the message in the `PUBLISH` packet at the start of `rx`, and
+    /// acknowledge it if it has QoS 1.
+    fn handle_publish(&mut self, first: u8, len: usize) -> Result<(), Error> {
+        let body = &self.rx[len - self.body_len(len)..len];
+        let [topic_high, topic_low, rest @ ..] = body else {
+            return Err(Error::InvalidPacket);
+        };
+        let topic_len = u16::from_be_bytes([*topic_high, *topic_low]) as usize;
+        let qos = (first >> 1) & 0b11;
+        let id_len = if qos > 0 { 2 } else { 0 };
+        if rest.len() < topic_len + id_len {
+            return Err(Error::InvalidPacket);
+        }
+        let topic = core::str::from_utf8(&rest[..topic_len]).map_err(|_| Error::InvalidPacket)?;
+        let packet_id = rest.get(topic_len..topic_len + id_len);
+        let message = Message {
+            topic,
+            payload: &rest[topic_len + id_len..],
+            qos: if qos > 0 {
+                QoS::AtLeastOnce
+            } else {
+                QoS::AtMostOnce
+            },
+            retain: first & 1 != 0,
+        };
+        for subscription in self.subscriptions.iter().flatten() {
+            if topic_matches(subscription.filter, message.topic) {
+                (subscription.handler)(&message);
+            }
+        }
+        if let Some(&[high, low]) = packet_id {
+            let socket = self.socket.as_ref().ok_or(Error::Closed)?;
+            self.stack.send(socket, &[PUBACK << 4, 2, high, low])?;
+            self.idle_ms = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Client<'_, '_> {
+    fn drop(&mut self) {
+        if let Some(socket) = self.socket.take() {
+            let _ = self.stack.close(socket);
+        }
+    }
+}
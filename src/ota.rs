@@ -0,0 +1,435 @@
+//! Over-the-air updates of the firmware on the RA4M1.
+//!
+//! The code flash after the Arduino bootloader is split in two halves: The running firmware at
+//! [`APP_START`], and the staging area at [`STAGING_START`]. [`Updater::download`] fetches an
+//! image with the client of [`crate::http`], over TLS for `https://` URLs, and programs it into
+//! the staging area as it arrives. Images from other sources, e.g. MQTT or BLE, are written in
+//! pieces with [`Updater::begin`], [`Updater::write`] and [`Updater::finish`]. Once the whole image
+//! is there, it is checked with the CRC calculator and the signature check given to
+//! [`Updater::new`], and marked as pending.
+//!
+//! [`apply_pending`], called at the start of `main`, copies a pending image over the running
+//! firmware and resets the MCU, so the new firmware starts with the next reset. The mark of the
+//! pending image stays until [`apply_pending`] of the new firmware finds the copy complete, so the
+//! new firmware must call it as well. The copy takes a few seconds. If it is cut off, e.g. by a
+//! power loss, the firmware is broken, and has to be uploaded again through the bootloader: press
+//! the reset button twice.
+//!
+//! An image is a header of [`HEADER_SIZE`] bytes, followed by the firmware as a binary, e.g. from
+//! `arm-none-eabi-objcopy -O binary`. The numbers of the header are little-endian:
+//! * 0-3: `R4UP`.
+//! * 4-7: Version, for the application.
+//! * 8-11: Size of the firmware in bytes, up to [`MAX_IMAGE_SIZE`].
+//! * 12-15: CRC-32 of the firmware, as [`Algorithm::CRC_32`].
+//! * 16-79: Signature of the firmware for the signature check, e.g. Ed25519. Zeros without one.
+//!
+//! **The crate doesn't verify signatures itself.** [`Updater::new`] takes a [`SignatureCheck`],
+//! e.g. one with an Ed25519 crate and your public key. The CRC only finds images that were damaged
+//! on the way, not forged ones, so [`Updater::unsigned`], which checks nothing else, is only for
+//! images downloaded over TLS with a CA certificate (see [`crate::wifi::TlsConfig`]).
+//!
+//! The running firmware must fit in the first half as well, below [`STAGING_START`], which
+//! [`Updater::begin`] checks.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::http::{Client, Request};
+//! use arduino_uno_r4_wifi_rt::ota::{self, Updater};
+//! use arduino_uno_r4_wifi_rt::peripherals::crc::Crc;
+//! use arduino_uno_r4_wifi_rt::peripherals::flash::CodeFlash;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::reset;
+//! use arduino_uno_r4_wifi_rt::wifi::{Stack, TlsConfig};
+//!
+//! // At the start of main, with `clocks` set up.
+//! let mut flash = CodeFlash::take(&clocks).unwrap();
+//! let mut crc = Crc::take(mstp::token()).unwrap();
+//! ota::apply_pending(&mut flash, &mut crc).unwrap();
+//!
+//! // With `at` set up and the station connected as in the `wifi` example.
+//! let mut stack = Stack::new(&mut at).unwrap();
+//! let mut client = Client::new(&mut stack);
+//! let request = Request::get("https://updates.example.com/firmware.bin")
+//!     .with_tls(TlsConfig::default().with_ca_certificate(0));
+//! // The image is only checked with its CRC, so it has to come over TLS with a CA certificate.
+//! let header = Updater::unsigned(&mut flash, &mut crc)
+//!     .download(&mut client, &request)
+//!     .unwrap();
+//! reset::system_reset();
+//! ```
+
+use crate::http::{self, Client, Request};
+use crate::peripherals::crc::{Algorithm, Crc};
+use crate::peripherals::flash::{
+    self, CodeFlash, CODE_FLASH_BLOCK_SIZE, CODE_FLASH_SIZE, CODE_FLASH_WRITE_SIZE,
+};
+
+/// Address of the running firmware, after the Arduino bootloader.
+pub const APP_START: u32 = 0x4000;
+
+/// Address of the staging area, half of the code flash after the bootloader.
+pub const STAGING_START: u32 = APP_START + (CODE_FLASH_SIZE - APP_START) / 2;
+
+/// Address of the block that marks a pending image, the last one.
+const PENDING_ADDRESS: u32 = CODE_FLASH_SIZE - CODE_FLASH_BLOCK_SIZE;
+
+/// Maximum size of the firmware in an image, the staging area without the last block.
+pub const MAX_IMAGE_SIZE: u32 = PENDING_ADDRESS - STAGING_START;
+
+/// Size of the header of an image.
+pub const HEADER_SIZE: usize = 80;
+
+/// First bytes of an image.
+const IMAGE_MAGIC: [u8; 4] = *b"R4UP";
+
+/// First bytes of the mark of a pending image, followed by its version, size and CRC.
+const PENDING_MAGIC: [u8; 4] = *b"R4PD";
+
+/// Size of the mark of a pending image. Each copy of it is recorded in a unit of
+/// [`CODE_FLASH_WRITE_SIZE`] after it.
+const PENDING_MARK_SIZE: u32 = 16;
+
+/// Copies of a pending image before [`apply_pending`] gives up on it.
+const MAX_COPY_ATTEMPTS: u32 = 3;
+
+/// Size of the pieces that are programmed at once, a multiple of [`CODE_FLASH_WRITE_SIZE`].
+const CHUNK_SIZE: usize = 256;
+
+/// Size of the buffer for the status line and headers of the response.
+const RESPONSE_HEAD_SIZE: usize = 512;
+
+/// Errors of the update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The download failed.
+    Http(http::Error),
+    /// The server answered with this status instead of 200.
+    Status(u16),
+    /// The flash driver failed.
+    Flash(flash::Error),
+    /// The image doesn't start with a valid header.
+    InvalidHeader,
+    /// The firmware is larger than [`MAX_IMAGE_SIZE`], or the running one reaches into the staging
+    /// area.
+    TooLarge,
+    /// The image ended before the size in its header, or more data followed.
+    SizeMismatch,
+    /// The CRC of the firmware doesn't match its header.
+    CrcMismatch,
+    /// The signature check rejected the firmware.
+    InvalidSignature,
+    /// [`Updater::write`] or [`Updater::finish`] was called without [`Updater::begin`].
+    NotStarted,
+    /// The copy of the pending image didn't match its CRC after [`MAX_COPY_ATTEMPTS`] attempts.
+    CopyFailed,
+}
+
+impl From<http::Error> for Error {
+    fn from(error: http::Error) -> Self {
+        Error::Http(error)
+    }
+}
+
+impl From<flash::Error> for Error {
+    fn from(error: flash::Error) -> Self {
+        Error::Flash(error)
+    }
+}
+
+/// The header of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImageHeader {
+    pub version: u32,
+    /// Size of the firmware in bytes.
+    pub size: u32,
+    /// CRC-32 of the firmware.
+    pub crc: u32,
+    pub signature: [u8; 64],
+}
+
+impl ImageHeader {
+    /// Parse the first [`HEADER_SIZE`] bytes of an image.
+    pub fn parse(bytes: &[u8; HEADER_SIZE]) -> Result<Self, Error> {
+        if bytes[..4] != IMAGE_MAGIC {
+            return Err(Error::InvalidHeader);
+        }
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        let mut signature = [0; 64];
+        signature.copy_from_slice(&bytes[16..]);
+        Ok(Self {
+            version: word(4),
+            size: word(8),
+            crc: word(12),
+            signature,
+        })
+    }
+}
+
+/// Checks the signature of the firmware, which it gets with its header.
+pub type SignatureCheck = fn(header: &ImageHeader, firmware: &[u8]) -> bool;
+
+/// An image being written to the staging area.
+struct Progress {
+    header: ImageHeader,
+    /// Bytes of the firmware received so far.
+    received: u32,
+    /// Bytes of the firmware programmed so far, the others are in the buffer.
+    programmed: u32,
+}
+
+/// Writes images to the staging area.
+///
+/// The images are checked with their CRC, and with a [`SignatureCheck`] unless made with
+/// [`Updater::unsigned`].
+pub struct Updater<'f> {
+    flash: &'f mut CodeFlash,
+    crc: &'f mut Crc,
+    check_signature: Option<SignatureCheck>,
+    progress: Option<Progress>,
+    buffer: [u8; CHUNK_SIZE],
+}
+
+impl<'f> Updater<'f> {
+    /// An updater that programs `flash`, checks the images with `crc`, and accepts only those
+    /// whose firmware passes `check` after the CRC.
+    pub fn new(flash: &'f mut CodeFlash, crc: &'f mut Crc, check: SignatureCheck) -> Self {
+        Self {
+            check_signature: Some(check),
+            ..Self::unsigned(flash, crc)
+        }
+    }
+
+    /// An updater that programs `flash` and checks the images only with `crc`.
+    ///
+    /// **Any image with a matching CRC-32 is accepted**, and anyone can compute one. Use it only
+    /// for images downloaded over TLS with a CA certificate, or from another trusted source.
+    pub fn unsigned(flash: &'f mut CodeFlash, crc: &'f mut Crc) -> Self {
+        Self {
+            flash,
+            crc,
+            check_signature: None,
+            progress: None,
+            buffer: [0; CHUNK_SIZE],
+        }
+    }
+
+    /// Download the image of `request` with `client`, write it to the staging area and mark it
+    /// as pending. Returns its header.
+    pub fn download(
+        &mut self,
+        client: &mut Client,
+        request: &Request,
+    ) -> Result<ImageHeader, Error> {
+        let mut head = [0; RESPONSE_HEAD_SIZE];
+        let mut response = client.request(request, &mut head)?;
+        if response.status() != 200 {
+            return Err(Error::Status(response.status()));
+        }
+        let mut header = [0; HEADER_SIZE];
+        let mut len = 0;
+        while len < HEADER_SIZE {
+            match response.read(&mut header[len..])? {
+                0 => return Err(Error::InvalidHeader),
+                count => len += count,
+            }
+        }
+        self.begin(&ImageHeader::parse(&header)?)?;
+        let mut data = [0; CHUNK_SIZE];
+        loop {
+            match response.read(&mut data)? {
+                0 => break,
+                count => self.write(&data[..count])?,
+            }
+        }
+        response.close()?;
+        self.finish()
+    }
+
+    /// Start writing the firmware of the image with `header` to the staging area. A pending image
+    /// is dropped.
+    pub fn begin(&mut self, header: &ImageHeader) -> Result<(), Error> {
+        if header.size > MAX_IMAGE_SIZE || firmware_end() > STAGING_START {
+            return Err(Error::TooLarge);
+        }
+        self.progress = None;
+        self.flash.erase(PENDING_ADDRESS, CODE_FLASH_SIZE)?;
+        let end =
+            STAGING_START + header.size.div_ceil(CODE_FLASH_BLOCK_SIZE) * CODE_FLASH_BLOCK_SIZE;
+        self.flash.erase(STAGING_START, end)?;
+        self.progress = Some(Progress {
+            header: *header,
+            received: 0,
+            programmed: 0,
+        });
+        Ok(())
+    }
+
+    /// Write the next bytes of the firmware.
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), Error> {
+        let progress = self.progress.as_mut().ok_or(Error::NotStarted)?;
+        if data.len() as u32 > progress.header.size - progress.received {
+            return Err(Error::SizeMismatch);
+        }
+        while !data.is_empty() {
+            let buffered = (progress.received - progress.programmed) as usize;
+            let count = data.len().min(CHUNK_SIZE - buffered);
+            self.buffer[buffered..buffered + count].copy_from_slice(&data[..count]);
+            progress.received += count as u32;
+            data = &data[count..];
+            if buffered + count == CHUNK_SIZE {
+                self.flash
+                    .program(STAGING_START + progress.programmed, &self.buffer)?;
+                progress.programmed = progress.received;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the firmware that was written, and mark it as pending for [`apply_pending`]. Returns
+    /// its header.
+    pub fn finish(&mut self) -> Result<ImageHeader, Error> {
+        let progress = self.progress.take().ok_or(Error::NotStarted)?;
+        let header = progress.header;
+        if progress.received != header.size {
+            return Err(Error::SizeMismatch);
+        }
+        let buffered = (progress.received - progress.programmed) as usize;
+        if buffered > 0 {
+            // Erased flash reads as 0xff.
+            let len = buffered.next_multiple_of(CODE_FLASH_WRITE_SIZE as usize);
+            self.buffer[buffered..len].fill(0xff);
+            self.flash
+                .program(STAGING_START + progress.programmed, &self.buffer[..len])?;
+        }
+
+        let firmware = staged_firmware(header.size);
+        if self.crc.checksum(Algorithm::CRC_32, firmware) != header.crc {
+            return Err(Error::CrcMismatch);
+        }
+        if let Some(check) = self.check_signature {
+            if !check(&header, firmware) {
+                return Err(Error::InvalidSignature);
+            }
+        }
+
+        let mut mark = [0; PENDING_MARK_SIZE as usize];
+        mark[..4].copy_from_slice(&PENDING_MAGIC);
+        mark[4..8].copy_from_slice(&header.version.to_le_bytes());
+        mark[8..12].copy_from_slice(&header.size.to_le_bytes());
+        mark[12..].copy_from_slice(&header.crc.to_le_bytes());
+        self.flash.program(PENDING_ADDRESS, &mark)?;
+        Ok(header)
+    }
+}
+
+/// Returns the version of the pending image, `None` if there is none.
+pub fn pending_version(flash: &CodeFlash) -> Option<u32> {
+    pending(flash).map(|(version, _, _)| version)
+}
+
+/// Drop the pending image, so it isn't installed.
+pub fn cancel_pending(flash: &mut CodeFlash) -> Result<(), Error> {
+    flash.erase(PENDING_ADDRESS, CODE_FLASH_SIZE)?;
+    Ok(())
+}
+
+/// Install the pending image, if there is one: Copy it over the running firmware and reset the
+/// MCU. Call it at the start of `main`, before anything that a reset would cut off.
+///
+/// The mark of the pending image is only removed once the running firmware matches its CRC, i.e.
+/// by the installed firmware after the reset, which must call this as well. An incomplete copy is
+/// started again, up to [`MAX_COPY_ATTEMPTS`] times.
+///
+/// Returns if there is no pending image or it was installed, with [`Error::CrcMismatch`] if the
+/// staging area was changed since, and with [`Error::CopyFailed`] if the copies failed. In both
+/// cases the mark is removed, so the image isn't copied again.
+pub fn apply_pending(flash: &mut CodeFlash, crc: &mut Crc) -> Result<(), Error> {
+    let Some((_, size, expected)) = pending(flash) else {
+        return Ok(());
+    };
+    if crc.checksum(Algorithm::CRC_32, installed_firmware(size)) == expected {
+        return cancel_pending(flash);
+    }
+    if crc.checksum(Algorithm::CRC_32, staged_firmware(size)) != expected {
+        cancel_pending(flash)?;
+        return Err(Error::CrcMismatch);
+    }
+    let attempts = copy_attempts(flash)?;
+    if attempts >= MAX_COPY_ATTEMPTS {
+        cancel_pending(flash)?;
+        return Err(Error::CopyFailed);
+    }
+    let record = PENDING_ADDRESS + PENDING_MARK_SIZE + attempts * CODE_FLASH_WRITE_SIZE;
+    flash.program(record, &[0; CODE_FLASH_WRITE_SIZE as usize])?;
+    match flash.copy_and_reset(STAGING_START, APP_START, size)? {}
+}
+
+/// Returns the number of copies of the pending image that were started.
+fn copy_attempts(flash: &CodeFlash) -> Result<u32, Error> {
+    let mut attempts = 0;
+    while attempts < MAX_COPY_ATTEMPTS {
+        let mut record = [0; CODE_FLASH_WRITE_SIZE as usize];
+        let address = PENDING_ADDRESS + PENDING_MARK_SIZE + attempts * CODE_FLASH_WRITE_SIZE;
+        flash.read(address, &mut record)?;
+        // Erased flash reads as 0xff.
+        if record.iter().all(|&byte| byte == 0xff) {
+            break;
+        }
+        attempts += 1;
+    }
+    Ok(attempts)
+}
+
+/// Returns the version, size and CRC of the pending image, `None` if there is none.
+fn pending(flash: &CodeFlash) -> Option<(u32, u32, u32)> {
+    let mut mark = [0; PENDING_MARK_SIZE as usize];
+    flash.read(PENDING_ADDRESS, &mut mark).ok()?;
+    if mark[..4] != PENDING_MAGIC {
+        return None;
+    }
+    let word = |offset: usize| {
+        u32::from_le_bytes([
+            mark[offset],
+            mark[offset + 1],
+            mark[offset + 2],
+            mark[offset + 3],
+        ])
+    };
+    let size = word(8);
+    (size <= MAX_IMAGE_SIZE).then_some((word(4), size, word(12)))
+}
+
+/// Returns the `size` bytes of firmware in the staging area.
+fn staged_firmware(size: u32) -> &'static [u8] {
+    let size = size.min(MAX_IMAGE_SIZE) as usize;
+    // The code flash is mapped at address 0, and only changes through the flash driver.
+    unsafe { core::slice::from_raw_parts(STAGING_START as *const u8, size) }
+}
+
+/// Returns the first `size` bytes of the running firmware.
+fn installed_firmware(size: u32) -> &'static [u8] {
+    let size = size.min(MAX_IMAGE_SIZE) as usize;
+    unsafe { core::slice::from_raw_parts(APP_START as *const u8, size) }
+}
+
+/// Returns the end of the running firmware in the code flash, after the initial values of the
+/// variables.
+fn firmware_end() -> u32 {
+    extern "C" {
+        static _sdata: u8;
+        static _edata: u8;
+        static _sidata: u8;
+    }
+    let sdata = core::ptr::addr_of!(_sdata) as u32;
+    let edata = core::ptr::addr_of!(_edata) as u32;
+    core::ptr::addr_of!(_sidata) as u32 + (edata - sdata)
+}
@@ -0,0 +1,562 @@
+//! Typed access to the registers of the RA4M1, for the units and settings the drivers don't cover.
+//!
+//! Each unit is a small handle with one method per register, which returns a [`Reg`] of the right
+//! width and access: [`ReadWrite`], [`ReadOnly`] or [`WriteOnly`]. A `Reg` reads and writes with
+//! volatile accesses, and [`Reg::modify`], [`Reg::set_bits`] and [`Reg::clear_bits`] do
+//! read-modify-write cycles. The bits of the registers are not modelled, see the manual for them.
+//!
+//! Getting a unit is unsafe: The registers are shared with the drivers, and writing them behind a
+//! driver's back breaks its assumptions. The clock of a unit has to be started with
+//! [`crate::peripherals::mstp::token`] before its registers can be written.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, the chapter of
+//! each unit.
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::pac;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp::{self, unit};
+//!
+//! let clock = mstp::token::<unit::Gpt16>();
+//! let gpt = unsafe { pac::gpt(4) };
+//! // Count PCLKD cycles up to 1000 and start.
+//! gpt.gtpr().write(999);
+//! gpt.gtcr().set_bits(1);
+//! let count = gpt.gtcnt().read();
+//! ```
+
+use core::marker::PhantomData;
+use core::ops::{BitAnd, BitOr, Not};
+
+/// A register that can be read and written.
+pub struct ReadWrite;
+
+/// A register that can only be read, e.g. a status register.
+pub struct ReadOnly;
+
+/// A register that can only be written, reads return an undefined value.
+pub struct WriteOnly;
+
+/// Access that allows reading.
+pub trait Readable {}
+impl Readable for ReadWrite {}
+impl Readable for ReadOnly {}
+
+/// Access that allows writing.
+pub trait Writable {}
+impl Writable for ReadWrite {}
+impl Writable for WriteOnly {}
+
+/// Width of a register.
+pub trait Width: Copy + BitAnd<Output = Self> + BitOr<Output = Self> + Not<Output = Self> {}
+impl Width for u8 {}
+impl Width for u16 {}
+impl Width for u32 {}
+
+/// A register of type `T` at a fixed address, with the access `A`.
+pub struct Reg<T, A = ReadWrite> {
+    address: u32,
+    _marker: PhantomData<(T, A)>,
+}
+
+impl<T, A> Clone for Reg<T, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, A> Copy for Reg<T, A> {}
+
+impl<T: Width, A> Reg<T, A> {
+    const fn new(address: u32) -> Self {
+        Self {
+            address,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the address of the register, e.g. for a DMA transfer.
+    pub fn address(self) -> u32 {
+        self.address
+    }
+}
+
+impl<T: Width, A: Readable> Reg<T, A> {
+    /// Read the register.
+    #[inline]
+    pub fn read(self) -> T {
+        unsafe { (self.address as *const T).read_volatile() }
+    }
+}
+
+impl<T: Width, A: Writable> Reg<T, A> {
+    /// Write `value` to the register.
+    #[inline]
+    pub fn write(self, value: T) {
+        unsafe { (self.address as *mut T).write_volatile(value) }
+    }
+}
+
+impl<T: Width> Reg<T, ReadWrite> {
+    /// Replace the value of the register with `f` of its value.
+    #[inline]
+    pub fn modify(self, f: impl FnOnce(T) -> T) {
+        self.write(f(self.read()));
+    }
+
+    /// Set the bits of `mask`.
+    #[inline]
+    pub fn set_bits(self, mask: T) {
+        self.modify(|value| value | mask);
+    }
+
+    /// Clear the bits of `mask`.
+    #[inline]
+    pub fn clear_bits(self, mask: T) {
+        self.modify(|value| value & !mask);
+    }
+}
+
+/// Define the registers of a unit as methods returning a [`Reg`] at their offset from the base
+/// address. Registers that repeat take an index and the distance between them.
+macro_rules! registers {
+    ($unit:ident { $($(#[$doc:meta])* $name:ident: $width:ty, $access:ty, $offset:literal $(, [$count:literal, $step:literal])?;)* }) => {
+        impl $unit {
+            $(registers!(@register $(#[$doc])* $name, $width, $access, $offset $(, $count, $step)?);)*
+        }
+    };
+    (@register $(#[$doc:meta])* $name:ident, $width:ty, $access:ty, $offset:literal) => {
+        $(#[$doc])*
+        #[inline]
+        pub fn $name(&self) -> Reg<$width, $access> {
+            Reg::new(self.base + $offset)
+        }
+    };
+    (@register $(#[$doc:meta])* $name:ident, $width:ty, $access:ty, $offset:literal, $count:literal, $step:literal) => {
+        $(#[$doc])*
+        ///
+        /// Panics if `index` is out of range.
+        #[inline]
+        pub fn $name(&self, index: usize) -> Reg<$width, $access> {
+            assert!(index < $count);
+            Reg::new(self.base + $offset + $step * index as u32)
+        }
+    };
+}
+
+/// System control: Clocks, low power modes, resets and register protection.
+#[derive(Clone, Copy)]
+pub struct System {
+    base: u32,
+}
+
+/// Returns the system control registers.
+///
+/// # Safety
+///
+/// The clock, power and reset drivers use these registers.
+pub unsafe fn system() -> System {
+    System { base: 0x4001e000 }
+}
+
+registers!(System {
+    /// Standby Control Register.
+    sbycr: u16, ReadWrite, 0x00c;
+    /// Module Stop Control Register A.
+    mstpcra: u32, ReadWrite, 0x01c;
+    /// System Clock Division Control Register.
+    sckdivcr: u32, ReadWrite, 0x020;
+    /// System Clock Source Control Register.
+    sckscr: u8, ReadWrite, 0x026;
+    /// Memory Wait Cycle Control Register.
+    memwait: u8, ReadWrite, 0x031;
+    /// Main Clock Oscillator Control Register.
+    mosccr: u8, ReadWrite, 0x032;
+    /// High-Speed On-Chip Oscillator Control Register.
+    hococr: u8, ReadWrite, 0x036;
+    /// Middle-Speed On-Chip Oscillator Control Register.
+    mococr: u8, ReadWrite, 0x038;
+    /// Oscillation Stabilization Flag Register.
+    oscsf: u8, ReadOnly, 0x03c;
+    /// Oscillation Stop Detection Control Register.
+    ostdcr: u8, ReadWrite, 0x040;
+    /// Oscillation Stop Detection Status Register.
+    ostdsr: u8, ReadWrite, 0x041;
+    /// Segment LCD Source Clock Control Register.
+    slcdsckcr: u8, ReadWrite, 0x050;
+    /// MOCO User Trimming Control Register.
+    mocoutcr: u8, ReadWrite, 0x061;
+    /// HOCO User Trimming Control Register.
+    hocoutcr: u8, ReadWrite, 0x062;
+    /// Snooze Control Register.
+    snzcr: u8, ReadWrite, 0x092;
+    /// Snooze End Control Register.
+    snzedcr: u8, ReadWrite, 0x094;
+    /// Snooze Request Control Register.
+    snzreqcr: u32, ReadWrite, 0x098;
+    /// Operating Power Control Register.
+    opccr: u8, ReadWrite, 0x0a0;
+    /// Sub Operating Power Control Register.
+    sopccr: u8, ReadWrite, 0x0aa;
+    /// Reset Status Register 1.
+    rstsr1: u16, ReadWrite, 0x0c0;
+    /// Protect Register.
+    prcr: u16, ReadWrite, 0x3fe;
+    /// Reset Status Register 0.
+    rstsr0: u8, ReadWrite, 0x410;
+    /// Reset Status Register 2.
+    rstsr2: u8, ReadWrite, 0x411;
+    /// Sub-Clock Oscillator Control Register.
+    sosccr: u8, ReadWrite, 0x480;
+    /// Sub-Clock Oscillator Mode Control Register.
+    somcr: u8, ReadWrite, 0x481;
+    /// Low-Speed On-Chip Oscillator Control Register.
+    lococr: u8, ReadWrite, 0x490;
+    /// LOCO User Trimming Control Register.
+    locoutcr: u8, ReadWrite, 0x492;
+});
+
+/// Module Stop Control Registers B-D.
+#[derive(Clone, Copy)]
+pub struct Mstp {
+    base: u32,
+}
+
+/// Returns the module stop control registers.
+///
+/// # Safety
+///
+/// The [`crate::peripherals::mstp`] module counts the users of each unit, and stops a unit whose
+/// last driver is dropped.
+pub unsafe fn mstp() -> Mstp {
+    Mstp { base: 0x40047000 }
+}
+
+registers!(Mstp {
+    mstpcrb: u32, ReadWrite, 0x0;
+    mstpcrc: u32, ReadWrite, 0x4;
+    mstpcrd: u32, ReadWrite, 0x8;
+});
+
+/// Interrupt Controller Unit.
+#[derive(Clone, Copy)]
+pub struct Icu {
+    base: u32,
+}
+
+/// Returns the ICU registers.
+///
+/// # Safety
+///
+/// [`crate::peripherals::icu`] manages the interrupt slots.
+pub unsafe fn icu() -> Icu {
+    Icu { base: 0x40006000 }
+}
+
+registers!(Icu {
+    /// IRQ Control Register of pin interrupt IRQ0-IRQ15.
+    irqcr: u8, ReadWrite, 0x000, [16, 1];
+    /// NMI Pin Interrupt Control Register.
+    nmicr: u8, ReadWrite, 0x100;
+    /// Non-Maskable Interrupt Enable Register.
+    nmier: u16, ReadWrite, 0x120;
+    /// Non-Maskable Interrupt Status Clear Register.
+    nmiclr: u16, ReadWrite, 0x130;
+    /// Non-Maskable Interrupt Status Register.
+    nmisr: u16, ReadOnly, 0x140;
+    /// Wake Up Interrupt Enable Register.
+    wupen: u32, ReadWrite, 0x1a0;
+    /// DMAC Event Link Setting Register of channel 0-3.
+    delsr: u32, ReadWrite, 0x280, [4, 4];
+    /// Interrupt Event Link Setting Register of slot 0-31.
+    ielsr: u32, ReadWrite, 0x300, [32, 4];
+});
+
+/// An I/O port.
+#[derive(Clone, Copy)]
+pub struct Port {
+    base: u32,
+}
+
+/// Returns the registers of port `n`, 0-9.
+///
+/// # Safety
+///
+/// The pins of the port may be owned by [`crate::peripherals::pins`] types.
+pub unsafe fn port(n: u32) -> Port {
+    assert!(n < 10);
+    Port {
+        base: 0x40040000 + 0x20 * n,
+    }
+}
+
+registers!(Port {
+    /// Port Control Register 1: PDR (b0-b15), direction, and PODR (b16-b31), output data.
+    pcntr1: u32, ReadWrite, 0x0;
+    /// Port Control Register 2: PIDR (b0-b15), input data, and EIDR (b16-b31), event input data.
+    pcntr2: u32, ReadOnly, 0x4;
+    /// Port Control Register 3: POSR (b0-b15) sets and PORR (b16-b31) resets outputs.
+    pcntr3: u32, WriteOnly, 0x8;
+    /// Port Control Register 4: EOSR (b0-b15) and EORR (b16-b31), event output set and reset.
+    pcntr4: u32, ReadWrite, 0xc;
+});
+
+/// Pin Function Select Register of pin `pin` of port `port`. Writes only take effect while they
+/// are allowed in PWPR, see [`pwpr`].
+///
+/// # Safety
+///
+/// The pin may be owned by a [`crate::peripherals::pins`] type or a driver.
+pub unsafe fn pfs(port: u32, pin: u32) -> Reg<u32> {
+    assert!(port < 10 && pin < 16);
+    Reg::new(0x40040800 + 4 * (16 * port + pin))
+}
+
+/// Write-Protect Register for the pin function select registers.
+///
+/// # Safety
+///
+/// The pin drivers lock the registers again after each change.
+pub unsafe fn pwpr() -> Reg<u8> {
+    Reg::new(0x40040d03)
+}
+
+/// A Serial Communications Interface channel.
+#[derive(Clone, Copy)]
+pub struct Sci {
+    base: u32,
+}
+
+/// Returns the registers of SCI channel `n`, which is 0, 1, 2 or 9.
+///
+/// # Safety
+///
+/// The channel may be used by a driver.
+pub unsafe fn sci(n: u32) -> Sci {
+    assert!(matches!(n, 0 | 1 | 2 | 9));
+    Sci {
+        base: 0x40070000 + 0x20 * n,
+    }
+}
+
+registers!(Sci {
+    /// Serial Mode Register.
+    smr: u8, ReadWrite, 0x00;
+    /// Bit Rate Register.
+    brr: u8, ReadWrite, 0x01;
+    /// Serial Control Register.
+    scr: u8, ReadWrite, 0x02;
+    /// Transmit Data Register.
+    tdr: u8, ReadWrite, 0x03;
+    /// Serial Status Register.
+    ssr: u8, ReadWrite, 0x04;
+    /// Receive Data Register.
+    rdr: u8, ReadOnly, 0x05;
+    /// Smart Card Mode Register.
+    scmr: u8, ReadWrite, 0x06;
+    /// Serial Extended Mode Register.
+    semr: u8, ReadWrite, 0x07;
+    /// Noise Filter Setting Register.
+    snfr: u8, ReadWrite, 0x08;
+    /// I2C Mode Registers 1-3.
+    simr: u8, ReadWrite, 0x09, [3, 1];
+    /// I2C Status Register.
+    sisr: u8, ReadOnly, 0x0c;
+    /// SPI Mode Register.
+    spmr: u8, ReadWrite, 0x0d;
+    /// Transmit 9-bit Data Register.
+    tdrhl: u16, ReadWrite, 0x0e;
+    /// Receive 9-bit Data Register.
+    rdrhl: u16, ReadOnly, 0x10;
+    /// Modulation Duty Register.
+    mddr: u8, ReadWrite, 0x12;
+});
+
+/// A channel of the General PWM Timer. Channels 0 and 1 have 32-bit counters, 2-7 16-bit ones.
+#[derive(Clone, Copy)]
+pub struct Gpt {
+    base: u32,
+}
+
+/// Returns the registers of GPT channel `n`, 0-7.
+///
+/// # Safety
+///
+/// The channel may be used by a driver.
+pub unsafe fn gpt(n: u32) -> Gpt {
+    assert!(n < 8);
+    Gpt {
+        base: 0x40078000 + 0x100 * n,
+    }
+}
+
+registers!(Gpt {
+    /// General PWM Timer Write-Protection Register.
+    gtwp: u32, ReadWrite, 0x00;
+    /// Software Start Register.
+    gtstr: u32, ReadWrite, 0x04;
+    /// Software Stop Register.
+    gtstp: u32, ReadWrite, 0x08;
+    /// Software Clear Register.
+    gtclr: u32, WriteOnly, 0x0c;
+    /// Start Source Select Register.
+    gtssr: u32, ReadWrite, 0x10;
+    /// Stop Source Select Register.
+    gtpsr: u32, ReadWrite, 0x14;
+    /// Clear Source Select Register.
+    gtcsr: u32, ReadWrite, 0x18;
+    /// Up Count Source Select Register.
+    gtupsr: u32, ReadWrite, 0x1c;
+    /// Down Count Source Select Register.
+    gtdnsr: u32, ReadWrite, 0x20;
+    /// Input Capture Source Select Register A.
+    gticasr: u32, ReadWrite, 0x24;
+    /// Input Capture Source Select Register B.
+    gticbsr: u32, ReadWrite, 0x28;
+    /// Timer Control Register.
+    gtcr: u32, ReadWrite, 0x2c;
+    /// Count Direction and Duty Setting Register.
+    gtuddtyc: u32, ReadWrite, 0x30;
+    /// I/O Control Register.
+    gtior: u32, ReadWrite, 0x34;
+    /// Interrupt Output Setting Register.
+    gtintad: u32, ReadWrite, 0x38;
+    /// Status Register.
+    gtst: u32, ReadWrite, 0x3c;
+    /// Buffer Enable Register.
+    gtber: u32, ReadWrite, 0x40;
+    /// Timer Counter.
+    gtcnt: u32, ReadWrite, 0x48;
+    /// Compare Capture Registers A-F, in the order A, B, C, E, D, F.
+    gtccr: u32, ReadWrite, 0x4c, [6, 4];
+    /// Cycle Setting Register.
+    gtpr: u32, ReadWrite, 0x64;
+    /// Cycle Setting Buffer Register.
+    gtpbr: u32, ReadWrite, 0x68;
+    /// Dead Time Control Register.
+    gtdtcr: u32, ReadWrite, 0x88;
+    /// Dead Time Value Register.
+    gtdvu: u32, ReadWrite, 0x8c;
+});
+
+/// A channel of the Low Power Asynchronous General Purpose Timer.
+#[derive(Clone, Copy)]
+pub struct Agt {
+    base: u32,
+}
+
+/// Returns the registers of AGT channel `n`, 0 or 1.
+///
+/// # Safety
+///
+/// The channel may be used by a driver.
+pub unsafe fn agt(n: u32) -> Agt {
+    assert!(n < 2);
+    Agt {
+        base: 0x40084000 + 0x100 * n,
+    }
+}
+
+registers!(Agt {
+    /// Counter and reload register.
+    agt: u16, ReadWrite, 0x00;
+    /// Compare Match A Register.
+    agtcma: u16, ReadWrite, 0x02;
+    /// Compare Match B Register.
+    agtcmb: u16, ReadWrite, 0x04;
+    /// Control Register.
+    agtcr: u8, ReadWrite, 0x08;
+    /// Mode Register 1.
+    agtmr1: u8, ReadWrite, 0x09;
+    /// Mode Register 2.
+    agtmr2: u8, ReadWrite, 0x0a;
+    /// I/O Control Register.
+    agtioc: u8, ReadWrite, 0x0c;
+    /// Event Pin Select Register.
+    agtisr: u8, ReadWrite, 0x0d;
+    /// Compare Match Function Select Register.
+    agtcmsr: u8, ReadWrite, 0x0e;
+    /// Pin Select Register.
+    agtiosel: u8, ReadWrite, 0x0f;
+});
+
+/// The 14-bit A/D converter.
+#[derive(Clone, Copy)]
+pub struct Adc {
+    base: u32,
+}
+
+/// Returns the ADC registers.
+///
+/// # Safety
+///
+/// [`crate::peripherals::adc`] uses the ADC for the internal channels.
+pub unsafe fn adc() -> Adc {
+    Adc { base: 0x4005c000 }
+}
+
+registers!(Adc {
+    /// A/D Control Register.
+    adcsr: u16, ReadWrite, 0x00;
+    /// A/D Channel Select Registers A0 and A1.
+    adansa: u16, ReadWrite, 0x04, [2, 2];
+    /// A/D-Converted Value Addition/Average Channel Select Registers 0 and 1.
+    adads: u16, ReadWrite, 0x08, [2, 2];
+    /// A/D-Converted Value Addition/Average Count Select Register.
+    adadc: u8, ReadWrite, 0x0c;
+    /// A/D Control Extended Register.
+    adcer: u16, ReadWrite, 0x0e;
+    /// A/D Conversion Start Trigger Select Register.
+    adstrgr: u16, ReadWrite, 0x10;
+    /// A/D Conversion Extended Input Control Register.
+    adexicr: u16, ReadWrite, 0x12;
+    /// A/D Channel Select Registers B0 and B1.
+    adansb: u16, ReadWrite, 0x14, [2, 2];
+    /// A/D Data Duplication Register.
+    addbldr: u16, ReadOnly, 0x18;
+    /// A/D Temperature Sensor Data Register.
+    adtsdr: u16, ReadOnly, 0x1a;
+    /// A/D Internal Reference Voltage Data Register.
+    adocdr: u16, ReadOnly, 0x1c;
+    /// A/D Data Registers of AN000-AN025.
+    addr: u16, ReadOnly, 0x20, [26, 2];
+    /// A/D High-Potential/Low-Potential Reference Voltage Control Register.
+    adhvrefcnt: u8, ReadWrite, 0x8a;
+    /// A/D Sampling State Register of AN016-AN025.
+    adsstrl: u8, ReadWrite, 0xdd;
+    /// A/D Sampling State Register of the temperature sensor.
+    adsstrt: u8, ReadWrite, 0xde;
+    /// A/D Sampling State Register of the internal reference voltage.
+    adsstro: u8, ReadWrite, 0xdf;
+    /// A/D Sampling State Registers of AN000-AN007.
+    adsstr: u8, ReadWrite, 0xe0, [8, 1];
+});
+
+/// The 12-bit D/A converter.
+#[derive(Clone, Copy)]
+pub struct Dac12 {
+    base: u32,
+}
+
+/// Returns the registers of the 12-bit DAC.
+///
+/// # Safety
+///
+/// The DAC may be used by a driver.
+pub unsafe fn dac12() -> Dac12 {
+    Dac12 { base: 0x4005e000 }
+}
+
+registers!(Dac12 {
+    /// D/A Data Register 0.
+    dadr0: u16, ReadWrite, 0x00;
+    /// D/A Control Register.
+    dacr: u8, ReadWrite, 0x04;
+    /// DADR0 Format Select Register.
+    dadpr: u8, ReadWrite, 0x05;
+    /// D/A A/D Synchronous Start Control Register.
+    daadscr: u8, ReadWrite, 0x06;
+    /// D/A VREF Control Register.
+    davrefcr: u8, ReadWrite, 0x07;
+});
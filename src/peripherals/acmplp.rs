@@ -0,0 +1,320 @@
+//! Low-power analog comparator (ACMPLP): Compare an analog input with a reference voltage.
+//!
+//! The unit has two channels. Each compares its input pin with its reference pin, or with the
+//! internal reference voltage (IVREF, about 1.44 V). Channel 1 can also compare with the output of
+//! the 12-bit D/A converter (DA0 on pin A0), for a threshold set at runtime:
+//! * Channel 0: Input CMPIN0 on pin A5 (P100), reference CMPREF0 on pin A4 (P101).
+//! * Channel 1: Input CMPIN1 on pin D10 (P103), reference CMPREF1 on pin D13 (P102).
+//!
+//! [`Comparator::output`] is high while the input is above the reference. When the output changes
+//! on the edges selected in [`Config`], the comparator raises its event ([`Comparator::event`]),
+//! which can interrupt the CPU ([`Comparator::set_interrupt`]), wake it from software standby (see
+//! [`super::power`]), or trigger another peripheral through the [`super::elc`]. The comparator has
+//! no adjustable hysteresis; the digital filter ([`Filter`]) suppresses the chatter of a slowly
+//! changing input instead.
+//!
+//! With [`Config::output_pin`], the result is also driven on the VCOUT pin, which has to be
+//! switched to its peripheral function.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter
+//! "Low-Power Analog Comparator (ACMPLP)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::acmplp::{Comparator, Config, Edge, Reference};
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//!
+//! fn on_zero_cross() {
+//!     // The input crossed the reference.
+//! }
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let mut comparator = Comparator::new(
+//!     pins.a5.into_analog(),
+//!     Reference::Pin(pins.a4.into_analog()),
+//!     mstp::token(),
+//!     &clocks,
+//!     Config::default().with_edge(Edge::Both),
+//! );
+//! comparator.set_interrupt(on_zero_cross).unwrap();
+//! ```
+
+use super::clocks::{self, Clocks};
+use super::icu::{self, Event, Slot};
+use super::mstp::{unit, MstpToken};
+use super::pins::{PinModeAnalog, P100, P101, P102, P103};
+use super::registers::VolatileBoolOps;
+
+/// Comparator Mode Setting Register.
+/// * b0: C0ENB, enables channel 0.
+/// * b1: C0WDE, window mode of channel 0.
+/// * b2: C0VRF, reference of channel 0, 0: CMPREF0, 1: IVREF.
+/// * b3: C0MON, output of channel 0 (read only).
+/// * b4-b7: The same for channel 1.
+const COMPMDR: *mut u8 = 0x40085e00 as *mut u8;
+
+/// Comparator Filter Control Register.
+/// * b0-b1: C0FCK, noise filter of channel 0, see [`Filter`].
+/// * b2: C0EPO, edge polarity of channel 0, 0: rising, 1: falling.
+/// * b3: C0EDG, 1: both edges.
+/// * b4-b7: The same for channel 1.
+const COMPFIR: *mut u8 = 0x40085e01 as *mut u8;
+
+/// Comparator Output Control Register.
+/// * b1: C0OE, drive the output of channel 0 on VCOUT.
+/// * b2: C0OP, invert the output of channel 0 on VCOUT.
+/// * b5-b6: The same for channel 1.
+/// * b7: SPDMD, 0: low-speed mode, 1: high-speed mode, for both channels.
+const COMPOCR: *mut u8 = 0x40085e02 as *mut u8;
+
+const COMPOCR_SPDMD: u8 = 1 << 7;
+
+/// Comparator Input Select Register.
+/// * b0-b2: IVCMP0, input of channel 0, 0b001: CMPIN0.
+/// * b4-b6: IVCMP1, input of channel 1, 0b001: CMPIN1.
+const COMPSEL0: *mut u8 = 0x40085e04 as *mut u8;
+
+/// Comparator Reference Voltage Select Register.
+/// * b0-b2: IVREF0, reference pin of channel 0, 0b001: CMPREF0.
+/// * b4-b6: IVREF1, reference pin of channel 1, 0b001: CMPREF1.
+/// * b7: C1VRF2, internal reference of channel 1 (with C1VRF set), 0: IVREF, 1: D/A output DA0.
+const COMPSEL1: *mut u8 = 0x40085e05 as *mut u8;
+
+const COMPSEL1_C1VRF2: u8 = 1 << 7;
+
+/// Time for a comparator to become stable after it is enabled, in microseconds.
+const STABILIZATION_US: u32 = 100;
+
+/// Errors of the comparator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// All interrupt slots are in use.
+    NoFreeSlot,
+}
+
+/// Digital noise filter on the output: The output only changes after it has been stable for 3
+/// samples of the filter clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Filter {
+    Disabled = 0b00,
+    Pclkb = 0b01,
+    PclkbDiv8 = 0b10,
+    PclkbDiv32 = 0b11,
+}
+
+/// The changes of the output that raise the event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Edge {
+    /// The input rises above the reference.
+    Rising,
+    /// The input falls below the reference.
+    Falling,
+    Both,
+}
+
+impl Edge {
+    /// Returns the C0EPO and C0EDG bits.
+    fn bits(self) -> u8 {
+        match self {
+            Edge::Rising => 0b00,
+            Edge::Falling => 0b01,
+            Edge::Both => 0b10,
+        }
+    }
+}
+
+/// Response time of the comparators. The mode applies to both channels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Speed {
+    /// Responds within about 100 µs and draws the least current.
+    Low,
+    /// Responds within about 1.2 µs.
+    High,
+}
+
+/// The reference voltage of a channel.
+pub enum Reference<P> {
+    /// The reference pin of the channel.
+    Pin(P),
+    /// The internal reference voltage.
+    Internal,
+    /// The output of the 12-bit D/A converter, which has to be started separately. Channel 1
+    /// only.
+    Dac,
+}
+
+/// An input pin of a comparator channel.
+pub trait ComparatorInput {
+    /// The reference pin of the channel.
+    type Reference;
+
+    /// Number of the channel.
+    const CHANNEL: u8;
+}
+
+impl ComparatorInput for P100<PinModeAnalog> {
+    type Reference = P101<PinModeAnalog>;
+    const CHANNEL: u8 = 0;
+}
+
+impl ComparatorInput for P103<PinModeAnalog> {
+    type Reference = P102<PinModeAnalog>;
+    const CHANNEL: u8 = 1;
+}
+
+/// Settings of a comparator channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub filter: Filter,
+    pub edge: Edge,
+    pub speed: Speed,
+    /// Drive the output on the VCOUT pin.
+    pub output_pin: bool,
+    /// Invert the output on the VCOUT pin.
+    pub invert_output: bool,
+}
+
+impl Default for Config {
+    /// No filter, events on rising edges, low speed and no output on VCOUT.
+    fn default() -> Self {
+        Self {
+            filter: Filter::Disabled,
+            edge: Edge::Rising,
+            speed: Speed::Low,
+            output_pin: false,
+            invert_output: false,
+        }
+    }
+}
+
+impl Config {
+    /// Filter the output with `filter`.
+    pub fn with_filter(self, filter: Filter) -> Self {
+        Self { filter, ..self }
+    }
+
+    /// Raise the event on `edge`.
+    pub fn with_edge(self, edge: Edge) -> Self {
+        Self { edge, ..self }
+    }
+
+    /// Use `speed` for both channels.
+    pub fn with_speed(self, speed: Speed) -> Self {
+        Self { speed, ..self }
+    }
+
+    /// Drive the output on the VCOUT pin, inverted if `invert` is true.
+    pub fn with_output_pin(self, invert: bool) -> Self {
+        Self {
+            output_pin: true,
+            invert_output: invert,
+            ..self
+        }
+    }
+}
+
+/// A channel of the low-power analog comparator.
+pub struct Comparator<I: ComparatorInput> {
+    input: I,
+    reference: Reference<I::Reference>,
+    slot: Option<Slot>,
+    _clock: MstpToken<unit::Acmplp>,
+}
+
+impl<I: ComparatorInput> Comparator<I> {
+    /// Compare `input` with `reference`, and wait until the comparator is stable.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reference` is [`Reference::Dac`] for channel 0.
+    pub fn new(
+        input: I,
+        reference: Reference<I::Reference>,
+        clock: MstpToken<unit::Acmplp>,
+        clocks: &Clocks,
+        config: Config,
+    ) -> Self {
+        assert!(
+            I::CHANNEL == 1 || !matches!(reference, Reference::Dac),
+            "channel 0 can't compare with the D/A output"
+        );
+        let shift = 4 * I::CHANNEL;
+        unsafe {
+            COMPMDR.volatile_and(!(0x0f << shift));
+            COMPFIR.volatile_and(!(0x0f << shift));
+            COMPFIR.volatile_or((config.filter as u8 | config.edge.bits() << 2) << shift);
+            COMPOCR.volatile_and(!(0b110 << shift));
+            let output = (config.output_pin as u8) << 1 | (config.invert_output as u8) << 2;
+            COMPOCR.volatile_or(output << shift);
+            match config.speed {
+                Speed::Low => COMPOCR.volatile_and(!COMPOCR_SPDMD),
+                Speed::High => COMPOCR.volatile_or(COMPOCR_SPDMD),
+            }
+            COMPSEL0.volatile_and(!(0b111 << shift));
+            COMPSEL0.volatile_or(0b001 << shift);
+            COMPSEL1.volatile_and(!(0b111 << shift));
+            COMPSEL1.volatile_or(0b001 << shift);
+            if I::CHANNEL == 1 {
+                match reference {
+                    Reference::Dac => COMPSEL1.volatile_or(COMPSEL1_C1VRF2),
+                    _ => COMPSEL1.volatile_and(!COMPSEL1_C1VRF2),
+                }
+            }
+            if matches!(reference, Reference::Internal | Reference::Dac) {
+                COMPMDR.volatile_or(0b100 << shift);
+            }
+            COMPMDR.volatile_or(1 << shift);
+        }
+        clocks::wait_us(clocks.iclk(), STABILIZATION_US);
+        Self {
+            input,
+            reference,
+            slot: None,
+            _clock: clock,
+        }
+    }
+
+    /// Disable the channel and return the pins.
+    pub fn release(mut self) -> (I, Reference<I::Reference>) {
+        self.clear_interrupt();
+        unsafe { COMPMDR.volatile_and(!(0x0f << (4 * I::CHANNEL))) };
+        (self.input, self.reference)
+    }
+
+    /// Returns true if the input is above the reference.
+    pub fn output(&self) -> bool {
+        unsafe { COMPMDR.read_volatile() & (0b1000 << (4 * I::CHANNEL)) != 0 }
+    }
+
+    /// Returns the event of the channel, e.g. to link it to another peripheral with
+    /// [`super::elc::link`].
+    pub fn event(&self) -> Event {
+        match I::CHANNEL {
+            0 => Event::Acmplp0,
+            _ => Event::Acmplp1,
+        }
+    }
+
+    /// Call `handler` when the output changes on the edges of the [`Config`].
+    pub fn set_interrupt(&mut self, handler: fn()) -> Result<(), Error> {
+        self.clear_interrupt();
+        self.slot = icu::attach(self.event(), handler);
+        self.slot.map(|_| ()).ok_or(Error::NoFreeSlot)
+    }
+
+    /// Disable the interrupt and free its interrupt slot.
+    pub fn clear_interrupt(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            icu::detach(slot);
+        }
+    }
+}
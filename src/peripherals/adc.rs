@@ -0,0 +1,169 @@
+//! 14-bit A/D converter (ADC140).
+//!
+//! Besides the analog pins, the ADC converts two internal signals: The output of the temperature
+//! sensor (see [`super::tsn`]) and the internal reference voltage. Each is converted once, with a
+//! single scan started by software, and the previous settings of the ADC are restored afterwards,
+//! so this doesn't disturb another user of the ADC between its scans.
+//!
+//! The ADC measures against AVCC0, the supply voltage. [`read_vcc_millivolts`] converts the
+//! internal reference voltage, which is independent of the supply, and computes the supply voltage
+//! from the result, e.g. to tell how full a battery is without a voltage divider. The reference
+//! varies by a few percent between parts, and so does the result.
+//!
+//! The functions borrow the token of the ADC (see [`super::mstp`]), so its clock runs.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::{adc, mstp};
+//!
+//! let clock = mstp::token();
+//! let low_battery = adc::read_vcc_millivolts(&clock) < 3300;
+//! ```
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "14-Bit
+//! A/D Converter (ADC14)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+
+use super::clocks::Cgc;
+use super::mstp::{unit, MstpToken};
+use super::registers::VolatileBoolOps;
+use crate::interrupt;
+
+/// A/D Control Register.
+/// * b13-b14: ADCS, scan mode, 0: single scan.
+/// * b15: ADST, starts a scan, cleared by the ADC when the scan is done.
+const ADCSR: *mut u16 = 0x4005c000 as *mut u16;
+
+/// A/D Channel Select Registers A0 and A1, one bit per channel AN000-AN015 and AN016-AN025.
+const ADANSA0: *mut u16 = 0x4005c004 as *mut u16;
+const ADANSA1: *mut u16 = 0x4005c006 as *mut u16;
+
+/// A/D Control Extended Register.
+/// * b1-b2: ADPRC, resolution, 0: 12 bits, 1: 10 bits, 3: 14 bits.
+/// * b15: ADRFMT, 0: right-aligned results.
+const ADCER: *mut u16 = 0x4005c00e as *mut u16;
+
+/// A/D Conversion Extended Input Control Register.
+/// * b8: TSSA, convert the temperature sensor output in the scan.
+/// * b9: OCSA, convert the internal reference voltage in the scan.
+const ADEXICR: *mut u16 = 0x4005c012 as *mut u16;
+
+/// A/D Data Registers of channels AN000-AN025, 2 bytes apart.
+#[cfg_attr(not(feature = "arduino"), allow(dead_code))]
+const ADDR_BASE: u32 = 0x4005c020;
+
+/// A/D Temperature Sensor Data Register and A/D Internal Reference Voltage Data Register.
+const ADTSDR: *const u16 = 0x4005c01a as *const u16;
+const ADOCDR: *const u16 = 0x4005c01c as *const u16;
+
+/// A/D Sampling State Registers of the temperature sensor and the internal reference voltage, in
+/// cycles of ADCLK (PCLKC), at least 5.
+const ADSSTRT: *mut u8 = 0x4005c0de as *mut u8;
+const ADSSTRO: *mut u8 = 0x4005c0df as *mut u8;
+
+const ADCSR_ADST: u16 = 1 << 15;
+#[cfg_attr(not(feature = "arduino"), allow(dead_code))]
+const ADCER_14_BITS: u16 = 3 << 1;
+const ADEXICR_TSSA: u16 = 1 << 8;
+const ADEXICR_OCSA: u16 = 1 << 9;
+
+/// Resolution of the conversions of the analog pins, see [`convert_channel`].
+#[cfg_attr(not(feature = "arduino"), allow(dead_code))]
+pub(crate) const CHANNEL_BITS: u32 = 14;
+
+/// Resolution of the conversions of the internal signals, the one the calibration data refers to.
+pub(crate) const INTERNAL_RESOLUTION: u32 = 4096;
+
+/// Typical internal reference voltage, in mV.
+const REFERENCE_MV: u32 = 1430;
+
+/// Minimum sampling time of the internal reference voltage, in nanoseconds.
+const REFERENCE_SAMPLING_NS: u32 = 5000;
+
+/// A signal inside the MCU that the ADC can convert.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Internal {
+    /// The output of the temperature sensor.
+    TemperatureSensor,
+    /// The internal reference voltage.
+    ReferenceVoltage,
+}
+
+/// Convert `signal` once with 12 bits, sampling it for at least `sampling_ns` nanoseconds.
+pub(crate) fn convert_internal(
+    clock: &MstpToken<unit::Adc140>,
+    signal: Internal,
+    sampling_ns: u32,
+) -> u16 {
+    let pclkc = Cgc::current().pclkc();
+    let states = (pclkc as u64 * sampling_ns as u64).div_ceil(1_000_000_000);
+    let states = states.clamp(5, 255) as u8;
+    let (select, sampling, data) = match signal {
+        Internal::TemperatureSensor => (ADEXICR_TSSA, ADSSTRT, ADTSDR),
+        Internal::ReferenceVoltage => (ADEXICR_OCSA, ADSSTRO, ADOCDR),
+    };
+    single_scan(
+        clock,
+        || unsafe {
+            ADEXICR.write_volatile(select);
+            sampling.write_volatile(states);
+        },
+        data,
+    )
+}
+
+/// Convert the analog input channel ANxxx `channel` once with [`CHANNEL_BITS`] bits. The pin of
+/// the channel must be set to analog.
+#[cfg_attr(not(feature = "arduino"), allow(dead_code))]
+pub(crate) fn convert_channel(clock: &MstpToken<unit::Adc140>, channel: u8) -> u16 {
+    let channel = channel as u32;
+    let data = (ADDR_BASE + 2 * channel) as *const u16;
+    single_scan(
+        clock,
+        || unsafe {
+            ADCER.write_volatile(ADCER_14_BITS);
+            if channel < 16 {
+                ADANSA0.write_volatile(1 << channel);
+            } else {
+                ADANSA1.write_volatile(1 << (channel - 16));
+            }
+        },
+        data,
+    )
+}
+
+/// Run a single scan with the settings of `select`, starting from none, and return the result in
+/// `data`. The previous settings are restored afterwards.
+fn single_scan(_clock: &MstpToken<unit::Adc140>, select: impl FnOnce(), data: *const u16) -> u16 {
+    interrupt::free(|| unsafe {
+        let saved = (
+            ADCSR.read_volatile(),
+            ADANSA0.read_volatile(),
+            ADANSA1.read_volatile(),
+            ADCER.read_volatile(),
+            ADEXICR.read_volatile(),
+        );
+        ADCSR.write_volatile(0);
+        ADANSA0.write_volatile(0);
+        ADANSA1.write_volatile(0);
+        ADCER.write_volatile(0);
+        ADEXICR.write_volatile(0);
+        select();
+        ADCSR.volatile_or(ADCSR_ADST);
+        while ADCSR.read_volatile() & ADCSR_ADST != 0 {}
+        let value = data.read_volatile();
+        let (adcsr, adansa0, adansa1, adcer, adexicr) = saved;
+        ADANSA0.write_volatile(adansa0);
+        ADANSA1.write_volatile(adansa1);
+        ADCER.write_volatile(adcer);
+        ADEXICR.write_volatile(adexicr);
+        ADCSR.write_volatile(adcsr & !ADCSR_ADST);
+        value
+    })
+}
+
+/// Returns the supply voltage AVCC0 in mV, measured against the internal reference voltage.
+pub fn read_vcc_millivolts(clock: &MstpToken<unit::Adc140>) -> u32 {
+    let value = convert_internal(clock, Internal::ReferenceVoltage, REFERENCE_SAMPLING_NS) as u32;
+    REFERENCE_MV * INTERNAL_RESOLUTION / value.max(1)
+}
@@ -0,0 +1,185 @@
+//! Async delays, timed with GPT channel 4.
+//!
+//! [`AsyncDelay`] lets the timer count for the delay and waits for its overflow interrupt with
+//! [`icu::wait`], so other tasks of the executor run in the meantime, unlike with the busy-waiting
+//! [`Delay`](super::systick::Delay). The timer counts PCLKD / 16, 3 MHz with the clocks of the
+//! Arduino core, and delays longer than one period of the 16-bit counter take several periods.
+//!
+//! With the `embedded-hal-async` feature, it implements `embedded_hal_async::delay::DelayNs`.
+//!
+//! GPT4 isn't used by the PWM of [`crate::arduino`], and none of its pins are on the headers.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "General
+//! PWM Timer (GPT)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::async_delay::AsyncDelay;
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let mut delay = AsyncDelay::take(mstp::token(), &clocks).unwrap();
+//! async {
+//!     delay.delay_ms(500).await;
+//! };
+//! ```
+
+use super::clocks::{ClockDependent, Clocks};
+use super::icu::{self, Event, Slot};
+use super::mstp::{unit, MstpToken};
+use super::registers::VolatileBoolOps;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// General PWM Timer Control Register of GPT4.
+/// * b0: CST, the counter runs.
+/// * b16-b18: MD, mode, 0 for saw-wave PWM, which counts up and overflows after GTPR.
+/// * b24-b26: TPCS, prescaler of PCLKD, 2 for 16.
+const GTCR: *mut u32 = 0x4007842c as *mut u32;
+
+/// General PWM Timer Status Register of GPT4.
+/// * b6: TCFPO, the counter overflowed. Cleared by writing 0.
+const GTST: *mut u32 = 0x4007843c as *mut u32;
+
+/// General PWM Timer Counter of GPT4.
+const GTCNT: *mut u32 = 0x40078448 as *mut u32;
+
+/// General PWM Timer Cycle Setting Register of GPT4, the period minus 1.
+const GTPR: *mut u32 = 0x40078464 as *mut u32;
+
+const GTCR_CST: u32 = 1 << 0;
+const GTCR_PCLKD_16: u32 = 2 << 24;
+const GTST_TCFPO: u32 = 1 << 6;
+
+/// Prescaler of PCLKD.
+const PRESCALER: u32 = 16;
+
+/// Longest period of the 16-bit counter, in counts.
+const MAX_PERIOD: u64 = 1 << 16;
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Errors of [`AsyncDelay::take`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// There is already an [`AsyncDelay`].
+    InUse,
+    /// All interrupt slots are in use, see [`icu::attach`].
+    NoFreeSlot,
+}
+
+/// Interrupt handler for the overflow. The waiting future is woken by [`icu::dispatch`].
+fn on_overflow() {}
+
+/// Async delays with GPT4.
+pub struct AsyncDelay {
+    slot: Slot,
+    /// Counts of the timer per second.
+    frequency: u32,
+    _clock: MstpToken<unit::Gpt16>,
+}
+
+impl AsyncDelay {
+    /// Take GPT4 for delays, with the timer clock derived from PCLKD of `clocks`.
+    pub fn take(clock: MstpToken<unit::Gpt16>, clocks: &Clocks) -> Result<Self, Error> {
+        if TAKEN.swap(true, Ordering::AcqRel) {
+            return Err(Error::InUse);
+        }
+        let Some(slot) = icu::attach(Event::Gpt4Overflow, on_overflow) else {
+            TAKEN.store(false, Ordering::Release);
+            return Err(Error::NoFreeSlot);
+        };
+        unsafe { GTCR.write_volatile(GTCR_PCLKD_16) };
+        Ok(Self {
+            slot,
+            frequency: clocks.pclkd() / PRESCALER,
+            _clock: clock,
+        })
+    }
+
+    /// Wait for at least `ns` nanoseconds.
+    pub async fn delay_ns(&mut self, ns: u32) {
+        self.delay_counts((ns as u64 * self.frequency as u64).div_ceil(1_000_000_000))
+            .await;
+    }
+
+    /// Wait for at least `us` microseconds.
+    pub async fn delay_us(&mut self, us: u32) {
+        self.delay_counts((us as u64 * self.frequency as u64).div_ceil(1_000_000))
+            .await;
+    }
+
+    /// Wait for at least `ms` milliseconds.
+    pub async fn delay_ms(&mut self, ms: u32) {
+        self.delay_counts((ms as u64 * self.frequency as u64).div_ceil(1000))
+            .await;
+    }
+
+    /// Wait until the timer has counted `counts` times, one period after the other.
+    async fn delay_counts(&mut self, mut counts: u64) {
+        while counts > 0 {
+            let period = counts.min(MAX_PERIOD);
+            counts -= period;
+            self.run(period as u32).await;
+        }
+    }
+
+    /// Let the timer count `period` times and wait for the overflow.
+    async fn run(&mut self, period: u32) {
+        // Stops the timer even if the future is dropped before the overflow.
+        struct Stop;
+        impl Drop for Stop {
+            fn drop(&mut self) {
+                unsafe { GTCR.write_volatile(GTCR_PCLKD_16) };
+            }
+        }
+
+        unsafe {
+            GTCR.write_volatile(GTCR_PCLKD_16);
+            GTPR.write_volatile(period - 1);
+            GTCNT.write_volatile(0);
+            GTST.volatile_and(!GTST_TCFPO);
+        }
+        let overflow = icu::wait(self.slot);
+        let _stop = Stop;
+        unsafe { GTCR.write_volatile(GTCR_PCLKD_16 | GTCR_CST) };
+        overflow.await;
+    }
+}
+
+impl Drop for AsyncDelay {
+    fn drop(&mut self) {
+        unsafe { GTCR.write_volatile(0) };
+        icu::detach(self.slot);
+        TAKEN.store(false, Ordering::Release);
+    }
+}
+
+impl ClockDependent for AsyncDelay {
+    fn set_clocks(&mut self, clocks: &Clocks) {
+        self.frequency = clocks.pclkd() / PRESCALER;
+    }
+}
+
+#[cfg(feature = "embedded-hal-async")]
+mod embedded_hal_async_impl {
+    use super::AsyncDelay;
+    use embedded_hal_async::delay::DelayNs;
+
+    impl DelayNs for AsyncDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            AsyncDelay::delay_ns(self, ns).await
+        }
+
+        async fn delay_us(&mut self, us: u32) {
+            AsyncDelay::delay_us(self, us).await
+        }
+
+        async fn delay_ms(&mut self, ms: u32) {
+            AsyncDelay::delay_ms(self, ms).await
+        }
+    }
+}
@@ -0,0 +1,1014 @@
+//! CAN bus controller on the pins D10 (CTX0) and D13 (CRX0), using the CAN0 unit of the RA4M1.
+//!
+//! The board has no CAN transceiver, so the pins have to be connected to the bus through an
+//! external one, e.g. an SN65HVD230 module.
+//!
+//! [`Can::new`] sets up the unit with the given bit rate. It uses the 32 mailboxes of the unit as
+//! follows:
+//! * Mailboxes 0-3 send frames. [`Can::transmit`] puts a frame into a free one, and the unit sends
+//!   the pending frames in the order of their IDs.
+//! * Mailboxes 4-15 receive frames with standard IDs, 16-31 frames with extended IDs. They accept
+//!   all IDs. [`Can::receive`] returns the frame from the lowest-numbered mailbox that has one, so
+//!   frames that arrive in a burst aren't necessarily returned in order.
+//!
+//! [`Can::set_filters`] changes which frames are received: Each [`Filter::Mask`] takes a group of
+//! 4 mailboxes, each [`Filter::Exact`] one mailbox, and frames that match none of them are
+//! ignored by the hardware. It can also switch on the FIFO mode, in which mailboxes 24-31 are
+//! replaced by a receive FIFO of 4 frames with its own two filters. Frames in the FIFO are
+//! returned in the order they arrived, before those in the mailboxes.
+//!
+//! [`Can::enable_receive_interrupt`] lets the receive interrupts of the unit (see [`super::icu`])
+//! move every frame from the mailboxes and the FIFO into a queue of [`RECEIVE_QUEUE_LEN`] frames,
+//! from which [`Can::receive`] returns them in order. This keeps the mailboxes free during bursts
+//! on the bus while the application is busy. Frames that are lost because the queue is full or
+//! because the interrupt came too late are counted in [`ReceiveCounters`].
+//!
+//! For tests on the bench, [`Can::set_test_mode`] switches to one of the [`TestMode`]s: In
+//! [`TestMode::InternalLoopback`], every transmitted frame is received by the unit itself, without
+//! a transceiver or a second node. In [`TestMode::ListenOnly`], the unit receives frames from a
+//! live bus but never drives it, not even to acknowledge frames.
+//!
+//! With the `embedded-can` feature, [`Can`] implements `embedded_can::nb::Can` and
+//! `embedded_can::blocking::Can`, and [`Frame`] implements `embedded_can::Frame`.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter
+//! "Controller Area Network (CAN) Module".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::can::{Can, Filter, Frame, Id};
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let mut can = Can::new(pins.d10, pins.d13, mstp::token(), &clocks, 500_000).unwrap();
+//! // Only receive the standard IDs 0x100-0x10f, and 0x7df in order.
+//! let range = Filter::Mask { id: Id::Standard(0x100), mask: 0x7f0 };
+//! let fifo = [Filter::Exact(Id::Standard(0x7df)); 2];
+//! can.set_filters(&[range], Some(fifo)).unwrap();
+//! can.transmit(&Frame::new(Id::Standard(0x123), &[1, 2, 3]).unwrap()).unwrap();
+//! let frame = can.receive().unwrap();
+//! ```
+
+use super::clocks::{self, Cgc, Clocks};
+use super::icu::{self, Event, Slot};
+use super::mstp::{unit, MstpToken};
+use super::pins::{PinMode, PinModePeripheral, P102, P103};
+use super::registers::VolatileBoolOps;
+use crate::interrupt;
+
+use core::ops::Range;
+use core::ptr;
+
+/// How long a switch between the modes of the unit may take, in microseconds. The unit finishes
+/// the current frame first, which takes about 13 ms at 10 kbit/s.
+const MODE_TIMEOUT_US: u32 = 50_000;
+
+/// How long [`Can::transmit`] waits for a free transmit mailbox, in microseconds.
+const TRANSMIT_TIMEOUT_US: u32 = 100_000;
+
+/// Interval at which the status is checked while waiting, in microseconds.
+const POLL_US: u32 = 10;
+
+/// Errors of the CAN driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The bit rate can't be derived from the peripheral clock.
+    InvalidBitrate,
+    /// A received frame was overwritten by a newer one before it was read.
+    Overrun,
+    /// The unit has left the bus after too many transmit errors.
+    BusOff,
+    /// There are not enough mailboxes for the filters.
+    TooManyFilters,
+    /// All interrupt slots are in use.
+    NoFreeSlot,
+    /// The unit didn't switch its mode, or no transmit mailbox became free, in time.
+    Timeout,
+    /// The unit is in [`TestMode::ListenOnly`], in which it doesn't transmit.
+    ListenOnly,
+}
+
+/// Identifier of a frame, which is also its priority: Lower IDs win the arbitration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Id {
+    /// 11-bit ID.
+    Standard(u16),
+    /// 29-bit ID.
+    Extended(u32),
+}
+
+impl Id {
+    /// Returns true if the ID fits into its number of bits.
+    fn is_valid(self) -> bool {
+        match self {
+            Id::Standard(id) => id <= 0x7ff,
+            Id::Extended(id) => id <= 0x1fff_ffff,
+        }
+    }
+}
+
+/// Which frames a mailbox or the receive FIFO accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Filter {
+    /// Frames with this ID.
+    Exact(Id),
+    /// Frames whose ID matches `id` in the bits that are 1 in `mask`, and that have the same kind
+    /// of ID (standard or extended) as `id`.
+    Mask { id: Id, mask: u32 },
+}
+
+impl Filter {
+    /// All frames with standard IDs.
+    pub const ALL_STANDARD: Filter = Filter::Mask {
+        id: Id::Standard(0),
+        mask: 0,
+    };
+
+    /// All frames with extended IDs.
+    pub const ALL_EXTENDED: Filter = Filter::Mask {
+        id: Id::Extended(0),
+        mask: 0,
+    };
+
+    /// The ID in the format of the mailbox ID registers.
+    fn id_register(self) -> u32 {
+        let (Filter::Exact(id) | Filter::Mask { id, .. }) = self;
+        match id {
+            Id::Standard(id) => ((id as u32) & 0x7ff) << 18,
+            Id::Extended(id) => (id & 0x1fff_ffff) | Can::ID_IDE,
+        }
+    }
+
+    /// The mask in the format of the mask registers.
+    fn mask_register(self) -> u32 {
+        match self {
+            Filter::Exact(_) => 0x1fff_ffff,
+            Filter::Mask {
+                id: Id::Standard(_),
+                mask,
+            } => (mask & 0x7ff) << 18,
+            Filter::Mask {
+                id: Id::Extended(_),
+                mask,
+            } => mask & 0x1fff_ffff,
+        }
+    }
+}
+
+/// A CAN frame with up to 8 bytes of data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Frame {
+    id: Id,
+    remote: bool,
+    dlc: u8,
+    data: [u8; 8],
+}
+
+impl Frame {
+    const EMPTY: Frame = Frame {
+        id: Id::Standard(0),
+        remote: false,
+        dlc: 0,
+        data: [0; 8],
+    };
+
+    /// A data frame, or `None` if the ID is out of range or there are more than 8 bytes.
+    pub fn new(id: Id, data: &[u8]) -> Option<Self> {
+        if !id.is_valid() || data.len() > 8 {
+            return None;
+        }
+        let mut frame = Self {
+            id,
+            remote: false,
+            dlc: data.len() as u8,
+            data: [0; 8],
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+        Some(frame)
+    }
+
+    /// A remote frame that requests `dlc` bytes, or `None` if the ID is out of range or `dlc` is
+    /// more than 8.
+    pub fn new_remote(id: Id, dlc: u8) -> Option<Self> {
+        if !id.is_valid() || dlc > 8 {
+            return None;
+        }
+        Some(Self {
+            id,
+            remote: true,
+            dlc,
+            data: [0; 8],
+        })
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Returns true for a remote frame, which requests data instead of carrying it.
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    /// Returns the data length code, the number of data bytes.
+    pub fn dlc(&self) -> u8 {
+        self.dlc
+    }
+
+    /// Returns the data, empty for remote frames.
+    pub fn data(&self) -> &[u8] {
+        if self.remote {
+            &[]
+        } else {
+            &self.data[..self.dlc as usize]
+        }
+    }
+}
+
+/// Test modes of the unit, see [`Can::set_test_mode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TestMode {
+    /// Normal operation on the bus.
+    Off,
+    /// Receive frames, but don't transmit anything, including acknowledgements and error frames.
+    /// [`Can::transmit`] returns [`Error::ListenOnly`].
+    ListenOnly,
+    /// Transmit on the bus as usual, and receive the transmitted frames as well. The unit
+    /// acknowledges its own frames, so no other node is needed, but the transceiver must be
+    /// connected.
+    ExternalLoopback,
+    /// Receive the transmitted frames inside the unit without driving the TX pin, so neither a
+    /// transceiver nor another node is needed.
+    InternalLoopback,
+}
+
+/// Number of frames that the receive queue holds, see [`Can::enable_receive_interrupt`].
+pub const RECEIVE_QUEUE_LEN: usize = 32;
+
+/// Frames that were lost on the way to the application since the counters were last reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReceiveCounters {
+    /// Frames that were dropped because the receive queue was full.
+    pub queue_full: u32,
+    /// Frames that were overwritten in a mailbox or dropped by the full FIFO before the interrupt
+    /// handler could read them.
+    pub overruns: u32,
+}
+
+/// Frames moved out of the unit by the interrupt handler, oldest first. Only accessed inside
+/// critical sections and by the interrupt handler.
+struct ReceiveQueue {
+    frames: [Frame; RECEIVE_QUEUE_LEN],
+    start: usize,
+    len: usize,
+    counters: ReceiveCounters,
+    /// The mailboxes and whether the FIFO is used, as in [`Can`].
+    receivers: u32,
+    fifo: bool,
+}
+
+impl ReceiveQueue {
+    fn push(&mut self, frame: Frame) {
+        if self.len == RECEIVE_QUEUE_LEN {
+            self.counters.queue_full = self.counters.queue_full.wrapping_add(1);
+            return;
+        }
+        self.frames[(self.start + self.len) % RECEIVE_QUEUE_LEN] = frame;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<Frame> {
+        if self.len == 0 {
+            return None;
+        }
+        let frame = self.frames[self.start];
+        self.start = (self.start + 1) % RECEIVE_QUEUE_LEN;
+        self.len -= 1;
+        Some(frame)
+    }
+}
+
+static mut RECEIVE_QUEUE: ReceiveQueue = ReceiveQueue {
+    frames: [Frame::EMPTY; RECEIVE_QUEUE_LEN],
+    start: 0,
+    len: 0,
+    counters: ReceiveCounters {
+        queue_full: 0,
+        overruns: 0,
+    },
+    receivers: 0,
+    fifo: false,
+};
+
+/// Handler of the receive interrupts: Move all received frames into the queue.
+fn on_receive() {
+    let queue = unsafe { &mut *ptr::addr_of_mut!(RECEIVE_QUEUE) };
+    loop {
+        match Can::receive_from_unit(queue.receivers, queue.fifo) {
+            Ok(Some(frame)) => queue.push(frame),
+            Ok(None) => break,
+            Err(_) => queue.counters.overruns = queue.counters.overruns.wrapping_add(1),
+        }
+    }
+}
+
+/// Settings of the bit timing, in time quanta (tq).
+struct BitTiming {
+    /// Division of the CAN clock for one tq.
+    prescaler: u32,
+    /// Propagation and phase segment 1, the time from the synchronization segment (1 tq) to the
+    /// sample point.
+    tseg1: u32,
+    /// Phase segment 2, the time from the sample point to the end of the bit.
+    tseg2: u32,
+    /// Resynchronization jump width.
+    sjw: u32,
+}
+
+impl BitTiming {
+    /// Settings for `bitrate` from `clock`, with as many tq per bit as possible and the sample
+    /// point at about 80%.
+    fn new(clock: u32, bitrate: u32) -> Option<Self> {
+        (8..=25).rev().find_map(|tq: u32| {
+            let divider = bitrate.checked_mul(tq)?;
+            if divider == 0 || !clock.is_multiple_of(divider) {
+                return None;
+            }
+            let prescaler = clock / divider;
+            let tseg2 = (tq / 5).clamp(2, 8);
+            let tseg1 = tq - 1 - tseg2;
+            if prescaler > 1024 || tseg1 > 16 {
+                return None;
+            }
+            Some(Self {
+                prescaler,
+                tseg1,
+                tseg2,
+                sjw: tseg2.min(4),
+            })
+        })
+    }
+
+    /// The value of BCR.
+    fn bcr(&self) -> u32 {
+        ((self.tseg1 - 1) << 28)
+            | ((self.prescaler - 1) << 16)
+            | ((self.sjw - 1) << 12)
+            | ((self.tseg2 - 1) << 8)
+    }
+}
+
+/// The CAN0 unit on pins D10 and D13.
+pub struct Can {
+    _tx: P103<PinModePeripheral>,
+    _rx: P102<PinModePeripheral>,
+    _clock: MstpToken<unit::Can0>,
+    /// Bit j is set if mailbox j receives frames.
+    receivers: u32,
+    /// The receive FIFO is used.
+    fifo: bool,
+    /// The slots of the mailbox and FIFO receive interrupts, if enabled.
+    receive_slots: Option<[Slot; 2]>,
+}
+
+impl Can {
+    const BASE_ADDRESS: u32 = 0x40050000;
+
+    /// Mailbox j is at `MB + 16 * j`.
+    /// * +0x00: ID. b0-b17: EID, b18-b28: SID, b30: RTR, remote frame, b31: IDE, extended ID.
+    /// * +0x04: DLC in b0-b3.
+    /// * +0x06-0x0d: The data bytes.
+    const MB: u32 = Self::BASE_ADDRESS + 0x200;
+
+    /// Mask Registers, one for each group of 4 mailboxes. Only the ID bits that are 1 in the mask
+    /// are compared when a frame is received.
+    const MKR: u32 = Self::BASE_ADDRESS + 0x400;
+
+    /// FIFO Received ID Compare Registers 0 and 1, the two filters of the receive FIFO, in the
+    /// format of the mailbox ID registers. Their masks are in the mask registers 6 and 7.
+    const FIDCR: u32 = Self::BASE_ADDRESS + 0x420;
+
+    /// Mask Invalid Register. If bit j is 1, mailbox j only receives its exact ID.
+    const MKIVLR: *mut u32 = (Self::BASE_ADDRESS + 0x428) as *mut u32;
+
+    /// Mailbox Interrupt Enable Register. Bit j enables the interrupt of mailbox j. In FIFO mode,
+    /// b24-b31 control the FIFO interrupts instead:
+    /// * b28: RX FIFO interrupt enable.
+    /// * b29: RX FIFO interrupt when the FIFO becomes full instead of for every frame.
+    const MIER: *mut u32 = (Self::BASE_ADDRESS + 0x42c) as *mut u32;
+    const MIER_RX_FIFO: u32 = 1 << 28;
+
+    /// Message Control Registers, one byte per mailbox at `MCTL + j`.
+    /// * b0: SENTDATA (transmit) or NEWDATA (receive), the frame was sent or received.
+    /// * b1: TRMACTIVE (transmit) or INVALDATA (receive), the mailbox is being accessed.
+    /// * b2: MSGLOST (receive), a frame was overwritten before it was read.
+    /// * b6: RECREQ, use the mailbox for reception.
+    /// * b7: TRMREQ, use the mailbox for transmission.
+    const MCTL: u32 = Self::BASE_ADDRESS + 0x820;
+
+    /// Control Register.
+    /// * b0: MBM, FIFO mailbox mode.
+    /// * b1-b2: IDFM, ID format. 10 for mixed, where each mailbox selects standard or extended.
+    /// * b8-b9: CANM, 00 for operation, 01 for reset and 10 for halt mode.
+    /// * b10: SLPM, sleep mode, which is active after a reset.
+    const CTLR: *mut u16 = (Self::BASE_ADDRESS + 0x840) as *mut u16;
+
+    /// Status Register.
+    /// * b8: RSTST, in reset mode.
+    /// * b9: HLTST, in halt mode.
+    /// * b10: SLPST, in sleep mode.
+    /// * b12: BOST, bus off.
+    const STR: *const u16 = (Self::BASE_ADDRESS + 0x842) as *const u16;
+
+    /// Receive FIFO Control Register.
+    /// * b0: RFE, enable the receive FIFO.
+    /// * b4: RFMLF, a frame was lost because the FIFO was full. Cleared by writing 0.
+    /// * b7: RFEST, the FIFO is empty.
+    const RFCR: *mut u8 = (Self::BASE_ADDRESS + 0x848) as *mut u8;
+
+    /// Receive FIFO Pointer Control Register. Writing 0xff moves on to the next frame.
+    const RFPCR: *mut u8 = (Self::BASE_ADDRESS + 0x849) as *mut u8;
+
+    /// Test Control Register. Can only be written in halt mode.
+    /// * b0: TSTE, enable the test mode.
+    /// * b1-b2: TSTM, 01 for listen-only, 10 for external and 11 for internal loopback.
+    const TCR: *mut u8 = (Self::BASE_ADDRESS + 0x858) as *mut u8;
+
+    /// Bit Configuration Register. Can only be written in reset mode.
+    /// * b0: CCLKS, use CANMCLK instead of PCLKB.
+    /// * b8-b10: TSEG2 - 1.
+    /// * b12-b13: SJW - 1.
+    /// * b16-b25: BRP, the prescaler - 1.
+    /// * b28-b31: TSEG1 - 1.
+    const BCR: *mut u32 = (Self::BASE_ADDRESS + 0x844) as *mut u32;
+
+    const ID_RTR: u32 = 1 << 30;
+    const ID_IDE: u32 = 1 << 31;
+
+    const MCTL_DONE: u8 = 1 << 0;
+    const MCTL_INVALDATA: u8 = 1 << 1;
+    const MCTL_MSGLOST: u8 = 1 << 2;
+    const MCTL_RECREQ: u8 = 1 << 6;
+    const MCTL_TRMREQ: u8 = 1 << 7;
+
+    const CTLR_MBM: u16 = 1 << 0;
+    const CTLR_IDFM_MIXED: u16 = 0b10 << 1;
+    const CTLR_CANM: u16 = 0b11 << 8;
+    const CTLR_CANM_RESET: u16 = 0b01 << 8;
+    const CTLR_CANM_HALT: u16 = 0b10 << 8;
+    const CTLR_SLPM: u16 = 1 << 10;
+
+    const STR_RSTST: u16 = 1 << 8;
+    const STR_HLTST: u16 = 1 << 9;
+    const STR_SLPST: u16 = 1 << 10;
+    const STR_EPST: u16 = 1 << 11;
+    const STR_BOST: u16 = 1 << 12;
+
+    /// TSTE and TSTM of TCR for listen-only mode.
+    const TCR_LISTEN_ONLY: u8 = 0b011;
+
+    const RFCR_RFE: u8 = 1 << 0;
+    const RFCR_RFMLF: u8 = 1 << 4;
+    const RFCR_RFEST: u8 = 1 << 7;
+
+    const TX_MAILBOXES: Range<usize> = 0..4;
+    /// The first mailbox of the receive FIFO, from which the frames are read.
+    const FIFO_MAILBOX: usize = 28;
+
+    /// The filters after [`Can::new`]: All standard IDs in mailboxes 4-15, all extended IDs in
+    /// 16-31.
+    const DEFAULT_FILTERS: [Filter; 7] = [
+        Filter::ALL_STANDARD,
+        Filter::ALL_STANDARD,
+        Filter::ALL_STANDARD,
+        Filter::ALL_EXTENDED,
+        Filter::ALL_EXTENDED,
+        Filter::ALL_EXTENDED,
+        Filter::ALL_EXTENDED,
+    ];
+
+    /// ID register of mailbox `j`.
+    fn mb_id(j: usize) -> *mut u32 {
+        (Self::MB + 16 * j as u32) as *mut u32
+    }
+
+    /// DLC register of mailbox `j`.
+    fn mb_dlc(j: usize) -> *mut u16 {
+        (Self::MB + 16 * j as u32 + 0x04) as *mut u16
+    }
+
+    /// First data byte of mailbox `j`.
+    fn mb_data(j: usize) -> *mut u8 {
+        (Self::MB + 16 * j as u32 + 0x06) as *mut u8
+    }
+
+    /// Mask register of mailboxes `4 * k` to `4 * k + 3`.
+    fn mkr(k: usize) -> *mut u32 {
+        (Self::MKR + 4 * k as u32) as *mut u32
+    }
+
+    /// Message control register of mailbox `j`.
+    fn mctl(j: usize) -> *mut u8 {
+        (Self::MCTL + j as u32) as *mut u8
+    }
+
+    /// FIFO received ID compare register `i`.
+    fn fidcr(i: usize) -> *mut u32 {
+        (Self::FIDCR + 4 * i as u32) as *mut u32
+    }
+
+    /// Set up the CAN0 unit on pins D10 (CTX0) and D13 (CRX0), with a bit rate of `bitrate` bit/s.
+    ///
+    /// Returns [`Error::InvalidBitrate`] if the bit rate can't be derived from PCLKB of `clocks`.
+    /// With the 24 MHz PCLKB of the Arduino setup, the usual rates from 10 kbit/s to 1 Mbit/s work.
+    pub fn new<M1: PinMode, M2: PinMode>(
+        tx: P103<M1>,
+        rx: P102<M2>,
+        clock: MstpToken<unit::Can0>,
+        clocks: &Clocks,
+        bitrate: u32,
+    ) -> Result<Self, Error> {
+        let timing = BitTiming::new(clocks.pclkb(), bitrate).ok_or(Error::InvalidBitrate)?;
+        // Peripheral function 16 is CAN.
+        let tx = tx.into_peripheral(0b10000, 0);
+        let rx = rx.into_peripheral(0b10000, 0);
+        unsafe {
+            Self::CTLR.volatile_and(!Self::CTLR_SLPM);
+            Self::wait_for_status(Self::STR_SLPST, 0)?;
+            Self::enter_reset_mode()?;
+
+            Self::BCR.write_volatile(timing.bcr());
+            Self::CTLR.write_volatile(Self::CTLR_IDFM_MIXED | Self::CTLR_CANM_RESET);
+        }
+        // Leaves reset mode.
+        let mut can = Self {
+            _tx: tx,
+            _rx: rx,
+            _clock: clock,
+            receivers: 0,
+            fifo: false,
+            receive_slots: None,
+        };
+        can.set_filters(&Self::DEFAULT_FILTERS, None)?;
+        Ok(can)
+    }
+
+    /// Receive only the frames that match one of `filters` into the mailboxes 4-31, or 4-23 if
+    /// `fifo` is given. With `fifo`, the receive FIFO is used for the frames that match one of its
+    /// two filters. Frames that match both a mailbox filter and a FIFO filter go to the mailbox.
+    ///
+    /// Each [`Filter::Mask`] takes a group of 4 mailboxes, so 4 matching frames can wait to be
+    /// read, and each [`Filter::Exact`] takes one of the remaining mailboxes. Returns
+    /// [`Error::TooManyFilters`] if they don't fit, and keeps the previous filters then.
+    ///
+    /// The unit is reset to change the filters, which cancels the pending transmissions and drops
+    /// the received frames that haven't been read yet.
+    pub fn set_filters(
+        &mut self,
+        filters: &[Filter],
+        fifo: Option<[Filter; 2]>,
+    ) -> Result<(), Error> {
+        let groups = if fifo.is_some() { 1..6 } else { 1..8 };
+        let mut ids = [None; 32];
+        let mut masks = [0; 8];
+        let mut exact = 0;
+        let mut free_groups = groups.clone();
+        for filter in filters.iter().filter(|f| matches!(f, Filter::Mask { .. })) {
+            let k = free_groups.next().ok_or(Error::TooManyFilters)?;
+            masks[k] = filter.mask_register();
+            ids[4 * k..4 * k + 4].fill(Some(filter.id_register()));
+        }
+        let mut free_mailboxes = free_groups.flat_map(|k| 4 * k..4 * k + 4);
+        for filter in filters.iter().filter(|f| matches!(f, Filter::Exact(_))) {
+            let j = free_mailboxes.next().ok_or(Error::TooManyFilters)?;
+            ids[j] = Some(filter.id_register());
+            exact |= 1 << j;
+        }
+
+        unsafe {
+            Self::enter_reset_mode()?;
+            let mut ctlr = Self::CTLR.read_volatile() & !Self::CTLR_MBM;
+            if let Some(fifo) = fifo {
+                ctlr |= Self::CTLR_MBM;
+                for (i, filter) in fifo.iter().enumerate() {
+                    Self::fidcr(i).write_volatile(filter.id_register());
+                    masks[6 + i] = filter.mask_register();
+                }
+            }
+            Self::CTLR.write_volatile(ctlr);
+            for (k, mask) in masks.iter().enumerate() {
+                Self::mkr(k).write_volatile(*mask);
+            }
+            Self::MKIVLR.write_volatile(exact);
+            let mut receivers = 0;
+            for (j, id) in ids.iter().enumerate() {
+                if let Some(id) = id {
+                    Self::mb_id(j).write_volatile(*id);
+                    receivers |= 1 << j;
+                }
+            }
+            Self::enter_operation_mode()?;
+            for j in 0..32 {
+                if receivers & (1 << j) != 0 {
+                    Self::mctl(j).write_volatile(Self::MCTL_RECREQ);
+                }
+            }
+            if fifo.is_some() {
+                Self::RFCR.write_volatile(Self::RFCR_RFE);
+            }
+            self.receivers = receivers;
+        }
+        self.fifo = fifo.is_some();
+        if self.receive_slots.is_some() {
+            self.update_receive_interrupts();
+        }
+        Ok(())
+    }
+
+    /// Move received frames into a queue from the receive interrupts, and let [`Can::receive`]
+    /// take them from there. Frames that arrive while the queue is full are dropped.
+    ///
+    /// Returns [`Error::NoFreeSlot`] if the two interrupt slots aren't available.
+    pub fn enable_receive_interrupt(&mut self) -> Result<(), Error> {
+        if self.receive_slots.is_some() {
+            return Ok(());
+        }
+        let mailbox = icu::attach(Event::Can0MailboxRx, on_receive).ok_or(Error::NoFreeSlot)?;
+        let Some(fifo) = icu::attach(Event::Can0FifoRx, on_receive) else {
+            icu::detach(mailbox);
+            return Err(Error::NoFreeSlot);
+        };
+        self.receive_slots = Some([mailbox, fifo]);
+        self.update_receive_interrupts();
+        Ok(())
+    }
+
+    /// Stop the receive interrupts. [`Can::receive`] returns the frames left in the queue first,
+    /// and then reads the mailboxes again.
+    pub fn disable_receive_interrupt(&mut self) {
+        if let Some(slots) = self.receive_slots.take() {
+            unsafe {
+                Self::MIER.write_volatile(0);
+            }
+            slots.into_iter().for_each(icu::detach);
+        }
+    }
+
+    /// Returns the numbers of frames lost since the counters were last reset.
+    pub fn receive_counters(&self) -> ReceiveCounters {
+        interrupt::free(|| unsafe { ptr::addr_of!(RECEIVE_QUEUE.counters).read() })
+    }
+
+    /// Set the numbers of lost frames back to 0.
+    pub fn reset_receive_counters(&mut self) {
+        interrupt::free(|| unsafe {
+            ptr::addr_of_mut!(RECEIVE_QUEUE.counters).write(ReceiveCounters::default());
+        });
+    }
+
+    /// Tell the interrupt handler which mailboxes to read, and enable their interrupts.
+    fn update_receive_interrupts(&mut self) {
+        interrupt::free(|| unsafe {
+            let queue = &mut *ptr::addr_of_mut!(RECEIVE_QUEUE);
+            queue.receivers = self.receivers;
+            queue.fifo = self.fifo;
+            let fifo = if self.fifo { Self::MIER_RX_FIFO } else { 0 };
+            Self::MIER.write_volatile(self.receivers | fifo);
+        });
+    }
+
+    /// Switch to reset mode, in which the settings can be changed. Clears all MCTL registers.
+    unsafe fn enter_reset_mode() -> Result<(), Error> {
+        Self::CTLR.write_volatile(
+            (Self::CTLR.read_volatile() & !Self::CTLR_CANM) | Self::CTLR_CANM_RESET,
+        );
+        Self::wait_for_status(Self::STR_RSTST, Self::STR_RSTST)
+    }
+
+    /// Switch to halt mode, in which the unit stops taking part in the bus after the current frame
+    /// but keeps its mailboxes.
+    unsafe fn enter_halt_mode() -> Result<(), Error> {
+        Self::CTLR
+            .write_volatile((Self::CTLR.read_volatile() & !Self::CTLR_CANM) | Self::CTLR_CANM_HALT);
+        Self::wait_for_status(Self::STR_HLTST, Self::STR_HLTST)
+    }
+
+    /// Switch to operation mode, in which the unit takes part in the bus.
+    unsafe fn enter_operation_mode() -> Result<(), Error> {
+        Self::CTLR.volatile_and(!Self::CTLR_CANM);
+        Self::wait_for_status(Self::STR_RSTST | Self::STR_HLTST, 0)
+    }
+
+    /// Wait until the bits of `mask` in STR have the value `value`. Returns [`Error::Timeout`]
+    /// after [`MODE_TIMEOUT_US`].
+    fn wait_for_status(mask: u16, value: u16) -> Result<(), Error> {
+        let iclk = Cgc::current().iclk();
+        for _ in 0..MODE_TIMEOUT_US / POLL_US {
+            if unsafe { Self::STR.read_volatile() } & mask == value {
+                return Ok(());
+            }
+            clocks::wait_us(iclk, POLL_US);
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Switch to a test mode, or back to normal operation with [`TestMode::Off`]. The unit is
+    /// halted for this, which keeps the pending and received frames.
+    ///
+    /// Returns [`Error::Timeout`] if the unit doesn't switch its mode.
+    pub fn set_test_mode(&mut self, mode: TestMode) -> Result<(), Error> {
+        let tcr = match mode {
+            TestMode::Off => 0,
+            TestMode::ListenOnly => Self::TCR_LISTEN_ONLY,
+            TestMode::ExternalLoopback => 0b101,
+            TestMode::InternalLoopback => 0b111,
+        };
+        unsafe {
+            Self::enter_halt_mode()?;
+            Self::TCR.write_volatile(tcr);
+            Self::enter_operation_mode()
+        }
+    }
+
+    /// Returns true if the unit has left the bus after too many transmit errors. It returns to the
+    /// bus on its own after 128 times 11 recessive bits.
+    pub fn is_bus_off(&self) -> bool {
+        unsafe { Self::STR.read_volatile() & Self::STR_BOST != 0 }
+    }
+
+    /// Returns true if the unit is error-passive after many transmit or receive errors, e.g.
+    /// because no other node acknowledges its frames. It keeps retrying the pending frames then.
+    pub fn is_error_passive(&self) -> bool {
+        unsafe { Self::STR.read_volatile() & Self::STR_EPST != 0 }
+    }
+
+    /// Put `frame` into a free transmit mailbox. Returns `Ok(false)` if all are busy.
+    ///
+    /// Returns [`Error::BusOff`] if the unit has left the bus, and [`Error::ListenOnly`] in
+    /// [`TestMode::ListenOnly`].
+    pub fn try_transmit(&mut self, frame: &Frame) -> Result<bool, Error> {
+        if self.is_bus_off() {
+            return Err(Error::BusOff);
+        }
+        if unsafe { Self::TCR.read_volatile() } == Self::TCR_LISTEN_ONLY {
+            return Err(Error::ListenOnly);
+        }
+        let Some(j) = Self::TX_MAILBOXES
+            .clone()
+            .find(|&j| Self::free_transmit_mailbox(j))
+        else {
+            return Ok(false);
+        };
+        unsafe {
+            Self::write_mailbox(j, frame);
+            Self::mctl(j).write_volatile(Self::MCTL_TRMREQ);
+        }
+        Ok(true)
+    }
+
+    /// Wait for a free transmit mailbox and put `frame` into it.
+    ///
+    /// Returns [`Error::Timeout`] if no mailbox becomes free within [`TRANSMIT_TIMEOUT_US`], e.g.
+    /// because no other node acknowledges the pending frames, see [`Can::is_error_passive`], and
+    /// the errors of [`Can::try_transmit`].
+    pub fn transmit(&mut self, frame: &Frame) -> Result<(), Error> {
+        let iclk = Cgc::current().iclk();
+        for _ in 0..TRANSMIT_TIMEOUT_US / POLL_US {
+            if self.try_transmit(frame)? {
+                return Ok(());
+            }
+            clocks::wait_us(iclk, POLL_US);
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Returns true if transmit mailbox `j` is free, clearing it if its frame was sent.
+    fn free_transmit_mailbox(j: usize) -> bool {
+        let mctl = Self::mctl(j);
+        unsafe {
+            let value = mctl.read_volatile();
+            if value & Self::MCTL_TRMREQ != 0 && value & Self::MCTL_DONE == 0 {
+                return false;
+            }
+            if value != 0 {
+                // SENTDATA can only be cleared after TRMREQ.
+                mctl.write_volatile(0);
+                mctl.write_volatile(0);
+            }
+        }
+        true
+    }
+
+    /// Returns the next received frame, or `Ok(None)` if there is none.
+    ///
+    /// Returns [`Error::Overrun`] once if a frame was lost because it wasn't read in time. The
+    /// frame that replaced it is returned by the next call. With the receive interrupt enabled,
+    /// lost frames are counted in [`Can::receive_counters`] instead.
+    pub fn try_receive(&mut self) -> Result<Option<Frame>, Error> {
+        let queued = interrupt::free(|| unsafe { (*ptr::addr_of_mut!(RECEIVE_QUEUE)).pop() });
+        if queued.is_some() || self.receive_slots.is_some() {
+            return Ok(queued);
+        }
+        Self::receive_from_unit(self.receivers, self.fifo)
+    }
+
+    /// Returns a frame from the FIFO if `fifo` is set, or else from the first mailbox in
+    /// `receivers` that has one.
+    fn receive_from_unit(receivers: u32, fifo: bool) -> Result<Option<Frame>, Error> {
+        if fifo {
+            if let Some(frame) = Self::try_receive_fifo()? {
+                return Ok(Some(frame));
+            }
+        }
+        for j in (0..32).filter(|j| receivers & (1 << j) != 0) {
+            let mctl = Self::mctl(j);
+            let value = unsafe { mctl.read_volatile() };
+            if value & Self::MCTL_DONE == 0 {
+                continue;
+            }
+            if value & Self::MCTL_MSGLOST != 0 {
+                unsafe {
+                    mctl.write_volatile(Self::MCTL_RECREQ | Self::MCTL_DONE);
+                }
+                log_error!("CAN: frame lost in mailbox {}", j);
+                return Err(Error::Overrun);
+            }
+            loop {
+                unsafe {
+                    mctl.write_volatile(Self::MCTL_RECREQ);
+                }
+                let frame = unsafe { Self::read_mailbox(j) };
+                // If the unit wrote the mailbox while we read it, read the new frame.
+                let value = unsafe { mctl.read_volatile() };
+                if value & (Self::MCTL_DONE | Self::MCTL_INVALDATA) == 0 {
+                    return Ok(Some(frame));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the oldest frame in the receive FIFO, if any.
+    fn try_receive_fifo() -> Result<Option<Frame>, Error> {
+        unsafe {
+            let rfcr = Self::RFCR.read_volatile();
+            if rfcr & Self::RFCR_RFMLF != 0 {
+                Self::RFCR.write_volatile(rfcr & !Self::RFCR_RFMLF);
+                return Err(Error::Overrun);
+            }
+            if rfcr & Self::RFCR_RFEST != 0 {
+                return Ok(None);
+            }
+            let frame = Self::read_mailbox(Self::FIFO_MAILBOX);
+            Self::RFPCR.write_volatile(0xff);
+            Ok(Some(frame))
+        }
+    }
+
+    /// Wait for a frame and return it.
+    pub fn receive(&mut self) -> Result<Frame, Error> {
+        loop {
+            if let Some(frame) = self.try_receive()? {
+                return Ok(frame);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    unsafe fn write_mailbox(j: usize, frame: &Frame) {
+        let mut id = match frame.id {
+            Id::Standard(id) => (id as u32) << 18,
+            Id::Extended(id) => id | Self::ID_IDE,
+        };
+        if frame.remote {
+            id |= Self::ID_RTR;
+        }
+        Self::mb_id(j).write_volatile(id);
+        Self::mb_dlc(j).write_volatile(frame.dlc as u16);
+        for (i, byte) in frame.data.iter().enumerate() {
+            Self::mb_data(j).add(i).write_volatile(*byte);
+        }
+    }
+
+    unsafe fn read_mailbox(j: usize) -> Frame {
+        let value = Self::mb_id(j).read_volatile();
+        let id = if value & Self::ID_IDE != 0 {
+            Id::Extended(value & 0x1fff_ffff)
+        } else {
+            Id::Standard(((value >> 18) & 0x7ff) as u16)
+        };
+        let mut data = [0; 8];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = Self::mb_data(j).add(i).read_volatile();
+        }
+        Frame {
+            id,
+            remote: value & Self::ID_RTR != 0,
+            dlc: (Self::mb_dlc(j).read_volatile() & 0xf).min(8) as u8,
+            data,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-can")]
+mod embedded_can_impl {
+    use super::{Can, Error, Frame, Id};
+    use embedded_can::{ErrorKind, ExtendedId, StandardId};
+
+    impl From<Id> for embedded_can::Id {
+        fn from(id: Id) -> Self {
+            // The IDs of frames are always in range.
+            match id {
+                Id::Standard(id) => StandardId::new(id).unwrap().into(),
+                Id::Extended(id) => ExtendedId::new(id).unwrap().into(),
+            }
+        }
+    }
+
+    impl From<embedded_can::Id> for Id {
+        fn from(id: embedded_can::Id) -> Self {
+            match id {
+                embedded_can::Id::Standard(id) => Id::Standard(id.as_raw()),
+                embedded_can::Id::Extended(id) => Id::Extended(id.as_raw()),
+            }
+        }
+    }
+
+    impl embedded_can::Error for Error {
+        fn kind(&self) -> ErrorKind {
+            match self {
+                Error::Overrun => ErrorKind::Overrun,
+                _ => ErrorKind::Other,
+            }
+        }
+    }
+
+    impl embedded_can::Frame for Frame {
+        fn new(id: impl Into<embedded_can::Id>, data: &[u8]) -> Option<Self> {
+            Frame::new(id.into().into(), data)
+        }
+
+        fn new_remote(id: impl Into<embedded_can::Id>, dlc: usize) -> Option<Self> {
+            Frame::new_remote(id.into().into(), u8::try_from(dlc).ok()?)
+        }
+
+        fn is_extended(&self) -> bool {
+            matches!(self.id, Id::Extended(_))
+        }
+
+        fn is_remote_frame(&self) -> bool {
+            self.remote
+        }
+
+        fn id(&self) -> embedded_can::Id {
+            self.id.into()
+        }
+
+        fn dlc(&self) -> usize {
+            self.dlc as usize
+        }
+
+        fn data(&self) -> &[u8] {
+            Frame::data(self)
+        }
+    }
+
+    impl embedded_can::nb::Can for Can {
+        type Frame = Frame;
+        type Error = Error;
+
+        fn transmit(&mut self, frame: &Frame) -> nb::Result<Option<Frame>, Error> {
+            match self.try_transmit(frame)? {
+                true => Ok(None),
+                false => Err(nb::Error::WouldBlock),
+            }
+        }
+
+        fn receive(&mut self) -> nb::Result<Frame, Error> {
+            self.try_receive()?.ok_or(nb::Error::WouldBlock)
+        }
+    }
+
+    impl embedded_can::blocking::Can for Can {
+        type Frame = Frame;
+        type Error = Error;
+
+        fn transmit(&mut self, frame: &Frame) -> Result<(), Error> {
+            Can::transmit(self, frame)
+        }
+
+        fn receive(&mut self) -> Result<Frame, Error> {
+            Can::receive(self)
+        }
+    }
+}
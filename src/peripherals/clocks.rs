@@ -0,0 +1,899 @@
+//! Clock generation circuit (CGC): The oscillators, the system clock and its dividers.
+//!
+//! The system clock is taken from one of six sources, see [`Source`], and divided down for the
+//! internal clocks:
+//! * ICLK drives the CPU, the SRAM and the DMA units, up to 48 MHz.
+//! * PCLKA drives the fast peripherals (SPI, the DMA and the USB unit), up to 48 MHz.
+//! * PCLKB drives the other peripherals (SCI, IIC, CAN, WDT, timers), up to 32 MHz.
+//! * PCLKC drives the A/D converter, up to 64 MHz.
+//! * PCLKD drives the GPT timers, up to 64 MHz.
+//! * FCLK drives the flash interface, up to 32 MHz.
+//!
+//! The Arduino bootloader starts the firmware with the 48 MHz HOCO as system clock, ICLK, PCLKA,
+//! PCLKC and PCLKD at 48 MHz and PCLKB and FCLK at 24 MHz. [`Config::default`] is this setup.
+//!
+//! Usually, the clocks are configured once: [`Cgc::freeze`] applies a [`Config`] and returns the
+//! resulting [`Clocks`], while [`Cgc::into_clocks`] keeps the clocks as they are. The drivers
+//! whose timing depends on a clock take a `&Clocks` in their constructor, e.g. the SPI, I2C and
+//! CAN drivers and the SysTick delay, so their bit rates and delays follow the configuration.
+//!
+//! To change the clocks at run time, e.g. to drop to the 8 MHz MOCO while there is little to do,
+//! keep the [`Cgc`] and use [`Cgc::rescale`]. It passes the new [`Clocks`] to the drivers that
+//! implement [`ClockDependent`], which recompute their dividers, so their bit rates and delays
+//! stay the same. This includes the SPI and I2C drivers and the SysTick delay. Other drivers have
+//! to be dropped before and created again after rescaling, like the CAN driver, whose bit rate
+//! may not be possible with every clock.
+//!
+//! [`ClockOut`] puts one of the oscillators, divided by up to 128, on the CLKOUT pin, e.g. to
+//! measure its actual frequency with a scope or to clock an external chip. On the UNO R4 WiFi,
+//! this is P205, which isn't on the headers but drives a line of the LED matrix, so the matrix
+//! can't be used at the same time.
+//!
+//! The main oscillator and the PLL, which only runs from it, need a crystal or a clock signal on
+//! P212 and P213 (EXTAL and XTAL), which the UNO R4 WiFi doesn't have.
+//!
+//! The frequency of the HOCO is chosen by the option bytes in flash (OFS1), and can't be changed
+//! at run time. Oscillators are started as needed, but not stopped, since peripherals like the
+//! RTC and the IWDT run from them independently of the system clock.
+//!
+//! The sub-clock oscillator and the LOCO, which drive the RTC and the low-power timers, can also
+//! be started and stopped on their own, see [`start_sub_oscillator`] and [`start_loco`]. The
+//! crystal of the sub-clock oscillator can be driven with less current, and the on-chip
+//! oscillators can be trimmed with [`set_trim`], e.g. after measuring them on the CLKOUT pin. The
+//! sub-clock oscillator takes a second to become stable, so [`Cgc::freeze`] blocks that long if it
+//! has to start it.
+//!
+//! If the main oscillator stops, e.g. because the crystal failed, the CPU would hang. With
+//! [`detect_oscillation_stop`], the hardware switches the system clock to the MOCO instead and
+//! raises an NMI, whose handler can log the event. The clocks then run at 8 MHz with the same
+//! dividers, so the drivers configured for the main oscillator are off, and the handler may want
+//! to reset the MCU.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Clock
+//! Generation Circuit".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::{Cgc, Config, Divider, Source};
+//!
+//! // Run everything from the 8 MHz MOCO to save power.
+//! let config = Config::default()
+//!     .with_source(Source::Moco)
+//!     .with_dividers(Divider::Div1, Divider::Div1, Divider::Div1, Divider::Div1, Divider::Div1)
+//!     .with_fclk(Divider::Div1);
+//! let clocks = Cgc::take().unwrap().freeze(&config).unwrap();
+//! assert_eq!(clocks.iclk(), 8_000_000);
+//! ```
+//!
+//! Example, slowing down while idle:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::{Cgc, Config, Source};
+//! use arduino_uno_r4_wifi_rt::peripherals::iic::{Iic, Speed};
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//! use arduino_uno_r4_wifi_rt::peripherals::systick::{Delay, SysTick};
+//!
+//! let mut cgc = Cgc::take().unwrap();
+//! let clocks = cgc.rescale(&Config::default(), &mut []).unwrap();
+//! let pins = get_pins().unwrap();
+//! let mut iic = Iic::new(pins.a5, pins.a4, mstp::token(), &clocks, Speed::Standard);
+//! let mut delay = Delay::new(SysTick::instance().unwrap(), &clocks);
+//! let idle = Config::default().with_source(Source::Moco);
+//! cgc.rescale(&idle, &mut [&mut iic, &mut delay]).unwrap();
+//! // The I2C bus still runs at 100 kHz, and the delays still take a second.
+//! delay.delay_ms(1000);
+//! ```
+
+use super::nmi;
+use super::pins::{Pin, PinMode, PinModePeripheral, PinModeUnknown, P205};
+use super::registers::{self, VolatileBoolOps, PRCR_PRC0};
+use crate::interrupt;
+
+/// Frequency of the MOCO.
+const MOCO_HZ: u32 = 8_000_000;
+
+/// Frequency of the LOCO, and of the usual 32.768 kHz crystal on the sub-clock oscillator.
+const LOCO_HZ: u32 = 32_768;
+const SOSC_HZ: u32 = 32_768;
+
+/// Maximum frequencies of the internal clocks in high-speed mode.
+const ICLK_MAX_HZ: u32 = 48_000_000;
+const PCLKA_MAX_HZ: u32 = 48_000_000;
+const PCLKB_MAX_HZ: u32 = 32_000_000;
+const PCLKC_MAX_HZ: u32 = 64_000_000;
+const PCLKD_MAX_HZ: u32 = 64_000_000;
+const FCLK_MAX_HZ: u32 = 32_000_000;
+
+/// Time for the MOCO, the LOCO and the sub-clock oscillator to become stable after they are
+/// started, in microseconds. They have no flag that tells when they are.
+const MOCO_WAIT_US: u32 = 15;
+const LOCO_WAIT_US: u32 = 60;
+const SOSC_WAIT_US: u32 = 1_000_000;
+
+/// Frequency of ICLK above which the flash needs a wait cycle.
+const MEMWAIT_THRESHOLD_HZ: u32 = 32_000_000;
+
+/// Ranges of the main oscillator, the PLL input and the PLL output.
+const MAIN_OSC_MIN_HZ: u32 = 1_000_000;
+const MAIN_OSC_MAX_HZ: u32 = 20_000_000;
+const PLL_IN_MIN_HZ: u32 = 4_000_000;
+const PLL_IN_MAX_HZ: u32 = 12_500_000;
+const PLL_OUT_MIN_HZ: u32 = 24_000_000;
+const PLL_OUT_MAX_HZ: u32 = 64_000_000;
+
+/// Errors of the clock configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The main oscillator or the PLL is used, but no main oscillator is configured.
+    NoMainOscillator,
+    /// The frequency of the main oscillator is outside of 1-20 MHz.
+    InvalidMainOscillator,
+    /// The PLL input isn't within 4-12.5 MHz, its output not within 24-64 MHz, or the multiplier
+    /// not within 8-31.
+    InvalidPll,
+    /// An internal clock is faster than its maximum.
+    TooFast,
+    /// The dividers don't keep ICLK >= PCLKA >= PCLKB and ICLK >= FCLK.
+    InvalidDividers,
+    /// The oscillator drives the system clock, or runs with other settings.
+    OscillatorInUse,
+}
+
+/// Source of the system clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Source {
+    /// High-speed on-chip oscillator, 24, 32, 48 or 64 MHz as set by the option bytes.
+    Hoco = 0,
+    /// Middle-speed on-chip oscillator, 8 MHz.
+    Moco = 1,
+    /// Low-speed on-chip oscillator, 32.768 kHz.
+    Loco = 2,
+    /// Main oscillator, see [`MainOscillator`].
+    MainOsc = 3,
+    /// Sub-clock oscillator, with a 32.768 kHz crystal.
+    SubOsc = 4,
+    /// PLL, see [`Pll`].
+    Pll = 5,
+}
+
+/// Division of the system clock for an internal clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Divider {
+    Div1 = 0,
+    Div2 = 1,
+    Div4 = 2,
+    Div8 = 3,
+    Div16 = 4,
+    Div32 = 5,
+    Div64 = 6,
+}
+
+impl Divider {
+    fn divide(self, hz: u32) -> u32 {
+        hz >> self as u32
+    }
+}
+
+/// The main oscillator, with its frequency in Hz.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MainOscillator {
+    /// A crystal or ceramic resonator between EXTAL and XTAL.
+    Resonator(u32),
+    /// A clock signal on EXTAL.
+    ExternalClock(u32),
+}
+
+impl MainOscillator {
+    fn hz(self) -> u32 {
+        match self {
+            MainOscillator::Resonator(hz) | MainOscillator::ExternalClock(hz) => hz,
+        }
+    }
+}
+
+/// Division of the PLL output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PllDivider {
+    Div1 = 0,
+    Div2 = 1,
+    Div4 = 2,
+}
+
+/// Settings of the PLL, which multiplies the main oscillator by `multiplier` and divides the
+/// result by `divider`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pll {
+    /// 8-31.
+    pub multiplier: u8,
+    pub divider: PllDivider,
+}
+
+/// Settings of the clocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub source: Source,
+    pub main_oscillator: Option<MainOscillator>,
+    pub pll: Option<Pll>,
+    /// Start the HOCO and MOCO even if they aren't the source, e.g. for peripherals that run from
+    /// them.
+    pub hoco: bool,
+    pub moco: bool,
+    pub iclk: Divider,
+    pub pclka: Divider,
+    pub pclkb: Divider,
+    pub pclkc: Divider,
+    pub pclkd: Divider,
+    pub fclk: Divider,
+}
+
+impl Default for Config {
+    /// The clocks as configured by the Arduino bootloader.
+    fn default() -> Self {
+        Self {
+            source: Source::Hoco,
+            main_oscillator: None,
+            pll: None,
+            hoco: false,
+            moco: false,
+            iclk: Divider::Div1,
+            pclka: Divider::Div1,
+            pclkb: Divider::Div2,
+            pclkc: Divider::Div1,
+            pclkd: Divider::Div1,
+            fclk: Divider::Div2,
+        }
+    }
+}
+
+impl Config {
+    /// Use `source` as system clock.
+    pub fn with_source(self, source: Source) -> Self {
+        Self { source, ..self }
+    }
+
+    /// Use the main oscillator, for the system clock or the PLL.
+    pub fn with_main_oscillator(self, main_oscillator: MainOscillator) -> Self {
+        Self {
+            main_oscillator: Some(main_oscillator),
+            ..self
+        }
+    }
+
+    /// Run the PLL from the main oscillator, and use it as system clock.
+    pub fn with_pll(self, multiplier: u8, divider: PllDivider) -> Self {
+        Self {
+            source: Source::Pll,
+            pll: Some(Pll {
+                multiplier,
+                divider,
+            }),
+            ..self
+        }
+    }
+
+    /// Keep the HOCO and MOCO running, even if they aren't the source.
+    pub fn with_oscillators(self, hoco: bool, moco: bool) -> Self {
+        Self { hoco, moco, ..self }
+    }
+
+    /// Divide the system clock by `iclk`, `pclka`, `pclkb`, `pclkc` and `pclkd` for the internal
+    /// clocks.
+    pub fn with_dividers(
+        self,
+        iclk: Divider,
+        pclka: Divider,
+        pclkb: Divider,
+        pclkc: Divider,
+        pclkd: Divider,
+    ) -> Self {
+        Self {
+            iclk,
+            pclka,
+            pclkb,
+            pclkc,
+            pclkd,
+            ..self
+        }
+    }
+
+    /// Divide the system clock by `fclk` for the flash interface.
+    pub fn with_fclk(self, fclk: Divider) -> Self {
+        Self { fclk, ..self }
+    }
+
+    /// Returns the frequencies that the configuration results in, or the reason it is invalid.
+    pub fn clocks(&self) -> Result<Clocks, Error> {
+        let main_oscillator = || {
+            let hz = self.main_oscillator.ok_or(Error::NoMainOscillator)?.hz();
+            if (MAIN_OSC_MIN_HZ..=MAIN_OSC_MAX_HZ).contains(&hz) {
+                Ok(hz)
+            } else {
+                Err(Error::InvalidMainOscillator)
+            }
+        };
+        let system = match self.source {
+            Source::Hoco => hoco_hz(),
+            Source::Moco => MOCO_HZ,
+            Source::Loco => LOCO_HZ,
+            Source::MainOsc => main_oscillator()?,
+            Source::SubOsc => SOSC_HZ,
+            Source::Pll => {
+                let input = main_oscillator()?;
+                let pll = self.pll.ok_or(Error::InvalidPll)?;
+                let output = (input * pll.multiplier as u32) >> pll.divider as u32;
+                if !(PLL_IN_MIN_HZ..=PLL_IN_MAX_HZ).contains(&input)
+                    || !(8..=31).contains(&pll.multiplier)
+                    || !(PLL_OUT_MIN_HZ..=PLL_OUT_MAX_HZ).contains(&(input * pll.multiplier as u32))
+                {
+                    return Err(Error::InvalidPll);
+                }
+                output
+            }
+        };
+        let (iclk, pclka, pclkb, fclk) = (
+            self.iclk as u8,
+            self.pclka as u8,
+            self.pclkb as u8,
+            self.fclk as u8,
+        );
+        if iclk > pclka || pclka > pclkb || iclk > fclk {
+            return Err(Error::InvalidDividers);
+        }
+        let clocks = Clocks {
+            system,
+            iclk: self.iclk.divide(system),
+            pclka: self.pclka.divide(system),
+            pclkb: self.pclkb.divide(system),
+            pclkc: self.pclkc.divide(system),
+            pclkd: self.pclkd.divide(system),
+            fclk: self.fclk.divide(system),
+        };
+        if clocks.iclk > ICLK_MAX_HZ
+            || clocks.pclka > PCLKA_MAX_HZ
+            || clocks.pclkb > PCLKB_MAX_HZ
+            || clocks.pclkc > PCLKC_MAX_HZ
+            || clocks.pclkd > PCLKD_MAX_HZ
+            || clocks.fclk > FCLK_MAX_HZ
+        {
+            return Err(Error::TooFast);
+        }
+        Ok(clocks)
+    }
+}
+
+/// The frequencies of the clocks in Hz, after they have been configured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Clocks {
+    system: u32,
+    iclk: u32,
+    pclka: u32,
+    pclkb: u32,
+    pclkc: u32,
+    pclkd: u32,
+    fclk: u32,
+}
+
+impl Clocks {
+    /// Returns the frequency of the system clock, before the dividers.
+    pub fn system(&self) -> u32 {
+        self.system
+    }
+
+    /// Returns the frequency of the CPU clock.
+    pub fn iclk(&self) -> u32 {
+        self.iclk
+    }
+
+    pub fn pclka(&self) -> u32 {
+        self.pclka
+    }
+
+    pub fn pclkb(&self) -> u32 {
+        self.pclkb
+    }
+
+    pub fn pclkc(&self) -> u32 {
+        self.pclkc
+    }
+
+    pub fn pclkd(&self) -> u32 {
+        self.pclkd
+    }
+
+    /// Returns the frequency of the flash interface clock.
+    pub fn fclk(&self) -> u32 {
+        self.fclk
+    }
+}
+
+/// Returns the frequency of the HOCO, as set by the option bytes.
+fn hoco_hz() -> u32 {
+    match (unsafe { Cgc::OFS1.read_volatile() } >> 12) & 0b111 {
+        0b000 => 24_000_000,
+        0b010 => 32_000_000,
+        0b101 => 64_000_000,
+        _ => 48_000_000,
+    }
+}
+
+/// Detect if the main oscillator stops, and call `handler` from the NMI when it does.
+///
+/// If the system clock runs from the main oscillator or the PLL, it is switched to the MOCO by the
+/// hardware, so the firmware keeps running, only slower. The detection can't be turned off again
+/// other than by a reset, and software standby can't be entered while it is enabled.
+pub fn detect_oscillation_stop(handler: fn()) {
+    nmi::set_handler(nmi::Source::OscillationStop, handler);
+    interrupt::free(|| unsafe {
+        registers::unprotected(PRCR_PRC0, || {
+            Cgc::OSTDCR.write_volatile(Cgc::OSTDCR_OSTDE | Cgc::OSTDCR_OSTDIE);
+        });
+    });
+}
+
+/// Returns true if the main oscillator stopped while [`detect_oscillation_stop`] was enabled.
+pub fn oscillation_stopped() -> bool {
+    unsafe { Cgc::OSTDSR.read_volatile() & 1 != 0 }
+}
+
+/// Busy-wait for at least `us` microseconds while the CPU runs at `iclk` Hz.
+pub(crate) fn wait_us(iclk: u32, us: u32) {
+    // Each iteration takes at least one cycle.
+    for _ in 0..(iclk / 1_000_000 + 1) * us {
+        core::hint::spin_loop();
+    }
+}
+
+/// A driver whose timing depends on the clocks, which can follow a change with [`Cgc::rescale`].
+pub trait ClockDependent {
+    /// Recompute the dividers of the driver for `clocks`, keeping its bit rate or timing.
+    fn set_clocks(&mut self, clocks: &Clocks);
+}
+
+/// The clock generation circuit.
+pub struct Cgc {
+    _private: (),
+}
+
+impl Cgc {
+    /// System Clock Division Control Register.
+    /// * b0-b2: PCKD, divider of PCLKD, see [`Divider`].
+    /// * b4-b6: PCKC, divider of PCLKC.
+    /// * b8-b10: PCKB, divider of PCLKB.
+    /// * b12-b14: PCKA, divider of PCLKA.
+    /// * b24-b26: ICK, divider of ICLK.
+    /// * b28-b30: FCK, divider of FCLK.
+    const SCKDIVCR: *mut u32 = 0x4001e020 as *mut u32;
+
+    /// System Clock Source Control Register. b0-b2: CKSEL, the source, see [`Source`].
+    const SCKSCR: *mut u8 = 0x4001e026 as *mut u8;
+
+    /// PLL Control Register. b0: PLLSTP, stop the PLL.
+    const PLLCR: *mut u8 = 0x4001e02a as *mut u8;
+
+    /// PLL Clock Control Register 2.
+    /// * b0-b4: PLLMUL, the multiplier minus 1.
+    /// * b6-b7: PLODIV, the output divider, see [`PllDivider`].
+    const PLLCCR2: *mut u8 = 0x4001e02b as *mut u8;
+
+    /// Memory Wait Cycle Control Register. b0: MEMWAIT, one wait cycle for the code flash, which
+    /// is needed if ICLK is faster than 32 MHz.
+    const MEMWAIT: *mut u8 = 0x4001e031 as *mut u8;
+
+    /// Main Clock Oscillator Control Register. b0: MOSTP, stop the main oscillator.
+    const MOSCCR: *mut u8 = 0x4001e032 as *mut u8;
+
+    /// High-Speed On-Chip Oscillator Control Register. b0: HCSTP, stop the HOCO.
+    const HOCOCR: *mut u8 = 0x4001e036 as *mut u8;
+
+    /// Middle-Speed On-Chip Oscillator Control Register. b0: MCSTP, stop the MOCO.
+    const MOCOCR: *mut u8 = 0x4001e038 as *mut u8;
+
+    /// Oscillation Stabilization Flag Register.
+    /// * b0: HOCOSF, the HOCO is stable.
+    /// * b3: MOSCSF, the main oscillator is stable.
+    /// * b5: PLLSF, the PLL is stable.
+    const OSCSF: *const u8 = 0x4001e03c as *const u8;
+
+    /// Main Clock Oscillator Mode Oscillation Control Register. Can only be written while the main
+    /// oscillator is stopped.
+    /// * b3: MODRV1, drive for a 1-10 MHz resonator instead of 10-20 MHz.
+    /// * b6: MOSEL, external clock input instead of a resonator.
+    const MOMCR: *mut u8 = 0x4001e413 as *mut u8;
+
+    /// Sub-Clock Oscillator Control Register. b0: SOSTP, stop the sub-clock oscillator.
+    const SOSCCR: *mut u8 = 0x4001e480 as *mut u8;
+
+    /// Sub-Clock Oscillator Mode Control Register. b0-b1: SODRV, the drive capability, see
+    /// [`SubOscillatorDrive`]. Can only be written while the sub-clock oscillator is stopped.
+    const SOMCR: *mut u8 = 0x4001e481 as *mut u8;
+
+    /// User trimming registers of the MOCO, the HOCO and the LOCO. A signed value added to the
+    /// factory trimming.
+    const MOCOUTCR: *mut i8 = 0x4001e061 as *mut i8;
+    const HOCOUTCR: *mut i8 = 0x4001e062 as *mut i8;
+    const LOCOUTCR: *mut i8 = 0x4001e492 as *mut i8;
+
+    /// Low-Speed On-Chip Oscillator Control Register. b0: LCSTP, stop the LOCO.
+    const LOCOCR: *mut u8 = 0x4001e490 as *mut u8;
+
+    /// Oscillation Stop Detection Control Register.
+    /// * b0: OSTDIE, raise the NMI on oscillation stop.
+    /// * b7: OSTDE, enable oscillation stop detection.
+    const OSTDCR: *mut u8 = 0x4001e040 as *mut u8;
+
+    /// Oscillation Stop Detection Status Register. b0: OSTDF, the main oscillator stopped.
+    const OSTDSR: *const u8 = 0x4001e041 as *const u8;
+
+    /// Option Function Select Register 1 in the option-setting memory of the flash. b12-b14:
+    /// HOCOFRQ1, the frequency of the HOCO.
+    const OFS1: *const u32 = 0x00000404 as *const u32;
+
+    const OSCSF_HOCOSF: u8 = 1 << 0;
+    const OSCSF_MOSCSF: u8 = 1 << 3;
+    const OSCSF_PLLSF: u8 = 1 << 5;
+    const MOMCR_MODRV1: u8 = 1 << 3;
+    const MOMCR_MOSEL: u8 = 1 << 6;
+    const OSTDCR_OSTDIE: u8 = 1 << 0;
+    const OSTDCR_OSTDE: u8 = 1 << 7;
+
+    /// Returns the clock generation circuit, unless it was taken already.
+    pub fn take() -> Option<Self> {
+        static mut TAKEN: bool = false;
+        interrupt::free(|| unsafe {
+            if TAKEN {
+                None
+            } else {
+                TAKEN = true;
+                Some(Self { _private: () })
+            }
+        })
+    }
+
+    /// Keep the clocks as they are, and return their frequencies.
+    pub fn into_clocks(self) -> Clocks {
+        Self::current()
+    }
+
+    /// Returns the frequencies of the clocks as they are set now.
+    pub(crate) fn current() -> Clocks {
+        let sckdivcr = unsafe { Self::SCKDIVCR.read_volatile() };
+        let divider = |shift: u32| match (sckdivcr >> shift) & 0b111 {
+            0 => Divider::Div1,
+            1 => Divider::Div2,
+            2 => Divider::Div4,
+            3 => Divider::Div8,
+            4 => Divider::Div16,
+            5 => Divider::Div32,
+            _ => Divider::Div64,
+        };
+        let system = match unsafe { Self::SCKSCR.read_volatile() } & 0b111 {
+            0 => hoco_hz(),
+            // After an oscillation stop, the MOCO replaces the main oscillator.
+            3 | 5 if oscillation_stopped() => MOCO_HZ,
+            1 => MOCO_HZ,
+            2 => LOCO_HZ,
+            4 => SOSC_HZ,
+            // The main oscillator and the PLL depend on the crystal, which isn't known. The
+            // bootloader doesn't use them.
+            _ => hoco_hz(),
+        };
+        Clocks {
+            system,
+            iclk: divider(24).divide(system),
+            pclka: divider(12).divide(system),
+            pclkb: divider(8).divide(system),
+            pclkc: divider(4).divide(system),
+            pclkd: divider(0).divide(system),
+            fclk: divider(28).divide(system),
+        }
+    }
+
+    /// Start the oscillators needed by `config`, switch the system clock to its source and set the
+    /// dividers. Returns the frequencies of the clocks, or the reason `config` is invalid, in
+    /// which case the clocks are left unchanged.
+    pub fn freeze(mut self, config: &Config) -> Result<Clocks, Error> {
+        self.rescale(config, &mut [])
+    }
+
+    /// Like [`Cgc::freeze`], but keep the clock generation circuit to change the clocks again
+    /// later, and pass the new clocks to `drivers`.
+    ///
+    /// The drivers are updated after the clocks have changed, and must not be in the middle of a
+    /// transfer. Drivers that depend on the clocks but aren't in `drivers` keep the old dividers,
+    /// so their timing is off afterwards.
+    pub fn rescale(
+        &mut self,
+        config: &Config,
+        drivers: &mut [&mut dyn ClockDependent],
+    ) -> Result<Clocks, Error> {
+        let clocks = config.clocks().inspect_err(|error| {
+            log_error!("clocks: invalid config: {}", error);
+        })?;
+        let mut current = Self::current();
+        interrupt::free(|| unsafe {
+            registers::unprotected(PRCR_PRC0, || {
+                if config.hoco || config.source == Source::Hoco {
+                    Self::HOCOCR.write_volatile(0);
+                    while Self::OSCSF.read_volatile() & Self::OSCSF_HOCOSF == 0 {}
+                }
+                let start = |register: *mut u8, us: u32| {
+                    if register.read_volatile() & 1 != 0 {
+                        register.write_volatile(0);
+                        wait_us(current.iclk, us);
+                    }
+                };
+                if config.moco || config.source == Source::Moco {
+                    start(Self::MOCOCR, MOCO_WAIT_US);
+                }
+                if config.source == Source::Loco {
+                    start(Self::LOCOCR, LOCO_WAIT_US);
+                }
+                if config.source == Source::SubOsc {
+                    start(Self::SOSCCR, SOSC_WAIT_US);
+                }
+                if let Some(main_oscillator) = config.main_oscillator {
+                    if Self::MOSCCR.read_volatile() & 1 != 0 {
+                        let mut momcr = 0;
+                        if main_oscillator.hz() <= 10_000_000 {
+                            momcr |= Self::MOMCR_MODRV1;
+                        }
+                        if let MainOscillator::ExternalClock(_) = main_oscillator {
+                            momcr |= Self::MOMCR_MOSEL;
+                        }
+                        Self::MOMCR.write_volatile(momcr);
+                        Self::MOSCCR.write_volatile(0);
+                    }
+                    while Self::OSCSF.read_volatile() & Self::OSCSF_MOSCSF == 0 {}
+                }
+                if let (Source::Pll, Some(pll)) = (config.source, config.pll) {
+                    // The PLL can only be changed while it is stopped, i.e. not the system clock. The
+                    // MOCO is slow enough for any dividers in the meantime.
+                    if Self::SCKSCR.read_volatile() & 0b111 == Source::Pll as u8 {
+                        start(Self::MOCOCR, MOCO_WAIT_US);
+                        Self::SCKSCR.write_volatile(Source::Moco as u8);
+                        current = Self::current();
+                    }
+                    Self::PLLCR.volatile_or(1);
+                    while Self::OSCSF.read_volatile() & Self::OSCSF_PLLSF != 0 {}
+                    Self::PLLCCR2
+                        .write_volatile(((pll.divider as u8) << 6) | ((pll.multiplier - 1) & 0x1f));
+                    Self::PLLCR.write_volatile(0);
+                    while Self::OSCSF.read_volatile() & Self::OSCSF_PLLSF == 0 {}
+                }
+
+                // The flash needs its wait cycle before ICLK gets faster than 32 MHz, and may only
+                // drop it after ICLK is slow again.
+                if clocks.iclk > MEMWAIT_THRESHOLD_HZ {
+                    Self::MEMWAIT.write_volatile(1);
+                }
+                // Switch in the order that keeps the clocks below both the old and the new
+                // frequencies: The new dividers first if the new source is faster, otherwise last.
+                let sckdivcr = ((config.fclk as u32) << 28)
+                    | ((config.iclk as u32) << 24)
+                    | ((config.pclka as u32) << 12)
+                    | ((config.pclkb as u32) << 8)
+                    | ((config.pclkc as u32) << 4)
+                    | config.pclkd as u32;
+                if clocks.system > current.system {
+                    Self::SCKDIVCR.write_volatile(sckdivcr);
+                    Self::SCKSCR.write_volatile(config.source as u8);
+                } else {
+                    Self::SCKSCR.write_volatile(config.source as u8);
+                    Self::SCKDIVCR.write_volatile(sckdivcr);
+                }
+                if clocks.iclk <= MEMWAIT_THRESHOLD_HZ {
+                    Self::MEMWAIT.write_volatile(0);
+                }
+            });
+        });
+        for driver in drivers {
+            driver.set_clocks(&clocks);
+        }
+        Ok(clocks)
+    }
+}
+
+/// Drive capability of the sub-clock oscillator. The lower the drive, the less current it draws,
+/// but the crystal must be specified for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SubOscillatorDrive {
+    Normal = 0,
+    LowPower1 = 1,
+    LowPower2 = 2,
+    LowPower3 = 3,
+}
+
+/// An on-chip oscillator that can be trimmed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Trimmable {
+    Hoco,
+    Moco,
+    Loco,
+}
+
+impl Trimmable {
+    /// Returns the user trimming register.
+    fn register(self) -> *mut i8 {
+        match self {
+            Trimmable::Hoco => Cgc::HOCOUTCR,
+            Trimmable::Moco => Cgc::MOCOUTCR,
+            Trimmable::Loco => Cgc::LOCOUTCR,
+        }
+    }
+}
+
+/// Returns true if the system clock runs from `source`.
+fn is_system_clock(source: Source) -> bool {
+    unsafe { Cgc::SCKSCR.read_volatile() & 0b111 == source as u8 }
+}
+
+/// Write `value` to a clock register, which is protected by PRC0.
+pub(crate) unsafe fn write_protected<T>(register: *mut T, value: T) {
+    interrupt::free(|| registers::unprotected(PRCR_PRC0, || register.write_volatile(value)));
+}
+
+/// Start the sub-clock oscillator with `drive`. It takes about a second until it is stable, which
+/// this doesn't wait for.
+///
+/// Returns [`Error::OscillatorInUse`] if it is running already with another drive capability.
+pub fn start_sub_oscillator(drive: SubOscillatorDrive) -> Result<(), Error> {
+    unsafe {
+        if Cgc::SOSCCR.read_volatile() & 1 == 0 {
+            if Cgc::SOMCR.read_volatile() & 0b11 != drive as u8 {
+                return Err(Error::OscillatorInUse);
+            }
+            return Ok(());
+        }
+        write_protected(Cgc::SOMCR, drive as u8);
+        write_protected(Cgc::SOSCCR, 0);
+    }
+    Ok(())
+}
+
+/// Stop the sub-clock oscillator, e.g. to change its drive capability. An RTC that counts it
+/// stops as well.
+///
+/// Returns [`Error::OscillatorInUse`] if it drives the system clock.
+pub fn stop_sub_oscillator() -> Result<(), Error> {
+    if is_system_clock(Source::SubOsc) {
+        return Err(Error::OscillatorInUse);
+    }
+    unsafe { write_protected(Cgc::SOSCCR, 1) };
+    Ok(())
+}
+
+/// Start the LOCO, and wait until it is stable.
+pub fn start_loco() {
+    unsafe {
+        if Cgc::LOCOCR.read_volatile() & 1 != 0 {
+            write_protected(Cgc::LOCOCR, 0);
+            wait_us(Cgc::current().iclk, LOCO_WAIT_US);
+        }
+    }
+}
+
+/// Stop the LOCO. An RTC that counts it stops as well.
+///
+/// Returns [`Error::OscillatorInUse`] if it drives the system clock.
+pub fn stop_loco() -> Result<(), Error> {
+    if is_system_clock(Source::Loco) {
+        return Err(Error::OscillatorInUse);
+    }
+    unsafe { write_protected(Cgc::LOCOCR, 1) };
+    Ok(())
+}
+
+/// Stop the oscillators for which `keep` returns false, except the source of the system clock and
+/// the main oscillator if that is the PLL.
+pub(crate) fn stop_unused(keep: impl Fn(Source) -> bool) {
+    let system = unsafe { Cgc::SCKSCR.read_volatile() } & 0b111;
+    let sources = [
+        (Source::Hoco, Cgc::HOCOCR),
+        (Source::Moco, Cgc::MOCOCR),
+        (Source::Loco, Cgc::LOCOCR),
+        (Source::Pll, Cgc::PLLCR),
+        (Source::MainOsc, Cgc::MOSCCR),
+        (Source::SubOsc, Cgc::SOSCCR),
+    ];
+    for (source, register) in sources {
+        let used =
+            system == source as u8 || (source == Source::MainOsc && system == Source::Pll as u8);
+        if !used && !keep(source) {
+            unsafe { write_protected(register, 1) };
+        }
+    }
+}
+
+/// Adjust the frequency of `oscillator` by `trim` steps from its factory trimming. Positive values
+/// make it faster. A step is a fraction of a percent, so measure the result, e.g. with
+/// [`ClockOut`] or against the sub-clock oscillator.
+///
+/// The [`Clocks`] returned before don't include the trimming.
+pub fn set_trim(oscillator: Trimmable, trim: i8) {
+    unsafe { write_protected(oscillator.register(), trim) };
+}
+
+/// Returns the user trimming of `oscillator`, see [`set_trim`].
+pub fn trim(oscillator: Trimmable) -> i8 {
+    unsafe { oscillator.register().read_volatile() }
+}
+
+/// Source of the clock output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockOutSource {
+    Hoco = 0,
+    Moco = 1,
+    Loco = 2,
+    MainOsc = 3,
+    SubOsc = 4,
+}
+
+/// Division of the clock output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockOutDivider {
+    Div1 = 0,
+    Div2 = 1,
+    Div4 = 2,
+    Div8 = 3,
+    Div16 = 4,
+    Div32 = 5,
+    Div64 = 6,
+    Div128 = 7,
+}
+
+/// An oscillator on the CLKOUT pin P205.
+pub struct ClockOut {
+    pin: P205<PinModePeripheral>,
+}
+
+impl ClockOut {
+    /// Clock Out Control Register.
+    /// * b0-b2: CKOSEL, the source, see [`ClockOutSource`].
+    /// * b4-b6: CKODIV, the divider, see [`ClockOutDivider`].
+    /// * b7: CKOEN, enable the output. The source and divider may only be changed while it is 0.
+    const CKOCR: *mut u8 = 0x4001e03e as *mut u8;
+
+    const CKOCR_CKOEN: u8 = 1 << 7;
+
+    /// Output `source` divided by `divider` on P205. The source must be running, see
+    /// [`Config::with_oscillators`].
+    pub fn new<M: PinMode>(pin: P205<M>, source: ClockOutSource, divider: ClockOutDivider) -> Self {
+        // Peripheral function 9 is CLKOUT.
+        let pin = pin.into_peripheral(0b01001, 0);
+        let mut clock_out = Self { pin };
+        clock_out.set(source, divider);
+        clock_out
+    }
+
+    /// Change the source and divider of the output.
+    pub fn set(&mut self, source: ClockOutSource, divider: ClockOutDivider) {
+        interrupt::free(|| unsafe {
+            registers::unprotected(PRCR_PRC0, || {
+                Self::CKOCR.volatile_and(!Self::CKOCR_CKOEN);
+                Self::CKOCR.write_volatile(((divider as u8) << 4) | source as u8);
+                Self::CKOCR.volatile_or(Self::CKOCR_CKOEN);
+            });
+        });
+    }
+
+    /// Stop the output and return the pin.
+    pub fn release(self) -> P205<PinModeUnknown> {
+        interrupt::free(|| unsafe {
+            registers::unprotected(PRCR_PRC0, || Self::CKOCR.volatile_and(!Self::CKOCR_CKOEN));
+        });
+        self.pin.into_unknown()
+    }
+}
@@ -0,0 +1,294 @@
+//! CRC calculator: Compute CRC-8, CRC-16, CRC-CCITT, CRC-32 and CRC-32C checksums in hardware.
+//!
+//! The unit takes a byte (or, for the 32-bit polynomials, a word) per write and updates the CRC in
+//! one cycle, so checksums of protocol frames or of a firmware image cost little more than reading
+//! the data. [`Algorithm`] selects the polynomial, the bit order, the initial value and the final
+//! XOR; the constants like [`Algorithm::CRC_32`] are the common parameter sets.
+//!
+//! [`Crc::checksum`] computes the CRC of a slice. For data that arrives in parts, start a
+//! [`Digest`] with [`Crc::digest`], feed it with [`Digest::update`] and get the CRC with
+//! [`Digest::finalize`].
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Cyclic
+//! Redundancy Check (CRC) Calculator".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::crc::{Algorithm, Crc};
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//!
+//! let mut crc = Crc::take(mstp::token()).unwrap();
+//! assert_eq!(crc.checksum(Algorithm::CRC_32, b"123456789"), 0xcbf43926);
+//!
+//! let mut digest = crc.digest(Algorithm::CRC_16_CCITT_FALSE);
+//! digest.update(b"1234");
+//! digest.update(b"56789");
+//! assert_eq!(digest.finalize(), 0x29b1);
+//! ```
+
+use super::mstp::{unit, MstpToken};
+use crate::interrupt;
+
+/// A generator polynomial of the unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Polynomial {
+    /// x^8 + x^2 + x + 1 (0x07).
+    Crc8 = 0b001,
+    /// x^16 + x^15 + x^2 + 1 (0x8005).
+    Crc16 = 0b010,
+    /// x^16 + x^12 + x^5 + 1 (0x1021).
+    Ccitt = 0b011,
+    /// x^32 + x^26 + x^23 + ... + x + 1 (0x04c11db7).
+    Crc32 = 0b100,
+    /// x^32 + x^28 + x^27 + ... + x^6 + 1 (0x1edc6f41), Castagnoli.
+    Crc32C = 0b101,
+}
+
+impl Polynomial {
+    /// Returns the width of the CRC in bits.
+    pub fn width(self) -> u32 {
+        match self {
+            Polynomial::Crc8 => 8,
+            Polynomial::Crc16 | Polynomial::Ccitt => 16,
+            Polynomial::Crc32 | Polynomial::Crc32C => 32,
+        }
+    }
+
+    /// Returns the polynomial without the highest term.
+    fn value(self) -> u32 {
+        match self {
+            Polynomial::Crc8 => 0x07,
+            Polynomial::Crc16 => 0x8005,
+            Polynomial::Ccitt => 0x1021,
+            Polynomial::Crc32 => 0x04c1_1db7,
+            Polynomial::Crc32C => 0x1edc_6f41,
+        }
+    }
+
+    fn mask(self) -> u32 {
+        u32::MAX >> (32 - self.width())
+    }
+}
+
+/// The order in which the bits of each byte enter the CRC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BitOrder {
+    /// Least significant bit first, for the "reflected" CRCs, e.g. CRC-32.
+    LsbFirst,
+    /// Most significant bit first.
+    MsbFirst,
+}
+
+/// The parameters of a CRC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Algorithm {
+    pub polynomial: Polynomial,
+    pub bit_order: BitOrder,
+    /// Value of the CRC before the first byte.
+    pub init: u32,
+    /// Value XORed with the CRC after the last byte.
+    pub xor_out: u32,
+}
+
+impl Algorithm {
+    /// CRC-8/SMBUS, e.g. the packet error code of SMBus.
+    pub const CRC_8: Algorithm = Algorithm::new(Polynomial::Crc8, BitOrder::MsbFirst);
+    /// CRC-16/ARC.
+    pub const CRC_16: Algorithm = Algorithm::new(Polynomial::Crc16, BitOrder::LsbFirst);
+    /// CRC-16/MODBUS.
+    pub const CRC_16_MODBUS: Algorithm = Algorithm::CRC_16.with_init(0xffff);
+    /// CRC-16/CCITT-FALSE, also known as CRC-16/IBM-3740.
+    pub const CRC_16_CCITT_FALSE: Algorithm =
+        Algorithm::new(Polynomial::Ccitt, BitOrder::MsbFirst).with_init(0xffff);
+    /// CRC-16/XMODEM.
+    pub const CRC_16_XMODEM: Algorithm = Algorithm::new(Polynomial::Ccitt, BitOrder::MsbFirst);
+    /// CRC-16/KERMIT, the CRC of HDLC with the bits in line order.
+    pub const CRC_16_KERMIT: Algorithm = Algorithm::new(Polynomial::Ccitt, BitOrder::LsbFirst);
+    /// CRC-32 of Ethernet, zlib and PNG.
+    pub const CRC_32: Algorithm = Algorithm::new(Polynomial::Crc32, BitOrder::LsbFirst)
+        .with_init(0xffff_ffff)
+        .with_xor_out(0xffff_ffff);
+    /// CRC-32/MPEG-2, e.g. of the STM32 CRC unit.
+    pub const CRC_32_MPEG2: Algorithm =
+        Algorithm::new(Polynomial::Crc32, BitOrder::MsbFirst).with_init(0xffff_ffff);
+    /// CRC-32C of iSCSI, SCTP and ext4.
+    pub const CRC_32C: Algorithm = Algorithm::new(Polynomial::Crc32C, BitOrder::LsbFirst)
+        .with_init(0xffff_ffff)
+        .with_xor_out(0xffff_ffff);
+
+    /// A CRC with `polynomial` and `bit_order`, an initial value of 0 and no final XOR.
+    pub const fn new(polynomial: Polynomial, bit_order: BitOrder) -> Self {
+        Self {
+            polynomial,
+            bit_order,
+            init: 0,
+            xor_out: 0,
+        }
+    }
+
+    /// Start the CRC at `init`.
+    pub const fn with_init(mut self, init: u32) -> Self {
+        self.init = init;
+        self
+    }
+
+    /// XOR the CRC with `xor_out` after the last byte.
+    pub const fn with_xor_out(mut self, xor_out: u32) -> Self {
+        self.xor_out = xor_out;
+        self
+    }
+}
+
+/// The CRC calculator.
+pub struct Crc {
+    _clock: MstpToken<unit::Crc>,
+}
+
+impl Crc {
+    /// CRC Control Register 0.
+    /// * b0-b2: GPS, the polynomial, see [`Polynomial`].
+    /// * b6: LMS, 0: LSB first, 1: MSB first.
+    /// * b7: DORCLR, writing 1 clears CRCDOR.
+    const CRCCR0: *mut u8 = 0x40074000 as *mut u8;
+
+    /// CRC Data Input Register. The 32-bit polynomials take a word, the others a byte.
+    const CRCDIR: *mut u32 = 0x40074004 as *mut u32;
+    const CRCDIR_BY: *mut u8 = 0x40074004 as *mut u8;
+
+    /// CRC Data Output Register, the current CRC. Accessed with the width of the CRC.
+    const CRCDOR: *mut u32 = 0x40074008 as *mut u32;
+    const CRCDOR_HA: *mut u16 = 0x40074008 as *mut u16;
+    const CRCDOR_BY: *mut u8 = 0x40074008 as *mut u8;
+
+    const CRCCR0_LMS: u8 = 1 << 6;
+    const CRCCR0_DORCLR: u8 = 1 << 7;
+
+    /// Returns the CRC calculator, unless it was taken already.
+    pub fn take(clock: MstpToken<unit::Crc>) -> Option<Self> {
+        static mut TAKEN: bool = false;
+        interrupt::free(|| unsafe {
+            if TAKEN {
+                None
+            } else {
+                TAKEN = true;
+                Some(Self { _clock: clock })
+            }
+        })
+    }
+
+    /// Start a CRC with `algorithm`.
+    pub fn digest(&mut self, algorithm: Algorithm) -> Digest<'_> {
+        let mut control = algorithm.polynomial as u8 | Self::CRCCR0_DORCLR;
+        if algorithm.bit_order == BitOrder::MsbFirst {
+            control |= Self::CRCCR0_LMS;
+        }
+        unsafe { Self::CRCCR0.write_volatile(control) };
+        let digest = Digest {
+            _crc: self,
+            algorithm,
+        };
+        digest.set_value(algorithm.init);
+        digest
+    }
+
+    /// Returns the CRC of `data` with `algorithm`.
+    pub fn checksum(&mut self, algorithm: Algorithm, data: &[u8]) -> u32 {
+        let mut digest = self.digest(algorithm);
+        digest.update(data);
+        digest.finalize()
+    }
+}
+
+/// A CRC in progress.
+pub struct Digest<'a> {
+    _crc: &'a mut Crc,
+    algorithm: Algorithm,
+}
+
+impl Digest<'_> {
+    /// Add `data` to the CRC.
+    pub fn update(&mut self, data: &[u8]) {
+        if self.algorithm.polynomial.width() < 32 {
+            for &byte in data {
+                unsafe { Crc::CRCDIR_BY.write_volatile(byte) };
+            }
+            return;
+        }
+        // The 32-bit polynomials take words, the remaining bytes are added in software.
+        let mut words = data.chunks_exact(4);
+        for word in &mut words {
+            let word = [word[0], word[1], word[2], word[3]];
+            let word = match self.algorithm.bit_order {
+                BitOrder::LsbFirst => u32::from_le_bytes(word),
+                BitOrder::MsbFirst => u32::from_be_bytes(word),
+            };
+            unsafe { Crc::CRCDIR.write_volatile(word) };
+        }
+        let rest = words.remainder();
+        if !rest.is_empty() {
+            let value = self.update_in_software(self.value(), rest);
+            self.set_value(value);
+        }
+    }
+
+    /// Returns the CRC of the data so far.
+    pub fn finalize(self) -> u32 {
+        (self.value() ^ self.algorithm.xor_out) & self.algorithm.polynomial.mask()
+    }
+
+    /// Returns the current value of CRCDOR.
+    fn value(&self) -> u32 {
+        unsafe {
+            match self.algorithm.polynomial.width() {
+                8 => Crc::CRCDOR_BY.read_volatile() as u32,
+                16 => Crc::CRCDOR_HA.read_volatile() as u32,
+                _ => Crc::CRCDOR.read_volatile(),
+            }
+        }
+    }
+
+    /// Set CRCDOR to `value`.
+    fn set_value(&self, value: u32) {
+        unsafe {
+            match self.algorithm.polynomial.width() {
+                8 => Crc::CRCDOR_BY.write_volatile(value as u8),
+                16 => Crc::CRCDOR_HA.write_volatile(value as u16),
+                _ => Crc::CRCDOR.write_volatile(value),
+            }
+        }
+    }
+
+    /// Returns `value` updated with `data`, bit by bit, for the 32-bit polynomials.
+    fn update_in_software(&self, mut value: u32, data: &[u8]) -> u32 {
+        let polynomial = self.algorithm.polynomial.value();
+        for &byte in data {
+            match self.algorithm.bit_order {
+                BitOrder::LsbFirst => {
+                    value ^= byte as u32;
+                    for _ in 0..8 {
+                        value = if value & 1 != 0 {
+                            (value >> 1) ^ polynomial.reverse_bits()
+                        } else {
+                            value >> 1
+                        };
+                    }
+                }
+                BitOrder::MsbFirst => {
+                    value ^= (byte as u32) << 24;
+                    for _ in 0..8 {
+                        value = if value & (1 << 31) != 0 {
+                            (value << 1) ^ polynomial
+                        } else {
+                            value << 1
+                        };
+                    }
+                }
+            }
+        }
+        value
+    }
+}
@@ -0,0 +1,93 @@
+//! Identification of the MCU: The factory-programmed unique ID, the part number and the version.
+//!
+//! The 128-bit [`unique_id`] is different for every RA4M1, so it makes a good serial number,
+//! e.g. for the USB device descriptor (see [`serial_number`]), or a seed for per-device keys.
+//! [`mac_address`] derives a locally administered MAC address from it, for network interfaces
+//! that don't have their own.
+//!
+//! The values are in a table in the flash, whose address is given by the Flash Memory Information
+//! Register Table (FMIFRT).
+//!
+//! For details, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Flash Memory", section
+//! "Unique ID Register".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::device_id;
+//!
+//! assert!(device_id::part_number().starts_with("R7FA4M1"));
+//! let mac = device_id::mac_address();
+//! assert_eq!(mac[0] & 0b11, 0b10);
+//! ```
+
+use crate::interrupt;
+
+use core::ptr;
+
+/// Flash Memory Information Register Table. Holds the address of the table with the IDs.
+const FMIFRT: *const u32 = 0x407fb19c as *const u32;
+
+/// Offsets in the table.
+/// * 0x14: UIDR0-UIDR3, the unique ID.
+/// * 0x24: PNR0-PNR3, the part number in ASCII, padded with spaces.
+/// * 0x44: MCUVER, the version of the MCU.
+const UNIQUE_ID_OFFSET: u32 = 0x14;
+const PART_NUMBER_OFFSET: u32 = 0x24;
+const MCU_VERSION_OFFSET: u32 = 0x44;
+
+/// Returns `len` bytes at `offset` in the table.
+fn table(offset: u32, len: usize) -> &'static [u8] {
+    unsafe {
+        let start = (FMIFRT.read_volatile() + offset) as *const u8;
+        core::slice::from_raw_parts(start, len)
+    }
+}
+
+/// Returns the unique ID of the MCU.
+pub fn unique_id() -> [u8; 16] {
+    let mut id = [0; 16];
+    id.copy_from_slice(table(UNIQUE_ID_OFFSET, 16));
+    id
+}
+
+/// Returns the part number, e.g. "R7FA4M1AB3CFM".
+pub fn part_number() -> &'static str {
+    let part_number = table(PART_NUMBER_OFFSET, 16);
+    core::str::from_utf8(part_number)
+        .unwrap_or("")
+        .trim_end_matches([' ', '\0'])
+}
+
+/// Returns the version of the MCU.
+pub fn mcu_version() -> u8 {
+    table(MCU_VERSION_OFFSET, 1)[0]
+}
+
+/// Returns the unique ID as 32 hexadecimal digits.
+pub fn serial_number() -> &'static str {
+    static mut SERIAL_NUMBER: [u8; 32] = [0; 32];
+    interrupt::free(|| unsafe {
+        let serial_number = &mut *ptr::addr_of_mut!(SERIAL_NUMBER);
+        if serial_number[0] == 0 {
+            const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+            for (digits, byte) in serial_number.chunks_mut(2).zip(unique_id()) {
+                digits[0] = DIGITS[(byte >> 4) as usize];
+                digits[1] = DIGITS[(byte & 0x0f) as usize];
+            }
+        }
+        core::str::from_utf8_unchecked(serial_number)
+    })
+}
+
+/// Returns a MAC address derived from the unique ID. It is marked as locally administered and
+/// unicast, so it doesn't collide with addresses assigned by manufacturers.
+pub fn mac_address() -> [u8; 6] {
+    // FNV-1a over the ID, which mixes all of its bits into the address.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in unique_id() {
+        hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+    }
+    let [a, b, c, d, e, f, _, _] = hash.to_le_bytes();
+    [(a & !0b11) | 0b10, b, c, d, e, f]
+}
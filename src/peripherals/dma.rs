@@ -0,0 +1,919 @@
+//! DMA Controller (DMAC): Four channels that move data between memory and peripherals without the
+//! CPU.
+//!
+//! [`Channels::take`] returns the four [`Channel`]s. A channel is programmed with a [`Config`]
+//! that sets the source and destination addresses, how they change after each transfer, the size
+//! of the data and the [`Mode`]:
+//! * [`Mode::Normal`] does `count` transfers.
+//! * [`Mode::Repeat`] does `count` transfers, then resets the address of the repeat area to its
+//!   start and continues, `repeats` times. Useful for a ring buffer.
+//! * [`Mode::Block`] moves a block of `count` items per request, `blocks` times.
+//!
+//! By default, a transfer is started by software. [`Config::with_trigger`] selects an event from
+//! the ICU instead (see [`super::icu::Event`]), e.g. a received byte, the transmit buffer of the SPI
+//! unit running empty, the end of an ADC scan or a timer overflow. Each event then moves data
+//! without involving the CPU.
+//!
+//! For continuous reception, [`Channel::start_stream`] fills two buffers in turn, and
+//! [`Channel::start_ring`] the two halves of one buffer. Each time one is full, the transfer end
+//! interrupt restarts the transfer on the other one and calls a handler with the [`Half`] that is
+//! ready, so it can be processed while the other one is filled. This goes on until
+//! [`Stream::stop`] is called. The restart isn't seamless: Trigger events that come between the end
+//! of a buffer and the start of the interrupt handler aren't transferred, so the events must be
+//! further apart than the interrupt latency.
+//!
+//! To learn when a transfer is done without polling [`Channel::is_done`],
+//! [`Channel::set_callback`] registers a function that is called from the transfer end interrupt,
+//! and [`Channel::start_async`] returns a [`Transfer`] future that completes at the end.
+//!
+//! The `_owned` functions, e.g. [`Channel::read_owned`], take the channel and `'static` buffers
+//! (see [`ReadBuffer`] and [`WriteBuffer`]) and return an [`OwnedTransfer`] that gives them back
+//! once the transfer is done. This way, the buffers can't be read, changed or dropped while the
+//! DMAC is using them, without blocking until the transfer is done.
+//!
+//! [`Channel::copy`] and [`Channel::fill`] are safe memory-to-memory transfers that wait until the
+//! DMAC is done. [`Channel::start`] starts any transfer and returns right away, it is `unsafe`
+//! because the compiler can't check that the memory stays valid until [`Channel::is_done`] returns
+//! true.
+//!
+//! The address and transfer size settings are the same as those of the [`super::dtc`].
+//!
+//! For details, see Renesas RA4M1 Group User's Manual: Hardware, chapter "DMA Controller (DMAC)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::dma::Channels;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//!
+//! let mut channels = Channels::take(mstp::token()).unwrap();
+//! let source = [1u32; 256];
+//! let mut destination = [0u32; 256];
+//! channels.ch0.copy(&source, &mut destination);
+//! ```
+
+pub use super::dtc::{AddressMode, Size};
+
+use super::icu::{self, Event, Slot};
+use super::mstp::{unit, MstpToken};
+use super::registers::VolatileBoolOps;
+use crate::interrupt::{self, WakerCell};
+
+use core::future::{poll_fn, Future};
+use core::pin::Pin;
+use core::ptr;
+use core::task::{Context, Poll};
+
+/// Errors of the DMA driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// All interrupt slots are in use, see [`super::icu::attach`].
+    NoFreeSlot,
+}
+
+/// Data that can be moved by one transfer.
+pub trait Element: Copy {
+    const SIZE: Size;
+}
+
+impl Element for u8 {
+    const SIZE: Size = Size::Byte;
+}
+
+impl Element for u16 {
+    const SIZE: Size = Size::HalfWord;
+}
+
+impl Element for u32 {
+    const SIZE: Size = Size::Word;
+}
+
+/// A buffer that the DMAC can read from while the transfer owns it.
+///
+/// # Safety
+///
+/// The pointer and length returned by `read_buffer` must stay valid as long as the buffer isn't
+/// dropped, even if the buffer is moved. This holds for `'static` references, whose memory can't
+/// be freed and is only reachable through the reference.
+pub unsafe trait ReadBuffer {
+    type Word: Element;
+
+    /// Returns the address and length of the buffer.
+    fn read_buffer(&self) -> (*const Self::Word, usize);
+}
+
+/// A buffer that the DMAC can write to while the transfer owns it.
+///
+/// # Safety
+///
+/// See [`ReadBuffer`]. Additionally, the buffer must not be reachable through any other reference.
+pub unsafe trait WriteBuffer {
+    type Word: Element;
+
+    /// Returns the address and length of the buffer.
+    fn write_buffer(&mut self) -> (*mut Self::Word, usize);
+}
+
+unsafe impl<T: Element> ReadBuffer for &'static [T] {
+    type Word = T;
+
+    fn read_buffer(&self) -> (*const T, usize) {
+        (self.as_ptr(), self.len())
+    }
+}
+
+unsafe impl<T: Element> ReadBuffer for &'static mut [T] {
+    type Word = T;
+
+    fn read_buffer(&self) -> (*const T, usize) {
+        (self.as_ptr(), self.len())
+    }
+}
+
+unsafe impl<T: Element, const N: usize> ReadBuffer for &'static [T; N] {
+    type Word = T;
+
+    fn read_buffer(&self) -> (*const T, usize) {
+        (self.as_ptr(), N)
+    }
+}
+
+unsafe impl<T: Element, const N: usize> ReadBuffer for &'static mut [T; N] {
+    type Word = T;
+
+    fn read_buffer(&self) -> (*const T, usize) {
+        (self.as_ptr(), N)
+    }
+}
+
+unsafe impl<T: Element> WriteBuffer for &'static mut [T] {
+    type Word = T;
+
+    fn write_buffer(&mut self) -> (*mut T, usize) {
+        (self.as_mut_ptr(), self.len())
+    }
+}
+
+unsafe impl<T: Element, const N: usize> WriteBuffer for &'static mut [T; N] {
+    type Word = T;
+
+    fn write_buffer(&mut self) -> (*mut T, usize) {
+        (self.as_mut_ptr(), N)
+    }
+}
+
+/// The address that is reset to its start after each repeat or block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Area {
+    Destination = 0b00,
+    Source = 0b01,
+}
+
+/// Transfer mode of a channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    /// Do `count` transfers, 1 to 65535.
+    Normal,
+    /// Do `count` transfers, 1 to 1024, then reset the address of `area` and start over. This is
+    /// done `repeats` times, 0 means 65536.
+    Repeat { area: Area, repeats: u16 },
+    /// Do `count` transfers, 1 to 1024, per request, then reset the address of `area`. This is done
+    /// for `blocks` requests, 0 means 65536.
+    Block { area: Area, blocks: u16 },
+}
+
+/// What starts the transfers of a channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Trigger {
+    /// [`Channel::start`] starts all transfers.
+    Software,
+    /// Each occurrence of the event starts a transfer, or a block in block mode. E.g. the receive
+    /// interrupt of a serial interface moves each received byte into memory. The event still
+    /// interrupts the CPU if it is attached to an interrupt slot.
+    Event(Event),
+}
+
+/// Settings of a transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub size: Size,
+    pub source: *const u8,
+    pub source_mode: AddressMode,
+    pub destination: *mut u8,
+    pub destination_mode: AddressMode,
+    pub mode: Mode,
+    /// Number of transfers, see [`Mode`].
+    pub count: u16,
+    pub trigger: Trigger,
+}
+
+impl Config {
+    /// Settings for normal mode: Move `count` items of `size` from `source` to `destination`,
+    /// updating the addresses after each transfer as given by the address modes.
+    pub fn normal(
+        size: Size,
+        source: *const u8,
+        source_mode: AddressMode,
+        destination: *mut u8,
+        destination_mode: AddressMode,
+        count: u16,
+    ) -> Self {
+        Self {
+            size,
+            source,
+            source_mode,
+            destination,
+            destination_mode,
+            mode: Mode::Normal,
+            count,
+            trigger: Trigger::Software,
+        }
+    }
+
+    /// Use `mode` instead of normal mode.
+    pub fn with_mode(self, mode: Mode) -> Self {
+        Self { mode, ..self }
+    }
+
+    /// Let `event` start the transfers instead of software.
+    pub fn with_trigger(self, event: Event) -> Self {
+        Self {
+            trigger: Trigger::Event(event),
+            ..self
+        }
+    }
+}
+
+/// One of the two buffers of a [`Stream`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Half {
+    First = 0,
+    Second = 1,
+}
+
+impl Half {
+    fn other(self) -> Self {
+        match self {
+            Half::First => Half::Second,
+            Half::Second => Half::First,
+        }
+    }
+}
+
+/// State of the stream of a channel, used by its interrupt handler.
+#[derive(Clone, Copy)]
+struct StreamState {
+    buffers: [u32; 2],
+    len: u16,
+    /// The buffer that is being filled.
+    filling: Half,
+    on_full: Option<fn(Half)>,
+}
+
+static mut STREAMS: [StreamState; 4] = [StreamState {
+    buffers: [0; 2],
+    len: 0,
+    filling: Half::First,
+    on_full: None,
+}; 4];
+
+/// Interrupt slots linked to the transfer end events of the channels, once they are needed.
+static mut SLOTS: [Option<Slot>; 4] = [None; 4];
+
+/// Handlers called at the end of a transfer, see [`Channel::set_callback`].
+static mut CALLBACKS: [Option<fn()>; 4] = [None; 4];
+
+/// Wakers of the [`Transfer`] futures.
+static WAKERS: [WakerCell; 4] = [const { WakerCell::new() }; 4];
+
+/// Interrupt handlers of the channels.
+const HANDLERS: [fn(); 4] = [
+    || on_interrupt(0),
+    || on_interrupt(1),
+    || on_interrupt(2),
+    || on_interrupt(3),
+];
+
+/// Transfer end of a channel: Continue a stream, or call the callback and wake the future.
+fn on_interrupt(number: u8) {
+    let channel = Channel(number);
+    unsafe {
+        channel.dmsts().write_volatile(0);
+    }
+    let state = unsafe { &mut *ptr::addr_of_mut!(STREAMS[number as usize]) };
+    if let Some(on_full) = state.on_full {
+        let full = state.filling;
+        state.filling = full.other();
+        unsafe {
+            channel
+                .dmdar()
+                .write_volatile(state.buffers[state.filling as usize]);
+            channel.dmcra().write_volatile(state.len as u32);
+            channel.dmcnt().write_volatile(1);
+        }
+        on_full(full);
+    } else {
+        if let Some(callback) = unsafe { ptr::addr_of!(CALLBACKS[number as usize]).read() } {
+            callback();
+        }
+        WAKERS[number as usize].wake();
+    }
+}
+
+/// A transfer that has been started with [`Channel::start_async`]. Completes when the channel has
+/// done all transfers. Dropping it before aborts the transfer.
+pub struct Transfer<'a> {
+    channel: &'a mut Channel,
+}
+
+impl Future for Transfer<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        WAKERS[self.channel.0 as usize].register(cx.waker());
+        if self.channel.is_done() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Transfer<'_> {
+    fn drop(&mut self) {
+        self.channel.abort();
+    }
+}
+
+/// A channel that streams data from a peripheral into two buffers in turn, see
+/// [`Channel::start_stream`].
+pub struct Stream {
+    channel: Channel,
+}
+
+impl Stream {
+    /// Returns the buffer that is being filled, and how many items have been written to it.
+    pub fn position(&self) -> (Half, usize) {
+        interrupt::free(|| {
+            let state = unsafe { ptr::addr_of!(STREAMS[self.channel.0 as usize]).read() };
+            let remaining = self.channel.remaining();
+            (state.filling, (state.len - remaining) as usize)
+        })
+    }
+
+    /// Stop the stream and return the channel.
+    pub fn stop(mut self) -> Channel {
+        self.channel.abort();
+        unsafe {
+            self.channel.dmint().write_volatile(0);
+            ptr::addr_of_mut!(STREAMS[self.channel.0 as usize].on_full).write(None);
+        }
+        self.channel
+    }
+}
+
+/// A transfer that owns its channel and buffers `B`, so they can't be touched until it is done.
+/// Returned by [`Channel::copy_owned`], [`Channel::read_owned`] and [`Channel::write_owned`].
+///
+/// Dropping the transfer doesn't stop it. The channel and the buffers are lost then, but since
+/// the buffers are `'static`, nothing else can access their memory.
+pub struct OwnedTransfer<B> {
+    channel: Channel,
+    buffers: B,
+}
+
+impl<B> OwnedTransfer<B> {
+    /// Returns true if the channel has done all transfers.
+    pub fn is_done(&self) -> bool {
+        self.channel.is_done()
+    }
+
+    /// Wait until the channel has done all transfers, and return the buffers and the channel.
+    pub fn wait(self) -> (B, Channel) {
+        self.channel.wait();
+        (self.buffers, self.channel)
+    }
+
+    /// Wait until the channel has done all transfers without blocking, and return the buffers and
+    /// the channel. Polls in a loop if no interrupt slot is free for the transfer end event.
+    pub async fn wait_async(self) -> (B, Channel) {
+        let woken = self.channel.attach_interrupt().is_ok();
+        if woken {
+            unsafe {
+                self.channel.dmint().write_volatile(Channel::DMINT_DTIE);
+            }
+        }
+        poll_fn(|cx| {
+            WAKERS[self.channel.0 as usize].register(cx.waker());
+            if self.channel.is_done() {
+                Poll::Ready(())
+            } else {
+                if !woken {
+                    cx.waker().wake_by_ref();
+                }
+                Poll::Pending
+            }
+        })
+        .await;
+        (self.buffers, self.channel)
+    }
+
+    /// Stop the transfer, and return the buffers and the channel.
+    pub fn abort(mut self) -> (B, Channel) {
+        self.channel.abort();
+        (self.buffers, self.channel)
+    }
+}
+
+/// The four DMAC channels.
+pub struct Channels {
+    pub ch0: Channel,
+    pub ch1: Channel,
+    pub ch2: Channel,
+    pub ch3: Channel,
+}
+
+impl Channels {
+    /// DMA Module Activation Register. b0: DMST, enable the DMAC.
+    const DMAST: *mut u8 = 0x40005200 as *mut u8;
+
+    /// Returns the channels, unless they were taken already. Enables the DMAC, whose clock keeps
+    /// running from then on.
+    pub fn take(clock: MstpToken<unit::DmacDtc>) -> Option<Self> {
+        static mut TAKEN: bool = false;
+        interrupt::free(|| unsafe {
+            if TAKEN {
+                None
+            } else {
+                TAKEN = true;
+                core::mem::forget(clock);
+                Self::DMAST.write_volatile(1);
+                Some(Self {
+                    ch0: Channel(0),
+                    ch1: Channel(1),
+                    ch2: Channel(2),
+                    ch3: Channel(3),
+                })
+            }
+        })
+    }
+}
+
+/// One of the four DMAC channels.
+pub struct Channel(u8);
+
+impl Channel {
+    const BASE_ADDRESS: u32 = 0x40005000;
+    const DELSR_BASE: u32 = 0x40006280;
+
+    /// The longest normal mode transfer.
+    const MAX_COUNT: usize = 0xffff;
+
+    const DMTMD_MD_REPEAT: u16 = 0b01 << 14;
+    const DMTMD_MD_BLOCK: u16 = 0b10 << 14;
+    const DMTMD_DCTG_EVENT: u16 = 0b01;
+    /// DTS value for no repeat or block area.
+    const DMTMD_DTS_NONE: u16 = 0b10 << 12;
+
+    const DMREQ_SWREQ: u8 = 1 << 0;
+    const DMREQ_CLRS: u8 = 1 << 4;
+
+    const DMSTS_ACT: u8 = 1 << 7;
+
+    const DMINT_DTIE: u8 = 1 << 4;
+
+    /// Transfer end events of the channels.
+    const EVENTS: [Event; 4] = [
+        Event::Dmac0Int,
+        Event::Dmac1Int,
+        Event::Dmac2Int,
+        Event::Dmac3Int,
+    ];
+
+    fn register<T>(&self, offset: u32) -> *mut T {
+        (Self::BASE_ADDRESS + 0x40 * self.0 as u32 + offset) as *mut T
+    }
+
+    /// DMA Source Address Register.
+    fn dmsar(&self) -> *mut u32 {
+        self.register(0x00)
+    }
+
+    /// DMA Destination Address Register.
+    fn dmdar(&self) -> *mut u32 {
+        self.register(0x04)
+    }
+
+    /// DMA Transfer Count Register. b0-b15: DMCRAL, the remaining transfers. b16-b25: DMCRAH, the
+    /// value DMCRAL is reloaded with in repeat and block mode.
+    fn dmcra(&self) -> *mut u32 {
+        self.register(0x08)
+    }
+
+    /// DMA Block Transfer Count Register. The remaining repeats or blocks.
+    fn dmcrb(&self) -> *mut u16 {
+        self.register(0x0c)
+    }
+
+    /// DMA Transfer Mode Register.
+    /// * b0-b1: DCTG, 00 for software start, 01 for an event (see [`super::icu`]).
+    /// * b8-b9: SZ, the transfer size.
+    /// * b12-b13: DTS, the repeat or block area, 10 for none.
+    /// * b14-b15: MD, 00 for normal, 01 for repeat and 10 for block mode.
+    fn dmtmd(&self) -> *mut u16 {
+        self.register(0x10)
+    }
+
+    /// DMA Interrupt Setting Register. b4: DTIE, transfer end interrupt.
+    fn dmint(&self) -> *mut u8 {
+        self.register(0x13)
+    }
+
+    /// DMA Address Mode Register.
+    /// * b6-b7: DM, the destination address mode.
+    /// * b14-b15: SM, the source address mode.
+    fn dmamd(&self) -> *mut u16 {
+        self.register(0x14)
+    }
+
+    /// DMA Transfer Enable Register. b0: DTE, enable transfers. Cleared by the DMAC at the end.
+    fn dmcnt(&self) -> *mut u8 {
+        self.register(0x1c)
+    }
+
+    /// DMA Software Start Register.
+    /// * b0: SWREQ, request a transfer.
+    /// * b4: CLRS, keep SWREQ set after a transfer, so all transfers are done.
+    fn dmreq(&self) -> *mut u8 {
+        self.register(0x1d)
+    }
+
+    /// DMA Status Register. b4: DTIF, transfer end. b7: ACT, a transfer is in progress.
+    fn dmsts(&self) -> *mut u8 {
+        self.register(0x1e)
+    }
+
+    /// DMAC Event Link Setting Register of the channel, in the ICU.
+    /// * b0-b8: DELS, the event number, 0 if no event activates the channel.
+    /// * b16: IR, the event occurred. Cleared by the DMAC when it starts the transfer.
+    fn delsr(&self) -> *mut u32 {
+        (Self::DELSR_BASE + 4 * self.0 as u32) as *mut u32
+    }
+
+    /// Returns the number of the channel.
+    #[inline]
+    pub fn number(&self) -> u8 {
+        self.0
+    }
+
+    /// Program the channel with `config` and start it. With [`Trigger::Software`], all transfers
+    /// are done right away. With [`Trigger::Event`], each event does one transfer, or one block in
+    /// block mode.
+    ///
+    /// # Safety
+    ///
+    /// The memory that `config` refers to must stay valid until the transfer is done, or until
+    /// [`Channel::abort`] is called.
+    pub unsafe fn start(&mut self, config: &Config) {
+        self.configure(config);
+        if ptr::addr_of!(SLOTS[self.0 as usize]).read().is_some() {
+            self.dmint().write_volatile(Self::DMINT_DTIE);
+        }
+        match config.trigger {
+            Trigger::Software => {
+                self.enable();
+                self.dmreq()
+                    .write_volatile(Self::DMREQ_CLRS | Self::DMREQ_SWREQ);
+            }
+            Trigger::Event(event) => {
+                self.delsr().write_volatile(event as u32);
+                self.enable();
+            }
+        }
+    }
+
+    /// Program the channel with `config`, while it is disabled.
+    unsafe fn configure(&mut self, config: &Config) {
+        self.abort();
+        let (md, count_b) = match config.mode {
+            Mode::Normal => (Self::DMTMD_DTS_NONE, 0),
+            Mode::Repeat { area, repeats } => {
+                (Self::DMTMD_MD_REPEAT | ((area as u16) << 12), repeats)
+            }
+            Mode::Block { area, blocks } => (Self::DMTMD_MD_BLOCK | ((area as u16) << 12), blocks),
+        };
+        let count_a = match config.mode {
+            Mode::Normal => config.count as u32,
+            _ => ((config.count as u32 & 0x3ff) << 16) | (config.count as u32 & 0x3ff),
+        };
+        let dctg = match config.trigger {
+            Trigger::Software => 0b00,
+            Trigger::Event(_) => Self::DMTMD_DCTG_EVENT,
+        };
+        self.dmsar().write_volatile(config.source as u32);
+        self.dmdar().write_volatile(config.destination as u32);
+        self.dmcra().write_volatile(count_a);
+        self.dmcrb().write_volatile(count_b);
+        self.dmtmd()
+            .write_volatile(md | ((config.size as u16) << 8) | dctg);
+        self.dmamd().write_volatile(
+            ((config.source_mode as u16) << 14) | ((config.destination_mode as u16) << 6),
+        );
+        self.dmint().write_volatile(0);
+        self.dmsts().write_volatile(0);
+    }
+
+    /// Enable transfers on the channel.
+    fn enable(&mut self) {
+        unsafe {
+            self.dmcnt().write_volatile(1);
+        }
+    }
+
+    /// Returns true if the channel has done all transfers, or was never started.
+    pub fn is_done(&self) -> bool {
+        unsafe {
+            self.dmcnt().read_volatile() & 1 == 0
+                && self.dmsts().read_volatile() & Self::DMSTS_ACT == 0
+        }
+    }
+
+    /// Wait until the channel has done all transfers.
+    pub fn wait(&self) {
+        while !self.is_done() {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Returns the number of transfers of the current repeat or block that haven't been done yet.
+    pub fn remaining(&self) -> u16 {
+        unsafe { self.dmcra().read_volatile() as u16 }
+    }
+
+    /// Stop the channel. Transfers that haven't been done yet are cancelled.
+    pub fn abort(&mut self) {
+        unsafe {
+            self.dmreq()
+                .volatile_and(!(Self::DMREQ_CLRS | Self::DMREQ_SWREQ));
+            self.dmcnt().write_volatile(0);
+            self.delsr().write_volatile(0);
+        }
+        while unsafe { self.dmsts().read_volatile() } & Self::DMSTS_ACT != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Call `callback` from an interrupt handler whenever a transfer started with
+    /// [`Channel::start`] is done. Replaces the previous callback.
+    pub fn set_callback(&mut self, callback: fn()) -> Result<(), Error> {
+        self.attach_interrupt()?;
+        unsafe {
+            ptr::addr_of_mut!(CALLBACKS[self.0 as usize]).write(Some(callback));
+        }
+        Ok(())
+    }
+
+    /// Remove the callback set with [`Channel::set_callback`].
+    pub fn clear_callback(&mut self) {
+        unsafe {
+            ptr::addr_of_mut!(CALLBACKS[self.0 as usize]).write(None);
+        }
+    }
+
+    /// Program the channel with `config` and start it like [`Channel::start`]. The returned future
+    /// completes when the channel has done all transfers, woken by the transfer end interrupt.
+    ///
+    /// # Safety
+    ///
+    /// The memory that `config` refers to must stay valid until the future completes or is
+    /// dropped. Leaking the future with `core::mem::forget` leaves the transfer running.
+    pub unsafe fn start_async(&mut self, config: &Config) -> Result<Transfer<'_>, Error> {
+        self.attach_interrupt()?;
+        self.start(config);
+        Ok(Transfer { channel: self })
+    }
+
+    /// Link the transfer end event of the channel to an interrupt slot, unless this was done
+    /// already. The slot stays linked to the channel from then on.
+    fn attach_interrupt(&self) -> Result<(), Error> {
+        interrupt::free(|| unsafe {
+            let slot = ptr::addr_of_mut!(SLOTS[self.0 as usize]);
+            if slot.read().is_none() {
+                let attached =
+                    icu::attach(Self::EVENTS[self.0 as usize], HANDLERS[self.0 as usize]);
+                slot.write(Some(attached.ok_or(Error::NoFreeSlot)?));
+            }
+            Ok(())
+        })
+    }
+
+    /// Stream items from the peripheral register `source` into `first` and `second` in turn, one
+    /// item per `trigger` event. Each time a buffer is full, `on_full` is called with it from an
+    /// interrupt handler, while the other one is filled. Both buffers must have the same length,
+    /// 1 to 65535.
+    ///
+    /// The handler of the transfer end interrupt points the channel at the other buffer, so the
+    /// trigger events that come before the handler runs are lost. Use it for events that are
+    /// further apart than the interrupt latency, e.g. ADC scans, and not for back-to-back bytes
+    /// of a fast serial port.
+    ///
+    /// Returns the channel if there is no free interrupt slot.
+    ///
+    /// # Safety
+    ///
+    /// `source` must be readable, and the buffers must stay valid until [`Stream::stop`] is
+    /// called. `on_full` must be done with a buffer before the other one is full.
+    pub unsafe fn start_stream<T: Element>(
+        mut self,
+        source: *const T,
+        trigger: Event,
+        first: *mut [T],
+        second: *mut [T],
+        on_full: fn(Half),
+    ) -> Result<Stream, Channel> {
+        let len = first.len() as u16;
+        if self.attach_interrupt().is_err() {
+            return Err(self);
+        }
+        ptr::addr_of_mut!(STREAMS[self.0 as usize]).write(StreamState {
+            buffers: [first as *mut T as u32, second as *mut T as u32],
+            len,
+            filling: Half::First,
+            on_full: Some(on_full),
+        });
+        let config = Config::normal(
+            T::SIZE,
+            source as *const u8,
+            AddressMode::Fixed,
+            first as *mut u8,
+            AddressMode::Increment,
+            len,
+        )
+        .with_trigger(trigger);
+        self.start(&config);
+        Ok(Stream { channel: self })
+    }
+
+    /// Stream items from the peripheral register `source` into the two halves of `buffer`, like
+    /// [`Channel::start_stream`]. The length of `buffer` must be even, up to 131070.
+    ///
+    /// # Safety
+    ///
+    /// See [`Channel::start_stream`].
+    pub unsafe fn start_ring<T: Element>(
+        self,
+        source: *const T,
+        trigger: Event,
+        buffer: *mut [T],
+        on_full: fn(Half),
+    ) -> Result<Stream, Channel> {
+        let half = buffer.len() / 2;
+        let first = buffer as *mut T;
+        self.start_stream(
+            source,
+            trigger,
+            ptr::slice_from_raw_parts_mut(first, half),
+            ptr::slice_from_raw_parts_mut(first.add(half), half),
+            on_full,
+        )
+    }
+
+    /// Copy `source` into `destination` and wait until the DMAC is done.
+    ///
+    /// Panics if the slices have different lengths.
+    pub fn copy<T: Element>(&mut self, source: &[T], destination: &mut [T]) {
+        assert_eq!(source.len(), destination.len());
+        for (source, destination) in source
+            .chunks(Self::MAX_COUNT)
+            .zip(destination.chunks_mut(Self::MAX_COUNT))
+        {
+            let config = Config::normal(
+                T::SIZE,
+                source.as_ptr() as *const u8,
+                AddressMode::Increment,
+                destination.as_mut_ptr() as *mut u8,
+                AddressMode::Increment,
+                source.len() as u16,
+            );
+            unsafe {
+                self.start(&config);
+            }
+            self.wait();
+        }
+    }
+
+    /// Set all elements of `destination` to `value` and wait until the DMAC is done.
+    pub fn fill<T: Element>(&mut self, value: T, destination: &mut [T]) {
+        for destination in destination.chunks_mut(Self::MAX_COUNT) {
+            let config = Config::normal(
+                T::SIZE,
+                &value as *const T as *const u8,
+                AddressMode::Fixed,
+                destination.as_mut_ptr() as *mut u8,
+                AddressMode::Increment,
+                destination.len() as u16,
+            );
+            unsafe {
+                self.start(&config);
+            }
+            self.wait();
+        }
+    }
+
+    /// Copy `source` into `destination` in the background. Panics if the buffers have different
+    /// lengths, are empty, or are longer than 65535 items.
+    pub fn copy_owned<S, D>(mut self, source: S, mut destination: D) -> OwnedTransfer<(S, D)>
+    where
+        S: ReadBuffer,
+        D: WriteBuffer<Word = S::Word>,
+    {
+        let (from, len) = source.read_buffer();
+        let (to, destination_len) = destination.write_buffer();
+        assert_eq!(len, destination_len);
+        // A count of 0 would make the transfer run without an end.
+        assert!((1..=Self::MAX_COUNT).contains(&len));
+        let config = Config::normal(
+            S::Word::SIZE,
+            from as *const u8,
+            AddressMode::Increment,
+            to as *mut u8,
+            AddressMode::Increment,
+            len as u16,
+        );
+        unsafe {
+            self.start(&config);
+        }
+        OwnedTransfer {
+            channel: self,
+            buffers: (source, destination),
+        }
+    }
+
+    /// Fill `buffer` from the peripheral register `source` in the background, one item per
+    /// `trigger` event. Panics if the buffer is empty or longer than 65535 items.
+    ///
+    /// # Safety
+    ///
+    /// `source` must be a readable peripheral register.
+    pub unsafe fn read_owned<B: WriteBuffer>(
+        mut self,
+        source: *const B::Word,
+        trigger: Event,
+        mut buffer: B,
+    ) -> OwnedTransfer<B> {
+        let (to, len) = buffer.write_buffer();
+        // A count of 0 would make the transfer run without an end.
+        assert!((1..=Self::MAX_COUNT).contains(&len));
+        let config = Config::normal(
+            B::Word::SIZE,
+            source as *const u8,
+            AddressMode::Fixed,
+            to as *mut u8,
+            AddressMode::Increment,
+            len as u16,
+        )
+        .with_trigger(trigger);
+        self.start(&config);
+        OwnedTransfer {
+            channel: self,
+            buffers: buffer,
+        }
+    }
+
+    /// Write `buffer` to the peripheral register `destination` in the background, one item per
+    /// `trigger` event. Panics if the buffer is empty or longer than 65535 items.
+    ///
+    /// # Safety
+    ///
+    /// `destination` must be a writable peripheral register.
+    pub unsafe fn write_owned<B: ReadBuffer>(
+        mut self,
+        buffer: B,
+        destination: *mut B::Word,
+        trigger: Event,
+    ) -> OwnedTransfer<B> {
+        let (from, len) = buffer.read_buffer();
+        // A count of 0 would make the transfer run without an end.
+        assert!((1..=Self::MAX_COUNT).contains(&len));
+        let config = Config::normal(
+            B::Word::SIZE,
+            from as *const u8,
+            AddressMode::Increment,
+            destination as *mut u8,
+            AddressMode::Fixed,
+            len as u16,
+        )
+        .with_trigger(trigger);
+        self.start(&config);
+        OwnedTransfer {
+            channel: self,
+            buffers: buffer,
+        }
+    }
+}
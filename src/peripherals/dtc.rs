@@ -0,0 +1,282 @@
+//! Data Transfer Controller (DTC): Move data between memory and peripherals when an event occurs,
+//! without interrupting the CPU.
+//!
+//! The DTC is activated by events that are linked to an interrupt slot (see [`super::icu`]). When
+//! the event occurs, the DTC reads the [`TransferInfo`] of the slot from its vector table and does
+//! one transfer instead of interrupting the CPU. Once the transfer count reaches 0, the DTC is
+//! deactivated for the slot and the event interrupts the CPU as usual. This way, a driver gets one
+//! interrupt per block instead of one per byte.
+//!
+//! A [`TransferInfo`] is built with [`TransferInfo::normal`] and changed with its `with_` methods:
+//! [`TransferInfo::with_repeat`] and [`TransferInfo::with_blocks`] select the repeat and block
+//! modes, [`TransferInfo::with_interrupt_every_transfer`] keeps interrupting the CPU after each
+//! transfer. Several transfer infos in a row can be chained with [`activate_chain`], so that one
+//! event does several transfers, e.g. to read a status register and then a data register.
+//! [`activate`] and [`deactivate`] enable and disable the DTC per event.
+//!
+//! For details, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Data Transfer
+//! Controller (DTC)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::dtc::{self, AddressMode, Area, Size, TransferInfo};
+//! use arduino_uno_r4_wifi_rt::peripherals::icu::{self, Event};
+//!
+//! static mut SAMPLES: [u16; 16] = [0; 16];
+//! static mut INFO: Option<TransferInfo> = None;
+//!
+//! fn on_buffer_full() {}
+//!
+//! let slot = icu::attach(Event::Adc0ScanEnd, on_buffer_full).unwrap();
+//! unsafe {
+//!     // Copy the result of each scan into SAMPLES, and start over after 16 scans.
+//!     let info = TransferInfo::normal(
+//!         Size::HalfWord,
+//!         0x4005c020 as *const u8,
+//!         AddressMode::Fixed,
+//!         core::ptr::addr_of_mut!(SAMPLES) as *mut u8,
+//!         AddressMode::Increment,
+//!         16,
+//!     )
+//!     .with_repeat(Area::Destination);
+//!     let info = (*core::ptr::addr_of_mut!(INFO)).insert(info);
+//!     dtc::activate(slot, info);
+//! }
+//! ```
+
+use super::icu::Slot;
+use super::mstp::{self, unit};
+use crate::{interrupt, NUM_EXTERNAL_INTERRUPTS};
+
+use core::ptr;
+
+/// Size of the data moved by one transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Size {
+    Byte = 0b00,
+    HalfWord = 0b01,
+    Word = 0b10,
+}
+
+/// How an address changes after each transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddressMode {
+    /// Stay the same, e.g. for a peripheral data register.
+    Fixed = 0b00,
+    /// Increment by the transfer size.
+    Increment = 0b10,
+    /// Decrement by the transfer size.
+    Decrement = 0b11,
+}
+
+/// The address that is reset to its start after each repeat or block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Area {
+    Destination = 0,
+    Source = 1,
+}
+
+/// Transfer information read by the DTC for each transfer. The DTC writes the updated addresses and
+/// count back after each transfer, so this must be in RAM and stay in place while the transfer is
+/// active.
+#[repr(C)]
+pub struct TransferInfo {
+    /// Mode Register A in b24-b31 and Mode Register B in b16-b23. The lower bits are reserved.
+    /// * MRA b2-b3: SM, source address mode.
+    /// * MRA b4-b5: SZ, transfer size.
+    /// * MRA b6-b7: MD, 00 for normal, 01 for repeat and 10 for block mode.
+    /// * MRB b2-b3: DM, destination address mode.
+    /// * MRB b4: DTS, the repeat or block area is the source instead of the destination.
+    /// * MRB b5: DISEL, interrupt the CPU after every transfer instead of only the last one.
+    /// * MRB b6: CHNS, chain only when the count reaches 0.
+    /// * MRB b7: CHNE, chain to the next transfer info in memory.
+    mode: u32,
+    /// Source Address Register.
+    source: u32,
+    /// Destination Address Register.
+    destination: u32,
+    /// Transfer Count Register B in b0-b15 (unused in normal mode) and Transfer Count Register A
+    /// in b16-b31. In normal mode, CRA counts the remaining transfers, 0 means 65536. In repeat
+    /// and block mode, CRAL (b16-b23) counts the transfers of the repeat or block and is reloaded
+    /// from CRAH (b24-b31), and CRB counts the blocks.
+    counts: u32,
+}
+
+impl TransferInfo {
+    /// Transfer information for normal mode: Move `count` items of `size` from `source` to
+    /// `destination`, updating the addresses after each transfer as given by the address modes.
+    pub fn normal(
+        size: Size,
+        source: *const u8,
+        source_mode: AddressMode,
+        destination: *mut u8,
+        destination_mode: AddressMode,
+        count: u16,
+    ) -> Self {
+        let mra = ((size as u32) << 4) | ((source_mode as u32) << 2);
+        let mrb = (destination_mode as u32) << 2;
+        Self {
+            mode: (mra << 24) | (mrb << 16),
+            source: source as u32,
+            destination: destination as u32,
+            counts: (count as u32) << 16,
+        }
+    }
+
+    const MRA_MD_REPEAT: u32 = 0b01 << 30;
+    const MRA_MD_BLOCK: u32 = 0b10 << 30;
+    const MRB_DTS: u32 = 1 << 20;
+    const MRB_DISEL: u32 = 1 << 21;
+    const MRB_CHNS: u32 = 1 << 22;
+    const MRB_CHNE: u32 = 1 << 23;
+
+    /// Repeat mode: After `count` transfers (1 to 256), reset the address of `area` and start
+    /// over. The DTC stays active for the event until it is deactivated.
+    pub fn with_repeat(self, area: Area) -> Self {
+        let count = self.counts >> 16;
+        Self {
+            mode: self.mode | Self::MRA_MD_REPEAT | Self::area_bit(area),
+            counts: Self::reloading_count(count),
+            ..self
+        }
+    }
+
+    /// Block mode: Each event moves a block of `count` items (1 to 256), then the address of
+    /// `area` is reset. This is done for `blocks` events, 0 means 65536.
+    pub fn with_blocks(self, area: Area, blocks: u16) -> Self {
+        let count = self.counts >> 16;
+        Self {
+            mode: self.mode | Self::MRA_MD_BLOCK | Self::area_bit(area),
+            counts: Self::reloading_count(count) | blocks as u32,
+            ..self
+        }
+    }
+
+    /// Interrupt the CPU after every transfer, not only after the last one.
+    pub fn with_interrupt_every_transfer(self) -> Self {
+        Self {
+            mode: self.mode | Self::MRB_DISEL,
+            ..self
+        }
+    }
+
+    /// In a chain, only continue with the next transfer info once the count of this one reaches
+    /// 0, instead of after every transfer.
+    pub fn with_chain_at_end(self) -> Self {
+        Self {
+            mode: self.mode | Self::MRB_CHNS,
+            ..self
+        }
+    }
+
+    fn area_bit(area: Area) -> u32 {
+        match area {
+            Area::Destination => 0,
+            Area::Source => Self::MRB_DTS,
+        }
+    }
+
+    /// CRA for repeat and block mode, with the same count in CRAH and CRAL. 256 is written as 0.
+    fn reloading_count(count: u32) -> u32 {
+        let count = count & 0xff;
+        (count << 24) | (count << 16)
+    }
+
+    /// Returns the number of transfers that haven't been done yet. In repeat and block mode, the
+    /// number of transfers left in the current repeat or block.
+    pub fn remaining(&self) -> u16 {
+        let counts = unsafe { ptr::addr_of!(self.counts).read_volatile() };
+        if self.mode & (Self::MRA_MD_REPEAT | Self::MRA_MD_BLOCK) == 0 {
+            (counts >> 16) as u16
+        } else {
+            ((counts >> 16) & 0xff) as u16
+        }
+    }
+}
+
+/// The DTC vector table: For each interrupt slot, the address of its [`TransferInfo`]. The lower
+/// 10 bits of the table address must be 0.
+#[repr(C, align(1024))]
+struct VectorTable([u32; NUM_EXTERNAL_INTERRUPTS]);
+
+static mut VECTOR_TABLE: VectorTable = VectorTable([0; NUM_EXTERNAL_INTERRUPTS]);
+
+struct Dtc;
+
+impl Dtc {
+    /// DTC Vector Base Register. Can only be written while the DTC is stopped.
+    const DTCVBR: *mut u32 = 0x40005404 as *mut u32;
+
+    /// DTC Module Start Register. Bit 0 starts the DTC.
+    const DTCST: *mut u8 = 0x4000540c as *mut u8;
+
+    /// Point the DTC to our vector table and start it, unless this was done already. Its clock
+    /// keeps running from then on.
+    fn start() {
+        unsafe {
+            if Self::DTCST.read_volatile() & 1 == 0 {
+                core::mem::forget(mstp::token::<unit::DmacDtc>());
+                Self::DTCVBR.write_volatile(ptr::addr_of!(VECTOR_TABLE) as u32);
+                Self::DTCST.write_volatile(1);
+            }
+        }
+    }
+}
+
+/// Let the event linked to `slot` activate the DTC with `info` instead of interrupting the CPU,
+/// until `info` runs out of transfers.
+///
+/// # Safety
+///
+/// `info` and the memory it refers to must stay valid until the transfer is done, or until
+/// [`deactivate`] is called.
+pub unsafe fn activate(slot: Slot, info: *mut TransferInfo) {
+    Dtc::start();
+    interrupt::free(|| {
+        ptr::addr_of_mut!(VECTOR_TABLE.0[slot.number() as usize]).write_volatile(info as u32);
+        slot.set_dtc_activation(true);
+    });
+}
+
+/// Let the event linked to `slot` activate the DTC with the transfer infos in `chain`, one after
+/// the other, see [`TransferInfo::with_chain_at_end`]. The DTC is deactivated once the last one
+/// runs out of transfers.
+///
+/// # Panics
+///
+/// Panics if `chain` is empty.
+///
+/// # Safety
+///
+/// `chain` and the memory it refers to must stay valid until the transfer is done, or until
+/// [`deactivate`] is called.
+pub unsafe fn activate_chain(slot: Slot, chain: *mut [TransferInfo]) {
+    let len = chain.len();
+    assert!(len > 0, "empty DTC chain");
+    let first = chain as *mut TransferInfo;
+    for i in 0..len {
+        let mode = ptr::addr_of_mut!((*first.add(i)).mode);
+        if i + 1 < len {
+            mode.write_volatile(mode.read_volatile() | TransferInfo::MRB_CHNE);
+        } else {
+            mode.write_volatile(mode.read_volatile() & !TransferInfo::MRB_CHNE);
+        }
+    }
+    activate(slot, first);
+}
+
+/// Stop the event linked to `slot` from activating the DTC. Transfers that haven't been done yet
+/// are cancelled.
+pub fn deactivate(slot: Slot) {
+    slot.set_dtc_activation(false);
+}
+
+/// Returns true if the event linked to `slot` still activates the DTC, i.e. the transfer isn't
+/// done yet.
+pub fn is_active(slot: Slot) -> bool {
+    slot.dtc_activation()
+}
@@ -0,0 +1,136 @@
+//! Event Link Controller (ELC): Let an event of one peripheral trigger another peripheral,
+//! without the CPU.
+//!
+//! The ELC uses the same event numbers as the ICU (see [`Event`]). [`link`] routes an event to one
+//! of the [`Peripheral`] inputs, e.g. a GPT compare match to the start of an ADC scan, or an IRQ
+//! pin to a GPT input capture. The receiving peripheral still has to be set up to react to its
+//! ELC input, e.g. the GPT through its GTSSR/GTCSR registers, or the ADC through ADSTRGR.
+//! [`unlink`] removes the route again.
+//!
+//! [`link`] takes the clock of the ELC, which keeps running while at least one link exists.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Event
+//! Link Controller (ELC)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::elc::{self, Peripheral};
+//! use arduino_uno_r4_wifi_rt::peripherals::icu::Event;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//!
+//! // Start an ADC scan on every compare match A of GPT0.
+//! let link = elc::link(mstp::token(), Event::Gpt0CompareA, Peripheral::Adc0).unwrap();
+//! // ...
+//! elc::unlink(link);
+//! ```
+
+use super::icu::Event;
+use super::mstp::{unit, MstpToken};
+use crate::interrupt;
+
+use core::ptr;
+
+/// Event Link Controller Register.
+/// * b7: ELCON, enables the links.
+const ELCR: *mut u8 = 0x40041000 as *mut u8;
+
+const ELCR_ELCON: u8 = 1 << 7;
+
+/// Base address of the Event Link Setting Registers, one u16 every 4 bytes per [`Peripheral`].
+/// * b0-b8: ELS, the event number, 0 if nothing is linked.
+const ELSR_BASE: u32 = 0x40041010;
+
+/// An input of a peripheral that an event can be linked to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Peripheral {
+    /// ELC event A of the GPT, which the channels can use to start, stop, clear or capture.
+    GptA = 0,
+    /// ELC event B of the GPT.
+    GptB = 1,
+    /// ELC event C of the GPT.
+    GptC = 2,
+    /// ELC event D of the GPT.
+    GptD = 3,
+    /// Start a scan of ADC0.
+    Adc0 = 8,
+    /// Start a scan of group B of ADC0.
+    Adc0GroupB = 9,
+    /// Start a conversion of the 12-bit DAC.
+    Dac12 = 12,
+    /// Output or input event of port 1.
+    Port1 = 14,
+    /// Output or input event of port 2.
+    Port2 = 15,
+    /// Output or input event of port 3.
+    Port3 = 16,
+    /// Output or input event of port 4.
+    Port4 = 17,
+    /// Start a measurement of the capacitive touch sensing unit.
+    Ctsu = 18,
+}
+
+impl Peripheral {
+    /// Event Link Setting Register of the peripheral.
+    fn elsr(self) -> *mut u16 {
+        (ELSR_BASE + 4 * self as u32) as *mut u16
+    }
+}
+
+/// A link from an event to a peripheral, see [`link`].
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Link {
+    event: Event,
+    peripheral: Peripheral,
+}
+
+impl Link {
+    /// Returns the linked event.
+    pub fn event(&self) -> Event {
+        self.event
+    }
+
+    /// Returns the peripheral that the event triggers.
+    pub fn peripheral(&self) -> Peripheral {
+        self.peripheral
+    }
+}
+
+/// Number of links, and the clock of the ELC while there are any.
+static mut LINKS: u8 = 0;
+static mut CLOCK: Option<MstpToken<unit::Elc>> = None;
+
+/// Let `event` trigger `peripheral`.
+///
+/// Returns `None` if another event is linked to `peripheral` already.
+pub fn link(clock: MstpToken<unit::Elc>, event: Event, peripheral: Peripheral) -> Option<Link> {
+    interrupt::free(|| unsafe {
+        if LINKS == 0 {
+            *ptr::addr_of_mut!(CLOCK) = Some(clock);
+            ELCR.write_volatile(ELCR_ELCON);
+        }
+        if peripheral.elsr().read_volatile() & 0x1ff != 0 {
+            if LINKS == 0 {
+                *ptr::addr_of_mut!(CLOCK) = None;
+            }
+            return None;
+        }
+        peripheral.elsr().write_volatile(event as u16);
+        LINKS += 1;
+        Some(Link { event, peripheral })
+    })
+}
+
+/// Remove `link`. The clock of the ELC is stopped after the last link is removed.
+pub fn unlink(link: Link) {
+    interrupt::free(|| unsafe {
+        link.peripheral.elsr().write_volatile(0);
+        LINKS -= 1;
+        if LINKS == 0 {
+            ELCR.write_volatile(0);
+            *ptr::addr_of_mut!(CLOCK) = None;
+        }
+    });
+}
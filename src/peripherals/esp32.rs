@@ -0,0 +1,519 @@
+//! Serial link to the ESP32-S3, the WiFi and Bluetooth module of the board.
+//!
+//! The ESP32-S3 is connected to the SCI9 unit of the RA4M1 through pins P109 (TXD9) and P110
+//! (RXD9), which aren't on the headers. It runs the ESP-AT firmware, which talks at 115200 baud
+//! after a reset and takes AT commands.
+//!
+//! [`Esp32`] owns the unit and the pins and exposes the link as a raw byte channel: [`Esp32::write`]
+//! busy-waits until the bytes are handed to the unit, received bytes are collected by an interrupt
+//! handler in a buffer of [`RX_BUFFER_SIZE`] bytes, from which [`Esp32::read`] takes them without
+//! waiting. If the buffer is full, further bytes are dropped and [`Esp32::take_overrun`] reports it.
+//!
+//! With the `embedded-io-async` feature, [`Esp32`] implements `embedded_io_async::Read` and
+//! `Write`. Reads sleep until the receive interrupt brings a byte, writes still busy-wait.
+//!
+//! No reset or boot pin of the ESP32-S3 is connected to the RA4M1, so [`Esp32::reset`] restarts it
+//! with the `AT+RST` command and waits for its `ready` message. [`Esp32::set_baud_rate`] switches
+//! both sides to another bit rate with `AT+UART_CUR`, until the next reset.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Serial
+//! Communications Interface (SCI)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::esp32::Esp32;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let mut esp32 = Esp32::new(pins.p109, pins.p110, mstp::token(), &clocks).unwrap();
+//! esp32.reset().unwrap();
+//! esp32.set_baud_rate(921_600).unwrap();
+//! esp32.write(b"AT\r\n");
+//! esp32.wait_for(b"OK\r\n", 100).unwrap();
+//! ```
+
+use super::clocks::{self, ClockDependent, Clocks};
+use super::icu::{self, Event, Slot};
+use super::mstp::{unit, MstpToken};
+use super::pins::{Pin, PinMode, PinModePeripheral, PinModeUnknown, P109, P110};
+use super::registers::VolatileBoolOps;
+use crate::interrupt::{self, WakerCell};
+
+use core::fmt::Write;
+use core::ptr;
+
+/// Serial Mode Register.
+/// * b0-b1: CKS, the clock of the baud rate generator, PCLKA / 4^CKS.
+/// * b7: CM, 0: asynchronous mode. The other bits 0 select 8 data bits, no parity, 1 stop bit.
+const SMR: *mut u8 = 0x40070120 as *mut u8;
+
+/// Bit Rate Register.
+const BRR: *mut u8 = 0x40070121 as *mut u8;
+
+/// Serial Control Register.
+/// * b4: RE, enables receiving.
+/// * b5: TE, enables transmitting.
+/// * b6: RIE, enables the RXI and ERI interrupts.
+const SCR: *mut u8 = 0x40070122 as *mut u8;
+
+/// Transmit Data Register.
+const TDR: *mut u8 = 0x40070123 as *mut u8;
+
+/// Serial Status Register.
+/// * b2: TEND, the last byte has been sent.
+/// * b3: PER, parity error.
+/// * b4: FER, framing error.
+/// * b5: ORER, overrun error.
+/// * b7: TDRE, TDR can take the next byte.
+///
+/// The error flags are cleared by writing 0 after reading 1.
+const SSR: *mut u8 = 0x40070124 as *mut u8;
+
+/// Receive Data Register.
+const RDR: *const u8 = 0x40070125 as *const u8;
+
+/// Smart Card Mode Register. 0xf2, the reset value, selects the UART with LSB first.
+const SCMR: *mut u8 = 0x40070126 as *mut u8;
+
+/// Serial Extended Mode Register.
+/// * b2: BRME, enables the bit rate modulation with MDDR.
+/// * b4: ABCS, 8 clock cycles per bit instead of 16.
+/// * b6: BGDM, doubles the clock of the baud rate generator.
+const SEMR: *mut u8 = 0x40070127 as *mut u8;
+
+/// Modulation Duty Register. With BRME, the bit rate is multiplied by MDDR / 256.
+const MDDR: *mut u8 = 0x40070132 as *mut u8;
+
+const SCR_RE: u8 = 1 << 4;
+const SCR_TE: u8 = 1 << 5;
+const SCR_RIE: u8 = 1 << 6;
+const SSR_TEND: u8 = 1 << 2;
+const SSR_PER: u8 = 1 << 3;
+const SSR_FER: u8 = 1 << 4;
+const SSR_ORER: u8 = 1 << 5;
+const SSR_TDRE: u8 = 1 << 7;
+const SEMR_BRME: u8 = 1 << 2;
+const SEMR_ABCS: u8 = 1 << 4;
+const SEMR_BGDM: u8 = 1 << 6;
+
+/// Peripheral function 5 is SCI1, 3, 5, 7 and 9.
+const PSEL_SCI: u32 = 0b00101;
+
+/// Bit rate of the ESP-AT firmware after a reset.
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+/// Size of the buffer for received bytes.
+pub const RX_BUFFER_SIZE: usize = 1024;
+
+/// Time for the ESP32-S3 to restart after `AT+RST`, in ms.
+const RESET_TIMEOUT_MS: u32 = 5000;
+
+/// Time for the ESP32-S3 to answer a command, in ms.
+const COMMAND_TIMEOUT_MS: u32 = 1000;
+
+/// Errors of the link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// There are no free interrupt slots for the receive interrupts.
+    NoFreeSlot,
+    /// The ESP32-S3 didn't answer in time.
+    Timeout,
+    /// The bit rate can't be generated from PCLKA.
+    UnsupportedBaudRate,
+}
+
+/// Received bytes that haven't been read yet, filled by an interrupt handler. Also used by the
+/// serial port of [`crate::arduino`].
+pub(crate) struct RxBuffer<const N: usize> {
+    data: [u8; N],
+    start: usize,
+    len: usize,
+    overrun: bool,
+}
+
+impl<const N: usize> RxBuffer<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            data: [0; N],
+            start: 0,
+            len: 0,
+            overrun: false,
+        }
+    }
+
+    pub(crate) fn push(&mut self, byte: u8) {
+        if self.len == N {
+            self.overrun = true;
+        } else {
+            self.data[(self.start + self.len) % N] = byte;
+            self.len += 1;
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.start = (self.start + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    /// Returns the next byte without taking it.
+    pub(crate) fn peek(&self) -> Option<u8> {
+        (self.len > 0).then(|| self.data[self.start])
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Drop all bytes.
+    pub(crate) fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+}
+
+static mut RX_BUFFER: RxBuffer<RX_BUFFER_SIZE> = RxBuffer::new();
+
+/// Woken when a byte is received.
+static RX_WAKER: WakerCell = WakerCell::new();
+
+/// Run `f` on the receive buffer, with interrupts disabled.
+fn with_rx_buffer<R>(f: impl FnOnce(&mut RxBuffer<RX_BUFFER_SIZE>) -> R) -> R {
+    interrupt::free(|| unsafe { f(&mut *ptr::addr_of_mut!(RX_BUFFER)) })
+}
+
+/// Interrupt handler for RXI: Move the received byte to the buffer.
+fn on_receive() {
+    let byte = unsafe { RDR.read_volatile() };
+    with_rx_buffer(|buffer| buffer.push(byte));
+    RX_WAKER.wake();
+}
+
+/// Interrupt handler for ERI: Drop the byte and clear the error, which stops the reception until
+/// it is cleared. A byte lost to an overrun counts as an overrun of the buffer.
+fn on_error() {
+    unsafe {
+        let status = SSR.read_volatile();
+        RDR.read_volatile();
+        if status & SSR_ORER != 0 {
+            with_rx_buffer(|buffer| buffer.overrun = true);
+        }
+        SSR.volatile_and(!(SSR_ORER | SSR_FER | SSR_PER));
+    }
+}
+
+/// Returns CKS, BRR and MDDR (`None` without modulation) for `baud_rate` with `pclka`.
+///
+/// With ABCS and BGDM, the bit rate is `pclka / (8 * 4^CKS * (BRR + 1)) * MDDR / 256`. We take
+/// the fastest clock for which BRR fits, round BRR + 1 down, and let the modulation take the bit
+/// rate down to the exact value.
+pub(crate) fn bit_rate_settings(pclka: u32, baud_rate: u32) -> Option<(u8, u8, Option<u8>)> {
+    if baud_rate == 0 {
+        return None;
+    }
+    for cks in 0..4 {
+        let divider = 8u64 * baud_rate as u64 * (1 << (2 * cks));
+        let counts = pclka as u64 / divider;
+        if counts == 0 {
+            return None;
+        }
+        if counts <= 256 {
+            let mddr = (256 * divider * counts + pclka as u64 / 2) / pclka as u64;
+            return Some((cks, (counts - 1) as u8, (mddr < 256).then_some(mddr as u8)));
+        }
+    }
+    None
+}
+
+/// The link to the ESP32-S3 over SCI9.
+pub struct Esp32 {
+    tx: P109<PinModePeripheral>,
+    rx: P110<PinModePeripheral>,
+    /// Slots of the RXI and ERI interrupts.
+    slots: [Slot; 2],
+    baud_rate: u32,
+    pclka: u32,
+    iclk: u32,
+    _clock: MstpToken<unit::Sci9>,
+}
+
+impl Esp32 {
+    /// Set up SCI9 on pins P109 and P110 at [`DEFAULT_BAUD_RATE`], and start receiving.
+    pub fn new<M1: PinMode, M2: PinMode>(
+        tx: P109<M1>,
+        rx: P110<M2>,
+        clock: MstpToken<unit::Sci9>,
+        clocks: &Clocks,
+    ) -> Result<Self, Error> {
+        let settings = bit_rate_settings(clocks.pclka(), DEFAULT_BAUD_RATE)
+            .ok_or(Error::UnsupportedBaudRate)?;
+        let receive = icu::attach(Event::Sci9Rxi, on_receive).ok_or(Error::NoFreeSlot)?;
+        let Some(error) = icu::attach(Event::Sci9Eri, on_error) else {
+            icu::detach(receive);
+            return Err(Error::NoFreeSlot);
+        };
+        let tx = tx.into_peripheral(PSEL_SCI, 0);
+        let rx = rx.into_peripheral(PSEL_SCI, 0);
+        with_rx_buffer(|buffer| {
+            buffer.clear();
+            buffer.overrun = false;
+        });
+        let mut esp32 = Self {
+            tx,
+            rx,
+            slots: [receive, error],
+            baud_rate: DEFAULT_BAUD_RATE,
+            pclka: clocks.pclka(),
+            iclk: clocks.iclk(),
+            _clock: clock,
+        };
+        unsafe {
+            SCMR.write_volatile(0xf2);
+        }
+        esp32.configure(settings);
+        Ok(esp32)
+    }
+
+    /// Stop the unit and program the bit rate `settings`, then start it again.
+    fn configure(&mut self, (cks, brr, mddr): (u8, u8, Option<u8>)) {
+        unsafe {
+            SCR.write_volatile(0);
+            SMR.write_volatile(cks);
+            BRR.write_volatile(brr);
+            match mddr {
+                Some(mddr) => {
+                    MDDR.write_volatile(mddr);
+                    SEMR.write_volatile(SEMR_ABCS | SEMR_BGDM | SEMR_BRME);
+                }
+                None => SEMR.write_volatile(SEMR_ABCS | SEMR_BGDM),
+            }
+            SSR.volatile_and(!(SSR_ORER | SSR_FER | SSR_PER));
+        }
+        // The unit needs one bit period before it is enabled.
+        clocks::wait_us(self.iclk, 1_000_000 / self.baud_rate + 1);
+        unsafe {
+            SCR.write_volatile(SCR_TE | SCR_RE | SCR_RIE);
+        }
+    }
+
+    /// Returns the current bit rate.
+    pub fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    /// Send `data`, waiting until the last byte is handed to the unit.
+    pub fn write(&mut self, data: &[u8]) {
+        for &byte in data {
+            unsafe {
+                while SSR.read_volatile() & SSR_TDRE == 0 {}
+                TDR.write_volatile(byte);
+            }
+        }
+    }
+
+    /// Wait until the last byte has left the pin.
+    pub fn flush(&mut self) {
+        unsafe { while SSR.read_volatile() & SSR_TEND == 0 {} }
+    }
+
+    /// Move received bytes to `buffer` and return how many, 0 if nothing has been received.
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        with_rx_buffer(|rx_buffer| {
+            let mut count = 0;
+            for slot in buffer.iter_mut() {
+                let Some(byte) = rx_buffer.pop() else {
+                    break;
+                };
+                *slot = byte;
+                count += 1;
+            }
+            count
+        })
+    }
+
+    /// Returns the next received byte, if there is one.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        with_rx_buffer(RxBuffer::pop)
+    }
+
+    /// Returns the number of received bytes that haven't been read.
+    pub fn available(&self) -> usize {
+        with_rx_buffer(|buffer| buffer.len())
+    }
+
+    /// Drop all received bytes that haven't been read.
+    pub fn clear(&mut self) {
+        with_rx_buffer(RxBuffer::clear);
+    }
+
+    /// Returns true if received bytes were dropped since the last call, because the buffer was
+    /// full or they weren't taken from the unit in time.
+    pub fn take_overrun(&mut self) -> bool {
+        with_rx_buffer(|buffer| core::mem::replace(&mut buffer.overrun, false))
+    }
+
+    /// Read and drop received bytes until `pattern` has been received, for up to `timeout_ms` ms.
+    pub fn wait_for(&mut self, pattern: &[u8], timeout_ms: u32) -> Result<(), Error> {
+        if pattern.is_empty() {
+            return Ok(());
+        }
+        let mut matched = 0;
+        for _ in 0..=timeout_ms.saturating_mul(10) {
+            while let Some(byte) = self.read_byte() {
+                if byte == pattern[matched] {
+                    matched += 1;
+                } else {
+                    matched = usize::from(byte == pattern[0]);
+                }
+                if matched == pattern.len() {
+                    return Ok(());
+                }
+            }
+            clocks::wait_us(self.iclk, 100);
+        }
+        log_error!("ESP32: timeout");
+        Err(Error::Timeout)
+    }
+
+    /// Restart the ESP32-S3 and wait until its firmware is ready. This also switches back to
+    /// [`DEFAULT_BAUD_RATE`].
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.write(b"AT+RST\r\n");
+        self.flush();
+        if self.baud_rate != DEFAULT_BAUD_RATE {
+            let settings = bit_rate_settings(self.pclka, DEFAULT_BAUD_RATE)
+                .ok_or(Error::UnsupportedBaudRate)?;
+            self.baud_rate = DEFAULT_BAUD_RATE;
+            self.configure(settings);
+        }
+        self.clear();
+        self.wait_for(b"ready\r\n", RESET_TIMEOUT_MS)
+    }
+
+    /// Switch the ESP32-S3 and the link to `baud_rate`, until the next reset.
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<(), Error> {
+        let settings =
+            bit_rate_settings(self.pclka, baud_rate).ok_or(Error::UnsupportedBaudRate)?;
+        let mut command = CommandBuffer::new();
+        // The buffer is large enough for any u32.
+        let _ = write!(command, "AT+UART_CUR={},8,1,0,0\r\n", baud_rate);
+        self.clear();
+        self.write(command.as_bytes());
+        // The firmware answers at the old rate, then switches.
+        self.wait_for(b"OK\r\n", COMMAND_TIMEOUT_MS)?;
+        self.flush();
+        self.baud_rate = baud_rate;
+        self.configure(settings);
+        Ok(())
+    }
+
+    /// Switch only the link to `baud_rate`, for when the ESP32-S3 is told to switch by someone
+    /// else, e.g. by esptool through [`crate::peripherals::usb::bridge`].
+    pub fn set_link_baud_rate(&mut self, baud_rate: u32) -> Result<(), Error> {
+        let settings =
+            bit_rate_settings(self.pclka, baud_rate).ok_or(Error::UnsupportedBaudRate)?;
+        self.flush();
+        self.baud_rate = baud_rate;
+        self.configure(settings);
+        Ok(())
+    }
+
+    /// Stop the unit and return the pins.
+    pub fn release(self) -> (P109<PinModeUnknown>, P110<PinModeUnknown>) {
+        unsafe {
+            SCR.write_volatile(0);
+        }
+        self.slots.into_iter().for_each(icu::detach);
+        (self.tx.into_unknown(), self.rx.into_unknown())
+    }
+}
+
+impl ClockDependent for Esp32 {
+    fn set_clocks(&mut self, clocks: &Clocks) {
+        self.pclka = clocks.pclka();
+        self.iclk = clocks.iclk();
+        // The bit rate was generated from the old clock, so it can be generated from the new one
+        // unless PCLKA got much slower.
+        if let Some(settings) = bit_rate_settings(self.pclka, self.baud_rate) {
+            self.configure(settings);
+        }
+    }
+}
+
+/// A short command line, formatted on the stack.
+struct CommandBuffer {
+    data: [u8; 32],
+    len: usize,
+}
+
+impl CommandBuffer {
+    fn new() -> Self {
+        Self {
+            data: [0; 32],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl Write for CommandBuffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+        if end > self.data.len() {
+            return Err(core::fmt::Error);
+        }
+        self.data[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+mod embedded_io_async_impl {
+    use super::{Esp32, RX_WAKER};
+    use core::convert::Infallible;
+    use core::future::poll_fn;
+    use core::task::Poll;
+    use embedded_io_async::{ErrorType, Read, Write};
+
+    impl ErrorType for Esp32 {
+        type Error = Infallible;
+    }
+
+    impl Read for Esp32 {
+        async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Infallible> {
+            if buffer.is_empty() {
+                return Ok(0);
+            }
+            poll_fn(|cx| {
+                RX_WAKER.register(cx.waker());
+                match Esp32::read(self, buffer) {
+                    0 => Poll::Pending,
+                    count => Poll::Ready(Ok(count)),
+                }
+            })
+            .await
+        }
+    }
+
+    impl Write for Esp32 {
+        async fn write(&mut self, data: &[u8]) -> Result<usize, Infallible> {
+            Esp32::write(self, data);
+            Ok(data.len())
+        }
+
+        async fn flush(&mut self) -> Result<(), Infallible> {
+            Esp32::flush(self);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,989 @@
+//! Flash memory: Program and erase the 8 KB data flash, which keeps settings through resets and
+//! power cycles, and the 256 KB code flash, e.g. to update the firmware.
+//!
+//! The data flash is mapped at [`DATA_FLASH_START`], and divided into 8 blocks of
+//! [`DATA_FLASH_BLOCK_SIZE`] bytes. A block can only be erased as a whole, and bytes can only be
+//! programmed once after that. [`DataFlash`] takes offsets into the data flash, and checks that
+//! they are in range and aligned to the blocks where needed.
+//!
+//! Erased flash doesn't necessarily read as 0xff, so [`DataFlash::blank_check`] is the reliable way
+//! to tell if an area is erased and can be programmed. Each block endures 100 000 erase cycles, so
+//! spread frequent writes over the blocks.
+//!
+//! The driver starts in [`Blocking`] mode, where every command busy-waits for the flash.
+//! [`DataFlash::into_async`] links the flash ready interrupt to an interrupt slot (see
+//! [`super::icu`]) and returns a driver with `async` functions, which sleep until each command is
+//! done instead. Erasing a block takes a few ms. The data flash can be read while the CPU runs
+//! from the code flash, but not while it is programmed or erased.
+//!
+//! [`CodeFlash`] programs the code flash, in blocks of [`CODE_FLASH_BLOCK_SIZE`] bytes and units
+//! of [`CODE_FLASH_WRITE_SIZE`] bytes, e.g. to store a new firmware image received over the
+//! network in the upper half and check it before [`CodeFlash::copy_and_reset`] copies it over the
+//! running one. The code flash can't be read while it is programmed or erased, so the commands run
+//! from RAM with interrupts disabled, which blocks interrupts for up to a few ms per erase block.
+//! The first 16 KB hold the Arduino bootloader and can't be erased.
+//!
+//! With the `embedded-storage` feature, the blocking [`DataFlash`] and [`CodeFlash`] implement
+//! `embedded_storage::nor_flash::NorFlash`, for crates like `sequential-storage`.
+//!
+//! With the `usb-device` feature, the data flash implements [`super::usb::msc::BlockDevice`], so it
+//! can show up as a tiny drive on the host.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Flash
+//! Memory".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::flash::DataFlash;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let mut flash = DataFlash::take(&clocks).unwrap();
+//! let mut boot_count = [0; 4];
+//! flash.read(0, &mut boot_count).unwrap();
+//! let boot_count = if flash.blank_check(0, 4).unwrap() {
+//!     0
+//! } else {
+//!     u32::from_le_bytes(boot_count) + 1
+//! };
+//! flash.erase(0, 1024).unwrap();
+//! flash.program(0, &boot_count.to_le_bytes()).unwrap();
+//! ```
+//!
+//! Example, storing a firmware image in the upper half of the code flash:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::flash::{CodeFlash, CODE_FLASH_SIZE};
+//!
+//! const SLOT: u32 = CODE_FLASH_SIZE / 2;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let mut flash = CodeFlash::take(&clocks).unwrap();
+//! flash.erase(SLOT, CODE_FLASH_SIZE).unwrap();
+//! // Program the image as it arrives, in pieces of a multiple of 8 bytes.
+//! let piece = [0u8; 256];
+//! flash.program(SLOT, &piece).unwrap();
+//! ```
+
+use super::clocks::{self, Clocks};
+use super::icu::{self, Event, Slot};
+use crate::interrupt::{self, block_on, WakerCell};
+
+use core::convert::Infallible;
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::task::Poll;
+
+/// Address of the data flash in the memory map.
+pub const DATA_FLASH_START: u32 = 0x40100000;
+
+/// Size of the data flash.
+pub const DATA_FLASH_SIZE: u32 = 8 * 1024;
+
+/// Size of the erase blocks of the data flash.
+pub const DATA_FLASH_BLOCK_SIZE: u32 = 1024;
+
+/// Size of the code flash, which starts at address 0.
+pub const CODE_FLASH_SIZE: u32 = 256 * 1024;
+
+/// Size of the erase blocks of the code flash.
+pub const CODE_FLASH_BLOCK_SIZE: u32 = 2048;
+
+/// The code flash is programmed in units of 8 bytes.
+pub const CODE_FLASH_WRITE_SIZE: u32 = 8;
+
+/// Size of the Arduino bootloader at the start of the code flash.
+const BOOTLOADER_SIZE: u32 = 0x4000;
+
+/// Address of the data flash for the program and erase commands.
+const DATA_FLASH_PE_START: u32 = 0xfe000000;
+
+/// Slowest FCLK at which the flash can be programmed and erased.
+const FCLK_MIN_HZ: u32 = 4_000_000;
+
+/// Time for the flash to enter and leave program/erase mode, in microseconds.
+const MODE_CHANGE_WAIT_US: u32 = 15;
+
+/// Data Flash Control Register. b0: DFLEN, enable access to the data flash.
+const DFLCTL: *mut u8 = 0x407ec090 as *mut u8;
+
+/// Flash P/E Mode Control Register. Written with the sequence 0xa5 to FPR, then the value, its
+/// complement and the value again.
+/// * b1: FMS0, b4: FMS1, b7: FMS2, the mode. 0x10 for programming the data flash.
+/// * b3: RPDIS, disable programming of the code flash. Set for reading.
+const FPMCR: *mut u8 = 0x407ec100 as *mut u8;
+
+/// Flash Area Select Register. b0: EXS, select the extra area instead of the code or data flash.
+const FASR: *mut u8 = 0x407ec104 as *mut u8;
+
+/// Flash Processing Start Address Registers, the low and high half words.
+const FSARL: *mut u16 = 0x407ec108 as *mut u16;
+const FSARH: *mut u16 = 0x407ec110 as *mut u16;
+
+/// Flash Control Register.
+/// * b0-b3: CMD, the command, see [`Command`].
+/// * b7: OPST, start the command. Must be cleared after FRDY is set.
+const FCR: *mut u8 = 0x407ec114 as *mut u8;
+
+/// Flash Processing End Address Registers, the low and high half words.
+const FEARL: *mut u16 = 0x407ec118 as *mut u16;
+const FEARH: *mut u16 = 0x407ec120 as *mut u16;
+
+/// Flash Reset Register. b0: FRESET, reset the flash sequencer.
+const FRESETR: *mut u8 = 0x407ec124 as *mut u8;
+
+/// Flash Status Register 1. b6: FRDY, the command is done.
+const FSTATR1: *const u8 = 0x407ec12c as *const u8;
+
+/// Flash Write Buffer Registers. The data flash takes a byte in FWBL0, the code flash 8 bytes in
+/// FWBL0, FWBH0, FWBL1 and FWBH1, from low to high.
+const FWBL0: *mut u16 = 0x407ec130 as *mut u16;
+const FWBH0: *mut u16 = 0x407ec138 as *mut u16;
+const FWBL1: *mut u16 = 0x407ec140 as *mut u16;
+const FWBH1: *mut u16 = 0x407ec144 as *mut u16;
+
+/// Protection Unlock Register, see FPMCR.
+const FPR: *mut u8 = 0x407ec180 as *mut u8;
+
+/// Flash Ready Interrupt Enable Register. b0: FRDYIE, raise the interrupt when FRDY is set.
+const FRDYIE: *mut u8 = 0x407ec190 as *mut u8;
+
+/// Flash Internal Clock Frequency Setting Register. b0-b4: PCKA, FCLK in MHz minus 1.
+const FISR: *mut u8 = 0x407ec1d8 as *mut u8;
+
+/// Flash Status Register 2. Error flags of the last command.
+/// * b0: ERERR, erase error.
+/// * b1: PRGERR, program error.
+/// * b3: BCERR, the blank check found programmed bytes.
+/// * b4: ILGLERR, illegal command.
+const FSTATR2: *const u16 = 0x407ec1f0 as *const u16;
+
+/// Flash P/E Mode Entry Register. Written with 0xaa in b8-b15.
+/// * b0: FENTRY0, program/erase mode for the code flash.
+/// * b7: FENTRYD, program/erase mode for the data flash.
+const FENTRYR: *mut u16 = 0x407effb0 as *mut u16;
+
+/// Flash Cache Enable Register. b0: FCACHEEN, enable the cache of the code flash.
+const FCACHEE: *mut u16 = 0x4001c100 as *mut u16;
+
+/// Flash Cache Invalidate Register. b0: FCACHEIV, invalidate the cache.
+const FCACHEIV: *mut u16 = 0x4001c104 as *mut u16;
+
+/// Application Interrupt and Reset Control Register of the core, for the reset after
+/// [`CodeFlash::copy_and_reset`]. Written with 0x05fa in b16-b31. b2: SYSRESETREQ, reset the MCU.
+const AIRCR: u32 = 0xe000ed0c;
+
+const FCR_OPST: u8 = 1 << 7;
+const FSTATR1_FRDY: u8 = 1 << 6;
+const FSTATR2_ERERR: u16 = 1 << 0;
+const FSTATR2_PRGERR: u16 = 1 << 1;
+const FSTATR2_BCERR: u16 = 1 << 3;
+const FSTATR2_ILGLERR: u16 = 1 << 4;
+const FPMCR_READ: u8 = 0x08;
+const FPMCR_DATA_FLASH_PE: u8 = 0x10;
+const FPMCR_CODE_FLASH_PE: u8 = 0x82;
+/// Intermediate steps between read mode and program/erase mode of the code flash.
+const FPMCR_DISCHARGE_1: u8 = 0x12;
+const FPMCR_DISCHARGE_2: u8 = 0x92;
+
+/// Errors of the flash driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The area isn't within the flash.
+    OutOfBounds,
+    /// An erase isn't aligned to the blocks.
+    NotAligned,
+    /// FCLK is too slow to program or erase the flash, it must be at least 4 MHz.
+    ClockTooSlow,
+    /// The flash reported an error while erasing.
+    EraseFailed,
+    /// The flash reported an error while programming, e.g. because the bytes weren't erased.
+    ProgramFailed,
+    /// The area holds the Arduino bootloader.
+    Protected,
+}
+
+/// Commands of the flash sequencer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Program = 0x01,
+    BlankCheck = 0x03,
+    BlockErase = 0x04,
+}
+
+/// Write `value` to FPMCR with the unlock sequence.
+unsafe fn write_fpmcr(value: u8) {
+    FPR.write_volatile(0xa5);
+    FPMCR.write_volatile(value);
+    FPMCR.write_volatile(!value);
+    FPMCR.write_volatile(value);
+}
+
+/// Marker trait for the operating modes of the driver.
+pub trait Mode {
+    /// Does this mode wait for interrupts instead of busy-waiting?
+    const INTERRUPTS: bool;
+}
+
+/// The driver busy-waits for the flash.
+pub struct Blocking;
+impl Mode for Blocking {
+    const INTERRUPTS: bool = false;
+}
+
+/// The driver waits for the flash ready interrupt and exposes `async` functions.
+pub struct Async {
+    slot: Slot,
+}
+impl Mode for Async {
+    const INTERRUPTS: bool = true;
+}
+
+/// Woken by the flash ready interrupt.
+static WAKER: WakerCell = WakerCell::new();
+
+/// Interrupt handler for the flash ready event: Disable further interrupts and wake the waiting
+/// future.
+fn on_interrupt() {
+    unsafe {
+        FRDYIE.write_volatile(0);
+    }
+    WAKER.wake();
+}
+
+/// The data flash.
+pub struct DataFlash<M> {
+    /// Frequency of ICLK, for the waits.
+    iclk: u32,
+    /// Frequency of FCLK, which drives the flash sequencer.
+    fclk: u32,
+    mode: M,
+}
+
+impl DataFlash<Blocking> {
+    /// Returns the data flash, unless it was taken already. Programming and erasing needs FCLK
+    /// from `clocks`, and fails with [`Error::ClockTooSlow`] below 4 MHz.
+    pub fn take(clocks: &Clocks) -> Option<Self> {
+        static mut TAKEN: bool = false;
+        interrupt::free(|| unsafe {
+            if TAKEN {
+                None
+            } else {
+                TAKEN = true;
+                DFLCTL.write_volatile(1);
+                clocks::wait_us(clocks.iclk(), 1);
+                Some(Self {
+                    iclk: clocks.iclk(),
+                    fclk: clocks.fclk(),
+                    mode: Blocking,
+                })
+            }
+        })
+    }
+
+    /// Link the flash ready interrupt to an interrupt slot and return the async driver.
+    ///
+    /// Returns the blocking driver back if there is no free interrupt slot.
+    pub fn into_async(self) -> Result<DataFlash<Async>, Self> {
+        match icu::attach(Event::FcuFrdyi, on_interrupt) {
+            Some(slot) => Ok(DataFlash {
+                iclk: self.iclk,
+                fclk: self.fclk,
+                mode: Async { slot },
+            }),
+            None => Err(self),
+        }
+    }
+
+    /// Returns true if the `len` bytes at `offset` are erased.
+    pub fn blank_check(&mut self, offset: u32, len: u32) -> Result<bool, Error> {
+        block_on(self.blank_check_area(offset, len))
+    }
+
+    /// Erase the blocks from `from` up to `to`, which must both be multiples of
+    /// [`DATA_FLASH_BLOCK_SIZE`].
+    pub fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        block_on(self.erase_blocks(from, to))
+    }
+
+    /// Program `data` at `offset`. The bytes must have been erased.
+    pub fn program(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        block_on(self.program_bytes(offset, data))
+    }
+}
+
+impl DataFlash<Async> {
+    /// Unlink the interrupt and return the blocking driver.
+    pub fn into_blocking(self) -> DataFlash<Blocking> {
+        icu::detach(self.mode.slot);
+        DataFlash {
+            iclk: self.iclk,
+            fclk: self.fclk,
+            mode: Blocking,
+        }
+    }
+
+    /// Returns true if the `len` bytes at `offset` are erased.
+    pub async fn blank_check(&mut self, offset: u32, len: u32) -> Result<bool, Error> {
+        self.blank_check_area(offset, len).await
+    }
+
+    /// Erase the blocks from `from` up to `to`, which must both be multiples of
+    /// [`DATA_FLASH_BLOCK_SIZE`].
+    pub async fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        self.erase_blocks(from, to).await
+    }
+
+    /// Program `data` at `offset`. The bytes must have been erased.
+    pub async fn program(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        self.program_bytes(offset, data).await
+    }
+}
+
+impl<M: Mode> DataFlash<M> {
+    /// Read `buffer.len()` bytes at `offset`.
+    pub fn read(&self, offset: u32, buffer: &mut [u8]) -> Result<(), Error> {
+        Self::check_bounds(offset, buffer.len() as u32)?;
+        let start = (DATA_FLASH_START + offset) as *const u8;
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = unsafe { start.add(i).read_volatile() };
+        }
+        Ok(())
+    }
+
+    /// Check that the `len` bytes at `offset` are within the data flash.
+    fn check_bounds(offset: u32, len: u32) -> Result<(), Error> {
+        match offset.checked_add(len) {
+            Some(end) if end <= DATA_FLASH_SIZE => Ok(()),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    async fn blank_check_area(&mut self, offset: u32, len: u32) -> Result<bool, Error> {
+        Self::check_bounds(offset, len)?;
+        if len == 0 {
+            return Ok(true);
+        }
+        self.enter_pe_mode()?;
+        let result = self
+            .run(Command::BlankCheck, offset, offset + len - 1, 0)
+            .await;
+        self.leave_pe_mode();
+        Ok(result & FSTATR2_BCERR == 0)
+    }
+
+    async fn erase_blocks(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        if from > to {
+            return Err(Error::OutOfBounds);
+        }
+        Self::check_bounds(from, to - from)?;
+        if !from.is_multiple_of(DATA_FLASH_BLOCK_SIZE) || !to.is_multiple_of(DATA_FLASH_BLOCK_SIZE)
+        {
+            return Err(Error::NotAligned);
+        }
+        if from == to {
+            return Ok(());
+        }
+        self.enter_pe_mode()?;
+        let result = self.run(Command::BlockErase, from, to - 1, 0).await;
+        self.leave_pe_mode();
+        match result & (FSTATR2_ERERR | FSTATR2_ILGLERR) {
+            0 => Ok(()),
+            _ => {
+                log_error!("flash: erase of {}..{} failed", from, to);
+                Err(Error::EraseFailed)
+            }
+        }
+    }
+
+    async fn program_bytes(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        Self::check_bounds(offset, data.len() as u32)?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.enter_pe_mode()?;
+        let mut result = Ok(());
+        // The data flash is programmed one byte at a time.
+        for (address, &byte) in (offset..).zip(data) {
+            let status = self.run(Command::Program, address, address, byte).await;
+            if status & (FSTATR2_PRGERR | FSTATR2_ILGLERR) != 0 {
+                log_error!("flash: programming {} failed", address);
+                result = Err(Error::ProgramFailed);
+                break;
+            }
+        }
+        self.leave_pe_mode();
+        result
+    }
+
+    /// Switch the data flash to program/erase mode.
+    fn enter_pe_mode(&mut self) -> Result<(), Error> {
+        if self.fclk < FCLK_MIN_HZ {
+            return Err(Error::ClockTooSlow);
+        }
+        unsafe {
+            FENTRYR.write_volatile(0xaa80);
+            write_fpmcr(FPMCR_DATA_FLASH_PE);
+            FISR.write_volatile((self.fclk.div_ceil(1_000_000) - 1) as u8);
+        }
+        clocks::wait_us(self.iclk, MODE_CHANGE_WAIT_US);
+        Ok(())
+    }
+
+    /// Switch the data flash back to read mode.
+    fn leave_pe_mode(&mut self) {
+        unsafe {
+            write_fpmcr(FPMCR_READ);
+            clocks::wait_us(self.iclk, MODE_CHANGE_WAIT_US);
+            FENTRYR.write_volatile(0xaa00);
+            while FENTRYR.read_volatile() != 0 {}
+        }
+    }
+
+    /// Run `command` on the data flash from offset `start` to `end` (inclusive), with `byte` for
+    /// programming, and return FSTATR2.
+    async fn run(&mut self, command: Command, start: u32, end: u32, byte: u8) -> u16 {
+        let start = DATA_FLASH_PE_START + start;
+        let end = DATA_FLASH_PE_START + end;
+        unsafe {
+            FASR.write_volatile(0);
+            FSARH.write_volatile((start >> 16) as u16);
+            FSARL.write_volatile(start as u16);
+            FEARH.write_volatile((end >> 16) as u16);
+            FEARL.write_volatile(end as u16);
+            FWBL0.write_volatile(byte as u16);
+            FCR.write_volatile(FCR_OPST | command as u8);
+        }
+        self.wait_ready().await;
+        let status = unsafe { FSTATR2.read_volatile() };
+        unsafe {
+            FCR.write_volatile(0);
+            while FSTATR1.read_volatile() & FSTATR1_FRDY != 0 {}
+            if status & FSTATR2_ILGLERR != 0 {
+                // The sequencer only accepts commands again after a reset.
+                FRESETR.write_volatile(1);
+                FRESETR.write_volatile(0);
+            }
+        }
+        status
+    }
+
+    /// Wait until the flash sequencer is done with the command.
+    async fn wait_ready(&mut self) {
+        let ready = || unsafe { FSTATR1.read_volatile() & FSTATR1_FRDY != 0 };
+        poll_fn(|cx| {
+            if ready() {
+                return Poll::Ready(());
+            }
+            if M::INTERRUPTS {
+                WAKER.register(cx.waker());
+                unsafe {
+                    FRDYIE.write_volatile(1);
+                }
+                // The command might have finished before the interrupt was enabled.
+                if ready() {
+                    unsafe {
+                        FRDYIE.write_volatile(0);
+                    }
+                    return Poll::Ready(());
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Register accesses for the code that runs from RAM, which must not call functions in the code
+/// flash. They are always inlined, even without optimizations.
+mod ram {
+    use core::arch::asm;
+
+    #[inline(always)]
+    pub unsafe fn read8(address: *const u8) -> u8 {
+        let value: u32;
+        asm!("ldrb {}, [{}]", out(reg) value, in(reg) address, options(nostack, preserves_flags));
+        value as u8
+    }
+
+    #[inline(always)]
+    pub unsafe fn read16(address: *const u16) -> u16 {
+        let value: u16;
+        asm!("ldrh {}, [{}]", out(reg) value, in(reg) address, options(nostack, preserves_flags));
+        value
+    }
+
+    #[inline(always)]
+    pub unsafe fn read32(address: u32) -> u32 {
+        let value: u32;
+        asm!("ldr {}, [{}]", out(reg) value, in(reg) address, options(nostack, preserves_flags));
+        value
+    }
+
+    #[inline(always)]
+    pub unsafe fn write32(address: u32, value: u32) {
+        asm!("str {}, [{}]", in(reg) value, in(reg) address, options(nostack, preserves_flags));
+    }
+
+    #[inline(always)]
+    pub unsafe fn write8(address: *mut u8, value: u8) {
+        asm!("strb {}, [{}]", in(reg) value as u32, in(reg) address, options(nostack, preserves_flags));
+    }
+
+    #[inline(always)]
+    pub unsafe fn write16(address: *mut u16, value: u16) {
+        asm!("strh {}, [{}]", in(reg) value, in(reg) address, options(nostack, preserves_flags));
+    }
+
+    /// Write `value` to FPMCR with the unlock sequence.
+    #[inline(always)]
+    pub unsafe fn write_fpmcr(value: u8) {
+        write8(super::FPR, 0xa5);
+        write8(super::FPMCR, value);
+        write8(super::FPMCR, !value);
+        write8(super::FPMCR, value);
+    }
+
+    /// Busy-wait for `loops` iterations.
+    #[inline(always)]
+    pub unsafe fn spin(loops: u32) {
+        let mut remaining = loops;
+        while remaining != 0 {
+            asm!("nop", options(nomem, nostack, preserves_flags));
+            remaining -= 1;
+        }
+    }
+}
+
+/// A command for the code flash, for [`run_in_ram`].
+struct CodeFlashCommand {
+    command: Command,
+    start: u32,
+    end: u32,
+    /// Address of the data to program in RAM, 8 bytes per unit from `start`.
+    data: u32,
+    units: u32,
+    /// Value of FISR for FCLK.
+    fisr: u8,
+    /// Loop iterations for the waits while the mode changes.
+    wait_loops: u32,
+}
+
+/// Switch the code flash to program/erase mode, run `command` and switch back to read mode.
+/// Returns the error flags of FSTATR2.
+///
+/// Runs from RAM, and must be called with interrupts disabled.
+#[link_section = ".ramfunc.flash"]
+#[inline(never)]
+unsafe fn run_in_ram(command: &CodeFlashCommand) -> u16 {
+    ram::write16(FENTRYR, 0xaa01);
+    ram::write_fpmcr(FPMCR_DISCHARGE_1);
+    ram::spin(command.wait_loops);
+    ram::write_fpmcr(FPMCR_DISCHARGE_2);
+    ram::write_fpmcr(FPMCR_CODE_FLASH_PE);
+    ram::spin(command.wait_loops);
+    ram::write8(FISR, command.fisr);
+    ram::write8(FASR, 0);
+
+    // Compared as u8, the derived PartialEq may be a call into the code flash.
+    let program = command.command as u8 == Command::Program as u8;
+    let mut status = 0;
+    let mut unit = 0;
+    loop {
+        let start = command.start + unit * CODE_FLASH_WRITE_SIZE;
+        ram::write16(FSARH, (start >> 16) as u16);
+        ram::write16(FSARL, start as u16);
+        if program {
+            let low = ram::read32(command.data + unit * CODE_FLASH_WRITE_SIZE);
+            let high = ram::read32(command.data + unit * CODE_FLASH_WRITE_SIZE + 4);
+            ram::write16(FWBL0, low as u16);
+            ram::write16(FWBH0, (low >> 16) as u16);
+            ram::write16(FWBL1, high as u16);
+            ram::write16(FWBH1, (high >> 16) as u16);
+        } else {
+            ram::write16(FEARH, (command.end >> 16) as u16);
+            ram::write16(FEARL, command.end as u16);
+        }
+        ram::write8(FCR, FCR_OPST | command.command as u8);
+        while ram::read8(FSTATR1) & FSTATR1_FRDY == 0 {}
+        status |= ram::read16(FSTATR2);
+        ram::write8(FCR, 0);
+        while ram::read8(FSTATR1) & FSTATR1_FRDY != 0 {}
+        unit += 1;
+        if !program || unit == command.units || status & (FSTATR2_PRGERR | FSTATR2_ILGLERR) != 0 {
+            break;
+        }
+    }
+    if status & FSTATR2_ILGLERR != 0 {
+        ram::write8(FRESETR, 1);
+        ram::write8(FRESETR, 0);
+    }
+
+    ram::write_fpmcr(FPMCR_DISCHARGE_2);
+    ram::spin(command.wait_loops);
+    ram::write_fpmcr(FPMCR_DISCHARGE_1);
+    ram::write_fpmcr(FPMCR_READ);
+    ram::spin(command.wait_loops);
+    ram::write16(FENTRYR, 0xaa00);
+    while ram::read16(FENTRYR) != 0 {}
+    status
+}
+
+/// A copy within the code flash, for [`copy_in_ram`].
+struct CopyCommand {
+    from: u32,
+    to: u32,
+    /// A multiple of [`CODE_FLASH_BLOCK_SIZE`].
+    len: u32,
+    /// Value of FISR for FCLK.
+    fisr: u8,
+    /// Loop iterations for the waits while the mode changes.
+    wait_loops: u32,
+}
+
+/// Erase the blocks at `copy.to`, program them with the bytes at `copy.from` and reset the MCU.
+/// Errors are ignored, there is no code left to handle them.
+///
+/// Runs from RAM, and must be called with interrupts disabled and the cache of the code flash
+/// disabled.
+#[link_section = ".ramfunc.flash"]
+#[inline(never)]
+unsafe fn copy_in_ram(copy: &CopyCommand) -> ! {
+    // Not initialized, which could call memset in the code flash.
+    let mut units = MaybeUninit::<[u32; 2 * CodeFlash::UNITS_PER_CHUNK]>::uninit();
+    let data = units.as_mut_ptr() as u32;
+    let chunk_size = CodeFlash::UNITS_PER_CHUNK as u32 * CODE_FLASH_WRITE_SIZE;
+    let mut offset = 0;
+    while offset < copy.len {
+        let address = copy.to + offset;
+        if offset & (CODE_FLASH_BLOCK_SIZE - 1) == 0 {
+            run_in_ram(&CodeFlashCommand {
+                command: Command::BlockErase,
+                start: address,
+                end: address + CODE_FLASH_BLOCK_SIZE - 1,
+                data: 0,
+                units: 0,
+                fisr: copy.fisr,
+                wait_loops: copy.wait_loops,
+            });
+        }
+        let mut word = 0;
+        while word < chunk_size {
+            ram::write32(data + word, ram::read32(copy.from + offset + word));
+            word += 4;
+        }
+        run_in_ram(&CodeFlashCommand {
+            command: Command::Program,
+            start: address,
+            end: 0,
+            data,
+            units: CodeFlash::UNITS_PER_CHUNK as u32,
+            fisr: copy.fisr,
+            wait_loops: copy.wait_loops,
+        });
+        offset += chunk_size;
+    }
+    core::arch::asm!("dsb", options(nostack, preserves_flags));
+    ram::write32(AIRCR, (0x05fa << 16) | (1 << 2));
+    loop {
+        core::arch::asm!("nop", options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// The code flash.
+pub struct CodeFlash {
+    /// Frequency of ICLK, for the waits.
+    iclk: u32,
+    /// Frequency of FCLK, which drives the flash sequencer.
+    fclk: u32,
+}
+
+impl CodeFlash {
+    /// Number of units programmed per call of [`run_in_ram`], copied to the stack first.
+    const UNITS_PER_CHUNK: usize = 32;
+
+    /// Returns the code flash, unless it was taken already. Programming and erasing needs FCLK
+    /// from `clocks`, and fails with [`Error::ClockTooSlow`] below 4 MHz.
+    pub fn take(clocks: &Clocks) -> Option<Self> {
+        static mut TAKEN: bool = false;
+        interrupt::free(|| unsafe {
+            if TAKEN {
+                None
+            } else {
+                TAKEN = true;
+                Some(Self {
+                    iclk: clocks.iclk(),
+                    fclk: clocks.fclk(),
+                })
+            }
+        })
+    }
+
+    /// Read `buffer.len()` bytes at `address`.
+    pub fn read(&self, address: u32, buffer: &mut [u8]) -> Result<(), Error> {
+        Self::check_bounds(address, buffer.len() as u32)?;
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = unsafe { ((address as usize + i) as *const u8).read_volatile() };
+        }
+        Ok(())
+    }
+
+    /// Returns true if the `len` bytes at `address` are erased. Both must be multiples of
+    /// [`CODE_FLASH_WRITE_SIZE`].
+    pub fn blank_check(&mut self, address: u32, len: u32) -> Result<bool, Error> {
+        Self::check_bounds(address, len)?;
+        Self::check_alignment(address, len, CODE_FLASH_WRITE_SIZE)?;
+        if len == 0 {
+            return Ok(true);
+        }
+        let status = self.run(Command::BlankCheck, address, address + len - 1, &[])?;
+        Ok(status & FSTATR2_BCERR == 0)
+    }
+
+    /// Erase the blocks from `from` up to `to`, which must both be multiples of
+    /// [`CODE_FLASH_BLOCK_SIZE`]. Returns [`Error::Protected`] for the bootloader.
+    pub fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        if from > to {
+            return Err(Error::OutOfBounds);
+        }
+        Self::check_bounds(from, to - from)?;
+        Self::check_alignment(from, to - from, CODE_FLASH_BLOCK_SIZE)?;
+        if from < BOOTLOADER_SIZE {
+            return Err(Error::Protected);
+        }
+        if from == to {
+            return Ok(());
+        }
+        let status = self.run(Command::BlockErase, from, to - 1, &[])?;
+        match status & (FSTATR2_ERERR | FSTATR2_ILGLERR) {
+            0 => Ok(()),
+            _ => {
+                log_error!("flash: erase of {}..{} failed", from, to);
+                Err(Error::EraseFailed)
+            }
+        }
+    }
+
+    /// Program `data` at `address`. Both `address` and the length of `data` must be multiples of
+    /// [`CODE_FLASH_WRITE_SIZE`], and the bytes must have been erased.
+    pub fn program(&mut self, address: u32, data: &[u8]) -> Result<(), Error> {
+        Self::check_bounds(address, data.len() as u32)?;
+        Self::check_alignment(address, data.len() as u32, CODE_FLASH_WRITE_SIZE)?;
+        if address < BOOTLOADER_SIZE {
+            return Err(Error::Protected);
+        }
+        let chunk_size = Self::UNITS_PER_CHUNK * CODE_FLASH_WRITE_SIZE as usize;
+        for (chunk_address, chunk) in (address..).step_by(chunk_size).zip(data.chunks(chunk_size)) {
+            let status = self.run(Command::Program, chunk_address, 0, chunk)?;
+            if status & (FSTATR2_PRGERR | FSTATR2_ILGLERR) != 0 {
+                log_error!("flash: programming {} failed", chunk_address);
+                return Err(Error::ProgramFailed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy `len` bytes from `from` to `to` and reset the MCU, e.g. to install a firmware image from
+    /// the upper half over the running one. `from` and `to` must be multiples of
+    /// [`CODE_FLASH_BLOCK_SIZE`], and the blocks from `to` are erased first, up to the end of
+    /// the last one with copied bytes. The areas must not overlap.
+    ///
+    /// The copy may overwrite the running code, so it runs from RAM with interrupts disabled, and
+    /// ends with the reset. Returns only if the arguments are invalid. If the copy is cut off, e.g.
+    /// by a power loss, the firmware is broken, and has to be uploaded again through the
+    /// bootloader.
+    pub fn copy_and_reset(&mut self, from: u32, to: u32, len: u32) -> Result<Infallible, Error> {
+        let len = len.div_ceil(CODE_FLASH_BLOCK_SIZE) * CODE_FLASH_BLOCK_SIZE;
+        Self::check_bounds(from, len)?;
+        Self::check_bounds(to, len)?;
+        Self::check_alignment(from, len, CODE_FLASH_BLOCK_SIZE)?;
+        Self::check_alignment(to, len, CODE_FLASH_BLOCK_SIZE)?;
+        if to < BOOTLOADER_SIZE {
+            return Err(Error::Protected);
+        }
+        if from < to + len && to < from + len {
+            return Err(Error::OutOfBounds);
+        }
+        if self.fclk < FCLK_MIN_HZ {
+            return Err(Error::ClockTooSlow);
+        }
+        let copy = CopyCommand {
+            from,
+            to,
+            len,
+            fisr: (self.fclk.div_ceil(1_000_000) - 1) as u8,
+            wait_loops: (self.iclk / 1_000_000 + 1) * MODE_CHANGE_WAIT_US,
+        };
+        interrupt::disable();
+        unsafe {
+            FCACHEE.write_volatile(0);
+            copy_in_ram(&copy)
+        }
+    }
+
+    /// Check that the `len` bytes at `address` are within the code flash.
+    fn check_bounds(address: u32, len: u32) -> Result<(), Error> {
+        match address.checked_add(len) {
+            Some(end) if end <= CODE_FLASH_SIZE => Ok(()),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    /// Check that `address` and `len` are multiples of `alignment`.
+    fn check_alignment(address: u32, len: u32, alignment: u32) -> Result<(), Error> {
+        if address.is_multiple_of(alignment) && len.is_multiple_of(alignment) {
+            Ok(())
+        } else {
+            Err(Error::NotAligned)
+        }
+    }
+
+    /// Run `command` from `start` to `end`, or program `data` at `start`, and return FSTATR2.
+    fn run(&mut self, command: Command, start: u32, end: u32, data: &[u8]) -> Result<u16, Error> {
+        if self.fclk < FCLK_MIN_HZ {
+            return Err(Error::ClockTooSlow);
+        }
+        // The data may be in the code flash, so it is copied to the stack.
+        let mut units = [0u32; 2 * Self::UNITS_PER_CHUNK];
+        for (word, bytes) in units.iter_mut().zip(data.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        let command = CodeFlashCommand {
+            command,
+            start,
+            end,
+            data: units.as_ptr() as u32,
+            units: data.len() as u32 / CODE_FLASH_WRITE_SIZE,
+            fisr: (self.fclk.div_ceil(1_000_000) - 1) as u8,
+            // Each iteration takes at least one cycle.
+            wait_loops: (self.iclk / 1_000_000 + 1) * MODE_CHANGE_WAIT_US,
+        };
+        Ok(interrupt::free(|| unsafe {
+            let cache = FCACHEE.read_volatile();
+            FCACHEE.write_volatile(0);
+            let status = run_in_ram(&command);
+            // The cache may hold the old contents.
+            FCACHEIV.write_volatile(1);
+            while FCACHEIV.read_volatile() & 1 != 0 {}
+            FCACHEE.write_volatile(cache);
+            status
+        }))
+    }
+}
+
+#[cfg(feature = "usb-device")]
+mod msc_impl {
+    use super::{Blocking, DataFlash, Error, DATA_FLASH_BLOCK_SIZE, DATA_FLASH_SIZE};
+    use crate::peripherals::usb::msc::{BlockDevice, BLOCK_SIZE};
+
+    /// Drive blocks per erase block.
+    const BLOCKS_PER_ERASE: usize = DATA_FLASH_BLOCK_SIZE as usize / BLOCK_SIZE;
+
+    impl BlockDevice for DataFlash<Blocking> {
+        type Error = Error;
+
+        fn block_count(&self) -> u32 {
+            DATA_FLASH_SIZE / BLOCK_SIZE as u32
+        }
+
+        fn read_block(&mut self, lba: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), Error> {
+            DataFlash::read(self, lba * BLOCK_SIZE as u32, block)
+        }
+
+        /// Erases the whole erase block, and programs the other drive blocks in it again.
+        fn write_block(&mut self, lba: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+            let start = lba * BLOCK_SIZE as u32 / DATA_FLASH_BLOCK_SIZE * DATA_FLASH_BLOCK_SIZE;
+            let mut contents = [[0; BLOCK_SIZE]; BLOCKS_PER_ERASE];
+            for (i, contents) in contents.iter_mut().enumerate() {
+                DataFlash::read(self, start + (i * BLOCK_SIZE) as u32, contents)?;
+            }
+            contents[lba as usize % BLOCKS_PER_ERASE] = *block;
+            DataFlash::<Blocking>::erase(self, start, start + DATA_FLASH_BLOCK_SIZE)?;
+            for (i, contents) in contents.iter().enumerate() {
+                self.program(start + (i * BLOCK_SIZE) as u32, contents)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+mod embedded_storage_impl {
+    use super::{
+        Blocking, CodeFlash, DataFlash, Error, CODE_FLASH_BLOCK_SIZE, CODE_FLASH_SIZE,
+        CODE_FLASH_WRITE_SIZE, DATA_FLASH_BLOCK_SIZE, DATA_FLASH_SIZE,
+    };
+    use embedded_storage::nor_flash::{
+        ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+    };
+
+    impl NorFlashError for Error {
+        fn kind(&self) -> NorFlashErrorKind {
+            match self {
+                Error::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+                Error::NotAligned => NorFlashErrorKind::NotAligned,
+                _ => NorFlashErrorKind::Other,
+            }
+        }
+    }
+
+    impl ErrorType for DataFlash<Blocking> {
+        type Error = Error;
+    }
+
+    impl ReadNorFlash for DataFlash<Blocking> {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+            DataFlash::read(self, offset, bytes)
+        }
+
+        fn capacity(&self) -> usize {
+            DATA_FLASH_SIZE as usize
+        }
+    }
+
+    impl NorFlash for DataFlash<Blocking> {
+        const WRITE_SIZE: usize = 1;
+        const ERASE_SIZE: usize = DATA_FLASH_BLOCK_SIZE as usize;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+            DataFlash::<Blocking>::erase(self, from, to)
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+            self.program(offset, bytes)
+        }
+    }
+
+    impl ErrorType for CodeFlash {
+        type Error = Error;
+    }
+
+    impl ReadNorFlash for CodeFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+            CodeFlash::read(self, offset, bytes)
+        }
+
+        fn capacity(&self) -> usize {
+            CODE_FLASH_SIZE as usize
+        }
+    }
+
+    impl NorFlash for CodeFlash {
+        const WRITE_SIZE: usize = CODE_FLASH_WRITE_SIZE as usize;
+        const ERASE_SIZE: usize = CODE_FLASH_BLOCK_SIZE as usize;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+            CodeFlash::erase(self, from, to)
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+            self.program(offset, bytes)
+        }
+    }
+}
@@ -0,0 +1,453 @@
+//! Route peripheral events to the interrupt lines of the CPU.
+//!
+//! The RA4M1 has far more interrupt sources ("events") than the 32 external interrupt lines of the
+//! CPU. The Interrupt Controller Unit (ICU) connects an event to one of the 32 lines, which we
+//! call slots, by writing the event number into the Interrupt Event Link Setting Register
+//! (IELSR) of that slot.
+//!
+//! [`attach`] picks a free slot, links the event to it, registers a handler function and enables
+//! the interrupt in the NVIC of the CPU. By default, all external interrupt entries of the vector
+//! table point to [`dispatch`], which calls the registered handler of the slot that fired.
+//!
+//! The entries of the vector table can be replaced by the program, see
+//! [`EXTERNAL_INTERRUPTS`](crate::EXTERNAL_INTERRUPTS). [`link`] links an event to such a slot
+//! without a handler, and [`reserve`] keeps a slot free for interrupts that the program pends
+//! itself.
+//!
+//! After the handler, [`dispatch`] wakes the future that waits for the slot with [`wait`], if
+//! there is one. This lets async drivers sleep until an interrupt without a waker of their own,
+//! as long as the handler has done what is needed to make progress.
+//!
+//! For details, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Interrupt Controller
+//! Unit (ICU)", and the Armv7-M Architecture Reference Manual, section B3.4 (NVIC).
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::icu::{attach, Event};
+//!
+//! fn on_receive() {
+//!     // Handle the interrupt.
+//! }
+//!
+//! let slot = attach(Event::Iic1Rxi, on_receive).unwrap();
+//! ```
+
+use super::registers::VolatileBoolOps;
+use crate::interrupt::{self, WakerCell};
+use crate::NUM_EXTERNAL_INTERRUPTS;
+
+use core::future::{poll_fn, Future};
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::Poll;
+
+/// Event numbers of the peripheral interrupt sources, as written to IELSR. The DMAC channels can be
+/// activated by the same events, see [`super::dma`], and the event link controller can route them
+/// to other peripherals, see [`super::elc`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Event {
+    /// External pin interrupts IRQ0-IRQ15.
+    Irq0 = 0x01,
+    Irq1 = 0x02,
+    Irq2 = 0x03,
+    Irq3 = 0x04,
+    Irq4 = 0x05,
+    Irq5 = 0x06,
+    Irq6 = 0x07,
+    Irq7 = 0x08,
+    Irq8 = 0x09,
+    Irq9 = 0x0a,
+    Irq10 = 0x0b,
+    Irq11 = 0x0c,
+    Irq12 = 0x0d,
+    Irq13 = 0x0e,
+    Irq14 = 0x0f,
+    Irq15 = 0x10,
+    /// DMAC channel 0 transfer end.
+    Dmac0Int = 0x20,
+    /// DMAC channel 1 transfer end.
+    Dmac1Int = 0x21,
+    /// DMAC channel 2 transfer end.
+    Dmac2Int = 0x22,
+    /// DMAC channel 3 transfer end.
+    Dmac3Int = 0x23,
+    /// Flash ready, a program or erase command is done.
+    FcuFrdyi = 0x31,
+    /// AGT0 underflow.
+    Agt0Underflow = 0x40,
+    /// AGT0 compare match A.
+    Agt0CompareA = 0x41,
+    /// AGT0 compare match B.
+    Agt0CompareB = 0x42,
+    /// AGT1 underflow.
+    Agt1Underflow = 0x43,
+    /// AGT1 compare match A.
+    Agt1CompareA = 0x44,
+    /// AGT1 compare match B.
+    Agt1CompareB = 0x45,
+    /// RTC alarm.
+    RtcAlarm = 0x48,
+    /// RTC periodic interrupt.
+    RtcPeriod = 0x49,
+    /// RTC carry, the second counter advanced.
+    RtcCarry = 0x4a,
+    /// ADC0 scan end.
+    Adc0ScanEnd = 0x4b,
+    /// ADC0 scan end of group B.
+    Adc0ScanEndB = 0x4c,
+    /// Low-power analog comparator channel 0, the output changed.
+    Acmplp0 = 0x52,
+    /// Low-power analog comparator channel 1, the output changed.
+    Acmplp1 = 0x53,
+    /// IIC0 receive data full.
+    Iic0Rxi = 0x57,
+    /// IIC0 transmit data empty.
+    Iic0Txi = 0x58,
+    /// IIC0 transmit end.
+    Iic0Tei = 0x59,
+    /// IIC0 transfer error (NACK, arbitration lost, stop condition, ...).
+    Iic0Eri = 0x5a,
+    /// IIC1 receive data full.
+    Iic1Rxi = 0x5c,
+    /// IIC1 transmit data empty.
+    Iic1Txi = 0x5d,
+    /// IIC1 transmit end.
+    Iic1Tei = 0x5e,
+    /// IIC1 transfer error (NACK, arbitration lost, stop condition, ...).
+    Iic1Eri = 0x5f,
+    /// CAN0 receive FIFO, a frame was received.
+    Can0FifoRx = 0x76,
+    /// CAN0 mailbox, a frame was received.
+    Can0MailboxRx = 0x78,
+    /// USBFS interrupt: Bus reset, suspend, resume, setup packets and transfers.
+    UsbfsInt = 0x6d,
+    /// GPT0 capture or compare match A.
+    Gpt0CompareA = 0x83,
+    /// GPT0 capture or compare match B.
+    Gpt0CompareB = 0x84,
+    /// GPT0 counter overflow.
+    Gpt0Overflow = 0x87,
+    /// GPT1 capture or compare match A.
+    Gpt1CompareA = 0x8b,
+    /// GPT1 capture or compare match B.
+    Gpt1CompareB = 0x8c,
+    /// GPT1 counter overflow.
+    Gpt1Overflow = 0x8f,
+    /// GPT2 capture or compare match A.
+    Gpt2CompareA = 0x93,
+    /// GPT2 capture or compare match B.
+    Gpt2CompareB = 0x94,
+    /// GPT2 counter overflow.
+    Gpt2Overflow = 0x97,
+    /// GPT3 capture or compare match A.
+    Gpt3CompareA = 0x9b,
+    /// GPT3 capture or compare match B.
+    Gpt3CompareB = 0x9c,
+    /// GPT3 counter overflow.
+    Gpt3Overflow = 0x9f,
+    /// GPT4 capture or compare match A.
+    Gpt4CompareA = 0xa3,
+    /// GPT4 capture or compare match B.
+    Gpt4CompareB = 0xa4,
+    /// GPT4 counter overflow.
+    Gpt4Overflow = 0xa7,
+    /// GPT5 capture or compare match A.
+    Gpt5CompareA = 0xab,
+    /// GPT5 capture or compare match B.
+    Gpt5CompareB = 0xac,
+    /// GPT5 counter overflow.
+    Gpt5Overflow = 0xaf,
+    /// GPT6 capture or compare match A.
+    Gpt6CompareA = 0xb3,
+    /// GPT6 capture or compare match B.
+    Gpt6CompareB = 0xb4,
+    /// GPT6 counter overflow.
+    Gpt6Overflow = 0xb7,
+    /// GPT7 capture or compare match A.
+    Gpt7CompareA = 0xbb,
+    /// GPT7 capture or compare match B.
+    Gpt7CompareB = 0xbc,
+    /// GPT7 counter overflow.
+    Gpt7Overflow = 0xbf,
+    /// SCI0 receive data full.
+    Sci0Rxi = 0xc2,
+    /// SCI0 transmit data empty.
+    Sci0Txi = 0xc3,
+    /// SCI1 receive data full.
+    Sci1Rxi = 0xc8,
+    /// SCI1 transmit data empty.
+    Sci1Txi = 0xc9,
+    /// SCI2 receive data full.
+    Sci2Rxi = 0xcd,
+    /// SCI2 transmit data empty.
+    Sci2Txi = 0xce,
+    /// SCI2 receive error.
+    Sci2Eri = 0xd0,
+    /// SCI9 receive data full.
+    Sci9Rxi = 0xd2,
+    /// SCI9 transmit data empty.
+    Sci9Txi = 0xd3,
+    /// SCI9 receive error.
+    Sci9Eri = 0xd5,
+    /// SPI0 receive buffer full.
+    Spi0Rxi = 0xd7,
+    /// SPI0 transmit buffer empty.
+    Spi0Txi = 0xd8,
+}
+
+impl Event {
+    /// Returns the event of the external pin interrupt IRQ`number`, for numbers up to 15.
+    pub fn irq(number: u8) -> Option<Self> {
+        const IRQS: [Event; 16] = [
+            Event::Irq0,
+            Event::Irq1,
+            Event::Irq2,
+            Event::Irq3,
+            Event::Irq4,
+            Event::Irq5,
+            Event::Irq6,
+            Event::Irq7,
+            Event::Irq8,
+            Event::Irq9,
+            Event::Irq10,
+            Event::Irq11,
+            Event::Irq12,
+            Event::Irq13,
+            Event::Irq14,
+            Event::Irq15,
+        ];
+        IRQS.get(number as usize).copied()
+    }
+}
+
+/// One of the 32 interrupt slots of the CPU, with an event linked to it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Slot(u8);
+
+impl Slot {
+    const IELSR_BASE: u32 = 0x40006300;
+
+    /// Interrupt Event Link Setting Register of this slot.
+    /// * b0-b8: Event number, 0 if the slot is unused.
+    /// * b16: Interrupt status flag. Set by the event, must be cleared by writing 0.
+    /// * b24: Activate the DTC instead of the CPU.
+    fn ielsr(self) -> *mut u32 {
+        (Self::IELSR_BASE + 4 * self.0 as u32) as *mut u32
+    }
+
+    /// Returns the number of this slot, which is also the IRQ number in the NVIC.
+    #[inline]
+    pub fn number(self) -> u8 {
+        self.0
+    }
+
+    /// Enable the interrupt in the NVIC.
+    #[inline]
+    pub fn enable(self) {
+        unsafe {
+            Nvic::ISER.write_volatile(1 << self.0);
+        }
+    }
+
+    /// Disable the interrupt in the NVIC. The event stays linked to the slot.
+    #[inline]
+    pub fn disable(self) {
+        unsafe {
+            Nvic::ICER.write_volatile(1 << self.0);
+        }
+    }
+
+    /// Let the event activate the DTC instead of interrupting the CPU, see [`super::dtc`].
+    pub(crate) fn set_dtc_activation(self, enable: bool) {
+        unsafe {
+            if enable {
+                self.ielsr().volatile_or(1 << 24);
+            } else {
+                self.ielsr().volatile_and(!(1 << 24));
+            }
+        }
+    }
+
+    /// Returns true if the event activates the DTC. The DTC clears this after its last transfer.
+    pub(crate) fn dtc_activation(self) -> bool {
+        unsafe { self.ielsr().read_volatile() & (1 << 24) != 0 }
+    }
+
+    /// Clear the interrupt status flag of the slot and the pending state in the NVIC.
+    ///
+    /// [`dispatch`] does this before calling the handler, so handlers don't need to do this.
+    #[inline]
+    pub fn clear_request(self) {
+        unsafe {
+            self.ielsr().volatile_and(!(1 << 16));
+            // Read back to make sure the write is done before the NVIC samples the request again.
+            self.ielsr().read_volatile();
+            Nvic::ICPR.write_volatile(1 << self.0);
+        }
+    }
+}
+
+/// The parts of the Nested Vectored Interrupt Controller of the CPU that we need. Each register
+/// has one bit per interrupt slot.
+struct Nvic;
+
+impl Nvic {
+    /// Interrupt Set-Enable Register. Writing 1 enables the interrupt, 0 has no effect.
+    const ISER: *mut u32 = 0xe000e100 as *mut u32;
+
+    /// Interrupt Clear-Enable Register. Writing 1 disables the interrupt, 0 has no effect.
+    const ICER: *mut u32 = 0xe000e180 as *mut u32;
+
+    /// Interrupt Clear-Pending Register. Writing 1 clears the pending state of the interrupt.
+    const ICPR: *mut u32 = 0xe000e280 as *mut u32;
+}
+
+/// Returns the slots that are enabled in the NVIC, one bit per slot.
+pub(crate) fn enabled_slots() -> u32 {
+    // Reading ISER returns the enabled interrupts.
+    unsafe { Nvic::ISER.read_volatile() }
+}
+
+/// Enable the slots whose bit is set in `slots` in the NVIC, and disable all others.
+pub(crate) fn set_enabled_slots(slots: u32) {
+    unsafe {
+        Nvic::ICER.write_volatile(!slots);
+        Nvic::ISER.write_volatile(slots);
+    }
+}
+
+/// Interrupt Control and State Register of the System Control Block. Bits b0-b8 hold the number
+/// of the exception that is currently being handled. External interrupt n has number 16 + n.
+const ICSR: *const u32 = 0xe000ed04 as *const u32;
+
+/// Slots that [`attach`] must not use, one bit per slot, see [`link`] and [`reserve`].
+static RESERVED: AtomicU32 = AtomicU32::new(0);
+
+/// Handlers registered for the slots.
+static mut HANDLERS: [Option<fn()>; NUM_EXTERNAL_INTERRUPTS] = [None; NUM_EXTERNAL_INTERRUPTS];
+
+/// Wakers of the futures returned by [`wait`].
+static WAKERS: [WakerCell; NUM_EXTERNAL_INTERRUPTS] =
+    [const { WakerCell::new() }; NUM_EXTERNAL_INTERRUPTS];
+
+/// The slots that interrupted since [`wait`] was called for them, one bit per slot.
+static FIRED: AtomicU32 = AtomicU32::new(0);
+
+/// Link `event` to a free interrupt slot and call `handler` whenever the event occurs.
+///
+/// Returns `None` if all slots are in use.
+pub fn attach(event: Event, handler: fn()) -> Option<Slot> {
+    interrupt::free(|| {
+        let reserved = RESERVED.load(Ordering::Acquire);
+        let slot = (0..NUM_EXTERNAL_INTERRUPTS as u8)
+            .map(Slot)
+            .filter(|slot| reserved & (1 << slot.0) == 0)
+            .find(|slot| unsafe { slot.ielsr().read_volatile() & 0x1ff == 0 })?;
+        unsafe {
+            ptr::addr_of_mut!(HANDLERS[slot.0 as usize]).write(Some(handler));
+            slot.ielsr().write_volatile(event as u32);
+        }
+        slot.clear_request();
+        slot.enable();
+        Some(slot)
+    })
+}
+
+/// Link `event` to the slot with the given number, without a handler and without enabling it.
+///
+/// This is for slots whose entry in the vector table the program replaces, e.g. with a hardware
+/// task of RTIC. That handler must call [`Slot::clear_request`], as [`dispatch`] doesn't run for
+/// it. The slot is kept from [`attach`] until [`detach`].
+///
+/// Returns `None` if `number` is not a slot or the slot is in use.
+pub fn link(number: u8, event: Event) -> Option<Slot> {
+    interrupt::free(|| {
+        if !reserve(number) {
+            return None;
+        }
+        let slot = Slot(number);
+        unsafe { slot.ielsr().write_volatile(event as u32) };
+        slot.clear_request();
+        Some(slot)
+    })
+}
+
+/// Keep the slot with the given number from [`attach`], without linking an event, e.g. for a
+/// software task dispatcher of RTIC, which pends the interrupt itself.
+///
+/// Returns false if `number` is not a slot or the slot is reserved or in use.
+pub fn reserve(number: u8) -> bool {
+    if number as usize >= NUM_EXTERNAL_INTERRUPTS {
+        return false;
+    }
+    interrupt::free(|| {
+        let slot = Slot(number);
+        let bit = 1 << number;
+        if RESERVED.load(Ordering::Acquire) & bit != 0
+            || unsafe { slot.ielsr().read_volatile() } & 0x1ff != 0
+        {
+            return false;
+        }
+        RESERVED.fetch_or(bit, Ordering::AcqRel);
+        true
+    })
+}
+
+/// Disable the interrupt of `slot`, unlink its event and remove the handler, so the slot can be
+/// used for another event.
+pub fn detach(slot: Slot) {
+    interrupt::free(|| {
+        slot.disable();
+        unsafe {
+            slot.ielsr().write_volatile(0);
+            ptr::addr_of_mut!(HANDLERS[slot.0 as usize]).write(None);
+        }
+        RESERVED.fetch_and(!(1 << slot.0), Ordering::AcqRel);
+        slot.clear_request();
+    });
+}
+
+/// Handler for all external interrupts, the default for their entries in the vector table.
+///
+/// Finds out which slot fired, clears its interrupt status flag and calls the registered handler.
+///
+/// # Safety
+///
+/// Must only be called by the CPU when it takes an external interrupt.
+#[export_name = "__icu_dispatch"]
+pub unsafe fn dispatch() {
+    let exception_number = (ICSR.read_volatile() & 0x1ff) as usize;
+    let Some(slot_number) = exception_number.checked_sub(16) else {
+        return;
+    };
+    if slot_number >= NUM_EXTERNAL_INTERRUPTS {
+        return;
+    }
+    Slot(slot_number as u8).clear_request();
+    if let Some(handler) = ptr::addr_of!(HANDLERS[slot_number]).read() {
+        handler();
+    }
+    FIRED.fetch_or(1 << slot_number, Ordering::AcqRel);
+    WAKERS[slot_number].wake();
+}
+
+/// Returns a future that completes after the handler of `slot` has run for the next interrupt.
+///
+/// Interrupts count from the call on, not from the first poll, so call this before starting what
+/// makes the interrupt. Only one future can wait for a slot at a time, a second one takes over
+/// the waker of the first.
+pub fn wait(slot: Slot) -> impl Future<Output = ()> {
+    let bit = 1 << slot.0;
+    FIRED.fetch_and(!bit, Ordering::AcqRel);
+    poll_fn(move |cx| {
+        WAKERS[slot.0 as usize].register(cx.waker());
+        if FIRED.load(Ordering::Acquire) & bit != 0 {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+}
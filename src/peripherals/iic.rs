@@ -0,0 +1,907 @@
+//! I2C bus master on the pins A4 (SDA) and A5 (SCL), using the IIC1 unit of the RA4M1.
+//!
+//! Create an [`Iic`] with [`Iic::new`], which takes ownership of the two pins. The driver starts in
+//! [`Blocking`] mode, where every transfer busy-waits for the hardware. [`Iic::into_async`] links
+//! the interrupts of the unit to interrupt slots (see [`super::icu`]) and returns a driver with
+//! `async` transfer functions that sleep until the hardware is ready instead.
+//!
+//! In async mode, the bytes of long reads and writes are moved by the DTC (see [`super::dtc`]), so
+//! writing a 1 KB display framebuffer takes a handful of interrupts instead of one per byte.
+//!
+//! With the `embedded-hal-async` feature, the async driver implements
+//! `embedded_hal_async::i2c::I2c`, so it can be used with async sensor drivers.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter
+//! "I2C Bus Interface (IIC)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::iic::{Iic, Speed};
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let mut iic = Iic::new(pins.a5, pins.a4, mstp::token(), &clocks, Speed::Standard);
+//! let mut temperature = [0; 2];
+//! iic.write_read(0x48, &[0x00], &mut temperature).unwrap();
+//! ```
+
+use super::clocks::{self, Cgc, ClockDependent, Clocks};
+use super::dtc::{self, AddressMode, Size, TransferInfo};
+use super::icu::{self, Event, Slot};
+use super::mstp::{unit, MstpToken};
+use super::pins::{Pin, PinMode, PinModePeripheral, PinModeUnknown, P100, P101};
+use super::registers::VolatileBoolOps;
+use crate::interrupt::{self, block_on, WakerCell};
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+/// In async mode, the DTC is used if at least this many bytes can be moved by it in one go.
+/// Below that, setting it up isn't worth it.
+const DTC_MIN_LEN: usize = 8;
+
+/// How long a start condition waits for the bus to become free, in microseconds. Longer than any
+/// transfer of another master should take.
+const BUS_FREE_TIMEOUT_US: u32 = 10_000;
+
+/// Interval at which the bus is checked while waiting for it to become free, in microseconds.
+const BUS_FREE_POLL_US: u32 = 10;
+
+/// Errors that can happen during a transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The device didn't acknowledge its address or a data byte.
+    Nack,
+    /// Another master took over the bus.
+    ArbitrationLost,
+    /// The bus didn't become free, e.g. because a device holds SDA low.
+    BusBusy,
+}
+
+/// Bit rate of the bus.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    /// 100 kHz.
+    Standard,
+    /// 400 kHz.
+    Fast,
+}
+
+impl Speed {
+    /// Returns the bit rate in Hz, the maximum rise plus fall time of SCL in ns, and the fraction
+    /// of the SCL period (in percent) that SCL has to be low.
+    fn timing(self) -> (u32, u32, u32) {
+        match self {
+            Speed::Standard => (100_000, 1300, 53),
+            Speed::Fast => (400_000, 600, 67),
+        }
+    }
+
+    /// Compute the settings for the internal clock divider (CKS) and the SCL high and low periods
+    /// (BRH and BRL) to get this bit rate with a peripheral clock of `pclkb` Hz.
+    ///
+    /// The SCL period is `(BRH + 1 + BRL + 1) / (pclkb / 2^CKS)` plus the rise and fall times, and
+    /// BRH and BRL only have 5 bits. We pick the fastest internal clock for which they fit.
+    fn bit_rate_settings(self, pclkb: u32) -> (u8, u8, u8) {
+        let (hz, rise_fall_ns, low_percent) = self.timing();
+        for cks in 0..8 {
+            let clock = pclkb >> cks;
+            let rise_fall_counts = clock / 1000 * rise_fall_ns / 1_000_000;
+            let counts = (clock / hz).saturating_sub(rise_fall_counts).max(2);
+            let low = (counts * low_percent / 100).max(1);
+            let high = (counts - low).max(1);
+            if low <= 32 && high <= 32 {
+                return (cks as u8, (high - 1) as u8, (low - 1) as u8);
+            }
+        }
+        (7, 31, 31)
+    }
+}
+
+/// Marker trait for the operating modes of the driver.
+pub trait Mode {
+    /// Does this mode wait for interrupts instead of busy-waiting?
+    const INTERRUPTS: bool;
+
+    /// The interrupt slots of the RXI and TXI events, which can activate the DTC.
+    fn dtc_slots(&self) -> Option<(Slot, Slot)>;
+}
+
+/// The driver busy-waits for the hardware.
+pub struct Blocking;
+impl Mode for Blocking {
+    const INTERRUPTS: bool = false;
+
+    fn dtc_slots(&self) -> Option<(Slot, Slot)> {
+        None
+    }
+}
+
+/// The driver waits for interrupts and exposes `async` transfer functions.
+pub struct Async {
+    /// Slots for the RXI, TXI, TEI and ERI events.
+    slots: [Slot; 4],
+}
+impl Mode for Async {
+    const INTERRUPTS: bool = true;
+
+    fn dtc_slots(&self) -> Option<(Slot, Slot)> {
+        Some((self.slots[0], self.slots[1]))
+    }
+}
+
+/// Woken by the interrupt handler of the IIC1 unit.
+static WAKER: WakerCell = WakerCell::new();
+
+/// Interrupt handler for all IIC1 events: Disable further interrupts and wake the waiting future,
+/// which checks the status flags itself.
+fn on_interrupt() {
+    unsafe {
+        Iic::<Blocking>::ICIER.write_volatile(0);
+    }
+    WAKER.wake();
+}
+
+/// What to do at the end of a read or write.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum End {
+    /// Release the bus with a stop condition.
+    Stop,
+    /// Keep the bus and issue a repeated start condition for the next transfer.
+    Restart,
+}
+
+/// The IIC1 unit, a master on the I2C bus on pins A4 and A5.
+pub struct Iic<M> {
+    _scl: P100<PinModePeripheral>,
+    _sda: P101<PinModePeripheral>,
+    _clock: MstpToken<unit::Iic1>,
+    speed: Speed,
+    mode: M,
+}
+
+impl<M> Iic<M> {
+    const BASE_ADDRESS: u32 = 0x40053100;
+
+    /// I2C Bus Control Register 1.
+    /// * b7: ICE, enable the bus interface.
+    /// * b6: IICRST, reset the unit. Resetting while ICE is 1 keeps the register settings.
+    const ICCR1: *mut u8 = Self::BASE_ADDRESS as *mut u8;
+
+    /// I2C Bus Control Register 2.
+    /// * b1: ST, request a start condition.
+    /// * b2: RS, request a repeated start condition.
+    /// * b3: SP, request a stop condition.
+    /// * b7: BBSY, the bus is busy.
+    const ICCR2: *mut u8 = (Self::BASE_ADDRESS + 0x01) as *mut u8;
+
+    /// I2C Bus Mode Register 1. Bits b4-b6 (CKS) divide PCLKB by `2^CKS` for the internal clock.
+    const ICMR1: *mut u8 = (Self::BASE_ADDRESS + 0x02) as *mut u8;
+
+    /// I2C Bus Mode Register 3.
+    /// * b3: ACKBT, send NACK instead of ACK for the received byte. Only writable if b4 is 1.
+    /// * b4: ACKWP, write protection for ACKBT.
+    /// * b6: WAIT, hold SCL low after the ninth clock cycle until ICDRR is read.
+    const ICMR3: *mut u8 = (Self::BASE_ADDRESS + 0x04) as *mut u8;
+
+    /// I2C Bus Interrupt Enable Register. The bits are in the same position as the flags they
+    /// enable in ICSR2. RXI, TXI and TEI are raised for RDRF, TDRE and TEND, ERI for the others.
+    const ICIER: *mut u8 = (Self::BASE_ADDRESS + 0x07) as *mut u8;
+
+    /// I2C Bus Status Register 2. Flags are cleared by writing 0 after reading them as 1.
+    /// * b1: AL, arbitration lost.
+    /// * b2: START, a start or repeated start condition was detected.
+    /// * b3: STOP, a stop condition was detected.
+    /// * b4: NACKF, a NACK was received.
+    /// * b5: RDRF, a byte was received and can be read from ICDRR.
+    /// * b6: TEND, transmission ended.
+    /// * b7: TDRE, ICDRT is empty and the next byte can be written.
+    const ICSR2: *mut u8 = (Self::BASE_ADDRESS + 0x09) as *mut u8;
+
+    /// I2C Bus Bit Rate Low-Level Register. b0-b4: Length of the SCL low period. The upper bits
+    /// read as 1 and must be written as 1.
+    const ICBRL: *mut u8 = (Self::BASE_ADDRESS + 0x10) as *mut u8;
+
+    /// I2C Bus Bit Rate High-Level Register. b0-b4: Length of the SCL high period. The upper bits
+    /// read as 1 and must be written as 1.
+    const ICBRH: *mut u8 = (Self::BASE_ADDRESS + 0x11) as *mut u8;
+
+    /// I2C Bus Transmit Data Register.
+    const ICDRT: *mut u8 = (Self::BASE_ADDRESS + 0x12) as *mut u8;
+
+    /// I2C Bus Receive Data Register.
+    const ICDRR: *const u8 = (Self::BASE_ADDRESS + 0x13) as *const u8;
+
+    const ICCR2_ST: u8 = 1 << 1;
+    const ICCR2_RS: u8 = 1 << 2;
+    const ICCR2_SP: u8 = 1 << 3;
+    const ICCR2_BBSY: u8 = 1 << 7;
+
+    const ICMR3_ACKBT: u8 = 1 << 3;
+    const ICMR3_ACKWP: u8 = 1 << 4;
+    const ICMR3_WAIT: u8 = 1 << 6;
+
+    const ICSR2_AL: u8 = 1 << 1;
+    const ICSR2_START: u8 = 1 << 2;
+    const ICSR2_STOP: u8 = 1 << 3;
+    const ICSR2_NACKF: u8 = 1 << 4;
+    const ICSR2_RDRF: u8 = 1 << 5;
+    const ICSR2_TEND: u8 = 1 << 6;
+    const ICSR2_TDRE: u8 = 1 << 7;
+}
+
+impl Iic<Blocking> {
+    /// Set up the IIC1 unit as bus master on pins A5 (SCL) and A4 (SDA), with the bit rate
+    /// derived from PCLKB of `clocks`.
+    pub fn new<M1: PinMode, M2: PinMode>(
+        scl: P100<M1>,
+        sda: P101<M2>,
+        clock: MstpToken<unit::Iic1>,
+        clocks: &Clocks,
+        speed: Speed,
+    ) -> Self {
+        // Peripheral function 7 is IIC. Bit 6 makes the pins open-drain.
+        let scl = scl.into_peripheral(0b00111, 1 << 6);
+        let sda = sda.into_peripheral(0b00111, 1 << 6);
+        let (cks, brh, brl) = speed.bit_rate_settings(clocks.pclkb());
+        unsafe {
+            // Reset the unit, then configure it while it is held in internal reset.
+            Self::ICCR1.write_volatile(1 << 6);
+            Self::ICCR1.write_volatile((1 << 7) | (1 << 6));
+            Self::ICMR1.write_volatile(cks << 4);
+            Self::ICBRH.write_volatile(0xe0 | brh);
+            Self::ICBRL.write_volatile(0xe0 | brl);
+            Self::ICIER.write_volatile(0);
+            Self::ICCR1.write_volatile(1 << 7);
+        }
+        Self {
+            _scl: scl,
+            _sda: sda,
+            _clock: clock,
+            speed,
+            mode: Blocking,
+        }
+    }
+
+    /// Link the IIC1 interrupts to interrupt slots and return the async driver.
+    ///
+    /// Returns the blocking driver back if there are not enough free interrupt slots.
+    pub fn into_async(self) -> Result<Iic<Async>, Self> {
+        let events = [
+            Event::Iic1Rxi,
+            Event::Iic1Txi,
+            Event::Iic1Tei,
+            Event::Iic1Eri,
+        ];
+        let mut slots = [None; 4];
+        for (slot, event) in slots.iter_mut().zip(events) {
+            *slot = icu::attach(event, on_interrupt);
+        }
+        match slots {
+            [Some(rxi), Some(txi), Some(tei), Some(eri)] => Ok(Iic {
+                _scl: self._scl,
+                _sda: self._sda,
+                _clock: self._clock,
+                speed: self.speed,
+                mode: Async {
+                    slots: [rxi, txi, tei, eri],
+                },
+            }),
+            _ => {
+                slots.into_iter().flatten().for_each(icu::detach);
+                Err(self)
+            }
+        }
+    }
+
+    /// Stop the unit and return the pins, SCL first.
+    pub fn release(self) -> (P100<PinModeUnknown>, P101<PinModeUnknown>) {
+        unsafe {
+            Self::ICCR1.write_volatile(0);
+        }
+        (self._scl.into_unknown(), self._sda.into_unknown())
+    }
+
+    /// Write `bytes` to the device at `address`.
+    pub fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        block_on(self.transfer(address, bytes, &mut []))
+    }
+
+    /// Read `buffer.len()` bytes from the device at `address`.
+    pub fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        block_on(self.transfer(address, &[], buffer))
+    }
+
+    /// Write `bytes` to the device at `address`, then read `buffer.len()` bytes from it after a
+    /// repeated start condition. This is how most devices expose their registers.
+    pub fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        block_on(self.transfer(address, bytes, buffer))
+    }
+
+    /// Write `bytes` to the device at `address`, then read a byte count `n` after a repeated start
+    /// condition, followed by `n` bytes into `buffer` and then `trailer.len()` more bytes into
+    /// `trailer`. This is the SMBus block read, see [`super::smbus`].
+    ///
+    /// If the device sends more than `buffer.len()` bytes, only that many are read. Returns the
+    /// byte count sent by the device.
+    pub(crate) fn read_counted(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+        trailer: &mut [u8],
+    ) -> Result<u8, Error> {
+        block_on(async {
+            let result = self
+                .read_counted_unrecovered(address, bytes, buffer, trailer)
+                .await;
+            match result {
+                Ok(count) => Ok(count),
+                Err(error) => Err(self.recover(error).await),
+            }
+        })
+    }
+}
+
+impl Iic<Async> {
+    /// Unlink the interrupts and return the blocking driver.
+    pub fn into_blocking(self) -> Iic<Blocking> {
+        self.mode.slots.into_iter().for_each(icu::detach);
+        Iic {
+            _scl: self._scl,
+            _sda: self._sda,
+            _clock: self._clock,
+            speed: self.speed,
+            mode: Blocking,
+        }
+    }
+
+    /// Write `bytes` to the device at `address`.
+    pub async fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.transfer(address, bytes, &mut []).await
+    }
+
+    /// Read `buffer.len()` bytes from the device at `address`.
+    pub async fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        self.transfer(address, &[], buffer).await
+    }
+
+    /// Write `bytes` to the device at `address`, then read `buffer.len()` bytes from it after a
+    /// repeated start condition.
+    pub async fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        self.transfer(address, bytes, buffer).await
+    }
+}
+
+impl<M: Mode> Iic<M> {
+    /// Write `bytes`, then read into `buffer` after a repeated start condition. If either is
+    /// empty, that part of the transfer is skipped. If both are empty, only the address is sent,
+    /// which checks if a device is present.
+    async fn transfer(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let result = self.transfer_unrecovered(address, bytes, buffer).await;
+        match result {
+            Ok(()) => Ok(()),
+            Err(error) => Err(self.recover(error).await),
+        }
+    }
+
+    async fn transfer_unrecovered(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let write = !bytes.is_empty() || buffer.is_empty();
+        if write {
+            self.start(address, false, 0, false).await?;
+            let end = if buffer.is_empty() {
+                End::Stop
+            } else {
+                End::Restart
+            };
+            self.send(bytes, end).await?;
+        }
+        if !buffer.is_empty() {
+            self.start(address, true, buffer.len(), write).await?;
+            self.receive(buffer, 0, End::Stop).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_counted_unrecovered(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+        trailer: &mut [u8],
+    ) -> Result<u8, Error> {
+        self.start(address, false, 0, false).await?;
+        self.send(bytes, End::Restart).await?;
+        // The count byte is never the last one, we always read at least one more.
+        self.start(address, true, 2, true).await?;
+        self.wait(Self::ICSR2_RDRF).await?;
+        // Reading the count starts the reception of the next byte. If that is the last one, we
+        // have to request the NACK before it is done, so don't get interrupted in between.
+        let (count, len) = interrupt::free(|| {
+            let count = unsafe { Self::ICDRR.read_volatile() };
+            let len = (count as usize).min(buffer.len());
+            if len + trailer.len() <= 1 {
+                Self::nack_next_byte();
+            }
+            (count, len)
+        });
+        if len + trailer.len() == 0 {
+            // Nothing to read, but the device is already sending a byte. Receive it and discard it.
+            self.receive(&mut [0], 0, End::Stop).await?;
+        } else {
+            self.receive(&mut buffer[..len], trailer.len(), End::Stop)
+                .await?;
+            self.receive(trailer, 0, End::Stop).await?;
+        }
+        Ok(count)
+    }
+
+    /// Wait until one of the `flags` in ICSR2 is set.
+    ///
+    /// In async mode, this enables the interrupts for the flags and sleeps until the interrupt
+    /// handler wakes us up. Returns an error if a NACK is received or arbitration is lost while
+    /// waiting.
+    async fn wait(&mut self, flags: u8) -> Result<(), Error> {
+        poll_fn(|cx| {
+            if let Poll::Ready(result) = Self::check(flags) {
+                return Poll::Ready(result);
+            }
+            if M::INTERRUPTS {
+                WAKER.register(cx.waker());
+                unsafe {
+                    Self::ICIER.write_volatile(flags | Self::ICSR2_NACKF | Self::ICSR2_AL);
+                }
+                // The flag might have been set before the interrupt was enabled.
+                if let Poll::Ready(result) = Self::check(flags) {
+                    unsafe {
+                        Self::ICIER.write_volatile(0);
+                    }
+                    return Poll::Ready(result);
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    fn check(flags: u8) -> Poll<Result<(), Error>> {
+        let status = unsafe { Self::ICSR2.read_volatile() };
+        if status & Self::ICSR2_AL != 0 {
+            Poll::Ready(Err(Error::ArbitrationLost))
+        } else if status & Self::ICSR2_NACKF != 0 {
+            Poll::Ready(Err(Error::Nack))
+        } else if status & flags != 0 {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Issue a start condition, unless the previous transfer already requested a repeated start
+    /// (`restarted`), and send the address. For reads, `read_len` is the total number of bytes
+    /// that will be read before the next stop or repeated start condition.
+    async fn start(
+        &mut self,
+        address: u8,
+        read: bool,
+        read_len: usize,
+        restarted: bool,
+    ) -> Result<(), Error> {
+        if !restarted {
+            Self::wait_for_bus_free()?;
+            unsafe {
+                Self::ICSR2.volatile_and(!Self::ICSR2_START);
+                Self::ICCR2.volatile_or(Self::ICCR2_ST);
+            }
+        }
+        self.wait(Self::ICSR2_START).await?;
+        unsafe {
+            Self::ICSR2.volatile_and(!Self::ICSR2_START);
+        }
+        self.wait(Self::ICSR2_TDRE).await?;
+        unsafe {
+            Self::ICDRT.write_volatile((address << 1) | read as u8);
+        }
+        if read {
+            self.wait(Self::ICSR2_RDRF).await?;
+            if read_len == 1 {
+                Self::nack_next_byte();
+            }
+            // Reading ICDRR after the address starts the reception of the first byte.
+            unsafe {
+                Self::ICDRR.read_volatile();
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait until no start condition is on the bus, or return [`Error::BusBusy`] after
+    /// [`BUS_FREE_TIMEOUT_US`].
+    fn wait_for_bus_free() -> Result<(), Error> {
+        let iclk = Cgc::current().iclk();
+        for _ in 0..BUS_FREE_TIMEOUT_US / BUS_FREE_POLL_US {
+            if unsafe { Self::ICCR2.read_volatile() } & Self::ICCR2_BBSY == 0 {
+                return Ok(());
+            }
+            clocks::wait_us(iclk, BUS_FREE_POLL_US);
+        }
+        Err(Error::BusBusy)
+    }
+
+    /// Request a repeated start condition. It is issued once the current byte is done.
+    fn request_restart() {
+        unsafe {
+            Self::ICSR2.volatile_and(!Self::ICSR2_START);
+            Self::ICCR2.volatile_or(Self::ICCR2_RS);
+        }
+    }
+
+    /// Send `bytes`, then end the transfer as given by `end`.
+    async fn send(&mut self, bytes: &[u8], end: End) -> Result<(), Error> {
+        self.send_bytes(bytes).await?;
+        self.end_write(end).await
+    }
+
+    async fn send_bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let mut i = 0;
+        while i < bytes.len() {
+            self.wait(Self::ICSR2_TDRE).await?;
+            let dtc_len = (bytes.len() - i - 1).min(u16::MAX as usize);
+            match self.mode.dtc_slots() {
+                Some((_, txi)) if dtc_len >= DTC_MIN_LEN => {
+                    // Writing this byte clears TDRE. When it is set again, TXI activates the DTC
+                    // for the following bytes.
+                    let mut info = TransferInfo::normal(
+                        Size::Byte,
+                        bytes[i + 1..].as_ptr(),
+                        AddressMode::Increment,
+                        Self::ICDRT,
+                        AddressMode::Fixed,
+                        dtc_len as u16,
+                    );
+                    let guard = DtcGuard(txi);
+                    interrupt::free(|| unsafe {
+                        dtc::activate(txi, &mut info);
+                        Self::ICIER.write_volatile(Self::ICSR2_TDRE);
+                        Self::ICDRT.write_volatile(bytes[i]);
+                    });
+                    self.wait_for_dtc(txi, Self::ICSR2_TDRE).await?;
+                    drop(guard);
+                    i += 1 + dtc_len;
+                }
+                _ => {
+                    unsafe {
+                        Self::ICDRT.write_volatile(bytes[i]);
+                    }
+                    i += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait until the DTC is done with the transfer activated by `slot`. `flags` are the ICSR2
+    /// flags whose interrupts activate the DTC.
+    async fn wait_for_dtc(&mut self, slot: Slot, flags: u8) -> Result<(), Error> {
+        poll_fn(|cx| {
+            if let Poll::Ready(Err(error)) = Self::check(0) {
+                return Poll::Ready(Err(error));
+            }
+            if !dtc::is_active(slot) {
+                return Poll::Ready(Ok(()));
+            }
+            WAKER.register(cx.waker());
+            // The interrupt handler disables the interrupts when the DTC is done. If we were woken
+            // for another reason, they have to be enabled again for the DTC to continue.
+            unsafe {
+                Self::ICIER.write_volatile(flags | Self::ICSR2_NACKF | Self::ICSR2_AL);
+            }
+            if !dtc::is_active(slot) {
+                return Poll::Ready(Ok(()));
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Wait until the last byte is sent, then end the transfer as given by `end`.
+    async fn end_write(&mut self, end: End) -> Result<(), Error> {
+        self.wait(Self::ICSR2_TEND).await?;
+        match end {
+            End::Stop => self.stop().await,
+            End::Restart => Self::request_restart(),
+        }
+        Ok(())
+    }
+
+    /// Receive bytes into `buffer`. `remaining_after` is the number of bytes that will be received
+    /// into other buffers before the transfer ends as given by `end`.
+    async fn receive(
+        &mut self,
+        buffer: &mut [u8],
+        remaining_after: usize,
+        end: End,
+    ) -> Result<(), Error> {
+        let len = buffer.len();
+        let mut i = 0;
+        while i < len {
+            self.wait(Self::ICSR2_RDRF).await?;
+            let remaining = len - i + remaining_after;
+            // The DTC can't take care of the last two bytes, we need to NACK and stop.
+            let dtc_len = (len - i - 1)
+                .min(remaining.saturating_sub(3))
+                .min(u16::MAX as usize);
+            let dtc_slots = self.mode.dtc_slots().filter(|_| dtc_len >= DTC_MIN_LEN);
+            if let Some((rxi, _)) = dtc_slots {
+                // Reading this byte starts the reception of the next one. When it is there, RXI
+                // activates the DTC for the following bytes.
+                let mut info = TransferInfo::normal(
+                    Size::Byte,
+                    Self::ICDRR,
+                    AddressMode::Fixed,
+                    buffer[i + 1..].as_mut_ptr(),
+                    AddressMode::Increment,
+                    dtc_len as u16,
+                );
+                let guard = DtcGuard(rxi);
+                interrupt::free(|| unsafe {
+                    dtc::activate(rxi, &mut info);
+                    Self::ICIER.write_volatile(Self::ICSR2_RDRF);
+                    buffer[i] = Self::ICDRR.read_volatile();
+                });
+                self.wait_for_dtc(rxi, Self::ICSR2_RDRF).await?;
+                drop(guard);
+                i += 1 + dtc_len;
+                continue;
+            }
+            let byte = &mut buffer[i];
+            i += 1;
+            if remaining == 1 {
+                // Last byte, SCL is held low until we read it. End the transfer first, otherwise
+                // reading it starts the reception of another byte.
+                match end {
+                    End::Stop => unsafe {
+                        Self::ICSR2.volatile_and(!Self::ICSR2_STOP);
+                        Self::ICCR2.volatile_or(Self::ICCR2_SP);
+                    },
+                    End::Restart => Self::request_restart(),
+                }
+                unsafe {
+                    *byte = Self::ICDRR.read_volatile();
+                    Self::ICMR3.write_volatile(Self::ICMR3_ACKWP);
+                    Self::ICMR3.write_volatile(0);
+                }
+                if end == End::Stop {
+                    self.wait_for_stop().await;
+                }
+            } else {
+                if remaining == 2 {
+                    Self::nack_next_byte();
+                }
+                *byte = unsafe { Self::ICDRR.read_volatile() };
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer the next received byte with NACK and hold SCL low after it, so we can issue the
+    /// stop condition before the device sends more.
+    fn nack_next_byte() {
+        unsafe {
+            Self::ICMR3.write_volatile(Self::ICMR3_WAIT | Self::ICMR3_ACKWP);
+            Self::ICMR3.write_volatile(Self::ICMR3_WAIT | Self::ICMR3_ACKWP | Self::ICMR3_ACKBT);
+        }
+    }
+
+    /// Issue a stop condition and wait until it is on the bus.
+    async fn stop(&mut self) {
+        unsafe {
+            Self::ICSR2.volatile_and(!Self::ICSR2_STOP);
+            Self::ICCR2.volatile_or(Self::ICCR2_SP);
+        }
+        self.wait_for_stop().await;
+    }
+
+    /// Wait for the stop condition and clear the status flags.
+    async fn wait_for_stop(&mut self) {
+        poll_fn(|cx| {
+            let status = unsafe { Self::ICSR2.read_volatile() };
+            if status & Self::ICSR2_STOP != 0 {
+                return Poll::Ready(());
+            }
+            if M::INTERRUPTS {
+                WAKER.register(cx.waker());
+                unsafe {
+                    Self::ICIER.write_volatile(Self::ICSR2_STOP);
+                    if Self::ICSR2.read_volatile() & Self::ICSR2_STOP != 0 {
+                        Self::ICIER.write_volatile(0);
+                        return Poll::Ready(());
+                    }
+                }
+            }
+            Poll::Pending
+        })
+        .await;
+        unsafe {
+            Self::ICSR2.volatile_and(!(Self::ICSR2_NACKF | Self::ICSR2_STOP));
+        }
+    }
+
+    /// Recover from an error during a transfer: Release the bus after a NACK and clear the flags.
+    async fn recover(&mut self, error: Error) -> Error {
+        log_error!("IIC: {}", error);
+        unsafe {
+            Self::ICMR3.write_volatile(Self::ICMR3_ACKWP);
+            Self::ICMR3.write_volatile(0);
+        }
+        match error {
+            Error::Nack => {
+                unsafe {
+                    Self::ICSR2.volatile_and(!Self::ICSR2_STOP);
+                    Self::ICCR2.volatile_or(Self::ICCR2_SP);
+                    // A dummy read releases SCL in case we were receiving.
+                    Self::ICDRR.read_volatile();
+                }
+                self.wait_for_stop().await;
+            }
+            Error::ArbitrationLost => unsafe {
+                // The unit has already dropped out of master mode.
+                Self::ICSR2.volatile_and(!Self::ICSR2_AL);
+            },
+            // Nothing was sent.
+            Error::BusBusy => {}
+        }
+        error
+    }
+}
+
+impl<M> ClockDependent for Iic<M> {
+    fn set_clocks(&mut self, clocks: &Clocks) {
+        let (cks, brh, brl) = self.speed.bit_rate_settings(clocks.pclkb());
+        unsafe {
+            // The bit rate registers are written while the unit is held in internal reset, which
+            // keeps the other settings.
+            Self::ICCR1.write_volatile((1 << 7) | (1 << 6));
+            Self::ICMR1.write_volatile(cks << 4);
+            Self::ICBRH.write_volatile(0xe0 | brh);
+            Self::ICBRL.write_volatile(0xe0 | brl);
+            Self::ICCR1.write_volatile(1 << 7);
+        }
+    }
+}
+
+/// Deactivates the DTC for a slot when dropped, so it stops writing into a buffer when the future
+/// that lent it is dropped before the transfer is done.
+struct DtcGuard(Slot);
+
+impl Drop for DtcGuard {
+    fn drop(&mut self) {
+        dtc::deactivate(self.0);
+        unsafe {
+            Iic::<Blocking>::ICIER.write_volatile(0);
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-async")]
+mod embedded_hal_async_impl {
+    use super::{Async, End, Error, Iic};
+    use embedded_hal_async::i2c::{self, ErrorKind, NoAcknowledgeSource, Operation};
+
+    impl i2c::Error for Error {
+        fn kind(&self) -> ErrorKind {
+            match self {
+                Error::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+                Error::ArbitrationLost => ErrorKind::ArbitrationLoss,
+                Error::BusBusy => ErrorKind::Bus,
+            }
+        }
+    }
+
+    impl i2c::ErrorType for Iic<Async> {
+        type Error = Error;
+    }
+
+    impl i2c::I2c for Iic<Async> {
+        async fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Error> {
+            let result = self.transaction_unrecovered(address, operations).await;
+            match result {
+                Ok(()) => Ok(()),
+                Err(error) => Err(self.recover(error).await),
+            }
+        }
+    }
+
+    impl Iic<Async> {
+        /// Run the operations, with a repeated start condition whenever the direction changes.
+        /// Consecutive operations in the same direction are merged into one.
+        async fn transaction_unrecovered(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Error> {
+            let mut restarted = false;
+            let mut i = 0;
+            while i < operations.len() {
+                let is_read = matches!(operations[i], Operation::Read(_));
+                let group_len = operations[i..]
+                    .iter()
+                    .take_while(|op| matches!(op, Operation::Read(_)) == is_read)
+                    .count();
+                // Reads of no bytes would leave the bus waiting for a byte, they are skipped.
+                let last_group = operations[i + group_len..].iter().all(is_empty_read);
+                let end = if last_group { End::Stop } else { End::Restart };
+                let group = &mut operations[i..i + group_len];
+                if is_read {
+                    let total = group.iter().map(len).sum::<usize>();
+                    if total == 0 {
+                        i += group_len;
+                        continue;
+                    }
+                    self.start(address, true, total, restarted).await?;
+                    let mut remaining = total;
+                    for op in group.iter_mut() {
+                        if let Operation::Read(buffer) = op {
+                            remaining -= buffer.len();
+                            self.receive(buffer, remaining, end).await?;
+                        }
+                    }
+                } else {
+                    self.start(address, false, 0, restarted).await?;
+                    let last = group_len - 1;
+                    for (j, op) in group.iter().enumerate() {
+                        if let Operation::Write(bytes) = op {
+                            self.send_bytes(bytes).await?;
+                            if j == last {
+                                self.end_write(end).await?;
+                            }
+                        }
+                    }
+                }
+                restarted = true;
+                i += group_len;
+            }
+            Ok(())
+        }
+    }
+
+    fn is_empty_read(op: &Operation<'_>) -> bool {
+        matches!(op, Operation::Read(buffer) if buffer.is_empty())
+    }
+
+    fn len(op: &Operation<'_>) -> usize {
+        match op {
+            Operation::Read(buffer) => buffer.len(),
+            Operation::Write(bytes) => bytes.len(),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Independent watchdog timer (IWDT): Resets the MCU unless it is fed regularly, clocked by its
+//! own 15 kHz oscillator so it keeps working if the main clocks fail.
+//!
+//! Unlike the [`super::wdt`], the IWDT of the RA4M1 has no control registers. It is configured
+//! only by the option bytes in flash (OFS0), and if they enable it, it runs from the reset on and
+//! can't be stopped. A program on a board whose option bytes enable the IWDT must feed it with
+//! [`Iwdt::feed`], otherwise it is reset over and over. [`Iwdt::settings`] decodes the option
+//! bytes to find out if this is necessary, and how often.
+//!
+//! The status flags ([`Iwdt::status`]) survive the reset caused by the IWDT, so they also tell
+//! whether the last reset was caused by an underflow or a refresh outside of the window.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter
+//! "Independent Watchdog Timer (IWDT)", and "Option-Setting Memory" for OFS0.
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::iwdt::Iwdt;
+//!
+//! let mut iwdt = Iwdt::take().unwrap();
+//! if let Some(settings) = iwdt.settings() {
+//!     // The IWDT is running, feed it at least every `settings.timeout_ms()` ms.
+//! }
+//! loop {
+//!     // Do some work, then:
+//!     iwdt.feed();
+//! }
+//! ```
+
+use crate::interrupt;
+
+/// Frequency of the IWDT-dedicated low-speed clock.
+const IWDTCLK_HZ: u32 = 15_000;
+
+/// The settings of the IWDT from the option bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Settings {
+    /// Division of IWDTCLK for the counter.
+    pub divider: u16,
+    /// Number of counter cycles until the counter underflows.
+    pub period: u16,
+    /// Counter value, in percent of the period, from which on feeding is allowed. The counter
+    /// starts at 100% after feeding and counts down to 0%.
+    pub window_start: u8,
+    /// Counter value, in percent of the period, after which feeding is no longer allowed.
+    pub window_end: u8,
+    /// Reset the MCU on underflow or a refresh error. Otherwise, a non-maskable interrupt is
+    /// raised, see [`super::nmi`], and the MCU is reset after its handler.
+    pub reset: bool,
+    /// The counter stops while the CPU sleeps.
+    pub stop_in_sleep: bool,
+}
+
+impl Settings {
+    /// Decode the IWDT fields of OFS0.
+    fn from_ofs0(ofs0: u32) -> Self {
+        let divider = match (ofs0 >> 4) & 0xf {
+            0b0000 => 1,
+            0b0010 => 16,
+            0b0011 => 32,
+            0b0100 => 64,
+            0b1111 => 128,
+            _ => 256,
+        };
+        Self {
+            divider,
+            period: [128, 512, 1024, 2048][((ofs0 >> 2) & 0b11) as usize],
+            window_end: [75, 50, 25, 0][((ofs0 >> 8) & 0b11) as usize],
+            window_start: [25, 50, 75, 100][((ofs0 >> 10) & 0b11) as usize],
+            reset: ofs0 & (1 << 12) != 0,
+            stop_in_sleep: ofs0 & (1 << 14) != 0,
+        }
+    }
+
+    /// Returns the timeout in milliseconds.
+    pub fn timeout_ms(&self) -> u32 {
+        self.divider as u32 * self.period as u32 * 1000 / IWDTCLK_HZ
+    }
+}
+
+/// Status flags of the IWDT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status {
+    /// The counter underflowed.
+    pub underflow: bool,
+    /// The IWDT was fed outside of the window.
+    pub refresh_error: bool,
+}
+
+/// The independent watchdog timer.
+pub struct Iwdt {
+    _private: (),
+}
+
+impl Iwdt {
+    const BASE_ADDRESS: u32 = 0x40044400;
+
+    /// IWDT Refresh Register. Writing 0x00 and then 0xff feeds the watchdog.
+    const IWDTRR: *mut u8 = Self::BASE_ADDRESS as *mut u8;
+
+    /// IWDT Status Register.
+    /// * b0-b13: CNTVAL, the counter.
+    /// * b14: UNDFF, the counter underflowed. Cleared by writing 0.
+    /// * b15: REFEF, refresh error. Cleared by writing 0.
+    const IWDTSR: *mut u16 = (Self::BASE_ADDRESS + 0x04) as *mut u16;
+
+    const IWDTSR_UNDFF: u16 = 1 << 14;
+    const IWDTSR_REFEF: u16 = 1 << 15;
+
+    /// Option Function Select Register 0 in the option-setting memory of the flash.
+    /// * b1: IWDTSTRT, 0 if the IWDT starts automatically after reset.
+    /// * b2-b3: IWDTTOPS, the period.
+    /// * b4-b7: IWDTCKS, the clock divider.
+    /// * b8-b9: IWDTRPES, the end of the window.
+    /// * b10-b11: IWDTRPSS, the start of the window.
+    /// * b12: IWDTRSTIRQS, reset instead of a non-maskable interrupt.
+    /// * b14: IWDTSTPCTL, stop counting in sleep modes.
+    const OFS0: *const u32 = 0x00000400 as *const u32;
+
+    /// Returns the IWDT, unless it was taken already.
+    pub fn take() -> Option<Self> {
+        static mut TAKEN: bool = false;
+        interrupt::free(|| unsafe {
+            if TAKEN {
+                None
+            } else {
+                TAKEN = true;
+                Some(Self { _private: () })
+            }
+        })
+    }
+
+    /// Returns true if the option bytes enable the IWDT, so it has been running since the reset.
+    pub fn is_running(&self) -> bool {
+        unsafe { Self::OFS0.read_volatile() & (1 << 1) == 0 }
+    }
+
+    /// Returns the settings from the option bytes, or `None` if the IWDT is disabled.
+    pub fn settings(&self) -> Option<Settings> {
+        if self.is_running() {
+            Some(Settings::from_ofs0(unsafe { Self::OFS0.read_volatile() }))
+        } else {
+            None
+        }
+    }
+
+    /// Set the counter back to the timeout. If the window is restricted, this must only be called
+    /// while the counter is inside the window, otherwise the MCU is reset. Has no effect if the
+    /// IWDT is disabled.
+    #[inline]
+    pub fn feed(&mut self) {
+        unsafe {
+            Self::IWDTRR.write_volatile(0x00);
+            Self::IWDTRR.write_volatile(0xff);
+        }
+    }
+
+    /// Returns the current value of the counter, which counts down to 0.
+    pub fn counter(&self) -> u16 {
+        unsafe { Self::IWDTSR.read_volatile() & 0x3fff }
+    }
+
+    /// Returns the status flags. They are kept through the reset caused by the IWDT.
+    pub fn status(&self) -> Status {
+        let status = unsafe { Self::IWDTSR.read_volatile() };
+        Status {
+            underflow: status & Self::IWDTSR_UNDFF != 0,
+            refresh_error: status & Self::IWDTSR_REFEF != 0,
+        }
+    }
+
+    /// Clear the status flags.
+    pub fn clear_status(&mut self) {
+        unsafe {
+            Self::IWDTSR.write_volatile(0);
+        }
+    }
+}
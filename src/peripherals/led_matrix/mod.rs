@@ -0,0 +1,870 @@
+//! The 12x8 LED matrix on the board.
+//!
+//! The 96 LEDs are charlieplexed on 11 pins, P205 and the pins of [`LedMatrixPins`]: Each LED sits
+//! between two of them, and lights up when one is driven high and the other low while the rest
+//! are inputs. So only one LED can be lit at a time. [`LedMatrix`] keeps a framebuffer with a
+//! greyscale level per LED, and the underflow interrupt of the AGT1 timer lights the LEDs that are
+//! on one after the other, [`REFRESH_RATE`] times per second for each, fast enough that they all
+//! seem to be lit.
+//!
+//! By default, all LEDs that are on are fully on. [`LedMatrix::set_greyscale`] shows their levels
+//! with binary-coded modulation, and [`LedMatrix::set_brightness`] dims the whole matrix with the
+//! compare match interrupt of the timer. With [`LedMatrix::set_double_buffered`], drawing goes to a
+//! back buffer, which [`LedMatrix::swap`] shows between two refreshes.
+//!
+//! The refresh runs entirely in the interrupt handlers, so the matrix stays lit while the main
+//! loop blocks, e.g. in a TLS handshake, as long as interrupts aren't disabled.
+//! [`LedMatrix::cpu_load`] measures what it costs. The DTC isn't used for the port writes, since
+//! which LED to light next depends on the framebuffer.
+//!
+//! A [`Frame`] has one bit per LED, row by row from the top left, starting with the most
+//! significant bit of the first word. This is the format of the frames of the Arduino LED matrix
+//! library. Its animations, sequences of frames with a duration in ms as a fourth word, play with
+//! [`Animation`].
+//!
+//! [`text`] shows and scrolls text, and [`widgets`] has ready-made frames for status displays.
+//!
+//! For details on the timer, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Low Power
+//! Asynchronous General Purpose Timer (AGT)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::led_matrix::LedMatrix;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let mut matrix = LedMatrix::new(pins.led_matrix, pins.p205, mstp::token(), &clocks).unwrap();
+//! // A diagonal line.
+//! for i in 0..8 {
+//!     matrix.set_pixel(i, i, true);
+//! }
+//! // Fading in from the left, at a third of the brightness.
+//! matrix.set_greyscale(true);
+//! matrix.set_brightness(85);
+//! for x in 0..8 {
+//!     matrix.set_level(x + 4, 0, x as u8);
+//! }
+//! ```
+
+pub mod text;
+pub mod widgets;
+
+use super::clocks::{ClockDependent, Clocks};
+use super::icu::{self, Event, Slot};
+use super::mstp::{unit, MstpToken};
+use super::pins::{
+    LedMatrixPins, Pin, PinMode, PinModeInput, PinModeUnknown, P003, P004, P011, P012, P013, P015,
+    P204, P205, P206, P212, P213,
+};
+use super::systick::Delay;
+use crate::interrupt;
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+/// Number of columns.
+pub const WIDTH: usize = 12;
+
+/// Number of rows.
+pub const HEIGHT: usize = 8;
+
+/// Number of LEDs.
+const LED_COUNT: usize = WIDTH * HEIGHT;
+
+/// How often each LED is lit per second, in Hz.
+pub const REFRESH_RATE: u32 = 100;
+
+/// Number of greyscale levels of an LED, from 0 for off to `GREY_LEVELS - 1` for fully on.
+pub const GREY_LEVELS: u8 = 1 << GREY_BITS;
+
+/// Number of bits of a greyscale level.
+const GREY_BITS: usize = 3;
+
+/// The contents of the matrix, one bit per LED, see the [module documentation](self).
+pub type Frame = [u32; 3];
+
+/// A frame of an animation of the Arduino LED matrix library: a [`Frame`], followed by the time
+/// it is shown in ms.
+pub type AnimationFrame = [u32; 4];
+
+/// AGT1 Counter Register. Writing it sets the reload value and the counter.
+const AGT: *mut u16 = 0x40084100 as *mut u16;
+
+/// AGT1 Compare Match A Register. The compare match A happens when the counter reaches it.
+const AGTCMA: *mut u16 = 0x40084102 as *mut u16;
+
+/// AGT1 Control Register.
+/// * b0: TSTART, start counting.
+/// * b1: TCSTF, the counter is running.
+/// * b2: TSTOP, stop counting and reset the registers.
+/// * b5: TUNDF, an underflow happened. Cleared by writing 0.
+/// * b6: TCMAF, a compare match A happened. Cleared by writing 0.
+const AGTCR: *mut u8 = 0x40084108 as *mut u8;
+
+/// AGT1 Mode Register 1.
+/// * b0-b2: TMOD, 0 for timer mode.
+/// * b4-b6: TCK, the count source. 0 for PCLKB.
+const AGTMR1: *mut u8 = 0x40084109 as *mut u8;
+
+/// AGT1 Compare Match Function Select Register.
+/// * b0: TCMEA, enable compare match A.
+const AGTCMSR: *mut u8 = 0x4008410e as *mut u8;
+
+const AGTCR_TSTART: u8 = 1 << 0;
+const AGTCR_TCSTF: u8 = 1 << 1;
+const AGTCR_TSTOP: u8 = 1 << 2;
+const AGTMR1_PCLKB: u8 = 0;
+const AGTCMSR_TCMEA: u8 = 1 << 0;
+
+/// Address of the Port Control Register 1 of port 0, and the distance to the next port.
+/// * b0-b15: PDR, pin n is an output.
+/// * b16-b31: PODR, pin n outputs high.
+const PCNTR1_BASE: u32 = 0x40040000;
+const PCNTR1_GAP: u32 = 0x20;
+
+/// Debug Exception and Monitor Control Register of the CPU.
+/// * b24: TRCENA, enable the DWT unit.
+const DEMCR: *mut u32 = 0xe000edfc as *mut u32;
+
+/// DWT Control Register.
+/// * b0: CYCCNTENA, enable the cycle counter.
+const DWT_CTRL: *mut u32 = 0xe0001000 as *mut u32;
+
+/// DWT Cycle Count Register, counts the cycles of ICLK.
+const DWT_CYCCNT: *const u32 = 0xe0001004 as *const u32;
+
+const DEMCR_TRCENA: u32 = 1 << 24;
+const DWT_CTRL_CYCCNTENA: u32 = 1 << 0;
+
+/// The lines of the matrix as port and pin numbers, in the order of [`LEDS`].
+const LINES: [(u8, u8); 11] = [
+    (2, 5),
+    (0, 12),
+    (0, 13),
+    (0, 3),
+    (0, 4),
+    (0, 11),
+    (0, 15),
+    (2, 4),
+    (2, 6),
+    (2, 12),
+    (2, 13),
+];
+
+/// The lines of the matrix on ports 0 and 2.
+const PORT0_MASK: u32 = (1 << 3) | (1 << 4) | (1 << 11) | (1 << 12) | (1 << 13) | (1 << 15);
+const PORT2_MASK: u32 = (1 << 4) | (1 << 5) | (1 << 6) | (1 << 12) | (1 << 13);
+
+/// The lines that are driven high and low to light each LED, as indices into [`LINES`].
+const LEDS: [(u8, u8); LED_COUNT] = [
+    (7, 3),
+    (3, 7),
+    (7, 4),
+    (4, 7),
+    (3, 4),
+    (4, 3),
+    (7, 8),
+    (8, 7),
+    (3, 8),
+    (8, 3),
+    (4, 8),
+    (8, 4),
+    (7, 0),
+    (0, 7),
+    (3, 0),
+    (0, 3),
+    (4, 0),
+    (0, 4),
+    (8, 0),
+    (0, 8),
+    (7, 6),
+    (6, 7),
+    (3, 6),
+    (6, 3),
+    (4, 6),
+    (6, 4),
+    (8, 6),
+    (6, 8),
+    (0, 6),
+    (6, 0),
+    (7, 5),
+    (5, 7),
+    (3, 5),
+    (5, 3),
+    (4, 5),
+    (5, 4),
+    (8, 5),
+    (5, 8),
+    (0, 5),
+    (5, 0),
+    (6, 5),
+    (5, 6),
+    (7, 1),
+    (1, 7),
+    (3, 1),
+    (1, 3),
+    (4, 1),
+    (1, 4),
+    (8, 1),
+    (1, 8),
+    (0, 1),
+    (1, 0),
+    (6, 1),
+    (1, 6),
+    (5, 1),
+    (1, 5),
+    (7, 2),
+    (2, 7),
+    (3, 2),
+    (2, 3),
+    (4, 2),
+    (2, 4),
+    (8, 2),
+    (2, 8),
+    (0, 2),
+    (2, 0),
+    (6, 2),
+    (2, 6),
+    (5, 2),
+    (2, 5),
+    (1, 2),
+    (2, 1),
+    (7, 10),
+    (10, 7),
+    (3, 10),
+    (10, 3),
+    (4, 10),
+    (10, 4),
+    (8, 10),
+    (10, 8),
+    (0, 10),
+    (10, 0),
+    (6, 10),
+    (10, 6),
+    (5, 10),
+    (10, 5),
+    (1, 10),
+    (10, 1),
+    (2, 10),
+    (10, 2),
+    (7, 9),
+    (9, 7),
+    (3, 9),
+    (9, 3),
+    (4, 9),
+    (9, 4),
+];
+
+/// Errors of the LED matrix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// All interrupt slots are in use, see [`super::icu::attach`].
+    NoFreeSlot,
+    /// PCLKB is too fast or too slow for the refresh rate.
+    UnsupportedClock,
+}
+
+/// The bit planes of the greyscale levels, from the least significant bit.
+type Planes = [Frame; GREY_BITS];
+
+/// The front and back buffer. The interrupt handler reads the one at [`FRONT`].
+static mut BUFFERS: [Planes; 2] = [[[0; 3]; GREY_BITS]; 2];
+
+/// Index of the buffer that is shown, changed by the interrupt handler.
+static FRONT: AtomicUsize = AtomicUsize::new(0);
+
+/// Set to have the interrupt handler swap the buffers before the next refresh, cleared when it
+/// has.
+static SWAP_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Cycles spent in the interrupt handlers since the last [`LedMatrix::cpu_load`].
+static BUSY_CYCLES: AtomicU32 = AtomicU32::new(0);
+
+/// Position of the refresh, changed by the interrupt handler.
+static mut SCAN: Scan = Scan {
+    led: 0,
+    plane: 0,
+    ticks: 0,
+};
+
+/// True if the LEDs are shown with their greyscale levels, and false if all that are on are
+/// fully on.
+static mut GREYSCALE: bool = false;
+
+/// True while the global brightness is 0.
+static mut DARK: bool = false;
+
+/// Position of the refresh.
+struct Scan {
+    /// The LED that is lit.
+    led: usize,
+    /// The bit plane that is shown.
+    plane: usize,
+    /// Underflows of AGT1 until the next LED.
+    ticks: u8,
+}
+
+/// Returns true if `led` is on in `frame`.
+fn is_on(frame: &Frame, led: usize) -> bool {
+    frame[led / 32] & (1 << (31 - led % 32)) != 0
+}
+
+/// Turn `led` on or off in `frame`.
+fn set(frame: &mut Frame, led: usize, on: bool) {
+    let bit = 1 << (31 - led % 32);
+    if on {
+        frame[led / 32] |= bit;
+    } else {
+        frame[led / 32] &= !bit;
+    }
+}
+
+/// The PCNTR1 bits of ports 0 and 2 that light each LED, computed from [`LEDS`] and [`LINES`], so
+/// the interrupt handler doesn't have to.
+const LED_BITS: [[u32; 2]; LED_COUNT] = led_bits();
+
+const fn led_bits() -> [[u32; 2]; LED_COUNT] {
+    let mut bits = [[0; 2]; LED_COUNT];
+    let mut led = 0;
+    while led < LED_COUNT {
+        let (high, low) = LEDS[led];
+        let (port, pin) = LINES[high as usize];
+        // Output, high.
+        bits[led][port as usize / 2] |= (1 << pin) | (1 << (pin + 16));
+        let (port, pin) = LINES[low as usize];
+        // Output, low.
+        bits[led][port as usize / 2] |= 1 << pin;
+        led += 1;
+    }
+    bits
+}
+
+/// Make all lines inputs, then light `led` if it is `Some`.
+#[inline(always)]
+fn drive(led: Option<usize>) {
+    let port0 = PCNTR1_BASE as *mut u32;
+    let port2 = (PCNTR1_BASE + 2 * PCNTR1_GAP) as *mut u32;
+    unsafe {
+        let off0 = port0.read_volatile() & !(PORT0_MASK | PORT0_MASK << 16);
+        let off2 = port2.read_volatile() & !(PORT2_MASK | PORT2_MASK << 16);
+        // Turn the old LED off first, so no other LED flashes up between the two writes.
+        port0.write_volatile(off0);
+        port2.write_volatile(off2);
+        if let Some(led) = led {
+            let [on0, on2] = LED_BITS[led];
+            port0.write_volatile(off0 | on0);
+            port2.write_volatile(off2 | on2);
+        }
+    }
+}
+
+/// Returns the cycle counter of the CPU.
+#[inline(always)]
+fn cycle_count() -> u32 {
+    unsafe { DWT_CYCCNT.read_volatile() }
+}
+
+/// Add the cycles since `start` to the time spent in the interrupt handlers.
+#[inline(always)]
+fn account(start: u32) {
+    BUSY_CYCLES.fetch_add(cycle_count().wrapping_sub(start), Ordering::Relaxed);
+}
+
+/// Light the LED of the refresh, on the underflow of AGT1.
+///
+/// Without greyscale, each LED that has a level above 0 is lit for one period of AGT1. With
+/// greyscale, the refresh goes through the LEDs once per bit plane, and lights each LED whose
+/// level has the bit of the plane set for 2^n periods in plane n. This is binary-coded
+/// modulation: over a full refresh, an LED is lit for as many periods as its level.
+fn on_underflow() {
+    let start = cycle_count();
+    unsafe {
+        // Clear TUNDF, the timer keeps running.
+        AGTCR.write_volatile(AGTCR_TSTART);
+        let scan = &mut *ptr::addr_of_mut!(SCAN);
+        let greyscale = GREYSCALE;
+        if scan.ticks > 1 {
+            scan.ticks -= 1;
+        } else {
+            scan.led += 1;
+            if scan.led == LED_COUNT {
+                scan.led = 0;
+                scan.plane = (scan.plane + 1) % GREY_BITS;
+                // A refresh is complete, which is the time to show the back buffer.
+                if (!greyscale || scan.plane == 0) && SWAP_PENDING.load(Ordering::Acquire) {
+                    FRONT.store(1 - FRONT.load(Ordering::Relaxed), Ordering::Relaxed);
+                    SWAP_PENDING.store(false, Ordering::Release);
+                }
+            }
+            scan.ticks = if greyscale { 1 << scan.plane } else { 1 };
+        }
+        let planes = &*ptr::addr_of!(BUFFERS[FRONT.load(Ordering::Relaxed)]);
+        let on = if greyscale {
+            is_on(&planes[scan.plane], scan.led)
+        } else {
+            planes.iter().any(|plane| is_on(plane, scan.led))
+        };
+        drive((on && !DARK).then_some(scan.led));
+    }
+    account(start);
+}
+
+/// Turn the LED off for the rest of the period, on the compare match A of AGT1. This dims the
+/// matrix below full brightness.
+fn on_compare_match() {
+    let start = cycle_count();
+    unsafe { AGTCR.write_volatile(AGTCR_TSTART) };
+    drive(None);
+    account(start);
+}
+
+/// Returns the reload value of AGT1 for `pclkb`, with one underflow per period.
+fn reload_value(pclkb: u32, greyscale: bool) -> Option<u16> {
+    let periods = if greyscale { (1 << GREY_BITS) - 1 } else { 1 };
+    let counts = pclkb / (REFRESH_RATE * LED_COUNT as u32 * periods);
+    u16::try_from(counts.checked_sub(1)?).ok()
+}
+
+/// The lines of the matrix as inputs, by pin number.
+type Lines = (
+    P003<PinModeInput>,
+    P004<PinModeInput>,
+    P011<PinModeInput>,
+    P012<PinModeInput>,
+    P013<PinModeInput>,
+    P015<PinModeInput>,
+    P204<PinModeInput>,
+    P205<PinModeInput>,
+    P206<PinModeInput>,
+    P212<PinModeInput>,
+    P213<PinModeInput>,
+);
+
+/// The LED matrix.
+pub struct LedMatrix {
+    pins: Lines,
+    underflow_slot: Slot,
+    compare_match_slot: Slot,
+    _clock: MstpToken<unit::Agt1>,
+    pclkb: u32,
+    greyscale: bool,
+    brightness: u8,
+    double_buffered: bool,
+    load_start: u32,
+}
+
+impl LedMatrix {
+    /// Take the lines of the matrix and start refreshing it with AGT1, with all LEDs off, full
+    /// brightness and without greyscale.
+    pub fn new<M: PinMode>(
+        pins: LedMatrixPins,
+        p205: P205<M>,
+        clock: MstpToken<unit::Agt1>,
+        clocks: &Clocks,
+    ) -> Result<Self, Error> {
+        let pclkb = clocks.pclkb();
+        // Greyscale needs the shorter periods, so check them as well.
+        let reload = reload_value(pclkb, false)
+            .filter(|_| reload_value(pclkb, true).is_some())
+            .ok_or(Error::UnsupportedClock)?;
+        let underflow_slot =
+            icu::attach(Event::Agt1Underflow, on_underflow).ok_or(Error::NoFreeSlot)?;
+        let Some(compare_match_slot) = icu::attach(Event::Agt1CompareA, on_compare_match) else {
+            icu::detach(underflow_slot);
+            return Err(Error::NoFreeSlot);
+        };
+        interrupt::free(|| unsafe {
+            ptr::addr_of_mut!(BUFFERS).write_volatile([[[0; 3]; GREY_BITS]; 2]);
+            FRONT.store(0, Ordering::Relaxed);
+            SWAP_PENDING.store(false, Ordering::Relaxed);
+            BUSY_CYCLES.store(0, Ordering::Relaxed);
+            DEMCR.write_volatile(DEMCR.read_volatile() | DEMCR_TRCENA);
+            DWT_CTRL.write_volatile(DWT_CTRL.read_volatile() | DWT_CTRL_CYCCNTENA);
+            ptr::addr_of_mut!(SCAN).write_volatile(Scan {
+                led: 0,
+                plane: 0,
+                ticks: 0,
+            });
+            GREYSCALE = false;
+            DARK = false;
+        });
+        let mut matrix = Self {
+            pins: (
+                pins.p003.into_input(),
+                pins.p004.into_input(),
+                pins.p011.into_input(),
+                pins.p012.into_input(),
+                pins.p013.into_input(),
+                pins.p015.into_input(),
+                pins.p204.into_input(),
+                p205.into_input(),
+                pins.p206.into_input(),
+                pins.p212.into_input(),
+                pins.p213.into_input(),
+            ),
+            underflow_slot,
+            compare_match_slot,
+            _clock: clock,
+            pclkb,
+            greyscale: false,
+            brightness: u8::MAX,
+            double_buffered: false,
+            load_start: 0,
+        };
+        unsafe {
+            AGTMR1.write_volatile(AGTMR1_PCLKB);
+            AGT.write_volatile(reload);
+            AGTCR.write_volatile(AGTCR_TSTART);
+        }
+        matrix.load_start = cycle_count();
+        Ok(matrix)
+    }
+
+    /// Turn the LED in column `x` and row `y` from the top left on or off. An LED that is on has
+    /// the highest greyscale level. Does nothing outside the matrix.
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        self.set_level(x, y, if on { GREY_LEVELS - 1 } else { 0 });
+    }
+
+    /// Returns true if the LED in column `x` and row `y` is on, that is, its greyscale level is
+    /// above 0.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.level(x, y) > 0
+    }
+
+    /// Set the greyscale level of the LED in column `x` and row `y`, up to `GREY_LEVELS - 1`.
+    /// Higher levels are capped. Does nothing outside the matrix.
+    ///
+    /// The levels only show with [`LedMatrix::set_greyscale`], otherwise all LEDs with a level
+    /// above 0 are fully on.
+    pub fn set_level(&mut self, x: usize, y: usize, level: u8) {
+        if x >= WIDTH || y >= HEIGHT {
+            return;
+        }
+        let led = y * WIDTH + x;
+        let level = level.min(GREY_LEVELS - 1);
+        let target = self.target();
+        interrupt::free(|| unsafe {
+            let planes = &mut *ptr::addr_of_mut!(BUFFERS[target]);
+            for (bit, plane) in planes.iter_mut().enumerate() {
+                set(plane, led, level & (1 << bit) != 0);
+            }
+        });
+    }
+
+    /// Returns the greyscale level of the LED in column `x` and row `y`, 0 outside the matrix.
+    pub fn level(&self, x: usize, y: usize) -> u8 {
+        if x >= WIDTH || y >= HEIGHT {
+            return 0;
+        }
+        let led = y * WIDTH + x;
+        let planes = self.planes();
+        planes
+            .iter()
+            .enumerate()
+            .filter(|(_, plane)| is_on(plane, led))
+            .map(|(bit, _)| 1 << bit)
+            .sum()
+    }
+
+    /// Show `frame`, with the LEDs that are on at the highest greyscale level.
+    pub fn set_frame(&mut self, frame: &Frame) {
+        self.set_planes(&[*frame; GREY_BITS]);
+    }
+
+    /// Returns the frame that is shown, with the LEDs on whose greyscale level is above 0.
+    pub fn frame(&self) -> Frame {
+        let planes = self.planes();
+        let mut frame = [0; 3];
+        for plane in planes {
+            for (word, bits) in frame.iter_mut().zip(plane) {
+                *word |= bits;
+            }
+        }
+        frame
+    }
+
+    /// Turn all LEDs off.
+    pub fn clear(&mut self) {
+        self.set_frame(&[0; 3]);
+    }
+
+    /// Draw to a back buffer if `enabled`, which only shows with [`LedMatrix::swap`], so frames
+    /// never appear half-drawn. Otherwise, changes show right away.
+    ///
+    /// When double buffering is enabled, the back buffer starts as a copy of the frame that is
+    /// shown. The methods that read the frame or levels read the back buffer.
+    pub fn set_double_buffered(&mut self, enabled: bool) {
+        if enabled && !self.double_buffered {
+            let front = self.planes();
+            self.double_buffered = true;
+            self.set_planes(&front);
+        }
+        self.double_buffered = enabled;
+    }
+
+    /// Returns true if the matrix is double buffered.
+    pub fn double_buffered(&self) -> bool {
+        self.double_buffered
+    }
+
+    /// Show the back buffer from the next refresh on, and wait until it is shown, for up to one
+    /// refresh. The new back buffer is a copy of it, so the next frame can be drawn on top.
+    ///
+    /// Does nothing if the matrix isn't double buffered.
+    pub fn swap(&mut self) {
+        if !self.double_buffered {
+            return;
+        }
+        SWAP_PENDING.store(true, Ordering::Release);
+        while SWAP_PENDING.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+        let front =
+            unsafe { ptr::addr_of!(BUFFERS[FRONT.load(Ordering::Relaxed)]).read_volatile() };
+        self.set_planes(&front);
+    }
+
+    /// Returns the share of the CPU time that the refresh took since the last call, or since
+    /// [`LedMatrix::new`] for the first one, in per mille.
+    ///
+    /// This counts the cycles spent in the interrupt handlers of the matrix, not the entry and
+    /// exit of the interrupts, about 30 cycles each. The cycle counter wraps after 2^32 cycles of
+    /// ICLK, about 89 s at 48 MHz, so the result is only right if it is called more often.
+    ///
+    /// Without greyscale and at full brightness, the refresh takes about 2% of the CPU at 48 MHz.
+    /// Greyscale multiplies that by 7, and dimming by 2.
+    pub fn cpu_load(&mut self) -> u32 {
+        let now = cycle_count();
+        let busy = BUSY_CYCLES.swap(0, Ordering::Relaxed);
+        let total = now.wrapping_sub(self.load_start).max(1);
+        self.load_start = now;
+        (busy as u64 * 1000 / total as u64).min(1000) as u32
+    }
+
+    /// Returns the index of the buffer that is drawn to.
+    fn target(&self) -> usize {
+        let front = FRONT.load(Ordering::Relaxed);
+        if self.double_buffered {
+            1 - front
+        } else {
+            front
+        }
+    }
+
+    /// Returns the bit planes of the buffer that is drawn to.
+    fn planes(&self) -> Planes {
+        let target = self.target();
+        interrupt::free(|| unsafe { ptr::addr_of!(BUFFERS[target]).read_volatile() })
+    }
+
+    /// Replace the bit planes of the buffer that is drawn to.
+    fn set_planes(&mut self, planes: &Planes) {
+        let target = self.target();
+        interrupt::free(|| unsafe { ptr::addr_of_mut!(BUFFERS[target]).write_volatile(*planes) });
+    }
+
+    /// Show the greyscale levels of the LEDs if `enabled`, or all LEDs that are on fully on.
+    ///
+    /// With greyscale, the refresh interrupt comes `GREY_LEVELS - 1` times as often.
+    pub fn set_greyscale(&mut self, enabled: bool) {
+        self.greyscale = enabled;
+        interrupt::free(|| unsafe {
+            GREYSCALE = enabled;
+            (*ptr::addr_of_mut!(SCAN)).ticks = 0;
+        });
+        self.set_timing();
+    }
+
+    /// Returns true if the greyscale levels are shown.
+    pub fn greyscale(&self) -> bool {
+        self.greyscale
+    }
+
+    /// Set the brightness of the whole matrix, from 0 for off to 255 for full brightness.
+    ///
+    /// The LEDs are dimmed by turning them off early in each period of the refresh, with a
+    /// second interrupt per period below full brightness.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+        interrupt::free(|| unsafe { DARK = brightness == 0 });
+        self.set_timing();
+    }
+
+    /// Returns the brightness of the whole matrix.
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Set the period of AGT1 for the greyscale setting, and the compare match for the
+    /// brightness.
+    fn set_timing(&mut self) {
+        // Checked by `new`, and `set_clocks` keeps the old clock if it doesn't fit.
+        let Some(reload) = reload_value(self.pclkb, self.greyscale) else {
+            return;
+        };
+        // The counter counts down from the reload value, so the LED is lit until it reaches
+        // the compare value.
+        let lit = reload as u32 * self.brightness as u32 / u8::MAX as u32;
+        unsafe {
+            AGT.write_volatile(reload);
+            AGTCMA.write_volatile(reload - lit as u16);
+            let enabled = if self.brightness == u8::MAX || self.brightness == 0 {
+                0
+            } else {
+                AGTCMSR_TCMEA
+            };
+            AGTCMSR.write_volatile(enabled);
+        }
+    }
+
+    /// Stop refreshing, turn the LEDs off and return the lines.
+    pub fn release(self) -> (LedMatrixPins, P205<PinModeUnknown>) {
+        unsafe {
+            AGTCR.write_volatile(AGTCR_TSTOP);
+            while AGTCR.read_volatile() & AGTCR_TCSTF != 0 {}
+        }
+        icu::detach(self.underflow_slot);
+        icu::detach(self.compare_match_slot);
+        drive(None);
+        let pins = self.pins;
+        (
+            LedMatrixPins {
+                p003: pins.0.into_unknown(),
+                p004: pins.1.into_unknown(),
+                p011: pins.2.into_unknown(),
+                p012: pins.3.into_unknown(),
+                p013: pins.4.into_unknown(),
+                p015: pins.5.into_unknown(),
+                p204: pins.6.into_unknown(),
+                p206: pins.8.into_unknown(),
+                p212: pins.9.into_unknown(),
+                p213: pins.10.into_unknown(),
+            },
+            pins.7.into_unknown(),
+        )
+    }
+}
+
+/// Plays an animation of the Arduino LED matrix library on a [`LedMatrix`].
+///
+/// The frames can be copied from the animations of the library, or from the export of its
+/// LED matrix editor, as `[u32; 4]` arrays. There is no time base, so [`Animation::poll`] gets
+/// the time that passed since the last call, or [`Animation::play`] waits with a [`Delay`].
+///
+/// Example:
+/// ```
+/// use arduino_uno_r4_wifi_rt::peripherals::led_matrix::{Animation, AnimationFrame};
+///
+/// const BLINK: [AnimationFrame; 2] = [
+///     [0x19819, 0x80000001, 0x81f8000, 500],
+///     [0, 0, 0, 500],
+/// ];
+///
+/// // With `matrix` set up as in the module example.
+/// let mut animation = Animation::new(&BLINK).with_loop(true);
+/// loop {
+///     animation.poll(&mut matrix, 10);
+///     // Wait 10 ms or do something else.
+/// }
+/// ```
+pub struct Animation<'a> {
+    frames: &'a [AnimationFrame],
+    looping: bool,
+    next: usize,
+    remaining_ms: u32,
+    done: bool,
+}
+
+impl<'a> Animation<'a> {
+    /// An animation of `frames` that plays once, starting with the first call of
+    /// [`Animation::poll`].
+    pub fn new(frames: &'a [AnimationFrame]) -> Self {
+        Self {
+            frames,
+            looping: false,
+            next: 0,
+            remaining_ms: 0,
+            done: frames.is_empty(),
+        }
+    }
+
+    /// Start over with the first frame after the last one if `looping`.
+    pub fn with_loop(self, looping: bool) -> Self {
+        Self { looping, ..self }
+    }
+
+    /// Returns true if the last frame has been shown for its duration, which doesn't happen
+    /// when the animation loops.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Start over with the first frame at the next call of [`Animation::poll`].
+    pub fn restart(&mut self) {
+        self.next = 0;
+        self.remaining_ms = 0;
+        self.done = self.frames.is_empty();
+    }
+
+    /// Show the frame that is due on `matrix`, `elapsed_ms` ms after the last call. Returns false
+    /// once the animation is done. The last frame stays on the matrix.
+    pub fn poll(&mut self, matrix: &mut LedMatrix, elapsed_ms: u32) -> bool {
+        if self.done {
+            return false;
+        }
+        let mut elapsed_ms = elapsed_ms;
+        // Skip the frames whose time has passed, but go through the animation at most once, in
+        // case all durations are 0.
+        let mut shown = None;
+        for _ in 0..=self.frames.len() {
+            if elapsed_ms < self.remaining_ms {
+                break;
+            }
+            elapsed_ms -= self.remaining_ms;
+            if self.next == self.frames.len() {
+                if !self.looping {
+                    self.done = true;
+                    break;
+                }
+                self.next = 0;
+            }
+            let frame = &self.frames[self.next];
+            shown = Some(frame);
+            self.remaining_ms = frame[3];
+            self.next += 1;
+        }
+        self.remaining_ms = self.remaining_ms.saturating_sub(elapsed_ms);
+        if let Some(frame) = shown {
+            matrix.set_frame(&[frame[0], frame[1], frame[2]]);
+        }
+        !self.done
+    }
+
+    /// Play the animation on `matrix` until it is done, waiting with `delay`. If it loops, this
+    /// doesn't return.
+    pub fn play(&mut self, matrix: &mut LedMatrix, delay: &mut Delay) {
+        let mut elapsed_ms = 0;
+        while self.poll(matrix, elapsed_ms) {
+            elapsed_ms = self.remaining_ms.max(1);
+            delay.delay_ms(elapsed_ms);
+        }
+    }
+}
+
+impl ClockDependent for LedMatrix {
+    /// Keep the refresh rate with the new PCLKB. If it can't be kept, the rate changes with the
+    /// clock.
+    fn set_clocks(&mut self, clocks: &Clocks) {
+        let pclkb = clocks.pclkb();
+        if reload_value(pclkb, false).is_some() && reload_value(pclkb, true).is_some() {
+            self.pclkb = pclkb;
+            self.set_timing();
+        }
+    }
+}
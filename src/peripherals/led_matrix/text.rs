@@ -0,0 +1,263 @@
+//! Text on the LED matrix, in a 5x7 font.
+//!
+//! The matrix fits two characters. [`print`] shows the start of a text, and [`scroll`] moves a
+//! longer one across the matrix from right to left. The [`matrix_print!`](crate::matrix_print)
+//! and [`matrix_scroll!`](crate::matrix_scroll) macros format the text first, like `write!`,
+//! which makes the matrix a debug output that needs no wires.
+//!
+//! The font has the printable ASCII characters. Others show as `?`.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::systick::{Delay, SysTick};
+//! use arduino_uno_r4_wifi_rt::{matrix_print, matrix_scroll};
+//!
+//! // With `clocks` and `matrix` set up as in the `led_matrix` example.
+//! let mut delay = Delay::new(SysTick::instance().unwrap(), &clocks);
+//! matrix_print!(&mut matrix, "{}", 42);
+//! matrix_scroll!(&mut matrix, &mut delay, "T = {} C", 21);
+//! ```
+
+use super::{set, Frame, LedMatrix, HEIGHT, WIDTH};
+use crate::peripherals::systick::Delay;
+
+use core::fmt;
+
+/// Width of a character in columns.
+pub const GLYPH_WIDTH: usize = 5;
+
+/// Columns per character, with the space to the next one.
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+
+/// Capacity of the text of the macros in bytes. Longer texts are cut off.
+pub const TEXT_CAPACITY: usize = 64;
+
+/// Time per column of [`scroll`] in the macros, in ms.
+pub const SCROLL_STEP_MS: u32 = 80;
+
+/// The glyphs from ` ` to `~`, one byte per column from the left, with the top row in b0.
+const FONT: [[u8; GLYPH_WIDTH]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // '#'
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '''
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // ')'
+    [0x08, 0x2a, 0x1c, 0x2a, 0x08], // '*'
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // '+'
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // '0'
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // '1'
+    [0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // '3'
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // '6'
+    [0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // '9'
+    [0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+    [0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // '@'
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // 'A'
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // 'D'
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7f, 0x09, 0x09, 0x09, 0x01], // 'F'
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], // 'G'
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // 'H'
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // 'J'
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], // 'M'
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // 'N'
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // 'O'
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // 'Q'
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // 'T'
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // 'U'
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // 'V'
+    [0x3f, 0x40, 0x38, 0x40, 0x3f], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x07, 0x08, 0x70, 0x08, 0x07], // 'Y'
+    [0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+    [0x00, 0x7f, 0x41, 0x41, 0x00], // '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], // '\'
+    [0x00, 0x41, 0x41, 0x7f, 0x00], // ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+    [0x40, 0x40, 0x40, 0x40, 0x40], // '_'
+    [0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+    [0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // 'b'
+    [0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // 'd'
+    [0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // 'f'
+    [0x0c, 0x52, 0x52, 0x52, 0x3e], // 'g'
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // 'h'
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // 'i'
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // 'j'
+    [0x7f, 0x10, 0x28, 0x44, 0x00], // 'k'
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // 'l'
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // 'm'
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // 'n'
+    [0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // 'p'
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // 'q'
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // 'r'
+    [0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // 't'
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // 'u'
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // 'v'
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // 'y'
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // 'z'
+    [0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // '|'
+    [0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+    [0x08, 0x04, 0x08, 0x10, 0x08], // '~'
+];
+
+/// Returns the glyph of `c`, or of `?` if the font doesn't have it.
+fn glyph(c: char) -> &'static [u8; GLYPH_WIDTH] {
+    let index = match c {
+        ' '..='~' => c as usize - ' ' as usize,
+        _ => '?' as usize - ' ' as usize,
+    };
+    &FONT[index]
+}
+
+/// Returns the width of `text` in columns, with a space after each character.
+pub fn width(text: &str) -> usize {
+    text.chars().count() * ADVANCE
+}
+
+/// Returns the frame that shows `text` from column `offset` of the text on, which may be
+/// negative to start further right. The text starts at the top row.
+pub fn render(text: &str, offset: isize) -> Frame {
+    let mut frame = [0; 3];
+    for (index, c) in text.chars().enumerate() {
+        let start = (index * ADVANCE) as isize - offset;
+        if start >= WIDTH as isize {
+            break;
+        }
+        for (column, bits) in glyph(c).iter().enumerate() {
+            let x = start + column as isize;
+            if !(0..WIDTH as isize).contains(&x) {
+                continue;
+            }
+            for y in 0..HEIGHT {
+                if bits & (1 << y) != 0 {
+                    set(&mut frame, y * WIDTH + x as usize, true);
+                }
+            }
+        }
+    }
+    frame
+}
+
+/// Show the first two characters of `text` on `matrix`.
+pub fn print(matrix: &mut LedMatrix, text: &str) {
+    matrix.set_frame(&render(text, 0));
+}
+
+/// Move `text` across `matrix`, from just beyond the right edge until it has left on the left,
+/// one column every `step_ms` ms, waiting with `delay`. The matrix is empty afterwards.
+pub fn scroll(matrix: &mut LedMatrix, delay: &mut Delay, text: &str, step_ms: u32) {
+    for offset in -(WIDTH as isize)..=width(text) as isize {
+        matrix.set_frame(&render(text, offset));
+        delay.delay_ms(step_ms);
+    }
+}
+
+/// A text formatted by the macros, cut off after `N` bytes.
+pub struct TextBuffer<const N: usize> {
+    buffer: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> TextBuffer<N> {
+    /// An empty text.
+    pub fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Returns the text.
+    pub fn as_str(&self) -> &str {
+        // Only whole characters are written.
+        core::str::from_utf8(&self.buffer[..self.len]).unwrap_or_default()
+    }
+}
+
+impl<const N: usize> Default for TextBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for TextBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(N - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// Format a text like `write!` and show its first two characters on a
+/// [`LedMatrix`](crate::peripherals::led_matrix::LedMatrix).
+///
+/// `matrix_print!(&mut matrix, "{}", value)`
+#[macro_export]
+macro_rules! matrix_print {
+    ($matrix:expr, $($arg:tt)*) => {{
+        let mut text = $crate::peripherals::led_matrix::text::TextBuffer::<
+            { $crate::peripherals::led_matrix::text::TEXT_CAPACITY },
+        >::new();
+        let _ = ::core::fmt::Write::write_fmt(&mut text, ::core::format_args!($($arg)*));
+        $crate::peripherals::led_matrix::text::print($matrix, text.as_str());
+    }};
+}
+
+/// Format a text like `write!` and scroll it across a
+/// [`LedMatrix`](crate::peripherals::led_matrix::LedMatrix), waiting with a
+/// [`Delay`](crate::peripherals::systick::Delay). Returns when the text has passed.
+///
+/// `matrix_scroll!(&mut matrix, &mut delay, "{}", value)`
+#[macro_export]
+macro_rules! matrix_scroll {
+    ($matrix:expr, $delay:expr, $($arg:tt)*) => {{
+        let mut text = $crate::peripherals::led_matrix::text::TextBuffer::<
+            { $crate::peripherals::led_matrix::text::TEXT_CAPACITY },
+        >::new();
+        let _ = ::core::fmt::Write::write_fmt(&mut text, ::core::format_args!($($arg)*));
+        $crate::peripherals::led_matrix::text::scroll(
+            $matrix,
+            $delay,
+            text.as_str(),
+            $crate::peripherals::led_matrix::text::SCROLL_STEP_MS,
+        );
+    }};
+}
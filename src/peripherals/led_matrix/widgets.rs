@@ -0,0 +1,213 @@
+//! Ready-made frames for status displays: bars, a progress bar and icons.
+//!
+//! The functions return a [`Frame`], which [`LedMatrix::set_frame`] shows, or which can be
+//! combined with others bit by bit.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::led_matrix::widgets::{self, Icon};
+//!
+//! // With `matrix` set up as in the `led_matrix` example.
+//! matrix.set_frame(&widgets::progress(3, 4));
+//! matrix.set_frame(&Icon::Check.frame());
+//! ```
+//!
+//! [`LedMatrix::set_frame`]: super::LedMatrix::set_frame
+
+use super::{set, AnimationFrame, Frame, HEIGHT, WIDTH};
+
+/// Direction in which a bar grows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Orientation {
+    /// From the left to the right.
+    Horizontal,
+    /// From the bottom to the top.
+    Vertical,
+}
+
+/// Preset icons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Icon {
+    /// A heart.
+    Heart,
+    /// A small heart, which alternates with [`Icon::Heart`] in [`HEARTBEAT`].
+    SmallHeart,
+    /// A check mark.
+    Check,
+    /// A cross.
+    Cross,
+    /// A smiling face.
+    Smile,
+    /// A sad face.
+    Sad,
+    /// The WiFi symbol.
+    Wifi,
+}
+
+impl Icon {
+    /// Returns the frame of the icon.
+    pub const fn frame(self) -> Frame {
+        match self {
+            Icon::Heart => HEART,
+            Icon::SmallHeart => SMALL_HEART,
+            Icon::Check => CHECK,
+            Icon::Cross => CROSS,
+            Icon::Smile => SMILE,
+            Icon::Sad => SAD,
+            Icon::Wifi => WIFI,
+        }
+    }
+}
+
+/// A beating heart, for [`Animation`](super::Animation), to show that the program is alive.
+pub const HEARTBEAT: [AnimationFrame; 2] = [
+    [HEART[0], HEART[1], HEART[2], 400],
+    [SMALL_HEART[0], SMALL_HEART[1], SMALL_HEART[2], 600],
+];
+
+const HEART: Frame = from_rows([
+    b"............",
+    b"...XX..XX...",
+    b"..XXXXXXXX..",
+    b"..XXXXXXXX..",
+    b"...XXXXXX...",
+    b"....XXXX....",
+    b".....XX.....",
+    b"............",
+]);
+
+const SMALL_HEART: Frame = from_rows([
+    b"............",
+    b"............",
+    b"...XX..XX...",
+    b"...XXXXXX...",
+    b"....XXXX....",
+    b".....XX.....",
+    b"............",
+    b"............",
+]);
+
+const CHECK: Frame = from_rows([
+    b"............",
+    b"..........X.",
+    b".........X..",
+    b"........X...",
+    b"..X....X....",
+    b"...X..X.....",
+    b"....XX......",
+    b"............",
+]);
+
+const CROSS: Frame = from_rows([
+    b"............",
+    b"...X....X...",
+    b"....X..X....",
+    b".....XX.....",
+    b".....XX.....",
+    b"....X..X....",
+    b"...X....X...",
+    b"............",
+]);
+
+const SMILE: Frame = from_rows([
+    b"....XXXX....",
+    b"...X....X...",
+    b"..X.X..X.X..",
+    b"..X......X..",
+    b"..X.X..X.X..",
+    b"..X..XX..X..",
+    b"...X....X...",
+    b"....XXXX....",
+]);
+
+const SAD: Frame = from_rows([
+    b"....XXXX....",
+    b"...X....X...",
+    b"..X.X..X.X..",
+    b"..X......X..",
+    b"..X..XX..X..",
+    b"..X.X..X.X..",
+    b"...X....X...",
+    b"....XXXX....",
+]);
+
+const WIFI: Frame = from_rows([
+    b"............",
+    b"...XXXXXX...",
+    b"..X......X..",
+    b".X..XXXX..X.",
+    b"...X....X...",
+    b".....XX.....",
+    b".....XX.....",
+    b"............",
+]);
+
+/// Returns the frame with the LEDs on that are `X` in `rows`.
+const fn from_rows(rows: [&[u8; WIDTH]; HEIGHT]) -> Frame {
+    let mut frame = [0; 3];
+    let mut led = 0;
+    while led < WIDTH * HEIGHT {
+        if rows[led / WIDTH][led % WIDTH] == b'X' {
+            frame[led / 32] |= 1 << (31 - led % 32);
+        }
+        led += 1;
+    }
+    frame
+}
+
+/// Returns how many of `length` LEDs show `value` of `max`, rounded to the nearest one.
+fn scale(value: u32, max: u32, length: usize) -> usize {
+    if max == 0 {
+        return 0;
+    }
+    let value = value.min(max) as u64;
+    ((value * length as u64 + max as u64 / 2) / max as u64) as usize
+}
+
+/// Turn on the LEDs of the rectangle from column `x` and row `y` with `width` and `height`.
+fn fill(frame: &mut Frame, x: usize, y: usize, width: usize, height: usize) {
+    for row in y..y + height {
+        for column in x..x + width {
+            set(frame, row * WIDTH + column, true);
+        }
+    }
+}
+
+/// Returns a bar over the whole matrix that shows `value` of `max`.
+pub fn bar(value: u32, max: u32, orientation: Orientation) -> Frame {
+    let mut frame = [0; 3];
+    match orientation {
+        Orientation::Horizontal => fill(&mut frame, 0, 0, scale(value, max, WIDTH), HEIGHT),
+        Orientation::Vertical => {
+            let height = scale(value, max, HEIGHT);
+            fill(&mut frame, 0, HEIGHT - height, WIDTH, height);
+        }
+    }
+    frame
+}
+
+/// Returns a bar graph of `values` of `max`, with one column per value from the left, growing
+/// from the bottom. Values beyond the 12 columns are left out.
+pub fn bar_graph(values: &[u32], max: u32) -> Frame {
+    let mut frame = [0; 3];
+    for (x, &value) in values.iter().take(WIDTH).enumerate() {
+        let height = scale(value, max, HEIGHT);
+        fill(&mut frame, x, HEIGHT - height, 1, height);
+    }
+    frame
+}
+
+/// Returns a progress bar that shows `done` of `total` steps, filling a box in the middle of the
+/// matrix from the left.
+pub fn progress(done: u32, total: u32) -> Frame {
+    let mut frame = [0; 3];
+    // The outline, 4 rows high.
+    fill(&mut frame, 0, 2, WIDTH, 1);
+    fill(&mut frame, 0, 5, WIDTH, 1);
+    fill(&mut frame, 0, 3, 1, 2);
+    fill(&mut frame, WIDTH - 1, 3, 1, 2);
+    fill(&mut frame, 1, 3, scale(done, total, WIDTH - 2), 2);
+    frame
+}
@@ -0,0 +1,122 @@
+//! Millisecond counter on AGT0, for [`millis`] and [`micros`] like those of the Arduino core.
+//!
+//! [`start`] lets AGT0 underflow every millisecond, and its interrupt counts the milliseconds.
+//! [`micros`] adds the progress of the counter within the current millisecond. This is for the
+//! whole program, so AGT0 and an interrupt slot stay in use from then on. [`crate::board::init`]
+//! and [`crate::arduino::begin`] start it.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Low
+//! Power Asynchronous General Purpose Timer (AGT)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::{millis, mstp};
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! millis::start(mstp::token(), &clocks).unwrap();
+//! let start = millis::millis();
+//! while millis::millis().wrapping_sub(start) < 500 {}
+//! ```
+
+use super::clocks::Clocks;
+use super::icu::{self, Event};
+use super::mstp::{unit, MstpToken};
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// AGT0 Counter Register. Writing it sets the reload value and the counter, reading it returns
+/// the counter.
+const AGT: *mut u16 = 0x40084000 as *mut u16;
+
+/// AGT0 Control Register.
+/// * b0: TSTART, start counting.
+/// * b5: TUNDF, an underflow happened. Cleared by writing 0.
+const AGTCR: *mut u8 = 0x40084008 as *mut u8;
+
+/// AGT0 Mode Register 1.
+/// * b0-b2: TMOD, 0 for timer mode.
+/// * b4-b6: TCK, the count source. 1 for PCLKB / 8.
+const AGTMR1: *mut u8 = 0x40084009 as *mut u8;
+
+const AGTCR_TSTART: u8 = 1 << 0;
+const AGTMR1_PCLKB_8: u8 = 1 << 4;
+
+/// Set by [`start`].
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Milliseconds since [`start`], counted by the AGT0 interrupt.
+static MILLIS: AtomicU32 = AtomicU32::new(0);
+
+/// Counts of AGT0 per millisecond.
+static COUNTS_PER_MS: AtomicU32 = AtomicU32::new(0);
+
+/// Errors of [`start`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// [`start`] was called before.
+    AlreadyStarted,
+    /// All interrupt slots are in use, see [`icu::attach`].
+    NoFreeSlot,
+    /// PCLKB is too fast or too slow for a 1 ms period of AGT0.
+    UnsupportedClock,
+}
+
+/// Start counting the milliseconds with AGT0, whose clock keeps running from now on.
+pub fn start(clock: MstpToken<unit::Agt0>, clocks: &Clocks) -> Result<(), Error> {
+    let counts = clocks.pclkb() / 8 / 1000;
+    let reload = u16::try_from(counts.saturating_sub(1))
+        .ok()
+        .filter(|&reload| reload > 0)
+        .ok_or(Error::UnsupportedClock)?;
+    if STARTED.swap(true, Ordering::AcqRel) {
+        return Err(Error::AlreadyStarted);
+    }
+    if icu::attach(Event::Agt0Underflow, on_tick).is_none() {
+        STARTED.store(false, Ordering::Release);
+        return Err(Error::NoFreeSlot);
+    }
+    core::mem::forget(clock);
+    COUNTS_PER_MS.store(counts, Ordering::Relaxed);
+    unsafe {
+        AGTMR1.write_volatile(AGTMR1_PCLKB_8);
+        AGT.write_volatile(reload);
+        AGTCR.write_volatile(AGTCR_TSTART);
+    }
+    Ok(())
+}
+
+/// Returns true if [`start`] was called.
+pub fn is_started() -> bool {
+    STARTED.load(Ordering::Relaxed)
+}
+
+/// Count a millisecond, on the underflow of AGT0.
+fn on_tick() {
+    unsafe { AGTCR.write_volatile(AGTCR_TSTART) };
+    MILLIS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the milliseconds since [`start`]. Wraps around after about 50 days.
+pub fn millis() -> u32 {
+    MILLIS.load(Ordering::Relaxed)
+}
+
+/// Returns the microseconds since [`start`], or 0 before. Wraps around after about 71 minutes.
+pub fn micros() -> u32 {
+    let counts_per_ms = COUNTS_PER_MS.load(Ordering::Relaxed);
+    if counts_per_ms == 0 {
+        return 0;
+    }
+    loop {
+        let ms = millis();
+        // The counter counts down from the reload value.
+        let counter = unsafe { AGT.read_volatile() } as u32;
+        if millis() == ms {
+            let us = (counts_per_ms - 1).saturating_sub(counter) * 1000 / counts_per_ms;
+            return ms.wrapping_mul(1000).wrapping_add(us);
+        }
+    }
+}
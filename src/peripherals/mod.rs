@@ -1,4 +1,35 @@
+pub mod acmplp;
+pub mod adc;
+pub mod async_delay;
+pub mod can;
+pub mod clocks;
+pub mod crc;
+pub mod device_id;
+pub mod dma;
+pub mod dtc;
+pub mod elc;
+pub mod esp32;
+pub mod flash;
+pub mod icu;
+pub mod iic;
+pub mod iwdt;
+pub mod led_matrix;
+pub mod millis;
+pub mod mstp;
+pub mod nmi;
+pub mod opamp;
 pub mod pins;
+pub mod power;
+pub mod reset;
+pub mod rtc;
+pub mod slcdc;
+pub mod smbus;
+pub mod spi;
 pub mod systick;
+pub mod tsn;
+#[cfg(feature = "usb-device")]
+pub mod usb;
+pub mod watchdog;
+pub mod wdt;
 
-mod registers;
+pub(crate) mod registers;
@@ -0,0 +1,294 @@
+//! Module stop control: Start and stop the clocks of the peripheral units.
+//!
+//! After a reset, most units of the RA4M1 are stopped, and their registers can't be written until
+//! their clock is started in one of the Module Stop Control Registers. [`token`] does this and
+//! returns an [`MstpToken`], which names the unit in its type, e.g. `MstpToken<unit::Crc>`. The
+//! clock keeps running until the token is dropped, unless another token of the unit still exists,
+//! so units that aren't used don't draw power.
+//!
+//! The constructors of the drivers take the token of their unit and keep it, so a driver can't be
+//! constructed while its unit is stopped, which would leave its register writes without effect.
+//! Functions that use a unit only for a moment, like [`super::adc::read_vcc_millivolts`], borrow
+//! the token instead.
+//!
+//! [`token`] also lets programs that drive a unit through its registers start it the same way.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Low
+//! Power Modes", section "Module-Stop Function".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::crc::Crc;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp::{self, unit, Module};
+//!
+//! let clock = mstp::token::<unit::Crc>();
+//! assert!(mstp::is_running(Module::Crc));
+//! let crc = Crc::take(clock).unwrap();
+//! // Use the CRC calculator, then stop it again:
+//! drop(crc);
+//!
+//! let gpt: mstp::MstpToken<unit::Gpt16> = mstp::token();
+//! assert_eq!(gpt.module(), Module::Gpt16);
+//! ```
+
+use super::registers::{self, VolatileBoolOps, PRCR_PRC1};
+use crate::interrupt;
+
+use core::marker::PhantomData;
+use core::ptr;
+
+/// Module Stop Control Register A, protected by PRC1 of PRCR.
+/// * b22: MSTPA22, the DMAC and the DTC.
+const MSTPCRA: *mut u32 = 0x4001e01c as *mut u32;
+
+/// Module Stop Control Registers B, C and D. See [`Module`] for the bits.
+const MSTPCRB: *mut u32 = 0x40047000 as *mut u32;
+const MSTPCRC: *mut u32 = 0x40047004 as *mut u32;
+const MSTPCRD: *mut u32 = 0x40047008 as *mut u32;
+
+/// A peripheral unit with its own module stop bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Module {
+    /// The DMA controller and the data transfer controller, which share one bit.
+    DmacDtc,
+    Can0,
+    Iic0,
+    Iic1,
+    Usbfs,
+    Spi0,
+    Spi1,
+    Sci0,
+    Sci1,
+    Sci2,
+    Sci9,
+    /// Clock frequency accuracy measurement circuit.
+    Cac,
+    Crc,
+    /// Capacitive touch sensing unit.
+    Ctsu,
+    /// Segment LCD controller.
+    Slcdc,
+    /// Data operation circuit.
+    Doc,
+    /// Event link controller.
+    Elc,
+    Agt0,
+    Agt1,
+    /// GPT channels 0-1 (32 bits).
+    Gpt32,
+    /// GPT channels 2-7 (16 bits).
+    Gpt16,
+    /// Port output enable for the GPT.
+    Poeg,
+    Adc140,
+    Dac8,
+    Dac12,
+    /// Temperature sensor.
+    Tsn,
+    /// Low-power analog comparator.
+    Acmplp,
+    Opamp,
+}
+
+impl Module {
+    const COUNT: usize = Module::Opamp as usize + 1;
+
+    const ALL: [Module; Module::COUNT] = [
+        Module::DmacDtc,
+        Module::Can0,
+        Module::Iic0,
+        Module::Iic1,
+        Module::Usbfs,
+        Module::Spi0,
+        Module::Spi1,
+        Module::Sci0,
+        Module::Sci1,
+        Module::Sci2,
+        Module::Sci9,
+        Module::Cac,
+        Module::Crc,
+        Module::Ctsu,
+        Module::Slcdc,
+        Module::Doc,
+        Module::Elc,
+        Module::Agt0,
+        Module::Agt1,
+        Module::Gpt32,
+        Module::Gpt16,
+        Module::Poeg,
+        Module::Adc140,
+        Module::Dac8,
+        Module::Dac12,
+        Module::Tsn,
+        Module::Acmplp,
+        Module::Opamp,
+    ];
+
+    /// Returns the module stop control register and the bit of the unit.
+    fn bit(self) -> (*mut u32, u32) {
+        match self {
+            Module::DmacDtc => (MSTPCRA, 22),
+            Module::Can0 => (MSTPCRB, 2),
+            Module::Iic1 => (MSTPCRB, 8),
+            Module::Iic0 => (MSTPCRB, 9),
+            Module::Usbfs => (MSTPCRB, 11),
+            Module::Spi1 => (MSTPCRB, 18),
+            Module::Spi0 => (MSTPCRB, 19),
+            Module::Sci9 => (MSTPCRB, 22),
+            Module::Sci2 => (MSTPCRB, 29),
+            Module::Sci1 => (MSTPCRB, 30),
+            Module::Sci0 => (MSTPCRB, 31),
+            Module::Cac => (MSTPCRC, 0),
+            Module::Crc => (MSTPCRC, 1),
+            Module::Ctsu => (MSTPCRC, 3),
+            Module::Slcdc => (MSTPCRC, 4),
+            Module::Doc => (MSTPCRC, 13),
+            Module::Elc => (MSTPCRC, 14),
+            Module::Agt1 => (MSTPCRD, 2),
+            Module::Agt0 => (MSTPCRD, 3),
+            Module::Gpt32 => (MSTPCRD, 5),
+            Module::Gpt16 => (MSTPCRD, 6),
+            Module::Poeg => (MSTPCRD, 14),
+            Module::Adc140 => (MSTPCRD, 16),
+            Module::Dac8 => (MSTPCRD, 19),
+            Module::Dac12 => (MSTPCRD, 20),
+            Module::Tsn => (MSTPCRD, 22),
+            Module::Acmplp => (MSTPCRD, 29),
+            Module::Opamp => (MSTPCRD, 31),
+        }
+    }
+
+    /// Set or clear the module stop bit.
+    unsafe fn set_stopped(self, stopped: bool) {
+        let (register, bit) = self.bit();
+        let protection = if register == MSTPCRA { PRCR_PRC1 } else { 0 };
+        registers::unprotected(protection, || {
+            if stopped {
+                register.volatile_or(1 << bit);
+            } else {
+                register.volatile_and(!(1 << bit));
+            }
+        });
+    }
+}
+
+/// Number of [`ModuleClock`]s of each unit. A count that reaches `u16::MAX` stays there instead of
+/// wrapping around, and the clock keeps running.
+static mut USERS: [u16; Module::COUNT] = [0; Module::COUNT];
+
+/// The clock of a unit, which is started while at least one of these exists.
+pub struct ModuleClock {
+    module: Module,
+}
+
+impl ModuleClock {
+    /// Returns the unit.
+    pub fn module(&self) -> Module {
+        self.module
+    }
+}
+
+impl Drop for ModuleClock {
+    fn drop(&mut self) {
+        interrupt::free(|| unsafe {
+            let users = &mut (*ptr::addr_of_mut!(USERS))[self.module as usize];
+            if *users == u16::MAX {
+                return;
+            }
+            *users -= 1;
+            if *users == 0 {
+                self.module.set_stopped(true);
+            }
+        });
+    }
+}
+
+/// A unit as a type, see [`unit`].
+pub trait Unit {
+    const MODULE: Module;
+}
+
+/// Types for the units, one for each [`Module`].
+pub mod unit {
+    use super::{Module, Unit};
+
+    macro_rules! units {
+        ($($name:ident),*) => {
+            $(
+                #[doc = concat!("The unit [`Module::", stringify!($name), "`].")]
+                pub struct $name;
+
+                impl Unit for $name {
+                    const MODULE: Module = Module::$name;
+                }
+            )*
+        };
+    }
+
+    units!(
+        DmacDtc, Can0, Iic0, Iic1, Usbfs, Spi0, Spi1, Sci0, Sci1, Sci2, Sci9, Cac, Crc, Ctsu,
+        Slcdc, Doc, Elc, Agt0, Agt1, Gpt32, Gpt16, Poeg, Adc140, Dac8, Dac12, Tsn, Acmplp, Opamp
+    );
+}
+
+/// The clock of the unit `U`, like a [`ModuleClock`] of `U::MODULE`.
+pub struct MstpToken<U: Unit> {
+    clock: ModuleClock,
+    _unit: PhantomData<U>,
+}
+
+impl<U: Unit> MstpToken<U> {
+    /// Returns the unit.
+    pub fn module(&self) -> Module {
+        self.clock.module
+    }
+}
+
+impl<U: Unit> From<MstpToken<U>> for ModuleClock {
+    fn from(token: MstpToken<U>) -> Self {
+        token.clock
+    }
+}
+
+/// Start the clock of `module`, and keep it running until the returned [`ModuleClock`] and all
+/// others for the unit are dropped.
+fn enable(module: Module) -> ModuleClock {
+    interrupt::free(|| unsafe {
+        let users = &mut (*ptr::addr_of_mut!(USERS))[module as usize];
+        if *users == 0 {
+            module.set_stopped(false);
+        }
+        *users = users.saturating_add(1);
+    });
+    ModuleClock { module }
+}
+
+/// Start the clock of the unit `U`, and keep it running until the returned [`MstpToken`] and all
+/// other users of the unit are dropped.
+pub fn token<U: Unit>() -> MstpToken<U> {
+    MstpToken {
+        clock: enable(U::MODULE),
+        _unit: PhantomData,
+    }
+}
+
+/// Stop the clocks of all units without a [`ModuleClock`], except those for which `keep` returns
+/// true.
+pub(crate) fn stop_unused(keep: impl Fn(Module) -> bool) {
+    interrupt::free(|| unsafe {
+        let users = &*ptr::addr_of!(USERS);
+        for module in Module::ALL {
+            if users[module as usize] == 0 && !keep(module) {
+                module.set_stopped(true);
+            }
+        }
+    });
+}
+
+/// Returns true if the clock of `module` is running.
+pub fn is_running(module: Module) -> bool {
+    let (register, bit) = module.bit();
+    unsafe { register.read_volatile() & (1 << bit) == 0 }
+}
@@ -0,0 +1,121 @@
+//! Non-maskable interrupt (NMI): Handlers for the events that the ICU routes to the NMI of the CPU.
+//!
+//! The NMI can't be disabled by [`crate::interrupt::disable`], so it still fires when the firmware
+//! is stuck with interrupts disabled. This makes it the right place for a last look at the state
+//! of the firmware before the watchdog resets the MCU: If the WDT is configured with
+//! [`super::wdt::Config::with_nmi`], or the option bytes select the interrupt mode of the IWDT
+//! (see [`super::iwdt::Settings::reset`]), an underflow or refresh error raises an NMI instead of
+//! a reset. The handler set with [`set_handler`] can then save state, e.g. to RAM that isn't
+//! initialized at startup, or write it to a serial port. When it returns, the MCU is reset.
+//!
+//! An event can't be disabled as NMI source again once it was enabled, so [`set_handler`] can only
+//! replace the handler.
+//!
+//! For details, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Interrupt Controller
+//! Unit (ICU)", section "Non-Maskable Interrupt Operation".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::nmi::{self, Source};
+//! use arduino_uno_r4_wifi_rt::peripherals::wdt::{Config, Wdt};
+//!
+//! fn on_watchdog() {
+//!     // Save some state, the MCU is reset afterwards.
+//! }
+//!
+//! nmi::set_handler(Source::Wdt, on_watchdog);
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let config = Config::with_timeout_ms(&clocks, 1000).with_nmi();
+//! let mut wdt = Wdt::take().unwrap().start(&config);
+//! ```
+
+use super::registers::VolatileBoolOps;
+use super::reset::system_reset;
+use crate::interrupt;
+
+use core::ptr;
+
+/// Events that can raise the NMI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Source {
+    /// Underflow or refresh error of the independent watchdog timer.
+    Iwdt,
+    /// Underflow or refresh error of the watchdog timer.
+    Wdt,
+    /// The main oscillator stopped, see [`super::clocks::detect_oscillation_stop`].
+    OscillationStop,
+}
+
+impl Source {
+    const ALL: [Source; 3] = [Source::Iwdt, Source::Wdt, Source::OscillationStop];
+
+    /// Bit of the source in NMIER, NMISR and NMICLR.
+    fn bit(self) -> u16 {
+        match self {
+            Source::Iwdt => 1 << 0,
+            Source::Wdt => 1 << 1,
+            Source::OscillationStop => 1 << 6,
+        }
+    }
+
+    /// Returns true if the MCU must be reset after the handler, because the source can't recover.
+    fn resets(self) -> bool {
+        matches!(self, Source::Iwdt | Source::Wdt)
+    }
+}
+
+/// Non-Maskable Interrupt Enable Register. Bits can only be set, not cleared.
+/// * b0: IWDTEN, IWDT underflow or refresh error.
+/// * b1: WDTEN, WDT underflow or refresh error.
+/// * b6: OSTEN, oscillation stop detected.
+const NMIER: *mut u16 = 0x40006120 as *mut u16;
+
+/// Non-Maskable Interrupt Status Clear Register. Writing 1 clears the bit in NMISR.
+const NMICLR: *mut u16 = 0x40006130 as *mut u16;
+
+/// Non-Maskable Interrupt Status Register, with the same bits as NMIER.
+const NMISR: *const u16 = 0x40006140 as *const u16;
+
+/// Handlers registered for the sources.
+static mut HANDLERS: [Option<fn()>; Source::ALL.len()] = [None; Source::ALL.len()];
+
+/// Call `handler` when `source` raises the NMI, and enable the source. Replaces the previous
+/// handler of the source.
+///
+/// For the watchdogs, the MCU is reset after `handler` returns.
+pub fn set_handler(source: Source, handler: fn()) {
+    interrupt::free(|| unsafe {
+        // The handler must be in place before the source is enabled.
+        ptr::addr_of_mut!(HANDLERS[source as usize]).write(Some(handler));
+        NMIER.volatile_or(source.bit());
+    });
+}
+
+/// Handler of the NMI, referenced in the vector table.
+///
+/// Calls the registered handlers of all sources that have their status bit set, clears the bits,
+/// and resets the MCU if one of them was a watchdog.
+///
+/// # Safety
+///
+/// Must only be called by the CPU when it takes the NMI.
+pub unsafe fn dispatch() {
+    let status = NMISR.read_volatile();
+    let mut reset = false;
+    for source in Source::ALL {
+        if status & source.bit() == 0 {
+            continue;
+        }
+        if let Some(handler) = ptr::addr_of!(HANDLERS[source as usize]).read() {
+            handler();
+        }
+        NMICLR.write_volatile(source.bit());
+        reset |= source.resets();
+    }
+    if reset {
+        system_reset();
+    }
+}
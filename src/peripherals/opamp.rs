@@ -0,0 +1,135 @@
+//! Operational amplifier (OPAMP): Amplify or buffer an analog signal on the board.
+//!
+//! The RA4M1 has four op-amps, of which OPAMP0 is on the header of the UNO R4 WiFi:
+//! * Non-inverting input AMP0+ on pin A1 (P000).
+//! * Inverting input AMP0- on pin A2 (P001).
+//! * Output AMP0O on pin A3 (P002).
+//!
+//! The inputs and the output are fixed to these pins, and the feedback network is external:
+//! * Voltage follower (buffer): Connect A3 to A2, the output follows A1.
+//! * Non-inverting amplifier with gain 1 + R2 / R1: R2 from A3 to A2, R1 from A2 to GND.
+//!
+//! The output can be read by the ADC on A3, so a weak sensor signal can be amplified before it is
+//! sampled.
+//!
+//! The op-amp runs in one of three [`Mode`]s, which trade current for bandwidth.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter
+//! "Operational Amplifier (OPAMP)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::opamp::{Mode, OpAmp};
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! // With A3 connected to A2, A3 buffers the voltage on A1.
+//! let opamp = OpAmp::new(pins.a1, pins.a2, pins.a3, mstp::token(), &clocks, Mode::HighSpeed);
+//! ```
+
+use super::clocks::{self, Clocks};
+use super::mstp::{unit, MstpToken};
+use super::pins::{PinMode, PinModeAnalog, P000, P001, P002};
+use super::registers::VolatileBoolOps;
+
+/// Operational Amplifier Mode Control Register.
+/// * b6-b7: AMPSP, the mode of all op-amps, see [`Mode`].
+const AMPMC: *mut u8 = 0x40086008 as *mut u8;
+
+/// Operational Amplifier Control Register.
+/// * b0-b3: AMPE0-AMPE3, enable the op-amps.
+/// * b7: IREFE, enables the reference current circuit, needed by all op-amps.
+const AMPC: *mut u8 = 0x4008600b as *mut u8;
+
+/// Operational Amplifier Monitor Register.
+/// * b0-b3: AMPMON0-AMPMON3, the op-amp is enabled (read only).
+const AMPMON: *const u8 = 0x4008600c as *const u8;
+
+const AMPC_AMPE0: u8 = 1 << 0;
+const AMPC_IREFE: u8 = 1 << 7;
+
+/// Power mode of the op-amps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    /// Lowest current, for slowly changing signals.
+    LowPower = 0b00,
+    MiddleSpeed = 0b01,
+    /// Highest bandwidth and slew rate.
+    HighSpeed = 0b11,
+}
+
+impl Mode {
+    /// Time for the output to become stable after the op-amp is enabled, in microseconds.
+    fn stabilization_us(self) -> u32 {
+        match self {
+            Mode::LowPower => 650,
+            Mode::MiddleSpeed => 185,
+            Mode::HighSpeed => 140,
+        }
+    }
+}
+
+/// OPAMP0, with its input and output pins.
+pub struct OpAmp {
+    plus: P000<PinModeAnalog>,
+    minus: P001<PinModeAnalog>,
+    output: P002<PinModeAnalog>,
+    _clock: MstpToken<unit::Opamp>,
+}
+
+impl OpAmp {
+    /// Enable OPAMP0 in `mode` with the inputs on A1 and A2 and the output on A3, and wait until
+    /// the output is stable.
+    pub fn new<M1: PinMode, M2: PinMode, M3: PinMode>(
+        plus: P000<M1>,
+        minus: P001<M2>,
+        output: P002<M3>,
+        clock: MstpToken<unit::Opamp>,
+        clocks: &Clocks,
+        mode: Mode,
+    ) -> Self {
+        let plus = plus.into_analog();
+        let minus = minus.into_analog();
+        let output = output.into_analog();
+        unsafe {
+            AMPMC.write_volatile((mode as u8) << 6);
+            AMPC.volatile_or(AMPC_IREFE);
+            AMPC.volatile_or(AMPC_AMPE0);
+        }
+        clocks::wait_us(clocks.iclk(), mode.stabilization_us());
+        Self {
+            plus,
+            minus,
+            output,
+            _clock: clock,
+        }
+    }
+
+    /// Change the power mode, and wait until the output is stable again.
+    pub fn set_mode(&mut self, clocks: &Clocks, mode: Mode) {
+        unsafe { AMPMC.write_volatile((mode as u8) << 6) };
+        clocks::wait_us(clocks.iclk(), mode.stabilization_us());
+    }
+
+    /// Returns true if the op-amp is running.
+    pub fn is_enabled(&self) -> bool {
+        unsafe { AMPMON.read_volatile() & AMPC_AMPE0 != 0 }
+    }
+
+    /// Disable the op-amp and return the pins.
+    pub fn release(
+        self,
+    ) -> (
+        P000<PinModeAnalog>,
+        P001<PinModeAnalog>,
+        P002<PinModeAnalog>,
+    ) {
+        unsafe { AMPC.write_volatile(0) };
+        (self.plus, self.minus, self.output)
+    }
+}
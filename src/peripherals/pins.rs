@@ -40,6 +40,14 @@ impl PinMode for PinModeInput {}
 pub struct PinModeInputPullup;
 impl PinMode for PinModeInputPullup {}
 
+/// The pin is controlled by a peripheral like the I2C bus, see the driver that owns it.
+pub struct PinModePeripheral;
+impl PinMode for PinModePeripheral {}
+
+/// The pin is an analog input or output, e.g. of the comparators or the op-amps.
+pub struct PinModeAnalog;
+impl PinMode for PinModeAnalog {}
+
 /// Status of a GPIO pin.
 pub enum PinStatus {
     /// Pin is at LOW voltage.
@@ -83,14 +91,12 @@ struct PinFunctionSelect<P: Pin> {
 }
 
 impl<P: Pin> PinFunctionSelect<P> {
-    const BASE_ADDRESS: u32 = 0x40040800;
-
     /// Pin Function Select Register.
     ///
     /// If all bits are 0, the pin is configured as a GPIO input pin without pullup.
     /// Setting bit 2 to 1 configures it as a GPIO output pin. Setting bit 4 to 1 activates the pullup
     /// resistor for an input pin.
-    const PFSR: *mut u32 = (Self::BASE_ADDRESS + 4 * (16 * P::PORT_NO + P::PIN_NO)) as *mut u32;
+    const PFSR: *mut u32 = pfs(P::PORT_NO, P::PIN_NO);
 
     fn new() -> Self {
         Self {
@@ -122,6 +128,116 @@ impl<P: Pin> PinFunctionSelect<P> {
             self.write_protection.lock();
         }
     }
+
+    /// Disconnect the digital input and output, so the pin can be used by the analog units.
+    /// Setting bit 15 (ASEL) to 1 selects the analog function.
+    fn set_to_analog(&mut self) {
+        unsafe {
+            self.write_protection.unlock();
+            Self::PFSR.write_volatile(1 << 15);
+            self.write_protection.lock();
+        }
+    }
+
+    /// Hand the pin over to the peripheral selected by `psel` (bits b24-b28 of PFSR). `extra_bits`
+    /// are other settings, like bit 6 for an open-drain output.
+    ///
+    /// The peripheral function must be selected before setting the Port Mode Control bit (b16).
+    fn set_to_peripheral(&mut self, psel: u32, extra_bits: u32) {
+        unsafe {
+            self.write_protection.unlock();
+            Self::PFSR.write_volatile((psel << 24) | extra_bits);
+            Self::PFSR.write_volatile((psel << 24) | extra_bits | (1 << 16));
+            self.write_protection.lock();
+        }
+    }
+}
+
+/// Returns the Pin Function Select Register of pin `pin_no` of port `port_no`.
+const fn pfs(port_no: u32, pin_no: u32) -> *mut u32 {
+    (0x40040800 + 4 * (16 * port_no + pin_no)) as *mut u32
+}
+
+/// Returns the Port Control Register `n` (1 to 4) of port `port_no`.
+const fn pcntr(port_no: u32, n: u32) -> *mut u32 {
+    (0x40040000 + 4 * (n - 1) + 0x20 * port_no) as *mut u32
+}
+
+/// Configure the pins of port `port_no` with a 1 in `mask` as inputs with pull-up, the state
+/// that draws the least current for a pin that isn't connected.
+pub(crate) fn park(port_no: u32, mask: u16) {
+    let mut write_protection = PinWriteProtection::new();
+    unsafe {
+        write_protection.unlock();
+        for pin_no in (0..16).filter(|pin_no| mask & (1 << pin_no) != 0) {
+            pfs(port_no, pin_no).write_volatile(1 << 4);
+        }
+        write_protection.lock();
+    }
+}
+
+/// Hand pin `pin_no` of port `port_no` over to the peripheral selected by `psel`, for drivers that
+/// use too many pins to take them one by one.
+pub(crate) fn set_peripheral(port_no: u32, pin_no: u32, psel: u32) {
+    let pfsr = pfs(port_no, pin_no);
+    let mut write_protection = PinWriteProtection::new();
+    unsafe {
+        write_protection.unlock();
+        pfsr.write_volatile(psel << 24);
+        pfsr.write_volatile((psel << 24) | (1 << 16));
+        write_protection.lock();
+    }
+}
+
+/// Write `value` to the Pin Function Select Register of pin `pin_no` of port `port_no`, for pins
+/// that are picked by number at runtime. See [`PinFunctionSelect`] for the bits.
+#[cfg_attr(not(feature = "arduino"), allow(dead_code))]
+pub(crate) fn set_function(port_no: u32, pin_no: u32, value: u32) {
+    let pfsr = pfs(port_no, pin_no);
+    let mut write_protection = PinWriteProtection::new();
+    unsafe {
+        write_protection.unlock();
+        pfsr.write_volatile(value);
+        write_protection.lock();
+    }
+}
+
+/// Use pin `pin_no` of port `port_no` as the input of its IRQ line if `enabled`, keeping the rest
+/// of its configuration. This is bit b14 (ISEL) of the Pin Function Select Register.
+#[cfg_attr(not(feature = "arduino"), allow(dead_code))]
+pub(crate) fn set_irq_input(port_no: u32, pin_no: u32, enabled: bool) {
+    let pfsr = pfs(port_no, pin_no);
+    let mut write_protection = PinWriteProtection::new();
+    unsafe {
+        write_protection.unlock();
+        if enabled {
+            pfsr.volatile_or(1 << 14);
+        } else {
+            pfsr.volatile_and(!(1 << 14));
+        }
+        write_protection.lock();
+    }
+}
+
+/// Make pin `pin_no` of port `port_no` output HIGH if `high`, or LOW.
+///
+/// This writes the Port Control Register 3, whose bits b0-b15 (POSR) set the output of a pin to
+/// HIGH and b16-b31 (PORR) to LOW, so other pins of the port don't change even if an interrupt
+/// changes them at the same time.
+#[cfg_attr(not(feature = "arduino"), allow(dead_code))]
+pub(crate) fn write_pin(port_no: u32, pin_no: u32, high: bool) {
+    let bit = if high {
+        1 << pin_no
+    } else {
+        1 << (pin_no + 16)
+    };
+    unsafe { pcntr(port_no, 3).write_volatile(bit) };
+}
+
+/// Returns true if pin `pin_no` of port `port_no` receives HIGH voltage.
+#[cfg_attr(not(feature = "arduino"), allow(dead_code))]
+pub(crate) fn read_pin(port_no: u32, pin_no: u32) -> bool {
+    unsafe { pcntr(port_no, 2).read_volatile() & (1 << pin_no) != 0 }
 }
 
 struct PortControl<P: PortNo> {
@@ -129,10 +245,8 @@ struct PortControl<P: PortNo> {
 }
 
 impl<P: PortNo> PortControl<P> {
-    const BASE_ADDRESS: u32 = 0x40040000;
-    const ADDRESS_GAP: u32 = 0x20;
-    const PCNTR1: *mut u32 = (Self::BASE_ADDRESS + P::PORT_NO * Self::ADDRESS_GAP) as *mut u32;
-    const PCNTR2: *mut u32 = (Self::BASE_ADDRESS + 4 + P::PORT_NO * Self::ADDRESS_GAP) as *mut u32;
+    const PCNTR1: *mut u32 = pcntr(P::PORT_NO, 1);
+    const PCNTR2: *mut u32 = pcntr(P::PORT_NO, 2);
 
     fn new() -> Self {
         Self { _port: PhantomData }
@@ -262,6 +376,25 @@ macro_rules! make_port_pins {
                         _mode: PhantomData,
                     }
                 }
+
+                /// Hand the pin over to a peripheral, see [`PinFunctionSelect::set_to_peripheral`].
+                #[allow(dead_code)]
+                #[inline]
+                pub(crate) fn into_peripheral(
+                    mut self,
+                    psel: u32,
+                    extra_bits: u32,
+                ) -> $pin_type<PinModePeripheral> {
+                    self.pin_function_select.set_to_peripheral(psel, extra_bits);
+                    $pin_type::new()
+                }
+
+                /// Configure this pin as an analog pin.
+                #[inline]
+                pub fn into_analog(mut self) -> $pin_type<PinModeAnalog> {
+                    self.pin_function_select.set_to_analog();
+                    $pin_type::new()
+                }
             }
 
             impl<M: PinMode> Pin for $pin_type<M> {
@@ -374,17 +507,42 @@ macro_rules! make_port_pins {
     };
 }
 
-make_port_pins!(0, Port0, Port0Pins, 0, p000, P000, 1, p001, P001, 2, p002, P002, 14, p014, P014);
+make_port_pins!(
+    0, Port0, Port0Pins, 0, p000, P000, 1, p001, P001, 2, p002, P002, 3, p003, P003, 4, p004, P004,
+    11, p011, P011, 12, p012, P012, 13, p013, P013, 14, p014, P014, 15, p015, P015
+);
 make_port_pins!(
     1, Port1, Port1Pins, 0, p100, P100, 1, p101, P101, 2, p102, P102, 3, p103, P103, 4, p104, P104,
-    5, p105, P105, 6, p106, P106, 7, p107, P107, 11, p111, P111, 12, p112, P112
+    5, p105, P105, 6, p106, P106, 7, p107, P107, 9, p109, P109, 10, p110, P110, 11, p111, P111, 12,
+    p112, P112
+);
+make_port_pins!(
+    2, Port2, Port2Pins, 4, p204, P204, 5, p205, P205, 6, p206, P206, 12, p212, P212, 13, p213,
+    P213
 );
 make_port_pins!(3, Port3, Port3Pins, 1, p301, P301, 2, p302, P302, 3, p303, P303, 4, p304, P304);
 make_port_pins!(4, Port4, Port4Pins, 10, p410, P410, 11, p411, P411);
 
+/// The other lines of the LED matrix besides p205, see [`super::led_matrix`].
+pub struct LedMatrixPins {
+    pub p003: P003<PinModeUnknown>,
+    pub p004: P004<PinModeUnknown>,
+    pub p011: P011<PinModeUnknown>,
+    pub p012: P012<PinModeUnknown>,
+    pub p013: P013<PinModeUnknown>,
+    pub p015: P015<PinModeUnknown>,
+    pub p204: P204<PinModeUnknown>,
+    pub p206: P206<PinModeUnknown>,
+    pub p212: P212<PinModeUnknown>,
+    pub p213: P213<PinModeUnknown>,
+}
+
 /// Pins that are exposed on the Arduino.
 ///
-/// Pin d13 controls the LED.
+/// Pin d13 controls the LED. Pin p205 isn't on the headers, it is one of the lines of the LED
+/// matrix, and the only pin that can output the clock signal of [`super::clocks::ClockOut`]. The
+/// other lines of the LED matrix are in `led_matrix`. Pins p109 and p110 aren't on the headers
+/// either, they are the serial link to the ESP32-S3, see [`super::esp32`].
 pub struct ArduinoPins {
     pub d0: P301<PinModeUnknown>,
     pub d1: P302<PinModeUnknown>,
@@ -406,11 +564,16 @@ pub struct ArduinoPins {
     pub a3: P002<PinModeUnknown>,
     pub a4: P101<PinModeUnknown>,
     pub a5: P100<PinModeUnknown>,
+    pub p205: P205<PinModeUnknown>,
+    pub p109: P109<PinModeUnknown>,
+    pub p110: P110<PinModeUnknown>,
+    pub led_matrix: LedMatrixPins,
 }
 
 pub struct Ports {
     port0: Port0,
     port1: Port1,
+    port2: Port2,
     port3: Port3,
     port4: Port4,
 }
@@ -418,6 +581,7 @@ pub struct Ports {
 pub static mut PORTS: Option<Ports> = Some(Ports {
     port0: Port0::new(),
     port1: Port1::new(),
+    port2: Port2::new(),
     port3: Port3::new(),
     port4: Port4::new(),
 });
@@ -427,6 +591,7 @@ pub fn get_pins() -> Option<ArduinoPins> {
     let ports = unsafe { PORTS.take()? };
     let port0_pins = ports.port0.split();
     let port1_pins = ports.port1.split();
+    let port2_pins = ports.port2.split();
     let port3_pins = ports.port3.split();
     let port4_pins = ports.port4.split();
 
@@ -451,5 +616,20 @@ pub fn get_pins() -> Option<ArduinoPins> {
         a3: port0_pins.p002,
         a4: port1_pins.p101,
         a5: port1_pins.p100,
+        p205: port2_pins.p205,
+        p109: port1_pins.p109,
+        p110: port1_pins.p110,
+        led_matrix: LedMatrixPins {
+            p003: port0_pins.p003,
+            p004: port0_pins.p004,
+            p011: port0_pins.p011,
+            p012: port0_pins.p012,
+            p013: port0_pins.p013,
+            p015: port0_pins.p015,
+            p204: port2_pins.p204,
+            p206: port2_pins.p206,
+            p212: port2_pins.p212,
+            p213: port2_pins.p213,
+        },
     })
 }
@@ -0,0 +1,484 @@
+//! Low power modes of the CPU.
+//!
+//! [`sleep`] stops the CPU until the next interrupt, while the peripherals and their clocks keep
+//! running. Any interrupt that is enabled wakes the CPU, including those that are masked with
+//! [`crate::interrupt::disable`]. An idle loop that sleeps instead of spinning draws a few mA
+//! less.
+//!
+//! [`sleep_until`] sleeps until a condition holds, e.g. a flag that an interrupt handler sets,
+//! without missing an interrupt that comes between checking the condition and going to sleep.
+//! [`sleep_on`] only lets the interrupt slots in a [`WakeSources`] wake the CPU, and keeps the
+//! other interrupts pending until it returns.
+//!
+//! In software standby, entered with [`Standby::enter`], the oscillators and most peripherals stop
+//! as well, and the MCU draws only a few uA. The registers and the SRAM keep their contents. Only
+//! the events in [`WakeEvent`] wake the MCU, e.g. an IRQ pin, the RTC alarm or AGT1, and only if
+//! they are also linked to an interrupt slot with [`super::icu::attach`]. The oscillators that ran
+//! before are restarted on wake-up, and the CPU continues with the same clocks as before, once
+//! they are stable.
+//!
+//! With snooze, a [`SnoozeRequest`] doesn't wake the CPU, but starts the DTC and peripherals like
+//! the A/D converter for a moment, e.g. to take a sample and only wake the CPU if it is out of
+//! range. A [`SnoozeEnd`] event sends the MCU back to software standby.
+//!
+//! [`minimize`] brings the current down further before sleeping, following a [`Minimize`]
+//! configuration: It parks the pins that aren't used as inputs with pull-up, so they don't float,
+//! stops the clocks of the peripheral units that no driver uses (see [`super::mstp`]), and stops
+//! the oscillators that nothing needs. Drivers created afterwards start their units again, but the
+//! pins and oscillators they need must be kept.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Low
+//! Power Modes", and the Armv7-M Architecture Reference Manual, section B1.5.19 (WFI).
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::icu::{self, Event};
+//! use arduino_uno_r4_wifi_rt::peripherals::power;
+//! use core::sync::atomic::{AtomicBool, Ordering};
+//!
+//! static ALARM: AtomicBool = AtomicBool::new(false);
+//!
+//! fn on_alarm() {
+//!     ALARM.store(true, Ordering::Relaxed);
+//! }
+//!
+//! icu::attach(Event::RtcAlarm, on_alarm).unwrap();
+//! power::sleep_until(|| ALARM.load(Ordering::Relaxed));
+//! ```
+//!
+//! Example, waking from software standby with the RTC alarm:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::icu::{self, Event};
+//! use arduino_uno_r4_wifi_rt::peripherals::power::{self, Minimize, Standby, WakeEvent};
+//!
+//! fn on_alarm() {}
+//!
+//! icu::attach(Event::RtcAlarm, on_alarm).unwrap();
+//! // Set the alarm with the RTC driver, then:
+//! // Stop everything but the RTC, and keep the LED on D13 (P102).
+//! power::minimize(&Minimize::default().with_pin(1, 2).with_oscillators(false, false, true, true));
+//! Standby::new().wake_on(WakeEvent::RtcAlarm).enter();
+//! ```
+
+use super::clocks::{self, Source};
+use super::icu::{self, Slot};
+use super::mstp::{self, Module};
+use super::pins;
+use super::registers::{self, VolatileBoolOps, PRCR_PRC1};
+use crate::interrupt;
+
+use core::arch::asm;
+
+/// Standby Control Register, protected by PRC1 of PRCR. b15: SSBY, enter software standby instead
+/// of sleep mode.
+const SBYCR: *mut u16 = 0x4001e00c as *mut u16;
+
+/// System Control Register of the System Control Block. b2: SLEEPDEEP, enter the deep sleep state
+/// of the CPU.
+const SCR: *mut u32 = 0xe000ed10 as *mut u32;
+
+/// Snooze Control Register, protected by PRC1.
+/// * b1: SNZDTCEN, let the DTC run in snooze mode.
+/// * b7: SNZE, enter snooze mode on a snooze request.
+const SNZCR: *mut u8 = 0x4001e092 as *mut u8;
+
+/// Snooze End Control Register, protected by PRC1. One bit per [`SnoozeEnd`].
+const SNZEDCR: *mut u8 = 0x4001e094 as *mut u8;
+
+/// Snooze Request Control Register, protected by PRC1. One bit per [`SnoozeRequest`].
+const SNZREQCR: *mut u32 = 0x4001e098 as *mut u32;
+
+/// Wake Up Interrupt Enable Register of the ICU. One bit per [`WakeEvent`].
+const WUPEN: *mut u32 = 0x400061a0 as *mut u32;
+
+/// Oscillation Stabilization Flag Register. b0: HOCOSF, the HOCO is stable.
+const OSCSF: *const u8 = 0x4001e03c as *const u8;
+
+/// High-Speed On-Chip Oscillator Control Register. b0: HCSTP, the HOCO is stopped.
+const HOCOCR: *const u8 = 0x4001e036 as *const u8;
+
+const SBYCR_SSBY: u16 = 1 << 15;
+const SCR_SLEEPDEEP: u32 = 1 << 2;
+const SNZCR_SNZDTCEN: u8 = 1 << 1;
+const SNZCR_SNZE: u8 = 1 << 7;
+
+/// A set of interrupt slots that may wake the CPU, see [`sleep_on`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WakeSources(u32);
+
+impl WakeSources {
+    /// No slots.
+    pub const NONE: Self = Self(0);
+
+    /// Add `slot` to the set.
+    pub fn with(self, slot: Slot) -> Self {
+        Self(self.0 | (1 << slot.number()))
+    }
+
+    /// Returns true if `slot` is in the set.
+    pub fn contains(&self, slot: Slot) -> bool {
+        self.0 & (1 << slot.number()) != 0
+    }
+}
+
+/// Select sleep mode as the mode that WFI enters.
+fn select_sleep_mode() {
+    unsafe {
+        SCR.volatile_and(!SCR_SLEEPDEEP);
+        if SBYCR.read_volatile() & SBYCR_SSBY != 0 {
+            registers::unprotected(PRCR_PRC1, || SBYCR.volatile_and(!SBYCR_SSBY));
+        }
+    }
+}
+
+/// Wait for an interrupt. Returns right away if one is pending.
+#[inline]
+fn wfi() {
+    unsafe {
+        asm!("wfi", options(nomem, nostack, preserves_flags));
+    }
+}
+
+/// Stop the CPU until an interrupt occurs. If interrupts are enabled, its handler runs before this
+/// returns.
+pub fn sleep() {
+    select_sleep_mode();
+    wfi();
+}
+
+/// Sleep until `condition` returns true. It is checked with interrupts disabled, so an interrupt
+/// that makes it true right after the check still wakes the CPU. The interrupt handlers run
+/// between the checks.
+pub fn sleep_until(mut condition: impl FnMut() -> bool) {
+    select_sleep_mode();
+    let was_enabled = interrupt::is_enabled();
+    interrupt::disable();
+    while !condition() {
+        wfi();
+        // Let the handler of the interrupt that woke the CPU run.
+        unsafe {
+            interrupt::enable();
+        }
+        interrupt::disable();
+    }
+    if was_enabled {
+        unsafe {
+            interrupt::enable();
+        }
+    }
+}
+
+/// Sleep until one of the slots in `wake` raises an interrupt, and return after its handler has
+/// run. The other slots are disabled in the NVIC meanwhile, so their interrupts stay pending and
+/// are handled after this returns.
+///
+/// Must not be called with interrupts disabled, since the handler can't run then.
+pub fn sleep_on(wake: WakeSources) {
+    let enabled = icu::enabled_slots();
+    icu::set_enabled_slots(enabled & wake.0);
+    sleep();
+    icu::set_enabled_slots(enabled);
+}
+
+/// Events that wake the MCU from software standby. Each must also be linked to an interrupt slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeEvent {
+    /// External pin interrupt IRQ0-IRQ15.
+    Irq(u8),
+    /// Underflow or refresh error of the IWDT.
+    Iwdt,
+    /// Voltage monitor 1 and 2.
+    Lvd1,
+    Lvd2,
+    /// Battery backup power switch.
+    Vbatt,
+    /// Low-power analog comparator.
+    Acmplp,
+    RtcAlarm,
+    RtcPeriod,
+    /// Resume from suspend on the USB bus.
+    Usbfs,
+    /// AGT1 underflow and compare matches.
+    Agt1Underflow,
+    Agt1CompareA,
+    Agt1CompareB,
+    /// IIC0 address match.
+    Iic0,
+}
+
+impl WakeEvent {
+    /// Returns the bit in WUPEN.
+    fn bit(self) -> u32 {
+        let n = match self {
+            WakeEvent::Irq(n) => n as u32 & 0x0f,
+            WakeEvent::Iwdt => 16,
+            WakeEvent::Lvd1 => 18,
+            WakeEvent::Lvd2 => 19,
+            WakeEvent::Vbatt => 20,
+            WakeEvent::Acmplp => 23,
+            WakeEvent::RtcAlarm => 24,
+            WakeEvent::RtcPeriod => 25,
+            WakeEvent::Usbfs => 27,
+            WakeEvent::Agt1Underflow => 28,
+            WakeEvent::Agt1CompareA => 29,
+            WakeEvent::Agt1CompareB => 30,
+            WakeEvent::Iic0 => 31,
+        };
+        1 << n
+    }
+}
+
+/// Events that enter snooze mode from software standby.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SnoozeRequest {
+    /// External pin interrupt IRQ0-IRQ15.
+    Irq(u8),
+    Acmplp,
+    RtcAlarm,
+    RtcPeriod,
+    Agt1Underflow,
+    Agt1CompareA,
+    Agt1CompareB,
+}
+
+impl SnoozeRequest {
+    /// Returns the bit in SNZREQCR.
+    fn bit(self) -> u32 {
+        let n = match self {
+            SnoozeRequest::Irq(n) => n as u32 & 0x0f,
+            SnoozeRequest::Acmplp => 23,
+            SnoozeRequest::RtcAlarm => 24,
+            SnoozeRequest::RtcPeriod => 25,
+            SnoozeRequest::Agt1Underflow => 28,
+            SnoozeRequest::Agt1CompareA => 29,
+            SnoozeRequest::Agt1CompareB => 30,
+        };
+        1 << n
+    }
+}
+
+/// Events that return from snooze mode to software standby.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SnoozeEnd {
+    Agt1Underflow = 1 << 0,
+    /// The DTC transfer count reached 0.
+    DtcDone = 1 << 1,
+    /// The DTC transfer count didn't reach 0.
+    DtcNotDone = 1 << 2,
+    /// The A/D converter result matched or didn't match its compare condition.
+    AdcMatch = 1 << 3,
+    AdcMismatch = 1 << 4,
+    /// SCI0 received an address that doesn't match.
+    Sci0AddressMismatch = 1 << 7,
+}
+
+/// Settings of software standby.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Standby {
+    wake: u32,
+    snooze_requests: u32,
+    snooze_ends: u8,
+    snooze_dtc: bool,
+}
+
+impl Standby {
+    /// Software standby without wake sources, add them with [`Standby::wake_on`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let `source` wake the MCU.
+    pub fn wake_on(self, source: WakeEvent) -> Self {
+        Self {
+            wake: self.wake | source.bit(),
+            ..self
+        }
+    }
+
+    /// Enter snooze mode on `request`.
+    pub fn snooze_on(self, request: SnoozeRequest) -> Self {
+        Self {
+            snooze_requests: self.snooze_requests | request.bit(),
+            ..self
+        }
+    }
+
+    /// Return from snooze mode to software standby on `end`.
+    pub fn snooze_end(self, end: SnoozeEnd) -> Self {
+        Self {
+            snooze_ends: self.snooze_ends | end as u8,
+            ..self
+        }
+    }
+
+    /// Let the DTC run in snooze mode, e.g. to move A/D results to memory.
+    pub fn with_snooze_dtc(self) -> Self {
+        Self {
+            snooze_dtc: true,
+            ..self
+        }
+    }
+
+    /// Enter software standby, and return after a wake source woke the MCU and the clocks are
+    /// stable again. If interrupts are enabled, the handler of the wake source runs before this
+    /// returns.
+    ///
+    /// Returns right away if an interrupt is pending, like [`sleep`]. Must not be used after
+    /// [`super::clocks::detect_oscillation_stop`].
+    pub fn enter(&self) {
+        interrupt::free(|| unsafe {
+            WUPEN.write_volatile(self.wake);
+            registers::unprotected(PRCR_PRC1, || {
+                SNZREQCR.write_volatile(self.snooze_requests);
+                SNZEDCR.write_volatile(self.snooze_ends);
+                let mut snzcr = 0;
+                if self.snooze_dtc {
+                    snzcr |= SNZCR_SNZDTCEN;
+                }
+                SNZCR.write_volatile(snzcr);
+                if self.snooze_requests != 0 {
+                    SNZCR.volatile_or(SNZCR_SNZE);
+                }
+                SBYCR.volatile_or(SBYCR_SSBY);
+            });
+            SCR.volatile_or(SCR_SLEEPDEEP);
+        });
+        wfi();
+        unsafe {
+            SCR.volatile_and(!SCR_SLEEPDEEP);
+            registers::unprotected(PRCR_PRC1, || {
+                SNZCR.write_volatile(0);
+                SBYCR.volatile_and(!SBYCR_SSBY);
+            });
+            WUPEN.write_volatile(0);
+            // The CPU may resume before the HOCO is stable, if the system clock doesn't run from
+            // it.
+            if HOCOCR.read_volatile() & 1 == 0 {
+                while OSCSF.read_volatile() & 1 == 0 {}
+            }
+        }
+    }
+}
+
+/// The pins of ports 0-5 of the package, one mask per port with bit n for pin n.
+const PORT_PINS: [u16; 6] = [0xf81f, 0x3fff, 0xf07f, 0x001f, 0x0fff, 0x0007];
+
+/// What [`minimize`] keeps running.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Minimize {
+    /// Pins that are left as they are, one mask per port 0-5 with bit n for pin n.
+    pub pins: [u16; 6],
+    /// Units that keep their clock although no driver enabled it, one bit per [`Module`].
+    pub modules: u32,
+    /// Oscillators that keep running although they aren't the system clock.
+    pub hoco: bool,
+    pub moco: bool,
+    pub loco: bool,
+    pub sub_oscillator: bool,
+}
+
+impl Default for Minimize {
+    /// Keeps the debug pins (P108 and P300), the mode pin P201 and the NMI pin P200, the LOCO and
+    /// the sub-clock oscillator with its pins P214 and P215 for the RTC, and the HOCO for USB.
+    fn default() -> Self {
+        Self {
+            pins: [
+                0,
+                1 << 8,
+                (1 << 0) | (1 << 1) | (1 << 14) | (1 << 15),
+                1 << 0,
+                0,
+                0,
+            ],
+            modules: 0,
+            hoco: true,
+            moco: false,
+            loco: true,
+            sub_oscillator: true,
+        }
+    }
+}
+
+impl Minimize {
+    /// Only keep the debug, mode and NMI pins, and the oscillator of the system clock.
+    pub fn nothing() -> Self {
+        Self {
+            pins: [0, 1 << 8, (1 << 0) | (1 << 1), 1 << 0, 0, 0],
+            hoco: false,
+            loco: false,
+            sub_oscillator: false,
+            ..Self::default()
+        }
+    }
+
+    /// Keep pin `pin_no` of port `port_no`, e.g. (1, 11) for P111.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if `port_no` is above 5 or `pin_no` above 15. Release builds
+    /// ignore such a pin, it doesn't exist.
+    pub fn with_pin(mut self, port_no: usize, pin_no: u32) -> Self {
+        debug_assert!(
+            port_no < 6 && pin_no < 16,
+            "no pin P{}{:02}",
+            port_no,
+            pin_no
+        );
+        if let Some(pins) = self.pins.get_mut(port_no) {
+            *pins |= 1u16.checked_shl(pin_no).unwrap_or(0);
+        }
+        self
+    }
+
+    /// Keep the clock of `module` running, e.g. for a unit that is used through its registers.
+    pub fn with_module(self, module: Module) -> Self {
+        Self {
+            modules: self.modules | (1 << module as u32),
+            ..self
+        }
+    }
+
+    /// Set which oscillators keep running.
+    pub fn with_oscillators(
+        self,
+        hoco: bool,
+        moco: bool,
+        loco: bool,
+        sub_oscillator: bool,
+    ) -> Self {
+        Self {
+            hoco,
+            moco,
+            loco,
+            sub_oscillator,
+            ..self
+        }
+    }
+}
+
+/// Park the unused pins, and stop the unused peripheral units and oscillators, see [`Minimize`].
+///
+/// The pins that drivers or the application use must be in `config`, otherwise they are parked
+/// as well. This isn't checked: The drivers that own them keep working on their registers, but
+/// their signals no longer reach the pins. Likewise, the clocks of units that a driver enabled
+/// keep running, but `config.modules` is needed for units used through their registers only.
+pub fn minimize(config: &Minimize) {
+    for (port_no, (&pins, &used)) in PORT_PINS.iter().zip(&config.pins).enumerate() {
+        pins::park(port_no as u32, pins & !used);
+    }
+    mstp::stop_unused(|module| config.modules & (1 << module as u32) != 0);
+    clocks::stop_unused(|source| match source {
+        Source::Hoco => config.hoco,
+        Source::Moco => config.moco,
+        Source::Loco => config.loco,
+        Source::SubOsc => config.sub_oscillator,
+        Source::MainOsc | Source::Pll => false,
+    });
+}
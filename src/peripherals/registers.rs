@@ -36,3 +36,25 @@ impl<T: RegisterType> VolatileBoolOps<T> for *mut T {
         self.write_volatile(self.read_volatile() ^ bitmask);
     }
 }
+
+/// Protect Register. Writes need the key 0xa5 in b8-b15, and set the bits below.
+/// * b0: PRC0, allows writing to the clock generation registers.
+/// * b1: PRC1, allows writing to the low power mode registers, MSTPCRA and the backup registers.
+/// * b3: PRC3, allows writing to the LVD registers.
+const PRCR: *mut u16 = 0x4001e3fe as *mut u16;
+
+const PRCR_KEY: u16 = 0xa5 << 8;
+pub(crate) const PRCR_PRC0: u16 = 1 << 0;
+pub(crate) const PRCR_PRC1: u16 = 1 << 1;
+const PRCR_PRC3: u16 = 1 << 3;
+
+/// Allow writing to the registers protected by `bits` of PRCR while `f` runs. The other bits keep
+/// their value, and `bits` are set back to what they were afterwards, so this can be nested, also
+/// in interrupt handlers.
+pub(crate) unsafe fn unprotected<R>(bits: u16, f: impl FnOnce() -> R) -> R {
+    let previous = PRCR.read_volatile() & (PRCR_PRC0 | PRCR_PRC1 | PRCR_PRC3);
+    PRCR.write_volatile(PRCR_KEY | previous | bits);
+    let result = f();
+    PRCR.write_volatile(PRCR_KEY | previous);
+    result
+}
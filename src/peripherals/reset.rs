@@ -0,0 +1,157 @@
+//! Reset the MCU, restart it into the Arduino bootloader, or find out why it was reset.
+//!
+//! [`system_reset`] resets the MCU like the reset button. [`enter_bootloader`] does the same, but
+//! first leaves a note for the Arduino bootloader to stay in its update mode instead of starting
+//! the firmware, so that new firmware can be uploaded over the USB port of the RA4M1. The upload
+//! tools ask for this by opening the serial port at 1200 baud and closing it again, see
+//! the `usb::serial` module.
+//!
+//! [`cause`] returns the reason of the last reset, e.g. to tell a watchdog reset from a cold boot.
+//! It clears the reset status flags the first time it is called, so that they only show the next
+//! reset, and returns the same cause afterwards. A reset by [`system_reset`] or
+//! [`enter_bootloader`] reads as [`Cause::Software`].
+//!
+//! For details, see the Armv7-M Architecture Reference Manual, section B3.2.6 (AIRCR), and
+//! Renesas RA4M1 Group User's Manual: Hardware, chapters "Resets" and "Battery Backup Function".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::reset;
+//!
+//! if reset::cause() == reset::Cause::Watchdog {
+//!     // The firmware got stuck before.
+//! }
+//! let update_requested = false;
+//! if update_requested {
+//!     reset::enter_bootloader();
+//! }
+//! ```
+
+use super::registers::{self, PRCR_PRC1};
+use crate::interrupt;
+
+/// Application Interrupt and Reset Control Register of the System Control Block. Writing the key
+/// 0x05fa in b16-b31 together with b2 (SYSRESETREQ) resets the MCU.
+const AIRCR: *mut u32 = 0xe000ed0c as *mut u32;
+
+/// The first four VBATT Backup Registers, which keep their value through a reset. The bootloader
+/// checks them for [`DOUBLE_TAP_MAGIC`] at startup.
+const VBTBKR: *mut u32 = 0x4001e500 as *mut u32;
+
+/// Reset Status Register 0. Flags are cleared by writing 0 after reading them as 1.
+/// * b0: PORF, power-on reset.
+/// * b1: LVD0RF, voltage monitor 0 reset.
+/// * b2: LVD1RF, voltage monitor 1 reset.
+/// * b3: LVD2RF, voltage monitor 2 reset.
+const RSTSR0: *mut u8 = 0x4001e410 as *mut u8;
+
+/// Reset Status Register 1. Flags are cleared by writing 0 after reading them as 1.
+/// * b0: IWDTRF, independent watchdog timer reset.
+/// * b1: WDTRF, watchdog timer reset.
+/// * b2: SWRF, software reset.
+/// * b8: RPERF, SRAM parity error reset.
+/// * b9: REERF, SRAM ECC error reset.
+/// * b10: BUSSRF, bus slave MPU error reset.
+/// * b11: BUSMRF, bus master MPU error reset.
+/// * b12: SPERF, CPU stack pointer error reset.
+const RSTSR1: *mut u16 = 0x4001e0c0 as *mut u16;
+
+/// Reset Status Register 2. b0: CWSF, warm start. Only a power-on reset clears it, and it is set
+/// by writing 1.
+const RSTSR2: *mut u8 = 0x4001e411 as *mut u8;
+
+/// Value that tells the Arduino bootloader to stay in its update mode, as if the reset button had
+/// been pressed twice.
+const DOUBLE_TAP_MAGIC: u32 = 0x07738135;
+
+/// Reset the MCU.
+pub fn system_reset() -> ! {
+    unsafe {
+        AIRCR.write_volatile((0x05fa << 16) | (1 << 2));
+    }
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Reset the MCU into the update mode of the Arduino bootloader.
+pub fn enter_bootloader() -> ! {
+    unsafe {
+        registers::unprotected(PRCR_PRC1, || VBTBKR.write_volatile(DOUBLE_TAP_MAGIC));
+    }
+    system_reset()
+}
+
+/// The reason of a reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Cause {
+    /// The supply voltage came up, i.e. a cold boot.
+    PowerOn,
+    /// The reset pin, e.g. the reset button, or a reset cause that left no flag.
+    Pin,
+    /// The voltage monitor 0, 1 or 2 saw the supply voltage drop.
+    LowVoltage0,
+    LowVoltage1,
+    LowVoltage2,
+    IndependentWatchdog,
+    Watchdog,
+    /// [`system_reset`] or another write to SYSRESETREQ.
+    Software,
+    /// An SRAM parity or ECC error.
+    SramParity,
+    SramEcc,
+    /// An access violating the bus slave or bus master MPU.
+    BusSlave,
+    BusMaster,
+    /// The stack pointer left the range set in the stack pointer monitor.
+    StackPointer,
+}
+
+/// Returns the reason of the last reset. If more than one reset happened since the flags were last
+/// cleared, the first one of the [`Cause`] list is returned.
+pub fn cause() -> Cause {
+    static mut CAUSE: Option<Cause> = None;
+    interrupt::free(|| unsafe {
+        if let Some(cause) = CAUSE {
+            return cause;
+        }
+        let rstsr0 = RSTSR0.read_volatile();
+        let rstsr1 = RSTSR1.read_volatile();
+        let warm = RSTSR2.read_volatile() & 1 != 0;
+        let cause = if rstsr0 & (1 << 0) != 0 || !warm {
+            Cause::PowerOn
+        } else if rstsr0 & (1 << 1) != 0 {
+            Cause::LowVoltage0
+        } else if rstsr0 & (1 << 2) != 0 {
+            Cause::LowVoltage1
+        } else if rstsr0 & (1 << 3) != 0 {
+            Cause::LowVoltage2
+        } else if rstsr1 & (1 << 0) != 0 {
+            Cause::IndependentWatchdog
+        } else if rstsr1 & (1 << 1) != 0 {
+            Cause::Watchdog
+        } else if rstsr1 & (1 << 2) != 0 {
+            Cause::Software
+        } else if rstsr1 & (1 << 8) != 0 {
+            Cause::SramParity
+        } else if rstsr1 & (1 << 9) != 0 {
+            Cause::SramEcc
+        } else if rstsr1 & (1 << 10) != 0 {
+            Cause::BusSlave
+        } else if rstsr1 & (1 << 11) != 0 {
+            Cause::BusMaster
+        } else if rstsr1 & (1 << 12) != 0 {
+            Cause::StackPointer
+        } else {
+            Cause::Pin
+        };
+        // Clear the flags that were read, and mark the next start as warm.
+        RSTSR0.write_volatile(!rstsr0);
+        RSTSR1.write_volatile(!rstsr1);
+        RSTSR2.write_volatile(1);
+        CAUSE = Some(cause);
+        cause
+    })
+}
@@ -0,0 +1,923 @@
+//! Real-time clock (RTC): Keeps the date and time in calendar form and raises an alarm interrupt
+//! when it matches a given time.
+//!
+//! The RTC keeps running through resets, so [`Rtc::is_running`] tells if it has to be set with
+//! [`Rtc::set_date_time`] after power-up.
+//!
+//! The RTC counts either the low-speed on-chip oscillator (LOCO) or the sub-clock oscillator with
+//! its 32.768 kHz crystal, see [`ClockSource`]. The LOCO is always available but can be off by a
+//! few percent. [`Rtc::set_loco_frequency`] compensates for that if its actual frequency has been
+//! measured. The crystal is much more accurate, and its remaining drift can be trimmed with
+//! [`Rtc::set_error_adjustment`].
+//!
+//! The RTC takes over changes of its settings with its own clock. The functions that change them
+//! wait for this, and return [`Error::ClockNotRunning`] if the clock doesn't run.
+//!
+//! An [`Alarm`] compares the enabled fields with the current date and time once per second. When
+//! all of them match, the handler passed to [`Rtc::set_alarm`] is called from the interrupt (see
+//! [`super::icu`]). For example, [`Alarm::daily`] fires every day at the same time.
+//!
+//! Instead of the calendar, the RTC can count the seconds in a 32-bit binary counter. Start it in
+//! this mode with [`Rtc::set_seconds`], e.g. with the seconds since the Unix epoch. The date and
+//! time are converted from and to the counter as needed, see [`DateTime::from_unix`] and
+//! [`DateTime::to_unix`]. The alarm is then set with [`Rtc::set_alarm_seconds`].
+//!
+//! [`DateTime::new`] checks the date against the calendar and computes the weekday. With the
+//! `time` feature, [`DateTime`] converts from and to `time::PrimitiveDateTime`.
+//!
+//! Besides the alarm, the RTC raises a periodic interrupt with a [`Period`] from 1/64 s to 2 s
+//! ([`Rtc::set_periodic_interrupt`]), and a carry interrupt every time the second counter
+//! advances ([`Rtc::set_carry_interrupt`]).
+//!
+//! The counters can advance while [`Rtc::date_time`] reads them one by one, e.g. from 09:59:59 to
+//! 10:00:00, which would give 09:00:00 or 10:59:59. It reads them again in that case, so the
+//! result is always consistent.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter
+//! "Realtime Clock (RTC)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::rtc::{Alarm, DateTime, Rtc};
+//!
+//! fn wake_up() {
+//!     // Runs every day at 07:30:00.
+//! }
+//!
+//! let mut rtc = Rtc::take().unwrap();
+//! if !rtc.is_running() {
+//!     rtc.set_date_time(&DateTime::new(2024, 1, 1, 0, 0, 0).unwrap())
+//!         .unwrap();
+//! }
+//! rtc.set_alarm(&Alarm::daily(7, 30), wake_up).unwrap();
+//! ```
+
+use super::clocks::{self, Cgc, SubOscillatorDrive};
+use super::icu::{self, Event, Slot};
+use super::registers::VolatileBoolOps;
+use crate::interrupt;
+
+/// How long the RTC may take to follow a change of its control registers, in microseconds. It
+/// does so with its own clock, which takes up to about a second to start after the sub-clock
+/// oscillator was started.
+const SYNC_TIMEOUT_US: u32 = 2_000_000;
+
+/// Interval at which the control registers are checked while waiting for the RTC, in
+/// microseconds.
+const SYNC_POLL_US: u32 = 10;
+
+/// Errors of the RTC functions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// A field of the date and time is out of range, or the year is not in 2000-2099.
+    InvalidDateTime,
+    /// The setting is out of range.
+    InvalidSetting,
+    /// The function isn't available in the current count mode (calendar or binary).
+    WrongMode,
+    /// All interrupt slots are in use.
+    NoFreeSlot,
+    /// The RTC didn't follow a change of its settings within 2 s, because its clock doesn't run.
+    ClockNotRunning,
+}
+
+/// A date and time as counted by the RTC, with years from 2000 to 2099.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DateTime {
+    /// 2000-2099.
+    pub year: u16,
+    /// 1-12.
+    pub month: u8,
+    /// 1-31.
+    pub day: u8,
+    /// 0-6, with 0 for Sunday.
+    pub weekday: u8,
+    /// 0-23.
+    pub hour: u8,
+    /// 0-59.
+    pub minute: u8,
+    /// 0-59.
+    pub second: u8,
+}
+
+impl DateTime {
+    /// The given date and time, with the weekday computed from the date.
+    ///
+    /// Returns [`Error::InvalidDateTime`] if a field is out of range, e.g. for February 29 in a
+    /// year that is not a leap year.
+    pub fn new(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Result<Self, Error> {
+        let date_time = Self {
+            year,
+            month,
+            day,
+            weekday: 0,
+            hour,
+            minute,
+            second,
+        };
+        if !date_time.has_valid_fields() {
+            return Err(Error::InvalidDateTime);
+        }
+        Ok(Self {
+            weekday: weekday(year, month, day),
+            ..date_time
+        })
+    }
+
+    /// Returns the day of the year, from 1 for January 1 to 365 or 366 for December 31.
+    pub fn day_of_year(&self) -> u16 {
+        (days_from_civil(self.year as i32, self.month, self.day)
+            - days_from_civil(self.year as i32, 1, 1)
+            + 1) as u16
+    }
+
+    /// The date and time `seconds` after 1970-01-01 00:00:00 (the Unix epoch).
+    pub fn from_unix(seconds: u32) -> Self {
+        let days = (seconds / 86400) as i32;
+        let time = seconds % 86400;
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year: year as u16,
+            month,
+            day,
+            weekday: weekday(year as u16, month, day),
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// Returns the seconds since 1970-01-01 00:00:00 (the Unix epoch). The weekday is ignored.
+    ///
+    /// Dates before 1970 give 0 and dates after 2106-02-07 06:28:15 overflow.
+    pub fn to_unix(&self) -> u32 {
+        let days = days_from_civil(self.year as i32, self.month, self.day).max(0) as u32;
+        days.wrapping_mul(86400)
+            .wrapping_add(self.hour as u32 * 3600 + self.minute as u32 * 60 + self.second as u32)
+    }
+
+    /// Returns true if all fields are in range and the year can be counted by the calendar of the
+    /// RTC. The weekday isn't checked against the date.
+    fn is_valid(&self) -> bool {
+        (2000..=2099).contains(&self.year) && self.weekday <= 6 && self.has_valid_fields()
+    }
+
+    /// Returns true if the date exists and the time is in range. Ignores the weekday.
+    fn has_valid_fields(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour <= 23
+            && self.minute <= 59
+            && self.second <= 59
+    }
+}
+
+/// Returns true if `year` is a leap year in the Gregorian calendar.
+pub fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+/// Returns the number of days in `month` (1-12) of `year`, or 0 if `month` is out of range.
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+/// Returns the day of the week of a date, 0 for Sunday.
+pub fn weekday(year: u16, month: u8, day: u8) -> u8 {
+    // 1970-01-01 was a Thursday.
+    (days_from_civil(year as i32, month, day) + 4).rem_euclid(7) as u8
+}
+
+/// The fields of the date and time that an alarm compares. Fields that are `None` match any
+/// value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Alarm {
+    pub year: Option<u16>,
+    pub month: Option<u8>,
+    pub day: Option<u8>,
+    pub weekday: Option<u8>,
+    pub hour: Option<u8>,
+    pub minute: Option<u8>,
+    pub second: Option<u8>,
+}
+
+impl Alarm {
+    /// An alarm every day at `hour:minute:00`.
+    pub fn daily(hour: u8, minute: u8) -> Self {
+        Self {
+            hour: Some(hour),
+            minute: Some(minute),
+            second: Some(0),
+            ..Self::default()
+        }
+    }
+
+    /// An alarm every week on `weekday` (0 for Sunday) at `hour:minute:00`.
+    pub fn weekly(weekday: u8, hour: u8, minute: u8) -> Self {
+        Self {
+            weekday: Some(weekday),
+            ..Self::daily(hour, minute)
+        }
+    }
+
+    /// An alarm at exactly `date_time`. The weekday is not compared.
+    pub fn at(date_time: &DateTime) -> Self {
+        Self {
+            year: Some(date_time.year),
+            month: Some(date_time.month),
+            day: Some(date_time.day),
+            weekday: None,
+            hour: Some(date_time.hour),
+            minute: Some(date_time.minute),
+            second: Some(date_time.second),
+        }
+    }
+
+    /// Returns true if all enabled fields are in range.
+    fn is_valid(&self) -> bool {
+        self.year.is_none_or(|year| (2000..=2099).contains(&year))
+            && self.month.is_none_or(|month| (1..=12).contains(&month))
+            && self.day.is_none_or(|day| (1..=31).contains(&day))
+            && self.weekday.is_none_or(|weekday| weekday <= 6)
+            && self.hour.is_none_or(|hour| hour <= 23)
+            && self.minute.is_none_or(|minute| minute <= 59)
+            && self.second.is_none_or(|second| second <= 59)
+    }
+}
+
+/// The clock that the RTC counts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockSource {
+    /// The low-speed on-chip oscillator, nominally 32.768 kHz.
+    Loco,
+    /// The sub-clock oscillator with a 32.768 kHz crystal.
+    SubClock,
+}
+
+/// Time error adjustment for the sub-clock: Every `interval`, `cycles` cycles of the sub-clock are
+/// added to (if positive) or removed from (if negative) the prescaler of the RTC.
+///
+/// Adding cycles makes the RTC run faster, use this if it is slow. With an interval of one minute,
+/// each cycle corrects about 0.51 ppm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ErrorAdjustment {
+    /// -63 to 63.
+    pub cycles: i8,
+    pub interval: AdjustmentInterval,
+}
+
+/// How often the [`ErrorAdjustment`] is applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AdjustmentInterval {
+    /// Every minute, at second 00.
+    Minute,
+    /// Every 10 seconds, at second 00, 10, 20, 30, 40 and 50.
+    TenSeconds,
+}
+
+/// Interval of the periodic interrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Period {
+    /// 1/64 s.
+    Hz64 = 0x8,
+    /// 1/32 s.
+    Hz32 = 0x9,
+    /// 1/16 s.
+    Hz16 = 0xa,
+    /// 1/8 s.
+    Hz8 = 0xb,
+    /// 1/4 s.
+    Hz4 = 0xc,
+    /// 1/2 s.
+    Hz2 = 0xd,
+    /// 1 s.
+    Hz1 = 0xe,
+    /// 2 s.
+    TwoSeconds = 0xf,
+}
+
+/// Returns the number of days from 1970-01-01 to the date. Works for any date in the proleptic
+/// Gregorian calendar, see <https://howardhinnant.github.io/date_algorithms.html>.
+fn days_from_civil(year: i32, month: u8, day: u8) -> i32 {
+    // Count from March, so the leap day is the last day of the year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month as i32 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i32 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the year, month and day of the date `days` after 1970-01-01. Inverse of
+/// [`days_from_civil`].
+fn civil_from_days(days: i32) -> (i32, u8, u8) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u8;
+    let month = ((month_from_march + 2) % 12 + 1) as u8;
+    let year = year_of_era + era * 400 + (month <= 2) as i32;
+    (year, month, day)
+}
+
+/// Convert `value` (0-99) to binary-coded decimal.
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// Convert `bcd` from binary-coded decimal.
+fn from_bcd(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0x0f)
+}
+
+/// The RTC.
+pub struct Rtc {
+    alarm_slot: Option<Slot>,
+    periodic_slot: Option<Slot>,
+    carry_slot: Option<Slot>,
+    clock_source: ClockSource,
+    /// The RFC field of RFRL for the LOCO.
+    loco_divider: u16,
+}
+
+impl Rtc {
+    const BASE_ADDRESS: u32 = 0x40044000;
+
+    /// Binary Counter 0-3, the bytes of the 32-bit seconds counter in binary count mode, least
+    /// significant first. They share the addresses of the calendar counters.
+    const BCNT: [*mut u8; 4] = [
+        (Self::BASE_ADDRESS + 0x02) as *mut u8,
+        (Self::BASE_ADDRESS + 0x04) as *mut u8,
+        (Self::BASE_ADDRESS + 0x06) as *mut u8,
+        (Self::BASE_ADDRESS + 0x08) as *mut u8,
+    ];
+
+    /// Binary Counter 0-3 Alarm Registers, compared with the bytes of the counter in binary count
+    /// mode. They share the addresses of the calendar alarm registers.
+    const BCNTAR: [*mut u8; 4] = [
+        (Self::BASE_ADDRESS + 0x10) as *mut u8,
+        (Self::BASE_ADDRESS + 0x12) as *mut u8,
+        (Self::BASE_ADDRESS + 0x14) as *mut u8,
+        (Self::BASE_ADDRESS + 0x16) as *mut u8,
+    ];
+
+    /// Binary Counter 0-3 Alarm Enable Registers. Each bit enables the comparison of the
+    /// corresponding bit of the alarm register.
+    const BCNTAER: [*mut u8; 4] = [
+        (Self::BASE_ADDRESS + 0x18) as *mut u8,
+        (Self::BASE_ADDRESS + 0x1a) as *mut u8,
+        (Self::BASE_ADDRESS + 0x1c) as *mut u8,
+        (Self::BASE_ADDRESS + 0x1e) as *mut u8,
+    ];
+
+    /// Second Counter, BCD.
+    const RSECCNT: *mut u8 = (Self::BASE_ADDRESS + 0x02) as *mut u8;
+
+    /// Minute Counter, BCD.
+    const RMINCNT: *mut u8 = (Self::BASE_ADDRESS + 0x04) as *mut u8;
+
+    /// Hour Counter, BCD. In 24-hour mode, b0-b5 hold the hour.
+    const RHRCNT: *mut u8 = (Self::BASE_ADDRESS + 0x06) as *mut u8;
+
+    /// Day-of-Week Counter, 0-6.
+    const RWKCNT: *mut u8 = (Self::BASE_ADDRESS + 0x08) as *mut u8;
+
+    /// Date Counter, BCD.
+    const RDAYCNT: *mut u8 = (Self::BASE_ADDRESS + 0x0a) as *mut u8;
+
+    /// Month Counter, BCD.
+    const RMONCNT: *mut u8 = (Self::BASE_ADDRESS + 0x0c) as *mut u8;
+
+    /// Year Counter, BCD. Counts the years 00-99.
+    const RYRCNT: *mut u16 = (Self::BASE_ADDRESS + 0x0e) as *mut u16;
+
+    /// Second Alarm Register. b0-b6: BCD value, b7: ENB, compare this field.
+    const RSECAR: *mut u8 = (Self::BASE_ADDRESS + 0x10) as *mut u8;
+
+    /// Minute Alarm Register, like RSECAR.
+    const RMINAR: *mut u8 = (Self::BASE_ADDRESS + 0x12) as *mut u8;
+
+    /// Hour Alarm Register, like RSECAR.
+    const RHRAR: *mut u8 = (Self::BASE_ADDRESS + 0x14) as *mut u8;
+
+    /// Day-of-Week Alarm Register, like RSECAR.
+    const RWKAR: *mut u8 = (Self::BASE_ADDRESS + 0x16) as *mut u8;
+
+    /// Date Alarm Register, like RSECAR.
+    const RDAYAR: *mut u8 = (Self::BASE_ADDRESS + 0x18) as *mut u8;
+
+    /// Month Alarm Register, like RSECAR.
+    const RMONAR: *mut u8 = (Self::BASE_ADDRESS + 0x1a) as *mut u8;
+
+    /// Year Alarm Register, BCD.
+    const RYRAR: *mut u16 = (Self::BASE_ADDRESS + 0x1c) as *mut u16;
+
+    /// Year Alarm Enable Register. b7: ENB, compare RYRAR.
+    const RYRAREN: *mut u8 = (Self::BASE_ADDRESS + 0x1e) as *mut u8;
+
+    /// RTC Control Register 1.
+    /// * b0: AIE, alarm interrupt enable.
+    /// * b1: CIE, carry interrupt enable.
+    /// * b2: PIE, periodic interrupt enable.
+    /// * b4-b7: PES, interval of the periodic interrupt, see [`Period`].
+    const RCR1: *mut u8 = (Self::BASE_ADDRESS + 0x22) as *mut u8;
+
+    /// Time Error Adjustment Register.
+    /// * b0-b5: ADJ, the number of sub-clock cycles to add or remove.
+    /// * b6-b7: PMADJ, 01 to add, 10 to remove, 00 for no adjustment.
+    const RADJ: *mut u8 = (Self::BASE_ADDRESS + 0x2e) as *mut u8;
+
+    /// RTC Control Register 2.
+    /// * b0: START, the counters are running.
+    /// * b1: RESET, reset the prescaler, the alarm registers and RADJ. Cleared when done.
+    /// * b4: AADJE, apply the adjustment in RADJ automatically.
+    /// * b5: AADJP, apply it every 10 seconds instead of every minute.
+    /// * b6: HR24, 24-hour mode.
+    /// * b7: CNTMD, binary count mode instead of calendar mode.
+    const RCR2: *mut u8 = (Self::BASE_ADDRESS + 0x24) as *mut u8;
+
+    /// RTC Control Register 4. b0: RCKSEL, 1 to count the LOCO instead of the sub-clock.
+    const RCR4: *mut u8 = (Self::BASE_ADDRESS + 0x28) as *mut u8;
+
+    /// Frequency Register L. b0-b8: RFC, the LOCO is divided by RFC + 1 to get the 128 Hz clock of
+    /// the prescaler. Only used with the LOCO.
+    const RFRL: *mut u16 = (Self::BASE_ADDRESS + 0x2c) as *mut u16;
+
+    /// Frequency Register H. Must be 0.
+    const RFRH: *mut u16 = (Self::BASE_ADDRESS + 0x2a) as *mut u16;
+
+    /// Field enable bit of the alarm registers.
+    const ENB: u8 = 1 << 7;
+
+    const RCR1_AIE: u8 = 1 << 0;
+    const RCR1_CIE: u8 = 1 << 1;
+    const RCR1_PIE: u8 = 1 << 2;
+    const RCR1_PES: u8 = 0xf << 4;
+
+    const RCR2_START: u8 = 1 << 0;
+    const RCR2_RESET: u8 = 1 << 1;
+    const RCR2_AADJE: u8 = 1 << 4;
+    const RCR2_AADJP: u8 = 1 << 5;
+    const RCR2_HR24: u8 = 1 << 6;
+    const RCR2_CNTMD: u8 = 1 << 7;
+
+    /// Returns the RTC, unless it was taken already.
+    pub fn take() -> Option<Self> {
+        static mut TAKEN: bool = false;
+        interrupt::free(|| unsafe {
+            if TAKEN {
+                None
+            } else {
+                TAKEN = true;
+                Some(Self {
+                    alarm_slot: None,
+                    periodic_slot: None,
+                    carry_slot: None,
+                    clock_source: Self::current_clock_source(),
+                    loco_divider: Self::current_loco_divider(),
+                })
+            }
+        })
+    }
+
+    /// Returns true if the RTC is counting. It keeps counting through resets, so this is false
+    /// only after power-up or if the RTC was stopped.
+    pub fn is_running(&self) -> bool {
+        unsafe { Self::RCR2.read_volatile() & Self::RCR2_START != 0 }
+    }
+
+    /// Reset the RTC, set it to `date_time` and start it in 24-hour calendar mode.
+    ///
+    /// The alarm and the error adjustment are cleared, call [`Rtc::set_alarm`] and
+    /// [`Rtc::set_error_adjustment`] again afterwards.
+    pub fn set_date_time(&mut self, date_time: &DateTime) -> Result<(), Error> {
+        if !date_time.is_valid() {
+            return Err(Error::InvalidDateTime);
+        }
+        unsafe {
+            Self::stop()?;
+            self.select_clock_source(self.clock_source)?;
+            Self::RCR2.write_volatile(Self::RCR2_RESET);
+            Self::wait_for(|| Self::RCR2.read_volatile() & Self::RCR2_RESET == 0)?;
+
+            Self::RSECCNT.write_volatile(to_bcd(date_time.second));
+            Self::RMINCNT.write_volatile(to_bcd(date_time.minute));
+            Self::RHRCNT.write_volatile(to_bcd(date_time.hour));
+            Self::RWKCNT.write_volatile(date_time.weekday);
+            Self::RDAYCNT.write_volatile(to_bcd(date_time.day));
+            Self::RMONCNT.write_volatile(to_bcd(date_time.month));
+            Self::RYRCNT.write_volatile(to_bcd((date_time.year - 2000) as u8) as u16);
+
+            Self::RCR2.write_volatile(Self::RCR2_HR24);
+            Self::write_and_wait(Self::RCR2, Self::RCR2_HR24 | Self::RCR2_START)
+        }
+    }
+
+    /// Returns the clock that the RTC counts.
+    pub fn clock_source(&self) -> ClockSource {
+        self.clock_source
+    }
+
+    /// Count `source` from now on. The sub-clock oscillator is started if necessary, it takes up
+    /// to a few seconds until it runs stably. The counters keep their values.
+    pub fn set_clock_source(&mut self, source: ClockSource) -> Result<(), Error> {
+        let running = self.is_running();
+        unsafe {
+            Self::stop()?;
+            self.select_clock_source(source)?;
+            self.clock_source = source;
+            if running {
+                Self::write_and_wait(Self::RCR2, Self::RCR2.read_volatile() | Self::RCR2_START)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compensate for the inaccuracy of the LOCO, given its measured frequency `hz`. The RTC
+    /// divides the LOCO by a whole number to get 128 Hz, so the compensation is only accurate to
+    /// about 0.4%. Only has an effect with [`ClockSource::Loco`].
+    ///
+    /// Returns [`Error::InvalidSetting`] if `hz` is not between 128 Hz and 64 kHz.
+    pub fn set_loco_frequency(&mut self, hz: u32) -> Result<(), Error> {
+        let divider = (hz + 64) / 128;
+        if !(1..=512).contains(&divider) {
+            return Err(Error::InvalidSetting);
+        }
+        self.loco_divider = (divider - 1) as u16;
+        unsafe {
+            Self::RFRH.write_volatile(0);
+            Self::RFRL.write_volatile(self.loco_divider);
+        }
+        Ok(())
+    }
+
+    /// Trim the drift of the sub-clock by regularly adding or removing cycles, or stop doing so if
+    /// `adjustment` is `None`. Only has an effect with [`ClockSource::SubClock`].
+    ///
+    /// Returns [`Error::InvalidSetting`] if the number of cycles is out of range.
+    pub fn set_error_adjustment(
+        &mut self,
+        adjustment: Option<ErrorAdjustment>,
+    ) -> Result<(), Error> {
+        if adjustment.is_some_and(|adjustment| !(-63..=63).contains(&adjustment.cycles)) {
+            return Err(Error::InvalidSetting);
+        }
+        unsafe {
+            let rcr2 = Self::RCR2.read_volatile() & !(Self::RCR2_AADJE | Self::RCR2_AADJP);
+            Self::write_and_wait(Self::RCR2, rcr2)?;
+            Self::write_and_wait(Self::RADJ, 0)?;
+            let Some(adjustment) = adjustment else {
+                return Ok(());
+            };
+            let interval = match adjustment.interval {
+                AdjustmentInterval::Minute => 0,
+                AdjustmentInterval::TenSeconds => Self::RCR2_AADJP,
+            };
+            Self::write_and_wait(Self::RCR2, rcr2 | interval)?;
+            let direction = if adjustment.cycles >= 0 { 0b01 } else { 0b10 };
+            let radj = (direction << 6) | adjustment.cycles.unsigned_abs();
+            if adjustment.cycles != 0 {
+                Self::write_and_wait(Self::RADJ, radj)?;
+            }
+            Self::write_and_wait(Self::RCR2, rcr2 | interval | Self::RCR2_AADJE)
+        }
+    }
+
+    /// Reset the RTC, set the binary counter to `seconds` and start it in binary count mode.
+    ///
+    /// The alarm and the error adjustment are cleared, call [`Rtc::set_alarm_seconds`] and
+    /// [`Rtc::set_error_adjustment`] again afterwards.
+    pub fn set_seconds(&mut self, seconds: u32) -> Result<(), Error> {
+        unsafe {
+            Self::stop()?;
+            self.select_clock_source(self.clock_source)?;
+            Self::RCR2.write_volatile(Self::RCR2_CNTMD | Self::RCR2_RESET);
+            Self::wait_for(|| Self::RCR2.read_volatile() & Self::RCR2_RESET == 0)?;
+
+            for (register, byte) in Self::BCNT.into_iter().zip(seconds.to_le_bytes()) {
+                register.write_volatile(byte);
+            }
+
+            Self::RCR2.write_volatile(Self::RCR2_CNTMD);
+            Self::write_and_wait(Self::RCR2, Self::RCR2_CNTMD | Self::RCR2_START)
+        }
+    }
+
+    /// Returns true if the RTC is in binary count mode, see [`Rtc::set_seconds`].
+    pub fn is_binary(&self) -> bool {
+        unsafe { Self::RCR2.read_volatile() & Self::RCR2_CNTMD != 0 }
+    }
+
+    /// Returns the binary counter in binary count mode, or the current date and time converted
+    /// with [`DateTime::to_unix`] in calendar mode.
+    pub fn seconds(&self) -> u32 {
+        if !self.is_binary() {
+            return self.date_time().to_unix();
+        }
+        let mut seconds = Self::read_binary_counter();
+        loop {
+            let again = Self::read_binary_counter();
+            if again == seconds {
+                return seconds;
+            }
+            seconds = again;
+        }
+    }
+
+    fn read_binary_counter() -> u32 {
+        let bytes = Self::BCNT.map(|register| unsafe { register.read_volatile() });
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Returns the current date and time. In binary count mode, the counter is converted with
+    /// [`DateTime::from_unix`].
+    pub fn date_time(&self) -> DateTime {
+        if self.is_binary() {
+            return DateTime::from_unix(self.seconds());
+        }
+        // If the counters advance while we read them, the two reads differ. They can't advance
+        // twice in the few cycles that the second read takes.
+        let mut date_time = Self::read_counters();
+        loop {
+            let again = Self::read_counters();
+            if again == date_time {
+                return date_time;
+            }
+            date_time = again;
+        }
+    }
+
+    fn read_counters() -> DateTime {
+        unsafe {
+            DateTime {
+                year: 2000 + from_bcd(Self::RYRCNT.read_volatile() as u8) as u16,
+                month: from_bcd(Self::RMONCNT.read_volatile()),
+                day: from_bcd(Self::RDAYCNT.read_volatile()),
+                weekday: Self::RWKCNT.read_volatile(),
+                hour: from_bcd(Self::RHRCNT.read_volatile() & 0x3f),
+                minute: from_bcd(Self::RMINCNT.read_volatile()),
+                second: from_bcd(Self::RSECCNT.read_volatile()),
+            }
+        }
+    }
+
+    /// Call `handler` from the alarm interrupt whenever the date and time match `alarm`. Replaces
+    /// the alarm that was set before, if any.
+    ///
+    /// Only available in calendar mode, returns [`Error::WrongMode`] in binary count mode.
+    pub fn set_alarm(&mut self, alarm: &Alarm, handler: fn()) -> Result<(), Error> {
+        if self.is_binary() {
+            return Err(Error::WrongMode);
+        }
+        if !alarm.is_valid() {
+            return Err(Error::InvalidDateTime);
+        }
+        self.clear_alarm()?;
+        let field = |value: Option<u8>| value.map_or(0, |value| Self::ENB | to_bcd(value));
+        unsafe {
+            Self::RSECAR.write_volatile(field(alarm.second));
+            Self::RMINAR.write_volatile(field(alarm.minute));
+            Self::RHRAR.write_volatile(field(alarm.hour));
+            Self::RWKAR.write_volatile(alarm.weekday.map_or(0, |weekday| Self::ENB | weekday));
+            Self::RDAYAR.write_volatile(field(alarm.day));
+            Self::RMONAR.write_volatile(field(alarm.month));
+            match alarm.year {
+                Some(year) => {
+                    Self::RYRAR.write_volatile(to_bcd((year - 2000) as u8) as u16);
+                    Self::RYRAREN.write_volatile(Self::ENB);
+                }
+                None => Self::RYRAREN.write_volatile(0),
+            }
+        }
+        // Attaching clears a request that the alarm registers might have raised while they were
+        // written.
+        Self::enable_interrupt(
+            &mut self.alarm_slot,
+            Event::RtcAlarm,
+            handler,
+            Self::RCR1_AIE,
+        )
+    }
+
+    /// Call `handler` from the alarm interrupt when the binary counter reaches `seconds`. Replaces
+    /// the alarm that was set before, if any.
+    ///
+    /// Only available in binary count mode, returns [`Error::WrongMode`] in calendar mode.
+    pub fn set_alarm_seconds(&mut self, seconds: u32, handler: fn()) -> Result<(), Error> {
+        if !self.is_binary() {
+            return Err(Error::WrongMode);
+        }
+        self.clear_alarm()?;
+        unsafe {
+            for (i, byte) in seconds.to_le_bytes().into_iter().enumerate() {
+                Self::BCNTAR[i].write_volatile(byte);
+                Self::BCNTAER[i].write_volatile(0xff);
+            }
+        }
+        Self::enable_interrupt(
+            &mut self.alarm_slot,
+            Event::RtcAlarm,
+            handler,
+            Self::RCR1_AIE,
+        )
+    }
+
+    /// Disable the alarm and free its interrupt slot.
+    pub fn clear_alarm(&mut self) -> Result<(), Error> {
+        Self::disable_interrupt(&mut self.alarm_slot, Self::RCR1_AIE)
+    }
+
+    /// Call `handler` from the periodic interrupt every `period`. Replaces the periodic interrupt
+    /// that was set before, if any.
+    pub fn set_periodic_interrupt(&mut self, period: Period, handler: fn()) -> Result<(), Error> {
+        self.clear_periodic_interrupt()?;
+        unsafe {
+            let rcr1 = Self::RCR1.read_volatile() & !Self::RCR1_PES;
+            Self::write_and_wait(Self::RCR1, rcr1 | ((period as u8) << 4))?;
+        }
+        Self::enable_interrupt(
+            &mut self.periodic_slot,
+            Event::RtcPeriod,
+            handler,
+            Self::RCR1_PIE,
+        )
+    }
+
+    /// Disable the periodic interrupt and free its interrupt slot.
+    pub fn clear_periodic_interrupt(&mut self) -> Result<(), Error> {
+        Self::disable_interrupt(&mut self.periodic_slot, Self::RCR1_PIE)
+    }
+
+    /// Call `handler` from the carry interrupt every time the second counter advances. Replaces the
+    /// carry interrupt that was set before, if any.
+    pub fn set_carry_interrupt(&mut self, handler: fn()) -> Result<(), Error> {
+        self.clear_carry_interrupt()?;
+        Self::enable_interrupt(
+            &mut self.carry_slot,
+            Event::RtcCarry,
+            handler,
+            Self::RCR1_CIE,
+        )
+    }
+
+    /// Disable the carry interrupt and free its interrupt slot.
+    pub fn clear_carry_interrupt(&mut self) -> Result<(), Error> {
+        Self::disable_interrupt(&mut self.carry_slot, Self::RCR1_CIE)
+    }
+
+    /// Set `enable` in RCR1 and link `event` to an interrupt slot that calls `handler`. The slot is
+    /// stored in `slot`.
+    fn enable_interrupt(
+        slot: &mut Option<Slot>,
+        event: Event,
+        handler: fn(),
+        enable: u8,
+    ) -> Result<(), Error> {
+        unsafe {
+            Self::write_and_wait(Self::RCR1, Self::RCR1.read_volatile() | enable)?;
+        }
+        *slot = icu::attach(event, handler);
+        if slot.is_some() {
+            Ok(())
+        } else {
+            Self::disable_interrupt(slot, enable)?;
+            Err(Error::NoFreeSlot)
+        }
+    }
+
+    /// Clear `enable` in RCR1 and free the interrupt slot in `slot`, if any.
+    fn disable_interrupt(slot: &mut Option<Slot>, enable: u8) -> Result<(), Error> {
+        if let Some(slot) = slot.take() {
+            icu::detach(slot);
+        }
+        unsafe { Self::write_and_wait(Self::RCR1, Self::RCR1.read_volatile() & !enable) }
+    }
+
+    /// Returns the clock source that the RTC is set to if it is running, or the LOCO otherwise.
+    fn current_clock_source() -> ClockSource {
+        unsafe {
+            if Self::RCR2.read_volatile() & Self::RCR2_START != 0
+                && Self::RCR4.read_volatile() & 1 == 0
+            {
+                ClockSource::SubClock
+            } else {
+                ClockSource::Loco
+            }
+        }
+    }
+
+    /// Returns the LOCO divider that the RTC is set to if it is running from the LOCO, or the one
+    /// for the nominal 32.768 kHz otherwise.
+    fn current_loco_divider() -> u16 {
+        let running = unsafe { Self::RCR2.read_volatile() } & Self::RCR2_START != 0;
+        match Self::current_clock_source() {
+            ClockSource::Loco if running => unsafe { Self::RFRL.read_volatile() & 0x1ff },
+            // Divide 32768 Hz by 256 for 128 Hz.
+            _ => 0xff,
+        }
+    }
+
+    /// Select `source` while the counters are stopped.
+    unsafe fn select_clock_source(&self, source: ClockSource) -> Result<(), Error> {
+        match source {
+            ClockSource::Loco => {
+                Self::write_and_wait(Self::RCR4, 1)?;
+                Self::RFRH.write_volatile(0);
+                Self::RFRL.write_volatile(self.loco_divider);
+            }
+            ClockSource::SubClock => {
+                // Fails only if the oscillator runs already, with another drive capability.
+                let _ = clocks::start_sub_oscillator(SubOscillatorDrive::Normal);
+                Self::write_and_wait(Self::RCR4, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop the counters and wait until they have stopped.
+    unsafe fn stop() -> Result<(), Error> {
+        Self::RCR2.volatile_and(!Self::RCR2_START);
+        Self::wait_for(|| Self::RCR2.read_volatile() & Self::RCR2_START == 0)
+    }
+
+    /// Write `value` to the control register `register` and wait until the RTC has taken it over,
+    /// which takes a few cycles of its clock.
+    unsafe fn write_and_wait(register: *mut u8, value: u8) -> Result<(), Error> {
+        register.write_volatile(value);
+        Self::wait_for(|| register.read_volatile() == value)
+    }
+
+    /// Wait until `done` returns true. Returns [`Error::ClockNotRunning`] after [`SYNC_TIMEOUT_US`].
+    fn wait_for(done: impl Fn() -> bool) -> Result<(), Error> {
+        let iclk = Cgc::current().iclk();
+        for _ in 0..SYNC_TIMEOUT_US / SYNC_POLL_US {
+            if done() {
+                return Ok(());
+            }
+            clocks::wait_us(iclk, SYNC_POLL_US);
+        }
+        Err(Error::ClockNotRunning)
+    }
+}
+
+#[cfg(feature = "time")]
+mod time_impl {
+    use super::{DateTime, Error};
+    use time::{Date, Month, PrimitiveDateTime, Time};
+
+    impl TryFrom<DateTime> for PrimitiveDateTime {
+        type Error = Error;
+
+        fn try_from(date_time: DateTime) -> Result<Self, Error> {
+            let month = Month::try_from(date_time.month).map_err(|_| Error::InvalidDateTime)?;
+            let date = Date::from_calendar_date(date_time.year as i32, month, date_time.day)
+                .map_err(|_| Error::InvalidDateTime)?;
+            let time = Time::from_hms(date_time.hour, date_time.minute, date_time.second)
+                .map_err(|_| Error::InvalidDateTime)?;
+            Ok(PrimitiveDateTime::new(date, time))
+        }
+    }
+
+    impl TryFrom<PrimitiveDateTime> for DateTime {
+        type Error = Error;
+
+        /// Fails for years before 0 or after 65535. The sub-second part is dropped.
+        fn try_from(date_time: PrimitiveDateTime) -> Result<Self, Error> {
+            Ok(DateTime {
+                year: u16::try_from(date_time.year()).map_err(|_| Error::InvalidDateTime)?,
+                month: date_time.month().into(),
+                day: date_time.day(),
+                weekday: date_time.weekday().number_days_from_sunday(),
+                hour: date_time.hour(),
+                minute: date_time.minute(),
+                second: date_time.second(),
+            })
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! Segment LCD controller (SLCDC): Drive a raw glass LCD with up to 4 (or 8) common and 39 segment
+//! lines.
+//!
+//! Each segment line SEGn has a data register. Its bits b0-b3 select which of COM0-COM3 light the
+//! segments on that line (pattern A). With 8 common lines, all 8 bits are used. The controller
+//! generates the AC waveform with the [`Bias`] and [`Duty`] of the [`Config`], so the segments
+//! only need to be written when the display changes.
+//!
+//! With 4 or fewer common lines, bits b4-b7 hold a second pattern (B). [`Slcdc::set_blinking`]
+//! alternates between both patterns at the period of the RTC periodic interrupt (see
+//! [`super::rtc`]), e.g. to blink a colon. [`Slcdc::show`] shows one of them steadily.
+//!
+//! The lines are spread over many pins, so they are selected by port and pin number with
+//! [`Slcdc::connect_pin`], see the pin table of the manual for the SEG and COM functions.
+//!
+//! The LCD clock comes from the LOCO, the sub-clock oscillator, the main oscillator or the HOCO,
+//! divided by [`Config::divider`] to get the frame frequency. The LOCO and sub-clock keep the
+//! display running in software standby.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter
+//! "Segment LCD Controller (SLCDC)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::slcdc::{Bias, Config, Duty, Slcdc};
+//!
+//! // Port and pin numbers of the COM and SEG pins that the glass is wired to.
+//! const LCD_PINS: [(u32, u32); 6] = [(3, 3), (3, 4), (3, 5), (3, 6), (1, 5), (1, 6)];
+//!
+//! let config = Config::default().with_duty(Duty::Quarter, Bias::Third);
+//! let mut lcd = Slcdc::take(mstp::token(), config).unwrap();
+//! for (port_no, pin_no) in LCD_PINS {
+//!     unsafe { lcd.connect_pin(port_no, pin_no) };
+//! }
+//! lcd.set_segment(0, 0b1111);
+//! lcd.set_segment(1, 0b0101);
+//! lcd.enable();
+//! ```
+
+use super::clocks;
+use super::mstp::{unit, MstpToken};
+use super::pins;
+use super::registers::VolatileBoolOps;
+use crate::interrupt;
+
+/// LCD Mode Register 0.
+/// * b0-b1: LBAS, bias, see [`Bias`].
+/// * b2-b4: LDTY, duty, see [`Duty`].
+/// * b5: LWAVE, 0: waveform A, 1: waveform B.
+/// * b6-b7: MDSET, drive method, see [`Drive`].
+const LCDM0: *mut u8 = 0x40082000 as *mut u8;
+
+/// LCD Mode Register 1.
+/// * b3: LCDSEL, shows pattern 0: A, 1: B.
+/// * b4: BLON, alternates between the patterns.
+/// * b5: VLCON, enables the voltage boost or capacitor split circuit.
+/// * b6: SCOC, enables the segment and common outputs.
+/// * b7: LCDON, turns the display on.
+const LCDM1: *mut u8 = 0x40082001 as *mut u8;
+
+/// LCD Clock Control Register 0, the divider of the LCD clock.
+const LCDC0: *mut u8 = 0x40082002 as *mut u8;
+
+/// LCD Boost Level Control Register, the reference voltage of the voltage boost circuit, which
+/// sets the contrast.
+const VLCD: *mut u8 = 0x40082003 as *mut u8;
+
+/// Segment data registers SEG00-SEG38.
+const SEG_BASE: u32 = 0x40082100;
+
+/// Segment LCD Source Clock Control Register, protected by PRC0 of PRCR.
+/// * b0-b2: LCDSCKSEL, the clock source, see [`ClockSource`].
+/// * b7: LCDSCKEN, enables the clock.
+const SLCDSCKCR: *mut u8 = 0x4001e050 as *mut u8;
+
+const LCDM1_LCDSEL: u8 = 1 << 3;
+const LCDM1_BLON: u8 = 1 << 4;
+const LCDM1_VLCON: u8 = 1 << 5;
+const LCDM1_SCOC: u8 = 1 << 6;
+const LCDM1_LCDON: u8 = 1 << 7;
+
+const SLCDSCKCR_LCDSCKEN: u8 = 1 << 7;
+
+/// Peripheral function of the SEG and COM pins.
+const PSEL_SLCDC: u32 = 0b01101;
+
+/// Number of segment lines.
+pub const SEGMENTS: usize = 39;
+
+/// Time for the boosted or split voltage to become stable, in microseconds.
+const VOLTAGE_STABLE_US: u32 = 5000;
+
+/// Bias of the LCD waveform, given by the LCD glass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Bias {
+    Half = 0b00,
+    Third = 0b01,
+    Quarter = 0b10,
+}
+
+/// Duty of the LCD waveform, the number of common lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Duty {
+    /// One common line, COM0.
+    Static = 0b000,
+    Half = 0b001,
+    Third = 0b010,
+    Quarter = 0b011,
+    /// Eight common lines, COM0-COM7. There is no pattern B.
+    Eighth = 0b101,
+}
+
+/// Waveform of the LCD drive signal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Waveform {
+    A = 0,
+    B = 1,
+}
+
+/// How the LCD drive voltages are generated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Drive {
+    /// External resistors between the VL pins.
+    ResistanceDivision = 0b00,
+    /// The internal voltage boost circuit, with capacitors on the VL pins. The contrast is set
+    /// with [`Slcdc::set_contrast`].
+    InternalBoost = 0b01,
+    /// The internal capacitor split circuit, with capacitors on the VL pins.
+    CapacitorSplit = 0b10,
+}
+
+/// Source of the LCD clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockSource {
+    Loco = 0b000,
+    SubOscillator = 0b001,
+    MainOscillator = 0b010,
+    Hoco = 0b100,
+}
+
+/// A pattern of the segment data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Pattern {
+    /// Bits b0-b3.
+    A,
+    /// Bits b4-b7.
+    B,
+}
+
+/// Settings of the LCD controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub bias: Bias,
+    pub duty: Duty,
+    pub waveform: Waveform,
+    pub drive: Drive,
+    pub clock_source: ClockSource,
+    /// Value of LCDC0, which divides the LCD clock down to the frame clock. See the table in the
+    /// manual for the values of each clock source.
+    pub divider: u8,
+}
+
+impl Default for Config {
+    /// Static drive from the LOCO, waveform A with external resistors, and a divider of 0x05.
+    fn default() -> Self {
+        Self {
+            bias: Bias::Half,
+            duty: Duty::Static,
+            waveform: Waveform::A,
+            drive: Drive::ResistanceDivision,
+            clock_source: ClockSource::Loco,
+            divider: 0x05,
+        }
+    }
+}
+
+impl Config {
+    /// Drive the LCD with `duty` and `bias`.
+    pub fn with_duty(self, duty: Duty, bias: Bias) -> Self {
+        Self { duty, bias, ..self }
+    }
+
+    /// Use `waveform`.
+    pub fn with_waveform(self, waveform: Waveform) -> Self {
+        Self { waveform, ..self }
+    }
+
+    /// Generate the drive voltages with `drive`.
+    pub fn with_drive(self, drive: Drive) -> Self {
+        Self { drive, ..self }
+    }
+
+    /// Clock the controller from `source`, divided by `divider`.
+    pub fn with_clock(self, source: ClockSource, divider: u8) -> Self {
+        Self {
+            clock_source: source,
+            divider,
+            ..self
+        }
+    }
+}
+
+/// The segment LCD controller.
+pub struct Slcdc {
+    config: Config,
+    _clock: MstpToken<unit::Slcdc>,
+}
+
+impl Slcdc {
+    /// Returns the LCD controller set up with `config`, with all segments off and the display
+    /// disabled, unless it was taken already. The clock source must be running.
+    pub fn take(clock: MstpToken<unit::Slcdc>, config: Config) -> Option<Self> {
+        static mut TAKEN: bool = false;
+        let taken = interrupt::free(|| unsafe {
+            if TAKEN {
+                true
+            } else {
+                TAKEN = true;
+                false
+            }
+        });
+        if taken {
+            return None;
+        }
+        unsafe {
+            clocks::write_protected(SLCDSCKCR, config.clock_source as u8);
+            clocks::write_protected(SLCDSCKCR, config.clock_source as u8 | SLCDSCKCR_LCDSCKEN);
+            LCDM1.write_volatile(0);
+            LCDM0.write_volatile(
+                config.bias as u8
+                    | (config.duty as u8) << 2
+                    | (config.waveform as u8) << 5
+                    | (config.drive as u8) << 6,
+            );
+            LCDC0.write_volatile(config.divider);
+        }
+        let mut slcdc = Self {
+            config,
+            _clock: clock,
+        };
+        slcdc.clear();
+        Some(slcdc)
+    }
+
+    /// Route pin `pin_no` of port `port_no` to its SEG or COM function.
+    ///
+    /// # Safety
+    ///
+    /// The pin must not be used by anything else, and must have a SEG or COM function.
+    pub unsafe fn connect_pin(&mut self, port_no: u32, pin_no: u32) {
+        pins::set_peripheral(port_no, pin_no, PSEL_SLCDC);
+    }
+
+    /// Start the drive voltages and turn the display on.
+    pub fn enable(&mut self) {
+        unsafe {
+            if self.config.drive != Drive::ResistanceDivision {
+                LCDM1.volatile_or(LCDM1_VLCON);
+                clocks::wait_us(clocks::Cgc::current().iclk(), VOLTAGE_STABLE_US);
+            }
+            LCDM1.volatile_or(LCDM1_SCOC);
+            LCDM1.volatile_or(LCDM1_LCDON);
+        }
+    }
+
+    /// Turn the display off and stop the drive voltages.
+    pub fn disable(&mut self) {
+        unsafe {
+            LCDM1.volatile_and(!LCDM1_LCDON);
+            LCDM1.volatile_and(!(LCDM1_SCOC | LCDM1_VLCON));
+        }
+    }
+
+    /// Set the reference voltage of the voltage boost circuit, which sets the contrast. Only
+    /// change it while the display is off.
+    pub fn set_contrast(&mut self, vlcd: u8) {
+        unsafe { VLCD.write_volatile(vlcd) };
+    }
+
+    /// Light the segments on line `segment` for the common lines set in `coms`, in pattern A.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `segment` is not below [`SEGMENTS`].
+    pub fn set_segment(&mut self, segment: usize, coms: u8) {
+        if self.config.duty == Duty::Eighth {
+            unsafe { Self::seg(segment).write_volatile(coms) };
+        } else {
+            self.set_pattern(Pattern::A, segment, coms);
+        }
+    }
+
+    /// Light the segments on line `segment` for the common lines COM0-COM3 set in `coms`, in
+    /// `pattern`. Not available with [`Duty::Eighth`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `segment` is not below [`SEGMENTS`].
+    pub fn set_pattern(&mut self, pattern: Pattern, segment: usize, coms: u8) {
+        let (mask, bits) = match pattern {
+            Pattern::A => (0xf0, coms & 0x0f),
+            Pattern::B => (0x0f, (coms & 0x0f) << 4),
+        };
+        let seg = Self::seg(segment);
+        unsafe { seg.write_volatile((seg.read_volatile() & mask) | bits) };
+    }
+
+    /// Write the segment data of consecutive lines, starting at `first`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lines don't fit below [`SEGMENTS`].
+    pub fn write(&mut self, first: usize, data: &[u8]) {
+        assert!(first + data.len() <= SEGMENTS);
+        for (i, &byte) in data.iter().enumerate() {
+            unsafe { Self::seg(first + i).write_volatile(byte) };
+        }
+    }
+
+    /// Turn all segments off.
+    pub fn clear(&mut self) {
+        self.write(0, &[0; SEGMENTS]);
+    }
+
+    /// Show `pattern` steadily.
+    pub fn show(&mut self, pattern: Pattern) {
+        unsafe {
+            LCDM1.volatile_and(!LCDM1_BLON);
+            match pattern {
+                Pattern::A => LCDM1.volatile_and(!LCDM1_LCDSEL),
+                Pattern::B => LCDM1.volatile_or(LCDM1_LCDSEL),
+            }
+        }
+    }
+
+    /// Alternate between pattern A and B at the period of the RTC periodic interrupt, or stop.
+    pub fn set_blinking(&mut self, blinking: bool) {
+        unsafe {
+            if blinking {
+                LCDM1.volatile_or(LCDM1_BLON);
+            } else {
+                LCDM1.volatile_and(!LCDM1_BLON);
+            }
+        }
+    }
+
+    /// Returns the data register of `segment`.
+    fn seg(segment: usize) -> *mut u8 {
+        assert!(segment < SEGMENTS, "the LCD has 39 segment lines");
+        (SEG_BASE + segment as u32) as *mut u8
+    }
+}
@@ -0,0 +1,255 @@
+//! SMBus-style transfers on top of the I2C driver in [`super::iic`].
+//!
+//! Many devices like battery fuel gauges and power supply controllers speak SMBus: Every transfer
+//! starts with a command code, followed by a byte, a little-endian word or a block of bytes
+//! prefixed with its length. Optionally, a Packet Error Code (PEC) is appended, a CRC-8 over all
+//! bytes of the transfer including the addresses. See [`pec`].
+//!
+//! The protocols are described in the System Management Bus Specification, section 6.5.
+//! <http://smbus.org/specs/>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::iic::{Iic, Speed};
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//! use arduino_uno_r4_wifi_rt::peripherals::smbus::Smbus;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let iic = Iic::new(pins.a5, pins.a4, mstp::token(), &clocks, Speed::Standard);
+//! let mut smbus = Smbus::new(iic).with_pec(true);
+//! let voltage_mv = smbus.read_word(0x0b, 0x09).unwrap();
+//! ```
+
+use super::iic::{self, Blocking, Iic};
+
+/// The maximum length of a block in SMBus 3.
+pub const MAX_BLOCK_LEN: usize = 255;
+
+/// Errors that can happen during an SMBus transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The transfer on the I2C bus failed.
+    Iic(iic::Error),
+    /// The Packet Error Code sent by the device doesn't match the data.
+    Pec,
+    /// The block is longer than [`MAX_BLOCK_LEN`].
+    BlockTooLong,
+}
+
+impl From<iic::Error> for Error {
+    fn from(error: iic::Error) -> Self {
+        Error::Iic(error)
+    }
+}
+
+/// Compute the Packet Error Code of `bytes`: CRC-8 with polynomial `x^8 + x^2 + x + 1`, starting
+/// from 0.
+pub fn pec(bytes: &[u8]) -> u8 {
+    update_pec(0, bytes)
+}
+
+/// Continue computing a Packet Error Code from `crc` with more `bytes`.
+fn update_pec(mut crc: u8, bytes: &[u8]) -> u8 {
+    for &byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// An SMBus host on top of the blocking I2C driver.
+pub struct Smbus {
+    iic: Iic<Blocking>,
+    pec: bool,
+}
+
+impl Smbus {
+    /// Use `iic` for SMBus transfers, without Packet Error Codes.
+    pub fn new(iic: Iic<Blocking>) -> Self {
+        Self { iic, pec: false }
+    }
+
+    /// Enable or disable Packet Error Codes. If enabled, a PEC is appended to every write and
+    /// expected after every read.
+    pub fn with_pec(mut self, pec: bool) -> Self {
+        self.pec = pec;
+        self
+    }
+
+    /// Return the I2C driver.
+    pub fn release(self) -> Iic<Blocking> {
+        self.iic
+    }
+
+    /// Send Byte: Write a single byte without command code.
+    pub fn send_byte(&mut self, address: u8, byte: u8) -> Result<(), Error> {
+        self.write(address, &[byte])
+    }
+
+    /// Receive Byte: Read a single byte without command code.
+    pub fn receive_byte(&mut self, address: u8) -> Result<u8, Error> {
+        let mut buffer = [0; 2];
+        let len = 1 + self.pec as usize;
+        self.iic.read(address, &mut buffer[..len])?;
+        self.check_pec(&[read_address(address)], &buffer[..len])?;
+        Ok(buffer[0])
+    }
+
+    /// Write Byte: Write `value` with the command code `command`.
+    pub fn write_byte(&mut self, address: u8, command: u8, value: u8) -> Result<(), Error> {
+        self.write(address, &[command, value])
+    }
+
+    /// Read Byte: Read a byte for the command code `command`.
+    pub fn read_byte(&mut self, address: u8, command: u8) -> Result<u8, Error> {
+        let mut buffer = [0; 1];
+        self.write_read(address, command, &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    /// Write Word: Write `value` with the command code `command`, low byte first.
+    pub fn write_word(&mut self, address: u8, command: u8, value: u16) -> Result<(), Error> {
+        let [low, high] = value.to_le_bytes();
+        self.write(address, &[command, low, high])
+    }
+
+    /// Read Word: Read a word for the command code `command`, low byte first.
+    pub fn read_word(&mut self, address: u8, command: u8) -> Result<u16, Error> {
+        let mut buffer = [0; 2];
+        self.write_read(address, command, &mut buffer)?;
+        Ok(u16::from_le_bytes(buffer))
+    }
+
+    /// Process Call: Write `value` with the command code `command` and read a word back.
+    pub fn process_call(&mut self, address: u8, command: u8, value: u16) -> Result<u16, Error> {
+        let [low, high] = value.to_le_bytes();
+        let mut buffer = [0; 3];
+        let len = 2 + self.pec as usize;
+        self.iic
+            .write_read(address, &[command, low, high], &mut buffer[..len])?;
+        self.check_pec(
+            &[
+                write_address(address),
+                command,
+                low,
+                high,
+                read_address(address),
+            ],
+            &buffer[..len],
+        )?;
+        Ok(u16::from_le_bytes([buffer[0], buffer[1]]))
+    }
+
+    /// Block Write: Write the length of `bytes`, followed by `bytes`, with the command code
+    /// `command`.
+    pub fn block_write(&mut self, address: u8, command: u8, bytes: &[u8]) -> Result<(), Error> {
+        if bytes.len() > MAX_BLOCK_LEN {
+            return Err(Error::BlockTooLong);
+        }
+        let mut buffer = [0; MAX_BLOCK_LEN + 2];
+        buffer[0] = command;
+        buffer[1] = bytes.len() as u8;
+        buffer[2..2 + bytes.len()].copy_from_slice(bytes);
+        self.write(address, &buffer[..2 + bytes.len()])
+    }
+
+    /// Block Read: Read a block for the command code `command` into `buffer`. Returns the number
+    /// of bytes read.
+    ///
+    /// If the device sends a longer block than fits into `buffer`, the rest isn't read and the
+    /// PEC (if enabled) can't be checked. In this case, [`Error::BlockTooLong`] is returned.
+    pub fn block_read(
+        &mut self,
+        address: u8,
+        command: u8,
+        buffer: &mut [u8],
+    ) -> Result<usize, Error> {
+        let mut pec = [0; 1];
+        let trailer_len = self.pec as usize;
+        let count = self
+            .iic
+            .read_counted(address, &[command], buffer, &mut pec[..trailer_len])?
+            as usize;
+        if count > buffer.len() {
+            return Err(Error::BlockTooLong);
+        }
+        if self.pec {
+            let crc = update_pec(
+                0,
+                &[
+                    write_address(address),
+                    command,
+                    read_address(address),
+                    count as u8,
+                ],
+            );
+            if update_pec(crc, &buffer[..count]) != pec[0] {
+                log_error!("SMBus: PEC mismatch from {}", address);
+                return Err(Error::Pec);
+            }
+        }
+        Ok(count)
+    }
+
+    /// Write `bytes`, with the PEC appended if enabled.
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        if !self.pec {
+            self.iic.write(address, bytes)?;
+            return Ok(());
+        }
+        let mut buffer = [0; MAX_BLOCK_LEN + 3];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        buffer[bytes.len()] = update_pec(pec(&[write_address(address)]), bytes);
+        self.iic.write(address, &buffer[..bytes.len() + 1])?;
+        Ok(())
+    }
+
+    /// Write `command`, then read into `buffer`, and check the PEC if enabled.
+    fn write_read(&mut self, address: u8, command: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        let mut with_pec = [0; 3];
+        let len = buffer.len() + self.pec as usize;
+        self.iic
+            .write_read(address, &[command], &mut with_pec[..len])?;
+        self.check_pec(
+            &[write_address(address), command, read_address(address)],
+            &with_pec[..len],
+        )?;
+        buffer.copy_from_slice(&with_pec[..buffer.len()]);
+        Ok(())
+    }
+
+    /// If PEC is enabled, check that the last byte of `received` is the PEC of `sent` followed by
+    /// the other bytes of `received`.
+    fn check_pec(&self, sent: &[u8], received: &[u8]) -> Result<(), Error> {
+        if !self.pec {
+            return Ok(());
+        }
+        let (data, pec) = received.split_at(received.len() - 1);
+        if update_pec(update_pec(0, sent), data) == pec[0] {
+            Ok(())
+        } else {
+            log_error!("SMBus: PEC mismatch");
+            Err(Error::Pec)
+        }
+    }
+}
+
+/// The address byte of a write transfer, as it appears on the bus.
+fn write_address(address: u8) -> u8 {
+    address << 1
+}
+
+/// The address byte of a read transfer, as it appears on the bus.
+fn read_address(address: u8) -> u8 {
+    (address << 1) | 1
+}
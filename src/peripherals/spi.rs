@@ -0,0 +1,739 @@
+//! SPI bus master on the pins D11 (MOSI), D12 (MISO) and D13 (SCK), using the RSPI0 unit of the
+//! RA4M1.
+//!
+//! Create an [`Spi`] with [`Spi::new`], which takes ownership of the three pins. By default, the
+//! driver doesn't control a chip select pin, use any output pin for that (D10 is the usual choice).
+//! Alternatively, hand D10 to [`Spi::with_chip_select`] to let the SPI unit assert it around every
+//! frame, with configurable delays (see [`ChipSelectDelays`]). This is what DACs and similar
+//! devices that latch every word on the rising edge of the chip select expect.
+//!
+//! Frames are 8, 16 or 32 bits long, chosen by the word type of the buffers ([`u8`], [`u16`] or
+//! [`u32`], see [`Word`]). The bit order is set with [`Spi::set_bit_order`].
+//!
+//! The driver starts in [`Blocking`] mode, where every frame busy-waits for the hardware.
+//! [`Spi::into_async`] links the receive interrupt of the unit to an interrupt slot (see
+//! [`super::icu`]) and returns a driver with `async` transfer functions, which sleep until each
+//! frame is done instead.
+//!
+//! With the `embedded-hal` feature, the blocking [`Spi`] implements `embedded_hal::spi::SpiBus`
+//! for all three word types, so it can be used with drivers for displays, SD cards and the like.
+//! With the `embedded-hal-async` feature, the async driver implements
+//! `embedded_hal_async::spi::SpiBus`.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "Serial
+//! Peripheral Interface (SPI)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::{get_pins, OutputPin, Pin};
+//! use arduino_uno_r4_wifi_rt::peripherals::spi::{DataMode, Spi};
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let mut cs = pins.d10.into_output();
+//! cs.set_high();
+//! let (sck, miso, mosi) = (pins.d13, pins.d12, pins.d11);
+//! let mut spi = Spi::new(sck, miso, mosi, mstp::token(), &clocks, 1_000_000, DataMode::Mode0);
+//! let mut id = [0x9f, 0, 0, 0];
+//! cs.set_low();
+//! spi.transfer_in_place(&mut id);
+//! cs.set_high();
+//! ```
+//!
+//! Example with a 12-bit DAC that takes one 16-bit word per sample:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//! use arduino_uno_r4_wifi_rt::peripherals::spi::{ChipSelectDelays, DataMode, Spi};
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let (sck, miso, mosi) = (pins.d13, pins.d12, pins.d11);
+//! let mut spi = Spi::new(sck, miso, mosi, mstp::token(), &clocks, 8_000_000, DataMode::Mode0)
+//!     .with_chip_select(pins.d10);
+//! spi.set_chip_select_delays(ChipSelectDelays { setup: 1, hold: 1, idle: 2 });
+//! spi.write(&[0x3000u16 | 2048]);
+//! ```
+
+use super::clocks::{ClockDependent, Clocks};
+use super::icu::{self, Event, Slot};
+use super::mstp::{unit, MstpToken};
+use super::pins::{Pin, PinMode, PinModePeripheral, PinModeUnknown, P102, P103, P410, P411};
+use super::registers::VolatileBoolOps;
+use crate::interrupt::{block_on, WakerCell};
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+/// The value of the words that are sent while reading.
+const READ_FILL: u8 = 0x00;
+
+/// Clock polarity and phase, numbered as usual.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DataMode {
+    /// Clock idles low, data is sampled on the rising edge.
+    Mode0,
+    /// Clock idles low, data is sampled on the falling edge.
+    Mode1,
+    /// Clock idles high, data is sampled on the falling edge.
+    Mode2,
+    /// Clock idles high, data is sampled on the rising edge.
+    Mode3,
+}
+
+impl DataMode {
+    /// Returns the CPHA (b0) and CPOL (b1) bits of SPCMD0.
+    fn command_bits(self) -> u16 {
+        match self {
+            DataMode::Mode0 => 0b00,
+            DataMode::Mode1 => 0b01,
+            DataMode::Mode2 => 0b10,
+            DataMode::Mode3 => 0b11,
+        }
+    }
+}
+
+/// Order of the bits in a frame.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Most significant bit first, which almost all devices use.
+    MsbFirst,
+    /// Least significant bit first.
+    LsbFirst,
+}
+
+/// Delays around each frame when the SPI unit drives the chip select pin, in cycles of SCK. Each
+/// delay is clamped to 1-8 cycles.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ChipSelectDelays {
+    /// From asserting the chip select to the first clock edge.
+    pub setup: u8,
+    /// From the last clock edge to negating the chip select.
+    pub hold: u8,
+    /// From negating the chip select to asserting it again for the next frame. The SPI unit adds 2
+    /// cycles of PCLKA to this.
+    pub idle: u8,
+}
+
+impl ChipSelectDelays {
+    /// The shortest delays, 1 cycle each. This is what the SPI unit does after a reset.
+    pub const MIN: Self = Self {
+        setup: 1,
+        hold: 1,
+        idle: 1,
+    };
+}
+
+impl Default for ChipSelectDelays {
+    fn default() -> Self {
+        Self::MIN
+    }
+}
+
+/// A word that can be sent in one frame. Implemented for [`u8`], [`u16`] and [`u32`], which select
+/// frames of 8, 16 and 32 bits.
+pub trait Word: Copy {
+    /// The SPB field of SPCMD0 for this frame length.
+    const DATA_LENGTH: u16;
+    /// The value of SPDCR that makes SPDR accessible with the width of this type.
+    const ACCESS_WIDTH: u8;
+
+    /// The word that is sent while reading.
+    fn fill() -> Self;
+
+    /// Write `word` to the data register.
+    ///
+    /// # Safety
+    ///
+    /// SPDCR must be set to [`Self::ACCESS_WIDTH`].
+    unsafe fn write(data_register: *mut u32, word: Self);
+
+    /// Read a word from the data register.
+    ///
+    /// # Safety
+    ///
+    /// SPDCR must be set to [`Self::ACCESS_WIDTH`].
+    unsafe fn read(data_register: *mut u32) -> Self;
+}
+
+impl Word for u8 {
+    const DATA_LENGTH: u16 = 0b0111;
+    const ACCESS_WIDTH: u8 = Spi::<Blocking>::SPDCR_SPBYT;
+
+    fn fill() -> Self {
+        READ_FILL
+    }
+
+    unsafe fn write(data_register: *mut u32, word: Self) {
+        (data_register as *mut u8).write_volatile(word);
+    }
+
+    unsafe fn read(data_register: *mut u32) -> Self {
+        (data_register as *mut u8).read_volatile()
+    }
+}
+
+impl Word for u16 {
+    const DATA_LENGTH: u16 = 0b1111;
+    const ACCESS_WIDTH: u8 = 0;
+
+    fn fill() -> Self {
+        READ_FILL as u16
+    }
+
+    unsafe fn write(data_register: *mut u32, word: Self) {
+        (data_register as *mut u16).write_volatile(word);
+    }
+
+    unsafe fn read(data_register: *mut u32) -> Self {
+        (data_register as *mut u16).read_volatile()
+    }
+}
+
+impl Word for u32 {
+    const DATA_LENGTH: u16 = 0b0011;
+    const ACCESS_WIDTH: u8 = Spi::<Blocking>::SPDCR_SPLW;
+
+    fn fill() -> Self {
+        READ_FILL as u32
+    }
+
+    unsafe fn write(data_register: *mut u32, word: Self) {
+        data_register.write_volatile(word);
+    }
+
+    unsafe fn read(data_register: *mut u32) -> Self {
+        data_register.read_volatile()
+    }
+}
+
+/// Compute the bit rate register SPBR and the divider BRDV for the fastest bit rate that doesn't
+/// exceed `hz`. The bit rate is `pclka / (2 * (SPBR + 1) * 2^BRDV)`.
+fn bit_rate_settings(pclka: u32, hz: u32) -> (u8, u8) {
+    let hz = hz.clamp(1, pclka / 2);
+    for brdv in 0..4 {
+        let divider = (2 * hz) << brdv;
+        let spbr = pclka.div_ceil(divider).saturating_sub(1);
+        if spbr <= 255 {
+            return (spbr as u8, brdv);
+        }
+    }
+    (255, 3)
+}
+
+/// Marker trait for the operating modes of the driver.
+pub trait Mode {
+    /// Does this mode wait for interrupts instead of busy-waiting?
+    const INTERRUPTS: bool;
+}
+
+/// The driver busy-waits for the hardware.
+pub struct Blocking;
+impl Mode for Blocking {
+    const INTERRUPTS: bool = false;
+}
+
+/// The driver waits for interrupts and exposes `async` transfer functions.
+pub struct Async {
+    /// Slot for the RXI event.
+    slot: Slot,
+}
+impl Mode for Async {
+    const INTERRUPTS: bool = true;
+}
+
+/// Woken by the interrupt handler of the RSPI0 unit.
+static WAKER: WakerCell = WakerCell::new();
+
+/// Interrupt handler for the RXI event: Disable further interrupts and wake the waiting future,
+/// which reads the received word itself.
+fn on_interrupt() {
+    unsafe {
+        Spi::<Blocking>::SPCR.volatile_and(!Spi::<Blocking>::SPCR_SPRIE);
+    }
+    WAKER.wake();
+}
+
+/// The RSPI0 unit, a master on the SPI bus on pins D11, D12 and D13.
+pub struct Spi<M> {
+    _sck: P102<PinModePeripheral>,
+    _miso: P410<PinModePeripheral>,
+    _mosi: P411<PinModePeripheral>,
+    _cs: Option<P103<PinModePeripheral>>,
+    _clock: MstpToken<unit::Spi0>,
+    /// Frequency of PCLKA, which drives the unit.
+    pclka: u32,
+    frequency: u32,
+    /// The bit rate passed to [`Spi::set_frequency`], kept when the clocks change.
+    requested: u32,
+    /// The SPB field of SPCMD0 as currently configured.
+    data_length: u16,
+    mode: M,
+}
+
+impl<M> Spi<M> {
+    const BASE_ADDRESS: u32 = 0x40072000;
+
+    /// SPI Control Register.
+    /// * b0: SPMS, 1 for clock synchronous operation without slave select. If 0, the unit drives
+    ///   the slave select pins, active low as set in SSLP after a reset.
+    /// * b3: MSTR, master mode.
+    /// * b6: SPE, enable the unit.
+    /// * b7: SPRIE, raise the RXI interrupt when the receive buffer is full.
+    const SPCR: *mut u8 = Self::BASE_ADDRESS as *mut u8;
+
+    /// SPI Status Register.
+    /// * b5: SPTEF, the transmit buffer is empty.
+    /// * b7: SPRF, the receive buffer is full.
+    const SPSR: *mut u8 = (Self::BASE_ADDRESS + 0x03) as *mut u8;
+
+    /// SPI Data Register. Accessed as a byte, half word or word, as set in SPDCR.
+    const SPDR: *mut u32 = (Self::BASE_ADDRESS + 0x04) as *mut u32;
+
+    /// SPI Bit Rate Register.
+    const SPBR: *mut u8 = (Self::BASE_ADDRESS + 0x0a) as *mut u8;
+
+    /// SPI Data Control Register. Can only be written while SPCR.SPE is 0.
+    /// * b5: SPLW, access SPDR as a word instead of a half word.
+    /// * b6: SPBYT, access SPDR as a byte.
+    const SPDCR: *mut u8 = (Self::BASE_ADDRESS + 0x0b) as *mut u8;
+
+    /// SPI Clock Delay Register. The delay from chip select to clock is b0-b2 + 1 cycles of SCK.
+    const SPCKD: *mut u8 = (Self::BASE_ADDRESS + 0x0c) as *mut u8;
+
+    /// SPI Slave Select Negation Delay Register. The delay from the last clock to negating the
+    /// chip select is b0-b2 + 1 cycles of SCK.
+    const SSLND: *mut u8 = (Self::BASE_ADDRESS + 0x0d) as *mut u8;
+
+    /// SPI Next-Access Delay Register. The delay between frames is b0-b2 + 1 cycles of SCK plus 2
+    /// cycles of PCLKA.
+    const SPND: *mut u8 = (Self::BASE_ADDRESS + 0x0e) as *mut u8;
+
+    /// SPI Command Register 0.
+    /// * b0: CPHA, clock phase.
+    /// * b1: CPOL, clock polarity.
+    /// * b2-b3: BRDV, additional bit rate divider `2^BRDV`.
+    /// * b4-b6: SSLA, which slave select to assert. 0 for SSLA0 on D10.
+    /// * b8-b11: SPB, data length. 0b0111 for 8, 0b1111 for 16 and 0b0011 for 32 bits.
+    /// * b12: LSBF, send the least significant bit first.
+    /// * b13: SPNDEN, use the delay between frames from SPND instead of 1 cycle.
+    /// * b14: SLNDEN, use the chip select negation delay from SSLND instead of 1 cycle.
+    /// * b15: SCKDEN, use the clock delay from SPCKD instead of 1 cycle.
+    const SPCMD0: *mut u16 = (Self::BASE_ADDRESS + 0x10) as *mut u16;
+
+    const SPCR_SPMS: u8 = 1 << 0;
+    const SPCR_MSTR: u8 = 1 << 3;
+    const SPCR_SPE: u8 = 1 << 6;
+    const SPCR_SPRIE: u8 = 1 << 7;
+
+    const SPSR_SPTEF: u8 = 1 << 5;
+    const SPSR_SPRF: u8 = 1 << 7;
+
+    const SPDCR_SPLW: u8 = 1 << 5;
+    const SPDCR_SPBYT: u8 = 1 << 6;
+
+    const SPCMD0_BRDV: u16 = 0b11 << 2;
+    const SPCMD0_SPB: u16 = 0b1111 << 8;
+    const SPCMD0_LSBF: u16 = 1 << 12;
+    const SPCMD0_DELAYS: u16 = 0b111 << 13;
+}
+
+impl Spi<Blocking> {
+    /// Set up the RSPI0 unit as bus master on pins D13 (SCK), D12 (MISO) and D11 (MOSI), with a
+    /// bit rate of at most `frequency` Hz, derived from PCLKA of `clocks`.
+    pub fn new<M1: PinMode, M2: PinMode, M3: PinMode>(
+        sck: P102<M1>,
+        miso: P410<M2>,
+        mosi: P411<M3>,
+        clock: MstpToken<unit::Spi0>,
+        clocks: &Clocks,
+        frequency: u32,
+        mode: DataMode,
+    ) -> Self {
+        // Peripheral function 6 is SPI.
+        let sck = sck.into_peripheral(0b00110, 0);
+        let miso = miso.into_peripheral(0b00110, 0);
+        let mosi = mosi.into_peripheral(0b00110, 0);
+        unsafe {
+            Self::SPCR.write_volatile(0);
+            Self::SPDCR.write_volatile(u8::ACCESS_WIDTH);
+            Self::SPCMD0.write_volatile(mode.command_bits() | (u8::DATA_LENGTH << 8));
+            Self::SPCR.write_volatile(Self::SPCR_SPMS | Self::SPCR_MSTR);
+            Self::SPCR.volatile_or(Self::SPCR_SPE);
+        }
+        let mut spi = Self {
+            _sck: sck,
+            _miso: miso,
+            _mosi: mosi,
+            _cs: None,
+            _clock: clock,
+            pclka: clocks.pclka(),
+            frequency: 0,
+            requested: 0,
+            data_length: u8::DATA_LENGTH,
+            mode: Blocking,
+        };
+        spi.set_frequency(frequency);
+        spi
+    }
+
+    /// Link the RXI interrupt of the RSPI0 unit to an interrupt slot and return the async driver.
+    ///
+    /// Returns the blocking driver back if there is no free interrupt slot.
+    pub fn into_async(self) -> Result<Spi<Async>, Self> {
+        match icu::attach(Event::Spi0Rxi, on_interrupt) {
+            Some(slot) => Ok(self.with_mode(Async { slot })),
+            None => Err(self),
+        }
+    }
+
+    /// Stop the unit and return the pins: SCK, MISO, MOSI and the chip select, if the unit drives
+    /// one.
+    pub fn release(
+        self,
+    ) -> (
+        P102<PinModeUnknown>,
+        P410<PinModeUnknown>,
+        P411<PinModeUnknown>,
+        Option<P103<PinModeUnknown>>,
+    ) {
+        unsafe {
+            Self::SPCR.write_volatile(0);
+        }
+        (
+            self._sck.into_unknown(),
+            self._miso.into_unknown(),
+            self._mosi.into_unknown(),
+            self._cs.map(Pin::into_unknown),
+        )
+    }
+
+    /// Send `byte` and return the byte received at the same time.
+    pub fn transfer_byte(&mut self, byte: u8) -> u8 {
+        self.transfer_word(byte)
+    }
+
+    /// Send `word` in one frame of its length and return the word received at the same time.
+    pub fn transfer_word<W: Word>(&mut self, word: W) -> W {
+        self.set_word::<W>();
+        block_on(self.exchange(word))
+    }
+
+    /// Send `words`, discarding the received words.
+    pub fn write<W: Word>(&mut self, words: &[W]) {
+        block_on(self.transfer_words(&mut [], words));
+    }
+
+    /// Receive words into `buffer`, sending zeros.
+    pub fn read<W: Word>(&mut self, buffer: &mut [W]) {
+        block_on(self.transfer_words(buffer, &[]));
+    }
+
+    /// Send `words` and receive into `buffer` at the same time. If one is longer than the other,
+    /// zeros are sent or the extra received words are discarded.
+    pub fn transfer<W: Word>(&mut self, buffer: &mut [W], words: &[W]) {
+        block_on(self.transfer_words(buffer, words));
+    }
+
+    /// Send the words in `buffer` and replace them with the received words.
+    pub fn transfer_in_place<W: Word>(&mut self, buffer: &mut [W]) {
+        block_on(self.transfer_words_in_place(buffer));
+    }
+}
+
+impl Spi<Async> {
+    /// Unlink the interrupt and return the blocking driver.
+    pub fn into_blocking(self) -> Spi<Blocking> {
+        icu::detach(self.mode.slot);
+        self.with_mode(Blocking)
+    }
+
+    /// Send `word` in one frame of its length and return the word received at the same time.
+    pub async fn transfer_word<W: Word>(&mut self, word: W) -> W {
+        self.set_word::<W>();
+        self.exchange(word).await
+    }
+
+    /// Send `words`, discarding the received words.
+    pub async fn write<W: Word>(&mut self, words: &[W]) {
+        self.transfer_words(&mut [], words).await
+    }
+
+    /// Receive words into `buffer`, sending zeros.
+    pub async fn read<W: Word>(&mut self, buffer: &mut [W]) {
+        self.transfer_words(buffer, &[]).await
+    }
+
+    /// Send `words` and receive into `buffer` at the same time. If one is longer than the other,
+    /// zeros are sent or the extra received words are discarded.
+    pub async fn transfer<W: Word>(&mut self, buffer: &mut [W], words: &[W]) {
+        self.transfer_words(buffer, words).await
+    }
+
+    /// Send the words in `buffer` and replace them with the received words.
+    pub async fn transfer_in_place<W: Word>(&mut self, buffer: &mut [W]) {
+        self.transfer_words_in_place(buffer).await
+    }
+}
+
+impl<M: Mode> Spi<M> {
+    /// Let the SPI unit drive D10 as an active low chip select. It is asserted for every frame and
+    /// negated in between, with the delays set by [`Spi::set_chip_select_delays`].
+    pub fn with_chip_select<CsMode: PinMode>(mut self, cs: P103<CsMode>) -> Self {
+        // Peripheral function 6 is SSLA0.
+        self._cs = Some(cs.into_peripheral(0b00110, 0));
+        Self::reconfigure(|| unsafe {
+            Self::SPCR.volatile_and(!Self::SPCR_SPMS);
+        });
+        self
+    }
+
+    /// Set the delays around each frame. Only has an effect if the chip select is driven by the
+    /// SPI unit, see [`Spi::with_chip_select`].
+    pub fn set_chip_select_delays(&mut self, delays: ChipSelectDelays) {
+        let cycles = |delay: u8| delay.clamp(1, 8) - 1;
+        Self::reconfigure(|| unsafe {
+            Self::SPCKD.write_volatile(cycles(delays.setup));
+            Self::SSLND.write_volatile(cycles(delays.hold));
+            Self::SPND.write_volatile(cycles(delays.idle));
+            Self::SPCMD0.volatile_or(Self::SPCMD0_DELAYS);
+        });
+    }
+
+    /// Set the order of the bits in each frame.
+    pub fn set_bit_order(&mut self, order: BitOrder) {
+        Self::reconfigure(|| unsafe {
+            match order {
+                BitOrder::MsbFirst => Self::SPCMD0.volatile_and(!Self::SPCMD0_LSBF),
+                BitOrder::LsbFirst => Self::SPCMD0.volatile_or(Self::SPCMD0_LSBF),
+            }
+        });
+    }
+
+    /// Set the clock polarity and phase.
+    pub fn set_data_mode(&mut self, mode: DataMode) {
+        Self::reconfigure(|| unsafe {
+            let command = Self::SPCMD0.read_volatile() & !0b11;
+            Self::SPCMD0.write_volatile(command | mode.command_bits());
+        });
+    }
+
+    /// Disable the SPI unit, run `f` to change its settings and enable it again.
+    fn reconfigure(f: impl FnOnce()) {
+        unsafe {
+            Self::SPCR.volatile_and(!Self::SPCR_SPE);
+        }
+        f();
+        unsafe {
+            Self::SPCR.volatile_or(Self::SPCR_SPE);
+        }
+    }
+
+    /// Switch to frames of the length of `W`, unless they already are.
+    fn set_word<W: Word>(&mut self) {
+        if self.data_length == W::DATA_LENGTH {
+            return;
+        }
+        Self::reconfigure(|| unsafe {
+            Self::SPDCR.write_volatile(W::ACCESS_WIDTH);
+            let command = Self::SPCMD0.read_volatile() & !Self::SPCMD0_SPB;
+            Self::SPCMD0.write_volatile(command | (W::DATA_LENGTH << 8));
+        });
+        self.data_length = W::DATA_LENGTH;
+    }
+
+    /// Returns the actual bit rate in Hz, which may be lower than requested.
+    #[inline]
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    /// Returns the fastest bit rate in Hz that the unit supports, half of PCLKA.
+    #[inline]
+    pub fn max_frequency(&self) -> u32 {
+        self.pclka / 2
+    }
+
+    /// Change the bit rate to at most `frequency` Hz, e.g. to initialize an SD card at 400 kHz and
+    /// then switch to full speed. Returns the actual bit rate, see [`Spi::frequency`].
+    pub fn set_frequency(&mut self, frequency: u32) -> u32 {
+        let (spbr, brdv) = bit_rate_settings(self.pclka, frequency);
+        Self::reconfigure(|| unsafe {
+            Self::SPBR.write_volatile(spbr);
+            let command = Self::SPCMD0.read_volatile() & !Self::SPCMD0_BRDV;
+            Self::SPCMD0.write_volatile(command | ((brdv as u16) << 2));
+        });
+        self.frequency = self.pclka / ((2 * (spbr as u32 + 1)) << brdv);
+        self.requested = frequency;
+        self.frequency
+    }
+
+    /// Move the pins and settings into a driver with another mode.
+    fn with_mode<N: Mode>(self, mode: N) -> Spi<N> {
+        Spi {
+            _sck: self._sck,
+            _miso: self._miso,
+            _mosi: self._mosi,
+            _cs: self._cs,
+            _clock: self._clock,
+            pclka: self.pclka,
+            frequency: self.frequency,
+            requested: self.requested,
+            data_length: self.data_length,
+            mode,
+        }
+    }
+
+    /// Send `words` and receive into `buffer` at the same time, see [`Spi::transfer`].
+    async fn transfer_words<W: Word>(&mut self, buffer: &mut [W], words: &[W]) {
+        self.set_word::<W>();
+        for i in 0..buffer.len().max(words.len()) {
+            let received = self
+                .exchange(words.get(i).copied().unwrap_or(W::fill()))
+                .await;
+            if let Some(word) = buffer.get_mut(i) {
+                *word = received;
+            }
+        }
+    }
+
+    async fn transfer_words_in_place<W: Word>(&mut self, buffer: &mut [W]) {
+        self.set_word::<W>();
+        for word in buffer {
+            *word = self.exchange(*word).await;
+        }
+    }
+
+    /// Send `word` and wait for the received word. The frame length must already be set.
+    async fn exchange<W: Word>(&mut self, word: W) -> W {
+        unsafe {
+            // The previous word has been received, so the transmit buffer is empty already unless
+            // the unit was just enabled.
+            while Self::SPSR.read_volatile() & Self::SPSR_SPTEF == 0 {}
+            W::write(Self::SPDR, word);
+        }
+        poll_fn(|cx| {
+            if Self::received() {
+                return Poll::Ready(());
+            }
+            if M::INTERRUPTS {
+                WAKER.register(cx.waker());
+                unsafe {
+                    Self::SPCR.volatile_or(Self::SPCR_SPRIE);
+                }
+                // The word might have arrived before the interrupt was enabled.
+                if Self::received() {
+                    unsafe {
+                        Self::SPCR.volatile_and(!Self::SPCR_SPRIE);
+                    }
+                    return Poll::Ready(());
+                }
+            }
+            Poll::Pending
+        })
+        .await;
+        unsafe { W::read(Self::SPDR) }
+    }
+
+    fn received() -> bool {
+        unsafe { Self::SPSR.read_volatile() & Self::SPSR_SPRF != 0 }
+    }
+}
+
+impl<M: Mode> ClockDependent for Spi<M> {
+    fn set_clocks(&mut self, clocks: &Clocks) {
+        self.pclka = clocks.pclka();
+        self.set_frequency(self.requested);
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+mod embedded_hal_impl {
+    use super::{Blocking, DataMode, Spi, Word};
+    use core::convert::Infallible;
+    use embedded_hal::spi::{self, Phase, Polarity};
+
+    impl From<spi::Mode> for DataMode {
+        fn from(mode: spi::Mode) -> Self {
+            match (mode.polarity, mode.phase) {
+                (Polarity::IdleLow, Phase::CaptureOnFirstTransition) => DataMode::Mode0,
+                (Polarity::IdleLow, Phase::CaptureOnSecondTransition) => DataMode::Mode1,
+                (Polarity::IdleHigh, Phase::CaptureOnFirstTransition) => DataMode::Mode2,
+                (Polarity::IdleHigh, Phase::CaptureOnSecondTransition) => DataMode::Mode3,
+            }
+        }
+    }
+
+    impl spi::ErrorType for Spi<Blocking> {
+        type Error = Infallible;
+    }
+
+    impl<W: Word + 'static> spi::SpiBus<W> for Spi<Blocking> {
+        fn read(&mut self, words: &mut [W]) -> Result<(), Infallible> {
+            Spi::<Blocking>::read(self, words);
+            Ok(())
+        }
+
+        fn write(&mut self, words: &[W]) -> Result<(), Infallible> {
+            Spi::<Blocking>::write(self, words);
+            Ok(())
+        }
+
+        fn transfer(&mut self, read: &mut [W], write: &[W]) -> Result<(), Infallible> {
+            Spi::<Blocking>::transfer(self, read, write);
+            Ok(())
+        }
+
+        fn transfer_in_place(&mut self, words: &mut [W]) -> Result<(), Infallible> {
+            Spi::<Blocking>::transfer_in_place(self, words);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Infallible> {
+            // Every transfer waits for the received word, so nothing is in flight.
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal-async")]
+mod embedded_hal_async_impl {
+    use super::{Async, Spi, Word};
+    use core::convert::Infallible;
+    use embedded_hal_async::spi;
+
+    impl spi::ErrorType for Spi<Async> {
+        type Error = Infallible;
+    }
+
+    impl<W: Word + 'static> spi::SpiBus<W> for Spi<Async> {
+        async fn read(&mut self, words: &mut [W]) -> Result<(), Infallible> {
+            Spi::<Async>::read(self, words).await;
+            Ok(())
+        }
+
+        async fn write(&mut self, words: &[W]) -> Result<(), Infallible> {
+            Spi::<Async>::write(self, words).await;
+            Ok(())
+        }
+
+        async fn transfer(&mut self, read: &mut [W], write: &[W]) -> Result<(), Infallible> {
+            Spi::<Async>::transfer(self, read, write).await;
+            Ok(())
+        }
+
+        async fn transfer_in_place(&mut self, words: &mut [W]) -> Result<(), Infallible> {
+            Spi::<Async>::transfer_in_place(self, words).await;
+            Ok(())
+        }
+
+        async fn flush(&mut self) -> Result<(), Infallible> {
+            // Every transfer waits for the received word, so nothing is in flight.
+            Ok(())
+        }
+    }
+}
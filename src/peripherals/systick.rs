@@ -1,8 +1,13 @@
 //! A module exposing the SysTick timer functions of the Arm CPU.
 //!
+//! [`Delay`] uses the timer for busy-waiting delays. With the `embedded-hal` feature, it implements
+//! `embedded_hal::delay::DelayNs`.
+//!
 //! See Armv7-M Architecture Reference Manual, p. 620-623.
 
+use super::clocks::{ClockDependent, Clocks};
 use super::registers::VolatileBoolOps;
+use crate::interrupt;
 
 /// System timer of the ARM CPU.
 ///
@@ -17,6 +22,7 @@ impl SysTick {
     /// The most important bits are:
     /// * b0: Set to 0/1 to enable/disable the timer.
     /// * b1: Enable SysTick interrupt (never got that to work...)
+    /// * b2: Count the processor clock (ICLK) instead of the external reference clock.
     /// * b16: Has `cvr` reached 0 since we last read this register?
     ///        Note: This bit is set to 0 every time we read this register.
     const CSR: *mut u32 = 0xe000e010 as *mut u32;
@@ -41,14 +47,15 @@ impl SysTick {
 
     /// Create a SysTick instance if no instance has been created yet.
     pub fn instance() -> Option<Self> {
-        unsafe {
-            static mut SYSTICK_CREATED: bool = false;
-            if !SYSTICK_CREATED {
-                Some(SysTick::new())
-            } else {
+        static mut SYSTICK_CREATED: bool = false;
+        interrupt::free(|| unsafe {
+            if SYSTICK_CREATED {
                 None
+            } else {
+                SYSTICK_CREATED = true;
+                Some(SysTick::new())
             }
-        }
+        })
     }
 
     /// Returns the number of ticks per 10ms.
@@ -123,3 +130,87 @@ impl SysTick {
         unsafe { Self::RVR.read_volatile() & 0x00ffffff }
     }
 }
+
+/// Busy-waiting delays, timed with the SysTick timer.
+///
+/// Takes over the timer and lets it run freely with the largest reset value, counting how many
+/// ticks have passed.
+pub struct Delay {
+    systick: SysTick,
+    ticks_per_10ms: u32,
+}
+
+impl Delay {
+    /// The largest reset value, the timer only has 24 bits.
+    const MAX_RESET_VALUE: u32 = 0x00ffffff;
+
+    /// Use `systick` for delays. The timer is set to count ICLK of `clocks`, enabled, and its
+    /// reset value is changed.
+    pub fn new(mut systick: SysTick, clocks: &Clocks) -> Self {
+        unsafe {
+            SysTick::CSR.volatile_or(1 << 2);
+        }
+        systick.set_reset_value(Self::MAX_RESET_VALUE);
+        systick.reset();
+        systick.enable();
+        let ticks_per_10ms = clocks.iclk() / 100;
+        Self {
+            systick,
+            ticks_per_10ms,
+        }
+    }
+
+    /// Return the timer.
+    pub fn release(self) -> SysTick {
+        self.systick
+    }
+
+    /// Wait until `ticks` timer ticks have passed.
+    pub fn delay_ticks(&mut self, ticks: u64) {
+        let mut remaining = ticks;
+        let mut last = self.systick.get_current_value();
+        while remaining > 0 {
+            let now = self.systick.get_current_value();
+            // The timer counts down and wraps from 0 to the reset value.
+            let elapsed = last.wrapping_sub(now) & Self::MAX_RESET_VALUE;
+            remaining = remaining.saturating_sub(elapsed as u64);
+            last = now;
+        }
+    }
+
+    /// Wait for at least `us` microseconds.
+    pub fn delay_us(&mut self, us: u32) {
+        self.delay_ticks((us as u64 * self.ticks_per_10ms as u64).div_ceil(10_000));
+    }
+
+    /// Wait for at least `ms` milliseconds.
+    pub fn delay_ms(&mut self, ms: u32) {
+        self.delay_ticks((ms as u64 * self.ticks_per_10ms as u64).div_ceil(10));
+    }
+}
+
+impl ClockDependent for Delay {
+    fn set_clocks(&mut self, clocks: &Clocks) {
+        self.ticks_per_10ms = clocks.iclk() / 100;
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+mod embedded_hal_impl {
+    use super::Delay;
+    use embedded_hal::delay::DelayNs;
+
+    impl DelayNs for Delay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.delay_ticks((ns as u64 * self.ticks_per_10ms as u64).div_ceil(10_000_000));
+        }
+
+        fn delay_us(&mut self, us: u32) {
+            Delay::delay_us(self, us);
+        }
+
+        fn delay_ms(&mut self, ms: u32) {
+            Delay::delay_ms(self, ms);
+        }
+    }
+}
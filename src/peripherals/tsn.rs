@@ -0,0 +1,74 @@
+//! Temperature sensor (TSN): Measure the temperature of the die.
+//!
+//! The sensor outputs a voltage that falls by about 3.65 mV per °C. The ADC converts it (see
+//! [`super::adc`]), and the factory calibration value, the conversion at 127 °C, turns the result
+//! into a temperature. The die is a few degrees warmer than the air around the board, more so when
+//! the CPU is busy.
+//!
+//! The sensor runs while its token (see [`super::mstp`]) exists. [`read_celsius`] borrows it and
+//! the one of the ADC, waits until the sensor is stable and converts its output once. The supply
+//! voltage, the reference of the ADC, is measured with [`adc::read_vcc_millivolts`] for each
+//! reading.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter
+//! "Temperature Sensor (TSN)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::{mstp, tsn};
+//!
+//! let (sensor_clock, adc_clock) = (mstp::token(), mstp::token());
+//! let celsius = tsn::read_celsius(&sensor_clock, &adc_clock);
+//! ```
+
+use super::adc::{self, Internal, INTERNAL_RESOLUTION};
+use super::clocks::{self, Cgc};
+use super::mstp::{unit, MstpToken};
+
+/// Temperature Sensor Calibration Data Register.
+/// * b0-b11: The 12-bit conversion of the sensor output at 127 °C, with AVCC0 at 3.3 V.
+const TSCDR: *const u32 = 0x407fb17c as *const u32;
+
+/// Temperature of the calibration, in m°C.
+const CALIBRATION_MILLICELSIUS: i32 = 127_000;
+
+/// Voltage of AVCC0 during the calibration, in mV.
+const CALIBRATION_VCC_MV: i32 = 3300;
+
+/// Change of the sensor output, in µV/°C.
+const SLOPE_UV: i32 = -3650;
+
+/// Time for the sensor to become stable after it is started, in microseconds.
+const START_US: u32 = 30;
+
+/// Minimum sampling time of the sensor output, in nanoseconds.
+const SAMPLING_NS: u32 = 5000;
+
+/// Returns the factory calibration value.
+pub fn calibration() -> u16 {
+    unsafe { (TSCDR.read_volatile() & 0x0fff) as u16 }
+}
+
+/// Returns the temperature of the die in m°C.
+pub fn read_millicelsius(
+    _sensor_clock: &MstpToken<unit::Tsn>,
+    adc_clock: &MstpToken<unit::Adc140>,
+) -> i32 {
+    clocks::wait_us(Cgc::current().iclk(), START_US);
+    let value = adc::convert_internal(adc_clock, Internal::TemperatureSensor, SAMPLING_NS);
+    // Multiplied before dividing, the products need 64 bits.
+    let resolution = INTERNAL_RESOLUTION as i64;
+    let calibration_uv = calibration() as i64 * CALIBRATION_VCC_MV as i64 * 1000 / resolution;
+    let vcc_mv = adc::read_vcc_millivolts(adc_clock) as i64;
+    let sensor_uv = value as i64 * vcc_mv * 1000 / resolution;
+    CALIBRATION_MILLICELSIUS + ((sensor_uv - calibration_uv) * 1000 / SLOPE_UV as i64) as i32
+}
+
+/// Returns the temperature of the die in °C.
+pub fn read_celsius(
+    sensor_clock: &MstpToken<unit::Tsn>,
+    adc_clock: &MstpToken<unit::Adc140>,
+) -> f32 {
+    read_millicelsius(sensor_clock, adc_clock) as f32 / 1000.0
+}
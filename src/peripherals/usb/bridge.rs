@@ -0,0 +1,169 @@
+//! Bridge between the USB serial port and the ESP32-S3, for updating its AT firmware with esptool
+//! while this runtime runs on the RA4M1.
+//!
+//! [`Bridge::poll`] copies the bytes from the host to the link of [`Esp32`] and back, and follows
+//! the baud rate that the host sets, so the bootloader of the ESP32-S3 and the flasher stub of
+//! esptool can switch to a faster rate.
+//!
+//! esptool restarts the ESP32-S3 into its download mode with the DTR and RTS lines, which
+//! usually drive its EN and GPIO0 pins through two transistors. These pins aren't connected to
+//! the RA4M1 on the UNO R4 WiFi. If they are wired to two pins, [`Bridge::with_reset_pins`] drives
+//! them like the transistors do. Otherwise, start the ESP32-S3 in download mode by hand, with
+//! GPIO0 connected to GND on the ESP header while the board restarts, and run esptool with
+//! `--before no_reset --after no_reset`.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::esp32::Esp32;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::bridge::Bridge;
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::serial::Serial;
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::UsbBus;
+//! use usb_device::bus::UsbBusAllocator;
+//!
+//! static mut BUS: Option<UsbBusAllocator<UsbBus>> = None;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let esp32 = Esp32::new(pins.p109, pins.p110, mstp::token(), &clocks).unwrap();
+//! let bus = UsbBus::take(mstp::token()).unwrap();
+//! let bus = unsafe { (*core::ptr::addr_of_mut!(BUS)).insert(bus) };
+//! // esptool --port /dev/ttyACM0 --before no_reset --after no_reset write_flash ...
+//! Bridge::new(Serial::new(bus), esp32).run();
+//! ```
+
+use super::serial::Serial;
+use crate::peripherals::esp32::Esp32;
+use crate::peripherals::pins::OutputPin;
+
+/// Size of the buffer for bytes from the ESP32-S3 that the host hasn't taken yet.
+const BUFFER_SIZE: usize = 64;
+
+/// The baud rate at which closing the port restarts the RA4M1 into its bootloader, which isn't
+/// passed on.
+const TOUCH_BAUD_RATE: u32 = 1200;
+
+/// Drives the EN and GPIO0 pins of the ESP32-S3 from the DTR and RTS lines.
+pub trait ResetControl {
+    /// Set the lines.
+    fn set_lines(&mut self, dtr: bool, rts: bool);
+}
+
+/// No reset pins.
+pub struct NoReset;
+
+impl ResetControl for NoReset {
+    fn set_lines(&mut self, _dtr: bool, _rts: bool) {}
+}
+
+/// Output pins wired to the EN and GPIO0 pins of the ESP32-S3.
+pub struct ResetPins<EN: OutputPin, BOOT: OutputPin> {
+    pub enable: EN,
+    pub boot: BOOT,
+}
+
+impl<EN: OutputPin, BOOT: OutputPin> ResetControl for ResetPins<EN, BOOT> {
+    /// Like the auto-reset circuit of the ESP32 development boards: EN is low while only RTS is
+    /// set, GPIO0 while only DTR is set.
+    fn set_lines(&mut self, dtr: bool, rts: bool) {
+        if rts && !dtr {
+            self.enable.set_low();
+        } else {
+            self.enable.set_high();
+        }
+        if dtr && !rts {
+            self.boot.set_low();
+        } else {
+            self.boot.set_high();
+        }
+    }
+}
+
+/// The bridge.
+pub struct Bridge<R: ResetControl = NoReset> {
+    serial: Serial,
+    esp32: Esp32,
+    reset: R,
+    /// The DTR and RTS lines passed on last.
+    lines: Option<(bool, bool)>,
+    /// Bytes from the ESP32-S3 that the host hasn't taken yet.
+    buffer: [u8; BUFFER_SIZE],
+    start: usize,
+    end: usize,
+}
+
+impl Bridge<NoReset> {
+    /// Bridge `serial` and `esp32`, without reset pins.
+    pub fn new(serial: Serial, esp32: Esp32) -> Self {
+        Self::with_reset(serial, esp32, NoReset)
+    }
+}
+
+impl<EN: OutputPin, BOOT: OutputPin> Bridge<ResetPins<EN, BOOT>> {
+    /// Bridge `serial` and `esp32`, and drive the EN and GPIO0 pins of the ESP32-S3 with `enable`
+    /// and `boot`.
+    pub fn with_reset_pins(serial: Serial, esp32: Esp32, enable: EN, boot: BOOT) -> Self {
+        Self::with_reset(serial, esp32, ResetPins { enable, boot })
+    }
+}
+
+impl<R: ResetControl> Bridge<R> {
+    /// Bridge `serial` and `esp32`, with `reset` for the DTR and RTS lines.
+    pub fn with_reset(serial: Serial, esp32: Esp32, mut reset: R) -> Self {
+        // Let the ESP32-S3 run until the host says otherwise.
+        reset.set_lines(false, false);
+        Self {
+            serial,
+            esp32,
+            reset,
+            lines: None,
+            buffer: [0; BUFFER_SIZE],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Handle the USB events and pass on the data, the lines and the baud rate. Must be called at
+    /// least every few milliseconds.
+    pub fn poll(&mut self) {
+        self.serial.poll();
+
+        let lines = (self.serial.dtr(), self.serial.rts());
+        if self.lines != Some(lines) {
+            self.reset.set_lines(lines.0, lines.1);
+            self.lines = Some(lines);
+        }
+
+        let baud_rate = self.serial.baud_rate();
+        if baud_rate != self.esp32.baud_rate() && baud_rate != TOUCH_BAUD_RATE {
+            // Rates that the unit can't generate are left as they are, the host will notice.
+            let _ = self.esp32.set_link_baud_rate(baud_rate);
+        }
+
+        let mut data = [0; BUFFER_SIZE];
+        let count = self.serial.read(&mut data);
+        self.esp32.write(&data[..count]);
+
+        if self.start == self.end {
+            self.start = 0;
+            self.end = self.esp32.read(&mut self.buffer);
+        }
+        if self.start < self.end {
+            self.start += self.serial.write(&self.buffer[self.start..self.end]);
+        }
+    }
+
+    /// Run the bridge until the board is reset, e.g. by the Arduino IDE for an upload.
+    pub fn run(mut self) -> ! {
+        loop {
+            self.poll();
+        }
+    }
+
+    /// Stop bridging and return the serial port, the link and the reset control.
+    pub fn release(self) -> (Serial, Esp32, R) {
+        (self.serial, self.esp32, self.reset)
+    }
+}
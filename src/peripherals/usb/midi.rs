@@ -0,0 +1,315 @@
+//! USB MIDI device class, for MIDI controllers and instruments that plug into a computer.
+//!
+//! [`MidiClass`] is a `usb_device` class with one MIDI input and one MIDI output, as described in
+//! the "Universal Serial Bus Device Class Definition for MIDI Devices", release 1.0. The host
+//! lists the board as a MIDI port, so it works with DAWs and synthesizers without a driver.
+//!
+//! Messages are sent with [`MidiClass::send`] or the helpers [`MidiClass::note_on`],
+//! [`MidiClass::note_off`] and [`MidiClass::control_change`], and received with
+//! [`MidiClass::receive`]. System exclusive messages aren't supported.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::midi::{Message, MidiClass};
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::{composite_device, UsbBus};
+//!
+//! let bus = UsbBus::take(mstp::token()).unwrap();
+//! let mut midi = MidiClass::new(&bus);
+//! let mut device = composite_device(&bus).build();
+//! loop {
+//!     if device.poll(&mut [&mut midi]) {
+//!         while let Some(message) = midi.receive() {
+//!             // Play back every note one octave higher.
+//!             if let Message::NoteOn { channel, note, velocity } = message {
+//!                 midi.note_on(channel, note + 12, velocity).ok();
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+
+use usb_device::class_prelude::*;
+use usb_device::Result;
+
+/// Audio device class.
+const USB_CLASS_AUDIO: u8 = 0x01;
+const SUBCLASS_AUDIO_CONTROL: u8 = 0x01;
+const SUBCLASS_MIDI_STREAMING: u8 = 0x03;
+
+/// Class-specific descriptor types.
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+
+/// Class-specific descriptor subtypes.
+const HEADER: u8 = 0x01;
+const MIDI_IN_JACK: u8 = 0x02;
+const MIDI_OUT_JACK: u8 = 0x03;
+const MS_GENERAL: u8 = 0x01;
+
+/// Jack types.
+const EMBEDDED: u8 = 0x01;
+const EXTERNAL: u8 = 0x02;
+
+/// IDs of the jacks: The host sends to the embedded IN jack, which is connected to the external
+/// OUT jack, i.e. the application. The application sends to the external IN jack, which is
+/// connected to the embedded OUT jack that the host receives from.
+const EMBEDDED_IN_JACK: u8 = 1;
+const EXTERNAL_IN_JACK: u8 = 2;
+const EMBEDDED_OUT_JACK: u8 = 3;
+const EXTERNAL_OUT_JACK: u8 = 4;
+
+/// Total length of the class-specific MIDI streaming descriptors: The header, four jacks, and the
+/// standard and class-specific descriptors of both endpoints.
+const MS_TOTAL_LENGTH: u16 = 7 + 6 + 6 + 9 + 9 + 9 + 5 + 9 + 5;
+
+/// Size of a USB-MIDI event packet.
+const PACKET_SIZE: usize = 4;
+
+/// Maximum packet size of the bulk endpoints.
+const MAX_PACKET_SIZE: u16 = 64;
+
+/// A MIDI channel message. Channels are 0-15, the other values 0-127, except for the 14-bit pitch
+/// bend value, which is 8192 in the center.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Message {
+    NoteOff { channel: u8, note: u8, velocity: u8 },
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    PolyPressure { channel: u8, note: u8, pressure: u8 },
+    ControlChange { channel: u8, control: u8, value: u8 },
+    ProgramChange { channel: u8, program: u8 },
+    ChannelPressure { channel: u8, pressure: u8 },
+    PitchBend { channel: u8, value: u16 },
+}
+
+impl Message {
+    /// The USB-MIDI event packet for this message on cable 0: The code index number, which is the
+    /// upper nibble of the status byte, and the MIDI message padded to 3 bytes.
+    fn to_packet(self) -> [u8; PACKET_SIZE] {
+        let (status, channel, data1, data2) = match self {
+            Message::NoteOff {
+                channel,
+                note,
+                velocity,
+            } => (0x8, channel, note, velocity),
+            Message::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => (0x9, channel, note, velocity),
+            Message::PolyPressure {
+                channel,
+                note,
+                pressure,
+            } => (0xa, channel, note, pressure),
+            Message::ControlChange {
+                channel,
+                control,
+                value,
+            } => (0xb, channel, control, value),
+            Message::ProgramChange { channel, program } => (0xc, channel, program, 0),
+            Message::ChannelPressure { channel, pressure } => (0xd, channel, pressure, 0),
+            Message::PitchBend { channel, value } => {
+                (0xe, channel, value as u8 & 0x7f, (value >> 7) as u8)
+            }
+        };
+        [
+            status,
+            (status << 4) | (channel & 0x0f),
+            data1 & 0x7f,
+            data2 & 0x7f,
+        ]
+    }
+
+    /// Parse a USB-MIDI event packet. Returns `None` for packets that don't hold a channel
+    /// message.
+    fn from_packet(packet: [u8; PACKET_SIZE]) -> Option<Self> {
+        let channel = packet[1] & 0x0f;
+        let (data1, data2) = (packet[2], packet[3]);
+        let message = match packet[0] & 0x0f {
+            0x8 => Message::NoteOff {
+                channel,
+                note: data1,
+                velocity: data2,
+            },
+            0x9 => Message::NoteOn {
+                channel,
+                note: data1,
+                velocity: data2,
+            },
+            0xa => Message::PolyPressure {
+                channel,
+                note: data1,
+                pressure: data2,
+            },
+            0xb => Message::ControlChange {
+                channel,
+                control: data1,
+                value: data2,
+            },
+            0xc => Message::ProgramChange {
+                channel,
+                program: data1,
+            },
+            0xd => Message::ChannelPressure {
+                channel,
+                pressure: data1,
+            },
+            0xe => Message::PitchBend {
+                channel,
+                value: data1 as u16 | ((data2 as u16) << 7),
+            },
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+/// A USB MIDI interface with one input and one output.
+pub struct MidiClass<'a, B: UsbBus> {
+    audio_control: InterfaceNumber,
+    midi_streaming: InterfaceNumber,
+    ep_out: EndpointOut<'a, B>,
+    ep_in: EndpointIn<'a, B>,
+    /// The last packet received from the host, and the position of the next event in it.
+    received: [u8; MAX_PACKET_SIZE as usize],
+    received_len: usize,
+    position: usize,
+}
+
+impl<'a, B: UsbBus> MidiClass<'a, B> {
+    /// Allocate the interfaces and endpoints of the class on `alloc`.
+    pub fn new(alloc: &'a UsbBusAllocator<B>) -> Self {
+        Self {
+            audio_control: alloc.interface(),
+            midi_streaming: alloc.interface(),
+            ep_out: alloc.bulk(MAX_PACKET_SIZE),
+            ep_in: alloc.bulk(MAX_PACKET_SIZE),
+            received: [0; MAX_PACKET_SIZE as usize],
+            received_len: 0,
+            position: 0,
+        }
+    }
+
+    /// Send `message` to the host. Returns `UsbError::WouldBlock` if the previous message hasn't
+    /// been sent yet.
+    pub fn send(&mut self, message: Message) -> Result<()> {
+        self.ep_in.write(&message.to_packet()).map(|_| ())
+    }
+
+    /// Send a note on message.
+    pub fn note_on(&mut self, channel: u8, note: u8, velocity: u8) -> Result<()> {
+        self.send(Message::NoteOn {
+            channel,
+            note,
+            velocity,
+        })
+    }
+
+    /// Send a note off message.
+    pub fn note_off(&mut self, channel: u8, note: u8, velocity: u8) -> Result<()> {
+        self.send(Message::NoteOff {
+            channel,
+            note,
+            velocity,
+        })
+    }
+
+    /// Send a control change message.
+    pub fn control_change(&mut self, channel: u8, control: u8, value: u8) -> Result<()> {
+        self.send(Message::ControlChange {
+            channel,
+            control,
+            value,
+        })
+    }
+
+    /// Returns the next message received from the host, if any. Packets that don't hold a channel
+    /// message are skipped.
+    pub fn receive(&mut self) -> Option<Message> {
+        loop {
+            if self.position + PACKET_SIZE > self.received_len {
+                self.received_len = self.ep_out.read(&mut self.received).ok()?;
+                self.position = 0;
+                continue;
+            }
+            let mut packet = [0; PACKET_SIZE];
+            packet.copy_from_slice(&self.received[self.position..self.position + PACKET_SIZE]);
+            self.position += PACKET_SIZE;
+            if let Some(message) = Message::from_packet(packet) {
+                return Some(message);
+            }
+        }
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for MidiClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        // Group the two interfaces into one function for composite devices.
+        writer.iad(self.audio_control, 2, USB_CLASS_AUDIO, 0, 0, None)?;
+        writer.interface(
+            self.audio_control,
+            USB_CLASS_AUDIO,
+            SUBCLASS_AUDIO_CONTROL,
+            0,
+        )?;
+        // Audio control header, revision 1.00, with the MIDI streaming interface.
+        writer.write(
+            CS_INTERFACE,
+            &[HEADER, 0x00, 0x01, 9, 0, 1, self.midi_streaming.into()],
+        )?;
+
+        writer.interface(
+            self.midi_streaming,
+            USB_CLASS_AUDIO,
+            SUBCLASS_MIDI_STREAMING,
+            0,
+        )?;
+        let [length_low, length_high] = MS_TOTAL_LENGTH.to_le_bytes();
+        writer.write(CS_INTERFACE, &[HEADER, 0x00, 0x01, length_low, length_high])?;
+        writer.write(CS_INTERFACE, &[MIDI_IN_JACK, EMBEDDED, EMBEDDED_IN_JACK, 0])?;
+        writer.write(CS_INTERFACE, &[MIDI_IN_JACK, EXTERNAL, EXTERNAL_IN_JACK, 0])?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                MIDI_OUT_JACK,
+                EMBEDDED,
+                EMBEDDED_OUT_JACK,
+                1,
+                EXTERNAL_IN_JACK,
+                1,
+                0,
+            ],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                MIDI_OUT_JACK,
+                EXTERNAL,
+                EXTERNAL_OUT_JACK,
+                1,
+                EMBEDDED_IN_JACK,
+                1,
+                0,
+            ],
+        )?;
+
+        // The endpoint descriptors of audio class have two more fields, bRefresh and
+        // bSynchAddress, which are 0.
+        let audio_fields = |data: &mut [u8]| {
+            data[..2].fill(0);
+            Ok(2)
+        };
+        writer.endpoint_ex(&self.ep_out, audio_fields)?;
+        writer.write(CS_ENDPOINT, &[MS_GENERAL, 1, EMBEDDED_IN_JACK])?;
+        writer.endpoint_ex(&self.ep_in, audio_fields)?;
+        writer.write(CS_ENDPOINT, &[MS_GENERAL, 1, EMBEDDED_OUT_JACK])?;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.received_len = 0;
+        self.position = 0;
+    }
+}
@@ -0,0 +1,644 @@
+//! USB full-speed device, using the USBFS unit of the RA4M1, for the `usb-device` crate.
+//!
+//! [`UsbBus::take`] returns a `usb_device::bus::UsbBusAllocator`, from which the classes of the
+//! `usb-device` ecosystem allocate their endpoints, e.g. `usbd_serial::SerialPort` or
+//! `usbd_hid::HidClass`. Build the device with `usb_device::device::UsbDeviceBuilder` and call its
+//! `poll` method regularly, at least every few milliseconds while the host enumerates the device.
+//! To poll from an interrupt instead, link [`Event::UsbfsInt`](super::icu::Event::UsbfsInt) to a
+//! handler with [`super::icu::attach`].
+//!
+//! With the `usbd-serial` feature, [`serial`] provides a ready-made serial port over USB, and
+//! [`bridge`] connects it to the ESP32-S3, e.g. for updating its firmware. [`midi`] is a class for
+//! MIDI controllers and instruments, [`msc`] shows a block device as a drive, and [`vendor`] is a
+//! raw bulk interface for custom protocols.
+//!
+//! Several classes can share one device, e.g. a serial port for debug output next to the MIDI
+//! interface of the application. [`composite_device`] returns a builder for such a device: Every
+//! class that spans several interfaces groups them with an interface association descriptor (IAD),
+//! so that the host binds one driver per function. Allocate all classes before building the
+//! device, and pass all of them to `UsbDevice::poll`.
+//!
+//! The unit transfers data through pipes, each of which is bound to one endpoint address when it
+//! is allocated:
+//! * The default control pipe (DCP) is endpoint 0 in both directions.
+//! * Pipes 1 and 2 take isochronous endpoints with packets of up to 256 bytes, or bulk endpoints.
+//! * Pipes 3 to 5 take bulk endpoints with packets of up to 64 bytes.
+//! * Pipes 6 to 9 take interrupt endpoints with packets of up to 64 bytes.
+//!
+//! The unit answers SET_ADDRESS requests on its own, and handles the status stage of control
+//! transfers in hardware when the driver tells it to.
+//!
+//! The unit runs from the 48 MHz USB clock that the Arduino bootloader sets up for its own USB
+//! connection. On the UNO R4 WiFi, the USB-C connector is shared with the ESP32-S3 through an
+//! analog switch, so the RA4M1 only sees the host while the switch selects it.
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter "USB
+//! 2.0 Full-Speed Module (USBFS)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::UsbBus;
+//! use usb_device::prelude::*;
+//!
+//! let bus = UsbBus::take(mstp::token()).unwrap();
+//! let mut device = UsbDeviceBuilder::new(&bus, UsbVidPid(0x2341, 0x1002))
+//!     .strings(&[StringDescriptors::default().product("UNO R4")])
+//!     .unwrap()
+//!     .build();
+//! loop {
+//!     device.poll(&mut []);
+//! }
+//! ```
+
+#[cfg(feature = "usbd-serial")]
+pub mod bridge;
+pub mod midi;
+pub mod msc;
+#[cfg(feature = "usbd-serial")]
+pub mod serial;
+pub mod vendor;
+
+use super::device_id;
+use super::mstp::{unit, MstpToken};
+use super::registers::VolatileBoolOps;
+use crate::interrupt;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use usb_device::bus::{PollResult, UsbBusAllocator};
+use usb_device::device::{StringDescriptors, UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::{EndpointAddress, EndpointType};
+use usb_device::{Result, UsbDirection, UsbError};
+
+/// Vendor and product ID of the UNO R4 WiFi, so that the Arduino tools recognize the board.
+pub const VID_PID: UsbVidPid = UsbVidPid(0x2341, 0x1002);
+
+/// Returns a builder for a device with the IDs and names of the UNO R4 WiFi, which consists of
+/// several functions with interface association descriptors.
+pub fn composite_device(alloc: &UsbBusAllocator<UsbBus>) -> UsbDeviceBuilder<'_, UsbBus> {
+    UsbDeviceBuilder::new(alloc, VID_PID)
+        .strings(&[StringDescriptors::default()
+            .manufacturer("Arduino")
+            .product("UNO R4 WiFi")
+            .serial_number(device_id::serial_number())])
+        .unwrap()
+        .composite_with_iads()
+}
+
+/// Number of pipes, including the DCP.
+const NUM_PIPES: usize = 10;
+
+/// Number of times to check FRDY before the FIFO port is considered busy. The flag takes a few
+/// cycles to follow a change of CFIFOSEL.
+const FRDY_RETRIES: u32 = 100;
+
+/// An endpoint bound to a pipe.
+#[derive(Clone, Copy)]
+struct Pipe {
+    address: EndpointAddress,
+    /// The TYPE field of PIPECFG, 0 for the DCP.
+    kind: u16,
+    max_packet_size: u16,
+}
+
+/// The USBFS unit as a `usb_device::bus::UsbBus`.
+pub struct UsbBus {
+    /// The endpoint bound to each pipe. The DCP holds the OUT direction of endpoint 0.
+    pipes: [Option<Pipe>; NUM_PIPES],
+    /// The host sent the status stage of a control read, which is reported as a zero-length OUT
+    /// packet on endpoint 0.
+    status_out: AtomicBool,
+    /// The status stage of a control write was started, which is reported as a completed IN
+    /// packet on endpoint 0.
+    status_in: AtomicBool,
+    /// The clock of the unit.
+    _clock: MstpToken<unit::Usbfs>,
+}
+
+impl UsbBus {
+    const BASE_ADDRESS: u32 = 0x40090000;
+
+    /// System Configuration Control Register.
+    /// * b0: USBE, enable the unit.
+    /// * b4: DPRPU, pull D+ up, which connects the device to the host.
+    /// * b5: DRPD, pull D+ and D- down, for host mode.
+    /// * b6: DCFM, host mode instead of device mode.
+    /// * b10: SCKE, supply the clock to the unit.
+    const SYSCFG: *mut u16 = Self::BASE_ADDRESS as *mut u16;
+
+    /// CFIFO Port Register, accessed one byte at a time.
+    const CFIFO: *mut u8 = (Self::BASE_ADDRESS + 0x14) as *mut u8;
+
+    /// CFIFO Port Select Register.
+    /// * b0-b3: CURPIPE, the pipe accessed through the port.
+    /// * b5: ISEL, write to the DCP instead of reading from it.
+    /// * b10: MBW, 16-bit instead of 8-bit access.
+    const CFIFOSEL: *mut u16 = (Self::BASE_ADDRESS + 0x20) as *mut u16;
+
+    /// CFIFO Port Control Register.
+    /// * b0-b8: DTLN, the number of received bytes.
+    /// * b13: FRDY, the port can be accessed.
+    /// * b14: BCLR, clear the buffer.
+    /// * b15: BVAL, send the buffer even if it isn't full.
+    const CFIFOCTR: *mut u16 = (Self::BASE_ADDRESS + 0x22) as *mut u16;
+
+    /// Interrupt Enable Register 0.
+    /// * b8: BRDYE, buffer ready.
+    /// * b10: BEMPE, buffer empty.
+    /// * b11: CTRE, control transfer stage transition.
+    /// * b12: DVSE, device state transition.
+    /// * b14: RSME, resume.
+    const INTENB0: *mut u16 = (Self::BASE_ADDRESS + 0x30) as *mut u16;
+
+    /// BRDY Interrupt Enable Register, one bit per pipe.
+    const BRDYENB: *mut u16 = (Self::BASE_ADDRESS + 0x36) as *mut u16;
+
+    /// BEMP Interrupt Enable Register, one bit per pipe.
+    const BEMPENB: *mut u16 = (Self::BASE_ADDRESS + 0x3a) as *mut u16;
+
+    /// Interrupt Status Register 0. The flags are cleared by writing 0, writing 1 has no effect.
+    /// * b0-b2: CTSQ, the stage of the current control transfer.
+    /// * b3: VALID, a setup packet was received.
+    /// * b4-b6: DVSQ, the device state. 001 after a bus reset, 1xx while suspended.
+    /// * b11: CTRT, the control transfer stage changed.
+    /// * b12: DVST, the device state changed.
+    /// * b14: RESM, the host resumed the bus.
+    const INTSTS0: *mut u16 = (Self::BASE_ADDRESS + 0x40) as *mut u16;
+
+    /// BRDY Interrupt Status Register: A pipe has received data, or the DCP in a control write.
+    /// Cleared by writing 0.
+    const BRDYSTS: *mut u16 = (Self::BASE_ADDRESS + 0x46) as *mut u16;
+
+    /// BEMP Interrupt Status Register: A pipe has sent all its data. Cleared by writing 0.
+    const BEMPSTS: *mut u16 = (Self::BASE_ADDRESS + 0x4a) as *mut u16;
+
+    /// The fields of the last setup packet: USBREQ (bmRequestType and bRequest), USBVAL, USBINDX
+    /// and USBLENG, one after the other.
+    const USBREQ: *const u16 = (Self::BASE_ADDRESS + 0x54) as *const u16;
+
+    /// DCP Maximum Packet Size Register.
+    const DCPMAXP: *mut u16 = (Self::BASE_ADDRESS + 0x5e) as *mut u16;
+
+    /// DCP Control Register.
+    /// * b0-b1: PID, 00 for NAK, 01 for BUF (answer with the buffer), 1x for STALL.
+    /// * b2: CCPL, complete the status stage of the control transfer.
+    /// * b8: SQCLR, reset the data toggle to DATA0.
+    const DCPCTR: *mut u16 = (Self::BASE_ADDRESS + 0x60) as *mut u16;
+
+    /// Pipe Window Select Register, the pipe that PIPECFG and PIPEMAXP refer to.
+    const PIPESEL: *mut u16 = (Self::BASE_ADDRESS + 0x64) as *mut u16;
+
+    /// Pipe Configuration Register.
+    /// * b0-b3: EPNUM, the endpoint number.
+    /// * b4: DIR, IN instead of OUT.
+    /// * b14-b15: TYPE, 01 for bulk, 10 for interrupt and 11 for isochronous.
+    const PIPECFG: *mut u16 = (Self::BASE_ADDRESS + 0x68) as *mut u16;
+
+    /// Pipe Maximum Packet Size Register.
+    const PIPEMAXP: *mut u16 = (Self::BASE_ADDRESS + 0x6c) as *mut u16;
+
+    /// Pipe Control Registers of pipes 1-9, with the same PID and SQCLR fields as DCPCTR.
+    /// * b9: ACLRM, clear the buffer while set.
+    const PIPECTR: u32 = Self::BASE_ADDRESS + 0x70;
+
+    /// USB Module Control Register.
+    /// * b0: VDDUSBE, enable the reference power supply of the transceiver.
+    /// * b7: VDCEN, enable the 3.3 V regulator of the transceiver, needed with a 5 V supply.
+    const USBMC: *mut u16 = (Self::BASE_ADDRESS + 0xcc) as *mut u16;
+
+    const SYSCFG_USBE: u16 = 1 << 0;
+    const SYSCFG_DPRPU: u16 = 1 << 4;
+    const SYSCFG_SCKE: u16 = 1 << 10;
+
+    const CFIFOSEL_ISEL: u16 = 1 << 5;
+    const CFIFOCTR_DTLN: u16 = 0x1ff;
+    const CFIFOCTR_FRDY: u16 = 1 << 13;
+    const CFIFOCTR_BCLR: u16 = 1 << 14;
+    const CFIFOCTR_BVAL: u16 = 1 << 15;
+
+    const INTENB0_BRDYE: u16 = 1 << 8;
+    const INTENB0_BEMPE: u16 = 1 << 10;
+    const INTENB0_CTRE: u16 = 1 << 11;
+    const INTENB0_DVSE: u16 = 1 << 12;
+    const INTENB0_RSME: u16 = 1 << 14;
+
+    const INTSTS0_CTSQ: u16 = 0b111;
+    const INTSTS0_VALID: u16 = 1 << 3;
+    const INTSTS0_CTRT: u16 = 1 << 11;
+    const INTSTS0_DVST: u16 = 1 << 12;
+    const INTSTS0_RESM: u16 = 1 << 14;
+
+    const CTSQ_READ_STATUS: u16 = 0b010;
+    const CTSQ_WRITE_STATUS: u16 = 0b100;
+    const CTSQ_NO_DATA_STATUS: u16 = 0b101;
+
+    const DVSQ_DEFAULT: u16 = 0b001;
+    const DVSQ_SUSPENDED: u16 = 0b100;
+
+    const PID: u16 = 0b11;
+    const PID_NAK: u16 = 0b00;
+    const PID_BUF: u16 = 0b01;
+    const PID_STALL: u16 = 0b10;
+    const CTR_CCPL: u16 = 1 << 2;
+    const CTR_SQCLR: u16 = 1 << 8;
+    const CTR_ACLRM: u16 = 1 << 9;
+
+    const PIPECFG_DIR: u16 = 1 << 4;
+    const TYPE_BULK: u16 = 0b01;
+    const TYPE_INTERRUPT: u16 = 0b10;
+    const TYPE_ISOCHRONOUS: u16 = 0b11;
+
+    const USBMC_VDDUSBE: u16 = 1 << 0;
+    const USBMC_VDCEN: u16 = 1 << 7;
+
+    /// Returns the allocator for the USB device, unless it was taken already.
+    pub fn take(clock: MstpToken<unit::Usbfs>) -> Option<UsbBusAllocator<Self>> {
+        static mut TAKEN: bool = false;
+        interrupt::free(|| unsafe {
+            if TAKEN {
+                None
+            } else {
+                TAKEN = true;
+                Some(UsbBusAllocator::new(Self {
+                    pipes: [None; NUM_PIPES],
+                    status_out: AtomicBool::new(false),
+                    status_in: AtomicBool::new(false),
+                    _clock: clock,
+                }))
+            }
+        })
+    }
+
+    /// Control register of pipe `n`, or DCPCTR for 0.
+    fn pipe_ctr(n: usize) -> *mut u16 {
+        if n == 0 {
+            Self::DCPCTR
+        } else {
+            (Self::PIPECTR + 2 * (n as u32 - 1)) as *mut u16
+        }
+    }
+
+    /// Set the PID field of pipe `n`.
+    fn set_pid(n: usize, pid: u16) {
+        let ctr = Self::pipe_ctr(n);
+        unsafe {
+            ctr.write_volatile((ctr.read_volatile() & !Self::PID) | pid);
+        }
+    }
+
+    /// Returns true if pipe `n` answers with STALL.
+    fn is_pid_stall(n: usize) -> bool {
+        unsafe { Self::pipe_ctr(n).read_volatile() & Self::PID_STALL != 0 }
+    }
+
+    /// Returns the pipe bound to `address`. Endpoint 0 uses the DCP in both directions.
+    fn pipe(&self, address: EndpointAddress) -> Option<usize> {
+        if address.index() == 0 {
+            return self.pipes[0].map(|_| 0);
+        }
+        self.pipes
+            .iter()
+            .position(|pipe| pipe.is_some_and(|pipe| pipe.address == address))
+    }
+
+    /// The pipes that can take an endpoint of `kind`, in the order they are tried.
+    fn candidate_pipes(kind: u16) -> &'static [usize] {
+        match kind {
+            Self::TYPE_ISOCHRONOUS => &[1, 2],
+            Self::TYPE_BULK => &[3, 4, 5, 1, 2],
+            _ => &[6, 7, 8, 9],
+        }
+    }
+
+    /// Select pipe `n` on the CFIFO port, and wait until the port can be accessed. Returns false
+    /// if the buffer of the pipe isn't ready, i.e. has no received data or is still sending.
+    fn select_pipe(n: usize, write: bool) -> bool {
+        let sel = n as u16 | if write { Self::CFIFOSEL_ISEL } else { 0 };
+        unsafe {
+            Self::CFIFOSEL.write_volatile(sel);
+            while Self::CFIFOSEL.read_volatile() & (0xf | Self::CFIFOSEL_ISEL) != sel {
+                core::hint::spin_loop();
+            }
+            (0..FRDY_RETRIES).any(|_| Self::CFIFOCTR.read_volatile() & Self::CFIFOCTR_FRDY != 0)
+        }
+    }
+
+    /// Read the data received by pipe `n` into `buf`.
+    fn read_fifo(n: usize, buf: &mut [u8]) -> Result<usize> {
+        if !Self::select_pipe(n, false) {
+            return Err(UsbError::WouldBlock);
+        }
+        unsafe {
+            let len = (Self::CFIFOCTR.read_volatile() & Self::CFIFOCTR_DTLN) as usize;
+            if len > buf.len() {
+                Self::CFIFOCTR.write_volatile(Self::CFIFOCTR_BCLR);
+                return Err(UsbError::BufferOverflow);
+            }
+            for byte in &mut buf[..len] {
+                *byte = Self::CFIFO.read_volatile();
+            }
+            // The buffer is released when all data has been read, except for empty packets.
+            if len == 0 {
+                Self::CFIFOCTR.write_volatile(Self::CFIFOCTR_BCLR);
+            }
+            Ok(len)
+        }
+    }
+
+    /// Write `buf` into the buffer of pipe `n`, and send it.
+    fn write_fifo(n: usize, buf: &[u8], max_packet_size: u16) -> Result<usize> {
+        if buf.len() > max_packet_size as usize {
+            return Err(UsbError::BufferOverflow);
+        }
+        if !Self::select_pipe(n, n == 0) {
+            return Err(UsbError::WouldBlock);
+        }
+        unsafe {
+            for byte in buf {
+                Self::CFIFO.write_volatile(*byte);
+            }
+            // A full buffer is sent on its own.
+            if buf.len() < max_packet_size as usize {
+                Self::CFIFOCTR.write_volatile(Self::CFIFOCTR_BVAL);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Read the last setup packet and mark it as read.
+    fn read_setup(buf: &mut [u8]) -> Result<usize> {
+        if buf.len() < 8 {
+            return Err(UsbError::BufferOverflow);
+        }
+        unsafe {
+            for i in 0..4 {
+                let field = Self::USBREQ.add(i).read_volatile();
+                buf[2 * i..2 * i + 2].copy_from_slice(&field.to_le_bytes());
+            }
+            Self::INTSTS0.write_volatile(!Self::INTSTS0_VALID);
+        }
+        // Accept the data stage of a control write. For a control read, the data is accepted
+        // once it has been written.
+        let length = u16::from_le_bytes([buf[6], buf[7]]);
+        if buf[0] & 0x80 == 0 && length > 0 {
+            Self::set_pid(0, Self::PID_BUF);
+        }
+        Ok(8)
+    }
+}
+
+// The flags are atomic, and the registers are only accessed from the one thread that polls the
+// device.
+unsafe impl Sync for UsbBus {}
+
+impl usb_device::bus::UsbBus for UsbBus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        _interval: u8,
+    ) -> Result<EndpointAddress> {
+        if let EndpointType::Control = ep_type {
+            if ep_addr.is_some_and(|address| address.index() != 0) || max_packet_size > 64 {
+                return Err(UsbError::Unsupported);
+            }
+            self.pipes[0] = Some(Pipe {
+                address: EndpointAddress::from_parts(0, UsbDirection::Out),
+                kind: 0,
+                max_packet_size,
+            });
+            return Ok(EndpointAddress::from_parts(0, ep_dir));
+        }
+
+        let (kind, max) = match ep_type {
+            EndpointType::Isochronous { .. } => (Self::TYPE_ISOCHRONOUS, 256),
+            EndpointType::Bulk => (Self::TYPE_BULK, 64),
+            _ => (Self::TYPE_INTERRUPT, 64),
+        };
+        if max_packet_size > max {
+            return Err(UsbError::EndpointMemoryOverflow);
+        }
+        let in_use = |address: EndpointAddress| self.pipe(address).is_some();
+        let address = match ep_addr {
+            Some(address) if address.index() == 0 || address.index() > 15 => {
+                return Err(UsbError::InvalidEndpoint)
+            }
+            Some(address) if in_use(address) => return Err(UsbError::InvalidEndpoint),
+            Some(address) => address,
+            None => (1..16)
+                .map(|index| EndpointAddress::from_parts(index, ep_dir))
+                .find(|address| !in_use(*address))
+                .ok_or(UsbError::EndpointOverflow)?,
+        };
+        let n = *Self::candidate_pipes(kind)
+            .iter()
+            .find(|n| self.pipes[**n].is_none())
+            .ok_or(UsbError::EndpointOverflow)?;
+        self.pipes[n] = Some(Pipe {
+            address,
+            kind,
+            max_packet_size,
+        });
+        Ok(address)
+    }
+
+    fn enable(&mut self) {
+        unsafe {
+            Self::USBMC.write_volatile(Self::USBMC_VDDUSBE | Self::USBMC_VDCEN);
+            Self::SYSCFG.write_volatile(Self::SYSCFG_SCKE);
+            while Self::SYSCFG.read_volatile() & Self::SYSCFG_SCKE == 0 {
+                core::hint::spin_loop();
+            }
+            Self::SYSCFG.volatile_or(Self::SYSCFG_USBE);
+        }
+        self.reset();
+        unsafe {
+            Self::INTENB0.write_volatile(
+                Self::INTENB0_BRDYE
+                    | Self::INTENB0_BEMPE
+                    | Self::INTENB0_CTRE
+                    | Self::INTENB0_DVSE
+                    | Self::INTENB0_RSME,
+            );
+            Self::SYSCFG.volatile_or(Self::SYSCFG_DPRPU);
+        }
+    }
+
+    fn reset(&self) {
+        let mut brdy = 0;
+        let mut bemp = 0;
+        unsafe {
+            if let Some(dcp) = self.pipes[0] {
+                Self::DCPMAXP.write_volatile(dcp.max_packet_size);
+                Self::DCPCTR.write_volatile(Self::CTR_SQCLR);
+                brdy |= 1;
+                bemp |= 1;
+            }
+            for (n, pipe) in self.pipes.iter().enumerate().skip(1) {
+                let Some(pipe) = pipe else { continue };
+                let ctr = Self::pipe_ctr(n);
+                // The configuration can only be changed while the pipe answers with NAK.
+                ctr.write_volatile(Self::PID_NAK);
+                Self::PIPESEL.write_volatile(n as u16);
+                let dir = if pipe.address.is_in() {
+                    bemp |= 1 << n;
+                    Self::PIPECFG_DIR
+                } else {
+                    brdy |= 1 << n;
+                    0
+                };
+                Self::PIPECFG.write_volatile((pipe.kind << 14) | dir | pipe.address.index() as u16);
+                Self::PIPEMAXP.write_volatile(pipe.max_packet_size);
+                ctr.write_volatile(Self::CTR_SQCLR | Self::CTR_ACLRM);
+                ctr.write_volatile(Self::PID_BUF);
+            }
+            Self::PIPESEL.write_volatile(0);
+            Self::BRDYENB.write_volatile(brdy);
+            Self::BEMPENB.write_volatile(bemp);
+        }
+        self.status_out.store(false, Ordering::Relaxed);
+        self.status_in.store(false, Ordering::Relaxed);
+    }
+
+    fn set_device_address(&self, _addr: u8) {
+        // The unit answers SET_ADDRESS and sets the address on its own.
+    }
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        let n = self.pipe(ep_addr).ok_or(UsbError::InvalidEndpoint)?;
+        let pipe = self.pipes[n].ok_or(UsbError::InvalidEndpoint)?;
+        if n == 0 {
+            let ctsq = unsafe { Self::INTSTS0.read_volatile() } & Self::INTSTS0_CTSQ;
+            if buf.is_empty()
+                && (ctsq == Self::CTSQ_WRITE_STATUS || ctsq == Self::CTSQ_NO_DATA_STATUS)
+            {
+                // The unit sends the empty packet of the status stage on its own.
+                unsafe {
+                    Self::DCPCTR.write_volatile(Self::CTR_CCPL | Self::PID_BUF);
+                }
+                self.status_in.store(true, Ordering::Relaxed);
+                return Ok(0);
+            }
+        }
+        let written = Self::write_fifo(n, buf, pipe.max_packet_size)?;
+        if n == 0 {
+            Self::set_pid(0, Self::PID_BUF);
+        }
+        Ok(written)
+    }
+
+    fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        let n = self.pipe(ep_addr).ok_or(UsbError::InvalidEndpoint)?;
+        if n == 0 {
+            if unsafe { Self::INTSTS0.read_volatile() } & Self::INTSTS0_VALID != 0 {
+                return Self::read_setup(buf);
+            }
+            if self.status_out.swap(false, Ordering::Relaxed) {
+                // The unit receives the empty packet of the status stage on its own.
+                unsafe {
+                    Self::DCPCTR.write_volatile(Self::CTR_CCPL | Self::PID_BUF);
+                }
+                return Ok(0);
+            }
+        }
+        Self::read_fifo(n, buf)
+    }
+
+    fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+        let Some(n) = self.pipe(ep_addr) else { return };
+        if stalled {
+            Self::set_pid(n, Self::PID_STALL);
+        } else if Self::is_pid_stall(n) {
+            // The PID must go through NAK to leave STALL. Clearing the halt also resets the data
+            // toggle.
+            Self::set_pid(n, Self::PID_NAK);
+            unsafe {
+                Self::pipe_ctr(n).volatile_or(Self::CTR_SQCLR);
+            }
+            if n != 0 {
+                Self::set_pid(n, Self::PID_BUF);
+            }
+        }
+    }
+
+    fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+        self.pipe(ep_addr).is_some_and(Self::is_pid_stall)
+    }
+
+    fn suspend(&self) {}
+
+    fn resume(&self) {}
+
+    fn poll(&self) -> PollResult {
+        let status = unsafe { Self::INTSTS0.read_volatile() };
+        if status & Self::INTSTS0_DVST != 0 {
+            unsafe {
+                Self::INTSTS0.write_volatile(!Self::INTSTS0_DVST);
+            }
+            let dvsq = (status >> 4) & 0b111;
+            if dvsq & Self::DVSQ_SUSPENDED != 0 {
+                return PollResult::Suspend;
+            }
+            if dvsq == Self::DVSQ_DEFAULT {
+                return PollResult::Reset;
+            }
+        }
+        if status & Self::INTSTS0_RESM != 0 {
+            unsafe {
+                Self::INTSTS0.write_volatile(!Self::INTSTS0_RESM);
+            }
+            return PollResult::Resume;
+        }
+        if status & Self::INTSTS0_CTRT != 0 {
+            unsafe {
+                Self::INTSTS0.write_volatile(!Self::INTSTS0_CTRT);
+            }
+            if status & Self::INTSTS0_CTSQ == Self::CTSQ_READ_STATUS {
+                self.status_out.store(true, Ordering::Relaxed);
+            }
+        }
+
+        let mut ep_setup = 0;
+        let mut ep_out = 0;
+        let mut ep_in_complete = 0;
+        if status & Self::INTSTS0_VALID != 0 {
+            ep_setup |= 1;
+        }
+        if self.status_out.load(Ordering::Relaxed) {
+            ep_out |= 1;
+        }
+        if self.status_in.swap(false, Ordering::Relaxed) {
+            ep_in_complete |= 1;
+        }
+        let (brdy, bemp) = unsafe {
+            let brdy = Self::BRDYSTS.read_volatile() & Self::BRDYENB.read_volatile();
+            let bemp = Self::BEMPSTS.read_volatile() & Self::BEMPENB.read_volatile();
+            Self::BRDYSTS.write_volatile(!brdy);
+            Self::BEMPSTS.write_volatile(!bemp);
+            (brdy, bemp)
+        };
+        for (n, pipe) in self.pipes.iter().enumerate() {
+            let Some(pipe) = pipe else { continue };
+            let bit = 1 << pipe.address.index();
+            if brdy & (1 << n) != 0 {
+                ep_out |= bit;
+            }
+            if bemp & (1 << n) != 0 {
+                ep_in_complete |= bit;
+            }
+        }
+
+        if ep_setup | ep_out | ep_in_complete == 0 {
+            PollResult::None
+        } else {
+            PollResult::Data {
+                ep_out,
+                ep_in_complete,
+                ep_setup,
+            }
+        }
+    }
+}
@@ -0,0 +1,488 @@
+//! USB mass storage class, which shows a block device as a small drive on the host.
+//!
+//! [`MscClass`] implements the Bulk-Only Transport with the SCSI commands that hosts use for
+//! removable drives, as described in the "Universal Serial Bus Mass Storage Class Bulk-Only
+//! Transport" specification, revision 1.0. The data comes from a [`BlockDevice`], which is read
+//! and written in blocks of [`BLOCK_SIZE`] bytes. The data flash
+//! ([`crate::peripherals::flash::DataFlash`]) is a block device of 8 KB. With the `embedded-sdmmc`
+//! feature, [`Sdmmc`] turns an SD card (see [`crate::sdcard`]) into a block device.
+//!
+//! The host caches the file system of the drive, so the firmware must not change it while the
+//! drive is mounted, and the host doesn't see changes before it mounts the drive again.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::msc::{BlockDevice, MscClass, BLOCK_SIZE};
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::{composite_device, UsbBus};
+//!
+//! /// A 16 KB drive in RAM.
+//! struct RamDisk([[u8; BLOCK_SIZE]; 32]);
+//!
+//! impl BlockDevice for RamDisk {
+//!     type Error = ();
+//!
+//!     fn block_count(&self) -> u32 {
+//!         self.0.len() as u32
+//!     }
+//!
+//!     fn read_block(&mut self, lba: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), ()> {
+//!         *block = self.0[lba as usize];
+//!         Ok(())
+//!     }
+//!
+//!     fn write_block(&mut self, lba: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), ()> {
+//!         self.0[lba as usize] = *block;
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let bus = UsbBus::take(mstp::token()).unwrap();
+//! let mut msc = MscClass::new(&bus, RamDisk([[0; BLOCK_SIZE]; 32]));
+//! let mut device = composite_device(&bus).build();
+//! loop {
+//!     device.poll(&mut [&mut msc]);
+//! }
+//! ```
+
+use usb_device::class_prelude::*;
+use usb_device::Result;
+
+/// Size of the blocks of a [`BlockDevice`].
+pub const BLOCK_SIZE: usize = 512;
+
+/// Mass storage class, with the SCSI transparent command set and the Bulk-Only Transport.
+const USB_CLASS_MSC: u8 = 0x08;
+const SUBCLASS_SCSI: u8 = 0x06;
+const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+/// Class-specific requests.
+const REQUEST_RESET: u8 = 0xff;
+const REQUEST_GET_MAX_LUN: u8 = 0xfe;
+
+/// Maximum packet size of the bulk endpoints.
+const MAX_PACKET_SIZE: u16 = 64;
+
+/// Signatures of the Command Block Wrapper and the Command Status Wrapper.
+const CBW_SIGNATURE: u32 = 0x43425355;
+const CSW_SIGNATURE: u32 = 0x53425355;
+const CBW_LEN: usize = 31;
+const CSW_LEN: usize = 13;
+
+/// SCSI commands.
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const MODE_SENSE_6: u8 = 0x1a;
+const START_STOP_UNIT: u8 = 0x1b;
+const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+const READ_FORMAT_CAPACITIES: u8 = 0x23;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2a;
+const VERIFY_10: u8 = 0x2f;
+const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+const MODE_SENSE_10: u8 = 0x5a;
+
+/// A storage medium that is read and written in blocks of [`BLOCK_SIZE`] bytes.
+pub trait BlockDevice {
+    type Error;
+
+    /// Returns the number of blocks.
+    fn block_count(&self) -> u32;
+
+    /// Read block `lba` into `block`.
+    fn read_block(
+        &mut self,
+        lba: u32,
+        block: &mut [u8; BLOCK_SIZE],
+    ) -> core::result::Result<(), Self::Error>;
+
+    /// Write `block` to block `lba`.
+    fn write_block(
+        &mut self,
+        lba: u32,
+        block: &[u8; BLOCK_SIZE],
+    ) -> core::result::Result<(), Self::Error>;
+
+    /// Returns false if the host must not write to the device.
+    fn is_writable(&self) -> bool {
+        true
+    }
+}
+
+/// Sense data of the last failed command: Sense key, additional sense code and qualifier.
+#[derive(Clone, Copy)]
+struct Sense(u8, u8, u8);
+
+impl Sense {
+    const NONE: Sense = Sense(0x00, 0x00, 0x00);
+    const INVALID_COMMAND: Sense = Sense(0x05, 0x20, 0x00);
+    const LBA_OUT_OF_RANGE: Sense = Sense(0x05, 0x21, 0x00);
+    const READ_ERROR: Sense = Sense(0x03, 0x11, 0x00);
+    const WRITE_ERROR: Sense = Sense(0x03, 0x0c, 0x00);
+    const WRITE_PROTECTED: Sense = Sense(0x07, 0x27, 0x00);
+}
+
+/// Stage of the Bulk-Only Transport.
+#[derive(Clone, Copy)]
+enum State {
+    /// Waiting for a command.
+    Command,
+    /// Sending `len` bytes of the buffer, of which `position` are sent. Then, the next `blocks`
+    /// blocks from `lba` on are read into the buffer and sent.
+    DataIn {
+        len: usize,
+        position: usize,
+        lba: u32,
+        blocks: u32,
+    },
+    /// Receiving `blocks` blocks, to be written from `lba` on. `position` bytes of the current
+    /// block are in the buffer.
+    DataOut {
+        position: usize,
+        lba: u32,
+        blocks: u32,
+    },
+    /// Sending the status of the command.
+    Status,
+}
+
+/// A USB mass storage interface for a [`BlockDevice`].
+pub struct MscClass<'a, B: UsbBus, D: BlockDevice> {
+    interface: InterfaceNumber,
+    ep_out: EndpointOut<'a, B>,
+    ep_in: EndpointIn<'a, B>,
+    device: D,
+    state: State,
+    /// Tag of the current command, echoed in its status.
+    tag: u32,
+    /// Number of bytes that the host expects to transfer in the data stage, and hasn't yet.
+    residue: u32,
+    /// The host expects data from the device in the current command.
+    direction_in: bool,
+    /// The command failed.
+    failed: bool,
+    sense: Sense,
+    buffer: [u8; BLOCK_SIZE],
+}
+
+impl<'a, B: UsbBus, D: BlockDevice> MscClass<'a, B, D> {
+    /// Allocate the interface and endpoints of the class on `alloc`, and show `device` to the
+    /// host.
+    pub fn new(alloc: &'a UsbBusAllocator<B>, device: D) -> Self {
+        Self {
+            interface: alloc.interface(),
+            ep_out: alloc.bulk(MAX_PACKET_SIZE),
+            ep_in: alloc.bulk(MAX_PACKET_SIZE),
+            device,
+            state: State::Command,
+            tag: 0,
+            residue: 0,
+            direction_in: false,
+            failed: false,
+            sense: Sense::NONE,
+            buffer: [0; BLOCK_SIZE],
+        }
+    }
+
+    /// Returns the block device, e.g. to access it while the host doesn't.
+    pub fn device(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    /// Receive and start a command.
+    fn receive_command(&mut self) {
+        let mut cbw = [0; MAX_PACKET_SIZE as usize];
+        let Ok(len) = self.ep_out.read(&mut cbw) else {
+            return;
+        };
+        let word = |i: usize| u32::from_le_bytes([cbw[i], cbw[i + 1], cbw[i + 2], cbw[i + 3]]);
+        if len != CBW_LEN || word(0) != CBW_SIGNATURE {
+            // Not a valid command, which the host must resolve with a reset.
+            self.ep_in.stall();
+            self.ep_out.stall();
+            return;
+        }
+        self.tag = word(4);
+        self.residue = word(8);
+        self.direction_in = cbw[12] & 0x80 != 0;
+        self.failed = false;
+        let mut command = [0; 16];
+        command.copy_from_slice(&cbw[15..31]);
+        self.execute(&command);
+    }
+
+    /// Execute the SCSI command in `cb`, and set the state for its data stage.
+    fn execute(&mut self, cb: &[u8; 16]) {
+        let lba = u32::from_be_bytes([cb[2], cb[3], cb[4], cb[5]]);
+        let blocks = u16::from_be_bytes([cb[7], cb[8]]) as u32;
+        let count = self.device.block_count();
+        let write_protect = if self.device.is_writable() { 0 } else { 0x80 };
+        match cb[0] {
+            TEST_UNIT_READY
+            | START_STOP_UNIT
+            | PREVENT_ALLOW_MEDIUM_REMOVAL
+            | VERIFY_10
+            | SYNCHRONIZE_CACHE_10 => self.respond(&[]),
+            REQUEST_SENSE => {
+                let Sense(key, asc, ascq) = self.sense;
+                self.sense = Sense::NONE;
+                self.respond(&[
+                    0x70, 0, key, 0, 0, 0, 0, 10, 0, 0, 0, 0, asc, ascq, 0, 0, 0, 0,
+                ]);
+            }
+            INQUIRY => {
+                let mut inquiry = [0; 36];
+                // Direct access device, removable, SPC-2.
+                inquiry[..5].copy_from_slice(&[0x00, 0x80, 0x04, 0x02, 31]);
+                inquiry[8..16].copy_from_slice(b"Arduino ");
+                inquiry[16..32].copy_from_slice(b"UNO R4 Storage  ");
+                inquiry[32..36].copy_from_slice(b"1.0 ");
+                self.respond(&inquiry);
+            }
+            MODE_SENSE_6 => self.respond(&[3, 0, write_protect, 0]),
+            MODE_SENSE_10 => self.respond(&[0, 6, 0, write_protect, 0, 0, 0, 0]),
+            READ_FORMAT_CAPACITIES => {
+                let mut capacities = [0, 0, 0, 8, 0, 0, 0, 0, 0x02, 0, 0, 0];
+                capacities[4..8].copy_from_slice(&count.to_be_bytes());
+                capacities[9..12].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes()[1..]);
+                self.respond(&capacities);
+            }
+            READ_CAPACITY_10 => {
+                let mut capacity = [0; 8];
+                capacity[..4].copy_from_slice(&count.saturating_sub(1).to_be_bytes());
+                capacity[4..].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+                self.respond(&capacity);
+            }
+            READ_10 | WRITE_10 if lba.checked_add(blocks).is_none_or(|end| end > count) => {
+                self.fail(Sense::LBA_OUT_OF_RANGE);
+            }
+            READ_10 => {
+                self.state = State::DataIn {
+                    len: 0,
+                    position: 0,
+                    lba,
+                    blocks,
+                };
+            }
+            WRITE_10 if !self.device.is_writable() => self.fail(Sense::WRITE_PROTECTED),
+            WRITE_10 => {
+                self.state = State::DataOut {
+                    position: 0,
+                    lba,
+                    blocks,
+                };
+            }
+            _ => self.fail(Sense::INVALID_COMMAND),
+        }
+    }
+
+    /// Send `data` in the data stage, as far as the host expects it.
+    fn respond(&mut self, data: &[u8]) {
+        let len = data.len().min(self.residue as usize);
+        self.buffer[..len].copy_from_slice(&data[..len]);
+        self.state = State::DataIn {
+            len,
+            position: 0,
+            lba: 0,
+            blocks: 0,
+        };
+    }
+
+    /// Fail the command with `sense`. The data stage is skipped by stalling its endpoint.
+    fn fail(&mut self, sense: Sense) {
+        self.sense = sense;
+        self.failed = true;
+        if self.residue > 0 {
+            if self.direction_in {
+                self.ep_in.stall();
+            } else {
+                self.ep_out.stall();
+            }
+        }
+        self.state = State::Status;
+    }
+
+    /// Continue sending the data stage.
+    fn send_data(&mut self, mut len: usize, mut position: usize, mut lba: u32, mut blocks: u32) {
+        if position == len {
+            if blocks == 0 || self.residue == 0 {
+                self.state = State::Status;
+                return self.send_status();
+            }
+            if self.device.read_block(lba, &mut self.buffer).is_err() {
+                return self.fail(Sense::READ_ERROR);
+            }
+            (len, position, lba, blocks) = (BLOCK_SIZE, 0, lba + 1, blocks - 1);
+        }
+        let end = len.min(position + MAX_PACKET_SIZE as usize);
+        if let Ok(written) = self.ep_in.write(&self.buffer[position..end]) {
+            self.residue = self.residue.saturating_sub(written as u32);
+            position += written;
+        }
+        self.state = State::DataIn {
+            len,
+            position,
+            lba,
+            blocks,
+        };
+    }
+
+    /// Continue receiving the data stage.
+    fn receive_data(&mut self, position: usize, lba: u32, blocks: u32) {
+        if blocks == 0 {
+            self.state = State::Status;
+            return self.send_status();
+        }
+        let Ok(len) = self.ep_out.read(&mut self.buffer[position..]) else {
+            return;
+        };
+        self.residue = self.residue.saturating_sub(len as u32);
+        let position = position + len;
+        if position < BLOCK_SIZE {
+            self.state = State::DataOut {
+                position,
+                lba,
+                blocks,
+            };
+            return;
+        }
+        if self.device.write_block(lba, &self.buffer).is_err() {
+            self.sense = Sense::WRITE_ERROR;
+            self.failed = true;
+        }
+        self.state = State::DataOut {
+            position: 0,
+            lba: lba + 1,
+            blocks: blocks - 1,
+        };
+        if blocks == 1 {
+            self.state = State::Status;
+            self.send_status();
+        }
+    }
+
+    /// Send the Command Status Wrapper.
+    fn send_status(&mut self) {
+        let mut csw = [0; CSW_LEN];
+        csw[0..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+        csw[4..8].copy_from_slice(&self.tag.to_le_bytes());
+        csw[8..12].copy_from_slice(&self.residue.to_le_bytes());
+        csw[12] = self.failed as u8;
+        if self.ep_in.write(&csw).is_ok() {
+            self.state = State::Command;
+        }
+    }
+}
+
+impl<B: UsbBus, D: BlockDevice> UsbClass<B> for MscClass<'_, B, D> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(
+            self.interface,
+            USB_CLASS_MSC,
+            SUBCLASS_SCSI,
+            PROTOCOL_BULK_ONLY,
+        )?;
+        writer.endpoint(&self.ep_out)?;
+        writer.endpoint(&self.ep_in)?;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.state = State::Command;
+    }
+
+    fn poll(&mut self) {
+        match self.state {
+            State::Command => self.receive_command(),
+            State::DataIn {
+                len,
+                position,
+                lba,
+                blocks,
+            } => self.send_data(len, position, lba, blocks),
+            State::DataOut {
+                position,
+                lba,
+                blocks,
+            } => self.receive_data(position, lba, blocks),
+            State::Status => self.send_status(),
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let request = xfer.request();
+        if request.request_type == RequestType::Class
+            && request.recipient == Recipient::Interface
+            && request.index == u8::from(self.interface) as u16
+            && request.request == REQUEST_GET_MAX_LUN
+        {
+            // A single logical unit.
+            xfer.accept_with(&[0]).ok();
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let request = xfer.request();
+        if request.request_type == RequestType::Class
+            && request.recipient == Recipient::Interface
+            && request.index == u8::from(self.interface) as u16
+            && request.request == REQUEST_RESET
+        {
+            self.state = State::Command;
+            xfer.accept().ok();
+        }
+    }
+}
+
+/// A block device of the `embedded-sdmmc` crate, e.g. an SD card, for [`MscClass`].
+#[cfg(feature = "embedded-sdmmc")]
+pub struct Sdmmc<D> {
+    device: D,
+    block_count: u32,
+}
+
+#[cfg(feature = "embedded-sdmmc")]
+mod embedded_sdmmc_impl {
+    use super::{BlockDevice, Sdmmc, BLOCK_SIZE};
+    use embedded_sdmmc::{Block, BlockIdx};
+
+    impl<D: embedded_sdmmc::BlockDevice> Sdmmc<D> {
+        /// Use `device` for [`super::MscClass`]. Returns the error of `device` if its size can't
+        /// be read.
+        pub fn new(device: D) -> Result<Self, D::Error> {
+            let block_count = device.num_blocks()?.0;
+            Ok(Self {
+                device,
+                block_count,
+            })
+        }
+
+        /// Returns the block device.
+        pub fn release(self) -> D {
+            self.device
+        }
+    }
+
+    impl<D: embedded_sdmmc::BlockDevice> BlockDevice for Sdmmc<D> {
+        type Error = D::Error;
+
+        fn block_count(&self) -> u32 {
+            self.block_count
+        }
+
+        fn read_block(&mut self, lba: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), D::Error> {
+            let mut blocks = [Block::new()];
+            self.device.read(&mut blocks, BlockIdx(lba))?;
+            block.copy_from_slice(&blocks[0].contents);
+            Ok(())
+        }
+
+        fn write_block(&mut self, lba: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), D::Error> {
+            let mut blocks = [Block::new()];
+            blocks[0].contents.copy_from_slice(block);
+            self.device.write(&blocks, BlockIdx(lba))
+        }
+    }
+}
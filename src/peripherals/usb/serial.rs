@@ -0,0 +1,337 @@
+//! Serial port over USB (CDC-ACM), like `Serial` in the Arduino core.
+//!
+//! [`Serial::new`] builds a USB device with a single `usbd_serial::SerialPort`, which shows up as
+//! a serial port on the host (`/dev/ttyACM0`, `COM3`, ...). The baud rate set by the host is
+//! ignored, data is always transferred at full USB speed.
+//!
+//! The device must be polled regularly, either by calling [`Serial::poll`] from the main loop, or
+//! from the USB interrupt after handing the port to [`install`]. Then [`with`] gives access to it
+//! from the main program.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::serial::{self, Serial};
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::UsbBus;
+//! use core::fmt::Write;
+//! use usb_device::bus::UsbBusAllocator;
+//!
+//! static mut BUS: Option<UsbBusAllocator<UsbBus>> = None;
+//!
+//! let bus = UsbBus::take(mstp::token()).unwrap();
+//! let bus = unsafe { (*core::ptr::addr_of_mut!(BUS)).insert(bus) };
+//! serial::install(Serial::new(bus)).ok().unwrap();
+//! serial::with(|serial| writeln!(serial, "Hello from the UNO R4").unwrap());
+//! ```
+//!
+//! Like the Arduino core, the port restarts the board into the bootloader when the host opens it
+//! at 1200 baud and closes it again (see [`crate::peripherals::reset::enter_bootloader`]), which
+//! is how the Arduino IDE and `arduino-cli` start an upload. [`Serial::set_bootloader_touch`]
+//! turns this off.
+//!
+//! Writes only block while a terminal has the port open (the host has set DTR). Otherwise, the
+//! data is dropped, so firmware that prints debug output doesn't hang without a host.
+//!
+//! If the program panics while a port is installed, the panic handler prints the panic message
+//! and the fault status registers of the CPU on it. It keeps polling the device afterwards, so the
+//! report is printed again whenever a terminal opens the port, and the Arduino IDE can still
+//! restart the board for an upload.
+
+use super::{composite_device, UsbBus};
+use crate::interrupt;
+use crate::peripherals::icu::{self, Event, Slot};
+use crate::peripherals::reset;
+
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::ptr;
+use usb_device::bus::UsbBusAllocator;
+use usb_device::class::UsbClass;
+use usb_device::device::{UsbDevice, UsbDeviceState};
+use usb_device::UsbError;
+use usbd_serial::SerialPort;
+
+/// The baud rate at which closing the port restarts into the bootloader.
+const TOUCH_BAUD_RATE: u32 = 1200;
+
+/// Interrupt control and state register of the system control block.
+/// * b8-b0: VECTACTIVE, number of the active exception, 0 in thread mode.
+const ICSR: *const u32 = 0xe000ed04 as *const u32;
+
+/// Configurable fault status register, with the causes of MemManage, BusFault and UsageFault.
+const CFSR: *const u32 = 0xe000ed28 as *const u32;
+
+/// HardFault status register.
+const HFSR: *const u32 = 0xe000ed2c as *const u32;
+
+/// MemManage fault address register.
+const MMFAR: *const u32 = 0xe000ed34 as *const u32;
+
+/// BusFault address register.
+const BFAR: *const u32 = 0xe000ed38 as *const u32;
+
+/// A USB device with a CDC-ACM serial port.
+pub struct Serial {
+    device: UsbDevice<'static, UsbBus>,
+    port: SerialPort<'static, UsbBus>,
+    bootloader_touch: bool,
+}
+
+impl Serial {
+    /// Build the USB device on `bus`. It is connected to the host by the first [`Serial::poll`].
+    ///
+    /// Classes that were allocated on `bus` before are part of the device as well, see
+    /// [`Serial::poll_with`].
+    pub fn new(bus: &'static UsbBusAllocator<UsbBus>) -> Self {
+        let port = SerialPort::new(bus);
+        let device = composite_device(bus).build();
+        Self {
+            device,
+            port,
+            bootloader_touch: true,
+        }
+    }
+
+    /// Handle the pending USB events. Must be called at least every few milliseconds, unless the
+    /// port is polled from the interrupt, see [`install`]. Returns true if there may be data to
+    /// read.
+    pub fn poll(&mut self) -> bool {
+        let data = self.device.poll(&mut [&mut self.port]);
+        self.check_bootloader_touch();
+        data
+    }
+
+    /// Like [`Serial::poll`], for a composite device that has up to three `classes` next to the
+    /// serial port. Returns [`UsbError::Unsupported`] if there are more, without polling.
+    ///
+    /// [`Serial::write_all`] and [`Serial::flush`] only poll the serial port while they wait, so
+    /// requests for the other classes are rejected meanwhile.
+    pub fn poll_with(
+        &mut self,
+        classes: &mut [&mut dyn UsbClass<UsbBus>],
+    ) -> Result<bool, UsbError> {
+        let data = match classes {
+            [] => self.device.poll(&mut [&mut self.port]),
+            [a] => self.device.poll(&mut [&mut self.port, *a]),
+            [a, b] => self.device.poll(&mut [&mut self.port, *a, *b]),
+            [a, b, c] => self.device.poll(&mut [&mut self.port, *a, *b, *c]),
+            _ => return Err(UsbError::Unsupported),
+        };
+        self.check_bootloader_touch();
+        Ok(data)
+    }
+
+    /// Restart into the bootloader if the host opened the port at 1200 baud and closed it again.
+    fn check_bootloader_touch(&self) {
+        if self.bootloader_touch
+            && self.port.line_coding().data_rate() == TOUCH_BAUD_RATE
+            && !self.port.dtr()
+            && self.device.state() == UsbDeviceState::Configured
+        {
+            reset::enter_bootloader();
+        }
+    }
+
+    /// Turn the restart into the bootloader on or off, which happens when the host opens the port
+    /// at 1200 baud and closes it again. It is on by default.
+    pub fn set_bootloader_touch(&mut self, enabled: bool) {
+        self.bootloader_touch = enabled;
+    }
+
+    /// Returns true if a terminal has the port open on the host.
+    pub fn is_connected(&self) -> bool {
+        self.device.state() == UsbDeviceState::Configured && self.port.dtr()
+    }
+
+    /// Returns the DTR (data terminal ready) line set by the host, which terminals set while they
+    /// have the port open.
+    pub fn dtr(&self) -> bool {
+        self.port.dtr()
+    }
+
+    /// Returns the RTS (request to send) line set by the host.
+    pub fn rts(&self) -> bool {
+        self.port.rts()
+    }
+
+    /// Returns the baud rate set by the host. It has no effect on the transfer.
+    pub fn baud_rate(&self) -> u32 {
+        self.port.line_coding().data_rate()
+    }
+
+    /// Read the received bytes into `buffer`, and return their number. Returns 0 if there are
+    /// none.
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        self.port.read(buffer).unwrap_or(0)
+    }
+
+    /// Write as many bytes of `data` as fit into the transmit buffer, and return their number.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        self.port.write(data).unwrap_or(0)
+    }
+
+    /// Write all of `data`, polling the device while the transmit buffer is full. Returns false,
+    /// and drops the rest, if no terminal has the port open.
+    pub fn write_all(&mut self, mut data: &[u8]) -> bool {
+        while !data.is_empty() {
+            if !self.is_connected() {
+                return false;
+            }
+            match self.port.write(data) {
+                Ok(written) => data = &data[written..],
+                Err(UsbError::WouldBlock) => {}
+                Err(_) => return false,
+            }
+            self.poll();
+        }
+        true
+    }
+
+    /// Wait until the buffered data has been sent, unless no terminal has the port open.
+    pub fn flush(&mut self) {
+        while self.is_connected() {
+            match self.port.flush() {
+                Err(UsbError::WouldBlock) => self.poll(),
+                _ => break,
+            };
+        }
+    }
+}
+
+impl fmt::Write for Serial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// A port polled by the USB interrupt.
+struct Installed {
+    serial: Serial,
+    slot: Slot,
+    poll: fn(&mut Serial),
+}
+
+static mut INSTALLED: Option<Installed> = None;
+
+/// Poll the installed port.
+fn on_interrupt() {
+    if let Some(installed) = unsafe { (*ptr::addr_of_mut!(INSTALLED)).as_mut() } {
+        (installed.poll)(&mut installed.serial);
+    }
+}
+
+/// Poll `serial` from the USB interrupt from now on. Returns the port if one is installed
+/// already, or if all interrupt slots are in use.
+pub fn install(serial: Serial) -> Result<(), Serial> {
+    install_with(serial, |serial| {
+        serial.poll();
+    })
+}
+
+/// Like [`install`], but call `poll` from the interrupt, e.g. to poll a composite device with
+/// [`Serial::poll_with`]:
+///
+/// ```
+/// use arduino_uno_r4_wifi_rt::interrupt;
+/// use arduino_uno_r4_wifi_rt::peripherals::mstp;
+/// use arduino_uno_r4_wifi_rt::peripherals::usb::midi::MidiClass;
+/// use arduino_uno_r4_wifi_rt::peripherals::usb::serial::{self, Serial};
+/// use arduino_uno_r4_wifi_rt::peripherals::usb::UsbBus;
+/// use usb_device::bus::UsbBusAllocator;
+///
+/// static mut BUS: Option<UsbBusAllocator<UsbBus>> = None;
+/// static mut MIDI: Option<MidiClass<'static, UsbBus>> = None;
+///
+/// fn poll(serial: &mut Serial) {
+///     if let Some(midi) = unsafe { (*core::ptr::addr_of_mut!(MIDI)).as_mut() } {
+///         serial.poll_with(&mut [midi]).ok();
+///     }
+/// }
+///
+/// let bus = UsbBus::take(mstp::token()).unwrap();
+/// let bus = unsafe { (*core::ptr::addr_of_mut!(BUS)).insert(bus) };
+/// unsafe { *core::ptr::addr_of_mut!(MIDI) = Some(MidiClass::new(bus)) };
+/// serial::install_with(Serial::new(bus), poll).ok().unwrap();
+/// interrupt::free(|| {
+///     let midi = unsafe { (*core::ptr::addr_of_mut!(MIDI)).as_mut().unwrap() };
+///     midi.note_on(0, 60, 100).ok();
+/// });
+/// ```
+pub fn install_with(serial: Serial, poll: fn(&mut Serial)) -> Result<(), Serial> {
+    interrupt::free(|| unsafe {
+        let installed = &mut *ptr::addr_of_mut!(INSTALLED);
+        if installed.is_some() {
+            return Err(serial);
+        }
+        match icu::attach(Event::UsbfsInt, on_interrupt) {
+            Some(slot) => {
+                *installed = Some(Installed { serial, slot, poll });
+                Ok(())
+            }
+            None => Err(serial),
+        }
+    })
+}
+
+/// Stop polling the installed port from the interrupt, and return it.
+pub fn uninstall() -> Option<Serial> {
+    interrupt::free(|| unsafe {
+        let installed = (*ptr::addr_of_mut!(INSTALLED)).take()?;
+        icu::detach(installed.slot);
+        Some(installed.serial)
+    })
+}
+
+/// Call `f` with the installed port, with interrupts disabled. Returns `None` if no port is
+/// installed.
+///
+/// Since the interrupt can't poll the device meanwhile, [`Serial::write_all`] and
+/// [`Serial::flush`] poll it themselves.
+pub fn with<R>(f: impl FnOnce(&mut Serial) -> R) -> Option<R> {
+    interrupt::free(|| unsafe {
+        let installed = (*ptr::addr_of_mut!(INSTALLED)).as_mut()?;
+        Some(f(&mut installed.serial))
+    })
+}
+
+/// Print `info` and the fault status registers on the installed port, and keep polling the device
+/// forever. Returns if no port is installed. Called by the panic handler of the crate.
+///
+/// Interrupts stay disabled, so the USB interrupt doesn't poll the device meanwhile. If the panic
+/// happened while polling the device, its state may be inconsistent, and the report may not reach
+/// the host.
+pub(crate) fn report_panic(info: &PanicInfo<'_>) {
+    interrupt::disable();
+    let Some(installed) = (unsafe { (*ptr::addr_of_mut!(INSTALLED)).as_mut() }) else {
+        return;
+    };
+    let sp: u32;
+    let (icsr, cfsr, hfsr, mmfar, bfar) = unsafe {
+        asm!("mov {}, sp", out(reg) sp, options(nomem, nostack, preserves_flags));
+        (
+            ICSR.read_volatile(),
+            CFSR.read_volatile(),
+            HFSR.read_volatile(),
+            MMFAR.read_volatile(),
+            BFAR.read_volatile(),
+        )
+    };
+    let mut connected = false;
+    loop {
+        (installed.poll)(&mut installed.serial);
+        let serial = &mut installed.serial;
+        if serial.is_connected() && !connected {
+            write!(
+                serial,
+                "\r\n{info}\r\nexception {} sp {sp:#010x}\r\n\
+                 cfsr {cfsr:#010x} hfsr {hfsr:#010x} mmfar {mmfar:#010x} bfar {bfar:#010x}\r\n",
+                icsr & 0x1ff,
+            )
+            .ok();
+            serial.flush();
+        }
+        connected = serial.is_connected();
+    }
+}
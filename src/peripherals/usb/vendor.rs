@@ -0,0 +1,112 @@
+//! Vendor-specific USB interface with a pair of bulk endpoints, for custom protocols.
+//!
+//! [`VendorClass`] has no class driver on the host. An application opens it with libusb (e.g.
+//! `rusb` or `pyusb`) through the vendor and product ID, claims the interface and transfers
+//! packets of up to [`MAX_PACKET_SIZE`] bytes on the endpoints. Without the line coding and
+//! notifications of CDC, and with the host reading large transfers at once, this is the fastest
+//! way to stream data, up to roughly 1 MB/s at full speed.
+//!
+//! A bulk transfer ends with a packet that is shorter than [`MAX_PACKET_SIZE`]. A host that reads
+//! with a larger buffer waits for more data after a full packet, so end a message whose length is
+//! a multiple of the packet size with an empty packet.
+//!
+//! On Windows, the interface needs the WinUSB driver, e.g. installed with Zadig.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::vendor::{VendorClass, MAX_PACKET_SIZE};
+//! use arduino_uno_r4_wifi_rt::peripherals::usb::{composite_device, UsbBus};
+//!
+//! let bus = UsbBus::take(mstp::token()).unwrap();
+//! let mut vendor = VendorClass::new(&bus);
+//! let mut device = composite_device(&bus).build();
+//! let mut sample: u16 = 0;
+//! loop {
+//!     device.poll(&mut [&mut vendor]);
+//!     // Stream a counter as packets of 32 samples.
+//!     let mut packet = [0; MAX_PACKET_SIZE];
+//!     for chunk in packet.chunks_mut(2) {
+//!         chunk.copy_from_slice(&sample.to_le_bytes());
+//!         sample = sample.wrapping_add(1);
+//!     }
+//!     while vendor.write_packet(&packet).is_err() {
+//!         device.poll(&mut [&mut vendor]);
+//!     }
+//! }
+//! ```
+
+use usb_device::class_prelude::*;
+use usb_device::Result;
+
+/// Vendor-specific interface class.
+const USB_CLASS_VENDOR: u8 = 0xff;
+
+/// Maximum packet size of the bulk endpoints.
+pub const MAX_PACKET_SIZE: usize = 64;
+
+/// A vendor-specific interface with one bulk OUT and one bulk IN endpoint.
+pub struct VendorClass<'a, B: UsbBus> {
+    interface: InterfaceNumber,
+    ep_out: EndpointOut<'a, B>,
+    ep_in: EndpointIn<'a, B>,
+    subclass: u8,
+    protocol: u8,
+}
+
+impl<'a, B: UsbBus> VendorClass<'a, B> {
+    /// Allocate the interface and endpoints of the class on `alloc`.
+    pub fn new(alloc: &'a UsbBusAllocator<B>) -> Self {
+        Self::with_protocol(alloc, 0, 0)
+    }
+
+    /// Like [`VendorClass::new`], with the subclass and protocol codes of the interface, which
+    /// the host application can use to tell several vendor interfaces apart.
+    pub fn with_protocol(alloc: &'a UsbBusAllocator<B>, subclass: u8, protocol: u8) -> Self {
+        Self {
+            interface: alloc.interface(),
+            ep_out: alloc.bulk(MAX_PACKET_SIZE as u16),
+            ep_in: alloc.bulk(MAX_PACKET_SIZE as u16),
+            subclass,
+            protocol,
+        }
+    }
+
+    /// Returns the number of the interface, which the host application claims.
+    pub fn interface(&self) -> InterfaceNumber {
+        self.interface
+    }
+
+    /// Read a packet from the host into `buffer`, which must hold [`MAX_PACKET_SIZE`] bytes, and
+    /// return its length. Returns `UsbError::WouldBlock` if no packet has been received.
+    pub fn read_packet(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        self.ep_out.read(buffer)
+    }
+
+    /// Send `data`, of up to [`MAX_PACKET_SIZE`] bytes, as one packet. Returns
+    /// `UsbError::WouldBlock` if the previous packet hasn't been sent yet.
+    pub fn write_packet(&mut self, data: &[u8]) -> Result<usize> {
+        self.ep_in.write(data)
+    }
+
+    /// Stop the endpoints with a STALL handshake, e.g. to tell the host application that the
+    /// protocol got out of sync. The host clears it with CLEAR_FEATURE.
+    pub fn stall(&mut self) {
+        self.ep_out.stall();
+        self.ep_in.stall();
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for VendorClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(
+            self.interface,
+            USB_CLASS_VENDOR,
+            self.subclass,
+            self.protocol,
+        )?;
+        writer.endpoint(&self.ep_out)?;
+        writer.endpoint(&self.ep_in)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+//! Helpers that keep a watchdog fed during long blocking operations.
+//!
+//! [`Watchdog`] is implemented by the running [`super::wdt::Wdt`] and by [`super::iwdt::Iwdt`].
+//! [`FeedingDelay`] waits like [`Delay`], but feeds the watchdog at a fixed interval while it
+//! waits, so long delays don't cause a reset. [`Watchdog::feed_every`] returns a guard for loops
+//! that feeds the watchdog every few iterations, e.g. while erasing flash page by page.
+//!
+//! If the window of the watchdog is restricted, the interval must be chosen so that every feed
+//! falls into the window, otherwise feeding too early resets the MCU.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::systick::{Delay, SysTick};
+//! use arduino_uno_r4_wifi_rt::peripherals::watchdog::{FeedingDelay, Watchdog};
+//! use arduino_uno_r4_wifi_rt::peripherals::wdt::{Config, Wdt};
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let mut wdt = Wdt::take().unwrap().start(&Config::with_timeout_ms(&clocks, 100));
+//! let delay = Delay::new(SysTick::instance().unwrap(), &clocks);
+//! let mut delay = FeedingDelay::new(delay, &mut wdt, 50);
+//! // Waits for 2 s without a reset.
+//! delay.delay_ms(2000);
+//!
+//! let mut guard = wdt.feed_every(1000);
+//! for i in 0..1_000_000 {
+//!     // Some work, then:
+//!     guard.tick();
+//! }
+//! ```
+
+use super::iwdt::Iwdt;
+use super::systick::Delay;
+use super::wdt::{Running, Wdt};
+
+/// A watchdog that can be fed.
+pub trait Watchdog {
+    /// Set the counter of the watchdog back to its timeout.
+    fn feed(&mut self);
+
+    /// Returns a guard that feeds the watchdog on every `iterations`th call of
+    /// [`FeedEvery::tick`].
+    fn feed_every(&mut self, iterations: u32) -> FeedEvery<'_, Self>
+    where
+        Self: Sized,
+    {
+        FeedEvery {
+            watchdog: self,
+            iterations: iterations.max(1),
+            count: 0,
+        }
+    }
+}
+
+impl Watchdog for Wdt<Running> {
+    #[inline]
+    fn feed(&mut self) {
+        Wdt::feed(self);
+    }
+}
+
+impl Watchdog for Iwdt {
+    #[inline]
+    fn feed(&mut self) {
+        Iwdt::feed(self);
+    }
+}
+
+/// Loop guard that feeds a watchdog every few iterations, see [`Watchdog::feed_every`].
+pub struct FeedEvery<'a, W> {
+    watchdog: &'a mut W,
+    iterations: u32,
+    count: u32,
+}
+
+impl<W: Watchdog> FeedEvery<'_, W> {
+    /// Count an iteration, and feed the watchdog if this completes the interval.
+    #[inline]
+    pub fn tick(&mut self) {
+        self.count += 1;
+        if self.count == self.iterations {
+            self.count = 0;
+            self.watchdog.feed();
+        }
+    }
+}
+
+/// Busy-waiting delays that feed a watchdog every `interval_ms` milliseconds.
+pub struct FeedingDelay<'a, W> {
+    delay: Delay,
+    watchdog: &'a mut W,
+    interval_ms: u32,
+}
+
+impl<'a, W: Watchdog> FeedingDelay<'a, W> {
+    /// Wait with `delay`, and feed `watchdog` every `interval_ms` milliseconds while waiting. The
+    /// first feed happens `interval_ms` after the start of a delay.
+    pub fn new(delay: Delay, watchdog: &'a mut W, interval_ms: u32) -> Self {
+        Self {
+            delay,
+            watchdog,
+            interval_ms: interval_ms.max(1),
+        }
+    }
+
+    /// Return the delay and release the watchdog.
+    pub fn release(self) -> Delay {
+        self.delay
+    }
+
+    /// Wait for at least `us` microseconds.
+    pub fn delay_us(&mut self, us: u32) {
+        let interval_us = self.interval_ms.saturating_mul(1000);
+        let mut remaining = us;
+        while remaining >= interval_us {
+            self.delay.delay_us(interval_us);
+            self.watchdog.feed();
+            remaining -= interval_us;
+        }
+        self.delay.delay_us(remaining);
+    }
+
+    /// Wait for at least `ms` milliseconds.
+    pub fn delay_ms(&mut self, ms: u32) {
+        let mut remaining = ms;
+        while remaining >= self.interval_ms {
+            self.delay.delay_ms(self.interval_ms);
+            self.watchdog.feed();
+            remaining -= self.interval_ms;
+        }
+        self.delay.delay_ms(remaining);
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+mod embedded_hal_impl {
+    use super::{FeedingDelay, Watchdog};
+    use embedded_hal::delay::DelayNs;
+
+    impl<W: Watchdog> DelayNs for FeedingDelay<'_, W> {
+        fn delay_ns(&mut self, ns: u32) {
+            FeedingDelay::delay_us(self, ns.div_ceil(1000));
+        }
+
+        fn delay_us(&mut self, us: u32) {
+            FeedingDelay::delay_us(self, us);
+        }
+
+        fn delay_ms(&mut self, ms: u32) {
+            FeedingDelay::delay_ms(self, ms);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Watchdog timer (WDT): Resets the MCU unless it is fed regularly.
+//!
+//! The WDT counts down from its timeout, clocked by PCLKB. [`Wdt::feed`] sets the counter back to
+//! the timeout. If it underflows, or if it is fed outside of the allowed window, the MCU is reset.
+//! The window makes sure that the firmware doesn't feed the watchdog from a loop that runs too
+//! fast, e.g. because it skips its actual work.
+//!
+//! The WDT can only be configured once after a reset. [`Wdt::take`] returns it in the [`Stopped`]
+//! state, [`Wdt::start`] configures and starts it and returns it in the [`Running`] state, which
+//! is the only one that can be fed. A function that takes a `&mut Wdt<Running>` thus knows that
+//! the watchdog is running.
+//!
+//! If the option bytes in flash (OFS0) select the auto-start mode, the WDT runs from the reset on
+//! with the settings in OFS0, and the configuration passed to [`Wdt::start`] is ignored. The
+//! option bytes are in the first 16 KB of flash, together with the bootloader, so they can't be
+//! changed by programs built with this crate.
+//!
+//! With [`Config::with_nmi`], an underflow or refresh error raises a non-maskable interrupt first,
+//! which gives the firmware a chance to save its state before the reset, see [`super::nmi`].
+//!
+//! For details on the registers, see Renesas RA4M1 Group User's Manual: Hardware, chapter
+//! "Watchdog Timer (WDT)".
+//! <https://cdn.sparkfun.com/assets/b/1/d/3/6/RA4M1_Datasheet.pdf>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::wdt::{Config, Wdt};
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let mut wdt = Wdt::take().unwrap().start(&Config::with_timeout_ms(&clocks, 1000));
+//! loop {
+//!     // Do some work, then:
+//!     wdt.feed();
+//! }
+//! ```
+
+use super::clocks::Clocks;
+use crate::interrupt;
+
+use core::marker::PhantomData;
+
+/// Division of PCLKB for the counter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Divider {
+    Div4 = 0b0001,
+    Div64 = 0b0100,
+    Div128 = 0b1111,
+    Div512 = 0b0110,
+    Div2048 = 0b0111,
+    Div8192 = 0b1000,
+}
+
+impl Divider {
+    const ALL: [Divider; 6] = [
+        Divider::Div4,
+        Divider::Div64,
+        Divider::Div128,
+        Divider::Div512,
+        Divider::Div2048,
+        Divider::Div8192,
+    ];
+
+    fn value(self) -> u32 {
+        match self {
+            Divider::Div4 => 4,
+            Divider::Div64 => 64,
+            Divider::Div128 => 128,
+            Divider::Div512 => 512,
+            Divider::Div2048 => 2048,
+            Divider::Div8192 => 8192,
+        }
+    }
+}
+
+/// Number of counter cycles until the counter underflows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Period {
+    Cycles1024 = 0b00,
+    Cycles4096 = 0b01,
+    Cycles8192 = 0b10,
+    Cycles16384 = 0b11,
+}
+
+impl Period {
+    const ALL: [Period; 4] = [
+        Period::Cycles1024,
+        Period::Cycles4096,
+        Period::Cycles8192,
+        Period::Cycles16384,
+    ];
+
+    fn value(self) -> u32 {
+        match self {
+            Period::Cycles1024 => 1024,
+            Period::Cycles4096 => 4096,
+            Period::Cycles8192 => 8192,
+            Period::Cycles16384 => 16384,
+        }
+    }
+}
+
+/// Counter value, in percent of the period, from which on feeding is allowed. The counter starts
+/// at 100% after feeding and counts down to 0%.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WindowStart {
+    /// Feeding is allowed right away.
+    Percent100 = 0b11,
+    Percent75 = 0b10,
+    Percent50 = 0b01,
+    Percent25 = 0b00,
+}
+
+/// Counter value, in percent of the period, after which feeding is no longer allowed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WindowEnd {
+    Percent75 = 0b00,
+    Percent50 = 0b01,
+    Percent25 = 0b10,
+    /// Feeding is allowed until the counter underflows.
+    Percent0 = 0b11,
+}
+
+/// Settings of the WDT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    pub divider: Divider,
+    pub period: Period,
+    pub window_start: WindowStart,
+    pub window_end: WindowEnd,
+    /// Stop the counter while the CPU sleeps.
+    pub stop_in_sleep: bool,
+    /// Raise a non-maskable interrupt instead of resetting the MCU, see [`super::nmi`].
+    pub nmi: bool,
+}
+
+impl Config {
+    /// The shortest timeout of at least `ms` milliseconds with PCLKB of `clocks`, or the longest
+    /// possible timeout (about 5.6 s at 24 MHz) if `ms` is longer. Feeding is allowed at any time.
+    pub fn with_timeout_ms(clocks: &Clocks, ms: u32) -> Self {
+        let cycles = (ms as u64 * clocks.pclkb() as u64).div_ceil(1000);
+        let settings = Divider::ALL
+            .into_iter()
+            .flat_map(|divider| Period::ALL.map(|period| (divider, period)))
+            .filter(|(divider, period)| divider.value() as u64 * period.value() as u64 >= cycles);
+        let shortest = settings.min_by_key(|(divider, period)| divider.value() * period.value());
+        let (divider, period) = shortest.unwrap_or((Divider::Div8192, Period::Cycles16384));
+        Self {
+            divider,
+            period,
+            window_start: WindowStart::Percent100,
+            window_end: WindowEnd::Percent0,
+            stop_in_sleep: false,
+            nmi: false,
+        }
+    }
+
+    /// Only allow feeding while the counter is between `start` and `end`.
+    pub fn with_window(self, start: WindowStart, end: WindowEnd) -> Self {
+        Self {
+            window_start: start,
+            window_end: end,
+            ..self
+        }
+    }
+
+    /// Raise a non-maskable interrupt instead of resetting the MCU. The handler set with
+    /// [`super::nmi::set_handler`] for [`super::nmi::Source::Wdt`] runs before the reset.
+    pub fn with_nmi(self) -> Self {
+        Self { nmi: true, ..self }
+    }
+
+    /// Returns the timeout in milliseconds with PCLKB of `clocks`.
+    pub fn timeout_ms(&self, clocks: &Clocks) -> u32 {
+        (self.divider.value() as u64 * self.period.value() as u64 * 1000 / clocks.pclkb() as u64)
+            as u32
+    }
+}
+
+/// Type-state of a WDT that hasn't been started.
+pub struct Stopped;
+
+/// Type-state of a running WDT.
+pub struct Running;
+
+/// The watchdog timer, in the state `S`.
+pub struct Wdt<S> {
+    _state: PhantomData<S>,
+}
+
+impl<S> Wdt<S> {
+    const BASE_ADDRESS: u32 = 0x40044200;
+
+    /// WDT Refresh Register. Writing 0x00 and then 0xff feeds the watchdog, and starts it in
+    /// register start mode.
+    const WDTRR: *mut u8 = Self::BASE_ADDRESS as *mut u8;
+
+    /// WDT Control Register. Can only be written once after a reset, before the WDT is started.
+    /// * b0-b1: TOPS, the period, see [`Period`].
+    /// * b4-b7: CKS, the clock divider, see [`Divider`].
+    /// * b8-b9: RPES, the end of the window, see [`WindowEnd`].
+    /// * b12-b13: RPSS, the start of the window, see [`WindowStart`].
+    const WDTCR: *mut u16 = (Self::BASE_ADDRESS + 0x02) as *mut u16;
+
+    /// WDT Status Register. b0-b13: CNTVAL, the counter.
+    const WDTSR: *mut u16 = (Self::BASE_ADDRESS + 0x04) as *mut u16;
+
+    /// WDT Reset Control Register. b7: RSTIRQS, reset instead of a non-maskable interrupt. Can only
+    /// be written once after a reset.
+    const WDTRCR: *mut u8 = (Self::BASE_ADDRESS + 0x06) as *mut u8;
+
+    /// WDT Count Stop Control Register. b7: SLCSTP, stop counting in sleep modes. Can only be
+    /// written once after a reset.
+    const WDTCSTPR: *mut u8 = (Self::BASE_ADDRESS + 0x08) as *mut u8;
+
+    /// Option Function Select Register 0 in the option-setting memory of the flash.
+    /// * b17: WDTSTRT, 0 if the WDT starts automatically after reset with the settings in OFS0.
+    const OFS0: *const u32 = 0x00000400 as *const u32;
+}
+
+impl Wdt<Stopped> {
+    /// Returns the WDT, unless it was taken already.
+    pub fn take() -> Option<Self> {
+        static mut TAKEN: bool = false;
+        interrupt::free(|| unsafe {
+            if TAKEN {
+                None
+            } else {
+                TAKEN = true;
+                Some(Self {
+                    _state: PhantomData,
+                })
+            }
+        })
+    }
+
+    /// Returns true if the option bytes select the auto-start mode, in which the WDT runs from
+    /// reset on with the settings in OFS0.
+    pub fn is_auto_started(&self) -> bool {
+        unsafe { Self::OFS0.read_volatile() & (1 << 17) == 0 }
+    }
+
+    /// Configure the WDT with `config` and start it. In auto-start mode, `config` is ignored.
+    pub fn start(self, config: &Config) -> Wdt<Running> {
+        if !self.is_auto_started() {
+            unsafe {
+                Self::WDTCR.write_volatile(
+                    ((config.window_start as u16) << 12)
+                        | ((config.window_end as u16) << 8)
+                        | ((config.divider as u16) << 4)
+                        | config.period as u16,
+                );
+                Self::WDTRCR.write_volatile((!config.nmi as u8) << 7);
+                Self::WDTCSTPR.write_volatile((config.stop_in_sleep as u8) << 7);
+            }
+        }
+        let mut wdt = Wdt {
+            _state: PhantomData,
+        };
+        // The first refresh starts the counter in register start mode.
+        wdt.feed();
+        wdt
+    }
+}
+
+impl Wdt<Running> {
+    /// Set the counter back to the timeout. If the window is restricted, this must only be called
+    /// while the counter is inside the window, otherwise the MCU is reset.
+    #[inline]
+    pub fn feed(&mut self) {
+        unsafe {
+            Self::WDTRR.write_volatile(0x00);
+            Self::WDTRR.write_volatile(0xff);
+        }
+    }
+
+    /// Returns the current value of the counter, which counts down to 0.
+    pub fn counter(&self) -> u16 {
+        unsafe { Self::WDTSR.read_volatile() & 0x3fff }
+    }
+}
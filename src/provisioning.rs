@@ -0,0 +1,383 @@
+//! Setting up the WiFi credentials of a device without building them into the firmware.
+//!
+//! [`connect`] joins the network stored in the [`KvStore`]. On the first boot, or if that fails,
+//! it opens an access point with a configuration page instead, see [`provision`]: A phone or
+//! laptop joins the access point and opens `http://192.168.4.1/`, the default address of the
+//! access point, in a browser. The page has a form for the SSID and the passphrase of the
+//! network, which are stored when it is sent. Then the access point is closed and the station
+//! joins the network.
+//!
+//! Instead of the form, the credentials can be posted by a script, e.g. with
+//! `curl -d ssid=my+network -d passphrase=secret http://192.168.4.1/`. The page answers with
+//! status 200 when they were stored, 400 when they are invalid.
+//!
+//! The credentials are stored under the keys [`SSID_KEY`] and [`PASSPHRASE_KEY`], so the other
+//! keys of the store remain free for the application.
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::kv_store::KvStore;
+//! use arduino_uno_r4_wifi_rt::peripherals::flash::DataFlash;
+//! use arduino_uno_r4_wifi_rt::provisioning;
+//! use arduino_uno_r4_wifi_rt::wifi::AccessPointConfig;
+//!
+//! // With `clocks` and `at` set up as in the `wifi` example.
+//! let flash = DataFlash::take(&clocks).unwrap();
+//! let mut store = KvStore::new(flash, 6..8).unwrap();
+//! let setup = AccessPointConfig::new("uno-r4-setup", "");
+//! let credentials = provisioning::connect(&mut at, &mut store, &setup).unwrap();
+//! ```
+
+use crate::at::At;
+use crate::kv_store::{self, KvStore};
+use crate::wifi::{self, AccessPoint, AccessPointConfig, Socket, Stack, Station};
+
+/// Key of the SSID in the store.
+pub const SSID_KEY: u16 = 0xff00;
+
+/// Key of the passphrase in the store.
+pub const PASSPHRASE_KEY: u16 = 0xff01;
+
+/// Maximum length of an SSID.
+const MAX_SSID_LEN: usize = 32;
+
+/// Maximum length of a WPA2 passphrase.
+const MAX_PASSPHRASE_LEN: usize = 64;
+
+/// Port of the configuration page.
+const PORT: u16 = 80;
+
+/// Size of the buffer for a request, head and body.
+const REQUEST_SIZE: usize = 1024;
+
+/// Time between polls of the server and the connections, in ms.
+const POLL_INTERVAL_MS: u32 = 10;
+
+/// Time for receiving a request, in ms.
+const REQUEST_TIMEOUT_MS: u32 = 5000;
+
+/// The configuration page.
+const FORM_PAGE: &str = "<!DOCTYPE html>\
+<html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width\">\
+<title>WiFi setup</title></head><body><h1>WiFi setup</h1>\
+<form method=\"post\" action=\"/\">\
+<p><label>Network<br><input name=\"ssid\" maxlength=\"32\" required></label></p>\
+<p><label>Passphrase<br><input name=\"passphrase\" type=\"password\" maxlength=\"64\"></label></p>\
+<p><button>Connect</button></p></form></body></html>";
+
+/// The answer to valid credentials.
+const SAVED_PAGE: &str = "<!DOCTYPE html>\
+<html><head><meta charset=\"utf-8\"><title>WiFi setup</title></head><body>\
+<h1>Saved</h1><p>The device closes this network and joins yours.</p></body></html>";
+
+/// The answer to invalid credentials.
+const INVALID_PAGE: &str = "<!DOCTYPE html>\
+<html><head><meta charset=\"utf-8\"><title>WiFi setup</title></head><body>\
+<h1>Invalid</h1><p>The network needs 1 to 32 characters, the passphrase none or 8 to 64.</p>\
+<p><a href=\"/\">Back</a></p></body></html>";
+
+/// Errors of the provisioning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Error of the access point, the server or the station.
+    Wifi(wifi::Error),
+    /// The credentials can't be read or stored.
+    Store(kv_store::Error),
+}
+
+impl From<wifi::Error> for Error {
+    fn from(error: wifi::Error) -> Self {
+        Error::Wifi(error)
+    }
+}
+
+impl From<kv_store::Error> for Error {
+    fn from(error: kv_store::Error) -> Self {
+        Error::Store(error)
+    }
+}
+
+/// The SSID and passphrase of a network.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Credentials {
+    ssid: [u8; MAX_SSID_LEN],
+    ssid_len: usize,
+    passphrase: [u8; MAX_PASSPHRASE_LEN],
+    passphrase_len: usize,
+}
+
+impl Credentials {
+    /// The network `ssid` with `passphrase`, empty for an open network. Returns `None` if `ssid`
+    /// isn't 1 to 32 bytes long, or `passphrase` isn't empty or 8 to 64 bytes long.
+    pub fn new(ssid: &str, passphrase: &str) -> Option<Self> {
+        let mut credentials = Self::empty();
+        credentials
+            .ssid
+            .get_mut(..ssid.len())?
+            .copy_from_slice(ssid.as_bytes());
+        credentials.ssid_len = ssid.len();
+        credentials
+            .passphrase
+            .get_mut(..passphrase.len())?
+            .copy_from_slice(passphrase.as_bytes());
+        credentials.passphrase_len = passphrase.len();
+        credentials.is_valid().then_some(credentials)
+    }
+
+    fn empty() -> Self {
+        Self {
+            ssid: [0; MAX_SSID_LEN],
+            ssid_len: 0,
+            passphrase: [0; MAX_PASSPHRASE_LEN],
+            passphrase_len: 0,
+        }
+    }
+
+    /// Returns true if the lengths are accepted by the firmware, and the strings are UTF-8.
+    fn is_valid(&self) -> bool {
+        (1..=MAX_SSID_LEN).contains(&self.ssid_len)
+            && (self.passphrase_len == 0 || (8..=MAX_PASSPHRASE_LEN).contains(&self.passphrase_len))
+            && core::str::from_utf8(&self.ssid[..self.ssid_len]).is_ok()
+            && core::str::from_utf8(&self.passphrase[..self.passphrase_len]).is_ok()
+    }
+
+    /// Returns the SSID.
+    pub fn ssid(&self) -> &str {
+        // Checked by is_valid.
+        core::str::from_utf8(&self.ssid[..self.ssid_len]).unwrap_or_default()
+    }
+
+    /// Returns the passphrase.
+    pub fn passphrase(&self) -> &str {
+        core::str::from_utf8(&self.passphrase[..self.passphrase_len]).unwrap_or_default()
+    }
+
+    /// Returns the credentials in `store`, `None` if there are none or they are invalid.
+    pub fn load(store: &mut KvStore) -> Result<Option<Self>, Error> {
+        let mut credentials = Self::empty();
+        let (Some(ssid_len), Some(passphrase_len)) = (
+            stored_len(store, SSID_KEY, &mut credentials.ssid)?,
+            stored_len(store, PASSPHRASE_KEY, &mut credentials.passphrase)?,
+        ) else {
+            return Ok(None);
+        };
+        credentials.ssid_len = ssid_len;
+        credentials.passphrase_len = passphrase_len;
+        Ok(credentials.is_valid().then_some(credentials))
+    }
+
+    /// Store the credentials in `store`.
+    pub fn save(&self, store: &mut KvStore) -> Result<(), Error> {
+        store.set(SSID_KEY, &self.ssid[..self.ssid_len])?;
+        store.set(PASSPHRASE_KEY, &self.passphrase[..self.passphrase_len])?;
+        Ok(())
+    }
+
+    /// Remove the credentials from `store`, so the next [`connect`] provisions again.
+    pub fn forget(store: &mut KvStore) -> Result<(), Error> {
+        store.remove(SSID_KEY)?;
+        store.remove(PASSPHRASE_KEY)?;
+        Ok(())
+    }
+}
+
+/// Read the value of `key` into `buffer` and return its length, `None` if there is none or it
+/// doesn't fit.
+fn stored_len(store: &mut KvStore, key: u16, buffer: &mut [u8]) -> Result<Option<usize>, Error> {
+    match store.get(key, buffer) {
+        Ok(value) => Ok(value.map(|value| value.len())),
+        Err(kv_store::Error::BufferTooSmall) => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Join the network stored in `store`. If there is none or joining fails, provision the
+/// credentials with an access point set up with `access_point`, and join that network, until
+/// it works. Returns the credentials of the network.
+pub fn connect(
+    at: &mut At,
+    store: &mut KvStore,
+    access_point: &AccessPointConfig,
+) -> Result<Credentials, Error> {
+    let mut stored = Credentials::load(store)?;
+    loop {
+        let credentials = match stored.take() {
+            Some(credentials) => credentials,
+            None => provision(at, store, access_point)?,
+        };
+        match Station::new(at)?.connect(credentials.ssid(), credentials.passphrase()) {
+            Ok(()) => return Ok(credentials),
+            Err(
+                wifi::Error::ConnectTimeout
+                | wifi::Error::WrongPassphrase
+                | wifi::Error::NoNetwork
+                | wifi::Error::ConnectFailed,
+            ) => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// Open an access point set up with `access_point` and serve the configuration page until valid
+/// credentials are sent. Stores them in `store`, closes the access point and returns them.
+pub fn provision(
+    at: &mut At,
+    store: &mut KvStore,
+    access_point: &AccessPointConfig,
+) -> Result<Credentials, Error> {
+    let mut access_point = AccessPoint::new(at, access_point)?;
+    let mut stack = Stack::new(access_point.at())?;
+    stack.listen(PORT)?;
+    let credentials = loop {
+        let Some(socket) = stack.accept()? else {
+            stack.at().delay_ms(POLL_INTERVAL_MS);
+            continue;
+        };
+        let result = serve(&mut stack, &socket);
+        stack.close(socket)?;
+        if let Some(credentials) = result? {
+            break credentials;
+        }
+    };
+    credentials.save(store)?;
+    stack.stop_listening()?;
+    access_point.stop()?;
+    Ok(credentials)
+}
+
+/// Answer the request on `socket`. Returns the credentials if they were posted and valid.
+fn serve(stack: &mut Stack, socket: &Socket) -> Result<Option<Credentials>, Error> {
+    let mut buffer = [0; REQUEST_SIZE];
+    let Some((head, body)) = receive_request(stack, socket, &mut buffer)? else {
+        return Ok(None);
+    };
+    let mut request_line = head.split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+    if path != "/" {
+        respond(stack, socket, "404 Not Found", "")?;
+        return Ok(None);
+    }
+    match method {
+        "GET" => {
+            respond(stack, socket, "200 OK", FORM_PAGE)?;
+            Ok(None)
+        }
+        "POST" => match parse_form(body) {
+            Some(credentials) => {
+                respond(stack, socket, "200 OK", SAVED_PAGE)?;
+                Ok(Some(credentials))
+            }
+            None => {
+                respond(stack, socket, "400 Bad Request", INVALID_PAGE)?;
+                Ok(None)
+            }
+        },
+        _ => {
+            respond(stack, socket, "405 Method Not Allowed", "")?;
+            Ok(None)
+        }
+    }
+}
+
+/// Receive a request into `buffer` and return its head and body. Returns `None` if the client
+/// closed the connection, took too long or sent more than fits.
+fn receive_request<'b>(
+    stack: &mut Stack,
+    socket: &Socket,
+    buffer: &'b mut [u8],
+) -> Result<Option<(&'b str, &'b [u8])>, Error> {
+    let mut len = 0;
+    let mut waited_ms = 0;
+    loop {
+        let count = stack.receive(socket, &mut buffer[len..])?;
+        len += count;
+        if let Some(end) = buffer[..len].windows(4).position(|w| w == b"\r\n\r\n") {
+            let Ok(head) = core::str::from_utf8(&buffer[..end]) else {
+                return Ok(None);
+            };
+            let body_len = content_length(head);
+            if len >= end + 4 + body_len {
+                let (head, rest) = buffer.split_at(end);
+                return Ok(Some((
+                    core::str::from_utf8(head).unwrap_or_default(),
+                    &rest[4..4 + body_len],
+                )));
+            }
+        }
+        if len == buffer.len() || waited_ms >= REQUEST_TIMEOUT_MS {
+            return Ok(None);
+        }
+        if count == 0 {
+            if !stack.is_open(socket)? {
+                return Ok(None);
+            }
+            stack.at().delay_ms(POLL_INTERVAL_MS);
+            waited_ms += POLL_INTERVAL_MS;
+        }
+    }
+}
+
+/// Returns the `Content-Length` of the request head `head`, 0 if it has none.
+fn content_length(head: &str) -> usize {
+    head.split("\r\n")
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Send a response with `status` and the HTML page `page`.
+fn respond(stack: &mut Stack, socket: &Socket, status: &str, page: &str) -> Result<(), Error> {
+    stack.send(socket, b"HTTP/1.1 ")?;
+    stack.send(socket, status.as_bytes())?;
+    stack.send(
+        socket,
+        b"\r\nContent-Type: text/html; charset=utf-8\r\nConnection: close\r\n\r\n",
+    )?;
+    stack.send(socket, page.as_bytes())?;
+    Ok(())
+}
+
+/// Returns the credentials of the form data `body`, `application/x-www-form-urlencoded` with the
+/// fields `ssid` and `passphrase`.
+fn parse_form(body: &[u8]) -> Option<Credentials> {
+    let mut credentials = Credentials::empty();
+    for field in body.split(|&byte| byte == b'&') {
+        let mut parts = field.splitn(2, |&byte| byte == b'=');
+        let name = parts.next()?;
+        let value = parts.next().unwrap_or_default();
+        match name {
+            b"ssid" => credentials.ssid_len = url_decode(value, &mut credentials.ssid)?,
+            b"passphrase" => {
+                credentials.passphrase_len = url_decode(value, &mut credentials.passphrase)?
+            }
+            _ => {}
+        }
+    }
+    credentials.is_valid().then_some(credentials)
+}
+
+/// Decode `value` of form data into `buffer` and return the length, `None` if it doesn't fit or
+/// has an invalid escape.
+fn url_decode(value: &[u8], buffer: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    let mut bytes = value.iter();
+    while let Some(&byte) = bytes.next() {
+        let decoded = match byte {
+            b'+' => b' ',
+            b'%' => {
+                let high = (*bytes.next()? as char).to_digit(16)?;
+                let low = (*bytes.next()? as char).to_digit(16)?;
+                (high << 4 | low) as u8
+            }
+            byte => byte,
+        };
+        *buffer.get_mut(len)? = decoded;
+        len += 1;
+    }
+    Some(len)
+}
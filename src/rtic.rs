@@ -0,0 +1,106 @@
+//! Device definitions for RTIC, so that this crate can be given as the `device` of an RTIC 2 app.
+//!
+//! RTIC needs the [`Interrupt`] enum, whose variants name the 32 external interrupts, and
+//! [`NVIC_PRIO_BITS`]. Hardware tasks bind to a slot of [`crate::peripherals::icu`] by its name,
+//! `IEL0` to `IEL31`, which replaces the entry of the slot in the vector table (see
+//! [`EXTERNAL_INTERRUPTS`](crate::EXTERNAL_INTERRUPTS)). The event of the task is linked to the
+//! slot with [`bind`], and the task must clear the request with [`Slot::clear_request`]. The slots
+//! given as dispatchers for software tasks must be kept from [`icu::attach`] with [`reserve`].
+//!
+//! There is no `Peripherals` struct, the drivers are taken with their own functions, so the app
+//! needs `peripherals = false`. RTIC sets the priorities and enables the interrupts in the NVIC.
+//!
+//! Example:
+//! ```
+//! #[rtic::app(device = arduino_uno_r4_wifi_rt::rtic, peripherals = false, dispatchers = [IEL31])]
+//! mod app {
+//!     use arduino_uno_r4_wifi_rt::peripherals::icu::{Event, Slot};
+//!     use arduino_uno_r4_wifi_rt::rtic::{bind, reserve, Interrupt};
+//!
+//!     #[shared]
+//!     struct Shared {}
+//!
+//!     #[local]
+//!     struct Local {
+//!         slot: Slot,
+//!     }
+//!
+//!     #[init]
+//!     fn init(_: init::Context) -> (Shared, Local) {
+//!         reserve(Interrupt::IEL31);
+//!         let slot = bind(Interrupt::IEL0, Event::Gpt4Overflow).unwrap();
+//!         (Shared {}, Local { slot })
+//!     }
+//!
+//!     #[task(binds = IEL0, local = [slot])]
+//!     fn overflow(cx: overflow::Context) {
+//!         cx.local.slot.clear_request();
+//!     }
+//! }
+//! ```
+
+use crate::peripherals::icu::{self, Event, Slot};
+
+/// Number of priority bits of the NVIC, so there are 16 priority levels.
+pub const NVIC_PRIO_BITS: u8 = 4;
+
+/// The external interrupts, named after the Interrupt Event Link Setting Register (IELSR) of their
+/// slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u16)]
+pub enum Interrupt {
+    IEL0 = 0,
+    IEL1 = 1,
+    IEL2 = 2,
+    IEL3 = 3,
+    IEL4 = 4,
+    IEL5 = 5,
+    IEL6 = 6,
+    IEL7 = 7,
+    IEL8 = 8,
+    IEL9 = 9,
+    IEL10 = 10,
+    IEL11 = 11,
+    IEL12 = 12,
+    IEL13 = 13,
+    IEL14 = 14,
+    IEL15 = 15,
+    IEL16 = 16,
+    IEL17 = 17,
+    IEL18 = 18,
+    IEL19 = 19,
+    IEL20 = 20,
+    IEL21 = 21,
+    IEL22 = 22,
+    IEL23 = 23,
+    IEL24 = 24,
+    IEL25 = 25,
+    IEL26 = 26,
+    IEL27 = 27,
+    IEL28 = 28,
+    IEL29 = 29,
+    IEL30 = 30,
+    IEL31 = 31,
+}
+
+unsafe impl cortex_m::interrupt::InterruptNumber for Interrupt {
+    #[inline]
+    fn number(self) -> u16 {
+        self as u16
+    }
+}
+
+/// Link `event` to the slot of `interrupt`, for a hardware task bound to it, see [`icu::link`].
+///
+/// Returns `None` if the slot is in use.
+pub fn bind(interrupt: Interrupt, event: Event) -> Option<Slot> {
+    icu::link(interrupt as u8, event)
+}
+
+/// Keep the slot of `interrupt` free for a dispatcher, see [`icu::reserve`].
+///
+/// Returns false if the slot is in use.
+pub fn reserve(interrupt: Interrupt) -> bool {
+    icu::reserve(interrupt as u8)
+}
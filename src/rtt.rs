@@ -0,0 +1,373 @@
+//! Real-Time Transfer (RTT): Debug output and a byte console over the SWD probe, without a UART.
+//!
+//! RTT keeps ring buffers in RAM, which the debug probe reads and writes while the program runs.
+//! They are described by a control block, the static `_SEGGER_RTT`, which probe-rs, the SEGGER
+//! J-Link software and OpenOCD find by its ID or its symbol. Up channels carry data to the host,
+//! down channels data from it.
+//!
+//! [`take`] returns the terminal channels, up and down channel 0. [`UpChannel::write`] never waits
+//! by default, and drops what doesn't fit when no probe reads the buffer, see [`ChannelMode`].
+//! [`UpChannel`] also implements [`core::fmt::Write`]. [`DownChannel::read`] returns what the host
+//! has sent so far.
+//!
+//! With the `defmt-rtt` feature, up channel 1 ("defmt") carries the frames of a `defmt` global
+//! logger, which is ready without calling [`take`]. Only one `defmt` logger can be enabled, so this
+//! feature can't be combined with `defmt-uart`.
+//!
+//! For details, see the RTT section of the SEGGER J-Link User Guide.
+//! <https://www.segger.com/downloads/jlink/UM08001>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::rtt;
+//! use core::fmt::Write;
+//!
+//! let mut rtt = rtt::take().unwrap();
+//! writeln!(rtt.up, "Hello from the RA4M1").unwrap();
+//! let mut buffer = [0; 16];
+//! let count = rtt.down.read(&mut buffer);
+//! rtt.up.write(&buffer[..count]);
+//! ```
+
+use crate::interrupt;
+
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, AtomicBool, AtomicU32, Ordering};
+
+/// Size of the buffer of the terminal up channel, in bytes.
+pub const UP_BUFFER_SIZE: usize = 1024;
+
+/// Size of the buffer of the terminal down channel, in bytes.
+pub const DOWN_BUFFER_SIZE: usize = 16;
+
+/// Size of the buffer of the `defmt` up channel, in bytes.
+#[cfg(feature = "defmt-rtt")]
+const DEFMT_BUFFER_SIZE: usize = 1024;
+
+#[cfg(not(feature = "defmt-rtt"))]
+const UP_CHANNELS: usize = 1;
+#[cfg(feature = "defmt-rtt")]
+const UP_CHANNELS: usize = 2;
+
+const DOWN_CHANNELS: usize = 1;
+
+/// ID at the start of the control block, which the probe searches the RAM for.
+const ID: &[u8; 16] = b"SEGGER RTT\0\0\0\0\0\0";
+
+/// What [`UpChannel::write`] does when the buffer is full, because the host doesn't read it fast
+/// enough or there is no probe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChannelMode {
+    /// Drop the whole write if it doesn't fit.
+    NoBlockSkip = 0,
+    /// Write what fits and drop the rest. The default.
+    NoBlockTrim = 1,
+    /// Wait until the host has read enough. Hangs without a probe.
+    BlockIfFull = 2,
+}
+
+/// Description of one ring buffer, as the probe expects it.
+#[repr(C)]
+struct Buffer {
+    /// Name of the channel, a NUL-terminated string.
+    name: *const u8,
+    buffer: *mut u8,
+    size: u32,
+    /// Offset of the next byte to write. Written by the target for up channels.
+    write: AtomicU32,
+    /// Offset of the next byte to read. Written by the host for up channels.
+    read: AtomicU32,
+    /// * b0-b1: The [`ChannelMode`], for up channels.
+    flags: AtomicU32,
+}
+
+impl Buffer {
+    const fn empty() -> Self {
+        Self {
+            name: ptr::null(),
+            buffer: ptr::null_mut(),
+            size: 0,
+            write: AtomicU32::new(0),
+            read: AtomicU32::new(0),
+            flags: AtomicU32::new(0),
+        }
+    }
+}
+
+/// The control block, as the probe expects it.
+#[repr(C)]
+struct ControlBlock {
+    id: [u8; 16],
+    max_up_channels: u32,
+    max_down_channels: u32,
+    up: [Buffer; UP_CHANNELS],
+    down: [Buffer; DOWN_CHANNELS],
+}
+
+#[no_mangle]
+static mut _SEGGER_RTT: ControlBlock = ControlBlock {
+    id: [0; 16],
+    max_up_channels: 0,
+    max_down_channels: 0,
+    up: [const { Buffer::empty() }; UP_CHANNELS],
+    down: [const { Buffer::empty() }; DOWN_CHANNELS],
+};
+
+static mut UP_BUFFER: [u8; UP_BUFFER_SIZE] = [0; UP_BUFFER_SIZE];
+static mut DOWN_BUFFER: [u8; DOWN_BUFFER_SIZE] = [0; DOWN_BUFFER_SIZE];
+#[cfg(feature = "defmt-rtt")]
+static mut DEFMT_BUFFER: [u8; DEFMT_BUFFER_SIZE] = [0; DEFMT_BUFFER_SIZE];
+
+/// Set when the control block is filled in.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Set when the terminal channels are taken.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Fill in the control block, if it isn't yet.
+fn initialize() {
+    interrupt::free(|| unsafe {
+        if INITIALIZED.load(Ordering::Acquire) {
+            return;
+        }
+        let block = ptr::addr_of_mut!(_SEGGER_RTT);
+        (*block).max_up_channels = UP_CHANNELS as u32;
+        (*block).max_down_channels = DOWN_CHANNELS as u32;
+        (*block).up[0] = Buffer {
+            name: c"Terminal".as_ptr().cast(),
+            buffer: ptr::addr_of_mut!(UP_BUFFER).cast(),
+            size: UP_BUFFER_SIZE as u32,
+            flags: AtomicU32::new(ChannelMode::NoBlockTrim as u32),
+            ..Buffer::empty()
+        };
+        #[cfg(feature = "defmt-rtt")]
+        {
+            (*block).up[1] = Buffer {
+                name: c"defmt".as_ptr().cast(),
+                buffer: ptr::addr_of_mut!(DEFMT_BUFFER).cast(),
+                size: DEFMT_BUFFER_SIZE as u32,
+                flags: AtomicU32::new(ChannelMode::NoBlockTrim as u32),
+                ..Buffer::empty()
+            };
+        }
+        (*block).down[0] = Buffer {
+            name: c"Terminal".as_ptr().cast(),
+            buffer: ptr::addr_of_mut!(DOWN_BUFFER).cast(),
+            size: DOWN_BUFFER_SIZE as u32,
+            ..Buffer::empty()
+        };
+        // Write the ID last and backwards, so the probe doesn't find a block that is only partly
+        // filled in.
+        compiler_fence(Ordering::SeqCst);
+        let id = ptr::addr_of_mut!((*block).id).cast::<u8>();
+        for (i, &byte) in ID.iter().enumerate().rev() {
+            id.add(i).write_volatile(byte);
+        }
+        INITIALIZED.store(true, Ordering::Release);
+    });
+}
+
+/// The terminal channels.
+pub struct Channels {
+    pub up: UpChannel,
+    pub down: DownChannel,
+}
+
+/// Set up the control block and return the terminal channels. Returns `None` if they were taken
+/// before.
+pub fn take() -> Option<Channels> {
+    if TAKEN.swap(true, Ordering::AcqRel) {
+        return None;
+    }
+    initialize();
+    Some(Channels {
+        up: UpChannel { index: 0 },
+        down: DownChannel { index: 0 },
+    })
+}
+
+/// A channel to the host.
+pub struct UpChannel {
+    index: usize,
+}
+
+impl UpChannel {
+    fn buffer(&self) -> *const Buffer {
+        unsafe { ptr::addr_of!(_SEGGER_RTT.up[self.index]) }
+    }
+
+    /// Set what [`UpChannel::write`] does when the buffer is full.
+    pub fn set_mode(&mut self, mode: ChannelMode) {
+        unsafe { (*self.buffer()).flags.store(mode as u32, Ordering::Relaxed) };
+    }
+
+    /// Returns the current mode, which the host can change too.
+    pub fn mode(&self) -> ChannelMode {
+        match unsafe { (*self.buffer()).flags.load(Ordering::Relaxed) } & 0b11 {
+            0 => ChannelMode::NoBlockSkip,
+            2 => ChannelMode::BlockIfFull,
+            _ => ChannelMode::NoBlockTrim,
+        }
+    }
+
+    /// Write `bytes` to the buffer, depending on the [`ChannelMode`]. Returns the number of bytes
+    /// written.
+    pub fn write(&mut self, bytes: &[u8]) -> usize {
+        unsafe { write(self.buffer(), self.mode(), bytes) }
+    }
+}
+
+impl fmt::Write for UpChannel {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Write `bytes` to the up channel `buffer` in `mode`.
+///
+/// # Safety
+///
+/// `buffer` must point to an up channel of the control block, and only one writer at a time may
+/// use it.
+unsafe fn write(buffer: *const Buffer, mode: ChannelMode, bytes: &[u8]) -> usize {
+    let buffer = &*buffer;
+    let size = buffer.size as usize;
+    let free = || {
+        let read = buffer.read.load(Ordering::Acquire) as usize;
+        let write = buffer.write.load(Ordering::Relaxed) as usize;
+        (read + size - write - 1) % size
+    };
+    if mode == ChannelMode::NoBlockSkip && free() < bytes.len() {
+        return 0;
+    }
+    let mut written = 0;
+    while written < bytes.len() {
+        let read = buffer.read.load(Ordering::Acquire) as usize;
+        let write = buffer.write.load(Ordering::Relaxed) as usize;
+        // Free bytes up to the end of the buffer, one is kept empty to tell full from empty.
+        let contiguous = if read > write {
+            read - write - 1
+        } else if read == 0 {
+            size - write - 1
+        } else {
+            size - write
+        };
+        if contiguous == 0 {
+            if mode == ChannelMode::BlockIfFull {
+                continue;
+            }
+            break;
+        }
+        let count = contiguous.min(bytes.len() - written);
+        ptr::copy_nonoverlapping(bytes.as_ptr().add(written), buffer.buffer.add(write), count);
+        buffer
+            .write
+            .store(((write + count) % size) as u32, Ordering::Release);
+        written += count;
+    }
+    written
+}
+
+/// A channel from the host.
+pub struct DownChannel {
+    index: usize,
+}
+
+impl DownChannel {
+    /// Move the bytes that the host has sent to `buffer`, without waiting. Returns the number of
+    /// bytes read.
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        let channel = unsafe { &*ptr::addr_of!(_SEGGER_RTT.down[self.index]) };
+        let size = channel.size as usize;
+        let mut count = 0;
+        while count < buffer.len() {
+            let write = channel.write.load(Ordering::Acquire) as usize;
+            let read = channel.read.load(Ordering::Relaxed) as usize;
+            let available = if write >= read {
+                write - read
+            } else {
+                size - read
+            };
+            if available == 0 {
+                break;
+            }
+            let chunk = available.min(buffer.len() - count);
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    channel.buffer.add(read),
+                    buffer.as_mut_ptr().add(count),
+                    chunk,
+                );
+            }
+            channel
+                .read
+                .store(((read + chunk) % size) as u32, Ordering::Release);
+            count += chunk;
+        }
+        count
+    }
+}
+
+#[cfg(feature = "defmt-rtt")]
+mod logger {
+    use super::{initialize, write, ChannelMode, _SEGGER_RTT};
+    use crate::interrupt;
+
+    use core::ptr;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    /// Set while a frame is written.
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+
+    /// Whether interrupts were enabled before the frame that is written.
+    static mut RESTORE_INTERRUPTS: bool = false;
+
+    static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+
+    /// Write to the `defmt` channel. Interrupts are disabled, so there is one writer.
+    fn send(bytes: &[u8]) {
+        unsafe {
+            let buffer = ptr::addr_of!(_SEGGER_RTT.up[1]);
+            let mode = match (*buffer).flags.load(Ordering::Relaxed) & 0b11 {
+                2 => ChannelMode::BlockIfFull,
+                _ => ChannelMode::NoBlockTrim,
+            };
+            write(buffer, mode, bytes);
+        }
+    }
+
+    #[defmt::global_logger]
+    struct Logger;
+
+    unsafe impl defmt::Logger for Logger {
+        fn acquire() {
+            let enabled = interrupt::is_enabled();
+            interrupt::disable();
+            if TAKEN.swap(true, Ordering::Acquire) {
+                panic!("defmt logger taken reentrantly");
+            }
+            initialize();
+            unsafe {
+                RESTORE_INTERRUPTS = enabled;
+                (*ptr::addr_of_mut!(ENCODER)).start_frame(send);
+            }
+        }
+
+        unsafe fn flush() {}
+
+        unsafe fn release() {
+            (*ptr::addr_of_mut!(ENCODER)).end_frame(send);
+            TAKEN.store(false, Ordering::Release);
+            if RESTORE_INTERRUPTS {
+                interrupt::enable();
+            }
+        }
+
+        unsafe fn write(bytes: &[u8]) {
+            (*ptr::addr_of_mut!(ENCODER)).write(bytes, send);
+        }
+    }
+}
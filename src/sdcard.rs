@@ -0,0 +1,126 @@
+//! SD cards on the SPI bus, using the `embedded-sdmmc` crate for the card protocol and the FAT
+//! file system.
+//!
+//! [`open`] takes the SPI driver, an output pin for the chip select of the card and a delay (e.g.
+//! [`crate::peripherals::systick::Delay`]). It wakes the card up at 400 kHz, initializes it with a
+//! few retries and then switches to the fastest bit rate that the card and the SPI unit support.
+//! The returned [`SdCard`] can be handed to `embedded_sdmmc::VolumeManager`.
+//!
+//! Requires the `embedded-sdmmc` feature.
+//!
+//! For the SPI mode of SD cards, see the SD Association's Physical Layer Simplified Specification,
+//! chapter 7.
+//! <https://www.sdcard.org/downloads/pls/>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::{get_pins, Pin};
+//! use arduino_uno_r4_wifi_rt::peripherals::spi::{DataMode, Spi};
+//! use arduino_uno_r4_wifi_rt::peripherals::systick::{Delay, SysTick};
+//! use arduino_uno_r4_wifi_rt::sdcard;
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let (sck, miso, mosi) = (pins.d13, pins.d12, pins.d11);
+//! let spi = Spi::new(sck, miso, mosi, mstp::token(), &clocks, 400_000, DataMode::Mode0);
+//! let delay = Delay::new(SysTick::instance().unwrap(), &clocks);
+//! let card = sdcard::open(spi, pins.d10.into_output(), delay).unwrap();
+//! let size = card.num_bytes().unwrap();
+//! ```
+
+use crate::peripherals::pins::OutputPin;
+use crate::peripherals::spi::{Blocking, Spi};
+
+use core::convert::Infallible;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{self, Operation};
+
+/// Bit rate while the card is initialized. Cards must accept 100-400 kHz.
+const INIT_FREQUENCY: u32 = 400_000;
+
+/// Fastest bit rate in the default speed mode of SD cards.
+const MAX_FREQUENCY: u32 = 25_000_000;
+
+/// How often the initialization is tried before giving up. Some cards need more than one attempt
+/// right after power-up.
+const INIT_ATTEMPTS: u32 = 3;
+
+/// An SD card on the SPI bus.
+pub type SdCard<CS, D> = embedded_sdmmc::SdCard<SdSpi<CS>, D>;
+
+/// The error type of [`SdCard`].
+pub use embedded_sdmmc::SdCardError as Error;
+
+/// The SPI driver and the chip select pin of the card, used as an `embedded_hal::spi::SpiDevice`.
+pub struct SdSpi<CS> {
+    spi: Spi<Blocking>,
+    cs: CS,
+}
+
+impl<CS: OutputPin> SdSpi<CS> {
+    /// Send at least 74 clock cycles with the chip select high, which puts the card into SPI mode
+    /// after power-up.
+    fn wake_up(&mut self) {
+        self.cs.set_high();
+        self.spi.write(&[0xffu8; 10]);
+    }
+}
+
+impl<CS> spi::ErrorType for SdSpi<CS> {
+    type Error = Infallible;
+}
+
+impl<CS: OutputPin> spi::SpiDevice for SdSpi<CS> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        self.cs.set_low();
+        for operation in operations {
+            match operation {
+                Operation::Read(buffer) => self.spi.read(buffer),
+                Operation::Write(bytes) => self.spi.write(bytes),
+                Operation::Transfer(buffer, bytes) => self.spi.transfer(buffer, bytes),
+                Operation::TransferInPlace(buffer) => self.spi.transfer_in_place(buffer),
+                Operation::DelayNs(ns) => {
+                    // Each iteration takes at least one cycle of the 48 MHz CPU clock.
+                    for _ in 0..ns.div_ceil(20) {
+                        core::hint::spin_loop();
+                    }
+                }
+            }
+        }
+        self.cs.set_high();
+        Ok(())
+    }
+}
+
+/// Initialize the SD card on `spi` with the chip select pin `cs`, and switch to the fastest bit
+/// rate it supports.
+///
+/// The bit rate of `spi` is changed, its other settings must be the defaults of [`Spi::new`] with
+/// [`crate::peripherals::spi::DataMode::Mode0`].
+pub fn open<CS: OutputPin, D: DelayNs>(
+    mut spi: Spi<Blocking>,
+    cs: CS,
+    delay: D,
+) -> Result<SdCard<CS, D>, Error> {
+    spi.set_frequency(INIT_FREQUENCY);
+    let mut device = SdSpi { spi, cs };
+    device.wake_up();
+    let card = embedded_sdmmc::SdCard::new(device, delay);
+    let mut attempt = 1;
+    // The card is initialized by the first access.
+    while let Err(error) = card.num_bytes() {
+        if attempt == INIT_ATTEMPTS {
+            return Err(error);
+        }
+        attempt += 1;
+        card.mark_card_uninit();
+        card.spi(SdSpi::wake_up);
+    }
+    card.spi(|device| {
+        let frequency = MAX_FREQUENCY.min(device.spi.max_frequency());
+        device.spi.set_frequency(frequency);
+    });
+    Ok(card)
+}
@@ -0,0 +1,138 @@
+//! Semihosting: Output on the host and exit codes, through the debugger or QEMU.
+//!
+//! With semihosting, the program asks the debugger to do I/O for it: It executes `bkpt 0xab` with
+//! an operation number in r0 and a parameter in r1, the debugger carries out the operation and lets
+//! the program continue. This needs no peripheral at all, so the startup code of the crate and
+//! modules like [`crate::math`] can be tried out under QEMU (`-semihosting`) or on the board with
+//! a debugger that has semihosting enabled (e.g. `arm semihosting enable` in OpenOCD).
+//!
+//! [`hprint!`](crate::hprint) and [`hprintln!`](crate::hprintln) write to the stdout of the host,
+//! [`exit`] ends the session with an exit code. With this feature, the panic handler prints the
+//! panic message to stderr and exits with code 1.
+//!
+//! Without a debugger attached, `bkpt` raises a HardFault, so semihosting is only for debug runs.
+//!
+//! For details, see the Arm Semihosting Specification.
+//! <https://github.com/ARM-software/abi-aa/blob/main/semihosting/semihosting.rst>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::math::map;
+//! use arduino_uno_r4_wifi_rt::{hprintln, semihosting};
+//!
+//! let value = map(512, 0, 1023, 0, 100);
+//! hprintln!("map: {}", value);
+//! semihosting::exit(if value == 50 { 0 } else { 1 });
+//! ```
+
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+/// Operation numbers.
+const SYS_OPEN: u32 = 0x01;
+const SYS_WRITE: u32 = 0x05;
+const SYS_EXIT: u32 = 0x18;
+
+/// Modes of SYS_OPEN, "w" for stdout and "a" for stderr when opening ":tt".
+const OPEN_MODE_W: u32 = 4;
+const OPEN_MODE_A: u32 = 8;
+
+/// Reasons for SYS_EXIT.
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+const ADP_STOPPED_RUN_TIME_ERROR_UNKNOWN: u32 = 0x20023;
+
+/// Execute the semihosting `operation` with `parameter`, and return the result.
+///
+/// # Safety
+///
+/// `parameter` must be valid for `operation`, e.g. point to the right block of arguments.
+unsafe fn call(operation: u32, parameter: usize) -> u32 {
+    let result;
+    asm!(
+        "bkpt #0xab",
+        inout("r0") operation => result,
+        in("r1") parameter,
+        options(nostack, preserves_flags),
+    );
+    result
+}
+
+/// A file of the host, opened with SYS_OPEN.
+struct HostFile {
+    handle: u32,
+}
+
+impl HostFile {
+    /// Open the console of the host, `:tt`, in `mode`.
+    fn console(mode: u32) -> Option<Self> {
+        let name = b":tt\0";
+        let arguments = [name.as_ptr() as usize, mode as usize, name.len() - 1];
+        let handle = unsafe { call(SYS_OPEN, arguments.as_ptr() as usize) };
+        (handle != u32::MAX).then_some(Self { handle })
+    }
+}
+
+impl Write for HostFile {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let arguments = [self.handle as usize, s.as_ptr() as usize, s.len()];
+        // SYS_WRITE returns the number of bytes that were not written.
+        match unsafe { call(SYS_WRITE, arguments.as_ptr() as usize) } {
+            0 => Ok(()),
+            _ => Err(fmt::Error),
+        }
+    }
+}
+
+/// Write `arguments` to the stdout of the host. Used by [`hprint!`](crate::hprint).
+pub fn write_fmt(arguments: fmt::Arguments<'_>) -> fmt::Result {
+    let mut stdout = HostFile::console(OPEN_MODE_W).ok_or(fmt::Error)?;
+    stdout.write_fmt(arguments)
+}
+
+/// End the session with `code`: 0 for success, anything else for failure. QEMU exits with 0 or 1
+/// then, debuggers stop the program.
+pub fn exit(code: u32) -> ! {
+    request_exit(code);
+    // The debugger may let the program continue.
+    loop {
+        unsafe { asm!("nop", options(nomem, nostack, preserves_flags)) };
+    }
+}
+
+/// Ask the host to end the session with `code`. Returns if the debugger lets the program continue.
+fn request_exit(code: u32) {
+    let reason = match code {
+        0 => ADP_STOPPED_APPLICATION_EXIT,
+        _ => ADP_STOPPED_RUN_TIME_ERROR_UNKNOWN,
+    };
+    unsafe { call(SYS_EXIT, reason as usize) };
+}
+
+/// Print `info` to the stderr of the host and exit with code 1. Called by the panic handler of the
+/// crate, which goes on if the debugger lets the program continue.
+pub(crate) fn report_panic(info: &PanicInfo<'_>) {
+    if let Some(mut stderr) = HostFile::console(OPEN_MODE_A) {
+        let _ = writeln!(stderr, "{info}");
+    }
+    request_exit(1);
+}
+
+#[macro_export]
+/// Print to the stdout of the host with semihosting, like `print!`.
+macro_rules! hprint {
+    ($($arg:tt)*) => {{
+        let _ = $crate::semihosting::write_fmt(format_args!($($arg)*));
+    }};
+}
+
+#[macro_export]
+/// Print to the stdout of the host with semihosting, followed by a newline, like `println!`.
+macro_rules! hprintln {
+    () => {{
+        let _ = $crate::semihosting::write_fmt(format_args!("\n"));
+    }};
+    ($($arg:tt)*) => {{
+        let _ = $crate::semihosting::write_fmt(format_args!("{}\n", format_args!($($arg)*)));
+    }};
+}
@@ -0,0 +1,195 @@
+//! The access point interface of the ESP32-S3 (SoftAP), which opens a network of its own.
+
+use super::{
+    disable_mode, enable_mode, parse_ip, parse_mac, query_ip_config, query_mac, set_ip_config,
+    set_mac, Error, IpConfig, QuotedMac, COMMAND_TIMEOUT_MS, MODE_ACCESS_POINT,
+};
+use crate::at::{self, At, Quoted};
+
+use core::net::Ipv4Addr;
+
+/// Time for starting the access point, in ms.
+const START_TIMEOUT_MS: u32 = 5000;
+
+/// Settings of the access point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config<'a> {
+    /// Name of the network, up to 32 bytes.
+    pub ssid: &'a str,
+    /// WPA2 passphrase of 8 to 64 characters, or empty for an open network.
+    pub passphrase: &'a str,
+    /// WiFi channel, 1-13.
+    pub channel: u8,
+    /// Maximum number of stations, 1-10.
+    pub max_clients: u8,
+    /// Don't broadcast the SSID.
+    pub hidden: bool,
+}
+
+impl<'a> Config<'a> {
+    /// A visible network `ssid` with `passphrase` on channel 1, for up to 4 stations.
+    pub fn new(ssid: &'a str, passphrase: &'a str) -> Self {
+        Self {
+            ssid,
+            passphrase,
+            channel: 1,
+            max_clients: 4,
+            hidden: false,
+        }
+    }
+
+    /// Set the WiFi channel.
+    pub fn with_channel(self, channel: u8) -> Self {
+        Self { channel, ..self }
+    }
+
+    /// Set the maximum number of stations.
+    pub fn with_max_clients(self, max_clients: u8) -> Self {
+        Self {
+            max_clients,
+            ..self
+        }
+    }
+
+    /// Hide the SSID.
+    pub fn with_hidden(self, hidden: bool) -> Self {
+        Self { hidden, ..self }
+    }
+
+    /// Returns true if the firmware accepts the settings.
+    fn is_valid(&self) -> bool {
+        (1..=32).contains(&self.ssid.len())
+            && (self.passphrase.is_empty() || (8..=64).contains(&self.passphrase.len()))
+            && (1..=13).contains(&self.channel)
+            && (1..=10).contains(&self.max_clients)
+    }
+}
+
+/// A station connected to the access point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Client {
+    /// The address the DHCP server of the access point gave the station.
+    pub address: Ipv4Addr,
+    pub mac: [u8; 6],
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self {
+            address: Ipv4Addr::UNSPECIFIED,
+            mac: [0; 6],
+        }
+    }
+}
+
+/// The access point interface.
+pub struct AccessPoint<'a> {
+    at: &'a mut At,
+}
+
+impl<'a> AccessPoint<'a> {
+    /// Enable the access point interface with `config`, keeping the station if it is enabled.
+    pub fn new(at: &'a mut At, config: &Config) -> Result<Self, Error> {
+        if !config.is_valid() {
+            return Err(Error::InvalidConfig);
+        }
+        enable_mode(at, MODE_ACCESS_POINT)?;
+        let mut access_point = Self { at };
+        access_point.configure(config)?;
+        Ok(access_point)
+    }
+
+    /// Returns the AT engine, e.g. for a [`Stack`](super::Stack) over the network.
+    pub fn at(&mut self) -> &mut At {
+        self.at
+    }
+
+    /// Change the settings. Connected stations are dropped.
+    pub fn configure(&mut self, config: &Config) -> Result<(), Error> {
+        if !config.is_valid() {
+            return Err(Error::InvalidConfig);
+        }
+        // Security 0 is an open network, 3 WPA2-PSK.
+        let security = if config.passphrase.is_empty() { 0 } else { 3 };
+        self.at.execute(
+            format_args!(
+                "AT+CWSAP={},{},{},{},{},{}",
+                Quoted(config.ssid),
+                Quoted(config.passphrase),
+                config.channel,
+                security,
+                config.max_clients,
+                config.hidden as u8
+            ),
+            START_TIMEOUT_MS,
+        )?;
+        Ok(())
+    }
+
+    /// Store the connected stations in `clients` and return how many, up to the length of
+    /// `clients`.
+    pub fn clients(&mut self, clients: &mut [Client]) -> Result<usize, Error> {
+        let mut count = 0;
+        let mut invalid = false;
+        self.at
+            .query(format_args!("AT+CWLIF"), COMMAND_TIMEOUT_MS, |line| {
+                let Some(parameters) = line.strip_prefix(b"+CWLIF:") else {
+                    return;
+                };
+                let mut fields = at::fields(parameters);
+                let address = fields.next().and_then(parse_ip);
+                let mac = fields.next().and_then(parse_mac);
+                match (address, mac) {
+                    (Some(address), Some(mac)) => {
+                        if let Some(slot) = clients.get_mut(count) {
+                            *slot = Client { address, mac };
+                            count += 1;
+                        }
+                    }
+                    _ => invalid = true,
+                }
+            })?;
+        if invalid {
+            return Err(Error::InvalidResponse);
+        }
+        Ok(count)
+    }
+
+    /// Drop the connection of the station with `mac`.
+    pub fn disconnect_client(&mut self, mac: [u8; 6]) -> Result<(), Error> {
+        self.at.execute(
+            format_args!("AT+CWQIF={}", QuotedMac(mac)),
+            COMMAND_TIMEOUT_MS,
+        )?;
+        Ok(())
+    }
+
+    /// Returns the IPv4 address, gateway and netmask of the access point.
+    pub fn ip_config(&mut self) -> Result<IpConfig, Error> {
+        query_ip_config(self.at, "AT+CIPAP?", b"+CIPAP:")
+    }
+
+    /// Set the IPv4 address, gateway and netmask of the access point. Its DHCP server hands out
+    /// addresses from the same subnet.
+    pub fn set_ip_config(&mut self, config: &IpConfig) -> Result<(), Error> {
+        set_ip_config(self.at, "AT+CIPAP", config)
+    }
+
+    /// Returns the MAC address of the access point, its BSSID.
+    pub fn mac(&mut self) -> Result<[u8; 6], Error> {
+        query_mac(self.at, "AT+CIPAPMAC?", b"+CIPAPMAC:")
+    }
+
+    /// Set the MAC address of the access point, until the ESP32-S3 is reset. It must differ from
+    /// the MAC address of the station.
+    pub fn set_mac(&mut self, mac: [u8; 6]) -> Result<(), Error> {
+        set_mac(self.at, "AT+CIPAPMAC", mac)
+    }
+
+    /// Close the network and disable the access point interface.
+    pub fn stop(self) -> Result<(), Error> {
+        disable_mode(self.at, MODE_ACCESS_POINT)
+    }
+}
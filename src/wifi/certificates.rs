@@ -0,0 +1,103 @@
+//! Certificates and keys for TLS, stored on the ESP32-S3.
+
+use super::{Error, COMMAND_TIMEOUT_MS};
+use crate::at::{self, At, Quoted};
+
+use core::fmt;
+
+/// Time for storing an item, in ms.
+const STORE_TIMEOUT_MS: u32 = 5000;
+
+/// Type of the data of `AT+SYSMFG`: binary.
+const BINARY: u8 = 8;
+
+/// A kind of item for TLS connections.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Credential {
+    /// A CA certificate, which verifies the server, see
+    /// [`TlsConfig::with_ca_certificate`](super::TlsConfig::with_ca_certificate).
+    CaCertificate,
+    /// A client certificate, which the server verifies, see
+    /// [`TlsConfig::with_client_certificate`](super::TlsConfig::with_client_certificate).
+    ClientCertificate,
+    /// The private key of the client certificate with the same index.
+    ClientKey,
+}
+
+impl Credential {
+    /// Returns the namespace of the items in the manufacturing NVS partition of the firmware.
+    fn namespace(self) -> &'static str {
+        match self {
+            Credential::CaCertificate => "client_ca",
+            Credential::ClientCertificate => "client_cert",
+            Credential::ClientKey => "client_key",
+        }
+    }
+}
+
+/// The key of an item in its namespace, e.g. `client_ca.0`.
+struct ItemKey(Credential, u8);
+
+impl fmt::Display for ItemKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}.{}\"", self.0.namespace(), self.1)
+    }
+}
+
+/// The certificates and keys stored in the flash of the ESP32-S3, which
+/// [`TlsConfig`](super::TlsConfig) selects by index. They stay there through resets, so they
+/// only need to be stored once, e.g. when the device is set up.
+///
+/// A certificate and its key are PEM or DER. The ESP32-S3 doesn't check them when they are
+/// stored, only when they are used for a connection.
+pub struct Certificates<'a> {
+    at: &'a mut At,
+}
+
+impl<'a> Certificates<'a> {
+    /// Manage the certificates and keys with `at`.
+    pub fn new(at: &'a mut At) -> Self {
+        Self { at }
+    }
+
+    /// Store `data` as the item `kind` with `index`, replacing the one that was there.
+    pub fn store(&mut self, kind: Credential, index: u8, data: &[u8]) -> Result<(), Error> {
+        // The TLS library expects PEM with a terminating NUL.
+        let pem = data.starts_with(b"-----BEGIN") && !data.ends_with(&[0]);
+        let terminator: &[u8] = if pem { &[0] } else { &[] };
+        self.at.send_data_parts(
+            format_args!(
+                "AT+SYSMFG=2,{},{},{},{}",
+                Quoted(kind.namespace()),
+                ItemKey(kind, index),
+                BINARY,
+                data.len() + terminator.len()
+            ),
+            &[data, terminator],
+            STORE_TIMEOUT_MS,
+        )?;
+        Ok(())
+    }
+
+    /// Store a client certificate and its private key with `index`.
+    pub fn store_client(&mut self, index: u8, certificate: &[u8], key: &[u8]) -> Result<(), Error> {
+        self.store(Credential::ClientCertificate, index, certificate)?;
+        self.store(Credential::ClientKey, index, key)
+    }
+
+    /// Remove the item `kind` with `index`. Removing one that isn't stored is fine.
+    pub fn remove(&mut self, kind: Credential, index: u8) -> Result<(), Error> {
+        match self.at.execute(
+            format_args!(
+                "AT+SYSMFG=0,{},{}",
+                Quoted(kind.namespace()),
+                ItemKey(kind, index)
+            ),
+            COMMAND_TIMEOUT_MS,
+        ) {
+            Ok(()) | Err(at::Error::Command(_)) => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Keeps the station connected to a network, see [`NetManager`].
+
+use super::{query_ip_config, Error, IpConfig, Station};
+use crate::at::At;
+
+/// Default time before the first reconnection attempt, in ms.
+const DEFAULT_INITIAL_BACKOFF_MS: u32 = 1000;
+
+/// Default maximum time between reconnection attempts, in ms.
+const DEFAULT_MAX_BACKOFF_MS: u32 = 60_000;
+
+/// Space for a `WIFI ...` URC.
+const URC_SIZE: usize = 32;
+
+/// A change of the connection, see [`NetManager::poll`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// The station joined the network.
+    Connected,
+    /// The station got its IPv4 configuration, so connections can be opened.
+    GotIp(IpConfig),
+    /// The station lost the network. It reconnects after the back-off time.
+    Disconnected,
+    /// A connection attempt failed. The next one follows after twice the back-off time.
+    ConnectFailed(Error),
+}
+
+/// State of the connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Not connected, the next attempt follows in this many ms.
+    Offline { retry_in_ms: u32 },
+    /// Joined the network, waiting for the IPv4 configuration.
+    Connected,
+    /// Ready with this IPv4 configuration.
+    Online(IpConfig),
+}
+
+/// Connects the station to a network, and again whenever the connection is lost.
+///
+/// The manager follows the `WIFI CONNECTED`, `WIFI GOT IP` and `WIFI DISCONNECT` URCs of the [`At`]
+/// engine and turns them into [`Event`]s. After a disconnection, it waits for a back-off time
+/// before it reconnects, which doubles with every failed attempt up to a maximum, and starts over
+/// once the station is online again. Other URCs stay in the buffer.
+///
+/// Like the other handles of this module, the manager doesn't keep the engine, so it can be used
+/// for other commands between the calls of [`NetManager::poll`]. There is no time base, so each
+/// call gets the time that passed since the last one.
+///
+/// Example:
+/// ```
+/// use arduino_uno_r4_wifi_rt::wifi::{ManagerEvent, NetManager};
+///
+/// // With `at` set up as in the `wifi` example.
+/// let mut manager = NetManager::new("my network", "secret");
+/// loop {
+///     while let Some(event) = manager.poll(&mut at, 10) {
+///         if let ManagerEvent::GotIp(config) = event {
+///             // Open connections to config.gateway, ...
+///         }
+///     }
+///     // Wait 10 ms or do something else.
+/// }
+/// ```
+pub struct NetManager<'a> {
+    ssid: &'a str,
+    passphrase: &'a str,
+    state: State,
+    backoff_ms: u32,
+    initial_backoff_ms: u32,
+    max_backoff_ms: u32,
+}
+
+impl<'a> NetManager<'a> {
+    /// A manager for the network `ssid` with `passphrase`, which connects with the first
+    /// [`NetManager::poll`]. It waits 1 s before the first reconnection attempt, and up to 60 s
+    /// between later ones.
+    pub fn new(ssid: &'a str, passphrase: &'a str) -> Self {
+        Self {
+            ssid,
+            passphrase,
+            state: State::Offline { retry_in_ms: 0 },
+            backoff_ms: DEFAULT_INITIAL_BACKOFF_MS,
+            initial_backoff_ms: DEFAULT_INITIAL_BACKOFF_MS,
+            max_backoff_ms: DEFAULT_MAX_BACKOFF_MS,
+        }
+    }
+
+    /// Set the time before the first reconnection attempt and the maximum time between
+    /// attempts, in ms.
+    pub fn with_backoff(self, initial_ms: u32, max_ms: u32) -> Self {
+        Self {
+            backoff_ms: initial_ms,
+            initial_backoff_ms: initial_ms,
+            max_backoff_ms: max_ms.max(initial_ms),
+            ..self
+        }
+    }
+
+    /// Returns true if the station has its IPv4 configuration.
+    pub fn is_online(&self) -> bool {
+        matches!(self.state, State::Online(_))
+    }
+
+    /// Returns the IPv4 configuration of the station while it is online.
+    pub fn ip_config(&self) -> Option<IpConfig> {
+        match self.state {
+            State::Online(config) => Some(config),
+            _ => None,
+        }
+    }
+
+    /// Handle the URCs about the connection and reconnect when it is time, `elapsed_ms` ms after
+    /// the last call. Returns the next event, so call it until it returns `None`.
+    ///
+    /// Connecting blocks until the station joined the network or the attempt failed, up to 20 s.
+    pub fn poll(&mut self, at: &mut At, elapsed_ms: u32) -> Option<Event> {
+        if let State::Offline { retry_in_ms } = &mut self.state {
+            *retry_in_ms = retry_in_ms.saturating_sub(elapsed_ms);
+        }
+
+        if at.take_urc_overrun() {
+            // URCs were dropped, so ask for the state instead.
+            if let Some(event) = self.resynchronize(at) {
+                return Some(event);
+            }
+        }
+        let mut buffer = [0; URC_SIZE];
+        if let Some(urc) = at.take_urc(&mut buffer, |head| head.starts_with(b"WIFI ")) {
+            return match urc {
+                b"WIFI CONNECTED" => {
+                    self.state = State::Connected;
+                    Some(Event::Connected)
+                }
+                b"WIFI GOT IP" => self.got_ip(at),
+                b"WIFI DISCONNECT" => self.disconnected(),
+                _ => None,
+            };
+        }
+
+        match self.state {
+            State::Offline { retry_in_ms: 0 } => {
+                let result = Station::new(at)
+                    .and_then(|mut station| station.connect(self.ssid, self.passphrase));
+                match result {
+                    // The URCs of the connection follow.
+                    Ok(()) => {
+                        self.state = State::Connected;
+                        None
+                    }
+                    Err(error) => {
+                        self.retry_later();
+                        Some(Event::ConnectFailed(error))
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Query the IPv4 configuration and go online.
+    fn got_ip(&mut self, at: &mut At) -> Option<Event> {
+        match query_ip_config(at, "AT+CIPSTA?", b"+CIPSTA:") {
+            Ok(config) => {
+                self.state = State::Online(config);
+                self.backoff_ms = self.initial_backoff_ms;
+                Some(Event::GotIp(config))
+            }
+            // Try again with the next call.
+            Err(_) => None,
+        }
+    }
+
+    /// Schedule a reconnection after a disconnection.
+    fn disconnected(&mut self) -> Option<Event> {
+        if matches!(self.state, State::Offline { .. }) {
+            // The failed attempt that caused the URC has scheduled the next one.
+            return None;
+        }
+        self.state = State::Offline {
+            retry_in_ms: self.backoff_ms,
+        };
+        Some(Event::Disconnected)
+    }
+
+    /// Schedule the next attempt after a failed one, with twice the back-off time.
+    fn retry_later(&mut self) {
+        self.state = State::Offline {
+            retry_in_ms: self.backoff_ms,
+        };
+        self.backoff_ms = self.backoff_ms.saturating_mul(2).min(self.max_backoff_ms);
+    }
+
+    /// Compare the state with the one of the station, after URCs were lost.
+    fn resynchronize(&mut self, at: &mut At) -> Option<Event> {
+        let connected = Station::new(at).and_then(|mut station| station.is_connected());
+        match (connected, self.state) {
+            (Ok(false), State::Connected | State::Online(_)) => self.disconnected(),
+            (Ok(true), State::Offline { .. } | State::Connected) => self.got_ip(at),
+            _ => None,
+        }
+    }
+}
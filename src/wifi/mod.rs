@@ -0,0 +1,277 @@
+//! WiFi through the ESP32-S3, with the AT commands of [`crate::at`].
+//!
+//! The handles of this module borrow the [`At`] engine for as long as they are used, and keep no
+//! state of their own, so they can be created again whenever they are needed:
+//! * [`Station`] joins a network: scan, connect, disconnect, signal strength and IP configuration,
+//!   by DHCP with a host name or static, and the MAC address.
+//! * [`AccessPoint`] opens a network of its own (SoftAP), e.g. for setting up a device without a
+//!   router, and lists the stations connected to it.
+//!
+//! Both interfaces can be enabled at the same time. [`NetManager`] keeps the station connected,
+//! reconnects with a back-off time when the network is lost, and reports the changes as events.
+//!
+//! [`Stack`] opens connections over them: TCP, UDP and TLS. TLS connections are checked against
+//! the certificates stored on the ESP32-S3 as set in [`TlsConfig`], and send the host name for
+//! server name indication (SNI), so HTTPS and MQTTS servers behind a shared address can be
+//! reached. It also runs a TCP server, which accepts connections from clients.
+//!
+//! [`Certificates`] stores CA certificates, and client certificates with their keys, on the
+//! ESP32-S3, for servers that authenticate their clients (mutual TLS), like AWS IoT Core or Azure
+//! IoT Hub. Servers with a pre-shared key are reached with [`TlsConfig::with_psk`] instead.
+//!
+//! [`ping`] checks that a host is reachable and measures the round-trip time.
+//!
+//! The ESP32-S3 keeps the connection on its own, it only needs commands to change it.
+//!
+//! For the commands, see the ESP-AT User Guide, "Wi-Fi AT Commands".
+//! <https://docs.espressif.com/projects/esp-at/en/latest/esp32s3/AT_Command_Set/Wi-Fi_AT_Commands.html>
+//!
+//! Example:
+//! ```
+//! use arduino_uno_r4_wifi_rt::at::At;
+//! use arduino_uno_r4_wifi_rt::peripherals::clocks::Cgc;
+//! use arduino_uno_r4_wifi_rt::peripherals::esp32::Esp32;
+//! use arduino_uno_r4_wifi_rt::peripherals::mstp;
+//! use arduino_uno_r4_wifi_rt::peripherals::pins::get_pins;
+//! use arduino_uno_r4_wifi_rt::wifi::{Stack, Station, TlsConfig};
+//!
+//! let clocks = Cgc::take().unwrap().into_clocks();
+//! let pins = get_pins().unwrap();
+//! let mut esp32 = Esp32::new(pins.p109, pins.p110, mstp::token(), &clocks).unwrap();
+//! esp32.reset().unwrap();
+//! let mut at = At::new(esp32, &clocks).unwrap();
+//! let mut station = Station::new(&mut at).unwrap();
+//! station.connect("my network", "secret").unwrap();
+//! let ip = station.ip_config().unwrap().address;
+//!
+//! let mut stack = Stack::new(&mut at).unwrap();
+//! let tls = TlsConfig::default().with_ca_certificate(0);
+//! let socket = stack.connect_tls("example.com", 443, &tls).unwrap();
+//! stack.send(&socket, b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+//! ```
+
+mod access_point;
+mod certificates;
+mod manager;
+mod socket;
+mod station;
+
+pub use access_point::{AccessPoint, Client, Config as AccessPointConfig};
+pub use certificates::{Certificates, Credential};
+pub use manager::{Event as ManagerEvent, NetManager};
+#[cfg(feature = "embedded-nal-async")]
+pub use socket::{AsyncStack, Connection};
+pub use socket::{Protocol, Psk, Socket, Stack, TlsConfig, MAX_SOCKETS};
+pub use station::{Network, Security, Station};
+
+use crate::at::{self, At, Quoted};
+
+use core::fmt;
+use core::net::Ipv4Addr;
+
+/// Errors of the WiFi functions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// Error of the AT command.
+    At(at::Error),
+    /// The network didn't answer in time.
+    ConnectTimeout,
+    /// The passphrase is wrong.
+    WrongPassphrase,
+    /// There is no network with this SSID.
+    NoNetwork,
+    /// The network refused the connection.
+    ConnectFailed,
+    /// The station isn't connected.
+    NotConnected,
+    /// The settings are out of the range the firmware accepts.
+    InvalidConfig,
+    /// All connections are in use.
+    NoFreeSocket,
+    /// The connection was closed, e.g. by the peer.
+    SocketClosed,
+    /// The host didn't answer the ping.
+    NoReply,
+    /// The firmware sent an answer that can't be parsed.
+    InvalidResponse,
+}
+
+impl From<at::Error> for Error {
+    fn from(error: at::Error) -> Self {
+        Error::At(error)
+    }
+}
+
+/// IPv4 configuration of an interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IpConfig {
+    /// The address of the interface, 0.0.0.0 until it got one.
+    pub address: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+}
+
+/// Time for simple commands, in ms.
+const COMMAND_TIMEOUT_MS: u32 = 1000;
+
+/// Time for a ping, including resolving the host name, in ms.
+const PING_TIMEOUT_MS: u32 = 10_000;
+
+/// Modes of `AT+CWMODE`, one bit each for the station and the access point.
+const MODE_STATION: i32 = 1;
+const MODE_ACCESS_POINT: i32 = 2;
+
+/// Ping `host`, a name or an IP address, and return the round-trip time in ms.
+pub fn ping(at: &mut At, host: &str) -> Result<u32, Error> {
+    let mut reply = None;
+    let result = at.query(
+        format_args!("AT+PING={}", Quoted(host)),
+        PING_TIMEOUT_MS,
+        |line| {
+            if let Some(time) = line.strip_prefix(b"+PING:") {
+                reply = Some(at::parse_int(time));
+            }
+        },
+    );
+    match (result, reply) {
+        // `+PING:TIMEOUT`, followed by `ERROR`.
+        (Err(at::Error::Command(_)), Some(None)) => Err(Error::NoReply),
+        (result, reply) => {
+            result?;
+            let time = reply.flatten().ok_or(Error::InvalidResponse)?;
+            Ok(time.max(0) as u32)
+        }
+    }
+}
+
+/// Returns the enabled interfaces.
+fn mode(at: &mut At) -> Result<i32, Error> {
+    let mut mode = None;
+    at.query(format_args!("AT+CWMODE?"), COMMAND_TIMEOUT_MS, |line| {
+        if let Some(parameters) = line.strip_prefix(b"+CWMODE:") {
+            mode = at::fields(parameters).next().and_then(at::parse_int);
+        }
+    })?;
+    mode.ok_or(Error::InvalidResponse)
+}
+
+/// Make sure that the interfaces of `mode` are enabled, keeping the others.
+fn enable_mode(at: &mut At, mode: i32) -> Result<(), Error> {
+    let current = self::mode(at)?;
+    if current & mode != mode {
+        at.execute(
+            format_args!("AT+CWMODE={}", current | mode),
+            COMMAND_TIMEOUT_MS,
+        )?;
+    }
+    Ok(())
+}
+
+/// Disable the interfaces of `mode`, keeping the others.
+fn disable_mode(at: &mut At, mode: i32) -> Result<(), Error> {
+    let current = self::mode(at)?;
+    if current & mode != 0 {
+        at.execute(
+            format_args!("AT+CWMODE={}", current & !mode),
+            COMMAND_TIMEOUT_MS,
+        )?;
+    }
+    Ok(())
+}
+
+/// Parse an IPv4 address like `192.168.1.2`.
+fn parse_ip(field: &[u8]) -> Option<Ipv4Addr> {
+    core::str::from_utf8(field).ok()?.parse().ok()
+}
+
+/// A MAC address in quotes, like `"24:0a:c4:00:01:02"`.
+struct QuotedMac([u8; 6]);
+
+impl fmt::Display for QuotedMac {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "\"{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}\"")
+    }
+}
+
+/// Parse a MAC address like `24:0a:c4:00:01:02`.
+fn parse_mac(field: &[u8]) -> Option<[u8; 6]> {
+    let mut mac = [0; 6];
+    let mut parts = field.split(|&byte| byte == b':');
+    for byte in mac.iter_mut() {
+        let part = core::str::from_utf8(parts.next()?).ok()?;
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    parts.next().is_none().then_some(mac)
+}
+
+/// Query the IPv4 configuration of an interface with `command`, which answers with lines like
+/// `<prefix>ip:"192.168.4.1"`.
+fn query_ip_config(at: &mut At, command: &str, prefix: &[u8]) -> Result<IpConfig, Error> {
+    let mut address = None;
+    let mut gateway = None;
+    let mut netmask = None;
+    at.query(format_args!("{}", command), COMMAND_TIMEOUT_MS, |line| {
+        let Some(parameter) = line.strip_prefix(prefix) else {
+            return;
+        };
+        let (field, target) = if let Some(field) = parameter.strip_prefix(b"ip:") {
+            (field, &mut address)
+        } else if let Some(field) = parameter.strip_prefix(b"gateway:") {
+            (field, &mut gateway)
+        } else if let Some(field) = parameter.strip_prefix(b"netmask:") {
+            (field, &mut netmask)
+        } else {
+            return;
+        };
+        *target = at::fields(field).next().and_then(parse_ip);
+    })?;
+    match (address, gateway, netmask) {
+        (Some(address), Some(gateway), Some(netmask)) => Ok(IpConfig {
+            address,
+            gateway,
+            netmask,
+        }),
+        _ => Err(Error::InvalidResponse),
+    }
+}
+
+/// Set the IPv4 configuration of an interface with `command`, e.g. `AT+CIPSTA`, which also stops
+/// its DHCP client.
+fn set_ip_config(at: &mut At, command: &str, config: &IpConfig) -> Result<(), Error> {
+    at.execute(
+        format_args!(
+            "{}=\"{}\",\"{}\",\"{}\"",
+            command, config.address, config.gateway, config.netmask
+        ),
+        COMMAND_TIMEOUT_MS,
+    )?;
+    Ok(())
+}
+
+/// Query the MAC address of an interface with `command`, which answers with a line like
+/// `<prefix>"24:0a:c4:00:01:02"`.
+fn query_mac(at: &mut At, command: &str, prefix: &[u8]) -> Result<[u8; 6], Error> {
+    let mut mac = None;
+    at.query(format_args!("{}", command), COMMAND_TIMEOUT_MS, |line| {
+        if let Some(parameters) = line.strip_prefix(prefix) {
+            mac = at::fields(parameters).next().and_then(parse_mac);
+        }
+    })?;
+    mac.ok_or(Error::InvalidResponse)
+}
+
+/// Set the MAC address of an interface with `command`, until the next reset. Multicast addresses
+/// are refused.
+fn set_mac(at: &mut At, command: &str, mac: [u8; 6]) -> Result<(), Error> {
+    if mac[0] & 1 != 0 {
+        return Err(Error::InvalidConfig);
+    }
+    at.execute(
+        format_args!("{}={}", command, QuotedMac(mac)),
+        COMMAND_TIMEOUT_MS,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,749 @@
+//! TCP, UDP and TLS connections of the ESP32-S3.
+
+use super::{parse_ip, Error, COMMAND_TIMEOUT_MS};
+use crate::at::{self, At, Hex, Quoted};
+
+use core::net::Ipv4Addr;
+
+/// Number of connections the firmware handles at the same time.
+pub const MAX_SOCKETS: u8 = 5;
+
+/// Maximum number of bytes sent with one `AT+CIPSEND`.
+const SEND_CHUNK_SIZE: usize = 2048;
+
+/// Maximum number of bytes read with one `AT+CIPRECVDATA`, so the answer fits in a line of the
+/// AT engine.
+const RECEIVE_CHUNK_SIZE: usize = at::LINE_SIZE - 32;
+
+/// Time for opening a TCP or UDP connection, in ms.
+const CONNECT_TIMEOUT_MS: u32 = 10_000;
+
+/// Time for opening a TLS connection, including the handshake, in ms.
+const TLS_CONNECT_TIMEOUT_MS: u32 = 20_000;
+
+/// Time for resolving a host name, in ms.
+const RESOLVE_TIMEOUT_MS: u32 = 5000;
+
+/// Time for sending a chunk, in ms.
+const SEND_TIMEOUT_MS: u32 = 5000;
+
+/// Transport of a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// A pre-shared key for TLS, which authenticates both ends without certificates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Psk<'a> {
+    /// The key, up to 32 bytes.
+    pub key: &'a [u8],
+    /// The identity the server knows the key by, up to 32 bytes.
+    pub identity: &'a str,
+}
+
+/// How the TLS connection is authenticated, with the certificates stored on the ESP32-S3 (see
+/// [`Certificates`](super::Certificates)) or a pre-shared key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TlsConfig<'a> {
+    /// Server name for SNI, the host name if `None`.
+    pub server_name: Option<&'a str>,
+    /// Verify the certificate of the server with the CA certificate of this index.
+    pub ca_certificate: Option<u8>,
+    /// Present the client certificate and key of this index to the server.
+    pub client_certificate: Option<u8>,
+    /// Offer a pre-shared key to the server.
+    pub psk: Option<Psk<'a>>,
+}
+
+impl<'a> TlsConfig<'a> {
+    /// Set the server name for SNI.
+    pub fn with_server_name(self, server_name: &'a str) -> Self {
+        Self {
+            server_name: Some(server_name),
+            ..self
+        }
+    }
+
+    /// Verify the server with the CA certificate `index`.
+    pub fn with_ca_certificate(self, index: u8) -> Self {
+        Self {
+            ca_certificate: Some(index),
+            ..self
+        }
+    }
+
+    /// Authenticate with the client certificate `index`.
+    pub fn with_client_certificate(self, index: u8) -> Self {
+        Self {
+            client_certificate: Some(index),
+            ..self
+        }
+    }
+
+    /// Use the pre-shared `key` with `identity`.
+    pub fn with_psk(self, key: &'a [u8], identity: &'a str) -> Self {
+        Self {
+            psk: Some(Psk { key, identity }),
+            ..self
+        }
+    }
+
+    /// Returns the `<auth_mode>` of `AT+CIPSSLCCONF`: b0 for the client certificate, b1 for the
+    /// verification of the server.
+    fn auth_mode(&self) -> u8 {
+        self.client_certificate.is_some() as u8 | (self.ca_certificate.is_some() as u8) << 1
+    }
+}
+
+/// An open connection, identified by its link ID.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Socket {
+    link: u8,
+    protocol: Protocol,
+}
+
+impl Socket {
+    /// Returns the link ID of the connection.
+    pub fn link(&self) -> u8 {
+        self.link
+    }
+
+    /// Returns the transport of the connection, [`Protocol::Tcp`] for TLS.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+}
+
+/// The connections of the ESP32-S3.
+///
+/// The firmware keeps data received over TCP until it is read with [`Stack::receive`], so nothing
+/// is lost while the application is busy. Datagrams received over UDP are kept with the URCs of
+/// the [`At`] engine instead, and are dropped if its buffer is full.
+pub struct Stack<'a> {
+    at: &'a mut At,
+}
+
+impl<'a> Stack<'a> {
+    /// Let the firmware handle several connections, and keep received data until it is read.
+    pub fn new(at: &'a mut At) -> Result<Self, Error> {
+        at.execute(format_args!("AT+CIPMUX=1"), COMMAND_TIMEOUT_MS)?;
+        at.execute(format_args!("AT+CIPRECVMODE=1"), COMMAND_TIMEOUT_MS)?;
+        Ok(Self { at })
+    }
+
+    /// Returns the AT engine, e.g. for a command this module doesn't cover.
+    pub fn at(&mut self) -> &mut At {
+        self.at
+    }
+
+    /// Returns the open connections and those of them that the server accepted, one bit per link
+    /// ID.
+    fn links(&mut self) -> Result<(u8, u8), Error> {
+        let mut links = 0;
+        let mut accepted = 0;
+        self.at
+            .query(format_args!("AT+CIPSTATE?"), COMMAND_TIMEOUT_MS, |line| {
+                if let Some(parameters) = line.strip_prefix(b"+CIPSTATE:") {
+                    // <link>,<type>,<remote IP>,<remote port>,<local port>,<tetype>
+                    let mut fields = at::fields(parameters);
+                    if let Some(link) = fields.next().and_then(at::parse_int) {
+                        links |= 1 << (link & 7);
+                        // tetype is 1 for connections to the server.
+                        if fields.nth(4).and_then(at::parse_int) == Some(1) {
+                            accepted |= 1 << (link & 7);
+                        }
+                    }
+                }
+            })?;
+        Ok((links, accepted))
+    }
+
+    /// Returns the open connections, one bit per link ID.
+    fn open_links(&mut self) -> Result<u8, Error> {
+        Ok(self.links()?.0)
+    }
+
+    /// Returns a link ID that isn't in use.
+    fn free_link(&mut self) -> Result<u8, Error> {
+        let links = self.open_links()?;
+        (0..MAX_SOCKETS)
+            .find(|link| links & (1 << link) == 0)
+            .ok_or(Error::NoFreeSocket)
+    }
+
+    /// Open a TCP connection or a UDP association to `port` on `host`, a name or an IP address.
+    pub fn connect(&mut self, protocol: Protocol, host: &str, port: u16) -> Result<Socket, Error> {
+        let link = self.free_link()?;
+        let name = match protocol {
+            Protocol::Tcp => "TCP",
+            Protocol::Udp => "UDP",
+        };
+        self.at.execute(
+            format_args!(
+                "AT+CIPSTART={},\"{}\",{},{}",
+                link,
+                name,
+                Quoted(host),
+                port
+            ),
+            CONNECT_TIMEOUT_MS,
+        )?;
+        Ok(Socket { link, protocol })
+    }
+
+    /// Open a TLS connection to `port` on `host`, authenticated as set in `tls`.
+    pub fn connect_tls(&mut self, host: &str, port: u16, tls: &TlsConfig) -> Result<Socket, Error> {
+        let link = self.free_link()?;
+        match (tls.client_certificate, tls.ca_certificate) {
+            (None, None) => self.at.execute(
+                format_args!("AT+CIPSSLCCONF={},0", link),
+                COMMAND_TIMEOUT_MS,
+            )?,
+            (client, ca) => self.at.execute(
+                format_args!(
+                    "AT+CIPSSLCCONF={},{},{},{}",
+                    link,
+                    tls.auth_mode(),
+                    client.unwrap_or(0),
+                    ca.unwrap_or(0)
+                ),
+                COMMAND_TIMEOUT_MS,
+            )?,
+        }
+        if let Some(psk) = tls.psk {
+            if psk.key.len() > 32 || psk.identity.len() > 32 {
+                return Err(Error::InvalidConfig);
+            }
+            self.at.execute(
+                format_args!(
+                    "AT+CIPSSLCPSKHEX={},\"{}\",{}",
+                    link,
+                    Hex(psk.key),
+                    Quoted(psk.identity)
+                ),
+                COMMAND_TIMEOUT_MS,
+            )?;
+        }
+        self.at.execute(
+            format_args!(
+                "AT+CIPSSLCSNI={},{}",
+                link,
+                Quoted(tls.server_name.unwrap_or(host))
+            ),
+            COMMAND_TIMEOUT_MS,
+        )?;
+        self.at.execute(
+            format_args!("AT+CIPSTART={},\"SSL\",{},{}", link, Quoted(host), port),
+            TLS_CONNECT_TIMEOUT_MS,
+        )?;
+        Ok(Socket {
+            link,
+            protocol: Protocol::Tcp,
+        })
+    }
+
+    /// Accept TCP connections on `port`, see [`Stack::accept`]. They count towards the
+    /// [`MAX_SOCKETS`] connections of the firmware.
+    pub fn listen(&mut self, port: u16) -> Result<(), Error> {
+        self.at
+            .execute(format_args!("AT+CIPSERVER=1,{}", port), COMMAND_TIMEOUT_MS)?;
+        Ok(())
+    }
+
+    /// Stop accepting connections, and close the ones that were accepted.
+    pub fn stop_listening(&mut self) -> Result<(), Error> {
+        self.at
+            .execute(format_args!("AT+CIPSERVER=0,1"), COMMAND_TIMEOUT_MS)?;
+        Ok(())
+    }
+
+    /// Returns the next connection that a client opened to the server of [`Stack::listen`], `None`
+    /// if there is none.
+    pub fn accept(&mut self) -> Result<Option<Socket>, Error> {
+        self.discard_notifications();
+        let mut buffer = [0; 16];
+        while let Some(urc) = self.at.take_urc(&mut buffer, is_connect_urc) {
+            let link = urc[0] - b'0';
+            // The URC also follows the connections opened with Stack::connect, and the client may
+            // have closed the connection already.
+            if self.links()?.1 & (1 << link) != 0 {
+                return Ok(Some(Socket {
+                    link,
+                    protocol: Protocol::Tcp,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Send all of `data`.
+    pub fn send(&mut self, socket: &Socket, data: &[u8]) -> Result<(), Error> {
+        for chunk in data.chunks(SEND_CHUNK_SIZE) {
+            self.at.send_data(
+                format_args!("AT+CIPSEND={},{}", socket.link, chunk.len()),
+                chunk,
+                SEND_TIMEOUT_MS,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns the IPv4 address of `host`.
+    pub fn resolve(&mut self, host: &str) -> Result<Ipv4Addr, Error> {
+        let mut address = None;
+        self.at.query(
+            format_args!("AT+CIPDOMAIN={}", Quoted(host)),
+            RESOLVE_TIMEOUT_MS,
+            |line| {
+                if let Some(parameters) = line.strip_prefix(b"+CIPDOMAIN:") {
+                    address = at::fields(parameters).next().and_then(parse_ip);
+                }
+            },
+        )?;
+        address.ok_or(Error::InvalidResponse)
+    }
+
+    /// Returns the number of received bytes that haven't been read, over TCP.
+    pub fn available(&mut self, socket: &Socket) -> Result<usize, Error> {
+        let mut available = None;
+        self.at
+            .query(format_args!("AT+CIPRECVLEN?"), COMMAND_TIMEOUT_MS, |line| {
+                if let Some(parameters) = line.strip_prefix(b"+CIPRECVLEN:") {
+                    available = at::fields(parameters)
+                        .nth(socket.link as usize)
+                        .and_then(at::parse_int);
+                }
+            })?;
+        // Links that aren't open are reported as -1.
+        Ok(available.ok_or(Error::InvalidResponse)?.max(0) as usize)
+    }
+
+    /// Move received bytes to `buffer` and return how many, 0 if there are none. Over UDP, this is
+    /// the next datagram, cut off at the end of `buffer`.
+    pub fn receive(&mut self, socket: &Socket, buffer: &mut [u8]) -> Result<usize, Error> {
+        self.discard_notifications();
+        if socket.protocol == Protocol::Udp {
+            return Ok(self.receive_datagram(socket, buffer));
+        }
+        if buffer.is_empty() || self.available(socket)? == 0 {
+            return Ok(0);
+        }
+        let len = buffer.len().min(RECEIVE_CHUNK_SIZE);
+        let mut received = None;
+        self.at.query(
+            format_args!("AT+CIPRECVDATA={},{}", socket.link, len),
+            COMMAND_TIMEOUT_MS,
+            |line| {
+                let Some(parameters) = line.strip_prefix(b"+CIPRECVDATA:") else {
+                    return;
+                };
+                let Some(comma) = parameters.iter().position(|&byte| byte == b',') else {
+                    return;
+                };
+                let data = &parameters[comma + 1..];
+                let count = data.len().min(buffer.len());
+                buffer[..count].copy_from_slice(&data[..count]);
+                received = Some(count);
+            },
+        )?;
+        received.ok_or(Error::InvalidResponse)
+    }
+
+    /// Move the next datagram of `socket` from its `+IPD,<link>,<length>:<data>` URC to `buffer`.
+    fn receive_datagram(&mut self, socket: &Socket, buffer: &mut [u8]) -> usize {
+        let prefix = [b'+', b'I', b'P', b'D', b',', b'0' + socket.link, b','];
+        // The passive notifications of TCP links have the same prefix, but no data.
+        let is_datagram = |head: &[u8]| head.starts_with(&prefix) && !is_passive_urc(head);
+        let Some(urc) = self.at.take_urc(buffer, is_datagram) else {
+            return 0;
+        };
+        let Some(colon) = urc.iter().position(|&byte| byte == b':') else {
+            return 0;
+        };
+        let end = urc.len();
+        buffer.copy_within(colon + 1..end, 0);
+        end - colon - 1
+    }
+
+    /// Returns true if the connection is still open. The peer may have closed it.
+    pub fn is_open(&mut self, socket: &Socket) -> Result<bool, Error> {
+        self.discard_notifications();
+        Ok(self.open_links()? & (1 << socket.link) != 0)
+    }
+
+    /// Drop the URCs that nothing waits for: the `+IPD,<link>,<length>` notifications of data
+    /// received over TCP, which [`Stack::available`] reports, and the `<link>,CLOSED` ones, which
+    /// [`Stack::is_open`] reports. Otherwise they fill the URC buffer of the [`At`] engine.
+    fn discard_notifications(&mut self) {
+        while self.at.take_urc(&mut [], is_passive_urc).is_some() {}
+    }
+
+    /// Close the connection. Data that hasn't been read is dropped.
+    pub fn close(&mut self, socket: Socket) -> Result<(), Error> {
+        match self.at.execute(
+            format_args!("AT+CIPCLOSE={}", socket.link),
+            COMMAND_TIMEOUT_MS,
+        ) {
+            // The peer closed it already.
+            Ok(()) | Err(at::Error::Command(_)) => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+/// Returns true if `urc` is `+IPD,<link>,<length>` without data, or `<link>,CLOSED`. Datagrams
+/// received over UDP are `+IPD,<link>,<length>:<data>` and don't match.
+fn is_passive_urc(urc: &[u8]) -> bool {
+    match urc {
+        [b'+', b'I', b'P', b'D', b',', link, b',', length @ ..] => {
+            link.is_ascii_digit() && !length.is_empty() && length.iter().all(u8::is_ascii_digit)
+        }
+        [link, b',', b'C', b'L', b'O', b'S', b'E', b'D'] => link.is_ascii_digit(),
+        _ => false,
+    }
+}
+
+/// Returns true if `urc` is `<link>,CONNECT`.
+fn is_connect_urc(urc: &[u8]) -> bool {
+    matches!(urc, [link, b',', b'C', b'O', b'N', b'N', b'E', b'C', b'T'] if link.is_ascii_digit() && *link < b'0' + MAX_SOCKETS)
+}
+
+#[cfg(any(feature = "embedded-nal", feature = "embedded-nal-async"))]
+/// Formats an IPv4 address for the commands, which take addresses as strings.
+struct AddressString {
+    buffer: [u8; 15],
+    len: usize,
+}
+
+#[cfg(any(feature = "embedded-nal", feature = "embedded-nal-async"))]
+impl AddressString {
+    fn new(address: Ipv4Addr) -> Self {
+        let mut string = Self {
+            buffer: [0; 15],
+            len: 0,
+        };
+        // The longest address, 255.255.255.255, fits.
+        let _ = core::fmt::write(&mut string, format_args!("{}", address));
+        string
+    }
+
+    fn as_str(&self) -> &str {
+        // Only ASCII digits and dots are written.
+        core::str::from_utf8(&self.buffer[..self.len]).unwrap_or_default()
+    }
+}
+
+#[cfg(any(feature = "embedded-nal", feature = "embedded-nal-async"))]
+impl core::fmt::Write for AddressString {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+        let slot = self.buffer.get_mut(self.len..end).ok_or(core::fmt::Error)?;
+        slot.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-nal")]
+mod embedded_nal_impl {
+    use super::{AddressString, Error, Protocol, Socket, Stack};
+    use embedded_nal::{
+        AddrType, Dns, IpAddr, SocketAddr, TcpClientStack, TcpError, TcpErrorKind, UdpClientStack,
+    };
+
+    impl TcpError for Error {
+        fn kind(&self) -> TcpErrorKind {
+            match self {
+                Error::SocketClosed => TcpErrorKind::PipeClosed,
+                _ => TcpErrorKind::Other,
+            }
+        }
+    }
+
+    /// Open a connection to `remote`, which has to be an IPv4 address.
+    fn connect(stack: &mut Stack, protocol: Protocol, remote: SocketAddr) -> Result<Socket, Error> {
+        let SocketAddr::V4(remote) = remote else {
+            return Err(Error::InvalidConfig);
+        };
+        let host = AddressString::new(*remote.ip());
+        stack.connect(protocol, host.as_str(), remote.port())
+    }
+
+    /// A socket is `None` until it is connected.
+    impl TcpClientStack for Stack<'_> {
+        type TcpSocket = Option<Socket>;
+        type Error = Error;
+
+        fn socket(&mut self) -> Result<Option<Socket>, Error> {
+            Ok(None)
+        }
+
+        fn connect(
+            &mut self,
+            socket: &mut Option<Socket>,
+            remote: SocketAddr,
+        ) -> nb::Result<(), Error> {
+            if let Some(previous) = socket.take() {
+                self.close(previous)?;
+            }
+            *socket = Some(connect(self, Protocol::Tcp, remote)?);
+            Ok(())
+        }
+
+        fn send(&mut self, socket: &mut Option<Socket>, buffer: &[u8]) -> nb::Result<usize, Error> {
+            let socket = socket.as_ref().ok_or(Error::SocketClosed)?;
+            Stack::send(self, socket, buffer)?;
+            Ok(buffer.len())
+        }
+
+        fn receive(
+            &mut self,
+            socket: &mut Option<Socket>,
+            buffer: &mut [u8],
+        ) -> nb::Result<usize, Error> {
+            let socket = socket.as_ref().ok_or(Error::SocketClosed)?;
+            // Check first, so that nothing that arrives before the close is missed.
+            let open = self.is_open(socket)?;
+            match Stack::receive(self, socket, buffer)? {
+                0 if open => Err(nb::Error::WouldBlock),
+                0 => Err(nb::Error::Other(Error::SocketClosed)),
+                count => Ok(count),
+            }
+        }
+
+        fn close(&mut self, socket: Option<Socket>) -> Result<(), Error> {
+            match socket {
+                Some(socket) => Stack::close(self, socket),
+                None => Ok(()),
+            }
+        }
+    }
+
+    /// A socket is `None` until it is connected, then it has the address of the peer.
+    impl UdpClientStack for Stack<'_> {
+        type UdpSocket = Option<(Socket, SocketAddr)>;
+        type Error = Error;
+
+        fn socket(&mut self) -> Result<Option<(Socket, SocketAddr)>, Error> {
+            Ok(None)
+        }
+
+        fn connect(
+            &mut self,
+            socket: &mut Option<(Socket, SocketAddr)>,
+            remote: SocketAddr,
+        ) -> Result<(), Error> {
+            if let Some((previous, _)) = socket.take() {
+                self.close(previous)?;
+            }
+            *socket = Some((connect(self, Protocol::Udp, remote)?, remote));
+            Ok(())
+        }
+
+        fn send(
+            &mut self,
+            socket: &mut Option<(Socket, SocketAddr)>,
+            buffer: &[u8],
+        ) -> nb::Result<(), Error> {
+            let (socket, _) = socket.as_ref().ok_or(Error::SocketClosed)?;
+            Ok(Stack::send(self, socket, buffer)?)
+        }
+
+        fn receive(
+            &mut self,
+            socket: &mut Option<(Socket, SocketAddr)>,
+            buffer: &mut [u8],
+        ) -> nb::Result<(usize, SocketAddr), Error> {
+            let (socket, remote) = socket.as_ref().ok_or(Error::SocketClosed)?;
+            match Stack::receive(self, socket, buffer)? {
+                0 => Err(nb::Error::WouldBlock),
+                count => Ok((count, *remote)),
+            }
+        }
+
+        fn close(&mut self, socket: Option<(Socket, SocketAddr)>) -> Result<(), Error> {
+            match socket {
+                Some((socket, _)) => Stack::close(self, socket),
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl Dns for Stack<'_> {
+        type Error = Error;
+
+        fn get_host_by_name(
+            &mut self,
+            hostname: &str,
+            addr_type: AddrType,
+        ) -> nb::Result<IpAddr, Error> {
+            if addr_type == AddrType::IPv6 {
+                return Err(nb::Error::Other(Error::InvalidConfig));
+            }
+            Ok(IpAddr::V4(self.resolve(hostname)?))
+        }
+
+        fn get_host_by_address(&mut self, _: IpAddr, _: &mut [u8]) -> nb::Result<usize, Error> {
+            // The firmware has no reverse lookup.
+            Err(nb::Error::Other(Error::InvalidConfig))
+        }
+    }
+}
+
+#[cfg(feature = "embedded-nal-async")]
+mod embedded_nal_async_impl {
+    use super::{AddressString, Error, Protocol, Socket, Stack};
+    use crate::at;
+    use core::cell::RefCell;
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+    use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+    use embedded_nal_async::{AddrType, Dns, IpAddr, SocketAddr, TcpConnect};
+
+    /// Time between polls of a connection, in ms.
+    const POLL_INTERVAL_MS: u32 = 10;
+
+    /// [`Stack`] for the async traits, which take it by shared reference.
+    ///
+    /// The AT commands still block, but waiting for data lets other tasks run.
+    pub struct AsyncStack<'s, 'a> {
+        stack: RefCell<&'s mut Stack<'a>>,
+    }
+
+    impl<'s, 'a> AsyncStack<'s, 'a> {
+        pub fn new(stack: &'s mut Stack<'a>) -> Self {
+            Self {
+                stack: RefCell::new(stack),
+            }
+        }
+    }
+
+    /// Returns `Pending` once, so the executor can run other tasks.
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            context.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    impl embedded_io_async::Error for Error {
+        fn kind(&self) -> ErrorKind {
+            match self {
+                Error::SocketClosed => ErrorKind::ConnectionReset,
+                Error::At(at::Error::Timeout) => ErrorKind::TimedOut,
+                Error::InvalidConfig => ErrorKind::InvalidInput,
+                _ => ErrorKind::Other,
+            }
+        }
+    }
+
+    /// A TCP connection of an [`AsyncStack`], closed when it is dropped.
+    pub struct Connection<'c, 's, 'a> {
+        stack: &'c RefCell<&'s mut Stack<'a>>,
+        socket: Option<Socket>,
+    }
+
+    impl ErrorType for Connection<'_, '_, '_> {
+        type Error = Error;
+    }
+
+    impl Read for Connection<'_, '_, '_> {
+        async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+            let socket = self.socket.as_ref().ok_or(Error::SocketClosed)?;
+            if buffer.is_empty() {
+                return Ok(0);
+            }
+            loop {
+                {
+                    let mut stack = self.stack.borrow_mut();
+                    // Check first, so that nothing that arrives before the close is missed.
+                    let open = stack.is_open(socket)?;
+                    match stack.receive(socket, buffer)? {
+                        // The end of the stream.
+                        0 if !open => return Ok(0),
+                        0 => stack.at().delay_ms(POLL_INTERVAL_MS),
+                        count => return Ok(count),
+                    }
+                }
+                YieldNow(false).await;
+            }
+        }
+    }
+
+    impl Write for Connection<'_, '_, '_> {
+        async fn write(&mut self, buffer: &[u8]) -> Result<usize, Error> {
+            let socket = self.socket.as_ref().ok_or(Error::SocketClosed)?;
+            self.stack.borrow_mut().send(socket, buffer)?;
+            Ok(buffer.len())
+        }
+    }
+
+    impl Drop for Connection<'_, '_, '_> {
+        fn drop(&mut self) {
+            if let (Some(socket), Ok(mut stack)) = (self.socket.take(), self.stack.try_borrow_mut())
+            {
+                let _ = stack.close(socket);
+            }
+        }
+    }
+
+    impl<'s, 'a> TcpConnect for AsyncStack<'s, 'a> {
+        type Error = Error;
+        type Connection<'c>
+            = Connection<'c, 's, 'a>
+        where
+            Self: 'c;
+
+        async fn connect<'c>(
+            &'c self,
+            remote: SocketAddr,
+        ) -> Result<Connection<'c, 's, 'a>, Error> {
+            let SocketAddr::V4(remote) = remote else {
+                return Err(Error::InvalidConfig);
+            };
+            let host = AddressString::new(*remote.ip());
+            let socket =
+                self.stack
+                    .borrow_mut()
+                    .connect(Protocol::Tcp, host.as_str(), remote.port())?;
+            Ok(Connection {
+                stack: &self.stack,
+                socket: Some(socket),
+            })
+        }
+    }
+
+    impl Dns for AsyncStack<'_, '_> {
+        type Error = Error;
+
+        async fn get_host_by_name(&self, host: &str, addr_type: AddrType) -> Result<IpAddr, Error> {
+            if addr_type == AddrType::IPv6 {
+                return Err(Error::InvalidConfig);
+            }
+            Ok(IpAddr::V4(self.stack.borrow_mut().resolve(host)?))
+        }
+
+        async fn get_host_by_address(&self, _: IpAddr, _: &mut [u8]) -> Result<usize, Error> {
+            // The firmware has no reverse lookup.
+            Err(Error::InvalidConfig)
+        }
+    }
+}
+
+#[cfg(feature = "embedded-nal-async")]
+pub use embedded_nal_async_impl::{AsyncStack, Connection};
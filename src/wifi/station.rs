@@ -0,0 +1,261 @@
+//! The station interface of the ESP32-S3, which joins a network.
+
+use super::{
+    enable_mode, parse_mac, query_ip_config, query_mac, set_ip_config, set_mac, Error, IpConfig,
+    COMMAND_TIMEOUT_MS, MODE_STATION,
+};
+use crate::at::{self, At, Quoted};
+
+/// Time for a scan, in ms.
+const SCAN_TIMEOUT_MS: u32 = 10_000;
+
+/// Maximum length of the DHCP host name.
+const HOSTNAME_SIZE: usize = 32;
+
+/// Bit of the station in the DHCP state of `AT+CWDHCP`.
+const DHCP_STATION: i32 = 1;
+
+/// Time for joining a network, a bit longer than the 15 s the firmware waits itself.
+const CONNECT_TIMEOUT_MS: u32 = 20_000;
+
+/// Security of a network, the `<ecn>` of `AT+CWLAP`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Security {
+    #[default]
+    Open,
+    Wep,
+    WpaPsk,
+    Wpa2Psk,
+    WpaWpa2Psk,
+    Wpa2Enterprise,
+    Wpa3Psk,
+    Wpa2Wpa3Psk,
+    WapiPsk,
+    Owe,
+    /// A method that this crate doesn't know yet.
+    Unknown,
+}
+
+impl Security {
+    fn from_ecn(ecn: i32) -> Self {
+        match ecn {
+            0 => Security::Open,
+            1 => Security::Wep,
+            2 => Security::WpaPsk,
+            3 => Security::Wpa2Psk,
+            4 => Security::WpaWpa2Psk,
+            5 => Security::Wpa2Enterprise,
+            6 => Security::Wpa3Psk,
+            7 => Security::Wpa2Wpa3Psk,
+            8 => Security::WapiPsk,
+            9 => Security::Owe,
+            _ => Security::Unknown,
+        }
+    }
+}
+
+/// A network found by [`Station::scan`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Network {
+    ssid: [u8; 32],
+    ssid_len: u8,
+    /// MAC address of the access point.
+    pub bssid: [u8; 6],
+    /// Signal strength in dBm.
+    pub rssi: i8,
+    pub channel: u8,
+    pub security: Security,
+}
+
+impl Network {
+    /// Returns the SSID, which is usually, but not necessarily, UTF-8.
+    pub fn ssid(&self) -> &[u8] {
+        &self.ssid[..self.ssid_len as usize]
+    }
+
+    /// Parse the parameters of a `+CWLAP:` line with all fields.
+    fn parse(parameters: &[u8]) -> Option<Self> {
+        let parameters = parameters.strip_prefix(b"(")?.strip_suffix(b")")?;
+        let mut fields = at::fields(parameters);
+        let security = Security::from_ecn(at::parse_int(fields.next()?)?);
+        let name = fields.next()?;
+        let rssi = at::parse_int(fields.next()?)?;
+        let bssid = parse_mac(fields.next()?)?;
+        let channel = at::parse_int(fields.next()?)?;
+        let mut network = Network {
+            bssid,
+            rssi: rssi.clamp(i8::MIN as i32, 0) as i8,
+            channel: channel as u8,
+            security,
+            ..Default::default()
+        };
+        let len = name.len().min(network.ssid.len());
+        network.ssid[..len].copy_from_slice(&name[..len]);
+        network.ssid_len = len as u8;
+        Some(network)
+    }
+}
+
+/// The station interface.
+pub struct Station<'a> {
+    at: &'a mut At,
+}
+
+impl<'a> Station<'a> {
+    /// Enable the station interface, keeping the access point if it is enabled.
+    pub fn new(at: &'a mut At) -> Result<Self, Error> {
+        enable_mode(at, MODE_STATION)?;
+        Ok(Self { at })
+    }
+
+    /// Scan for networks and store them in `networks`, the strongest first. Returns the number of
+    /// networks stored, the weakest are left out if there are more than fit.
+    pub fn scan(&mut self, networks: &mut [Network]) -> Result<usize, Error> {
+        // Sort by RSSI, and list the security, SSID, RSSI, MAC address and channel.
+        self.at
+            .execute(format_args!("AT+CWLAPOPT=1,31"), COMMAND_TIMEOUT_MS)?;
+        let mut count = 0;
+        let mut invalid = false;
+        self.at
+            .query(format_args!("AT+CWLAP"), SCAN_TIMEOUT_MS, |line| {
+                let Some(parameters) = line.strip_prefix(b"+CWLAP:") else {
+                    return;
+                };
+                match Network::parse(parameters) {
+                    Some(network) => {
+                        if let Some(slot) = networks.get_mut(count) {
+                            *slot = network;
+                            count += 1;
+                        }
+                    }
+                    None => invalid = true,
+                }
+            })?;
+        if invalid {
+            return Err(Error::InvalidResponse);
+        }
+        Ok(count)
+    }
+
+    /// Join the network `ssid` with `passphrase`, empty for an open network, and wait until the
+    /// station got its IP address.
+    pub fn connect(&mut self, ssid: &str, passphrase: &str) -> Result<(), Error> {
+        let mut reason = None;
+        let result = self.at.query(
+            format_args!("AT+CWJAP={},{}", Quoted(ssid), Quoted(passphrase)),
+            CONNECT_TIMEOUT_MS,
+            |line| {
+                if let Some(code) = line.strip_prefix(b"+CWJAP:") {
+                    reason = at::parse_int(code);
+                }
+            },
+        );
+        match (result, reason) {
+            (Err(at::Error::Command(_)), Some(1)) => Err(Error::ConnectTimeout),
+            (Err(at::Error::Command(_)), Some(2)) => Err(Error::WrongPassphrase),
+            (Err(at::Error::Command(_)), Some(3)) => Err(Error::NoNetwork),
+            (Err(at::Error::Command(_)), Some(_)) => Err(Error::ConnectFailed),
+            (result, _) => Ok(result?),
+        }
+    }
+
+    /// Leave the network.
+    pub fn disconnect(&mut self) -> Result<(), Error> {
+        self.at
+            .execute(format_args!("AT+CWQAP"), COMMAND_TIMEOUT_MS)?;
+        Ok(())
+    }
+
+    /// Returns the parameters of the `+CWJAP:` line of the current connection, if there is one.
+    fn connection<R>(&mut self, parse: impl Fn(&[u8]) -> Option<R>) -> Result<R, Error> {
+        let mut connection = None;
+        let mut connected = false;
+        self.at
+            .query(format_args!("AT+CWJAP?"), COMMAND_TIMEOUT_MS, |line| {
+                if let Some(parameters) = line.strip_prefix(b"+CWJAP:") {
+                    connected = true;
+                    connection = parse(parameters);
+                }
+            })?;
+        match connection {
+            Some(connection) => Ok(connection),
+            None if connected => Err(Error::InvalidResponse),
+            None => Err(Error::NotConnected),
+        }
+    }
+
+    /// Returns true if the station is connected to a network.
+    pub fn is_connected(&mut self) -> Result<bool, Error> {
+        match self.connection(|_| Some(())) {
+            Ok(()) => Ok(true),
+            Err(Error::NotConnected) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Returns the signal strength of the network in dBm.
+    pub fn rssi(&mut self) -> Result<i8, Error> {
+        self.connection(|parameters| {
+            let rssi = at::fields(parameters).nth(3).and_then(at::parse_int)?;
+            Some(rssi.clamp(i8::MIN as i32, 0) as i8)
+        })
+    }
+
+    /// Returns the IPv4 address, gateway and netmask of the station.
+    pub fn ip_config(&mut self) -> Result<IpConfig, Error> {
+        query_ip_config(self.at, "AT+CIPSTA?", b"+CIPSTA:")
+    }
+
+    /// Use a static IPv4 address, gateway and netmask instead of DHCP, until
+    /// [`Station::enable_dhcp`].
+    pub fn set_static_ip(&mut self, config: &IpConfig) -> Result<(), Error> {
+        set_ip_config(self.at, "AT+CIPSTA", config)
+    }
+
+    /// Get the IPv4 configuration from the DHCP server of the network again.
+    pub fn enable_dhcp(&mut self) -> Result<(), Error> {
+        self.at.execute(
+            format_args!("AT+CWDHCP=1,{}", DHCP_STATION),
+            COMMAND_TIMEOUT_MS,
+        )?;
+        Ok(())
+    }
+
+    /// Returns true if the station gets its IPv4 configuration by DHCP.
+    pub fn is_dhcp_enabled(&mut self) -> Result<bool, Error> {
+        let mut state = None;
+        self.at
+            .query(format_args!("AT+CWDHCP?"), COMMAND_TIMEOUT_MS, |line| {
+                if let Some(parameters) = line.strip_prefix(b"+CWDHCP:") {
+                    state = at::parse_int(parameters);
+                }
+            })?;
+        Ok(state.ok_or(Error::InvalidResponse)? & DHCP_STATION != 0)
+    }
+
+    /// Set the host name that the station sends to the DHCP server, up to 32 characters. Takes
+    /// effect with the next connection.
+    pub fn set_hostname(&mut self, hostname: &str) -> Result<(), Error> {
+        if !(1..=HOSTNAME_SIZE).contains(&hostname.len()) {
+            return Err(Error::InvalidConfig);
+        }
+        self.at.execute(
+            format_args!("AT+CWHOSTNAME={}", Quoted(hostname)),
+            COMMAND_TIMEOUT_MS,
+        )?;
+        Ok(())
+    }
+
+    /// Returns the MAC address of the station.
+    pub fn mac(&mut self) -> Result<[u8; 6], Error> {
+        query_mac(self.at, "AT+CIPSTAMAC?", b"+CIPSTAMAC:")
+    }
+
+    /// Set the MAC address of the station, until the ESP32-S3 is reset. It must differ from the
+    /// MAC address of the access point.
+    pub fn set_mac(&mut self, mac: [u8; 6]) -> Result<(), Error> {
+        set_mac(self.at, "AT+CIPSTAMAC", mac)
+    }
+}